@@ -3,16 +3,25 @@ use std::process::Command;
 fn main() {
     println!("cargo:rerun-if-changed=shaders/");
     
+    // With runtime shader compilation the GLSL is compiled (and cached) at startup
+    if std::env::var_os("CARGO_FEATURE_RUNTIME_SHADERS").is_some() {
+        return;
+    }
+    
     // Check if Vulkan SDK is installed
     let vulkan_sdk = match std::env::var("VULKAN_SDK") {
         Ok(sdk) => sdk,
         Err(_) => {
-            println!("cargo:warning=VULKAN_SDK not set - shaders must be compiled manually");
+            println!("cargo:warning=VULKAN_SDK not set - using committed .spv (enable the runtime-shaders feature to compile from GLSL)");
             return;
         }
     };
     
-    let glslc = format!("{}\\Bin\\glslc.exe", vulkan_sdk);
+    let glslc = if cfg!(windows) {
+        format!("{}\\Bin\\glslc.exe", vulkan_sdk)
+    } else {
+        format!("{}/bin/glslc", vulkan_sdk)
+    };
     
     // Compile cube vertex shader
     let status = Command::new(&glslc)
@@ -34,6 +43,16 @@ fn main() {
         _ => println!("cargo:warning=Cube fragment shader compile failed - using existing .spv"),
     }
     
+    // Compile instanced cube vertex shader
+    let status = Command::new(&glslc)
+        .args(["shaders/cube_instanced.vert", "-o", "shaders/cube_instanced.vert.spv"])
+        .status();
+    
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Instanced cube vertex shader compiled"),
+        _ => println!("cargo:warning=Instanced cube vertex shader compile failed - using existing .spv"),
+    }
+    
     // Compile glTF vertex shader
     let status = Command::new(&glslc)
         .args(&["shaders/gltf.vert", "-o", "shaders/gltf.vert.spv"])
@@ -44,6 +63,16 @@ fn main() {
         _ => println!("cargo:warning=glTF vertex shader compile failed"),
     }
     
+    // Instanced permutation of the glTF vertex shader (see GltfPermutation::INSTANCED)
+    let status = Command::new(&glslc)
+        .args(["-DINSTANCED", "shaders/gltf.vert", "-o", "shaders/gltf.vert.instanced.spv"])
+        .status();
+    
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=glTF INSTANCED vertex shader compiled"),
+        _ => println!("cargo:warning=glTF INSTANCED vertex shader compile failed - using existing .spv"),
+    }
+    
     // Compile glTF fragment shader
     let status = Command::new(&glslc)
         .args(&["shaders/gltf.frag", "-o", "shaders/gltf.frag.spv"])
@@ -54,6 +83,61 @@ fn main() {
         _ => println!("cargo:warning=glTF fragment shader compile failed"),
     }
     
+    // Compile glTF fragment shader permutations (see GltfPermutation)
+    let status = Command::new(&glslc)
+        .args(["-DALPHA_MASK", "shaders/gltf.frag", "-o", "shaders/gltf.frag.alpha_mask.spv"])
+        .status();
+    
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=glTF ALPHA_MASK fragment shader compiled"),
+        _ => println!("cargo:warning=glTF ALPHA_MASK fragment shader compile failed - using existing .spv"),
+    }
+    
+    // Ray query permutations need SPIR-V 1.4+
+    let status = Command::new(&glslc)
+        .args(["--target-env=vulkan1.2", "-DRAY_QUERY", "shaders/gltf.frag", "-o", "shaders/gltf.frag.ray_query.spv"])
+        .status();
+    
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=glTF RAY_QUERY fragment shader compiled"),
+        _ => println!("cargo:warning=glTF RAY_QUERY fragment shader compile failed - using existing .spv"),
+    }
+    
+    let status = Command::new(&glslc)
+        .args([
+            "--target-env=vulkan1.2",
+            "-DALPHA_MASK",
+            "-DRAY_QUERY",
+            "shaders/gltf.frag",
+            "-o",
+            "shaders/gltf.frag.alpha_mask.ray_query.spv",
+        ])
+        .status();
+    
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=glTF ALPHA_MASK+RAY_QUERY fragment shader compiled"),
+        _ => println!("cargo:warning=glTF ALPHA_MASK+RAY_QUERY fragment shader compile failed - using existing .spv"),
+    }
+    
+    // Meshlet task + mesh shaders (VK_EXT_mesh_shader needs SPIR-V 1.4+)
+    let status = Command::new(&glslc)
+        .args(["--target-env=vulkan1.2", "shaders/gltf.task", "-o", "shaders/gltf.task.spv"])
+        .status();
+    
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=glTF task shader compiled"),
+        _ => println!("cargo:warning=glTF task shader compile failed - using existing .spv"),
+    }
+    
+    let status = Command::new(&glslc)
+        .args(["--target-env=vulkan1.2", "shaders/gltf.mesh", "-o", "shaders/gltf.mesh.spv"])
+        .status();
+    
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=glTF mesh shader compiled"),
+        _ => println!("cargo:warning=glTF mesh shader compile failed - using existing .spv"),
+    }
+    
     // Compile egui vertex shader
     let status = Command::new(&glslc)
         .args(&["shaders/egui.vert", "-o", "shaders/egui.vert.spv"])
@@ -84,6 +168,15 @@ fn main() {
         _ => println!("cargo:warning=Shadow vertex shader compile failed - using existing .spv"),
     }
 
+    let status = Command::new(&glslc)
+        .args(["-DINSTANCED", "shaders/shadow.vert", "-o", "shaders/shadow.vert.instanced.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Instanced shadow vertex shader compiled"),
+        _ => println!("cargo:warning=Instanced shadow vertex shader compile failed - using existing .spv"),
+    }
+
     // Compile shadow map fragment shader
     let status = Command::new(&glslc)
         .args(&["shaders/shadow.frag", "-o", "shaders/shadow.frag.spv"])
@@ -93,4 +186,328 @@ fn main() {
         Ok(s) if s.success() => println!("cargo:warning=Shadow fragment shader compiled"),
         _ => println!("cargo:warning=Shadow fragment shader compile failed - using existing .spv"),
     }
+
+    // Compile AOV fragment shader
+    let status = Command::new(&glslc)
+        .args(["shaders/aov.frag", "-o", "shaders/aov.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=AOV fragment shader compiled"),
+        _ => println!("cargo:warning=AOV fragment shader compile failed - using existing .spv"),
+    }
+
+    // Compile particle compute shader
+    let status = Command::new(&glslc)
+        .args(["shaders/particles.comp", "-o", "shaders/particles.comp.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Particle compute shader compiled"),
+        _ => println!("cargo:warning=Particle compute shader compile failed - using existing .spv"),
+    }
+
+    // Compile particle vertex shader
+    let status = Command::new(&glslc)
+        .args(["shaders/particles.vert", "-o", "shaders/particles.vert.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Particle vertex shader compiled"),
+        _ => println!("cargo:warning=Particle vertex shader compile failed - using existing .spv"),
+    }
+
+    // Compile particle fragment shader
+    let status = Command::new(&glslc)
+        .args(["shaders/particles.frag", "-o", "shaders/particles.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Particle fragment shader compiled"),
+        _ => println!("cargo:warning=Particle fragment shader compile failed - using existing .spv"),
+    }
+
+    // Compile skinning compute shader
+    let status = Command::new(&glslc)
+        .args(["shaders/skinning.comp", "-o", "shaders/skinning.comp.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Skinning compute shader compiled"),
+        _ => println!("cargo:warning=Skinning compute shader compile failed - using existing .spv"),
+    }
+
+    // Compile GPU-driven culling compute shader
+    let status = Command::new(&glslc)
+        .args(["shaders/cull.comp", "-o", "shaders/cull.comp.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Culling compute shader compiled"),
+        _ => println!("cargo:warning=Culling compute shader compile failed - using existing .spv"),
+    }
+
+    // Compile instance culling compute shader
+    let status = Command::new(&glslc)
+        .args(["shaders/instance_cull.comp", "-o", "shaders/instance_cull.comp.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Instance culling compute shader compiled"),
+        _ => println!("cargo:warning=Instance culling compute shader compile failed - using existing .spv"),
+    }
+
+    // Compile lightmap baking shaders
+    let status = Command::new(&glslc)
+        .args(["shaders/lightmap.vert", "-o", "shaders/lightmap.vert.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Lightmap vertex shader compiled"),
+        _ => println!("cargo:warning=Lightmap vertex shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/lightmap_radiance.frag", "-o", "shaders/lightmap_radiance.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Lightmap radiance shader compiled"),
+        _ => println!("cargo:warning=Lightmap radiance shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/lightmap_accumulate.frag", "-o", "shaders/lightmap_accumulate.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Lightmap accumulation shader compiled"),
+        _ => println!("cargo:warning=Lightmap accumulation shader compile failed - using existing .spv"),
+    }
+
+    // Compile toon outline shaders
+    let status = Command::new(&glslc)
+        .args(["shaders/outline.vert", "-o", "shaders/outline.vert.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Outline vertex shader compiled"),
+        _ => println!("cargo:warning=Outline vertex shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/outline.frag", "-o", "shaders/outline.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Outline fragment shader compiled"),
+        _ => println!("cargo:warning=Outline fragment shader compile failed - using existing .spv"),
+    }
+
+    // Compile post effect shaders
+    let status = Command::new(&glslc)
+        .args(["shaders/post.vert", "-o", "shaders/post.vert.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Post effect vertex shader compiled"),
+        _ => println!("cargo:warning=Post effect vertex shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/post.frag", "-o", "shaders/post.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Post effect fragment shader compiled"),
+        _ => println!("cargo:warning=Post effect fragment shader compile failed - using existing .spv"),
+    }
+
+    // Compile lens flare shaders
+    let status = Command::new(&glslc)
+        .args(["shaders/flare_occlusion.vert", "-o", "shaders/flare_occlusion.vert.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Flare occlusion vertex shader compiled"),
+        _ => println!("cargo:warning=Flare occlusion vertex shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/flare_occlusion.frag", "-o", "shaders/flare_occlusion.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Flare occlusion fragment shader compiled"),
+        _ => println!("cargo:warning=Flare occlusion fragment shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/lens_flare.vert", "-o", "shaders/lens_flare.vert.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Lens flare vertex shader compiled"),
+        _ => println!("cargo:warning=Lens flare vertex shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/lens_flare.frag", "-o", "shaders/lens_flare.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Lens flare fragment shader compiled"),
+        _ => println!("cargo:warning=Lens flare fragment shader compile failed - using existing .spv"),
+    }
+
+    // Compile god ray shader (the vertex shader is the post effects' full-screen triangle)
+    let status = Command::new(&glslc)
+        .args(["shaders/god_rays.frag", "-o", "shaders/god_rays.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=God ray fragment shader compiled"),
+        _ => println!("cargo:warning=God ray fragment shader compile failed - using existing .spv"),
+    }
+
+    // Compile weather shaders
+    let status = Command::new(&glslc)
+        .args(["shaders/weather.comp", "-o", "shaders/weather.comp.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Weather compute shader compiled"),
+        _ => println!("cargo:warning=Weather compute shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/weather.vert", "-o", "shaders/weather.vert.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Weather vertex shader compiled"),
+        _ => println!("cargo:warning=Weather vertex shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/weather.frag", "-o", "shaders/weather.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Weather fragment shader compiled"),
+        _ => println!("cargo:warning=Weather fragment shader compile failed - using existing .spv"),
+    }
+
+    // Compile vegetation shaders
+    let status = Command::new(&glslc)
+        .args(["shaders/vegetation.vert", "-o", "shaders/vegetation.vert.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Vegetation vertex shader compiled"),
+        _ => println!("cargo:warning=Vegetation vertex shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/vegetation.frag", "-o", "shaders/vegetation.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Vegetation fragment shader compiled"),
+        _ => println!("cargo:warning=Vegetation fragment shader compile failed - using existing .spv"),
+    }
+
+    // Compile impostor shaders
+    let status = Command::new(&glslc)
+        .args(["shaders/impostor_bake.vert", "-o", "shaders/impostor_bake.vert.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Impostor bake vertex shader compiled"),
+        _ => println!("cargo:warning=Impostor bake vertex shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/impostor_bake.frag", "-o", "shaders/impostor_bake.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Impostor bake fragment shader compiled"),
+        _ => println!("cargo:warning=Impostor bake fragment shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/impostor.vert", "-o", "shaders/impostor.vert.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Impostor vertex shader compiled"),
+        _ => println!("cargo:warning=Impostor vertex shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/impostor.frag", "-o", "shaders/impostor.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Impostor fragment shader compiled"),
+        _ => println!("cargo:warning=Impostor fragment shader compile failed - using existing .spv"),
+    }
+
+    // Compile half-resolution transparency shaders (the vertex shader is the post effects' triangle)
+    let status = Command::new(&glslc)
+        .args(["shaders/half_res_depth.frag", "-o", "shaders/half_res_depth.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Half-res depth fragment shader compiled"),
+        _ => println!("cargo:warning=Half-res depth fragment shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/half_res_composite.frag", "-o", "shaders/half_res_composite.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Half-res composite fragment shader compiled"),
+        _ => println!("cargo:warning=Half-res composite fragment shader compile failed - using existing .spv"),
+    }
+
+    // Compile screen-space global illumination shaders
+    let status = Command::new(&glslc)
+        .args(["shaders/ssgi_trace.frag", "-o", "shaders/ssgi_trace.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=SSGI trace fragment shader compiled"),
+        _ => println!("cargo:warning=SSGI trace fragment shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/ssgi_temporal.frag", "-o", "shaders/ssgi_temporal.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=SSGI temporal fragment shader compiled"),
+        _ => println!("cargo:warning=SSGI temporal fragment shader compile failed - using existing .spv"),
+    }
+
+    let status = Command::new(&glslc)
+        .args(["shaders/ssgi_filter.frag", "-o", "shaders/ssgi_filter.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=SSGI filter fragment shader compiled"),
+        _ => println!("cargo:warning=SSGI filter fragment shader compile failed - using existing .spv"),
+    }
+
+    // Compile environment skybox shader
+    let status = Command::new(&glslc)
+        .args(["shaders/skybox.frag", "-o", "shaders/skybox.frag.spv"])
+        .status();
+
+    match status {
+        Ok(s) if s.success() => println!("cargo:warning=Skybox fragment shader compiled"),
+        _ => println!("cargo:warning=Skybox fragment shader compile failed - using existing .spv"),
+    }
 }
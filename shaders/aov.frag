@@ -0,0 +1,43 @@
+#version 450
+
+// Auxiliary outputs (AOVs) for data export, see aov.rs. Fed by gltf.vert.
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec2 fragTexCoord;
+layout(location = 3) in vec3 fragWorldPos;
+layout(location = 4) in float fragViewDepth;
+
+layout(location = 0) out vec4 outAlbedo;   // Base color, unlit (sRGB attachment)
+layout(location = 1) out vec4 outNormal;   // World-space normal
+layout(location = 2) out float outDepth;   // View-space distance
+layout(location = 3) out uint outObjectId; // 1 = ground, 2 + mesh index = model
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    int useTexture;
+    float alphaCutoff; // 0 unless the material is alpha-masked
+    uint objectId;
+} pc;
+
+layout(set = 1, binding = 0) uniform MaterialParams {
+    vec4 baseColor;
+    vec4 metallicRoughness;
+    vec4 emissive;
+    uvec4 textureSlots;
+} material;
+
+layout(binding = 1) uniform sampler2D texSampler;
+
+void main() {
+    vec4 texColor = (pc.useTexture != 0) ? texture(texSampler, fragTexCoord) : vec4(1.0);
+    vec4 albedo = texColor * material.baseColor;
+    if (albedo.a < pc.alphaCutoff) {
+        discard;
+    }
+
+    outAlbedo = vec4(albedo.rgb * fragColor, 1.0);
+    outNormal = vec4(normalize(fragNormal), 0.0);
+    outDepth = fragViewDepth;
+    outObjectId = pc.objectId;
+}
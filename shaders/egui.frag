@@ -2,11 +2,22 @@
 
 layout(binding = 0) uniform sampler2D u_tex;
 
+layout(push_constant) uniform PushConstants {
+    vec2 screen_size;      // Used by egui.vert
+    uint srgb_framebuffer; // 1 when the target encodes to sRGB on write
+} u_push;
+
 layout(location = 0) in vec2 v_uv;
 layout(location = 1) in vec4 v_color;
 
 layout(location = 0) out vec4 out_color;
 
+vec3 linear_from_gamma(vec3 gamma) {
+    vec3 lower = gamma / 12.92;
+    vec3 higher = pow((gamma + 0.055) / 1.055, vec3(2.4));
+    return mix(higher, lower, vec3(lessThan(gamma, vec3(0.04045))));
+}
+
 void main() {
     // Premultiplied-alpha output.
     // Our pipeline blend state is:
@@ -17,5 +28,12 @@ void main() {
     // egui vertex colors are premultiplied; the font atlas uses A as coverage.
     // Multiply BOTH RGB and A by coverage so fully-transparent texels contribute 0.
     float a = v_color.a * tex.a;
-    out_color = vec4(v_color.rgb * tex.rgb * tex.a, a);
+    vec3 rgb = v_color.rgb * tex.rgb * tex.a;
+
+    // Colors are gamma encoded; an sRGB target would encode them again on write.
+    // Like egui's reference renderers, decode the premultiplied color as it is.
+    if (u_push.srgb_framebuffer != 0u) {
+        rgb = linear_from_gamma(rgb);
+    }
+    out_color = vec4(rgb, a);
 }
@@ -0,0 +1,9 @@
+#version 450
+
+// Only the samples count: blended additively, black changes nothing
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = vec4(0.0);
+}
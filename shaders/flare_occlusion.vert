@@ -0,0 +1,17 @@
+#version 450
+
+// A square over the sun at the far plane, for the lens flare occlusion query (see
+// lens_flare.rs). Any geometry drawn in front of the sky hides its samples.
+
+layout(push_constant) uniform OcclusionPushConstants {
+    vec4 rect; // xy = center, zw = half size, in NDC
+} pc;
+
+const vec2 corners[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main() {
+    gl_Position = vec4(pc.rect.xy + corners[gl_VertexIndex] * pc.rect.zw, 1.0, 1.0);
+}
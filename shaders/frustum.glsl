@@ -0,0 +1,19 @@
+// Frustum test of the GPU culling shaders (cull.comp, instance_cull.comp, gltf.task),
+// included with GL_GOOGLE_include_directive. frustum.rs is the CPU side of it.
+
+// Whether the sphere at `center` with `radius` (world space) may be seen through
+// `viewProj`. The planes come from the matrix's rows (Vulkan 0..1 depth).
+bool sphereVisible(mat4 viewProj, vec3 center, float radius) {
+    vec4 rowX = vec4(viewProj[0].x, viewProj[1].x, viewProj[2].x, viewProj[3].x);
+    vec4 rowY = vec4(viewProj[0].y, viewProj[1].y, viewProj[2].y, viewProj[3].y);
+    vec4 rowZ = vec4(viewProj[0].z, viewProj[1].z, viewProj[2].z, viewProj[3].z);
+    vec4 rowW = vec4(viewProj[0].w, viewProj[1].w, viewProj[2].w, viewProj[3].w);
+    vec4 planes[6] = vec4[6](rowW + rowX, rowW - rowX, rowW + rowY, rowW - rowY, rowZ, rowW - rowZ);
+    for (int i = 0; i < 6; i++) {
+        vec4 plane = planes[i] / length(planes[i].xyz);
+        if (dot(plane.xyz, center) + plane.w < -radius) {
+            return false;
+        }
+    }
+    return true;
+}
@@ -1,13 +1,33 @@
-#version 450
+#version 460
+
+#ifdef RAY_QUERY
+#extension GL_EXT_ray_query : require
+#endif
 
 layout(location = 0) in vec3 fragColor;
 layout(location = 1) in vec3 fragNormal;
 layout(location = 2) in vec2 fragTexCoord;
 layout(location = 3) in vec3 fragWorldPos;
 layout(location = 4) in float fragViewDepth;
+layout(location = 5) in vec2 fragLightmapUV; // -1 without lightmap UVs
+layout(location = 6) flat in uint fragTextureSlot; // 1 + array << 8 | layer, 0 for texSampler; bit 31 = planar reflection
 
 layout(location = 0) out vec4 outColor;
 
+// Permutation defines (see GltfPermutation): ALPHA_MASK, RAY_QUERY
+
+// Specialization constants - baked per pipeline variant (see GltfShaderVariant)
+layout(constant_id = 0) const int SHADOW_SAMPLES = 16;  // PCF / PCSS taps
+layout(constant_id = 1) const bool USE_PCSS = true;     // Contact-hardening shadows
+layout(constant_id = 2) const int LIGHT_COUNT = 2;      // 1 = sun only, 2 = sun + fill
+layout(constant_id = 3) const int DEBUG_VIEW = 0;       // 0 = lit, 1 = cascade colors
+layout(constant_id = 4) const bool RAY_TRACED_SHADOWS = false; // RAY_QUERY only: shadow rays instead of cascades
+layout(constant_id = 5) const int AO_RAYS = 0;          // RAY_QUERY only: ambient occlusion rays per pixel (0 = off)
+
+const int MAX_LIGHT_PROBES = 8;
+const int MAX_TEXTURE_ARRAYS = 4;
+const int MAX_REFLECTION_PROBES = 4;
+
 layout(binding = 0) uniform UniformBufferObject {
     mat4 view;
     mat4 proj;
@@ -16,24 +36,70 @@ layout(binding = 0) uniform UniformBufferObject {
     mat4 lightViewProj[4];
     vec4 cascadeSplits;
     vec4 shadowMapSize; // (w,h,1/w,1/h)
-    vec4 debugFlags;    // x = debug cascades, y = use PCSS, z = shadow TAA
+    vec4 debugFlags;    // z = shadow TAA, w = frame (x/y moved to specialization constants)
     vec4 shadowBias;    // x = pcf slope-scale, y = pcf min-bias
 
     mat4 prevViewProj;
+
+    // Diffuse light probes (see light_probes.rs)
+    vec4 probePositions[MAX_LIGHT_PROBES]; // xyz = position, w = radius
+    vec4 probeIrradiance[MAX_LIGHT_PROBES * 9]; // Cosine-convolved SH, 9 coefficients per probe
+    vec4 probeParams; // x = probe count, y = intensity
+
+    vec4 lightmapParams; // x = 1 when a baked lightmap is in use
+    vec4 virtualTextureParams; // x = 1 when the scene texture is virtual, y = 1 to write feedback, zw = size in texels
+
+    // Reflection probes (see reflection_probes.rs)
+    vec4 reflectionProbeBoxes[MAX_REFLECTION_PROBES * 2]; // Per probe: xyz = position, w = 1 if baked; xyz = half extents
+    vec4 reflectionProbeParams; // x = probe count, y = intensity, z = lowest mip
+
+    vec4 planarReflectionParams; // x = 1 when rendered, y = strength, zw = 1 / view size (see planar_reflection.rs)
+
+    vec4 toonParams; // x = 1 to cel shade every mesh, y = bands, z = rim strength (see toon.rs)
+    vec4 ditherParams; // x = strength in 8-bit steps, y = 1 when the target encodes to sRGB (see post_effects.rs)
+    vec4 weatherParams; // x = wetness, y = snow cover (see weather.rs)
+    vec4 ssgiParams; // x = 1 when the previous frame traced bounce light, y = strength (see ssgi.rs)
 } ubo;
 
 layout(push_constant) uniform PushConstants {
     mat4 model;
     int useTexture;
+    float alphaCutoff;
 } pc;
 
+// Factors of the mesh's material (see material.rs). Metallic and roughness weight the
+// reflection probes; the Blinn-Phong shading below doesn't use them.
+layout(set = 1, binding = 0) uniform MaterialParams {
+    vec4 baseColor;
+    vec4 metallicRoughness; // x = metallic, y = roughness, z = 1 for cel shading
+    vec4 emissive;          // rgb = factor * strength, a = occlusion strength
+    uvec4 textureSlots;     // Texture array slots: x = emissive, y = occlusion, 0 for none;
+                            // z = 1 for a base color texture left out of the arrays
+} material;
+layout(set = 1, binding = 1) uniform sampler2DArray materialTexture; // That texture, one layer
+
 layout(binding = 1) uniform sampler2D texSampler;
 layout(binding = 2) uniform sampler2DArrayShadow shadowMap;  // Hardware shadow comparison
 layout(binding = 3) uniform sampler2DArray shadowMapDepth;   // Raw depth for PCSS blocker search
-layout(binding = 4) uniform sampler2D shadowHistory;          // Previous frame history: (shadow, ndcDepth)
-layout(rg16f, binding = 5) uniform image2D shadowHistoryOut;   // Current frame history write: (shadow, ndcDepth)
+layout(binding = 4) uniform sampler2D shadowHistory;          // Previous frame history: (shadow, ndcDepth, ao)
+layout(rgba16f, binding = 5) uniform image2D shadowHistoryOut; // Current frame history write: (shadow, ndcDepth, ao)
 layout(binding = 6) uniform sampler2D sceneDepthLinear;       // Scene depth with bilinear filtering (for contact shadows)
 layout(binding = 7) uniform sampler2D sceneDepthNearest;      // Scene depth with nearest filtering (for contact shadows)
+layout(binding = 9) uniform sampler2D lightmap;               // Baked indirect light (see lightmap.rs)
+// Virtual scene texture (see virtual_texture.rs)
+layout(binding = 10) uniform usampler2D vtPageTable;          // Per page of each mip: atlas slot xy, resident mip, 1 if any
+layout(binding = 11) uniform sampler2D vtAtlas;               // Resident pages, each with a border texel
+layout(std430, binding = 12) buffer VirtualTextureFeedback {
+    uint vtRequests[]; // Pages sampled this frame, each hashed to its own entry
+};
+layout(binding = 13) uniform sampler2DArray textureArrays[MAX_TEXTURE_ARRAYS]; // Material textures by size (see texture_array.rs)
+layout(binding = 14) uniform sampler2DArray reflectionProbeFaces; // Six cube faces per probe (see reflection_probes.rs)
+layout(binding = 15) uniform sampler2D planarReflection;      // The scene mirrored in the ground plane, flipped in X
+layout(binding = 16) uniform sampler2D blueNoise;             // Dithering thresholds, tiled (see post_effects.rs)
+layout(binding = 17) uniform sampler2D ssgi;                  // Bounce light in the previous frame's view (see ssgi.rs)
+#ifdef RAY_QUERY
+layout(binding = 8) uniform accelerationStructureEXT sceneTlas; // Scene geometry (see acceleration_structure.rs)
+#endif
 
 struct ShadowResult {
     float v;
@@ -370,7 +436,7 @@ float findBlockerDepth(int cascadeIndex, vec2 uv, float receiverDepth, float sea
     // Use interleaved gradient noise for rotation
     float phi = shadowFramePhi(gl_FragCoord.xy);
     
-    const int BLOCKER_SAMPLES = 16;
+    const int BLOCKER_SAMPLES = SHADOW_SAMPLES;
     for (int i = 0; i < BLOCKER_SAMPLES; i++) {
         vec2 offset = vogelDiskSample(i, BLOCKER_SAMPLES, phi) * searchRadius * texel;
         float depth = texture(shadowMapDepth, vec3(uv + offset, float(cascadeIndex))).r;
@@ -433,7 +499,7 @@ ShadowResult shadowPCSS(int cascadeIndex, vec3 worldPos, vec3 normalWs, float Nd
     // Step 3: PCF with penumbra-sized kernel
     float phi = shadowFramePhi(gl_FragCoord.xy);
     
-    const int PCF_SAMPLES = 16;
+    const int PCF_SAMPLES = SHADOW_SAMPLES;
     float shadow = 0.0;
     float shadow2 = 0.0;
     
@@ -491,7 +557,7 @@ ShadowResult shadowPCF(int cascadeIndex, vec3 worldPos, vec3 normalWs, float Ndo
     // Vogel disk PCF with interleaved gradient noise
     float phi = shadowFramePhi(gl_FragCoord.xy);
     
-    const int TAP_COUNT = 16;
+    const int TAP_COUNT = SHADOW_SAMPLES;
     float sum = 0.0;
     float sum2 = 0.0;
     
@@ -506,17 +572,73 @@ ShadowResult shadowPCF(int cascadeIndex, vec3 worldPos, vec3 normalWs, float Ndo
     return ShadowResult(m1, m1, m2, radiusTexels);
 }
 
-// Main shadow function - switches between PCF and PCSS based on debugFlags.y
+// Main shadow function - PCF or PCSS, chosen at pipeline creation (USE_PCSS)
 ShadowResult computeShadow(int cascadeIndex, vec3 worldPos, vec3 normalWs, float NdotL) {
-    bool usePCSS = ubo.debugFlags.y > 0.5;
-    
-    if (usePCSS) {
+    if (USE_PCSS) {
         return shadowPCSS(cascadeIndex, worldPos, normalWs, NdotL);
     } else {
         return shadowPCF(cascadeIndex, worldPos, normalWs, NdotL);
     }
 }
 
+#ifdef RAY_QUERY
+// One shadow ray per pixel against the scene TLAS (hard shadows, exact at contact)
+ShadowResult shadowRayQuery(vec3 worldPos, vec3 normalWs, vec3 lightDir) {
+    rayQueryEXT query;
+    rayQueryInitializeEXT(
+        query,
+        sceneTlas,
+        gl_RayFlagsTerminateOnFirstHitEXT | gl_RayFlagsOpaqueEXT | gl_RayFlagsSkipClosestHitShaderEXT,
+        0xFF,
+        worldPos + normalWs * 0.01,
+        0.001,
+        lightDir,
+        1000.0
+    );
+    while (rayQueryProceedEXT(query)) {
+    }
+
+    float v = rayQueryGetIntersectionTypeEXT(query, true) == gl_RayQueryCommittedIntersectionNoneEXT ? 1.0 : 0.0;
+    return ShadowResult(v, v, v, 0.0);
+}
+
+// Fraction of AO_RAYS short cosine-weighted hemisphere rays that escape. The pattern is
+// rotated per pixel and per frame so the temporal accumulation in applyShadowTAA converges.
+float rayQueryAO(vec3 worldPos, vec3 normalWs) {
+    const float AO_RADIUS = 0.5; // World units
+
+    vec3 tangent = normalize(cross(normalWs, abs(normalWs.y) < 0.99 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0)));
+    vec3 bitangent = cross(normalWs, tangent);
+    float frame = ubo.debugFlags.w;
+    float phi = interleavedGradientNoise(gl_FragCoord.xy + vec2(frame * 13.37, frame * 17.17)) * 6.2831853;
+
+    float unoccluded = 0.0;
+    for (int i = 0; i < AO_RAYS; i++) {
+        // Uniform disk sample projected onto the hemisphere = cosine-weighted direction
+        vec2 d = vogelDiskSample(i, AO_RAYS, phi);
+        vec3 dir = normalize(tangent * d.x + bitangent * d.y + normalWs * sqrt(max(0.0, 1.0 - dot(d, d))));
+
+        rayQueryEXT query;
+        rayQueryInitializeEXT(
+            query,
+            sceneTlas,
+            gl_RayFlagsTerminateOnFirstHitEXT | gl_RayFlagsOpaqueEXT | gl_RayFlagsSkipClosestHitShaderEXT,
+            0xFF,
+            worldPos + normalWs * 0.01,
+            0.001,
+            dir,
+            AO_RADIUS
+        );
+        while (rayQueryProceedEXT(query)) {
+        }
+        if (rayQueryGetIntersectionTypeEXT(query, true) == gl_RayQueryCommittedIntersectionNoneEXT) {
+            unoccluded += 1.0;
+        }
+    }
+    return unoccluded / float(max(AO_RAYS, 1));
+}
+#endif
+
 ShadowResult mixShadowResult(ShadowResult a, ShadowResult b, float t) {
     ShadowResult r;
     r.v = mix(a.v, b.v, t);
@@ -526,8 +648,11 @@ ShadowResult mixShadowResult(ShadowResult a, ShadowResult b, float t) {
     return r;
 }
 
-float applyShadowTAA(ShadowResult cur, vec3 worldPos) {
+// Shadow TAA and RTAO accumulation share one reprojected history texel:
+// x = shadow, y = ndc depth, z = ambient occlusion
+float applyShadowTAA(ShadowResult cur, vec3 worldPos, inout float ao) {
     float currentShadow = cur.v;
+    float currentAo = ao;
 
     // Always write something so history stays valid.
     float outShadow = currentShadow;
@@ -538,7 +663,8 @@ float applyShadowTAA(ShadowResult cur, vec3 worldPos) {
     curNdcDepth = clamp(curNdcDepth, 0.0, 1.0);
 
     bool enableTaa = ubo.debugFlags.z > 0.5;
-    if (enableTaa) {
+    bool accumulateAo = AO_RAYS > 0;
+    if (enableTaa || accumulateAo) {
         ivec2 historySizeI = imageSize(shadowHistoryOut);
         vec2 historySize = vec2(max(historySizeI.x, 1), max(historySizeI.y, 1));
         vec2 currentUv = (gl_FragCoord.xy + vec2(0.5)) / historySize;
@@ -550,7 +676,7 @@ float applyShadowTAA(ShadowResult cur, vec3 worldPos) {
             // Vulkan NDC depth is 0..1
             bool inBounds = (prevUv.x >= 0.0 && prevUv.x <= 1.0 && prevUv.y >= 0.0 && prevUv.y <= 1.0 && prevNdc.z >= 0.0 && prevNdc.z <= 1.0);
             if (inBounds) {
-                vec2 history = texture(shadowHistory, prevUv).rg;
+                vec3 history = texture(shadowHistory, prevUv).rgb;
                 float historyShadow = history.x;
                 float historyDepth = history.y;
 
@@ -560,38 +686,230 @@ float applyShadowTAA(ShadowResult cur, vec3 worldPos) {
                 float motion = length(prevUv - currentUv);
                 float delta = abs(historyShadow - currentShadow);
                 float depthDelta = abs(historyDepth - prevNdc.z);
-                if (motion > 0.02 || depthDelta > 0.02 || delta > 0.35) {
-                    outShadow = currentShadow;
-                    imageStore(shadowHistoryOut, ivec2(gl_FragCoord.xy), vec4(outShadow, curNdcDepth, 0.0, 0.0));
-                    return outShadow;
+                bool disoccluded = motion > 0.02 || depthDelta > 0.02;
+
+                // AO rays are few per frame, so lean on history heavily (~10 frame average)
+                if (accumulateAo && !disoccluded) {
+                    ao = mix(currentAo, history.z, 0.9);
                 }
 
-                // Variance clamp around the current neighborhood estimate.
-                float variance = max(0.0, cur.m2 - cur.m1 * cur.m1);
-                float stdev = sqrt(variance);
-                float softness = clamp(cur.kernelRadiusTexels / 8.0, 0.0, 1.0);
-
-                // Softer shadows => tighter clamp (prevents history bleed).
-                float sigma = mix(2.5, 0.9, softness);
-                float lo = cur.m1 - sigma * stdev;
-                float hi = cur.m1 + sigma * stdev;
-                float historyClamped = clamp(historyShadow, lo, hi);
-
-                // Softer shadows benefit from more history weight (reduces crawl),
-                // but cap it to avoid ghosting.
-                float historyWeight = mix(0.55, 0.85, softness);
-                outShadow = mix(currentShadow, historyClamped, historyWeight);
+                if (enableTaa && !disoccluded && delta <= 0.35) {
+                    // Variance clamp around the current neighborhood estimate.
+                    float variance = max(0.0, cur.m2 - cur.m1 * cur.m1);
+                    float stdev = sqrt(variance);
+                    float softness = clamp(cur.kernelRadiusTexels / 8.0, 0.0, 1.0);
+
+                    // Softer shadows => tighter clamp (prevents history bleed).
+                    float sigma = mix(2.5, 0.9, softness);
+                    float lo = cur.m1 - sigma * stdev;
+                    float hi = cur.m1 + sigma * stdev;
+                    float historyClamped = clamp(historyShadow, lo, hi);
+
+                    // Softer shadows benefit from more history weight (reduces crawl),
+                    // but cap it to avoid ghosting.
+                    float historyWeight = mix(0.55, 0.85, softness);
+                    outShadow = mix(currentShadow, historyClamped, historyWeight);
+                }
             }
         }
     }
 
-    imageStore(shadowHistoryOut, ivec2(gl_FragCoord.xy), vec4(outShadow, curNdcDepth, 0.0, 0.0));
+    imageStore(shadowHistoryOut, ivec2(gl_FragCoord.xy), vec4(outShadow, curNdcDepth, ao, 0.0));
     return outShadow;
 }
 
+// Irradiance of probe `probe` for normal `n`
+vec3 probeSH(int probe, vec3 n) {
+    int b = probe * 9;
+    return ubo.probeIrradiance[b + 0].rgb * 0.282095
+         + ubo.probeIrradiance[b + 1].rgb * 0.488603 * n.y
+         + ubo.probeIrradiance[b + 2].rgb * 0.488603 * n.z
+         + ubo.probeIrradiance[b + 3].rgb * 0.488603 * n.x
+         + ubo.probeIrradiance[b + 4].rgb * 1.092548 * n.x * n.y
+         + ubo.probeIrradiance[b + 5].rgb * 1.092548 * n.y * n.z
+         + ubo.probeIrradiance[b + 6].rgb * 0.315392 * (3.0 * n.z * n.z - 1.0)
+         + ubo.probeIrradiance[b + 7].rgb * 1.092548 * n.x * n.z
+         + ubo.probeIrradiance[b + 8].rgb * 0.546274 * (n.x * n.x - n.y * n.y);
+}
+
+// Irradiance blended from the probes reaching the object's origin, so a whole object gets
+// the same probes. w = coverage: 0 outside every probe, 1 well inside one.
+vec4 probeIrradiance(vec3 n) {
+    vec3 origin = pc.model[3].xyz;
+    vec3 irradiance = vec3(0.0);
+    float totalWeight = 0.0;
+    int count = int(ubo.probeParams.x);
+    for (int i = 0; i < count; i++) {
+        float t = clamp(1.0 - distance(ubo.probePositions[i].xyz, origin) / ubo.probePositions[i].w, 0.0, 1.0);
+        float w = t * t * (3.0 - 2.0 * t);
+        if (w > 0.0) {
+            irradiance += w * max(probeSH(i, n), vec3(0.0));
+            totalWeight += w;
+        }
+    }
+    if (totalWeight > 0.0) {
+        irradiance /= totalWeight;
+    }
+    return vec4(irradiance * ubo.probeParams.y, min(totalWeight, 1.0));
+}
+
+// Up vector of each cube face (+X, -X, +Y, -Y, +Z, -Z) as screenshot.rs renders them
+const vec3 CUBE_FACE_UP[6] = vec3[6](
+    vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0),
+    vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, 1.0),
+    vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)
+);
+
+// Face of direction `d` in z and where it lands on that face's image in xy
+vec3 cubeFaceCoord(vec3 d) {
+    vec3 a = abs(d);
+    int face;
+    vec3 axis;
+    if (a.x >= a.y && a.x >= a.z) {
+        face = d.x > 0.0 ? 0 : 1;
+        axis = vec3(sign(d.x), 0.0, 0.0);
+    } else if (a.y >= a.z) {
+        face = d.y > 0.0 ? 2 : 3;
+        axis = vec3(0.0, sign(d.y), 0.0);
+    } else {
+        face = d.z > 0.0 ? 4 : 5;
+        axis = vec3(0.0, 0.0, sign(d.z));
+    }
+    // The face's look-at basis; its projection has +Y down like the image rows
+    vec3 right = normalize(cross(axis, CUBE_FACE_UP[face]));
+    vec3 up = cross(right, axis);
+    vec2 ndc = vec2(dot(right, d), -dot(up, d)) / dot(axis, d);
+    return vec3(ndc * 0.5 + 0.5, float(face));
+}
+
+// Reflection from the smallest baked probe box containing the object's origin, so a whole
+// object uses the same probe. w = 1 when there is one.
+vec4 probeReflection(vec3 n, vec3 toCamera, float roughness) {
+    vec3 origin = pc.model[3].xyz;
+    int probe = -1;
+    float smallest = 1e30;
+    int count = int(ubo.reflectionProbeParams.x);
+    for (int i = 0; i < count; i++) {
+        vec3 center = ubo.reflectionProbeBoxes[i * 2].xyz;
+        vec3 extents = ubo.reflectionProbeBoxes[i * 2 + 1].xyz;
+        float volume = extents.x * extents.y * extents.z;
+        if (ubo.reflectionProbeBoxes[i * 2].w > 0.5 && all(lessThanEqual(abs(origin - center), extents)) && volume < smallest) {
+            probe = i;
+            smallest = volume;
+        }
+    }
+    if (probe < 0) {
+        return vec4(0.0);
+    }
+
+    // Box projection: look up where the reflected ray leaves the box, as seen from the
+    // capture point
+    vec3 center = ubo.reflectionProbeBoxes[probe * 2].xyz;
+    vec3 extents = ubo.reflectionProbeBoxes[probe * 2 + 1].xyz;
+    vec3 r = reflect(-toCamera, n);
+    vec3 exits = max((center + extents - fragWorldPos) / r, (center - extents - fragWorldPos) / r);
+    float t = max(min(min(exits.x, exits.y), exits.z), 0.0);
+    vec3 coord = cubeFaceCoord(fragWorldPos + r * t - center);
+    float layer = float(probe * 6) + coord.z;
+    vec3 radiance = textureLod(reflectionProbeFaces, vec3(coord.xy, layer), roughness * ubo.reflectionProbeParams.z).rgb;
+    return vec4(radiance * ubo.reflectionProbeParams.y, 1.0);
+}
+
+// Must match virtual_texture.rs
+const float VT_PAGE_SIZE = 128.0;
+const float VT_PAGE_BORDER = 1.0;
+const uint VT_FEEDBACK_SLOTS_LOG2 = 12u;
+
+vec4 sampleVirtualTexture(vec2 uv) {
+    vec2 size = ubo.virtualTextureParams.zw;
+    vec2 dx = dFdx(uv * size);
+    vec2 dy = dFdy(uv * size);
+    float lod = 0.5 * log2(max(max(dot(dx, dx), dot(dy, dy)), 1e-8));
+    int mip = clamp(int(floor(lod)), 0, textureQueryLevels(vtPageTable) - 1);
+
+    // Wrap like a REPEAT sampler
+    vec2 texel = fract(uv) * size;
+    ivec2 page = min(ivec2(texel / (VT_PAGE_SIZE * exp2(float(mip)))), textureSize(vtPageTable, mip) - 1);
+
+    // One pixel of each 4x4 block reports its page, a different one every frame
+    uint frame = uint(ubo.debugFlags.w);
+    if (ubo.virtualTextureParams.y > 0.5 && all(equal(uvec2(gl_FragCoord.xy) & 3u, uvec2(frame & 3u, (frame >> 2) & 3u)))) {
+        uint id = 0x80000000u | (uint(mip) << 26) | (uint(page.y) << 13) | uint(page.x);
+        vtRequests[(id * 2654435761u) >> (32u - VT_FEEDBACK_SLOTS_LOG2)] = id;
+    }
+
+    // The entry names the page itself or its nearest resident ancestor
+    uvec4 entry = texelFetch(vtPageTable, page, mip);
+    if (entry.w == 0u) {
+        return vec4(0.5, 0.5, 0.5, 1.0); // Nothing resident yet
+    }
+    vec2 inPage = fract(texel / (VT_PAGE_SIZE * exp2(float(entry.z)))) * VT_PAGE_SIZE;
+    vec2 atlasTexel = vec2(entry.xy) * (VT_PAGE_SIZE + 2.0 * VT_PAGE_BORDER) + VT_PAGE_BORDER + inPage;
+    return textureLod(vtAtlas, atlasTexel / vec2(textureSize(vtAtlas, 0)), 0.0);
+}
+
+// Material texture from the arrays. The slot is uniform per draw, but indexing sampler
+// arrays by it would need a device feature, hence constant indices.
+vec4 sampleTextureArray(uint slot, vec2 uv) {
+    vec3 coord = vec3(uv, float((slot - 1u) & 0xFFu));
+    switch ((slot - 1u) >> 8) {
+        case 0u: return texture(textureArrays[0], coord);
+        case 1u: return texture(textureArrays[1], coord);
+        case 2u: return texture(textureArrays[2], coord);
+        default: return texture(textureArrays[3], coord);
+    }
+}
+
+// Cel shading: the direct light `light` (shadowed N.L) in flat bands, a hard highlight
+// and a rim light along the lit side of silhouettes
+vec3 toonShade(vec3 baseColor, float light, vec3 ambientLight, vec3 n, vec3 l, vec3 toCamera) {
+    float bands = ubo.toonParams.y;
+    float band = min(floor(light * bands), bands - 1.0) / (bands - 1.0);
+    float highlight = step(0.95, max(dot(n, normalize(l + toCamera)), 0.0)) * band;
+    float rim = smoothstep(0.6, 0.65, 1.0 - max(dot(n, toCamera), 0.0)) * max(dot(n, l), 0.25);
+    return baseColor * (ambientLight + 0.65 * band) + vec3(0.3) * highlight + vec3(ubo.toonParams.z * rim);
+}
+
+// Add blue noise of up to half `strength` 8-bit steps either way, so gradients don't band
+// once the target quantizes them. An sRGB target quantizes the encoded value, here
+// approximated by a 2.2 gamma.
+const int BLUE_NOISE_SIZE = 64;
+
+vec3 dither(vec3 color) {
+    float strength = ubo.ditherParams.x;
+    if (strength <= 0.0) {
+        return color;
+    }
+    float noise = (texelFetch(blueNoise, ivec2(gl_FragCoord.xy) & (BLUE_NOISE_SIZE - 1), 0).r - 0.5) * strength / 255.0;
+    if (ubo.ditherParams.y > 0.5) {
+        vec3 encoded = pow(max(color, vec3(0.0)), vec3(1.0 / 2.2)) + noise;
+        return pow(max(encoded, vec3(0.0)), vec3(2.2));
+    }
+    return color + noise;
+}
+
+const uint PLANAR_REFLECTION_BIT = 0x80000000u;
+
 void main() {
+    uint textureSlot = fragTextureSlot & ~PLANAR_REFLECTION_BIT;
+
     // Sample texture unless disabled (used for the ground plane)
-    vec4 texColor = (pc.useTexture != 0) ? texture(texSampler, fragTexCoord) : vec4(1.0);
+    vec4 texColor = vec4(1.0);
+    if (textureSlot != 0u) {
+        texColor = sampleTextureArray(textureSlot, fragTexCoord);
+    } else if (material.textureSlots.z != 0u) {
+        texColor = texture(materialTexture, vec3(fragTexCoord, 0.0));
+    } else if (pc.useTexture != 0) {
+        texColor = ubo.virtualTextureParams.x > 0.5 ? sampleVirtualTexture(fragTexCoord) : texture(texSampler, fragTexCoord);
+    }
+    vec4 albedo = texColor * material.baseColor;
+    
+#ifdef ALPHA_MASK
+    // glTF alphaMode MASK: cut out instead of blending
+    if (albedo.a < pc.alphaCutoff) {
+        discard;
+    }
+#endif
     
     vec3 normal = normalize(fragNormal);
     vec3 lightDir = normalize(ubo.lightDir.xyz);
@@ -605,20 +923,46 @@ void main() {
     float ct;
     selectCascadeBlend(fragViewDepth, c0, c1, ct);
 
-    ShadowResult s0 = computeShadow(c0, fragWorldPos, normal, diff);
-    ShadowResult s = s0;
-    if (ct > 0.0) {
-        ShadowResult s1 = computeShadow(c1, fragWorldPos, normal, diff);
-        s = mixShadowResult(s0, s1, ct);
+    ShadowResult s;
+#ifdef RAY_QUERY
+    const bool tracedShadows = RAY_TRACED_SHADOWS;
+    if (tracedShadows) {
+        // Traced against the real geometry: no cascades, bias tuning or contact shadows needed
+        s = shadowRayQuery(fragWorldPos, normal, lightDir);
+    } else
+#else
+    const bool tracedShadows = false;
+#endif
+    {
+        s = computeShadow(c0, fragWorldPos, normal, diff);
+        if (ct > 0.0) {
+            ShadowResult s1 = computeShadow(c1, fragWorldPos, normal, diff);
+            s = mixShadowResult(s, s1, ct);
+        }
+    }
+
+    float ao = 1.0;
+#ifdef RAY_QUERY
+    if (AO_RAYS > 0) {
+        ao = rayQueryAO(fragWorldPos, normal);
     }
+#endif
 
-    float shadow = applyShadowTAA(s, fragWorldPos);
+    float shadow = applyShadowTAA(s, fragWorldPos, ao);
+    // Baked occlusion from the material's red channel, after the history so it isn't
+    // accumulated. The arrays are sRGB, which linear occlusion data isn't: undo the decode.
+    if (material.textureSlots.y != 0u) {
+        float occlusion = pow(sampleTextureArray(material.textureSlots.y, fragTexCoord).r, 1.0 / 2.2);
+        ao *= mix(1.0, occlusion, material.emissive.a);
+    }
     
-    // Apply Tiny Glade style contact shadows (screen-space ray march)
-    float contactShadow = computeContactShadow(fragWorldPos, normal, lightDir);
-    shadow = min(shadow, contactShadow);
+    if (!tracedShadows) {
+        // Apply Tiny Glade style contact shadows (screen-space ray march)
+        float contactShadow = computeContactShadow(fragWorldPos, normal, lightDir);
+        shadow = min(shadow, contactShadow);
+    }
 
-    if (ubo.debugFlags.x > 0.5) {
+    if (DEBUG_VIEW == 1) {
         vec3 colors[4] = vec3[4](
             vec3(1.0, 0.2, 0.2),
             vec3(0.2, 1.0, 0.2),
@@ -634,22 +978,82 @@ void main() {
     }
     
     // Add a secondary fill light from opposite side
-    vec3 fillLightDir = normalize(vec3(-0.5, 0.3, -0.8));
-    float fillDiff = max(dot(normal, fillLightDir), 0.0) * 0.3;
+    float fillDiff = 0.0;
+    if (LIGHT_COUNT > 1) {
+        vec3 fillLightDir = normalize(vec3(-0.5, 0.3, -0.8));
+        fillDiff = max(dot(normal, fillLightDir), 0.0) * 0.3;
+    }
     
     // Specular highlight (Blinn-Phong)
     vec3 halfDir = normalize(lightDir + viewDir);
     float spec = pow(max(dot(normal, halfDir), 0.0), 32.0);
     
     // Combine lighting with texture
-    vec3 baseColor = texColor.rgb * fragColor;
-    vec3 ambient = 0.25 * baseColor;
+    vec3 baseColor = albedo.rgb * fragColor;
+    // Weather: snow settles on surfaces that face up, rain darkens the rest and makes it glossy
+    float snow = ubo.weatherParams.y * smoothstep(0.4, 0.8, normal.y);
+    float wet = ubo.weatherParams.x * (1.0 - snow);
+    baseColor = mix(baseColor * (1.0 - 0.45 * wet), vec3(0.9, 0.92, 0.95), snow);
+    // Probe irradiance E gives Lambertian radiance E / pi; the constant term where they don't reach.
+    // Lightmapped surfaces use the baked E / pi instead.
+    vec3 ambientLight;
+    if (ubo.lightmapParams.x > 0.5 && fragLightmapUV.x >= 0.0) {
+        ambientLight = texture(lightmap, fragLightmapUV).rgb;
+    } else {
+        vec4 probe = probeIrradiance(normal);
+        ambientLight = mix(vec3(0.25), probe.rgb / 3.14159265, probe.a);
+    }
+    // Screen-space bounce light, traced in the previous frame: find this point in its view
+    if (ubo.ssgiParams.x > 0.5) {
+        vec4 clip = ubo.prevViewProj * vec4(fragWorldPos, 1.0);
+        vec2 uv = clip.xy / clip.w * 0.5 + 0.5;
+        if (clip.w > 0.0 && all(greaterThanEqual(uv, vec2(0.0))) && all(lessThanEqual(uv, vec2(1.0)))) {
+            ambientLight += texture(ssgi, uv).rgb * ubo.ssgiParams.y;
+        }
+    }
+    vec3 ambient = ambientLight * baseColor * ao;
     vec3 diffuse = 0.65 * diff * baseColor * shadow;
-    vec3 fill = fillDiff * baseColor;
+    vec3 fill = fillDiff * baseColor * ao;
     float specFactor = (pc.useTexture != 0) ? 1.0 : 0.0;
-    vec3 specular = vec3(0.3) * spec * specFactor;
+    vec3 specular = vec3(0.3) * spec * specFactor + vec3(0.5) * pow(max(dot(normal, halfDir), 0.0), 96.0) * wet * shadow;
     
     vec3 result = ambient + diffuse + fill + specular;
+    vec3 toCamera = normalize(ubo.cameraPos.xyz - fragWorldPos);
+
+    // Cel shading replaces the lighting and leaves out the local reflections
+    bool toon = ubo.toonParams.x > 0.5 || material.metallicRoughness.z > 0.5;
+    if (toon) {
+        result = toonShade(baseColor, diff * shadow, ambientLight * ao, normal, lightDir, toCamera);
+    }
+
+    // Local reflections, by Schlick's Fresnel of the metallic-roughness factors. Metals
+    // reflect instead of scattering light diffusely.
+    float roughness = mix(material.metallicRoughness.y, 0.1, wet);
+    vec4 reflection = toon ? vec4(0.0) : probeReflection(normal, toCamera, roughness);
+    if (reflection.a > 0.0) {
+        float metallic = material.metallicRoughness.x;
+        vec3 f0 = mix(vec3(0.04), baseColor, metallic);
+        float grazing = pow(1.0 - max(dot(normal, toCamera), 0.0), 5.0) * (1.0 - roughness);
+        vec3 fresnel = f0 + (vec3(1.0) - f0) * grazing;
+        result = (ambient + diffuse + fill) * (1.0 - metallic) + specular + reflection.rgb * fresnel * ao;
+    }
+
+    // Planar reflection of flat surfaces: the mirrored view at this pixel, stronger at
+    // grazing angles
+    if ((fragTextureSlot & PLANAR_REFLECTION_BIT) != 0u && ubo.planarReflectionParams.x > 0.5) {
+        vec2 uv = gl_FragCoord.xy * ubo.planarReflectionParams.zw;
+        vec3 mirrored = texture(planarReflection, vec2(1.0 - uv.x, uv.y)).rgb;
+        float strength = ubo.planarReflectionParams.y;
+        float grazing = pow(1.0 - max(dot(normal, toCamera), 0.0), 5.0);
+        result = mix(result, mirrored, strength * (1.0 + (1.0 - strength) * grazing));
+    }
+    
+    // Emitted light, unaffected by lighting, shadows or occlusion
+    vec3 emissive = material.emissive.rgb;
+    if (material.textureSlots.x != 0u) {
+        emissive *= sampleTextureArray(material.textureSlots.x, fragTexCoord).rgb;
+    }
+    result += emissive;
     
-    outColor = vec4(result, texColor.a);
+    outColor = vec4(dither(result), albedo.a);
 }
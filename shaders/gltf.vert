@@ -4,12 +4,22 @@ layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 inColor;
 layout(location = 2) in vec3 inNormal;
 layout(location = 3) in vec2 inTexCoord;
+layout(location = 4) in vec2 inLightmapUV;
+#ifdef INSTANCED
+// Per-instance model matrix (one column per attribute), see GltfRenderer::upload_instances
+layout(location = 5) in vec4 inModel0;
+layout(location = 6) in vec4 inModel1;
+layout(location = 7) in vec4 inModel2;
+layout(location = 8) in vec4 inModel3;
+#endif
 
 layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec3 fragNormal;
 layout(location = 2) out vec2 fragTexCoord;
 layout(location = 3) out vec3 fragWorldPos;
 layout(location = 4) out float fragViewDepth;
+layout(location = 5) out vec2 fragLightmapUV;
+layout(location = 6) flat out uint fragTextureSlot; // Texture array layer of the draw (see texture_array.rs)
 
 layout(binding = 0) uniform UniformBufferObject {
     mat4 view;
@@ -28,20 +38,35 @@ layout(binding = 0) uniform UniformBufferObject {
 layout(push_constant) uniform PushConstants {
     mat4 model;
     int useTexture;
+    float alphaCutoff;
+    uint objectId;
+    uint textureSlot; // Of instanced draws, whose gl_InstanceIndex counts the instances
 } pc;
 
 void main() {
-    vec4 worldPos = pc.model * vec4(inPosition, 1.0);
+#ifdef INSTANCED
+    mat4 model = mat4(inModel0, inModel1, inModel2, inModel3);
+#else
+    mat4 model = pc.model;
+#endif
+    vec4 worldPos = model * vec4(inPosition, 1.0);
     gl_Position = ubo.proj * ubo.view * worldPos;
 
     vec4 viewPos = ubo.view * worldPos;
     fragViewDepth = -viewPos.z; // view-space distance (positive in front)
     fragWorldPos = worldPos.xyz;
     
-    // Transform normal to world space (assumes uniform scale)
-    mat3 normalMatrix = mat3(pc.model);
+    // Transform normal to world space; the inverse transpose keeps it perpendicular to
+    // the surface under the non-uniform scales of per-object transforms
+    mat3 normalMatrix = transpose(inverse(mat3(model)));
     fragNormal = normalize(normalMatrix * inNormal);
     
     fragColor = inColor;
     fragTexCoord = inTexCoord;
+    fragLightmapUV = inLightmapUV;
+#ifdef INSTANCED
+    fragTextureSlot = pc.textureSlot;
+#else
+    fragTextureSlot = uint(gl_InstanceIndex); // firstInstance of the single instance
+#endif
 }
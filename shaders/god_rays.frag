@@ -0,0 +1,34 @@
+#version 450
+
+// Light shafts from the sun (see god_rays.rs): a radial blur of the open sky around the
+// sun towards its position on screen, added onto the frame. Geometry in front of the sky
+// blocks the blur and leaves shafts of shadow between the rays.
+
+layout(location = 0) in vec2 fragUV;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 0) uniform sampler2D sceneDepth;
+
+layout(push_constant) uniform GodRayPushConstants {
+    vec4 sun;    // xy = sun in texture coordinates, z = aspect ratio, w = intensity
+    vec4 params; // x = samples, y = ray length (fraction of the way to the sun), z = decay, w = sun radius
+} pc;
+
+void main() {
+    int samples = int(pc.params.x);
+    vec2 delta = (pc.sun.xy - fragUV) * pc.params.y / float(samples);
+    vec2 uv = fragUV;
+    float weight = 1.0;
+    float light = 0.0;
+    for (int i = 0; i < samples; i++) {
+        uv += delta;
+        // The source: sky (cleared depth) close to the sun
+        float sky = texture(sceneDepth, uv).r >= 1.0 ? 1.0 : 0.0;
+        vec2 fromSun = (uv - pc.sun.xy) * vec2(pc.sun.z, 1.0);
+        light += sky * exp(-dot(fromSun, fromSun) / (pc.params.w * pc.params.w)) * weight;
+        weight *= pc.params.z;
+    }
+    light /= float(samples);
+    outColor = vec4(vec3(1.0, 0.9, 0.75) * light * pc.sun.w, 0.0);
+}
@@ -0,0 +1,45 @@
+#version 450
+
+// Depth-aware upsample of the half-resolution transparency (see half_res.rs), added onto
+// the frame. Of the four half-resolution pixels around each full-resolution one, those
+// whose depth is close to the pixel's own count the most, so particles stay on their side
+// of silhouettes instead of bleeding a blocky halo across them.
+
+layout(location = 0) in vec2 fragUV;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 0) uniform sampler2D sceneDepth;
+layout(binding = 1) uniform sampler2D halfDepth;
+layout(binding = 2) uniform sampler2D halfColor;
+
+layout(push_constant) uniform CompositePushConstants {
+    vec4 depth; // x = projection[3][2], y = projection[2][2] (to linearize depth), z = depth tolerance
+} pc;
+
+float linearDepth(float d) {
+    return pc.depth.x / (d + pc.depth.y);
+}
+
+void main() {
+    ivec2 size = textureSize(halfColor, 0);
+    float full = linearDepth(texelFetch(sceneDepth, ivec2(gl_FragCoord.xy), 0).r);
+
+    vec2 position = gl_FragCoord.xy * 0.5 - 0.5;
+    ivec2 base = ivec2(floor(position));
+    vec2 f = position - vec2(base);
+
+    vec3 color = vec3(0.0);
+    float total = 0.0;
+    for (int y = 0; y < 2; y++) {
+        for (int x = 0; x < 2; x++) {
+            ivec2 texel = clamp(base + ivec2(x, y), ivec2(0), size - 1);
+            float bilinear = (x == 0 ? 1.0 - f.x : f.x) * (y == 0 ? 1.0 - f.y : f.y);
+            float difference = abs(linearDepth(texelFetch(halfDepth, texel, 0).r) - full) / max(full, 1e-4);
+            float weight = bilinear / (pc.depth.z + difference);
+            color += texelFetch(halfColor, texel, 0).rgb * weight;
+            total += weight;
+        }
+    }
+    outColor = vec4(color / max(total, 1e-6), 0.0);
+}
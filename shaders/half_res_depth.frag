@@ -0,0 +1,25 @@
+#version 450
+
+// Scene depth downsample for the half-resolution transparency pass (see half_res.rs): each
+// half-resolution pixel keeps the farthest of the 2x2 full-resolution depths under it, so
+// sprites are never cut off by an edge that only covers part of the block; the composite
+// sorts out those edges. Also clears the color the transparency is added onto.
+
+layout(location = 0) in vec2 fragUV;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 0) uniform sampler2D sceneDepth;
+
+void main() {
+    ivec2 size = textureSize(sceneDepth, 0);
+    ivec2 base = ivec2(gl_FragCoord.xy) * 2;
+    float depth = 0.0;
+    for (int y = 0; y < 2; y++) {
+        for (int x = 0; x < 2; x++) {
+            depth = max(depth, texelFetch(sceneDepth, min(base + ivec2(x, y), size - 1), 0).r);
+        }
+    }
+    gl_FragDepth = depth;
+    outColor = vec4(0.0);
+}
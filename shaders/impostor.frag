@@ -0,0 +1,30 @@
+#version 450
+
+// Lit like cube.frag from the baked color and normal, minus the view-dependent terms
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) flat in vec3 fragAxisX;
+layout(location = 2) flat in vec3 fragAxisY;
+layout(location = 3) flat in vec3 fragAxisZ;
+
+layout(set = 0, binding = 0) uniform sampler2D albedoAtlas;
+layout(set = 0, binding = 1) uniform sampler2D normalAtlas;
+
+layout(location = 0) out vec4 outColor;
+
+// Must match the cube renderer's light
+const vec3 LIGHT_DIRECTION = vec3(0.5, 1.0, 0.3);
+
+void main() {
+    vec4 albedo = texture(albedoAtlas, fragTexCoord);
+    if (albedo.a < 0.5) {
+        discard;
+    }
+    vec3 local = texture(normalAtlas, fragTexCoord).xyz * 2.0 - 1.0;
+    vec3 normal = normalize(mat3(fragAxisX, fragAxisY, fragAxisZ) * local);
+
+    float diffuse = max(dot(normal, normalize(LIGHT_DIRECTION)), 0.0);
+    float fill = max(dot(normal, normalize(vec3(-0.5, 0.3, -0.8))), 0.0) * 0.3;
+    vec3 color = albedo.rgb * (0.20 + 0.65 * diffuse + fill);
+    outColor = vec4(color, 1.0);
+}
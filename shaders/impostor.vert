@@ -0,0 +1,61 @@
+#version 450
+
+// Camera-facing quads standing in for distant instances, six vertices each. Every quad
+// shows the atlas tile baked from the direction closest to the one it is seen from, in
+// the instance's own space, so rotating instances turn in their impostors too.
+
+layout(location = 0) in vec4 inModel0;
+layout(location = 1) in vec4 inModel1;
+layout(location = 2) in vec4 inModel2;
+layout(location = 3) in vec4 inModel3;
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj;
+    vec4 camera; // xyz = camera position, w = bounding radius of the mesh
+    vec4 atlas;  // x = directions around, y = elevation rows
+} pc;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) flat out vec3 fragAxisX; // Mesh space to world space, for the normals
+layout(location = 2) flat out vec3 fragAxisY;
+layout(location = 3) flat out vec3 fragAxisZ;
+
+const float PI = 3.14159265;
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(1.0, 1.0), vec2(-1.0, 1.0), vec2(-1.0, -1.0)
+);
+
+void main() {
+    mat4 model = mat4(inModel0, inModel1, inModel2, inModel3);
+    vec3 center = model[3].xyz;
+    float scale = length(model[0].xyz);
+    mat3 rotation = mat3(model[0].xyz / scale, model[1].xyz / length(model[1].xyz), model[2].xyz / length(model[2].xyz));
+
+    // Nearest baked direction to the camera, in mesh space
+    vec3 toCamera = normalize(pc.camera.xyz - center);
+    vec3 local = transpose(rotation) * toCamera;
+    float around = pc.atlas.x;
+    float rows = pc.atlas.y;
+    float column = mod(round(atan(local.z, local.x) / (2.0 * PI) * around), around);
+    float row = clamp(floor((asin(clamp(local.y, -1.0, 1.0)) / PI + 0.5) * rows), 0.0, rows - 1.0);
+
+    // Upright in the baked view; fall back to the mesh's Z looking straight along its Y
+    vec3 up = rotation[1];
+    vec3 right = cross(up, toCamera);
+    if (dot(right, right) < 1e-6) {
+        right = cross(rotation[2], toCamera);
+    }
+    right = normalize(right);
+    up = cross(toCamera, right);
+
+    vec2 corner = CORNERS[gl_VertexIndex];
+    float radius = pc.camera.w * scale;
+    gl_Position = pc.viewProj * vec4(center + (right * corner.x + up * corner.y) * radius, 1.0);
+
+    vec2 tile = vec2(corner.x, -corner.y) * 0.5 + 0.5;
+    fragTexCoord = (vec2(column, row) + tile) / vec2(around, rows);
+    fragAxisX = rotation[0];
+    fragAxisY = rotation[1];
+    fragAxisZ = rotation[2];
+}
@@ -0,0 +1,20 @@
+#version 450
+
+// One view of a mesh into its tile of the impostor atlas, see impostor.rs
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inColor;
+layout(location = 2) in vec3 inNormal;
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj; // Orthographic, looking at the mesh from one of the atlas directions
+} pc;
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec3 fragNormal;
+
+void main() {
+    gl_Position = pc.viewProj * vec4(inPosition, 1.0);
+    fragColor = inColor;
+    fragNormal = inNormal;
+}
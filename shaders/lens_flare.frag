@@ -0,0 +1,25 @@
+#version 450
+
+// Lens flare sprites, added onto the finished frame
+
+layout(location = 0) in vec2 fragOffset;
+layout(location = 1) flat in vec3 fragColor;
+layout(location = 2) flat in int fragGhost;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    float r = length(fragOffset);
+    float brightness;
+    if (fragGhost == 0) {
+        // Soft glow with a thin horizontal streak
+        float glow = exp(-r * r * 6.0);
+        float streak = exp(-abs(fragOffset.y) * 60.0) * (1.0 - abs(fragOffset.x));
+        brightness = glow * 0.8 + streak * 0.5;
+    } else {
+        // Discs that brighten toward their rim, like out of focus aperture images
+        float disc = 1.0 - smoothstep(0.85, 1.0, r);
+        brightness = disc * mix(0.5, 1.0, smoothstep(0.4, 0.95, r));
+    }
+    outColor = vec4(fragColor * brightness, 0.0);
+}
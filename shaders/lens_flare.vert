@@ -0,0 +1,50 @@
+#version 450
+
+// Lens flare sprites (see lens_flare.rs). Instance 0 is the glow around the sun, the
+// others are ghosts strung along the line from the sun through the center of the screen.
+
+layout(push_constant) uniform FlarePushConstants {
+    vec4 sun;    // xy = sun in NDC, z = aspect ratio, w = intensity (occlusion included)
+    vec4 params; // x = ghost count, y = ghost spacing, z = glow size
+} pc;
+
+layout(location = 0) out vec2 fragOffset;       // -1 to 1 across the sprite
+layout(location = 1) flat out vec3 fragColor;
+layout(location = 2) flat out int fragGhost;    // 0 for the glow
+
+const vec2 corners[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+float hash(float n) {
+    return fract(sin(n * 12.9898) * 43758.5453);
+}
+
+void main() {
+    vec2 corner = corners[gl_VertexIndex];
+    int ghost = gl_InstanceIndex;
+    vec2 sun = pc.sun.xy;
+
+    vec2 center;
+    float size;
+    vec3 color;
+    if (ghost == 0) {
+        center = sun;
+        size = pc.params.z;
+        color = vec3(1.0, 0.92, 0.8);
+    } else {
+        // Ghosts mirror the sun through the center, and fade in as it nears the center
+        float t = float(ghost) * pc.params.y;
+        center = sun * (1.0 - t);
+        size = mix(0.04, 0.14, hash(float(ghost)));
+        float hue = hash(float(ghost) + 7.0);
+        color = mix(vec3(0.4, 0.7, 1.0), vec3(1.0, 0.6, 0.3), hue) * 0.25;
+        color *= 1.0 - 0.6 * clamp(length(sun), 0.0, 1.0);
+    }
+
+    fragOffset = corner;
+    fragColor = color * pc.sun.w;
+    fragGhost = ghost;
+    gl_Position = vec4(center + corner * vec2(size / pc.sun.z, size), 0.0, 1.0);
+}
@@ -0,0 +1,45 @@
+#version 450
+
+// Scene geometry for lightmap baking (see lightmap.rs): seen from the sampled direction,
+// seen from the sun, or unwrapped into the lightmap atlas at its lightmap UVs.
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inColor;
+layout(location = 2) in vec3 inNormal;
+layout(location = 4) in vec2 inLightmapUV;
+
+layout(location = 0) out vec3 fragWorldPos;
+layout(location = 1) out vec3 fragNormal;
+layout(location = 2) out vec3 fragColor;
+layout(location = 3) out vec2 fragLightmapUV;
+
+layout(binding = 0) uniform BakeUniforms {
+    mat4 sunViewProj;
+    mat4 directionViewProj;
+    vec4 sunDirection;    // xyz = towards the sun, w = sun irradiance
+    vec4 sampleDirection; // xyz = direction being sampled, w = normal offset against self-shadowing
+    vec4 skyRadiance;
+} bake;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    vec4 baseColor;
+    int mode; // 0 = sampled direction, 1 = sun, 2 = lightmap atlas
+} pc;
+
+void main() {
+    vec4 worldPos = pc.model * vec4(inPosition, 1.0);
+    fragWorldPos = worldPos.xyz;
+    fragNormal = normalize(transpose(inverse(mat3(pc.model))) * inNormal); // As in gltf.vert
+    fragColor = inColor * pc.baseColor.rgb;
+    fragLightmapUV = inLightmapUV;
+
+    if (pc.mode == 0) {
+        gl_Position = bake.directionViewProj * worldPos;
+    } else if (pc.mode == 1) {
+        gl_Position = bake.sunViewProj * worldPos;
+    } else {
+        // Meshes without lightmap UVs (-1) land outside the viewport
+        gl_Position = vec4(inLightmapUV * 2.0 - 1.0, 0.5, 1.0);
+    }
+}
@@ -0,0 +1,46 @@
+#version 450
+
+// One sample of the irradiance at a lightmap texel: the radiance arriving from the sampled
+// direction, from the direction map where geometry is in the way and from the sky
+// elsewhere. Directions are spread uniformly over the sphere, hence the 4 pi weight.
+
+const float PI = 3.14159265;
+
+layout(location = 0) in vec3 fragWorldPos;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec3 fragColor;
+layout(location = 3) in vec2 fragLightmapUV;
+
+layout(location = 0) out vec4 outSample; // Added to the sum; a counts samples
+
+layout(binding = 0) uniform BakeUniforms {
+    mat4 sunViewProj;
+    mat4 directionViewProj;
+    vec4 sunDirection;
+    vec4 sampleDirection;
+    vec4 skyRadiance;
+} bake;
+
+layout(binding = 2) uniform sampler2D directionRadiance;
+layout(binding = 3) uniform sampler2D directionDepth;
+
+void main() {
+    vec3 normal = normalize(fragNormal);
+    vec3 direction = bake.sampleDirection.xyz;
+    float cosTheta = dot(normal, direction);
+    if (cosTheta <= 0.0) {
+        outSample = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    vec4 clip = bake.directionViewProj * vec4(fragWorldPos + normal * bake.sampleDirection.w, 1.0);
+    vec3 ndc = clip.xyz / clip.w;
+    vec2 uv = ndc.xy * 0.5 + 0.5;
+    vec3 radiance = bake.skyRadiance.rgb;
+    bool inside = all(greaterThanEqual(uv, vec2(0.0))) && all(lessThanEqual(uv, vec2(1.0)));
+    if (inside && texture(directionDepth, uv).r < ndc.z) {
+        radiance = texture(directionRadiance, uv).rgb;
+    }
+
+    outSample = vec4(radiance * cosTheta * 4.0 * PI, 1.0);
+}
@@ -0,0 +1,51 @@
+#version 450
+
+// Radiance leaving the surfaces seen from the sampled direction, towards the texels that
+// look in that direction: the sun's light plus the indirect light baked so far, reflected
+// by a Lambertian surface of the material's base color.
+
+const float PI = 3.14159265;
+
+layout(location = 0) in vec3 fragWorldPos;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec3 fragColor;
+layout(location = 3) in vec2 fragLightmapUV;
+
+layout(location = 0) out vec4 outRadiance;
+
+layout(binding = 0) uniform BakeUniforms {
+    mat4 sunViewProj;
+    mat4 directionViewProj;
+    vec4 sunDirection;
+    vec4 sampleDirection;
+    vec4 skyRadiance;
+} bake;
+
+layout(binding = 1) uniform sampler2D sunDepth;
+layout(binding = 4) uniform sampler2D accumulated; // rgb = summed irradiance, a = sample count
+
+float sunVisibility(vec3 worldPos, vec3 normal) {
+    vec4 clip = bake.sunViewProj * vec4(worldPos + normal * bake.sampleDirection.w, 1.0);
+    vec3 ndc = clip.xyz / clip.w;
+    vec2 uv = ndc.xy * 0.5 + 0.5;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        return 1.0; // Outside the baked geometry's bounds
+    }
+    return ndc.z <= texture(sunDepth, uv).r ? 1.0 : 0.0;
+}
+
+void main() {
+    vec3 normal = normalize(fragNormal);
+    vec3 sunDir = bake.sunDirection.xyz;
+    float sun = bake.sunDirection.w * max(dot(normal, sunDir), 0.0) * sunVisibility(fragWorldPos, normal);
+
+    vec3 indirect = vec3(0.0);
+    if (fragLightmapUV.x >= 0.0) {
+        vec4 sum = texture(accumulated, fragLightmapUV);
+        if (sum.a > 0.0) {
+            indirect = sum.rgb / sum.a;
+        }
+    }
+
+    outRadiance = vec4(fragColor * (sun + indirect) / PI, 1.0);
+}
@@ -0,0 +1,15 @@
+#version 450
+
+// Flat outline color (see toon.rs)
+
+layout(location = 0) out vec4 outColor;
+
+layout(push_constant) uniform OutlinePushConstants {
+    mat4 model;
+    vec4 color;
+    vec4 params;
+} pc;
+
+void main() {
+    outColor = pc.color;
+}
@@ -0,0 +1,30 @@
+#version 450
+
+// Inverted hull outlines of cel shaded meshes (see toon.rs)
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 2) in vec3 inNormal;
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+layout(push_constant) uniform OutlinePushConstants {
+    mat4 model;
+    vec4 color;
+    vec4 params; // x = width in pixels, zw = 2 / viewport size
+} pc;
+
+void main() {
+    mat4 viewProj = ubo.proj * ubo.view;
+    gl_Position = viewProj * pc.model * vec4(inPosition, 1.0);
+
+    // Push the vertex out along its normal as seen on screen, by the same number of pixels
+    // at any distance
+    vec3 worldNormal = transpose(inverse(mat3(pc.model))) * inNormal; // As in gltf.vert
+    vec2 screenNormal = (viewProj * vec4(worldNormal, 0.0)).xy;
+    if (dot(screenNormal, screenNormal) > 1e-10) {
+        gl_Position.xy += normalize(screenNormal) * pc.params.x * pc.params.zw * gl_Position.w;
+    }
+}
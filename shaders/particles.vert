@@ -0,0 +1,48 @@
+#version 450
+
+// Camera-facing quads, six vertices per particle, expanded from the particle buffer.
+
+struct Particle {
+    vec4 position; // xyz = position, w = age (seconds)
+    vec4 velocity; // xyz = velocity, w = lifetime (seconds)
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer Particles {
+    Particle particles[];
+} buf;
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj;
+    vec4 cameraRight; // xyz = right vector, w = particle size
+    vec4 cameraUp;
+} pc;
+
+layout(location = 0) out vec2 fragCoord;
+layout(location = 1) out vec4 fragColor;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(1.0, 1.0), vec2(-1.0, 1.0), vec2(-1.0, -1.0)
+);
+
+void main() {
+    Particle p = buf.particles[gl_VertexIndex / 6];
+    vec2 corner = CORNERS[gl_VertexIndex % 6];
+
+    if (p.position.w < 0.0) {
+        // Not spawned yet: place outside the clip volume
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        fragCoord = vec2(0.0);
+        fragColor = vec4(0.0);
+        return;
+    }
+
+    float life = clamp(p.position.w / p.velocity.w, 0.0, 1.0);
+    float size = pc.cameraRight.w * (1.0 - life * 0.5);
+    vec3 world = p.position.xyz + (pc.cameraRight.xyz * corner.x + pc.cameraUp.xyz * corner.y) * size;
+
+    gl_Position = pc.viewProj * vec4(world, 1.0);
+    fragCoord = corner;
+    // Warm when fresh, cooling and fading out with age
+    fragColor = vec4(mix(vec3(1.0, 0.75, 0.3), vec3(0.3, 0.5, 1.0), life), 1.0 - life);
+}
@@ -0,0 +1,34 @@
+#version 450
+
+// Film grain and vignette over the finished frame (see post_effects.rs). Blended as
+// src + dst * src.a: alpha scales the frame, color adds to it.
+
+layout(location = 0) in vec2 fragUV;
+
+layout(location = 0) out vec4 outColor;
+
+layout(push_constant) uniform PostPushConstants {
+    vec4 params; // x = grain, y = vignette, z = frame, w = aspect ratio
+} pc;
+
+// White noise in [0, 1) per pixel and frame
+float hash(vec3 p) {
+    p = fract(p * vec3(0.1031, 0.1030, 0.0973));
+    p += dot(p, p.yxz + 33.33);
+    return fract((p.x + p.y) * p.z);
+}
+
+void main() {
+    float grain = pc.params.x;
+    float vignette = pc.params.y;
+
+    // Darken in circles around the center, whatever the window's aspect
+    vec2 centered = (fragUV - 0.5) * vec2(pc.params.w, 1.0);
+    float distance = length(centered) / length(vec2(0.5 * pc.params.w, 0.5)); // 1 in the corners
+    float scale = 1.0 - vignette * smoothstep(0.4, 1.0, distance);
+
+    // Grain: scale down by grain / 2 and add up to grain back, which keeps mid grey's
+    // brightness on average
+    float noise = hash(vec3(gl_FragCoord.xy, pc.params.z));
+    outColor = vec4(vec3(grain * noise * scale), scale * (1.0 - 0.5 * grain));
+}
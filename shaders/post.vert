@@ -0,0 +1,10 @@
+#version 450
+
+// Full-screen triangle for the post effects (see post_effects.rs)
+
+layout(location = 0) out vec2 fragUV;
+
+void main() {
+    fragUV = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(fragUV * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -1,6 +1,13 @@
 #version 450
 
 layout(location = 0) in vec3 inPosition;
+#ifdef INSTANCED
+// Per-instance model matrix, as in gltf.vert
+layout(location = 5) in vec4 inModel0;
+layout(location = 6) in vec4 inModel1;
+layout(location = 7) in vec4 inModel2;
+layout(location = 8) in vec4 inModel3;
+#endif
 
 layout(binding = 0) uniform UniformBufferObject {
     mat4 view;
@@ -22,6 +29,11 @@ layout(push_constant) uniform ShadowPushConstants {
 } pc;
 
 void main() {
-    vec4 worldPos = pc.model * vec4(inPosition, 1.0);
+#ifdef INSTANCED
+    mat4 model = mat4(inModel0, inModel1, inModel2, inModel3);
+#else
+    mat4 model = pc.model;
+#endif
+    vec4 worldPos = model * vec4(inPosition, 1.0);
     gl_Position = ubo.lightViewProj[pc.cascadeIndex] * worldPos;
 }
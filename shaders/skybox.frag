@@ -0,0 +1,30 @@
+#version 450
+
+// Equirectangular environment behind the scene (see skybox.rs), drawn over post.vert's
+// full-screen triangle
+
+layout(set = 0, binding = 0) uniform sampler2D environment;
+
+layout(push_constant) uniform PushConstants {
+    mat4 invViewProj;
+} pc;
+
+layout(location = 0) in vec2 fragUV;
+
+layout(location = 0) out vec4 outColor;
+
+const float PI = 3.14159265359;
+
+void main() {
+    // The pixel's view ray, from the near plane to halfway through the depth range; a far
+    // plane at infinity would unproject to w = 0
+    vec2 ndc = fragUV * 2.0 - 1.0;
+    vec4 near = pc.invViewProj * vec4(ndc, 0.0, 1.0);
+    vec4 far = pc.invViewProj * vec4(ndc, 0.5, 1.0);
+    vec3 dir = normalize(far.xyz / far.w - near.xyz / near.w);
+
+    // Longitude around +Y, latitude from the top. Level 0 only: the longitude wraps
+    // around at the seam, where derivatives would pick the smallest mip.
+    vec2 uv = vec2(atan(dir.z, dir.x) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+    outColor = vec4(textureLod(environment, uv, 0.0).rgb, 1.0);
+}
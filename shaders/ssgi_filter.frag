@@ -0,0 +1,41 @@
+#version 450
+
+// Depth-aware blur of the accumulated bounce light (see ssgi.rs). Neighbors at a depth
+// close to the pixel's count the most, so light from one surface doesn't bleed across a
+// silhouette onto another.
+
+layout(location = 0) in vec2 fragUV;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 0) uniform sampler2D light;      // Half resolution
+layout(binding = 1) uniform sampler2D sceneDepth; // Full resolution
+
+layout(push_constant) uniform FilterPushConstants {
+    vec4 depth; // x = projection[3][2], y = projection[2][2] (to linearize depth), z = depth tolerance
+} pc;
+
+float linearDepth(float d) {
+    return pc.depth.x / (d + pc.depth.y);
+}
+
+void main() {
+    ivec2 size = textureSize(light, 0);
+    ivec2 depthSize = textureSize(sceneDepth, 0);
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    float center = linearDepth(texelFetch(sceneDepth, min(texel * 2, depthSize - 1), 0).r);
+
+    vec3 color = vec3(0.0);
+    float total = 0.0;
+    for (int y = -2; y <= 2; y++) {
+        for (int x = -2; x <= 2; x++) {
+            ivec2 neighbor = clamp(texel + ivec2(x, y), ivec2(0), size - 1);
+            float d = linearDepth(texelFetch(sceneDepth, min(neighbor * 2, depthSize - 1), 0).r);
+            float difference = abs(d - center) / max(center, 1e-4);
+            float weight = exp(-float(x * x + y * y) / 4.5) / (pc.depth.z + difference);
+            color += texelFetch(light, neighbor, 0).rgb * weight;
+            total += weight;
+        }
+    }
+    outColor = vec4(color / max(total, 1e-6), 1.0);
+}
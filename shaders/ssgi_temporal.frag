@@ -0,0 +1,51 @@
+#version 450
+
+// Temporal accumulation of the traced bounce light (see ssgi.rs): last frame's history,
+// reprojected through the scene depth, is clamped to the spread of the new samples around
+// each pixel and blended with them. Disocclusions and fast changes fall back to the new
+// samples instead of smearing old light.
+
+layout(location = 0) in vec2 fragUV;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 0) uniform sampler2D current;    // This frame's trace
+layout(binding = 1) uniform sampler2D history;    // Last frame's output of this pass
+layout(binding = 2) uniform sampler2D sceneDepth; // Full resolution
+
+layout(push_constant) uniform TemporalPushConstants {
+    mat4 reprojection; // This frame's NDC to last frame's clip space
+    vec4 params;       // x = history weight, y = 1 if the history is valid
+} pc;
+
+void main() {
+    ivec2 size = textureSize(current, 0);
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec3 center = texelFetch(current, texel, 0).rgb;
+
+    // Mean and deviation of the neighborhood bound the history
+    vec3 sum = vec3(0.0);
+    vec3 sumSquares = vec3(0.0);
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            vec3 value = texelFetch(current, clamp(texel + ivec2(x, y), ivec2(0), size - 1), 0).rgb;
+            sum += value;
+            sumSquares += value * value;
+        }
+    }
+    vec3 mean = sum / 9.0;
+    vec3 deviation = sqrt(max(sumSquares / 9.0 - mean * mean, vec3(0.0)));
+
+    ivec2 depthSize = textureSize(sceneDepth, 0);
+    float depth = texelFetch(sceneDepth, min(texel * 2, depthSize - 1), 0).r;
+    vec4 previous = pc.reprojection * vec4(fragUV * 2.0 - 1.0, depth, 1.0);
+    vec2 previousUv = previous.xy / previous.w * 0.5 + 0.5;
+
+    vec3 result = center;
+    if (pc.params.y > 0.5 && previous.w > 0.0 && all(greaterThanEqual(previousUv, vec2(0.0))) &&
+        all(lessThanEqual(previousUv, vec2(1.0)))) {
+        vec3 old = clamp(texture(history, previousUv).rgb, mean - 2.0 * deviation, mean + 2.0 * deviation);
+        result = mix(center, old, pc.params.x);
+    }
+    outColor = vec4(result, 1.0);
+}
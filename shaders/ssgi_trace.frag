@@ -0,0 +1,104 @@
+#version 450
+
+// Screen-space bounce light (see ssgi.rs), traced at half resolution. Each pixel rebuilds
+// its view-space position and normal from the scene depth, then marches a few short rays
+// over the cosine-weighted hemisphere through the depth buffer. A ray that passes just
+// behind a surface has hit it and brings back that surface's color from the frame; rays
+// that leave the screen or find nothing bring back nothing, the ambient term covers them.
+
+layout(location = 0) in vec2 fragUV;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 0) uniform sampler2D sceneDepth; // Full resolution
+layout(binding = 1) uniform sampler2D sceneColor; // The frame, at half resolution
+
+layout(push_constant) uniform TracePushConstants {
+    vec4 proj;   // x = projection[0][0], y = projection[1][1], z = projection[2][2], w = projection[3][2]
+    vec4 params; // x = rays, y = ray length (meters), z = thickness (meters), w = frame index
+} pc;
+
+const int STEPS = 12;
+const float PI = 3.14159265;
+
+vec3 viewPosition(vec2 uv, float depth) {
+    vec2 ndc = uv * 2.0 - 1.0;
+    float z = -pc.proj.w / (depth + pc.proj.z);
+    return vec3(-ndc.x * z / pc.proj.x, -ndc.y * z / pc.proj.y, z);
+}
+
+vec3 project(vec3 position) {
+    vec2 ndc = vec2(pc.proj.x * position.x, pc.proj.y * position.y) / -position.z;
+    return vec3(ndc * 0.5 + 0.5, (pc.proj.z * position.z + pc.proj.w) / -position.z);
+}
+
+vec3 viewPositionAt(ivec2 texel, ivec2 size) {
+    texel = clamp(texel, ivec2(0), size - 1);
+    vec2 uv = (vec2(texel) + 0.5) / vec2(size);
+    return viewPosition(uv, texelFetch(sceneDepth, texel, 0).r);
+}
+
+// Of the two neighbors along each axis, the closer one, so normals don't bend across edges
+vec3 viewNormal(ivec2 texel, ivec2 size, vec3 center) {
+    vec3 left = center - viewPositionAt(texel - ivec2(1, 0), size);
+    vec3 right = viewPositionAt(texel + ivec2(1, 0), size) - center;
+    vec3 down = center - viewPositionAt(texel - ivec2(0, 1), size);
+    vec3 up = viewPositionAt(texel + ivec2(0, 1), size) - center;
+    vec3 dx = abs(left.z) < abs(right.z) ? left : right;
+    vec3 dy = abs(down.z) < abs(up.z) ? down : up;
+    vec3 normal = normalize(cross(dy, dx));
+    return dot(normal, center) > 0.0 ? -normal : normal;
+}
+
+// Interleaved gradient noise, shifted every frame for the temporal filter to average
+float noise(vec2 pixel, float offset) {
+    pixel += offset * vec2(47.0, 17.0);
+    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+}
+
+void main() {
+    ivec2 size = textureSize(sceneDepth, 0);
+    ivec2 texel = min(ivec2(gl_FragCoord.xy) * 2, size - 1);
+    float depth = texelFetch(sceneDepth, texel, 0).r;
+    if (depth >= 1.0) {
+        outColor = vec4(0.0);
+        return;
+    }
+    vec3 position = viewPositionAt(texel, size);
+    vec3 normal = viewNormal(texel, size, position);
+    vec3 tangent = normalize(abs(normal.y) < 0.99 ? cross(normal, vec3(0.0, 1.0, 0.0)) : cross(normal, vec3(1.0, 0.0, 0.0)));
+    vec3 bitangent = cross(normal, tangent);
+
+    int rays = int(pc.params.x);
+    float frame = pc.params.w;
+    float jitter = noise(gl_FragCoord.xy, frame);
+    vec3 origin = position + normal * 0.02 * -position.z;
+    vec3 light = vec3(0.0);
+    for (int i = 0; i < rays; i++) {
+        // Cosine-weighted direction, rotated per pixel and frame
+        float u = fract((float(i) + 0.5) / float(rays) + jitter);
+        float v = noise(gl_FragCoord.xy + float(i) * 13.0, frame + 0.5);
+        float r = sqrt(u);
+        float phi = 2.0 * PI * v;
+        vec3 direction = tangent * (r * cos(phi)) + bitangent * (r * sin(phi)) + normal * sqrt(1.0 - u);
+
+        for (int step = 0; step < STEPS; step++) {
+            float t = pc.params.y * (float(step) + jitter) / float(STEPS);
+            vec3 sample_ = origin + direction * t;
+            if (sample_.z >= 0.0) {
+                break;
+            }
+            vec3 projected = project(sample_);
+            if (any(lessThan(projected.xy, vec2(0.0))) || any(greaterThan(projected.xy, vec2(1.0)))) {
+                break;
+            }
+            float sceneZ = viewPosition(projected.xy, texture(sceneDepth, projected.xy).r).z;
+            float behind = sceneZ - sample_.z;
+            if (behind > 0.0 && behind < pc.params.z) {
+                light += textureLod(sceneColor, projected.xy, 0.0).rgb;
+                break;
+            }
+        }
+    }
+    outColor = vec4(light / float(max(rays, 1)), 1.0);
+}
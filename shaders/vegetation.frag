@@ -0,0 +1,25 @@
+#version 450
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in float fragSway;
+
+layout(location = 0) out vec4 outColor;
+
+// Must match gltf_renderer::SUN_DIRECTION
+const vec3 SUN_DIRECTION = vec3(0.5, 1.0, 0.3);
+
+void main() {
+    // Blades are thin and two-sided: the side away from the sun glows through almost as
+    // brightly as the side facing it
+    vec3 normal = normalize(fragNormal);
+    vec3 light = normalize(SUN_DIRECTION);
+    float NdotL = dot(normal, light);
+    float diffuse = max(NdotL, 0.0) + max(-NdotL, 0.0) * 0.6;
+
+    // Darker at the root, where neighbours shade it
+    float occlusion = mix(0.35, 1.0, fragSway);
+    vec3 sky = vec3(0.35, 0.45, 0.6);
+    vec3 lit = fragColor * (sky * 0.6 + vec3(1.0, 0.95, 0.85) * diffuse * 0.8);
+    outColor = vec4(lit * occlusion, 1.0);
+}
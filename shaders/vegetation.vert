@@ -0,0 +1,62 @@
+#version 450
+
+// Scattered grass and foliage, one instance per plant. Vertices high up the plant sway in
+// the wind; plants shrink away between the fade distances so the layer ends softly.
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in float inSway; // 0 at the root, 1 at the tips
+
+layout(location = 3) in vec4 inPlacement; // xyz = root position, w = scale
+layout(location = 4) in vec4 inVariation; // x = rotation about Y, y = wind phase, z = tint
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj;
+    vec4 camera; // xyz = camera position, w = time
+    vec4 wind;   // xy = wind velocity over XZ (m/s), z = base sway, w = gust strength
+    vec4 fade;   // x = fade start, y = fade end (meters from the camera)
+    vec4 color;  // rgb = base color
+} pc;
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec3 fragNormal;
+layout(location = 2) out float fragSway;
+
+void main() {
+    vec3 root = inPlacement.xyz;
+    float distance = length(root - pc.camera.xyz);
+    float shrink = 1.0 - smoothstep(pc.fade.x, pc.fade.y, distance);
+    if (shrink <= 0.0) {
+        // Past the fade: collapse outside the clip volume
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        fragColor = vec3(0.0);
+        fragNormal = vec3(0.0, 1.0, 0.0);
+        fragSway = 0.0;
+        return;
+    }
+
+    float s = sin(inVariation.x);
+    float c = cos(inVariation.x);
+    mat3 rotation = mat3(c, 0.0, -s, 0.0, 1.0, 0.0, s, 0.0, c);
+    vec3 local = rotation * inPosition * inPlacement.w * shrink;
+    vec3 normal = rotation * inNormal;
+
+    // Lean with the wind, plus gusts rolling across the field along it
+    float speed = length(pc.wind.xy);
+    vec2 direction = speed > 0.001 ? pc.wind.xy / speed : vec2(1.0, 0.0);
+    float time = pc.camera.w;
+    float wave = dot(root.xz, direction) * 0.35 - time * (0.8 + speed * 0.15);
+    float gust = (sin(wave) * 0.5 + 0.5) * pc.wind.w;
+    float flutter = sin(time * 2.3 + inVariation.y) * pc.wind.z;
+    float bend = (min(speed * 0.04, 0.5) + gust + flutter) * inSway * inSway * inPlacement.w;
+    vec3 offset = vec3(direction.x, 0.0, direction.y) * bend;
+    // Keep the blade about its length as it bends over
+    offset.y = -0.5 * bend * bend / max(inPlacement.w, 0.001);
+
+    vec4 world = vec4(root + local + offset, 1.0);
+    gl_Position = pc.viewProj * world;
+
+    fragColor = pc.color.rgb * mix(0.75, 1.2, inVariation.z);
+    fragNormal = normalize(normal);
+    fragSway = inSway;
+}
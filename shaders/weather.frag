@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec2 fragCoord;
+layout(location = 1) in vec4 fragColor;
+layout(location = 2) flat in int fragSnow;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // Round flakes; streaks soft across and at their ends
+    float falloff = fragSnow == 1
+        ? 1.0 - dot(fragCoord, fragCoord)
+        : (1.0 - fragCoord.x * fragCoord.x) * (1.0 - pow(abs(fragCoord.y), 4.0));
+    if (falloff <= 0.0) {
+        discard;
+    }
+    outColor = vec4(fragColor.rgb * fragColor.a * falloff, 0.0);
+}
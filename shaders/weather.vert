@@ -0,0 +1,67 @@
+#version 450
+
+// Rain streaks along their velocity and snow flakes facing the camera, six vertices per
+// particle, expanded from the weather particle buffer (see weather.rs).
+
+struct Particle {
+    vec4 position; // xyz = position, w = age (seconds), negative while inactive
+    vec4 velocity; // xyz = velocity, w = age when it landed, negative while falling
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer Particles {
+    Particle particles[];
+} buf;
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj;
+    vec4 camera; // xyz = camera position, w = 1 for snow
+    vec4 params; // x = particle size, y = streak length per m/s, z = fade after landing (seconds)
+} pc;
+
+layout(location = 0) out vec2 fragCoord;
+layout(location = 1) out vec4 fragColor;
+layout(location = 2) flat out int fragSnow;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(1.0, 1.0), vec2(-1.0, 1.0), vec2(-1.0, -1.0)
+);
+
+void main() {
+    Particle p = buf.particles[gl_VertexIndex / 6];
+    vec2 corner = CORNERS[gl_VertexIndex % 6];
+    bool snow = pc.camera.w > 0.5;
+    fragSnow = snow ? 1 : 0;
+    fragCoord = corner;
+
+    if (p.position.w < 0.0) {
+        // Inactive: place outside the clip volume
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        fragColor = vec4(0.0);
+        return;
+    }
+
+    bool landed = p.velocity.w >= 0.0;
+    float fade = landed ? 1.0 - (p.position.w - p.velocity.w) / pc.params.z : 1.0;
+    vec3 toCamera = normalize(pc.camera.xyz - p.position.xyz);
+    float size = pc.params.x;
+
+    // Falling drops stretch along their velocity; landed ones splash into flat dots
+    vec3 axis;
+    if (!snow && !landed) {
+        axis = p.velocity.xyz * pc.params.y * 0.5;
+    } else {
+        vec3 right = normalize(cross(vec3(0.0, 1.0, 0.0), toCamera) + vec3(1e-4, 0.0, 0.0));
+        axis = normalize(cross(toCamera, right)) * size;
+    }
+    vec3 side = normalize(cross(axis, toCamera)) * size;
+    if (!snow && landed) {
+        side *= 2.0;
+        axis *= 0.5;
+    }
+    vec3 world = p.position.xyz + side * corner.x + axis * corner.y;
+
+    gl_Position = pc.viewProj * vec4(world, 1.0);
+    vec3 color = snow ? vec3(0.85, 0.87, 0.9) : vec3(0.5, 0.55, 0.62) * 0.5;
+    fragColor = vec4(color, clamp(fade, 0.0, 1.0));
+}
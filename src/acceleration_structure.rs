@@ -0,0 +1,393 @@
+//! Ray tracing acceleration structures
+//!
+//! On devices with `VK_KHR_ray_query` the scene geometry is built once into one
+//! bottom-level acceleration structure (BLAS) per mesh, and a top-level structure (TLAS)
+//! places an instance of each. Fragment shaders trace rays against the TLAS with ray
+//! queries, so no ray tracing pipeline or shader binding table is needed.
+//!
+//! The TLAS is rebuilt in the frame's command buffer only when an instance transform
+//! changes. All graphics work is submitted to one queue, so the barriers around the
+//! rebuild also order it against earlier frames still reading the old structure.
+//! BLAS are static: skinned meshes are traced in their bind pose.
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+use crate::compute::{self, Access};
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+
+/// Indexed triangle geometry for one BLAS. Positions must be three floats at the start
+/// of each vertex; both buffers need `SHADER_DEVICE_ADDRESS` and
+/// `ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR` usage.
+#[derive(Clone, Copy, Debug)]
+pub struct BlasGeometry {
+    pub vertex_buffer: vk::Buffer,
+    pub vertex_count: u32,
+    pub vertex_stride: u64,
+    pub index_buffer: vk::Buffer,
+    pub index_count: u32,
+}
+
+pub struct AccelerationStructure {
+    pub handle: vk::AccelerationStructureKHR,
+    pub buffer: vk::Buffer,
+    pub allocation: Option<Allocation>,
+    pub address: vk::DeviceAddress,
+}
+
+pub struct SceneAccelerationStructure {
+    pub loader: ash::khr::acceleration_structure::Device,
+    pub blases: Vec<AccelerationStructure>,
+    pub tlas: AccelerationStructure,
+    // Written by the host each rebuild, so one per frame in flight
+    instance_buffers: Vec<vk::Buffer>,
+    instance_allocations: Vec<Option<Allocation>>,
+    scratch_buffer: vk::Buffer,
+    scratch_allocation: Option<Allocation>,
+    scratch_address: vk::DeviceAddress,
+    // Transforms the TLAS was last built with (None = never built)
+    built_transforms: Option<Vec<glam::Mat4>>,
+}
+
+impl SceneAccelerationStructure {
+    /// Build a BLAS for every geometry. Returns `None` if the device has no ray query support.
+    ///
+    /// # Safety
+    ///
+    /// `geometries` must reference live vertex and index buffers with device addresses. Waits for
+    /// the builds on the graphics queue.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        geometries: &[BlasGeometry],
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if !renderer.ray_query_supported || geometries.is_empty() {
+            return Ok(None);
+        }
+        let device = &renderer.device;
+        let loader = ash::khr::acceleration_structure::Device::new(&renderer.instance, device);
+
+        let mut properties = vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut properties);
+        renderer
+            .instance
+            .get_physical_device_properties2(renderer.physical_device, &mut properties2);
+        let scratch_alignment = properties.min_acceleration_structure_scratch_offset_alignment as u64;
+
+        // Size every BLAS first so one scratch buffer can be shared by all builds
+        let triangle_geometries: Vec<vk::AccelerationStructureGeometryKHR> = geometries
+            .iter()
+            .map(|geometry| Self::triangle_geometry(device, geometry))
+            .collect();
+        let mut blas_sizes = Vec::new();
+        for (geometry, triangles) in geometries.iter().zip(&triangle_geometries) {
+            let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::default()
+                .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+                .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+                .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+                .geometries(std::slice::from_ref(triangles));
+            let mut sizes = vk::AccelerationStructureBuildSizesInfoKHR::default();
+            loader.get_acceleration_structure_build_sizes(
+                vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                &build_info,
+                &[geometry.index_count / 3],
+                &mut sizes,
+            );
+            blas_sizes.push(sizes);
+        }
+
+        // TLAS with one instance per BLAS
+        let instance_count = geometries.len() as u32;
+        let tlas_geometry = Self::instance_geometry(0);
+        let tlas_build_info = vk::AccelerationStructureBuildGeometryInfoKHR::default()
+            .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .geometries(std::slice::from_ref(&tlas_geometry));
+        let mut tlas_sizes = vk::AccelerationStructureBuildSizesInfoKHR::default();
+        loader.get_acceleration_structure_build_sizes(
+            vk::AccelerationStructureBuildTypeKHR::DEVICE,
+            &tlas_build_info,
+            &[instance_count],
+            &mut tlas_sizes,
+        );
+
+        let scratch_size = blas_sizes
+            .iter()
+            .map(|sizes| sizes.build_scratch_size)
+            .chain(std::iter::once(tlas_sizes.build_scratch_size))
+            .max()
+            .unwrap_or(0);
+        // Over-allocate so the address can be rounded up to the scratch alignment
+        let (scratch_buffer, scratch_allocation) = create_buffer(
+            renderer,
+            "acceleration_structure_scratch",
+            scratch_size + scratch_alignment,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            MemoryLocation::GpuOnly,
+        )?;
+        let scratch_address = buffer_address(device, scratch_buffer).next_multiple_of(scratch_alignment.max(1));
+
+        let mut blases = Vec::new();
+        for sizes in &blas_sizes {
+            blases.push(Self::create_structure(
+                renderer,
+                &loader,
+                vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL,
+                sizes.acceleration_structure_size,
+            )?);
+        }
+        let tlas = Self::create_structure(
+            renderer,
+            &loader,
+            vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+            tlas_sizes.acceleration_structure_size,
+        )?;
+
+        // Build all BLAS up front, one after another through the shared scratch buffer
+        let cmd_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(renderer.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let cmd = device.allocate_command_buffers(&cmd_info)?[0];
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(cmd, &begin_info)?;
+
+        for ((geometry, triangles), blas) in geometries.iter().zip(&triangle_geometries).zip(&blases) {
+            let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::default()
+                .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+                .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+                .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+                .dst_acceleration_structure(blas.handle)
+                .geometries(std::slice::from_ref(triangles))
+                .scratch_data(vk::DeviceOrHostAddressKHR { device_address: scratch_address });
+            let range = vk::AccelerationStructureBuildRangeInfoKHR::default()
+                .primitive_count(geometry.index_count / 3);
+            loader.cmd_build_acceleration_structures(cmd, &[build_info], &[std::slice::from_ref(&range)]);
+            compute::memory_barrier(device, cmd, Access::ACCELERATION_STRUCTURE_BUILD, Access::ACCELERATION_STRUCTURE_BUILD);
+        }
+
+        device.end_command_buffer(cmd)?;
+        let submit_info = vk::SubmitInfo::default().command_buffers(std::slice::from_ref(&cmd));
+        device.queue_submit(renderer.graphics_queue, &[submit_info], vk::Fence::null())?;
+        device.queue_wait_idle(renderer.graphics_queue)?;
+        device.free_command_buffers(renderer.command_pool, &[cmd]);
+
+        let instance_size = (std::mem::size_of::<vk::AccelerationStructureInstanceKHR>() * geometries.len()) as u64;
+        let mut instance_buffers = Vec::new();
+        let mut instance_allocations = Vec::new();
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let (buffer, allocation) = create_buffer(
+                renderer,
+                "tlas_instances",
+                instance_size,
+                vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                    | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+                MemoryLocation::CpuToGpu,
+            )?;
+            instance_buffers.push(buffer);
+            instance_allocations.push(Some(allocation));
+        }
+
+        let triangle_count: u32 = geometries.iter().map(|g| g.index_count / 3).sum();
+        println!("✓ Built {} BLAS ({} triangles) for ray queries", blases.len(), triangle_count);
+
+        Ok(Some(Self {
+            loader,
+            blases,
+            tlas,
+            instance_buffers,
+            instance_allocations,
+            scratch_buffer,
+            scratch_allocation: Some(scratch_allocation),
+            scratch_address,
+            built_transforms: None,
+        }))
+    }
+
+    /// Rebuild the TLAS if any instance moved. `transforms` has one entry per BLAS, in
+    /// the order the geometries were given to `new`. Record before any pass that traces
+    /// against the TLAS.
+    ///
+    /// # Safety
+    ///
+    /// `command_buffer` must be recording outside a render pass, and no submit still in flight may
+    /// use `frame_index`'s instance buffer.
+    pub unsafe fn update(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        transforms: &[glam::Mat4],
+    ) {
+        if self.built_transforms.as_deref() == Some(transforms) {
+            return;
+        }
+
+        let instances: Vec<vk::AccelerationStructureInstanceKHR> = self
+            .blases
+            .iter()
+            .zip(transforms)
+            .enumerate()
+            .map(|(i, (blas, transform))| {
+                // 3x4 row-major
+                let rows = transform.transpose().to_cols_array();
+                let mut matrix = [0.0; 12];
+                matrix.copy_from_slice(&rows[..12]);
+                vk::AccelerationStructureInstanceKHR {
+                    transform: vk::TransformMatrixKHR { matrix },
+                    instance_custom_index_and_mask: vk::Packed24_8::new(i as u32, 0xFF),
+                    instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
+                        0,
+                        vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8,
+                    ),
+                    acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
+                        device_handle: blas.address,
+                    },
+                }
+            })
+            .collect();
+        if let Some(allocation) = &self.instance_allocations[frame_index] {
+            let ptr = allocation.mapped_ptr().unwrap().as_ptr() as *mut vk::AccelerationStructureInstanceKHR;
+            std::ptr::copy_nonoverlapping(instances.as_ptr(), ptr, instances.len());
+        }
+
+        // Earlier frames may still be tracing against the TLAS being rebuilt
+        compute::memory_barrier(device, command_buffer, Access::FRAGMENT_RAY_QUERY, Access::ACCELERATION_STRUCTURE_BUILD);
+
+        let geometry = Self::instance_geometry(buffer_address(device, self.instance_buffers[frame_index]));
+        let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::default()
+            .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .dst_acceleration_structure(self.tlas.handle)
+            .geometries(std::slice::from_ref(&geometry))
+            .scratch_data(vk::DeviceOrHostAddressKHR { device_address: self.scratch_address });
+        let range = vk::AccelerationStructureBuildRangeInfoKHR::default()
+            .primitive_count(instances.len() as u32);
+        self.loader
+            .cmd_build_acceleration_structures(command_buffer, &[build_info], &[std::slice::from_ref(&range)]);
+
+        compute::memory_barrier(device, command_buffer, Access::ACCELERATION_STRUCTURE_BUILD, Access::FRAGMENT_RAY_QUERY);
+        self.built_transforms = Some(transforms.to_vec());
+    }
+
+    unsafe fn triangle_geometry<'a>(
+        device: &ash::Device,
+        geometry: &BlasGeometry,
+    ) -> vk::AccelerationStructureGeometryKHR<'a> {
+        let triangles = vk::AccelerationStructureGeometryTrianglesDataKHR::default()
+            .vertex_format(vk::Format::R32G32B32_SFLOAT)
+            .vertex_data(vk::DeviceOrHostAddressConstKHR {
+                device_address: buffer_address(device, geometry.vertex_buffer),
+            })
+            .vertex_stride(geometry.vertex_stride)
+            .max_vertex(geometry.vertex_count.saturating_sub(1))
+            .index_type(vk::IndexType::UINT32)
+            .index_data(vk::DeviceOrHostAddressConstKHR {
+                device_address: buffer_address(device, geometry.index_buffer),
+            });
+        vk::AccelerationStructureGeometryKHR::default()
+            .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+            .geometry(vk::AccelerationStructureGeometryDataKHR { triangles })
+            .flags(vk::GeometryFlagsKHR::OPAQUE)
+    }
+
+    fn instance_geometry<'a>(instance_address: vk::DeviceAddress) -> vk::AccelerationStructureGeometryKHR<'a> {
+        let instances = vk::AccelerationStructureGeometryInstancesDataKHR::default()
+            .array_of_pointers(false)
+            .data(vk::DeviceOrHostAddressConstKHR { device_address: instance_address });
+        vk::AccelerationStructureGeometryKHR::default()
+            .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+            .geometry(vk::AccelerationStructureGeometryDataKHR { instances })
+    }
+
+    unsafe fn create_structure(
+        renderer: &VulkanRenderer,
+        loader: &ash::khr::acceleration_structure::Device,
+        ty: vk::AccelerationStructureTypeKHR,
+        size: u64,
+    ) -> Result<AccelerationStructure, Box<dyn std::error::Error>> {
+        let (buffer, allocation) = create_buffer(
+            renderer,
+            "acceleration_structure",
+            size,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            MemoryLocation::GpuOnly,
+        )?;
+        let create_info = vk::AccelerationStructureCreateInfoKHR::default()
+            .buffer(buffer)
+            .size(size)
+            .ty(ty);
+        let handle = loader.create_acceleration_structure(&create_info, None)?;
+        let address = loader.get_acceleration_structure_device_address(
+            &vk::AccelerationStructureDeviceAddressInfoKHR::default().acceleration_structure(handle),
+        );
+        Ok(AccelerationStructure {
+            handle,
+            buffer,
+            allocation: Some(allocation),
+            address,
+        })
+    }
+
+    /// # Safety
+    ///
+    /// The device must be idle; `renderer` is the one the structures were built with.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let structures = self.blases.drain(..).chain(std::iter::once(std::mem::replace(
+            &mut self.tlas,
+            AccelerationStructure {
+                handle: vk::AccelerationStructureKHR::null(),
+                buffer: vk::Buffer::null(),
+                allocation: None,
+                address: 0,
+            },
+        )));
+        for mut structure in structures {
+            self.loader.destroy_acceleration_structure(structure.handle, None);
+            renderer.device.destroy_buffer(structure.buffer, None);
+            if let Some(alloc) = structure.allocation.take() {
+                let _ = renderer.allocator.lock().free(alloc);
+            }
+        }
+
+        let buffers = self.instance_buffers.drain(..).chain(std::iter::once(self.scratch_buffer));
+        for buffer in buffers {
+            renderer.device.destroy_buffer(buffer, None);
+        }
+        let allocations = self.instance_allocations.drain(..).chain(std::iter::once(self.scratch_allocation.take()));
+        for alloc in allocations.flatten() {
+            let _ = renderer.allocator.lock().free(alloc);
+        }
+        self.built_transforms = None;
+    }
+}
+
+unsafe fn buffer_address(device: &ash::Device, buffer: vk::Buffer) -> vk::DeviceAddress {
+    device.get_buffer_device_address(&vk::BufferDeviceAddressInfo::default().buffer(buffer))
+}
+
+unsafe fn create_buffer(
+    renderer: &VulkanRenderer,
+    name: &str,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+    location: MemoryLocation,
+) -> Result<(vk::Buffer, Allocation), Box<dyn std::error::Error>> {
+    let buffer_info = vk::BufferCreateInfo::default()
+        .size(size)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = renderer.device.create_buffer(&buffer_info, None)?;
+    let requirements = renderer.device.get_buffer_memory_requirements(buffer);
+    let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+        name,
+        requirements,
+        location,
+        linear: true,
+        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+    })?;
+    renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+    Ok((buffer, allocation))
+}
@@ -0,0 +1,225 @@
+//! Keyframe animation of ECS transforms
+//!
+//! A `TransformTrack` holds position, rotation and scale keyframes, each eased into the
+//! next one. Tracks are shared through an `Arc`, so any number of `AnimationPlayer`s can
+//! play the same turntable or door swing at their own time and speed. `animation_system`
+//! advances every playing player and writes the sampled channels into its `Transform`;
+//! channels without keyframes leave that part of the transform alone.
+
+use bevy_ecs::prelude::*;
+use glam::{Quat, Vec3};
+use std::sync::Arc;
+
+use crate::fixed_timestep::{FixedTime, TransformInterpolation};
+use crate::Transform;
+
+/// How a keyframe blends into the next one
+#[allow(dead_code)] // The demo props don't use every curve
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Easing {
+    /// Hold the value until the next keyframe
+    Step,
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Remap the normalized time `t` (0..1) between two keyframes
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Step => 0.0,
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe<T> {
+    pub time: f32, // Seconds from the start of the track
+    pub value: T,
+    pub easing: Easing, // Towards the following keyframe
+}
+
+/// Values a track channel can interpolate
+pub trait Animatable: Copy {
+    fn interpolate(self, other: Self, t: f32) -> Self;
+}
+
+impl Animatable for Vec3 {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+impl Animatable for Quat {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.slerp(other, t)
+    }
+}
+
+/// Keyframes for the three transform channels, kept sorted by time
+#[derive(Clone, Debug, Default)]
+pub struct TransformTrack {
+    pub position: Vec<Keyframe<Vec3>>,
+    pub rotation: Vec<Keyframe<Quat>>,
+    pub scale: Vec<Keyframe<Vec3>>,
+}
+
+impl TransformTrack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_position(mut self, time: f32, value: Vec3, easing: Easing) -> Self {
+        insert_keyframe(&mut self.position, Keyframe { time, value, easing });
+        self
+    }
+
+    pub fn with_rotation(mut self, time: f32, value: Quat, easing: Easing) -> Self {
+        insert_keyframe(&mut self.rotation, Keyframe { time, value, easing });
+        self
+    }
+
+    pub fn with_scale(mut self, time: f32, value: Vec3, easing: Easing) -> Self {
+        insert_keyframe(&mut self.scale, Keyframe { time, value, easing });
+        self
+    }
+
+    /// A full turn around +Y every `period` seconds, for looping playback. Slerp takes the
+    /// short way round, so the turn is split into quarters.
+    pub fn turntable(period: f32) -> Self {
+        (0..=4).fold(Self::new(), |track, i| {
+            let angle = i as f32 * std::f32::consts::FRAC_PI_2;
+            track.with_rotation(period * i as f32 / 4.0, Quat::from_rotation_y(angle), Easing::Linear)
+        })
+    }
+
+    /// Time of the last keyframe in any channel
+    pub fn duration(&self) -> f32 {
+        let last = |times: &mut dyn Iterator<Item = f32>| times.last().unwrap_or(0.0);
+        last(&mut self.position.iter().map(|k| k.time))
+            .max(last(&mut self.rotation.iter().map(|k| k.time)))
+            .max(last(&mut self.scale.iter().map(|k| k.time)))
+    }
+
+    /// Write the channels that have keyframes, sampled at `time`, into `transform`
+    pub fn sample(&self, time: f32, transform: &mut Transform) {
+        if let Some(position) = sample_channel(&self.position, time) {
+            transform.position = position;
+        }
+        if let Some(rotation) = sample_channel(&self.rotation, time) {
+            transform.rotation = rotation.normalize();
+        }
+        if let Some(scale) = sample_channel(&self.scale, time) {
+            transform.scale = scale;
+        }
+    }
+}
+
+fn insert_keyframe<T>(keyframes: &mut Vec<Keyframe<T>>, keyframe: Keyframe<T>) {
+    let index = keyframes.partition_point(|k| k.time <= keyframe.time);
+    keyframes.insert(index, keyframe);
+}
+
+fn sample_channel<T: Animatable>(keyframes: &[Keyframe<T>], time: f32) -> Option<T> {
+    let first = keyframes.first()?;
+    let next = keyframes.partition_point(|k| k.time <= time);
+    if next == 0 {
+        return Some(first.value);
+    }
+    let from = &keyframes[next - 1];
+    let Some(to) = keyframes.get(next) else {
+        return Some(from.value);
+    };
+    let t = (time - from.time) / (to.time - from.time).max(f32::EPSILON);
+    Some(from.value.interpolate(to.value, from.easing.apply(t)))
+}
+
+/// What happens when playback reaches either end of the track
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RepeatMode {
+    /// Stop on the last (or, playing backwards, first) frame
+    #[default]
+    Once,
+    Loop,
+    /// Bounce back and forth between the ends
+    PingPong,
+}
+
+/// Plays a `TransformTrack` on the entity's `Transform`
+#[derive(Component, Clone)]
+#[require(TransformInterpolation)]
+pub struct AnimationPlayer {
+    pub track: Arc<TransformTrack>,
+    pub time: f32,
+    pub speed: f32, // Negative plays backwards
+    pub repeat: RepeatMode,
+    pub playing: bool,
+}
+
+impl AnimationPlayer {
+    pub fn new(track: Arc<TransformTrack>) -> Self {
+        Self { track, time: 0.0, speed: 1.0, repeat: RepeatMode::Once, playing: true }
+    }
+
+    pub fn with_repeat(mut self, repeat: RepeatMode) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    #[allow(dead_code)] // Part of the scripting API, the demo props play at normal speed
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Move the playhead by `dt` seconds of wall time, honouring speed and repeat mode
+    pub fn advance(&mut self, dt: f32) {
+        if !self.playing {
+            return;
+        }
+        let duration = self.track.duration();
+        if duration <= 0.0 {
+            self.playing = false; // A single pose, nothing to play
+            return;
+        }
+        self.time += dt * self.speed;
+        match self.repeat {
+            RepeatMode::Once => {
+                if !(0.0..=duration).contains(&self.time) {
+                    self.time = self.time.clamp(0.0, duration);
+                    self.playing = false;
+                }
+            }
+            RepeatMode::Loop => self.time = self.time.rem_euclid(duration),
+            RepeatMode::PingPong => {
+                let bounced = self.time.rem_euclid(2.0 * duration);
+                if bounced > duration {
+                    self.time = 2.0 * duration - bounced;
+                    self.speed = -self.speed;
+                } else {
+                    self.time = bounced;
+                }
+            }
+        }
+    }
+}
+
+/// Advance every playing `AnimationPlayer` by a fixed step and apply its track
+pub fn animation_system(fixed_time: Res<FixedTime>, mut query: Query<(&mut Transform, &mut AnimationPlayer)>) {
+    let dt = fixed_time.step;
+    for (mut transform, mut player) in query.iter_mut() {
+        if !player.playing {
+            continue;
+        }
+        player.advance(dt);
+        player.track.sample(player.time, &mut transform);
+    }
+}
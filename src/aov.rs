@@ -0,0 +1,351 @@
+//! Auxiliary output (AOV) export
+//!
+//! With `--export-aovs [dir]`, every frame the main camera's view of the glTF scene is
+//! rendered again offscreen, once with the regular scene pass (the beauty image) and once
+//! with `aov.frag` into four targets, and all five are written to `dir`:
+//!
+//! - `frame_NNNNN_beauty.png`: the lit scene, as in the window
+//! - `frame_NNNNN_albedo.png`: unlit base color (texture times vertex color)
+//! - `frame_NNNNN_normal.exr`: world-space normals, zero where nothing was drawn
+//! - `frame_NNNNN_depth.exr`: view-space distance in every channel, zero where nothing was drawn
+//! - `frame_NNNNN_id.png`: 16-bit object IDs; 0 = background, 1 = ground, 2 + i = mesh i
+//!
+//! Like stills, the export covers the glTF scene only and stalls the GPU every frame, so
+//! it is meant for producing datasets rather than interactive use.
+
+use ash::vk;
+use gpu_allocator::vulkan::Allocation;
+use std::path::{Path, PathBuf};
+
+use crate::command_encoder::{CommandEncoder, PipelineBinding};
+use crate::gltf_renderer::{GltfPushConstants, GltfRenderer, GltfVertex, GltfView, ViewCamera};
+use crate::offscreen::{self, OffscreenTarget};
+use crate::pipeline_builder::{DepthMode, GraphicsPipelineBuilder};
+use crate::renderer::VulkanRenderer;
+use crate::shader_compiler::load_shader;
+
+const ALBEDO_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+const NORMAL_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+const DEPTH_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+const ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+const DEPTH_BUFFER_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// Render pass, pipeline and targets for AOV export at one resolution
+pub struct AovPass {
+    pub extent: vk::Extent2D,
+    render_pass: vk::RenderPass,
+    pipeline: vk::Pipeline,
+    // Albedo, normal, depth, object ID, then the depth buffer
+    images: Vec<vk::Image>,
+    views: Vec<vk::ImageView>,
+    allocations: Vec<Option<Allocation>>,
+    framebuffer: vk::Framebuffer,
+    beauty: OffscreenTarget,
+    view: Option<GltfView>,
+    dir: PathBuf,
+}
+
+impl AovPass {
+    /// Create the pass for frames of `extent`, writing into `dir` (created if missing)
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        gltf_renderer: &GltfRenderer,
+        extent: vk::Extent2D,
+        dir: &Path,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(dir)?;
+        let device = &renderer.device;
+
+        let render_pass = create_render_pass(device)?;
+        let pipeline = match create_pipeline(device, render_pass, gltf_renderer.pipeline_layout) {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                device.destroy_render_pass(render_pass, None);
+                return Err(e);
+            }
+        };
+        let beauty = match OffscreenTarget::new(renderer, gltf_renderer.render_pass, extent) {
+            Ok(beauty) => beauty,
+            Err(e) => {
+                device.destroy_pipeline(pipeline, None);
+                device.destroy_render_pass(render_pass, None);
+                return Err(e);
+            }
+        };
+
+        let mut pass = Self {
+            extent,
+            render_pass,
+            pipeline,
+            images: Vec::new(),
+            views: Vec::new(),
+            allocations: Vec::new(),
+            framebuffer: vk::Framebuffer::null(),
+            beauty,
+            view: None,
+            dir: dir.to_path_buf(),
+        };
+        if let Err(e) = pass.create_targets(renderer, gltf_renderer) {
+            pass.destroy(renderer, gltf_renderer);
+            return Err(e);
+        }
+
+        println!("✓ AOV export: {}x{} frames to {}", extent.width, extent.height, dir.display());
+        Ok(pass)
+    }
+
+    unsafe fn create_targets(
+        &mut self,
+        renderer: &VulkanRenderer,
+        gltf_renderer: &GltfRenderer,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let color_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC;
+        let targets = [
+            ("aov_albedo", ALBEDO_FORMAT, color_usage, vk::ImageAspectFlags::COLOR),
+            ("aov_normal", NORMAL_FORMAT, color_usage, vk::ImageAspectFlags::COLOR),
+            ("aov_depth", DEPTH_FORMAT, color_usage, vk::ImageAspectFlags::COLOR),
+            ("aov_id", ID_FORMAT, color_usage, vk::ImageAspectFlags::COLOR),
+            (
+                "aov_depth_buffer",
+                DEPTH_BUFFER_FORMAT,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                vk::ImageAspectFlags::DEPTH,
+            ),
+        ];
+        for (name, format, usage, aspect) in targets {
+            let (image, view, allocation) = offscreen::create_image(renderer, name, format, self.extent, usage, aspect)?;
+            self.images.push(image);
+            self.views.push(view);
+            self.allocations.push(Some(allocation));
+        }
+
+        let framebuffer_info = vk::FramebufferCreateInfo::default()
+            .render_pass(self.render_pass)
+            .attachments(&self.views)
+            .width(self.extent.width)
+            .height(self.extent.height)
+            .layers(1);
+        self.framebuffer = renderer.device.create_framebuffer(&framebuffer_info, None)?;
+
+        self.view = Some(gltf_renderer.create_view(renderer, self.extent)?);
+        Ok(())
+    }
+
+    pub fn view(&self) -> Option<&GltfView> {
+        self.view.as_ref()
+    }
+
+    /// Render `camera`'s view and write the beauty image and AOVs of frame `frame_number`.
+    /// Waits for the device to go idle first and leaves it idle.
+    pub unsafe fn export_frame(
+        &mut self,
+        renderer: &VulkanRenderer,
+        gltf_renderer: &GltfRenderer,
+        camera: &ViewCamera,
+        frame_number: u64,
+        (debug_cascades, shadow_softness, use_pcss): (bool, f32, bool),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let view = self.view.as_mut().ok_or("AOV view was not created")?;
+
+        device.device_wait_idle()?;
+        view.layers = gltf_renderer.capture_layers();
+        gltf_renderer.update_view_uniform_buffer(view, 0, camera, debug_cascades, shadow_softness, use_pcss);
+
+        let cmd_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(renderer.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let cmd = device.allocate_command_buffers(&cmd_info)?[0];
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(cmd, &begin_info)?;
+
+        gltf_renderer.render_view(device, cmd, view, self.beauty.framebuffer, 0);
+
+        let clear_values = [
+            vk::ClearValue { color: vk::ClearColorValue { float32: [0.0; 4] } },
+            vk::ClearValue { color: vk::ClearColorValue { float32: [0.0; 4] } },
+            vk::ClearValue { color: vk::ClearColorValue { float32: [0.0; 4] } },
+            vk::ClearValue { color: vk::ClearColorValue { uint32: [0; 4] } },
+            vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } },
+        ];
+        let mut encoder = CommandEncoder::new(device, cmd);
+        let pipeline = PipelineBinding {
+            pipeline: self.pipeline,
+            layout: gltf_renderer.pipeline_layout,
+            render_pass: self.render_pass,
+            push_constant_size: std::mem::size_of::<GltfPushConstants>() as u32,
+        };
+        let mut pass = encoder.begin_render_pass(self.render_pass, self.framebuffer, self.extent, &clear_values);
+        gltf_renderer.draw_object_ids(&mut pass, self.extent, view.descriptor_sets[0], pipeline, view.layers);
+        drop(pass);
+
+        device.end_command_buffer(cmd)?;
+        let command_buffers = [cmd];
+        let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+        device.queue_submit(renderer.graphics_queue, &[submit_info], vk::Fence::null())?;
+        device.queue_wait_idle(renderer.graphics_queue)?;
+        device.free_command_buffers(renderer.command_pool, &command_buffers);
+
+        let (width, height) = (self.extent.width, self.extent.height);
+        let layout = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+        let path = |name: &str, ext: &str| self.dir.join(format!("frame_{:05}_{}.{}", frame_number, name, ext));
+
+        let beauty = self.beauty.read_rgba8(renderer, vk::ImageLayout::PRESENT_SRC_KHR)?;
+        image::RgbaImage::from_raw(width, height, beauty)
+            .ok_or("Beauty pixel buffer has the wrong size")?
+            .save(path("beauty", "png"))?;
+
+        let albedo = offscreen::read_image(renderer, self.images[0], self.extent, 4, layout)?;
+        image::RgbaImage::from_raw(width, height, albedo)
+            .ok_or("Albedo pixel buffer has the wrong size")?
+            .save(path("albedo", "png"))?;
+
+        let normal: Vec<f32> = floats(&offscreen::read_image(renderer, self.images[1], self.extent, 16, layout)?)
+            .chunks_exact(4)
+            .flat_map(|n| [n[0], n[1], n[2]])
+            .collect();
+        image::DynamicImage::ImageRgb32F(
+            image::Rgb32FImage::from_raw(width, height, normal).ok_or("Normal pixel buffer has the wrong size")?,
+        )
+        .save(path("normal", "exr"))?;
+
+        // EXR via `image` needs RGB(A); replicate the single channel
+        let depth: Vec<f32> = floats(&offscreen::read_image(renderer, self.images[2], self.extent, 4, layout)?)
+            .into_iter()
+            .flat_map(|d| [d; 3])
+            .collect();
+        image::DynamicImage::ImageRgb32F(
+            image::Rgb32FImage::from_raw(width, height, depth).ok_or("Depth pixel buffer has the wrong size")?,
+        )
+        .save(path("depth", "exr"))?;
+
+        let ids: Vec<u16> = offscreen::read_image(renderer, self.images[3], self.extent, 4, layout)?
+            .chunks_exact(4)
+            .map(|id| u32::from_ne_bytes([id[0], id[1], id[2], id[3]]).min(u16::MAX as u32) as u16)
+            .collect();
+        image::ImageBuffer::<image::Luma<u16>, _>::from_raw(width, height, ids)
+            .ok_or("Object ID pixel buffer has the wrong size")?
+            .save(path("id", "png"))?;
+
+        Ok(())
+    }
+
+    /// Destroy the pass. The device must be idle.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer, gltf_renderer: &GltfRenderer) {
+        let device = &renderer.device;
+        if let Some(view) = self.view.take() {
+            let _ = gltf_renderer.destroy_view(renderer, view);
+        }
+        self.beauty.destroy(renderer);
+        device.destroy_framebuffer(self.framebuffer, None);
+        for &view in &self.views {
+            device.destroy_image_view(view, None);
+        }
+        for &image in &self.images {
+            device.destroy_image(image, None);
+        }
+        for allocation in self.allocations.drain(..).flatten() {
+            let _ = renderer.allocator.lock().free(allocation);
+        }
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_render_pass(self.render_pass, None);
+    }
+}
+
+fn floats(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+unsafe fn create_render_pass(device: &ash::Device) -> Result<vk::RenderPass, vk::Result> {
+    let color_attachment = |format| {
+        vk::AttachmentDescription::default()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+    };
+    let attachments = [
+        color_attachment(ALBEDO_FORMAT),
+        color_attachment(NORMAL_FORMAT),
+        color_attachment(DEPTH_FORMAT),
+        color_attachment(ID_FORMAT),
+        vk::AttachmentDescription::default()
+            .format(DEPTH_BUFFER_FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+    ];
+
+    let color_refs: Vec<vk::AttachmentReference> = (0..4)
+        .map(|attachment| vk::AttachmentReference {
+            attachment,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        })
+        .collect();
+    let depth_ref = vk::AttachmentReference {
+        attachment: 4,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+
+    let subpass = vk::SubpassDescription::default()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_refs)
+        .depth_stencil_attachment(&depth_ref);
+
+    let dependency = vk::SubpassDependency::default()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        )
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        )
+        .dst_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        );
+
+    let render_pass_info = vk::RenderPassCreateInfo::default()
+        .attachments(&attachments)
+        .subpasses(std::slice::from_ref(&subpass))
+        .dependencies(std::slice::from_ref(&dependency));
+
+    device.create_render_pass(&render_pass_info, None)
+}
+
+/// gltf.vert + aov.frag on the glTF pipeline layout, so the scene's descriptor sets and
+/// push constants apply unchanged
+unsafe fn create_pipeline(
+    device: &ash::Device,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+    let vert_code = load_shader("gltf.vert", include_bytes!("../shaders/gltf.vert.spv"));
+    let frag_code = load_shader("aov.frag", include_bytes!("../shaders/aov.frag.spv"));
+
+    // Data, not color: no blending on any target
+    GraphicsPipelineBuilder::new(pipeline_layout, render_pass)
+        .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+        .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+        .vertex_buffer::<GltfVertex>()
+        .color_targets(4)
+        .depth(DepthMode::ReadWrite(vk::CompareOp::LESS))
+        .build(device)
+}
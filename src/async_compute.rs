@@ -0,0 +1,253 @@
+//! Async compute
+//!
+//! When the device exposes a compute-only queue family, independent compute work is
+//! recorded into its own per-frame command buffers and submitted there, so it runs
+//! alongside the shadow and opaque passes instead of in front of them. Each submit
+//! signals a semaphore that the *next* graphics submit waits on: consumers read results
+//! one frame behind and the graphics queue never stalls on the current frame's compute.
+//!
+//! Timestamps are written at both ends of the compute and graphics command buffers so
+//! the overlap actually gained can be shown in the UI.
+
+use ash::vk;
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+
+/// GPU timings of the last completed frame, in milliseconds
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AsyncComputeStats {
+    pub compute_ms: f32,
+    pub graphics_ms: f32,
+    pub overlap_ms: f32,
+}
+
+pub struct AsyncCompute {
+    pub queue: vk::Queue,
+    pub queue_family_index: u32,
+    pub command_pool: vk::CommandPool,
+    pub command_buffers: Vec<vk::CommandBuffer>,
+    pub finished_semaphores: Vec<vk::Semaphore>,
+    pub fences: Vec<vk::Fence>,
+    pub stats: AsyncComputeStats,
+
+    // Two timestamps (begin/end) per frame in flight on each queue
+    compute_queries: vk::QueryPool,
+    graphics_queries: vk::QueryPool,
+    timestamp_period: f32,
+    timestamps_supported: bool,
+    queries_pending: Vec<bool>,
+
+    // Semaphore from the most recent submit, waited on by the next graphics submit
+    last_signaled: Option<vk::Semaphore>,
+}
+
+impl AsyncCompute {
+    /// Returns `None` if the device has no compute-only queue family.
+    ///
+    /// # Safety
+    ///
+    /// `renderer` must outlive the returned object.
+    pub unsafe fn new(renderer: &VulkanRenderer) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let (queue, queue_family_index) = match (renderer.compute_queue, renderer.compute_queue_family_index) {
+            (Some(queue), Some(index)) => (queue, index),
+            _ => return Ok(None),
+        };
+        let device = &renderer.device;
+
+        let pool_info = vk::CommandPoolCreateInfo::default()
+            .queue_family_index(queue_family_index)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let command_pool = device.create_command_pool(&pool_info, None)?;
+
+        let alloc_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(MAX_FRAMES_IN_FLIGHT as u32);
+        let command_buffers = device.allocate_command_buffers(&alloc_info)?;
+
+        let semaphore_info = vk::SemaphoreCreateInfo::default();
+        let fence_info = vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
+        let mut finished_semaphores = Vec::new();
+        let mut fences = Vec::new();
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            finished_semaphores.push(device.create_semaphore(&semaphore_info, None)?);
+            fences.push(device.create_fence(&fence_info, None)?);
+        }
+
+        // Timestamps need valid bits on both queue families to compare the two timelines
+        let properties = renderer.instance.get_physical_device_properties(renderer.physical_device);
+        let families = renderer
+            .instance
+            .get_physical_device_queue_family_properties(renderer.physical_device);
+        let timestamps_supported = properties.limits.timestamp_compute_and_graphics == vk::TRUE
+            && families[queue_family_index as usize].timestamp_valid_bits > 0
+            && families[renderer.graphics_queue_family_index as usize].timestamp_valid_bits > 0;
+
+        let query_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(2 * MAX_FRAMES_IN_FLIGHT as u32);
+        let compute_queries = device.create_query_pool(&query_info, None)?;
+        let graphics_queries = device.create_query_pool(&query_info, None)?;
+
+        if !timestamps_supported {
+            println!("⚠ Timestamps unavailable on the compute queue, async overlap won't be measured");
+        }
+
+        Ok(Some(Self {
+            queue,
+            queue_family_index,
+            command_pool,
+            command_buffers,
+            finished_semaphores,
+            fences,
+            stats: AsyncComputeStats::default(),
+            compute_queries,
+            graphics_queries,
+            timestamp_period: properties.limits.timestamp_period,
+            timestamps_supported,
+            queries_pending: vec![false; MAX_FRAMES_IN_FLIGHT],
+            last_signaled: None,
+        }))
+    }
+
+    /// Wait for this frame slot's previous compute submit and start recording a new one.
+    ///
+    /// # Safety
+    ///
+    /// The graphics fence for `frame_index` must have been waited on, so no graphics work still
+    /// reads this slot's results.
+    pub unsafe fn begin(
+        &mut self,
+        device: &ash::Device,
+        frame_index: usize,
+    ) -> Result<vk::CommandBuffer, vk::Result> {
+        device.wait_for_fences(&[self.fences[frame_index]], true, u64::MAX)?;
+        self.read_timestamps(device, frame_index);
+
+        let command_buffer = self.command_buffers[frame_index];
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(command_buffer, &begin_info)?;
+
+        if self.timestamps_supported {
+            let first = 2 * frame_index as u32;
+            device.cmd_reset_query_pool(command_buffer, self.compute_queries, first, 2);
+            device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, self.compute_queries, first);
+        }
+
+        Ok(command_buffer)
+    }
+
+    /// Submit the compute work recorded since `begin`. Returns the semaphore signaled by
+    /// the *previous* submit, which the graphics submit for this frame should wait on.
+    ///
+    /// # Safety
+    ///
+    /// Call once per `begin`, with the command buffer it returned no longer recording anything
+    /// else.
+    pub unsafe fn submit(
+        &mut self,
+        device: &ash::Device,
+        frame_index: usize,
+    ) -> Result<Option<vk::Semaphore>, vk::Result> {
+        let command_buffer = self.command_buffers[frame_index];
+        if self.timestamps_supported {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.compute_queries,
+                2 * frame_index as u32 + 1,
+            );
+        }
+        device.end_command_buffer(command_buffer)?;
+
+        let command_buffers = [command_buffer];
+        let signal_semaphores = [self.finished_semaphores[frame_index]];
+        let submit_info = vk::SubmitInfo::default()
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+
+        device.reset_fences(&[self.fences[frame_index]])?;
+        device.queue_submit(self.queue, &[submit_info], self.fences[frame_index])?;
+
+        Ok(self.last_signaled.replace(self.finished_semaphores[frame_index]))
+    }
+
+    /// Reset and write the graphics-side begin timestamp; call first in the frame's
+    /// graphics command buffer.
+    ///
+    /// # Safety
+    ///
+    /// `command_buffer` must be recording outside a render pass.
+    pub unsafe fn begin_graphics_timing(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        if !self.timestamps_supported {
+            return;
+        }
+        let first = 2 * frame_index as u32;
+        device.cmd_reset_query_pool(command_buffer, self.graphics_queries, first, 2);
+        device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, self.graphics_queries, first);
+    }
+
+    /// Write the graphics-side end timestamp, after the passes compute should overlap with.
+    ///
+    /// # Safety
+    ///
+    /// `command_buffer` must be recording outside a render pass, after `begin_graphics_timing` for
+    /// the same `frame_index`.
+    pub unsafe fn end_graphics_timing(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        if !self.timestamps_supported {
+            return;
+        }
+        device.cmd_write_timestamp(
+            command_buffer,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            self.graphics_queries,
+            2 * frame_index as u32 + 1,
+        );
+        self.queries_pending[frame_index] = true;
+    }
+
+    unsafe fn read_timestamps(&mut self, device: &ash::Device, frame_index: usize) {
+        if !self.queries_pending[frame_index] {
+            return;
+        }
+        self.queries_pending[frame_index] = false;
+
+        let first = 2 * frame_index as u32;
+        let mut compute = [0u64; 2];
+        let mut graphics = [0u64; 2];
+        let flags = vk::QueryResultFlags::TYPE_64;
+        if device.get_query_pool_results(self.compute_queries, first, &mut compute, flags).is_err()
+            || device.get_query_pool_results(self.graphics_queries, first, &mut graphics, flags).is_err()
+        {
+            return;
+        }
+
+        let to_ms = |ticks: u64| ticks as f32 * self.timestamp_period / 1_000_000.0;
+        let overlap_start = compute[0].max(graphics[0]);
+        let overlap_end = compute[1].min(graphics[1]);
+
+        // Smooth over a few frames so the readout is legible
+        let blend = |old: f32, new: f32| old * 0.9 + new * 0.1;
+        self.stats.compute_ms = blend(self.stats.compute_ms, to_ms(compute[1].saturating_sub(compute[0])));
+        self.stats.graphics_ms = blend(self.stats.graphics_ms, to_ms(graphics[1].saturating_sub(graphics[0])));
+        self.stats.overlap_ms = blend(self.stats.overlap_ms, to_ms(overlap_end.saturating_sub(overlap_start)));
+    }
+
+    /// # Safety
+    ///
+    /// The device must be idle.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        device.destroy_query_pool(self.compute_queries, None);
+        device.destroy_query_pool(self.graphics_queries, None);
+        for &semaphore in &self.finished_semaphores {
+            device.destroy_semaphore(semaphore, None);
+        }
+        for &fence in &self.fences {
+            device.destroy_fence(fence, None);
+        }
+        device.destroy_command_pool(self.command_pool, None);
+        self.last_signaled = None;
+    }
+}
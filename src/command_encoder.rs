@@ -0,0 +1,331 @@
+//! Command encoders
+//!
+//! `CommandEncoder` wraps a command buffer that is being recorded, and `RenderPassEncoder`
+//! one render pass in it, with a mostly safe API over `device.cmd_*`. Creating an encoder
+//! is the unsafe step: the caller vouches that the command buffer is recording and that
+//! every handle passed in later stays valid until the commands have executed. From there
+//! the encoder
+//!
+//! - tracks the bound pipeline and its layout, so descriptor sets and push constants are
+//!   recorded against the right layout,
+//! - skips redundant binds through a [`StateCache`] shared by all passes of the encoder
+//!   (bindings outlive render passes),
+//! - counts draws and binds in the [`draw_stats`],
+//! - in debug builds checks that pipelines were built for the render pass being recorded
+//!   and that push constants fit the pipeline layout's range.
+//!
+//! Barriers, queries and extension commands still go through `device()` and
+//! `command_buffer()`.
+
+use ash::vk;
+use crate::draw_stats;
+use crate::state_cache::StateCache;
+
+/// What an encoder needs to know to bind and validate a graphics pipeline
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PipelineBinding {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    pub render_pass: vk::RenderPass, // The pipeline was created for (a pass compatible with) this one
+    pub push_constant_size: u32,     // Bytes covered by the layout's push constant range
+}
+
+pub struct CommandEncoder<'a> {
+    device: &'a ash::Device,
+    command_buffer: vk::CommandBuffer,
+    state: StateCache,
+    pipeline: Option<PipelineBinding>,
+}
+
+impl<'a> CommandEncoder<'a> {
+    /// Encoder for `command_buffer`
+    ///
+    /// # Safety
+    ///
+    /// `command_buffer` must be recording and belong to `device`. Other code must not bind
+    /// graphics state in it while the encoder is in use.
+    pub unsafe fn new(device: &'a ash::Device, command_buffer: vk::CommandBuffer) -> Self {
+        Self { device, command_buffer, state: StateCache::default(), pipeline: None }
+    }
+
+    pub fn device(&self) -> &'a ash::Device {
+        self.device
+    }
+
+    pub fn command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    /// Begin `render_pass` on `framebuffer` over `extent`; the pass ends when the returned
+    /// encoder is dropped
+    pub fn begin_render_pass(
+        &mut self,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        extent: vk::Extent2D,
+        clear_values: &[vk::ClearValue],
+    ) -> RenderPassEncoder<'_, 'a> {
+        let render_pass_info = vk::RenderPassBeginInfo::default()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent })
+            .clear_values(clear_values);
+        unsafe {
+            self.device
+                .cmd_begin_render_pass(self.command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+        }
+        RenderPassEncoder { encoder: self, render_pass, ends_pass: true }
+    }
+
+    /// Record into an instance of `render_pass` that other code has begun and will end
+    ///
+    /// # Safety
+    ///
+    /// An instance of `render_pass` must be active in the command buffer until the returned encoder
+    /// is dropped.
+    pub unsafe fn continue_render_pass(&mut self, render_pass: vk::RenderPass) -> RenderPassEncoder<'_, 'a> {
+        RenderPassEncoder { encoder: self, render_pass, ends_pass: false }
+    }
+}
+
+pub struct RenderPassEncoder<'e, 'a> {
+    encoder: &'e mut CommandEncoder<'a>,
+    render_pass: vk::RenderPass,
+    ends_pass: bool,
+}
+
+impl RenderPassEncoder<'_, '_> {
+    pub fn command_buffer(&self) -> vk::CommandBuffer {
+        self.encoder.command_buffer
+    }
+
+    /// The render pass being recorded
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    pub fn bind_pipeline(&mut self, pipeline: PipelineBinding) {
+        debug_assert_eq!(
+            pipeline.render_pass, self.render_pass,
+            "pipeline was created for another render pass than the one being recorded"
+        );
+        let encoder = &mut *self.encoder;
+        unsafe { encoder.state.bind_pipeline(encoder.device, encoder.command_buffer, pipeline.pipeline) };
+        encoder.pipeline = Some(pipeline);
+    }
+
+    /// Bind `descriptor_set` as set number `set` of the bound pipeline's layout
+    pub fn bind_descriptor_set(&mut self, set: u32, descriptor_set: vk::DescriptorSet) {
+        let encoder = &mut *self.encoder;
+        let layout = encoder.pipeline.expect("bind a pipeline before its descriptor sets").layout;
+        unsafe {
+            encoder
+                .state
+                .bind_descriptor_set(encoder.device, encoder.command_buffer, layout, set, descriptor_set)
+        };
+    }
+
+    /// Read set number `set` of the bound pipeline's layout from `offset` into descriptor
+    /// buffer `buffer`, which must have been created for it (see descriptor_buffer.rs).
+    /// Descriptor sets bound before are disturbed, so the next ones are bound again.
+    pub fn bind_descriptor_buffer(
+        &mut self,
+        loader: &ash::ext::descriptor_buffer::Device,
+        buffer: &vk::DescriptorBufferBindingInfoEXT,
+        set: u32,
+        offset: vk::DeviceSize,
+    ) {
+        let encoder = &mut *self.encoder;
+        let layout = encoder.pipeline.expect("bind a pipeline before its descriptors").layout;
+        unsafe {
+            loader.cmd_bind_descriptor_buffers(encoder.command_buffer, std::slice::from_ref(buffer));
+            loader.cmd_set_descriptor_buffer_offsets(
+                encoder.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                layout,
+                set,
+                &[0],
+                &[offset],
+            );
+        }
+        draw_stats::state_changes(1);
+        encoder.state.forget_descriptor_sets();
+    }
+
+    /// Record `writes` as set number `set` of the bound pipeline's layout, whose set layout
+    /// must have been created with `PUSH_DESCRIPTOR_KHR`. The writes' `dst_set` is ignored.
+    pub fn push_descriptor_set(
+        &mut self,
+        loader: &ash::khr::push_descriptor::Device,
+        set: u32,
+        writes: &[vk::WriteDescriptorSet],
+    ) {
+        let encoder = &mut *self.encoder;
+        let layout = encoder.pipeline.expect("bind a pipeline before its descriptors").layout;
+        unsafe {
+            loader.cmd_push_descriptor_set(encoder.command_buffer, vk::PipelineBindPoint::GRAPHICS, layout, set, writes)
+        };
+        draw_stats::state_changes(1);
+        encoder.state.forget_descriptor_set(set);
+    }
+
+    /// Bind `buffers` at `offsets` to the vertex bindings starting at 0
+    pub fn bind_vertex_buffers(&mut self, buffers: &[vk::Buffer], offsets: &[vk::DeviceSize]) {
+        let encoder = &mut *self.encoder;
+        unsafe { encoder.state.bind_vertex_buffers(encoder.device, encoder.command_buffer, buffers, offsets) };
+    }
+
+    pub fn bind_index_buffer(&mut self, buffer: vk::Buffer, offset: vk::DeviceSize, index_type: vk::IndexType) {
+        let encoder = &mut *self.encoder;
+        unsafe {
+            encoder
+                .state
+                .bind_index_buffer(encoder.device, encoder.command_buffer, buffer, offset, index_type)
+        };
+    }
+
+    pub fn set_viewport(&mut self, viewport: vk::Viewport) {
+        let encoder = &mut *self.encoder;
+        unsafe { encoder.state.set_viewport(encoder.device, encoder.command_buffer, viewport) };
+    }
+
+    pub fn set_scissor(&mut self, scissor: vk::Rect2D) {
+        let encoder = &mut *self.encoder;
+        unsafe { encoder.state.set_scissor(encoder.device, encoder.command_buffer, scissor) };
+    }
+
+    /// Viewport and scissor covering all of `extent`
+    pub fn set_full_viewport(&mut self, extent: vk::Extent2D) {
+        self.set_viewport(vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        });
+        self.set_scissor(vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent });
+    }
+
+    /// Push `data` at `offset` of the bound pipeline layout's push constant range
+    pub fn push_constants<T: Copy>(&mut self, stages: vk::ShaderStageFlags, offset: u32, data: &T) {
+        let pipeline = self.encoder.pipeline.expect("bind a pipeline before pushing constants");
+        let size = std::mem::size_of::<T>() as u32;
+        debug_assert!(
+            offset + size <= pipeline.push_constant_size,
+            "{} bytes of push constants at offset {} overflow the layout's {} byte range",
+            size,
+            offset,
+            pipeline.push_constant_size
+        );
+        unsafe {
+            let bytes = std::slice::from_raw_parts((data as *const T) as *const u8, size as usize);
+            self.encoder
+                .device
+                .cmd_push_constants(self.encoder.command_buffer, pipeline.layout, stages, offset, bytes);
+        }
+    }
+
+    pub fn draw(&mut self, vertex_count: u32, instance_count: u32) {
+        self.check_draw();
+        unsafe { self.encoder.device.cmd_draw(self.encoder.command_buffer, vertex_count, instance_count, 0, 0) };
+        draw_stats::draw(instance_count, vertex_count as u64 / 3);
+    }
+
+    /// Draw `index_count` indices from `first_index` of the bound index buffer
+    pub fn draw_indexed(&mut self, index_count: u32, instance_count: u32, first_index: u32) {
+        self.check_draw();
+        unsafe {
+            self.encoder
+                .device
+                .cmd_draw_indexed(self.encoder.command_buffer, index_count, instance_count, first_index, 0, 0)
+        };
+        draw_stats::draw(instance_count, index_count as u64 / 3);
+    }
+
+    /// One instance of `index_count` indices from `first_index`, numbered `first_instance`
+    /// (`gl_InstanceIndex`), which the glTF shaders read as the draw's texture array slot
+    pub fn draw_indexed_from_instance(&mut self, index_count: u32, first_index: u32, first_instance: u32) {
+        self.check_draw();
+        unsafe {
+            self.encoder.device.cmd_draw_indexed(
+                self.encoder.command_buffer,
+                index_count,
+                1,
+                first_index,
+                0,
+                first_instance,
+            )
+        };
+        draw_stats::draw(1, index_count as u64 / 3);
+    }
+
+    /// `draw_count` indexed indirect draws from `buffer` at `offset`
+    pub fn draw_indexed_indirect(&mut self, (buffer, offset): (vk::Buffer, vk::DeviceSize), draw_count: u32) {
+        self.check_draw();
+        let stride = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        unsafe {
+            self.encoder
+                .device
+                .cmd_draw_indexed_indirect(self.encoder.command_buffer, buffer, offset, draw_count, stride)
+        };
+        draw_stats::indirect_draw();
+    }
+
+    /// Up to `max_draw_count` indexed indirect draws from `buffer` at `offset`, with the
+    /// actual count read from `count_buffer` at `count_offset`
+    pub fn draw_indexed_indirect_count(
+        &mut self,
+        (buffer, offset): (vk::Buffer, vk::DeviceSize),
+        (count_buffer, count_offset): (vk::Buffer, vk::DeviceSize),
+        max_draw_count: u32,
+    ) {
+        self.check_draw();
+        let stride = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        unsafe {
+            self.encoder.device.cmd_draw_indexed_indirect_count(
+                self.encoder.command_buffer,
+                buffer,
+                offset,
+                count_buffer,
+                count_offset,
+                max_draw_count,
+                stride,
+            )
+        };
+        draw_stats::indirect_draw();
+    }
+
+    /// Clear `rects` of color attachment `attachment` to `color` without drawing
+    pub fn clear_color_rects(&mut self, attachment: u32, color: [f32; 4], rects: &[vk::Rect2D]) {
+        if rects.is_empty() {
+            return;
+        }
+        let attachments = [vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            color_attachment: attachment,
+            clear_value: vk::ClearValue { color: vk::ClearColorValue { float32: color } },
+        }];
+        let rects: Vec<_> = rects
+            .iter()
+            .map(|&rect| vk::ClearRect { rect, base_array_layer: 0, layer_count: 1 })
+            .collect();
+        unsafe { self.encoder.device.cmd_clear_attachments(self.encoder.command_buffer, &attachments, &rects) };
+    }
+
+    /// Draws need a pipeline for this render pass
+    fn check_draw(&self) {
+        debug_assert!(
+            self.encoder.pipeline.is_some_and(|pipeline| pipeline.render_pass == self.render_pass),
+            "draw without a pipeline bound for this render pass"
+        );
+    }
+}
+
+impl Drop for RenderPassEncoder<'_, '_> {
+    fn drop(&mut self) {
+        if self.ends_pass {
+            unsafe { self.encoder.device.cmd_end_render_pass(self.encoder.command_buffer) };
+        }
+    }
+}
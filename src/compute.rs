@@ -0,0 +1,372 @@
+//! Compute pipelines
+//!
+//! Compute work is recorded into the same command buffers as graphics (the renderer
+//! picks a queue family that supports both). Descriptor set layouts and workgroup
+//! sizes are reflected from the SPIR-V; the barrier helpers cover the usual hand-offs
+//! between compute writes and graphics/transfer reads.
+
+use ash::vk;
+use crate::renderer::VulkanRenderer;
+use crate::shader_reflection::{self, ShaderReflection};
+use std::ffi::CString;
+
+/// A pipeline stage + access mask pair, one side of a barrier
+#[derive(Clone, Copy, Debug)]
+pub struct Access {
+    pub stage: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+impl Access {
+    pub const COMPUTE_WRITE: Self = Self {
+        stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        access: vk::AccessFlags::SHADER_WRITE,
+    };
+    pub const COMPUTE_READ: Self = Self {
+        stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        access: vk::AccessFlags::SHADER_READ,
+    };
+    /// Atomics and other read-modify-writes in compute shaders
+    pub const COMPUTE_READ_WRITE: Self = Self {
+        stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        access: vk::AccessFlags::from_raw(vk::AccessFlags::SHADER_READ.as_raw() | vk::AccessFlags::SHADER_WRITE.as_raw()),
+    };
+    pub const VERTEX_INPUT: Self = Self {
+        stage: vk::PipelineStageFlags::VERTEX_INPUT,
+        access: vk::AccessFlags::from_raw(
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ.as_raw() | vk::AccessFlags::INDEX_READ.as_raw(),
+        ),
+    };
+    pub const INDIRECT: Self = Self {
+        stage: vk::PipelineStageFlags::DRAW_INDIRECT,
+        access: vk::AccessFlags::INDIRECT_COMMAND_READ,
+    };
+    pub const VERTEX_SHADER_READ: Self = Self {
+        stage: vk::PipelineStageFlags::VERTEX_SHADER,
+        access: vk::AccessFlags::SHADER_READ,
+    };
+    pub const TRANSFER_WRITE: Self = Self {
+        stage: vk::PipelineStageFlags::TRANSFER,
+        access: vk::AccessFlags::TRANSFER_WRITE,
+    };
+    pub const TRANSFER_READ: Self = Self {
+        stage: vk::PipelineStageFlags::TRANSFER,
+        access: vk::AccessFlags::TRANSFER_READ,
+    };
+    pub const COLOR_ATTACHMENT_WRITE: Self = Self {
+        stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+    };
+    pub const ACCELERATION_STRUCTURE_BUILD: Self = Self {
+        stage: vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+        access: vk::AccessFlags::from_raw(
+            vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR.as_raw()
+                | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR.as_raw(),
+        ),
+    };
+    /// Ray queries in fragment shaders
+    pub const FRAGMENT_RAY_QUERY: Self = Self {
+        stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+        access: vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+    };
+}
+
+pub struct ComputePipeline {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_pool: vk::DescriptorPool,
+    pub local_size: [u32; 3],
+}
+
+impl ComputePipeline {
+    /// Build a compute pipeline from SPIR-V. Set 0 is reflected from the shader and a
+    /// descriptor pool with room for `max_sets` sets is created alongside it.
+    /// `push_constant_size` is the size of the CPU-side push-constant struct (0 if none).
+    ///
+    /// # Safety
+    ///
+    /// `code` must be valid SPIR-V for a compute shader.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        code: &[u32],
+        push_constant_size: u32,
+        max_sets: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let reflection = ShaderReflection::reflect(code)?;
+        if reflection.stage != vk::ShaderStageFlags::COMPUTE {
+            return Err(format!("Expected a compute shader, got {:?}", reflection.stage).into());
+        }
+
+        // Descriptor set layout (set 0)
+        let bindings = shader_reflection::set_layout_bindings(&[&reflection], 0)?;
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        let descriptor_set_layout = device.create_descriptor_set_layout(&layout_info, None)?;
+
+        // Pipeline layout
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(&[&reflection], push_constant_size)?
+            .into_iter()
+            .collect();
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(std::slice::from_ref(&descriptor_set_layout))
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None)?;
+
+        // Pipeline
+        let module_info = vk::ShaderModuleCreateInfo::default().code(code);
+        let module = device.create_shader_module(&module_info, None)?;
+        let main_name = CString::new("main")?;
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(&main_name);
+        let pipeline_info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(pipeline_layout);
+        let pipeline = device
+            .create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+            .map_err(|(_, e)| e)?[0];
+        device.destroy_shader_module(module, None);
+
+        // Descriptor pool sized from the reflected bindings
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = bindings
+            .iter()
+            .map(|b| vk::DescriptorPoolSize {
+                ty: b.descriptor_type,
+                descriptor_count: b.descriptor_count * max_sets.max(1),
+            })
+            .collect();
+        let descriptor_pool = if pool_sizes.is_empty() {
+            vk::DescriptorPool::null()
+        } else {
+            let pool_info = vk::DescriptorPoolCreateInfo::default()
+                .pool_sizes(&pool_sizes)
+                .max_sets(max_sets.max(1));
+            device.create_descriptor_pool(&pool_info, None)?
+        };
+
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+            descriptor_set_layout,
+            descriptor_pool,
+            local_size: reflection.local_size,
+        })
+    }
+
+    /// # Safety
+    ///
+    /// `device` must be the one the pipeline was created on.
+    pub unsafe fn allocate_descriptor_set(&self, device: &ash::Device) -> Result<vk::DescriptorSet, vk::Result> {
+        let alloc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(std::slice::from_ref(&self.descriptor_set_layout));
+        Ok(device.allocate_descriptor_sets(&alloc_info)?[0])
+    }
+
+    /// Record a dispatch of `group_count` workgroups
+    ///
+    /// # Safety
+    ///
+    /// `command_buffer` must be recording outside a render pass and `descriptor_set` must come from
+    /// `allocate_descriptor_set`.
+    pub unsafe fn dispatch(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        descriptor_set: vk::DescriptorSet,
+        push_constants: &[u8],
+        group_count: [u32; 3],
+    ) {
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        if descriptor_set != vk::DescriptorSet::null() {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+        }
+        if !push_constants.is_empty() {
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                push_constants,
+            );
+        }
+        device.cmd_dispatch(command_buffer, group_count[0], group_count[1], group_count[2]);
+    }
+
+    /// Dispatch enough workgroups to cover `threads` invocations (rounded up to the
+    /// shader's `local_size`)
+    ///
+    /// # Safety
+    ///
+    /// As for `dispatch`.
+    pub unsafe fn dispatch_threads(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        descriptor_set: vk::DescriptorSet,
+        push_constants: &[u8],
+        threads: [u32; 3],
+    ) {
+        let groups = [
+            threads[0].div_ceil(self.local_size[0]),
+            threads[1].div_ceil(self.local_size[1]),
+            threads[2].div_ceil(self.local_size[2]),
+        ];
+        self.dispatch(device, command_buffer, descriptor_set, push_constants, groups);
+    }
+
+    /// # Safety
+    ///
+    /// No command buffer using the pipeline or its descriptor sets may still be executing.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        if self.descriptor_pool != vk::DescriptorPool::null() {
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+    }
+}
+
+/// Collects buffer/image bindings and writes them into a descriptor set in one call
+#[derive(Default)]
+pub struct DescriptorWriter {
+    buffers: Vec<(u32, vk::DescriptorType, vk::DescriptorBufferInfo)>,
+    images: Vec<(u32, vk::DescriptorType, vk::DescriptorImageInfo)>,
+}
+
+impl DescriptorWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn storage_buffer(mut self, binding: u32, buffer: vk::Buffer) -> Self {
+        self.buffers.push((binding, vk::DescriptorType::STORAGE_BUFFER, whole_buffer(buffer)));
+        self
+    }
+
+    /// Storage buffer bound to part of a buffer, such as a frame arena slice
+    pub fn storage_buffer_range(mut self, binding: u32, info: vk::DescriptorBufferInfo) -> Self {
+        self.buffers.push((binding, vk::DescriptorType::STORAGE_BUFFER, info));
+        self
+    }
+
+    pub fn sampled_image(mut self, binding: u32, view: vk::ImageView, sampler: vk::Sampler) -> Self {
+        self.images.push((
+            binding,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorImageInfo {
+                sampler,
+                image_view: view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+        ));
+        self
+    }
+
+    /// # Safety
+    ///
+    /// `set` must not be in use by a command buffer that is pending execution, and the written
+    /// resources must stay alive while it is.
+    pub unsafe fn write(&self, device: &ash::Device, set: vk::DescriptorSet) {
+        let mut writes = Vec::with_capacity(self.buffers.len() + self.images.len());
+        for (binding, ty, info) in &self.buffers {
+            writes.push(
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(*binding)
+                    .descriptor_type(*ty)
+                    .buffer_info(std::slice::from_ref(info)),
+            );
+        }
+        for (binding, ty, info) in &self.images {
+            writes.push(
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(*binding)
+                    .descriptor_type(*ty)
+                    .image_info(std::slice::from_ref(info)),
+            );
+        }
+        device.update_descriptor_sets(&writes, &[]);
+    }
+}
+
+fn whole_buffer(buffer: vk::Buffer) -> vk::DescriptorBufferInfo {
+    vk::DescriptorBufferInfo {
+        buffer,
+        offset: 0,
+        range: vk::WHOLE_SIZE,
+    }
+}
+
+/// Global memory barrier (all buffers and images) between two accesses
+///
+/// # Safety
+///
+/// `command_buffer` must be recording outside a render pass.
+pub unsafe fn memory_barrier(device: &ash::Device, command_buffer: vk::CommandBuffer, src: Access, dst: Access) {
+    let barrier = vk::MemoryBarrier::default()
+        .src_access_mask(src.access)
+        .dst_access_mask(dst.access);
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src.stage,
+        dst.stage,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[],
+        &[],
+    );
+}
+
+/// Layout transition + barrier on all mips/layers of a color image
+///
+/// # Safety
+///
+/// `command_buffer` must be recording outside a render pass, and `old_layout` must be the layout
+/// `image` is in when the barrier executes.
+pub unsafe fn image_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src: Access,
+    dst: Access,
+) {
+    let barrier = vk::ImageMemoryBarrier::default()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_access_mask(src.access)
+        .dst_access_mask(dst.access)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: vk::REMAINING_MIP_LEVELS,
+            base_array_layer: 0,
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
+        });
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src.stage,
+        dst.stage,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[barrier],
+    );
+}
@@ -1,16 +1,25 @@
 use ash::vk;
-use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
-use gpu_allocator::MemoryLocation;
+use gpu_allocator::vulkan::Allocation;
+use crate::command_encoder::{CommandEncoder, PipelineBinding, RenderPassEncoder};
+use crate::frame_arena::ArenaSlice;
+use crate::pipeline_builder::{DepthMode, GraphicsPipelineBuilder};
 use crate::renderer::{VulkanRenderer, Vertex, UniformBufferObject, MAX_FRAMES_IN_FLIGHT};
+use crate::shader_compiler::load_shader;
+use crate::upload::BufferUploader;
 
 pub struct CubeRenderer {
     pub vertex_buffer: vk::Buffer,
     pub vertex_allocation: Option<Allocation>,
     pub index_buffer: vk::Buffer,
     pub index_allocation: Option<Allocation>,
-    pub uniform_buffers: Vec<vk::Buffer>,
-    pub uniform_allocations: Vec<Option<Allocation>>,
+    pub uniform_sets: Vec<vk::DescriptorSet>, // Per frame in flight, UBO lives in the frame arena
     pub index_count: u32,
+    
+    // Instanced drawing (one model matrix per ECS entity)
+    pub instanced_pipeline: vk::Pipeline,
+    instanced_render_pass: vk::RenderPass,
+    pub instance_slices: Vec<Option<ArenaSlice>>, // Per frame in flight, in the frame arena
+    pub instance_count: u32,
 }
 
 impl CubeRenderer {
@@ -61,106 +70,65 @@ impl CubeRenderer {
             20, 21, 22, 22, 23, 20,  // Left
         ];
         
-        // Create vertex buffer
-        let (vertex_buffer, vertex_allocation) = Self::create_buffer(
-            renderer,
-            std::mem::size_of_val(&vertices) as u64,
-            vk::BufferUsageFlags::VERTEX_BUFFER,
-            &vertices,
-        )?;
-        
-        // Create index buffer
-        let (index_buffer, index_allocation) = Self::create_buffer(
-            renderer,
-            std::mem::size_of_val(&indices) as u64,
-            vk::BufferUsageFlags::INDEX_BUFFER,
-            &indices,
-        )?;
-        
-        // Create uniform buffers (one per frame in flight)
-        let mut uniform_buffers = Vec::new();
-        let mut uniform_allocations = Vec::new();
-        
-        let ubo_size = std::mem::size_of::<UniformBufferObject>() as u64;
-        for i in 0..MAX_FRAMES_IN_FLIGHT {
-            let buffer_info = vk::BufferCreateInfo::default()
-                .size(ubo_size)
-                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE);
-            
-            let buffer = renderer.device.create_buffer(&buffer_info, None)?;
-            let requirements = renderer.device.get_buffer_memory_requirements(buffer);
-            
-            let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
-                name: &format!("Uniform Buffer {}", i),
-                requirements,
-                location: MemoryLocation::CpuToGpu,
-                linear: true,
-                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-            })?;
-            
-            renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
-            
-            // Update descriptor sets
-            let buffer_info_desc = vk::DescriptorBufferInfo {
-                buffer,
-                offset: 0,
-                range: ubo_size,
-            };
-            
-            let descriptor_write = vk::WriteDescriptorSet::default()
-                .dst_set(renderer.descriptor_sets[i])
-                .dst_binding(0)
-                .dst_array_element(0)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .buffer_info(std::slice::from_ref(&buffer_info_desc));
-            
-            renderer.device.update_descriptor_sets(&[descriptor_write], &[]);
-            
-            uniform_buffers.push(buffer);
-            uniform_allocations.push(Some(allocation));
-        }
+        // Vertex and index buffers, in device-local memory
+        let mut uploader = BufferUploader::new();
+        let (vertex_buffer, vertex_allocation) =
+            uploader.upload(renderer, "cube_vertex_buffer", &vertices, vk::BufferUsageFlags::VERTEX_BUFFER)?;
+        let (index_buffer, index_allocation) =
+            uploader.upload(renderer, "cube_index_buffer", &indices, vk::BufferUsageFlags::INDEX_BUFFER)?;
+        uploader.finish(renderer)?;
         
         Ok(Self {
             vertex_buffer,
             vertex_allocation: Some(vertex_allocation),
             index_buffer,
             index_allocation: Some(index_allocation),
-            uniform_buffers,
-            uniform_allocations,
+            uniform_sets: vec![vk::DescriptorSet::null(); MAX_FRAMES_IN_FLIGHT],
             index_count: indices.len() as u32,
+            instanced_pipeline: vk::Pipeline::null(),
+            instanced_render_pass: vk::RenderPass::null(),
+            instance_slices: vec![None; MAX_FRAMES_IN_FLIGHT],
+            instance_count: 0,
         })
     }
     
-    unsafe fn create_buffer<T: Copy>(
+    /// Create the instanced cube pipeline for `render_pass`. With `depth_tested` the pass has
+    /// a depth attachment (e.g. the glTF scene pass), so cubes are depth-tested against the
+    /// rest of the scene; without, cubes cover each other in the order they are drawn. Back
+    /// faces are culled, or without a depth buffer a cube's far side would cover its near one.
+    ///
+    /// # Safety
+    ///
+    /// `render_pass` must stay alive as long as the pipeline. Any previous instanced pipeline must
+    /// no longer be in use.
+    pub unsafe fn create_instanced_pipeline(
+        &mut self,
         renderer: &VulkanRenderer,
-        size: u64,
-        usage: vk::BufferUsageFlags,
-        data: &[T],
-    ) -> Result<(vk::Buffer, Allocation), Box<dyn std::error::Error>> {
-        let buffer_info = vk::BufferCreateInfo::default()
-            .size(size)
-            .usage(usage)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
-        
-        let buffer = renderer.device.create_buffer(&buffer_info, None)?;
-        let requirements = renderer.device.get_buffer_memory_requirements(buffer);
-        
-        let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
-            name: "Buffer",
-            requirements,
-            location: MemoryLocation::CpuToGpu,
-            linear: true,
-            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-        })?;
-        
-        renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
-        
-        // Copy data
-        let mapped = allocation.mapped_ptr().unwrap().as_ptr() as *mut T;
-        std::ptr::copy_nonoverlapping(data.as_ptr(), mapped, data.len());
+        render_pass: vk::RenderPass,
+        depth_tested: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        
+        let vert_code = load_shader("cube_instanced.vert", include_bytes!("../shaders/cube_instanced.vert.spv"));
+        let frag_code = load_shader("cube.frag", include_bytes!("../shaders/cube.frag.spv"));
+        
+        // Binding 0: cube vertices, binding 1: per-instance model matrices
+        let pipeline = GraphicsPipelineBuilder::new(renderer.pipeline_layout, render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+            .vertex_buffer::<Vertex>()
+            .instance_buffer::<glam::Mat4>()
+            .cull_mode(vk::CullModeFlags::BACK)
+            .depth(if depth_tested { DepthMode::ReadWrite(vk::CompareOp::LESS) } else { DepthMode::Disabled })
+            .build(device)?;
+        
+        if self.instanced_pipeline != vk::Pipeline::null() {
+            device.destroy_pipeline(self.instanced_pipeline, None);
+        }
+        self.instanced_pipeline = pipeline;
+        self.instanced_render_pass = render_pass;
         
-        Ok((buffer, allocation))
+        Ok(())
     }
     
     pub unsafe fn update_uniform_buffer(
@@ -209,57 +177,129 @@ impl CubeRenderer {
             light_dir: glam::Vec4::new(light_dir.x, light_dir.y, light_dir.z, 0.0),
         };
         
-        if let Some(ref allocation) = self.uniform_allocations[frame_index] {
-            let mapped = allocation.mapped_ptr().unwrap().as_ptr() as *mut UniformBufferObject;
-            std::ptr::copy_nonoverlapping(&ubo, mapped, 1);
-        }
-        
-        Ok(())
+        self.upload_ubo(renderer, frame_index, &ubo)
     }
     
-    pub unsafe fn draw(
-        &self,
+    /// Update the per-frame UBO for instanced drawing. Uses the same yaw/pitch convention
+    /// as `GltfRenderer` so cubes line up with the rest of the scene. `proj` is the camera's
+    /// projection, with Y already flipped for Vulkan.
+    ///
+    /// # Safety
+    ///
+    /// The frame's in-flight fence must have been waited on, so no GPU work still reads
+    /// `frame_index`'s uniform buffer.
+    pub unsafe fn update_camera(
+        &mut self,
         renderer: &VulkanRenderer,
-        command_buffer: vk::CommandBuffer,
         frame_index: usize,
-    ) -> Result<(), vk::Result> {
-        renderer.device.cmd_bind_pipeline(
-            command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            renderer.graphics_pipeline,
-        );
+        camera_pos: glam::Vec3,
+        camera_yaw: f32,
+        camera_pitch: f32,
+        proj: glam::Mat4,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let camera_front = glam::Vec3::new(
+            camera_yaw.cos() * camera_pitch.cos(),
+            camera_pitch.sin(),
+            camera_yaw.sin() * camera_pitch.cos(),
+        ).normalize();
         
-        let viewport = vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: renderer.swapchain_extent.width as f32,
-            height: renderer.swapchain_extent.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0,
-        };
-        renderer.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        let view = glam::Mat4::look_at_rh(camera_pos, camera_pos + camera_front, glam::Vec3::Y);
+        
+        // Match the glTF sun direction
+        let light_dir = glam::Vec3::new(0.5, 1.0, 0.3).normalize();
         
-        let scissor = vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: renderer.swapchain_extent,
+        let ubo = UniformBufferObject {
+            model: glam::Mat4::IDENTITY, // per-instance model comes from the instance buffer
+            view,
+            proj,
+            camera_pos: camera_pos.extend(0.0),
+            light_dir: light_dir.extend(0.0),
         };
-        renderer.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
         
-        renderer.device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer], &[0]);
-        renderer.device.cmd_bind_index_buffer(command_buffer, self.index_buffer, 0, vk::IndexType::UINT16);
+        self.upload_ubo(renderer, frame_index, &ubo)
+    }
+    
+    /// Write this frame's UBO to the frame arena and point a transient descriptor set at it.
+    /// Call after `VulkanRenderer::frame_arena` has begun the frame.
+    unsafe fn upload_ubo(
+        &mut self,
+        renderer: &VulkanRenderer,
+        frame_index: usize,
+        ubo: &UniformBufferObject,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // The previous set of this slot was freed with the arena's pool
+        self.uniform_sets[frame_index] = vk::DescriptorSet::null();
+        self.uniform_sets[frame_index] = renderer
+            .frame_arena
+            .uniform_set(renderer.descriptor_set_layout, ubo)
+            .ok_or("frame arena allocation failed")?;
+        Ok(())
+    }
+    
+    /// Upload per-instance model matrices for this frame into the frame arena
+    ///
+    /// # Safety
+    ///
+    /// `VulkanRenderer::frame_arena` must have begun `frame_index`.
+    pub unsafe fn update_instances(
+        &mut self,
+        renderer: &VulkanRenderer,
+        frame_index: usize,
+        models: &[glam::Mat4],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.instance_count = models.len() as u32;
+        self.instance_slices[frame_index] = None;
+        if models.is_empty() {
+            return Ok(());
+        }
         
-        renderer.device.cmd_bind_descriptor_sets(
-            command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            renderer.pipeline_layout,
-            0,
-            &[renderer.descriptor_sets[frame_index]],
-            &[],
-        );
+        self.instance_slices[frame_index] = Some(renderer.frame_arena.push(models).ok_or("frame arena allocation failed")?);
+        Ok(())
+    }
+    
+    /// Cube `pipeline` (built on the renderer's layout, without push constants) for
+    /// `render_pass`
+    fn pipeline_binding(renderer: &VulkanRenderer, pipeline: vk::Pipeline, render_pass: vk::RenderPass) -> PipelineBinding {
+        PipelineBinding { pipeline, layout: renderer.pipeline_layout, render_pass, push_constant_size: 0 }
+    }
+    
+    /// Draw all instances uploaded for this frame. Must be called inside the render pass the
+    /// instanced pipeline was created for.
+    pub fn draw_instanced(
+        &self,
+        renderer: &VulkanRenderer,
+        pass: &mut RenderPassEncoder,
+        extent: vk::Extent2D,
+        frame_index: usize,
+    ) {
+        let (Some(instances), uniform_set) = (self.instance_slices[frame_index], self.uniform_sets[frame_index]) else {
+            return;
+        };
+        if self.instance_count == 0 || self.instanced_pipeline == vk::Pipeline::null() || uniform_set == vk::DescriptorSet::null() {
+            return;
+        }
         
-        renderer.device.cmd_draw_indexed(command_buffer, self.index_count, 1, 0, 0, 0);
+        pass.bind_pipeline(Self::pipeline_binding(renderer, self.instanced_pipeline, self.instanced_render_pass));
+        pass.set_full_viewport(extent);
+        pass.bind_vertex_buffers(&[self.vertex_buffer, instances.buffer], &[0, instances.offset]);
+        pass.bind_index_buffer(self.index_buffer, 0, vk::IndexType::UINT16);
+        pass.bind_descriptor_set(0, uniform_set);
+        pass.draw_indexed(self.index_count, self.instance_count, 0);
+    }
+    
+    /// Draw the single cube with the renderer's own pipeline, inside its render pass
+    pub fn draw(&self, renderer: &VulkanRenderer, pass: &mut RenderPassEncoder, frame_index: usize) {
+        // Nothing to draw with if the UBO didn't fit in the frame arena
+        if self.uniform_sets[frame_index] == vk::DescriptorSet::null() {
+            return;
+        }
         
-        Ok(())
+        pass.bind_pipeline(Self::pipeline_binding(renderer, renderer.graphics_pipeline, renderer.render_pass));
+        pass.set_full_viewport(renderer.swapchain_extent);
+        pass.bind_vertex_buffers(&[self.vertex_buffer], &[0]);
+        pass.bind_index_buffer(self.index_buffer, 0, vk::IndexType::UINT16);
+        pass.bind_descriptor_set(0, self.uniform_sets[frame_index]);
+        pass.draw_indexed(self.index_count, 1, 0);
     }
     
     pub unsafe fn record_commands(
@@ -278,74 +318,29 @@ impl CubeRenderer {
             },
         }];
         
-        let render_pass_info = vk::RenderPassBeginInfo::default()
-            .render_pass(renderer.render_pass)
-            .framebuffer(framebuffer)
-            .render_area(vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: renderer.swapchain_extent,
-            })
-            .clear_values(&clear_values);
-        
-        renderer.device.cmd_begin_render_pass(
-            command_buffer,
-            &render_pass_info,
-            vk::SubpassContents::INLINE,
-        );
-        
-        renderer.device.cmd_bind_pipeline(
-            command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            renderer.graphics_pipeline,
+        let mut encoder = CommandEncoder::new(&renderer.device, command_buffer);
+        let mut pass = encoder.begin_render_pass(
+            renderer.render_pass,
+            framebuffer,
+            renderer.swapchain_extent,
+            &clear_values,
         );
+        self.draw(renderer, &mut pass, frame_index);
+        drop(pass);
         
-        // Set dynamic viewport
-        let viewport = vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: renderer.swapchain_extent.width as f32,
-            height: renderer.swapchain_extent.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0,
-        };
-        renderer.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
-        
-        // Set dynamic scissor
-        let scissor = vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: renderer.swapchain_extent,
-        };
-        renderer.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
-        
-        renderer.device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer], &[0]);
-        renderer.device.cmd_bind_index_buffer(command_buffer, self.index_buffer, 0, vk::IndexType::UINT16);
-        
-        renderer.device.cmd_bind_descriptor_sets(
-            command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            renderer.pipeline_layout,
-            0,
-            &[renderer.descriptor_sets[frame_index]],
-            &[],
-        );
-        
-        renderer.device.cmd_draw_indexed(command_buffer, self.index_count, 1, 0, 0, 0);
-        
-        renderer.device.cmd_end_render_pass(command_buffer);
         renderer.device.end_command_buffer(command_buffer)?;
         
         Ok(())
     }
     
     pub unsafe fn cleanup(&mut self, renderer: &VulkanRenderer) {
-        for buffer in &self.uniform_buffers {
-            renderer.device.destroy_buffer(*buffer, None);
-        }
-        for allocation in self.uniform_allocations.drain(..) {
-            if let Some(alloc) = allocation {
-                let _ = renderer.allocator.lock().free(alloc);
-            }
+        if self.instanced_pipeline != vk::Pipeline::null() {
+            renderer.device.destroy_pipeline(self.instanced_pipeline, None);
+            self.instanced_pipeline = vk::Pipeline::null();
         }
+        self.instance_slices.clear();
+        self.uniform_sets.clear();
+        self.instance_count = 0;
         
         renderer.device.destroy_buffer(self.index_buffer, None);
         if let Some(alloc) = self.index_allocation.take() {
@@ -0,0 +1,161 @@
+//! Immediate-mode debug drawing
+//!
+//! Anything can queue world-space lines and labels into the `DebugDraw` resource during a
+//! frame. Before the UI is built they are projected with the main camera into an `Overlay`,
+//! which egui paints behind its windows. The shapes are not depth tested, so hidden parts
+//! such as joints inside a mesh stay visible.
+
+use bevy_ecs::prelude::*;
+use egui::{Color32, Pos2, Stroke};
+use glam::{Mat4, Vec3, Vec3Swizzles, Vec4Swizzles};
+
+struct Line {
+    from: Vec3,
+    to: Vec3,
+    color: Color32,
+}
+
+struct Label {
+    position: Vec3,
+    text: String,
+    color: Color32,
+}
+
+/// World-space shapes queued for this frame
+#[derive(Resource, Default)]
+pub struct DebugDraw {
+    lines: Vec<Line>,
+    labels: Vec<Label>,
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, from: Vec3, to: Vec3, color: Color32) {
+        self.lines.push(Line { from, to, color });
+    }
+
+    pub fn label(&mut self, position: Vec3, text: impl Into<String>, color: Color32) {
+        self.labels.push(Label { position, text: text.into(), color });
+    }
+
+    /// Octahedron with its vertices `radius` away from `center` along each axis
+    pub fn octahedron(&mut self, center: Vec3, radius: f32, color: Color32) {
+        let axes = [Vec3::X, Vec3::NEG_X, Vec3::Y, Vec3::NEG_Y, Vec3::Z, Vec3::NEG_Z];
+        let tips = axes.map(|axis| center + axis * radius);
+        for (i, &a) in tips.iter().enumerate() {
+            // Every tip connects to all others except its opposite, each edge once
+            for &b in &tips[(i | 1) + 1..] {
+                self.line(a, b, color);
+            }
+        }
+    }
+
+    /// Octahedral bone from `from` to `to`, widest a fifth of the way along, as in DCC tools
+    pub fn bone(&mut self, from: Vec3, to: Vec3, color: Color32) {
+        let axis = to - from;
+        let length = axis.length();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let (u, v) = (axis / length).any_orthonormal_pair();
+        let center = from + axis * 0.2;
+        let radius = length * 0.1;
+        let ring = [u, v, -u, -v].map(|side| center + side * radius);
+        for (i, &point) in ring.iter().enumerate() {
+            self.line(from, point, color);
+            self.line(point, to, color);
+            self.line(point, ring[(i + 1) % ring.len()], color);
+        }
+    }
+
+    /// Wireframe of the volume `view_proj` maps to clip space, such as a camera's view or a
+    /// shadow cascade's box. `depth` (0..1] cuts the edges short, since a whole perspective
+    /// frustum is mostly far plane.
+    pub fn frustum(&mut self, view_proj: Mat4, depth: f32, color: Color32) {
+        let inverse = view_proj.inverse();
+        let corner = |x: f32, y: f32, z: f32| inverse.project_point3(Vec3::new(x, y, z));
+        let ring = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+        let near = ring.map(|(x, y)| corner(x, y, 0.0));
+        let far = ring.map(|(x, y)| corner(x, y, 1.0));
+        let far = std::array::from_fn::<_, 4, _>(|i| near[i].lerp(far[i], depth));
+        for i in 0..4 {
+            let next = (i + 1) % 4;
+            self.line(near[i], near[next], color);
+            self.line(far[i], far[next], color);
+            self.line(near[i], far[i], color);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.labels.clear();
+    }
+
+    /// Project everything queued with `view_proj`, clipping lines at the near plane
+    pub fn overlay(&self, view_proj: Mat4) -> Overlay {
+        let lines = self
+            .lines
+            .iter()
+            .filter_map(|line| {
+                let mut a = view_proj * line.from.extend(1.0);
+                let mut b = view_proj * line.to.extend(1.0);
+                // Vulkan clip space puts the near plane at z = 0
+                if a.z < 0.0 && b.z < 0.0 {
+                    return None;
+                }
+                if a.z < 0.0 {
+                    a = a.lerp(b, a.z / (a.z - b.z));
+                } else if b.z < 0.0 {
+                    b = b.lerp(a, b.z / (b.z - a.z));
+                }
+                Some(([a.xy() / a.w, b.xy() / b.w], line.color))
+            })
+            .collect();
+        let labels = self
+            .labels
+            .iter()
+            .filter_map(|label| {
+                let clip = view_proj * label.position.extend(1.0);
+                let ndc = clip.xyz() / clip.w;
+                let visible = clip.z >= 0.0 && ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0;
+                visible.then(|| (ndc.xy(), label.text.clone(), label.color))
+            })
+            .collect();
+        Overlay { lines, labels }
+    }
+}
+
+/// Debug shapes in normalized device coordinates, ready to paint
+#[derive(Default)]
+pub struct Overlay {
+    lines: Vec<([glam::Vec2; 2], Color32)>,
+    labels: Vec<(glam::Vec2, String, Color32)>,
+}
+
+impl Overlay {
+    /// Paint on egui's background layer, under every window
+    pub fn paint(&self, ctx: &egui::Context) {
+        if self.lines.is_empty() && self.labels.is_empty() {
+            return;
+        }
+        let painter = ctx.layer_painter(egui::LayerId::background());
+        let screen = ctx.screen_rect();
+        let to_screen = |ndc: glam::Vec2| {
+            Pos2::new(
+                screen.left() + (ndc.x * 0.5 + 0.5) * screen.width(),
+                screen.top() + (ndc.y * 0.5 + 0.5) * screen.height(),
+            )
+        };
+        for ([from, to], color) in &self.lines {
+            painter.line_segment([to_screen(*from), to_screen(*to)], Stroke::new(1.5, *color));
+        }
+        for (position, text, color) in &self.labels {
+            painter.text(
+                to_screen(*position) + egui::vec2(4.0, -4.0),
+                egui::Align2::LEFT_BOTTOM,
+                text,
+                egui::FontId::proportional(11.0),
+                *color,
+            );
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Vulkan validation
+//!
+//! With validation requested (`RendererBuilder::validation`, `--validation`, or the
+//! `FUNKY_VALIDATION` environment variable) the instance is created with the Khronos
+//! validation layer, and a `VK_EXT_debug_utils` messenger prints the errors and warnings
+//! it reports to stderr. The messenger is also chained into instance creation, so
+//! mistakes made while the instance is created are reported too. Without the layer
+//! installed (it ships with the Vulkan SDK) the renderer runs unvalidated, with a warning.
+
+use ash::vk;
+use std::ffi::{c_void, CStr};
+
+pub const VALIDATION_LAYER: &CStr = c"VK_LAYER_KHRONOS_validation";
+/// Set (to anything) to validate every renderer, including headless ones in tests
+pub const VALIDATION_ENV: &str = "FUNKY_VALIDATION";
+
+pub fn requested_by_env() -> bool {
+    std::env::var_os(VALIDATION_ENV).is_some()
+}
+
+/// Whether the validation layer and the debug utils extension can be enabled
+///
+/// # Safety
+///
+/// `entry` must have loaded a Vulkan library.
+pub unsafe fn available(entry: &ash::Entry) -> Result<bool, vk::Result> {
+    let layer = entry
+        .enumerate_instance_layer_properties()?
+        .iter()
+        .any(|layer| layer.layer_name_as_c_str() == Ok(VALIDATION_LAYER));
+    if !layer {
+        return Ok(false);
+    }
+    // The layer provides the extension when the loader doesn't
+    let has_debug_utils = |extensions: Vec<vk::ExtensionProperties>| {
+        extensions.iter().any(|ext| ext.extension_name_as_c_str() == Ok(ash::ext::debug_utils::NAME))
+    };
+    Ok(has_debug_utils(entry.enumerate_instance_extension_properties(None)?)
+        || has_debug_utils(entry.enumerate_instance_extension_properties(Some(VALIDATION_LAYER))?))
+}
+
+/// Messages worth seeing: the layers' info and verbose output is left out
+pub fn create_info() -> vk::DebugUtilsMessengerCreateInfoEXT<'static> {
+    vk::DebugUtilsMessengerCreateInfoEXT::default()
+        .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING)
+        .message_type(
+            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+        )
+        .pfn_user_callback(Some(callback))
+}
+
+pub struct DebugMessenger {
+    debug_utils: ash::ext::debug_utils::Instance,
+    messenger: vk::DebugUtilsMessengerEXT,
+}
+
+impl DebugMessenger {
+    /// Start printing the messages of `instance`, created with the validation layer and
+    /// the debug utils extension enabled
+    ///
+    /// # Safety
+    ///
+    /// `instance` must outlive the messenger.
+    pub unsafe fn new(entry: &ash::Entry, instance: &ash::Instance) -> Result<Self, vk::Result> {
+        let debug_utils = ash::ext::debug_utils::Instance::new(entry, instance);
+        let messenger = debug_utils.create_debug_utils_messenger(&create_info(), None)?;
+        Ok(Self { debug_utils, messenger })
+    }
+
+    /// # Safety
+    ///
+    /// Call once, before the instance is destroyed.
+    pub unsafe fn destroy(&self) {
+        self.debug_utils.destroy_debug_utils_messenger(self.messenger, None);
+    }
+}
+
+unsafe extern "system" fn callback(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    _user_data: *mut c_void,
+) -> vk::Bool32 {
+    let message = match data.as_ref() {
+        Some(data) if !data.p_message.is_null() => CStr::from_ptr(data.p_message).to_string_lossy(),
+        _ => "(no message)".into(),
+    };
+    let kind = if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+        "performance"
+    } else if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+        "validation"
+    } else {
+        "general"
+    };
+
+    if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        eprintln!("✗ Vulkan {} error: {}", kind, message);
+    } else {
+        eprintln!("⚠ Vulkan {} warning: {}", kind, message);
+    }
+
+    // Never abort the call that triggered the message
+    vk::FALSE
+}
@@ -0,0 +1,250 @@
+//! GPU memory defragmentation
+//!
+//! gpu_allocator sub-allocates from large memory blocks and only returns a block to the
+//! driver once it is empty. Loading and dropping models over a long session leaves blocks
+//! holding a few small allocations each, so a large allocation can fail while plenty of
+//! memory is free. During idle frames the defragmenter picks the emptiest block and moves
+//! what it can out of it: a new buffer is allocated (the allocator fills earlier blocks
+//! first), the contents copied over, and the owner switched to the copy. The old buffer
+//! goes onto a deletion queue until no frame in flight can still be reading it, after
+//! which freeing it may release the whole block.
+//!
+//! Only buffers their owners hand over as `MovableBuffer`s move: ones bound while recording
+//! (vertex and index buffers). Host-visible ones are copied on the CPU, device-local ones
+//! with a copy command the defragmenter waits for. Anything a descriptor set or
+//! acceleration structure points at, including every image, stays put.
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::upload;
+
+/// Blocks less full than this are worth emptying
+const SPARSE_OCCUPANCY: f32 = 0.5;
+/// Most bytes copied in one idle frame
+const BYTES_PER_STEP: u64 = 16 * 1024 * 1024;
+
+/// A buffer whose owner reads `buffer` anew every time it records, so it may be swapped
+/// for a copy between frames. Device-local ones need `upload::TRANSFER_USAGE` in `usage`.
+pub struct MovableBuffer<'a> {
+    pub name: &'static str,
+    pub buffer: &'a mut vk::Buffer,
+    pub allocation: &'a mut Option<Allocation>,
+    pub size: u64,
+    pub usage: vk::BufferUsageFlags,
+}
+
+/// For the memory panel, since startup
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefragStats {
+    pub moved_buffers: u32,
+    pub moved_bytes: u64,
+    pub blocks_freed: u32,
+    pub retired: usize, // Old copies waiting for the frames in flight
+}
+
+pub struct Defragmenter {
+    pub enabled: bool, // Whether idle frames defragment
+    pub stats: DefragStats,
+    retired: Vec<(u64, vk::Buffer, Allocation)>, // With the frame they went, until no frame in flight uses them
+    frame: u64,
+}
+
+impl Default for Defragmenter {
+    fn default() -> Self {
+        Self { enabled: true, stats: DefragStats::default(), retired: Vec::new(), frame: 0 }
+    }
+}
+
+impl Defragmenter {
+    /// Free the old copies no frame in flight can use anymore. Call once per frame.
+    pub unsafe fn collect(&mut self, renderer: &VulkanRenderer) {
+        self.frame += 1;
+        let (frame, retired) = (self.frame, std::mem::take(&mut self.retired));
+        if retired.iter().all(|&(retired_at, ..)| frame - retired_at <= MAX_FRAMES_IN_FLIGHT as u64) {
+            self.retired = retired;
+            return;
+        }
+        let mut allocator = renderer.allocator.lock();
+        let blocks_before = allocator.generate_report().blocks.len();
+        for (retired_at, buffer, allocation) in retired {
+            if frame - retired_at > MAX_FRAMES_IN_FLIGHT as u64 {
+                renderer.device.destroy_buffer(buffer, None);
+                let _ = allocator.free(allocation);
+            } else {
+                self.retired.push((retired_at, buffer, allocation));
+            }
+        }
+        let blocks_after = allocator.generate_report().blocks.len();
+        self.stats.blocks_freed += blocks_before.saturating_sub(blocks_after) as u32;
+        self.stats.retired = self.retired.len();
+    }
+
+    /// Move what can be moved of `buffers` out of the emptiest block, up to a frame's
+    /// budget. Call between frames, on frames with nothing else to do. Returns how many
+    /// buffers moved.
+    pub unsafe fn step(&mut self, renderer: &VulkanRenderer, buffers: Vec<MovableBuffer<'_>>) -> u32 {
+        if !self.enabled {
+            return 0;
+        }
+        let report = renderer.allocator.lock().generate_report();
+        if report.blocks.len() < 2 {
+            return 0;
+        }
+
+        // The block behind each memory object the buffers are in. The report only has offsets
+        // within a block, which identical allocations in different blocks share, so a memory
+        // object's block is the first not yet taken holding all of its buffers, by name,
+        // offset and size, the memory objects with the most buffers placed first.
+        let mut memories: Vec<(vk::DeviceMemory, Vec<&MovableBuffer>)> = Vec::new();
+        for buffer in &buffers {
+            let Some(allocation) = buffer.allocation.as_ref() else { continue };
+            match memories.iter_mut().find(|(memory, _)| *memory == allocation.memory()) {
+                Some((_, in_memory)) => in_memory.push(buffer),
+                None => memories.push((allocation.memory(), vec![buffer])),
+            }
+        }
+        memories.sort_by_key(|(_, in_memory)| std::cmp::Reverse(in_memory.len()));
+        let mut block_of_memory: Vec<(vk::DeviceMemory, usize)> = Vec::with_capacity(memories.len());
+        for (memory, in_memory) in &memories {
+            let block = (0..report.blocks.len())
+                .filter(|&block| block_of_memory.iter().all(|&(_, taken)| taken != block))
+                .find(|&block| {
+                    let in_block = &report.allocations[report.blocks[block].allocations.clone()];
+                    in_memory.iter().all(|buffer| {
+                        buffer.allocation.as_ref().is_some_and(|allocation| {
+                            in_block.iter().any(|a| {
+                                a.name == buffer.name && a.offset == allocation.offset() && a.size == allocation.size()
+                            })
+                        })
+                    })
+                });
+            if let Some(block) = block {
+                block_of_memory.push((*memory, block));
+            }
+        }
+        let block_of = |allocation: &Allocation| {
+            block_of_memory.iter().find(|&&(memory, _)| memory == allocation.memory()).map(|&(_, block)| block)
+        };
+        let occupancy = |block: usize| {
+            let block = &report.blocks[block];
+            let used: u64 = report.allocations[block.allocations.clone()].iter().map(|a| a.size).sum();
+            used as f32 / block.size.max(1) as f32
+        };
+        let mut candidates: Vec<(usize, MovableBuffer)> = buffers
+            .into_iter()
+            .filter_map(|buffer| {
+                let block = block_of(buffer.allocation.as_ref()?)?;
+                Some((block, buffer))
+            })
+            .collect();
+        let Some(source) = candidates
+            .iter()
+            .map(|&(block, _)| block)
+            .filter(|&block| occupancy(block) < SPARSE_OCCUPANCY)
+            .min_by(|&a, &b| occupancy(a).total_cmp(&occupancy(b)))
+        else {
+            return 0;
+        };
+
+        let mut moved = 0;
+        let mut budget = BYTES_PER_STEP;
+        for (_, buffer) in candidates.iter_mut().filter(|(block, _)| *block == source) {
+            if buffer.size > budget {
+                break;
+            }
+            match self.migrate(renderer, buffer, report.blocks.len()) {
+                Ok(true) => {
+                    moved += 1;
+                    budget -= buffer.size;
+                    self.stats.moved_buffers += 1;
+                    self.stats.moved_bytes += buffer.size;
+                }
+                // Nowhere better to go
+                Ok(false) => break,
+                Err(e) => {
+                    eprintln!("✗ Failed to move {} while defragmenting: {}", buffer.name, e);
+                    break;
+                }
+            }
+        }
+        self.stats.retired = self.retired.len();
+        moved
+    }
+
+    /// Copy `buffer` into a new allocation and retire the old one, unless the allocator
+    /// puts the copy in the same block or has to add a block to the `blocks` there are
+    unsafe fn migrate(
+        &mut self,
+        renderer: &VulkanRenderer,
+        buffer: &mut MovableBuffer,
+        blocks: usize,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let Some(old_allocation) = buffer.allocation.as_ref() else {
+            return Ok(false);
+        };
+        // Host-visible buffers are copied on the CPU, device-local ones on the GPU
+        let old_ptr = old_allocation.mapped_ptr();
+        let location = if old_ptr.is_some() { MemoryLocation::CpuToGpu } else { MemoryLocation::GpuOnly };
+
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(buffer.size)
+            .usage(buffer.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let new_buffer = device.create_buffer(&buffer_info, None)?;
+        let requirements = device.get_buffer_memory_requirements(new_buffer);
+        let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: buffer.name,
+            requirements,
+            location,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        });
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy_buffer(new_buffer, None);
+                return Err(e.into());
+            }
+        };
+        let same_block = allocation.memory() == old_allocation.memory();
+        let new_block = renderer.allocator.lock().generate_report().blocks.len() > blocks;
+        if same_block || new_block {
+            device.destroy_buffer(new_buffer, None);
+            let _ = renderer.allocator.lock().free(allocation);
+            return Ok(false);
+        }
+        let copied = device
+            .bind_buffer_memory(new_buffer, allocation.memory(), allocation.offset())
+            .map_err(|e| e.into())
+            .and_then(|_| match (old_ptr, allocation.mapped_ptr()) {
+                (Some(old_ptr), Some(new_ptr)) => {
+                    std::ptr::copy_nonoverlapping(old_ptr.as_ptr() as *const u8, new_ptr.as_ptr() as *mut u8, buffer.size as usize);
+                    Ok(())
+                }
+                _ => upload::copy_buffer(renderer, *buffer.buffer, new_buffer, buffer.size),
+            });
+        if let Err(e) = copied {
+            device.destroy_buffer(new_buffer, None);
+            let _ = renderer.allocator.lock().free(allocation);
+            return Err(e);
+        }
+
+        let old_buffer = std::mem::replace(buffer.buffer, new_buffer);
+        if let Some(old_allocation) = buffer.allocation.replace(allocation) {
+            self.retired.push((self.frame, old_buffer, old_allocation));
+        }
+        Ok(true)
+    }
+
+    /// Free every old copy. The device must be idle.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        for (_, buffer, allocation) in self.retired.drain(..) {
+            renderer.device.destroy_buffer(buffer, None);
+            let _ = renderer.allocator.lock().free(allocation);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Descriptor buffers
+//!
+//! With `VK_EXT_descriptor_buffer` descriptors are bytes the host writes into an ordinary
+//! buffer, and a pipeline reads a set from an offset into a bound buffer instead of from a
+//! `VkDescriptorSet` allocated out of a pool. A `DescriptorBuffer` holds a number of slots
+//! for one set layout, back to back: one per frame in flight, per material, or whatever
+//! else a pass picks between per draw. Picking one is just an offset, and writing one is a
+//! memcpy into mapped memory, with no pool to size and no sets to allocate or update.
+//!
+//! Pipelines either read all their sets from descriptor buffers or none, so a pass opts in
+//! as a whole: its set layouts are created with `DESCRIPTOR_BUFFER_EXT` and its pipelines
+//! with `PipelineCreateFlags::DESCRIPTOR_BUFFER_EXT`. Passes keep their descriptor sets
+//! on devices without the extension, see `VulkanRenderer::descriptor_buffer_supported`.
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+
+use crate::command_encoder::RenderPassEncoder;
+use crate::renderer::VulkanRenderer;
+
+/// `slots` sets of one layout in a host-visible buffer
+pub struct DescriptorBuffer {
+    pub loader: ash::ext::descriptor_buffer::Device,
+    buffer: vk::Buffer,
+    allocation: Option<Allocation>,
+    address: vk::DeviceAddress,
+    usage: vk::BufferUsageFlags,
+    slot_size: u64,
+    binding_offsets: Vec<u64>, // Indexed by binding number, within a slot
+    combined_image_sampler_size: usize, // Bytes of such a descriptor on this device
+}
+
+impl DescriptorBuffer {
+    /// Room for `slots` sets of `set_layout`, which must have been created with
+    /// `DESCRIPTOR_BUFFER_EXT` from `bindings`. Every slot starts out empty.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        set_layout: vk::DescriptorSetLayout,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        slots: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if !renderer.descriptor_buffer_supported {
+            return Err("descriptor buffers are not supported".into());
+        }
+        let device = &renderer.device;
+        let loader = ash::ext::descriptor_buffer::Device::new(&renderer.instance, device);
+
+        let mut properties = vk::PhysicalDeviceDescriptorBufferPropertiesEXT::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut properties);
+        renderer
+            .instance
+            .get_physical_device_properties2(renderer.physical_device, &mut properties2);
+        let combined_image_sampler_size = properties.combined_image_sampler_descriptor_size;
+
+        // Set offsets must be aligned, so each slot is padded to the alignment
+        let alignment = properties.descriptor_buffer_offset_alignment.max(1);
+        let slot_size = loader.get_descriptor_set_layout_size(set_layout).next_multiple_of(alignment);
+        let binding_count = bindings.iter().map(|binding| binding.binding + 1).max().unwrap_or(0);
+        let binding_offsets = (0..binding_count)
+            .map(|binding| {
+                if bindings.iter().any(|b| b.binding == binding) {
+                    loader.get_descriptor_set_layout_binding_offset(set_layout, binding)
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        // Combined image samplers carry their sampler, so the buffer holds both kinds
+        let usage = vk::BufferUsageFlags::RESOURCE_DESCRIPTOR_BUFFER_EXT
+            | vk::BufferUsageFlags::SAMPLER_DESCRIPTOR_BUFFER_EXT
+            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size((slot_size * slots as u64).max(alignment))
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = device.create_buffer(&buffer_info, None)?;
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: "descriptor_buffer",
+            requirements,
+            location: MemoryLocation::CpuToGpu,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        });
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy_buffer(buffer, None);
+                return Err(e.into());
+            }
+        };
+        if let Err(e) = device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset()) {
+            device.destroy_buffer(buffer, None);
+            let _ = renderer.allocator.lock().free(allocation);
+            return Err(e.into());
+        }
+        let address = device.get_buffer_device_address(&vk::BufferDeviceAddressInfo::default().buffer(buffer));
+
+        Ok(Self {
+            loader,
+            buffer,
+            allocation: Some(allocation),
+            address,
+            usage,
+            slot_size,
+            binding_offsets,
+            combined_image_sampler_size,
+        })
+    }
+
+    /// Read set number `set` of the pipeline bound to `pass` from `slot`
+    pub fn bind(&self, pass: &mut RenderPassEncoder, set: u32, slot: usize) {
+        let binding_info = vk::DescriptorBufferBindingInfoEXT::default()
+            .address(self.address)
+            .usage(self.usage);
+        pass.bind_descriptor_buffer(&self.loader, &binding_info, set, self.slot_size * slot as u64);
+    }
+
+    /// Point `binding` of `slot` at `view`, sampled with `sampler` in
+    /// `SHADER_READ_ONLY_OPTIMAL` layout. No frame in flight may be reading the slot.
+    pub unsafe fn write_sampled_image(&self, slot: usize, binding: u32, view: vk::ImageView, sampler: vk::Sampler) {
+        let image_info = vk::DescriptorImageInfo {
+            sampler,
+            image_view: view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let data = vk::DescriptorDataEXT { p_combined_image_sampler: &image_info };
+        self.write(slot, binding, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, data, self.combined_image_sampler_size);
+    }
+
+    unsafe fn write(&self, slot: usize, binding: u32, ty: vk::DescriptorType, data: vk::DescriptorDataEXT, size: usize) {
+        let Some(ptr) = self.allocation.as_ref().and_then(|allocation| allocation.mapped_ptr()) else {
+            return;
+        };
+        let offset = self.slot_size * slot as u64 + self.binding_offsets[binding as usize];
+        let descriptor = std::slice::from_raw_parts_mut((ptr.as_ptr() as *mut u8).add(offset as usize), size);
+        let info = vk::DescriptorGetInfoEXT::default().ty(ty).data(data);
+        self.loader.get_descriptor(&info, descriptor);
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        renderer.device.destroy_buffer(self.buffer, None);
+        if let Some(allocation) = self.allocation.take() {
+            let _ = renderer.allocator.lock().free(allocation);
+        }
+    }
+}
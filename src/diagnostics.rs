@@ -0,0 +1,141 @@
+//! Diagnostics snapshots for bug reports
+//!
+//! "Copy diagnostics" in the debug UI writes everything a bug report usually has to ask
+//! for into one JSON file under `diagnostics/`: the GPU and its driver, the enabled device
+//! extensions and optional features, the swapchain, a summary of GPU memory, the loaded
+//! assets, the current settings and the last `LOG_LINES` lines of console output.
+//!
+//! Console output is kept by the `println!` and `eprintln!` in main.rs, which shadow the
+//! standard ones for the whole binary and pass every line to `log_line` as well.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use ash::vk;
+use gltf::json::Value;
+
+use crate::renderer::VulkanRenderer;
+
+/// Console lines kept for the snapshot
+pub const LOG_LINES: usize = 200;
+/// Allocation names listed by total size
+const TOP_ALLOCATIONS: usize = 16;
+
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Keep `line` of console output, dropping the oldest past `LOG_LINES`
+pub fn log_line(line: String) {
+    let Ok(mut log) = LOG.lock() else {
+        return;
+    };
+    if log.len() == LOG_LINES {
+        log.pop_front();
+    }
+    log.push_back(line);
+}
+
+/// The GPU, its driver, and what was enabled on it
+pub unsafe fn device(renderer: &VulkanRenderer) -> Value {
+    let properties = renderer.instance.get_physical_device_properties(renderer.physical_device);
+    let mut driver = vk::PhysicalDeviceDriverProperties::default();
+    let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut driver);
+    renderer
+        .instance
+        .get_physical_device_properties2(renderer.physical_device, &mut properties2);
+    let conformance = driver.conformance_version;
+
+    let features = [
+        ("ray_query", renderer.ray_query_supported),
+        ("mesh_shader", renderer.mesh_shader_supported),
+        ("multi_draw_indirect", renderer.multi_draw_indirect_supported),
+        ("draw_indirect_count", renderer.draw_indirect_count_supported),
+        ("comparison_samplers", renderer.comparison_samplers_supported),
+        ("texture_compression_bc", renderer.texture_compression_bc_supported),
+        ("occlusion_query_precise", renderer.occlusion_query_precise_supported),
+        ("descriptor_buffer", renderer.descriptor_buffer_supported),
+        ("push_descriptor", renderer.push_descriptor_supported),
+        ("pipeline_statistics", renderer.pipeline_statistics_supported),
+    ];
+    [
+        ("name", Value::from(renderer.gpu_name.clone())),
+        ("type", format!("{:?}", properties.device_type).into()),
+        ("vendor_id", format!("{:#06x}", properties.vendor_id).into()),
+        ("device_id", format!("{:#06x}", properties.device_id).into()),
+        ("vulkan", renderer.vulkan_version.clone().into()),
+        ("driver_id", format!("{:?}", driver.driver_id).into()),
+        ("driver_name", driver.driver_name_as_c_str().map_or_else(|_| String::new(), |name| name.to_string_lossy().into_owned()).into()),
+        ("driver_info", driver.driver_info_as_c_str().map_or_else(|_| String::new(), |info| info.to_string_lossy().into_owned()).into()),
+        ("driver_version", format!("{:#x}", properties.driver_version).into()),
+        (
+            "conformance",
+            format!("{}.{}.{}.{}", conformance.major, conformance.minor, conformance.subminor, conformance.patch).into(),
+        ),
+        ("extensions", renderer.device_extensions.clone().into()),
+        ("validation", renderer.debug_messenger.is_some().into()),
+        ("features", features.into_iter().collect()),
+    ]
+    .into_iter()
+    .collect()
+}
+
+pub fn swapchain(renderer: &VulkanRenderer) -> Value {
+    [
+        ("format", Value::from(format!("{:?}", renderer.swapchain_format))),
+        ("color_space", format!("{:?}", renderer.swapchain_color_space).into()),
+        ("present_mode", format!("{:?}", renderer.present_mode).into()),
+        ("present_mode_config", format!("{:?}", renderer.present_mode_config).into()),
+        ("extent", vec![renderer.swapchain_extent.width, renderer.swapchain_extent.height].into()),
+        ("images", renderer.swapchain_images.len().into()),
+        ("frames_in_flight", renderer.frames_in_flight.into()),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// Totals, blocks and the largest users of GPU memory
+pub fn memory(renderer: &VulkanRenderer) -> Value {
+    let report = renderer.allocator.lock().generate_report();
+    let mut by_name = BTreeMap::<&str, (u64, usize)>::new();
+    for allocation in &report.allocations {
+        let (bytes, count) = by_name.entry(&allocation.name).or_default();
+        *bytes += allocation.size;
+        *count += 1;
+    }
+    let mut by_name: Vec<_> = by_name.into_iter().collect();
+    by_name.sort_by_key(|&(_, (bytes, _))| std::cmp::Reverse(bytes));
+    let largest: Value = by_name
+        .into_iter()
+        .take(TOP_ALLOCATIONS)
+        .map(|(name, (bytes, count))| (name, [("bytes", bytes), ("count", count as u64)].into_iter().collect::<Value>()))
+        .collect();
+    [
+        ("allocated_bytes", Value::from(report.total_allocated_bytes)),
+        ("capacity_bytes", report.total_capacity_bytes.into()),
+        ("blocks", report.blocks.iter().map(|block| block.size).collect::<Vec<_>>().into()),
+        ("allocations", report.allocations.len().into()),
+        ("largest", largest),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// The kept console lines, oldest first
+pub fn log() -> Value {
+    LOG.lock().map_or(Value::Null, |log| log.iter().cloned().collect::<Vec<_>>().into())
+}
+
+/// Write `sections` as one JSON object to `diagnostics/diagnostics_<unix time>.json`
+pub fn save(sections: Vec<(&str, Value)>) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all("diagnostics")?;
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let path = PathBuf::from(format!("diagnostics/diagnostics_{}.json", timestamp));
+    let snapshot: Value = std::iter::once(("created", Value::from(timestamp)))
+        .chain(std::iter::once(("version", Value::from(env!("CARGO_PKG_VERSION")))))
+        .chain(sections)
+        .collect();
+    std::fs::write(&path, gltf::json::serialize::to_string_pretty(&snapshot)?)?;
+    Ok(path)
+}
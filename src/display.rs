@@ -0,0 +1,170 @@
+//! Monitor and fullscreen mode selection
+//!
+//! F11 fullscreens the main window on the monitor picked in the debug UI, either borderless
+//! at the desktop resolution or exclusive with a chosen video mode. The choice is saved to
+//! `display.cfg` in the working directory and restored on the next run. A monitor or mode
+//! that has since gone away falls back to the window's current monitor and its best mode.
+
+use winit::monitor::{MonitorHandle, VideoModeHandle};
+use winit::window::{Fullscreen, Window};
+
+const SETTINGS_PATH: &str = "display.cfg";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum FullscreenMode {
+    #[default]
+    Borderless,
+    Exclusive, // Changes the display mode; unsupported on Wayland and some macOS setups
+}
+
+/// A video mode as it is stored, independent of the monitor handle it came from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VideoModeKey {
+    pub width: u32,
+    pub height: u32,
+    pub refresh_millihertz: u32,
+    pub bit_depth: u16,
+}
+
+impl VideoModeKey {
+    pub fn of(mode: &VideoModeHandle) -> Self {
+        let size = mode.size();
+        Self {
+            width: size.width,
+            height: size.height,
+            refresh_millihertz: mode.refresh_rate_millihertz(),
+            bit_depth: mode.bit_depth(),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        format!(
+            "{}x{} @ {:.2} Hz ({}-bit)",
+            self.width,
+            self.height,
+            self.refresh_millihertz as f64 / 1000.0,
+            self.bit_depth
+        )
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        // "1920x1080@60000/32"
+        let (size, rest) = value.split_once('@')?;
+        let (width, height) = size.split_once('x')?;
+        let (refresh, bit_depth) = rest.split_once('/')?;
+        Some(Self {
+            width: width.parse().ok()?,
+            height: height.parse().ok()?,
+            refresh_millihertz: refresh.parse().ok()?,
+            bit_depth: bit_depth.parse().ok()?,
+        })
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct DisplaySettings {
+    pub monitor: Option<String>, // By name; None for the monitor the window is on
+    pub mode: FullscreenMode,
+    pub video_mode: Option<VideoModeKey>, // Exclusive only; None for the monitor's best mode
+}
+
+impl DisplaySettings {
+    /// Settings saved by a previous run, or the defaults
+    pub fn load() -> Self {
+        let mut settings = Self::default();
+        let Ok(text) = std::fs::read_to_string(SETTINGS_PATH) else {
+            return settings;
+        };
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "monitor" if !value.is_empty() => settings.monitor = Some(value.to_string()),
+                "mode" if value == "exclusive" => settings.mode = FullscreenMode::Exclusive,
+                "video_mode" => settings.video_mode = VideoModeKey::parse(value),
+                _ => {}
+            }
+        }
+        println!("✓ Display settings loaded from {}", SETTINGS_PATH);
+        settings
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let mut text = String::new();
+        if let Some(monitor) = &self.monitor {
+            text += &format!("monitor={}\n", monitor);
+        }
+        let mode = match self.mode {
+            FullscreenMode::Borderless => "borderless",
+            FullscreenMode::Exclusive => "exclusive",
+        };
+        text += &format!("mode={}\n", mode);
+        if let Some(key) = self.video_mode {
+            text += &format!(
+                "video_mode={}x{}@{}/{}\n",
+                key.width, key.height, key.refresh_millihertz, key.bit_depth
+            );
+        }
+        std::fs::write(SETTINGS_PATH, text)
+    }
+
+    /// The selected monitor if it is connected, else the one the window is on
+    pub fn monitor(&self, window: &Window) -> Option<MonitorHandle> {
+        self.monitor
+            .as_ref()
+            .and_then(|name| {
+                window
+                    .available_monitors()
+                    .find(|monitor| monitor.name().as_ref() == Some(name))
+            })
+            .or_else(|| window.current_monitor())
+            .or_else(|| window.primary_monitor())
+    }
+
+    /// Leave fullscreen, or enter it with these settings
+    pub fn toggle_fullscreen(&self, window: &Window) {
+        if window.fullscreen().is_some() {
+            window.set_fullscreen(None);
+        } else {
+            window.set_fullscreen(self.fullscreen(window));
+        }
+    }
+
+    /// What F11 switches the window to
+    pub fn fullscreen(&self, window: &Window) -> Option<Fullscreen> {
+        let monitor = self.monitor(window);
+        match self.mode {
+            FullscreenMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+            FullscreenMode::Exclusive => {
+                let modes = video_modes(&monitor?);
+                let mode = self
+                    .video_mode
+                    .and_then(|key| modes.iter().find(|mode| VideoModeKey::of(mode) == key))
+                    .or_else(|| modes.first())?;
+                Some(Fullscreen::Exclusive(mode.clone()))
+            }
+        }
+    }
+}
+
+/// The monitor's video modes, largest and fastest first
+pub fn video_modes(monitor: &MonitorHandle) -> Vec<VideoModeHandle> {
+    let mut modes: Vec<VideoModeHandle> = monitor.video_modes().collect();
+    modes.sort_by_key(|mode| {
+        let key = VideoModeKey::of(mode);
+        std::cmp::Reverse((key.width * key.height, key.refresh_millihertz, key.bit_depth))
+    });
+    modes
+}
+
+pub fn monitor_label(monitor: &MonitorHandle) -> String {
+    let size = monitor.size();
+    format!(
+        "{} ({}x{})",
+        monitor.name().unwrap_or_else(|| "Unnamed monitor".to_string()),
+        size.width,
+        size.height
+    )
+}
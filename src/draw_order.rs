@@ -0,0 +1,29 @@
+//! Draw submission order
+//!
+//! Draws are recorded in the order of a 64-bit sort key rather than the order they were
+//! loaded in: pipeline in the top 16 bits, then material, then view depth. Consecutive
+//! draws then share their pipeline (and, once materials get their own descriptor sets,
+//! their material), and opaque surfaces go front to back so nearer ones fill the depth
+//! buffer first and hidden fragments are rejected before shading.
+
+use glam::{Mat4, Vec3};
+
+/// Sort key of an opaque draw. `pipeline` and `material` only need to be equal for draws
+/// that share them; lower values are drawn first.
+pub fn opaque_key(pipeline: u16, material: u16, depth: f32) -> u64 {
+    ((pipeline as u64) << 48) | ((material as u64) << 32) | depth_bits(depth) as u64
+}
+
+/// View depth of `center` (model space) under `model` for the camera `view_proj`
+pub fn view_depth(view_proj: &Mat4, model: &Mat4, center: Vec3) -> f32 {
+    (*view_proj * *model * center.extend(1.0)).w
+}
+
+/// Non-negative floats compare like their bits; anything behind the camera sorts first
+fn depth_bits(depth: f32) -> u32 {
+    if depth > 0.0 {
+        depth.to_bits()
+    } else {
+        0
+    }
+}
@@ -0,0 +1,94 @@
+//! Draw statistics
+//!
+//! Every pass bumps process-wide counters as it records: `draw` for each draw call with its
+//! instance and triangle counts, `state_changes` for pipeline, descriptor set, vertex and
+//! index buffer binds. The frame loop collects them once per frame with `take` into a
+//! `DrawStatsHistory`, which the debug UI shows as numbers and graphs.
+//!
+//! Indirect draws only count as calls when recorded: how many objects survive GPU culling is
+//! known once the GPU is done, so `gpu_culled` adds the survivors' instances and triangles
+//! and the culled objects when the counts are read back, `MAX_FRAMES_IN_FLIGHT` frames
+//! late. Draws culled on the CPU, see `frustum`, count as they are recorded with `culled`.
+//! The renderer has frustum culling only; nothing is occlusion culled yet.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Frames kept for the graphs
+pub const HISTORY_LEN: usize = 240;
+
+static DRAW_CALLS: AtomicU32 = AtomicU32::new(0);
+static INSTANCES: AtomicU32 = AtomicU32::new(0);
+static TRIANGLES: AtomicU64 = AtomicU64::new(0);
+static CULLED: AtomicU32 = AtomicU32::new(0);
+static STATE_CHANGES: AtomicU32 = AtomicU32::new(0);
+
+/// Counts for one frame
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DrawStats {
+    pub draw_calls: u32,
+    pub instances: u32,
+    pub triangles: u64,
+    pub culled: u32,
+    pub state_changes: u32,
+}
+
+/// A draw call of `instances` instances of `triangles` triangles each
+pub fn draw(instances: u32, triangles: u64) {
+    DRAW_CALLS.fetch_add(1, Ordering::Relaxed);
+    INSTANCES.fetch_add(instances, Ordering::Relaxed);
+    TRIANGLES.fetch_add(triangles * instances as u64, Ordering::Relaxed);
+}
+
+/// An indirect draw call; what it drew is added by `gpu_culled`
+pub fn indirect_draw() {
+    DRAW_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Objects drawn and culled by a GPU culling pass
+pub fn gpu_culled(visible: u32, triangles: u64, culled: u32) {
+    INSTANCES.fetch_add(visible, Ordering::Relaxed);
+    TRIANGLES.fetch_add(triangles, Ordering::Relaxed);
+    CULLED.fetch_add(culled, Ordering::Relaxed);
+}
+
+/// `count` objects skipped by frustum culling on the CPU while recording
+pub fn culled(count: u32) {
+    CULLED.fetch_add(count, Ordering::Relaxed);
+}
+
+/// `count` binds of a pipeline, descriptor sets or vertex / index buffers
+pub fn state_changes(count: u32) {
+    STATE_CHANGES.fetch_add(count, Ordering::Relaxed);
+}
+
+/// Counts since the last call, resetting them
+pub fn take() -> DrawStats {
+    DrawStats {
+        draw_calls: DRAW_CALLS.swap(0, Ordering::Relaxed),
+        instances: INSTANCES.swap(0, Ordering::Relaxed),
+        triangles: TRIANGLES.swap(0, Ordering::Relaxed),
+        culled: CULLED.swap(0, Ordering::Relaxed),
+        state_changes: STATE_CHANGES.swap(0, Ordering::Relaxed),
+    }
+}
+
+/// The last `HISTORY_LEN` frames, oldest first
+#[derive(Default)]
+pub struct DrawStatsHistory {
+    frames: VecDeque<DrawStats>,
+}
+
+impl DrawStatsHistory {
+    pub fn push(&mut self, stats: DrawStats) {
+        if self.frames.len() == HISTORY_LEN {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(stats);
+    }
+
+    /// Oldest first
+    pub fn frames(&self) -> impl Iterator<Item = &DrawStats> {
+        self.frames.iter()
+    }
+}
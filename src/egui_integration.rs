@@ -2,7 +2,31 @@
 //! 
 //! Provides debug UI showing ECS stats and performance metrics.
 
+use bevy_ecs::entity::Entity;
 use egui::Context;
+use crate::async_compute::AsyncComputeStats;
+use crate::debug_draw;
+use crate::defrag::DefragStats;
+use crate::draw_stats::DrawStats;
+use crate::god_rays::GodRayStyle;
+use crate::ground::{GroundPattern, GroundStyle};
+use crate::lens_flare::FlareStyle;
+use crate::letterbox::AspectLock;
+use crate::light_probes;
+use crate::material::MaterialOverride;
+use crate::material_preview;
+use crate::pipeline_statistics::PipelineStats;
+use crate::reflection_probes;
+use crate::render_layers::RenderLayers;
+use crate::ssgi::SsgiStyle;
+use crate::swapchain::PresentModeConfig;
+use crate::toon::ToonStyle;
+use crate::weather::{Precipitation, Weather, WeatherPreset};
+use crate::gltf_export::ExportFormat;
+use crate::gltf_renderer::GROUND_SIZE;
+use crate::screenshot;
+use crate::texture_streaming;
+use crate::time_control;
 use egui_winit::State as EguiWinitState;
 use winit::window::Window;
 
@@ -35,9 +59,12 @@ impl EguiIntegration {
         }
     }
     
+    /// Forward a window event. `ScaleFactorChanged` updates the native pixels-per-point
+    /// egui lays out with, so the next `build_ui` tessellates for the new scale.
     pub fn handle_event(&mut self, window: &Window, event: &winit::event::WindowEvent) -> bool {
         self.state.on_window_event(window, event).consumed
     }
+
     
     pub fn toggle_ui(&mut self) {
         self.ui_visible = !self.ui_visible;
@@ -51,6 +78,7 @@ impl EguiIntegration {
         
         let output = self.ctx.run(raw_input, |ctx| {
             if self.ui_visible {
+                ui_data.debug_overlay.paint(ctx);
                 changes = render_debug_ui(ctx, ui_data);
             }
         });
@@ -63,28 +91,268 @@ impl EguiIntegration {
 pub struct UiData {
     pub fps: f64,
     pub frame_time_ms: f64,
+    pub render_on_demand: bool,
+    pub idle_rate_hz: f32,
+    pub async_compute: Option<AsyncComputeStats>, // None when compute shares the graphics queue
+    pub draw_history: Vec<DrawStats>, // Oldest first, ending with the last finished frame
+    pub pipeline_stats: Option<PipelineStats>, // Of the scene pass; None without support or a scene
     pub entity_count: usize,
     pub component_counts: ComponentCounts,
     pub vulkan_version: String,
+    pub swapchain_images: usize,
+    pub frames_in_flight: usize,
+    pub present_mode: PresentModeConfig,
+    pub present_mode_negotiated: String, // What the surface gave for it
     pub gpu_name: String,
+    pub camera_fov: f32, // Radians, where zooming is heading
+    pub orthographic: bool,
+    pub ortho_height: f32,
+    pub aspect_lock: AspectLock,
+    pub time_scale: f32,
+    pub time_paused: bool,
     pub gltf_scale: f32,
+    pub trackball: bool,
+    pub model_instances: usize, // Of the glTF scene, see `scene`
+    pub ground: Option<GroundStyle>, // None without a glTF scene
+    pub cube_count: usize,
+    pub cubes_visible: bool,
+    pub scene_tree: Vec<SceneTreeNode>, // Root entities other than cubes
+    pub main_layers: RenderLayers,
+    pub view_layers: RenderLayers, // Of the view windows
+    pub cube_spawn_count: u32,
+    pub skinned_mesh_count: usize,
+    pub compute_skinning: bool,
+    pub skeleton_debug: bool,
+    pub joint_labels: bool,
+    pub animation_clips: Vec<String>, // Empty without glTF animations
+    pub animation_clip: Option<usize>, // None for the rest pose
+    pub animation_playing: bool,
+    pub animation_speed: f32,
+    pub animation_time: f32,
+    pub animation_duration: f32,
+    pub debug_overlay: debug_draw::Overlay, // Projected `DebugDraw` shapes for this frame
+    pub meshlet_count: u32, // 0 without mesh shader support
+    pub mesh_shading: bool,
+    pub gpu_driven_meshes: usize, // 0 without multi-draw indirect support
+    pub gpu_driven_calls: usize,
+    pub gpu_driven_culling: bool, // False without draw indirect count support
+    pub gpu_driven: bool,
+
+    // Material editor
+    pub material_editor_open: bool,
+    pub material_mesh_count: usize, // 0 without the glTF scene
+    pub material_selected_mesh: usize,
+    pub material: Option<MaterialOverride>, // Factors the selected mesh is drawn with
+    pub material_overridden: bool,
+    pub material_preview: Option<egui::TextureId>, // Sphere with `material`, once rendered
+    pub material_save_target: Option<String>, // File and material the edit saves to, if any
 
     // Shadows
     pub shadow_debug_cascades: bool,
+    pub shadow_show_frustums: bool,
     pub shadow_softness: f32,
     pub shadow_use_pcss: bool,
     pub shadow_use_taa: bool,
+    pub shadow_samples: u32,
+    pub light_count: u32,
+    pub ray_traced_shadows_supported: bool,
+    pub shadow_ray_traced: bool,
+
+    // Ambient occlusion
+    pub ray_traced_ao: bool,
+    pub ao_rays: u32,
+
+    // Light probes
+    pub light_probes_available: bool, // Needs the glTF scene
+    pub light_probe_count: usize,
+    pub light_probes_baked: usize,
+    pub light_probe_radius: f32,
+    pub light_probe_intensity: f32,
+
+    // Reflection probes
+    pub reflection_probe_count: usize,
+    pub reflection_probes_baked: usize,
+    pub reflection_probe_extents: [f32; 3], // Half extents of the box of new probes
+    pub reflection_probe_intensity: f32,
+
+    // Planar reflection
+    pub planar_reflection_enabled: bool,
+    pub planar_reflection_scale: f32, // Resolution of the mirrored view relative to the window
+    pub planar_reflection_strength: f32,
+
+    pub toon: ToonStyle,
+
+    // Post effects
+    pub dither: f32, // In 8-bit steps
+    pub grain: f32,
+    pub vignette: f32,
+    pub lens_flare: Option<FlareStyle>, // None without a glTF scene
+    pub sun_visibility: f32,
+    pub god_rays: Option<GodRayStyle>, // None without a glTF scene
+    pub ssgi: Option<SsgiStyle>, // None without a glTF scene
+    pub weather: Option<Weather>, // None without a glTF scene
+    pub half_res_transparency: Option<bool>, // None without the half-resolution pass
+
+    // Lightmap
+    pub lightmap_available: bool, // Needs the glTF scene
+    pub lightmap_baked: bool,
+    pub lightmap_enabled: bool,
+    pub lightmap_bake_progress: Option<f32>, // While baking
+    pub lightmap_uncharted_meshes: usize,
+
+    // Memory
+    pub gpu_memory_allocated: u64, // Bytes in live allocations
+    pub gpu_memory_reserved: u64,  // Bytes in allocator memory blocks
+    pub gpu_memory_blocks: usize,
+    pub defrag_enabled: bool,
+    pub defrag: DefragStats,
+    pub texture_streaming: Option<texture_streaming::StreamingStats>, // Needs the glTF scene
+    pub streaming_enabled: bool,
+    pub streaming_budget_mb: u32,
+    pub texture_filter: texture_streaming::TextureFilter,
+    pub max_anisotropy: u32, // 0 without anisotropic filtering
+    pub virtual_texture: Option<String>, // Summary, when the scene texture is virtual
+    pub texture_arrays: Option<String>,  // One line per array, when material textures are packed
+    
+    // Display
+    pub monitors: Vec<String>,
+    pub monitor_index: usize,
+    pub video_modes: Vec<String>, // Of the selected monitor
+    pub video_mode_index: usize,  // 0 = best available, else 1 + index into `video_modes`
+    pub exclusive_fullscreen: bool,
+    pub fullscreen: bool,
+    
+    // Stills
+    pub still_available: bool, // Needs the glTF scene
+    pub export_available: bool, // Needs the glTF scene, loaded from a file
+    pub still_scale: u32,
+    pub still_samples: u32,
+    pub panorama_width: u32,
+    
+    // Stress test
+    pub stress_count: u32,
+    pub stress_running: bool,
+    pub stress_report: Option<String>, // Summary of the last finished run
+    
+    // Impostors
+    pub impostors_available: bool, // Needs the instanced cube renderer or a glTF scene
+    pub impostors_enabled: bool,
+    pub impostor_distance: f32,
+    pub impostor_count: u32, // Cubes and glTF instances drawn as impostors last frame
 }
 
 #[derive(Default, Clone, Copy)]
 pub struct UiChanges {
+    pub render_on_demand: Option<bool>,
+    pub camera_fov: Option<f32>,
+    pub orthographic: Option<bool>,
+    pub ortho_height: Option<f32>,
+    pub present_mode: Option<PresentModeConfig>,
+    pub aspect_lock: Option<AspectLock>,
+    pub time_scale: Option<f32>,
+    pub time_paused: Option<bool>,
+    pub time_step: bool,
     pub gltf_scale: Option<f32>,
+    pub trackball: Option<bool>,
+    pub reset_model_transform: bool,
+    pub spawn_model_instance: bool,
+    pub despawn_model_instance: bool,
+    pub visibility: Option<(Entity, bool)>, // Shown or hidden from the scene tree
+    pub cubes_visible: Option<bool>,
+    pub entity_layers: Option<(Entity, RenderLayers)>,
+    pub main_layers: Option<RenderLayers>,
+    pub view_layers: Option<RenderLayers>,
+    pub ground: Option<GroundStyle>,
+    pub cube_spawn_count: Option<u32>,
+    pub spawn_cubes: bool,
+    pub despawn_cubes: bool,
+    pub stress_count: Option<u32>,
+    pub start_stress_test: bool,
+    pub impostors_enabled: Option<bool>,
+    pub impostor_distance: Option<f32>,
+    pub open_window: bool,
+    pub compute_skinning: Option<bool>,
+    pub skeleton_debug: Option<bool>,
+    pub joint_labels: Option<bool>,
+    pub animation_clip: Option<Option<usize>>,
+    pub animation_playing: Option<bool>,
+    pub animation_speed: Option<f32>,
+    pub animation_time: Option<f32>,
+    pub mesh_shading: Option<bool>,
+    pub gpu_driven: Option<bool>,
+
+    pub material_editor_open: Option<bool>,
+    pub material_selected_mesh: Option<usize>,
+    pub material_override: Option<MaterialOverride>,
+    pub reset_material: bool,
+    pub save_material: bool,
 
     pub shadow_settings_changed: bool,
     pub shadow_debug_cascades: bool,
+    pub shadow_show_frustums: bool,
     pub shadow_softness: f32,
     pub shadow_use_pcss: bool,
     pub shadow_use_taa: bool,
+    pub shadow_samples: u32,
+    pub light_count: u32,
+    pub shadow_ray_traced: bool,
+    pub ray_traced_ao: bool,
+    pub ao_rays: u32,
+
+    pub light_probe_radius: Option<f32>,
+    pub light_probe_intensity: Option<f32>,
+    pub place_light_probe: bool,
+    pub bake_light_probes: bool,
+    pub clear_light_probes: bool,
+
+    pub reflection_probe_extents: Option<[f32; 3]>,
+    pub reflection_probe_intensity: Option<f32>,
+    pub place_reflection_probe: bool,
+    pub bake_reflection_probes: bool,
+    pub clear_reflection_probes: bool,
+    pub planar_reflection_enabled: Option<bool>,
+    pub planar_reflection_scale: Option<f32>,
+    pub planar_reflection_strength: Option<f32>,
+    pub toon: Option<ToonStyle>,
+    pub dither: Option<f32>,
+    pub grain: Option<f32>,
+    pub vignette: Option<f32>,
+    pub lens_flare: Option<FlareStyle>,
+    pub god_rays: Option<GodRayStyle>,
+    pub ssgi: Option<SsgiStyle>,
+    pub weather: Option<Weather>,
+    pub half_res_transparency: Option<bool>,
+
+    pub lightmap_enabled: Option<bool>,
+    pub bake_lightmap: bool,
+
+    pub defrag_enabled: Option<bool>,
+    pub streaming_enabled: Option<bool>,
+    pub streaming_budget_mb: Option<u32>,
+    pub texture_filter: Option<texture_streaming::TextureFilter>,
+
+    pub monitor_index: Option<usize>,
+    pub video_mode_index: Option<usize>,
+    pub exclusive_fullscreen: Option<bool>,
+    pub toggle_fullscreen: bool,
+
+    pub still_scale: Option<u32>,
+    pub still_samples: Option<u32>,
+    pub render_still: bool,
+    pub panorama_width: Option<u32>,
+    pub capture_panorama: bool,
+    pub export_scene: Option<ExportFormat>,
+    pub save_diagnostics: bool,
+}
+
+/// An entity in the scene tree, with its children
+pub struct SceneTreeNode {
+    pub entity: Entity,
+    pub label: String,
+    pub visible: bool, // Its own `Visibility`
+    pub shown: bool,   // Whether it is drawn, which a hidden ancestor prevents
+    pub layers: RenderLayers,
+    pub children: Vec<SceneTreeNode>,
 }
 
 pub struct ComponentCounts {
@@ -96,13 +364,104 @@ pub struct ComponentCounts {
 
 fn render_debug_ui(ctx: &egui::Context, data: &UiData) -> UiChanges {
     let mut changes = UiChanges {
+        render_on_demand: None,
+        camera_fov: None,
+        orthographic: None,
+        ortho_height: None,
+        present_mode: None,
+        aspect_lock: None,
+        time_scale: None,
+        time_paused: None,
+        time_step: false,
         gltf_scale: None,
+        trackball: None,
+        reset_model_transform: false,
+        spawn_model_instance: false,
+        despawn_model_instance: false,
+        visibility: None,
+        cubes_visible: None,
+        entity_layers: None,
+        main_layers: None,
+        view_layers: None,
+        ground: None,
+        cube_spawn_count: None,
+        spawn_cubes: false,
+        despawn_cubes: false,
+        stress_count: None,
+        start_stress_test: false,
+        impostors_enabled: None,
+        impostor_distance: None,
+        open_window: false,
+        compute_skinning: None,
+        material_editor_open: None,
+        material_selected_mesh: None,
+        material_override: None,
+        reset_material: false,
+        save_material: false,
+        skeleton_debug: None,
+        joint_labels: None,
+        animation_clip: None,
+        animation_playing: None,
+        animation_speed: None,
+        animation_time: None,
+        mesh_shading: None,
+        gpu_driven: None,
 
         shadow_settings_changed: false,
         shadow_debug_cascades: data.shadow_debug_cascades,
+        shadow_show_frustums: data.shadow_show_frustums,
         shadow_softness: data.shadow_softness,
         shadow_use_pcss: data.shadow_use_pcss,
         shadow_use_taa: data.shadow_use_taa,
+        shadow_samples: data.shadow_samples,
+        light_count: data.light_count,
+        shadow_ray_traced: data.shadow_ray_traced,
+        ray_traced_ao: data.ray_traced_ao,
+        ao_rays: data.ao_rays,
+
+        light_probe_radius: None,
+        light_probe_intensity: None,
+        place_light_probe: false,
+        bake_light_probes: false,
+        clear_light_probes: false,
+        reflection_probe_extents: None,
+        reflection_probe_intensity: None,
+        place_reflection_probe: false,
+        bake_reflection_probes: false,
+        clear_reflection_probes: false,
+        planar_reflection_enabled: None,
+        planar_reflection_scale: None,
+        planar_reflection_strength: None,
+        toon: None,
+        dither: None,
+        grain: None,
+        vignette: None,
+        lens_flare: None,
+        god_rays: None,
+        ssgi: None,
+        weather: None,
+        half_res_transparency: None,
+
+        lightmap_enabled: None,
+        bake_lightmap: false,
+
+        defrag_enabled: None,
+        streaming_enabled: None,
+        streaming_budget_mb: None,
+        texture_filter: None,
+
+        monitor_index: None,
+        video_mode_index: None,
+        exclusive_fullscreen: None,
+        toggle_fullscreen: false,
+
+        still_scale: None,
+        still_samples: None,
+        render_still: false,
+        panorama_width: None,
+        capture_panorama: false,
+        export_scene: None,
+        save_diagnostics: false,
     };
     
     egui::Window::new("🎮 Funky Renderer Debug")
@@ -122,6 +481,144 @@ fn render_debug_ui(ctx: &egui::Context, data: &UiData) -> UiChanges {
                 ui.colored_label(egui::Color32::LIGHT_BLUE, format!("{:.2} ms", data.frame_time_ms));
             });
             
+            let mut render_on_demand = data.render_on_demand;
+            if ui.checkbox(&mut render_on_demand, "Power saving (render on demand)").changed() {
+                changes.render_on_demand = Some(render_on_demand);
+            }
+            if data.render_on_demand {
+                ui.small(format!(
+                    "Redraws on input or animation, otherwise at {:.0} Hz",
+                    data.idle_rate_hz
+                ));
+            }
+            
+            ui.horizontal(|ui| {
+                ui.label("Async compute:");
+                match data.async_compute {
+                    Some(stats) => {
+                        let overlap = if stats.compute_ms > 0.0 { stats.overlap_ms / stats.compute_ms * 100.0 } else { 0.0 };
+                        ui.colored_label(
+                            egui::Color32::LIGHT_BLUE,
+                            format!("{:.2} ms, {:.0}% overlapped", stats.compute_ms, overlap),
+                        );
+                    }
+                    None => {
+                        ui.label("off (no compute-only queue)");
+                    }
+                }
+            });
+            if let Some(stats) = data.async_compute {
+                ui.small(format!(
+                    "{:.2} ms of compute hidden under {:.2} ms of shadow/opaque rendering",
+                    stats.overlap_ms, stats.graphics_ms
+                ));
+            }
+            
+            egui::CollapsingHeader::new("Draw statistics").default_open(true).show(ui, |ui| {
+                let history = &data.draw_history;
+                stat_graph(ui, "Draw calls", history, egui::Color32::LIGHT_BLUE, |s| s.draw_calls as f64);
+                stat_graph(ui, "Instances", history, egui::Color32::LIGHT_GREEN, |s| s.instances as f64);
+                stat_graph(ui, "Triangles", history, egui::Color32::GOLD, |s| s.triangles as f64);
+                stat_graph(ui, "Culled (frustum)", history, egui::Color32::LIGHT_RED, |s| s.culled as f64);
+                stat_graph(ui, "State changes", history, egui::Color32::LIGHT_GRAY, |s| s.state_changes as f64);
+                ui.small("State changes: pipeline, descriptor set and vertex/index buffer binds");
+
+                if let Some(stats) = data.pipeline_stats {
+                    ui.add_space(5.0);
+                    ui.label("Scene pass on the GPU:");
+                    egui::Grid::new("pipeline_stats").num_columns(2).show(ui, |ui| {
+                        let rows = [
+                            ("Vertices fetched", stats.input_vertices),
+                            ("Primitives fetched", stats.input_primitives),
+                            ("Vertex shader invocations", stats.vertex_invocations),
+                            ("Clipping input", stats.clipping_invocations),
+                            ("Clipping output", stats.clipping_primitives),
+                            ("Fragment shader invocations", stats.fragment_invocations),
+                        ];
+                        for (label, count) in rows {
+                            ui.label(label);
+                            ui.colored_label(egui::Color32::LIGHT_BLUE, format_count(count));
+                            ui.end_row();
+                        }
+                    });
+                    ui.small("Mesh shader draws are not counted by the vertex stages");
+                }
+            });
+            
+            ui.add_space(10.0);
+            ui.heading("Camera");
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                let mut orthographic = data.orthographic;
+                ui.selectable_value(&mut orthographic, false, "Perspective");
+                ui.selectable_value(&mut orthographic, true, "Orthographic");
+                if orthographic != data.orthographic {
+                    changes.orthographic = Some(orthographic);
+                }
+            });
+            if data.orthographic {
+                let mut height = data.ortho_height;
+                if ui
+                    .add(egui::Slider::new(&mut height, 0.1..=200.0).logarithmic(true).text("View height (m)"))
+                    .changed()
+                {
+                    changes.ortho_height = Some(height);
+                }
+            } else {
+                let mut fov = data.camera_fov.to_degrees();
+                if ui.add(egui::Slider::new(&mut fov, 10.0..=120.0).text("Field of view (°)")).changed() {
+                    changes.camera_fov = Some(fov.to_radians());
+                }
+            }
+            ui.small("Z/X and the mouse wheel zoom either way");
+            ui.horizontal(|ui| {
+                ui.label("Aspect:");
+                let mut aspect_lock = data.aspect_lock;
+                for lock in AspectLock::ALL {
+                    ui.selectable_value(&mut aspect_lock, lock, lock.label());
+                }
+                if aspect_lock != data.aspect_lock {
+                    changes.aspect_lock = Some(aspect_lock);
+                }
+            });
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Layers:");
+                changes.main_layers = layer_toggles(ui, data.main_layers);
+            });
+
+            ui.add_space(10.0);
+            ui.heading("Time");
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button(if data.time_paused { "▶ Play" } else { "⏸ Pause" }).clicked() {
+                    changes.time_paused = Some(!data.time_paused);
+                }
+                if ui.button("⏭ Step").clicked() {
+                    changes.time_step = true;
+                }
+            });
+            let mut scale = data.time_scale;
+            if ui
+                .add(
+                    egui::Slider::new(&mut scale, time_control::MIN_SCALE..=time_control::MAX_SCALE)
+                        .logarithmic(true)
+                        .text("Speed"),
+                )
+                .changed()
+            {
+                changes.time_scale = Some(scale);
+            }
+            ui.horizontal(|ui| {
+                for preset in time_control::PRESET_SCALES {
+                    if ui.selectable_label(data.time_scale == preset, format!("{}×", preset)).clicked() {
+                        changes.time_scale = Some(preset);
+                    }
+                }
+            });
+            ui.small("P pauses, . steps a frame, [ and ] halve and double the speed");
+
             ui.add_space(10.0);
             ui.heading("Scene Objects");
             ui.separator();
@@ -133,16 +630,261 @@ fn render_debug_ui(ctx: &egui::Context, data: &UiData) -> UiChanges {
                 changes.gltf_scale = Some(gltf_scale);
             }
 
+            ui.horizontal(|ui| {
+                let mut trackball = data.trackball;
+                if ui.checkbox(&mut trackball, "Trackball").changed() {
+                    changes.trackball = Some(trackball);
+                }
+                if ui.button("Reset placement").clicked() {
+                    changes.reset_model_transform = true;
+                }
+            });
+            if data.trackball {
+                ui.small("Drag to turn the model, shift-drag to move it over the ground");
+            }
+            if data.ground.is_some() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Instances: {}", data.model_instances));
+                    if ui.button("Add").clicked() {
+                        changes.spawn_model_instance = true;
+                    }
+                    if ui.add_enabled(data.model_instances > 0, egui::Button::new("Remove")).clicked() {
+                        changes.despawn_model_instance = true;
+                    }
+                });
+            }
+
+            if let Some(style) = data.ground {
+                ui.add_space(5.0);
+                let mut ground = style;
+                ui.checkbox(&mut ground.visible, "Ground plane");
+                ui.add_enabled_ui(ground.visible, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(&mut ground.pattern, GroundPattern::Solid, "Solid");
+                        ui.selectable_value(&mut ground.pattern, GroundPattern::Checker, "Checker");
+                        ui.color_edit_button_rgb(&mut ground.color);
+                        if ground.pattern == GroundPattern::Checker {
+                            ui.color_edit_button_rgb(&mut ground.checker_color);
+                        }
+                    });
+                    if ground.pattern == GroundPattern::Checker {
+                        ui.add(egui::Slider::new(&mut ground.checker_size, 1..=5).text("Checker size (m)"));
+                    }
+                    ui.checkbox(&mut ground.fade, "Fade into the sky");
+                    ui.add_enabled(
+                        ground.fade,
+                        egui::Slider::new(&mut ground.fade_start, 0.0..=GROUND_SIZE * 0.5).text("Fade start (m)"),
+                    );
+                });
+                if ground != style {
+                    changes.ground = Some(ground);
+                }
+            }
+
+            ui.add_space(5.0);
+            ui.label(format!("Cubes: {}", data.cube_count));
+            ui.horizontal(|ui| {
+                let mut spawn_count = data.cube_spawn_count;
+                if ui.add(egui::DragValue::new(&mut spawn_count).range(1..=10_000)).changed() {
+                    changes.cube_spawn_count = Some(spawn_count);
+                }
+                if ui.button(format!("Spawn {} cubes", spawn_count)).clicked() {
+                    changes.cube_spawn_count = Some(spawn_count);
+                    changes.spawn_cubes = true;
+                }
+                if ui.button("Clear").clicked() {
+                    changes.despawn_cubes = true;
+                }
+            });
+
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                let mut stress_count = data.stress_count;
+                if ui.add(egui::DragValue::new(&mut stress_count).range(1..=1_000_000)).changed() {
+                    changes.stress_count = Some(stress_count);
+                }
+                ui.add_enabled_ui(!data.stress_running, |ui| {
+                    if ui.button("Run stress test").clicked() {
+                        changes.stress_count = Some(stress_count);
+                        changes.start_stress_test = true;
+                    }
+                });
+            });
+            if data.stress_running {
+                ui.small("Measuring frame times...");
+            } else if let Some(report) = &data.stress_report {
+                ui.small(report);
+            } else {
+                ui.small("Replaces the cubes with a grid and reports frame times after 6 s");
+            }
+
+            egui::CollapsingHeader::new("Scene tree").show(ui, |ui| {
+                ui.small("Right-click an entity to pick its render layers");
+                ui.horizontal(|ui| {
+                    let mut visible = data.cubes_visible;
+                    if ui.toggle_value(&mut visible, "👁").changed() {
+                        changes.cubes_visible = Some(visible);
+                    }
+                    ui.label(format!("Cubes ({})", data.cube_count));
+                });
+                for node in &data.scene_tree {
+                    scene_tree_node(ui, node, &mut changes);
+                }
+            });
+
+            if data.impostors_available {
+                ui.horizontal(|ui| {
+                    let mut enabled = data.impostors_enabled;
+                    if ui.checkbox(&mut enabled, "Impostors beyond").changed() {
+                        changes.impostors_enabled = Some(enabled);
+                    }
+                    let mut distance = data.impostor_distance;
+                    if ui
+                        .add_enabled(enabled, egui::Slider::new(&mut distance, 2.0..=100.0).suffix(" m"))
+                        .changed()
+                    {
+                        changes.impostor_distance = Some(distance);
+                    }
+                });
+                ui.small(format!("{} cubes and instances drawn as baked camera-facing quads", data.impostor_count));
+            }
+
+            if data.skinned_mesh_count > 0 {
+                let mut compute_skinning = data.compute_skinning;
+                if ui
+                    .checkbox(&mut compute_skinning, format!("Compute skinning ({} meshes)", data.skinned_mesh_count))
+                    .changed()
+                {
+                    changes.compute_skinning = Some(compute_skinning);
+                }
+                ui.small("Skinned once per frame, shared by shadow and main passes");
+
+                ui.horizontal(|ui| {
+                    let mut skeleton_debug = data.skeleton_debug;
+                    if ui.checkbox(&mut skeleton_debug, "Show skeleton").changed() {
+                        changes.skeleton_debug = Some(skeleton_debug);
+                    }
+                    let mut joint_labels = data.joint_labels;
+                    if ui
+                        .add_enabled(data.skeleton_debug, egui::Checkbox::new(&mut joint_labels, "Joint names"))
+                        .changed()
+                    {
+                        changes.joint_labels = Some(joint_labels);
+                    }
+                });
+                if data.skeleton_debug {
+                    ui.small("Grey: bind pose from the inverse bind matrices");
+                }
+            }
+
+            if !data.animation_clips.is_empty() {
+                let mut clip = data.animation_clip;
+                let clip_name = clip.map_or("Rest pose", |clip| data.animation_clips[clip].as_str());
+                egui::ComboBox::from_label("Animation").selected_text(clip_name).show_ui(ui, |ui| {
+                    ui.selectable_value(&mut clip, None, "Rest pose");
+                    for (i, name) in data.animation_clips.iter().enumerate() {
+                        ui.selectable_value(&mut clip, Some(i), name);
+                    }
+                });
+                if clip != data.animation_clip {
+                    changes.animation_clip = Some(clip);
+                }
+                ui.add_enabled_ui(data.animation_clip.is_some(), |ui| {
+                    ui.horizontal(|ui| {
+                        let mut playing = data.animation_playing;
+                        if ui.checkbox(&mut playing, "Play").changed() {
+                            changes.animation_playing = Some(playing);
+                        }
+                        let mut speed = data.animation_speed;
+                        if ui
+                            .add(egui::Slider::new(&mut speed, 0.1..=4.0).logarithmic(true).suffix("x").text("speed"))
+                            .changed()
+                        {
+                            changes.animation_speed = Some(speed);
+                        }
+                    });
+                    let mut time = data.animation_time;
+                    if ui
+                        .add(egui::Slider::new(&mut time, 0.0..=data.animation_duration).suffix(" s").text("Time"))
+                        .changed()
+                    {
+                        changes.animation_time = Some(time);
+                    }
+                });
+            }
+
+            if data.meshlet_count > 0 {
+                let mut mesh_shading = data.mesh_shading;
+                if ui
+                    .checkbox(&mut mesh_shading, format!("Mesh shaders ({} meshlets)", data.meshlet_count))
+                    .changed()
+                {
+                    changes.mesh_shading = Some(mesh_shading);
+                }
+                ui.small("Task shader culls meshlets by frustum and normal cone");
+            }
+
+            // The other instances are culled on the GPU too
+            if data.gpu_driven_meshes > 0 || data.model_instances > 1 {
+                let mut gpu_driven = data.gpu_driven;
+                let label = format!(
+                    "GPU-driven draws ({} meshes, {} calls)",
+                    data.gpu_driven_meshes, data.gpu_driven_calls
+                );
+                if ui.checkbox(&mut gpu_driven, label).changed() {
+                    changes.gpu_driven = Some(gpu_driven);
+                }
+                if data.gpu_driven_culling {
+                    ui.small("Compute culling writes draws + counts; off while mesh shaders draw");
+                } else {
+                    ui.small("Unculled batches, no draw indirect count; off while mesh shaders draw");
+                }
+                if data.model_instances > 1 {
+                    ui.small("Other instances: compute culling writes one indirect draw per mesh");
+                }
+            }
+
+            if data.material.is_some() {
+                let mut open = data.material_editor_open;
+                if ui.checkbox(&mut open, "🎨 Material editor").changed() {
+                    changes.material_editor_open = Some(open);
+                }
+            }
+
             ui.add_space(10.0);
             ui.heading("Shadows");
             ui.separator();
 
+            let mut ray_traced = data.shadow_ray_traced && data.ray_traced_shadows_supported;
+            let response = ui.add_enabled(
+                data.ray_traced_shadows_supported,
+                egui::Checkbox::new(&mut ray_traced, "Ray-traced shadows (ray query)"),
+            );
+            if response.changed() {
+                changes.shadow_settings_changed = true;
+                changes.shadow_ray_traced = ray_traced;
+            }
+            if data.ray_traced_shadows_supported {
+                ui.small("Per-pixel shadow rays against a TLAS; replaces the cascades");
+            } else {
+                ui.small("Not supported by this GPU, using shadow maps");
+            }
+
             let mut debug_cascades = data.shadow_debug_cascades;
             if ui.checkbox(&mut debug_cascades, "Debug cascades").changed() {
                 changes.shadow_settings_changed = true;
                 changes.shadow_debug_cascades = debug_cascades;
             }
 
+            let mut show_frustums = data.shadow_show_frustums;
+            if ui.checkbox(&mut show_frustums, "Show frustums").changed() {
+                changes.shadow_settings_changed = true;
+                changes.shadow_show_frustums = show_frustums;
+            }
+            if data.shadow_show_frustums {
+                ui.small("Cascade light volumes, and the cameras of view windows");
+            }
+
             let mut use_pcss = data.shadow_use_pcss;
             if ui.checkbox(&mut use_pcss, "PCSS (contact hardening)").changed() {
                 changes.shadow_settings_changed = true;
@@ -166,6 +908,316 @@ fn render_debug_ui(ctx: &egui::Context, data: &UiData) -> UiChanges {
                 changes.shadow_softness = softness;
             }
             ui.small("Controls penumbra width");
+
+            let mut samples = data.shadow_samples;
+            if ui
+                .add(egui::Slider::new(&mut samples, 4..=32).text("Shadow samples"))
+                .changed()
+            {
+                changes.shadow_settings_changed = true;
+                changes.shadow_samples = samples;
+            }
+
+            let mut fill_light = data.light_count > 1;
+            if ui.checkbox(&mut fill_light, "Fill light").changed() {
+                changes.shadow_settings_changed = true;
+                changes.light_count = if fill_light { 2 } else { 1 };
+            }
+            ui.small("Samples, PCSS, lights and debug view are specialization constants");
+
+            ui.add_space(10.0);
+            ui.heading("Ambient Occlusion");
+            ui.separator();
+
+            let mut ray_traced_ao = data.ray_traced_ao && data.ray_traced_shadows_supported;
+            let response = ui.add_enabled(
+                data.ray_traced_shadows_supported,
+                egui::Checkbox::new(&mut ray_traced_ao, "Ray-traced AO (ray query)"),
+            );
+            if response.changed() {
+                changes.shadow_settings_changed = true;
+                changes.ray_traced_ao = ray_traced_ao;
+            }
+            if data.ray_traced_shadows_supported {
+                ui.small("Short occlusion rays, accumulated in the shadow history");
+            } else {
+                ui.small("Not supported by this GPU");
+            }
+
+            let mut ao_rays = data.ao_rays;
+            if ui
+                .add_enabled(ray_traced_ao, egui::Slider::new(&mut ao_rays, 1..=16).text("AO rays"))
+                .changed()
+            {
+                changes.shadow_settings_changed = true;
+                changes.ao_rays = ao_rays;
+            }
+
+            if let Some(style) = data.ssgi {
+                ui.add_space(10.0);
+                ui.heading("Global Illumination");
+                ui.separator();
+
+                let mut ssgi = style;
+                ui.checkbox(&mut ssgi.enabled, "Screen-space bounce light");
+                ui.add_enabled_ui(ssgi.enabled, |ui| {
+                    ui.add(egui::Slider::new(&mut ssgi.strength, 0.0..=4.0).text("Strength"));
+                    ui.add(egui::Slider::new(&mut ssgi.rays, 1..=16).text("Rays"));
+                    ui.add(egui::Slider::new(&mut ssgi.ray_length, 0.25..=5.0).text("Ray length (m)"));
+                });
+                ui.small("Traced at half resolution against the last frame");
+                if ssgi != style {
+                    changes.ssgi = Some(ssgi);
+                }
+            }
+
+            ui.add_space(10.0);
+            ui.heading("Light Probes");
+            ui.separator();
+
+            ui.label(format!(
+                "Probes: {} ({} baked, up to {} used)",
+                data.light_probe_count,
+                data.light_probes_baked,
+                light_probes::MAX_LIGHT_PROBES
+            ));
+            let mut radius = data.light_probe_radius;
+            if ui
+                .add(egui::Slider::new(&mut radius, 1.0..=30.0).text("Radius of new probes"))
+                .changed()
+            {
+                changes.light_probe_radius = Some(radius);
+            }
+            let mut intensity = data.light_probe_intensity;
+            if ui
+                .add(egui::Slider::new(&mut intensity, 0.0..=4.0).text("Intensity"))
+                .changed()
+            {
+                changes.light_probe_intensity = Some(intensity);
+            }
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(data.light_probes_available, egui::Button::new("📍 Place at camera"))
+                    .clicked()
+                {
+                    changes.place_light_probe = true;
+                }
+                if ui
+                    .add_enabled(data.light_probe_count > 0, egui::Button::new("💡 Bake"))
+                    .clicked()
+                {
+                    changes.bake_light_probes = true;
+                }
+                if ui
+                    .add_enabled(data.light_probe_count > 0, egui::Button::new("Clear"))
+                    .clicked()
+                {
+                    changes.clear_light_probes = true;
+                }
+            });
+
+            ui.add_space(10.0);
+            ui.heading("Reflection Probes");
+            ui.separator();
+
+            ui.label(format!(
+                "Probes: {} ({} baked, up to {} used)",
+                data.reflection_probe_count,
+                data.reflection_probes_baked,
+                reflection_probes::MAX_REFLECTION_PROBES
+            ));
+            let mut extents = data.reflection_probe_extents;
+            ui.horizontal(|ui| {
+                ui.label("Box of new probes ±");
+                let mut changed = false;
+                for extent in &mut extents {
+                    changed |= ui.add(egui::DragValue::new(extent).speed(0.1).range(0.5..=50.0)).changed();
+                }
+                if changed {
+                    changes.reflection_probe_extents = Some(extents);
+                }
+            });
+            let mut intensity = data.reflection_probe_intensity;
+            if ui
+                .add(egui::Slider::new(&mut intensity, 0.0..=4.0).text("Intensity"))
+                .changed()
+            {
+                changes.reflection_probe_intensity = Some(intensity);
+            }
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(data.light_probes_available, egui::Button::new("📍 Place at camera"))
+                    .clicked()
+                {
+                    changes.place_reflection_probe = true;
+                }
+                if ui
+                    .add_enabled(data.reflection_probe_count > 0, egui::Button::new("🪞 Bake"))
+                    .clicked()
+                {
+                    changes.bake_reflection_probes = true;
+                }
+                if ui
+                    .add_enabled(data.reflection_probe_count > 0, egui::Button::new("Clear"))
+                    .clicked()
+                {
+                    changes.clear_reflection_probes = true;
+                }
+            });
+            ui.small("Objects within a baked probe's radius take their ambient light from it");
+
+            ui.add_space(10.0);
+            ui.heading("Planar Reflection");
+            ui.separator();
+
+            let mut planar_enabled = data.planar_reflection_enabled;
+            if ui
+                .add_enabled(data.light_probes_available, egui::Checkbox::new(&mut planar_enabled, "Mirror the scene in the ground"))
+                .changed()
+            {
+                changes.planar_reflection_enabled = Some(planar_enabled);
+            }
+            let mut scale = data.planar_reflection_scale;
+            if ui
+                .add(egui::Slider::new(&mut scale, 0.25..=1.0).text("Resolution scale"))
+                .changed()
+            {
+                changes.planar_reflection_scale = Some(scale);
+            }
+            let mut strength = data.planar_reflection_strength;
+            if ui
+                .add(egui::Slider::new(&mut strength, 0.0..=1.0).text("Strength"))
+                .changed()
+            {
+                changes.planar_reflection_strength = Some(strength);
+            }
+            ui.small("Renders the scene a second time, mirrored, before the main pass");
+
+            ui.add_space(10.0);
+            ui.heading("Toon Shading");
+            ui.separator();
+
+            let mut toon = data.toon;
+            ui.add_enabled(data.light_probes_available, egui::Checkbox::new(&mut toon.enabled, "Cel shade every mesh"));
+            ui.add(egui::Slider::new(&mut toon.bands, 2..=8).text("Bands"));
+            ui.add(egui::Slider::new(&mut toon.rim, 0.0..=1.0).text("Rim light"));
+            ui.add(egui::Slider::new(&mut toon.outline_width, 0.0..=8.0).text("Outline (px)"));
+            ui.horizontal(|ui| {
+                ui.label("Outline color");
+                ui.color_edit_button_rgb(&mut toon.outline_color);
+            });
+            if toon != data.toon {
+                changes.toon = Some(toon);
+            }
+            ui.small("Single materials can be cel shaded from the material editor");
+
+            ui.add_space(10.0);
+            ui.heading("Post Effects");
+            ui.separator();
+
+            let mut dither = data.dither;
+            if ui.add(egui::Slider::new(&mut dither, 0.0..=4.0).text("Dither (8-bit steps)")).changed() {
+                changes.dither = Some(dither);
+            }
+            let mut grain = data.grain;
+            if ui.add(egui::Slider::new(&mut grain, 0.0..=0.5).text("Film grain")).changed() {
+                changes.grain = Some(grain);
+            }
+            let mut vignette = data.vignette;
+            if ui.add(egui::Slider::new(&mut vignette, 0.0..=1.0).text("Vignette")).changed() {
+                changes.vignette = Some(vignette);
+            }
+            ui.small("Dithering hides banding in smooth gradients on 8-bit displays");
+
+            if let Some(style) = data.lens_flare {
+                let mut flare = style;
+                ui.add(egui::Slider::new(&mut flare.intensity, 0.0..=3.0).text("Lens flare"));
+                ui.add(egui::Slider::new(&mut flare.ghosts, 0..=12).text("Ghosts"));
+                ui.add(egui::Slider::new(&mut flare.ghost_spacing, 0.1..=1.0).text("Ghost spacing"));
+                ui.add(egui::Slider::new(&mut flare.glow_size, 0.05..=1.0).text("Glow size"));
+                if flare != style {
+                    changes.lens_flare = Some(flare);
+                }
+                ui.label(format!("Sun visible: {:.0}%", data.sun_visibility * 100.0));
+            }
+
+            if let Some(style) = data.god_rays {
+                let mut rays = style;
+                ui.add(egui::Slider::new(&mut rays.intensity, 0.0..=2.0).text("God rays"));
+                ui.add(egui::Slider::new(&mut rays.length, 0.1..=1.0).text("Ray length"));
+                ui.add(egui::Slider::new(&mut rays.decay, 0.9..=1.0).text("Ray decay"));
+                if rays != style {
+                    changes.god_rays = Some(rays);
+                }
+            }
+
+            if let Some(current) = data.weather {
+                ui.add_space(10.0);
+                ui.heading("Weather");
+                ui.separator();
+
+                let mut weather = current;
+                ui.horizontal_wrapped(|ui| {
+                    for preset in WeatherPreset::ALL {
+                        if ui.button(preset.name()).clicked() {
+                            weather.apply_preset(preset);
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut weather.precipitation, Precipitation::None, "None");
+                    ui.selectable_value(&mut weather.precipitation, Precipitation::Rain, "Rain");
+                    ui.selectable_value(&mut weather.precipitation, Precipitation::Snow, "Snow");
+                });
+                ui.add(egui::Slider::new(&mut weather.intensity, 0.0..=1.0).text("Intensity"));
+                ui.add(egui::Slider::new(&mut weather.wind_direction, 0.0..=360.0).text("Wind direction (°)"));
+                ui.add(egui::Slider::new(&mut weather.wind_speed, 0.0..=15.0).text("Wind speed (m/s)"));
+                ui.add(egui::Slider::new(&mut weather.wetness, 0.0..=1.0).text("Wetness"));
+                ui.add(egui::Slider::new(&mut weather.snow_cover, 0.0..=1.0).text("Snow cover"));
+                ui.add(egui::Slider::new(&mut weather.accumulation, 0.0..=0.5).text("Accumulation rate"));
+                if weather != current {
+                    changes.weather = Some(weather);
+                }
+                ui.small("Wetness and snow cover build up while it falls and fade after");
+            }
+
+            if let Some(current) = data.half_res_transparency {
+                let mut enabled = current;
+                if ui.checkbox(&mut enabled, "Particles at half resolution").changed() {
+                    changes.half_res_transparency = Some(enabled);
+                }
+                ui.small("Fountain, rain and snow drawn at a quarter of the pixels, upsampled by depth");
+            }
+
+            ui.add_space(10.0);
+            ui.heading("Lightmap");
+            ui.separator();
+
+            let mut lightmap_enabled = data.lightmap_enabled;
+            if ui
+                .add_enabled(data.lightmap_baked, egui::Checkbox::new(&mut lightmap_enabled, "Use lightmap"))
+                .changed()
+            {
+                changes.lightmap_enabled = Some(lightmap_enabled);
+            }
+            ui.horizontal(|ui| {
+                let baking = data.lightmap_bake_progress.is_some();
+                if ui
+                    .add_enabled(data.lightmap_available && !baking, egui::Button::new("💡 Bake lightmap"))
+                    .clicked()
+                {
+                    changes.bake_lightmap = true;
+                }
+                if let Some(progress) = data.lightmap_bake_progress {
+                    ui.add(egui::ProgressBar::new(progress).show_percentage());
+                }
+            });
+            if data.lightmap_uncharted_meshes > 0 {
+                ui.small(format!(
+                    "{} mesh(es) have no lightmap UVs; run with --bake-lightmap to chart them",
+                    data.lightmap_uncharted_meshes
+                ));
+            }
             
             ui.add_space(10.0);
             ui.heading("Bevy ECS Stats");
@@ -196,16 +1248,385 @@ fn render_debug_ui(ctx: &egui::Context, data: &UiData) -> UiChanges {
                 ui.label(format!("• Renderables: {}", data.component_counts.renderables));
             });
             
+            ui.add_space(10.0);
+            ui.heading("Display");
+            ui.separator();
+
+            let mut monitor_index = data.monitor_index;
+            egui::ComboBox::from_label("Monitor")
+                .selected_text(data.monitors.get(monitor_index).map_or("None", String::as_str))
+                .show_ui(ui, |ui| {
+                    for (index, name) in data.monitors.iter().enumerate() {
+                        ui.selectable_value(&mut monitor_index, index, name);
+                    }
+                });
+            if monitor_index != data.monitor_index {
+                changes.monitor_index = Some(monitor_index);
+            }
+
+            let mut exclusive = data.exclusive_fullscreen;
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut exclusive, false, "Borderless");
+                ui.radio_value(&mut exclusive, true, "Exclusive");
+            });
+            if exclusive != data.exclusive_fullscreen {
+                changes.exclusive_fullscreen = Some(exclusive);
+            }
+
+            let mode_label = |index: usize| match index {
+                0 => "Best available",
+                _ => data.video_modes.get(index - 1).map_or("Best available", String::as_str),
+            };
+            let mut video_mode_index = data.video_mode_index;
+            ui.add_enabled_ui(data.exclusive_fullscreen, |ui| {
+                egui::ComboBox::from_label("Video mode")
+                    .selected_text(mode_label(video_mode_index))
+                    .show_ui(ui, |ui| {
+                        for index in 0..=data.video_modes.len() {
+                            ui.selectable_value(&mut video_mode_index, index, mode_label(index));
+                        }
+                    });
+            });
+            if video_mode_index != data.video_mode_index {
+                changes.video_mode_index = Some(video_mode_index);
+            }
+
+            let label = if data.fullscreen { "Leave fullscreen" } else { "Enter fullscreen" };
+            if ui.button(label).clicked() {
+                changes.toggle_fullscreen = true;
+            }
+            ui.small("F11 toggles with these settings; saved to display.cfg");
+
+            ui.add_space(10.0);
+            ui.heading("Stills");
+            ui.separator();
+
+            let mut still_scale = data.still_scale;
+            if ui
+                .add(egui::Slider::new(&mut still_scale, 1..=4).text("Resolution (x window)"))
+                .changed()
+            {
+                changes.still_scale = Some(still_scale);
+            }
+            // Rendered size is capped at MAX_RENDER_SCALE times the window
+            let max_samples = (screenshot::MAX_RENDER_SCALE / still_scale).max(1);
+            let mut still_samples = data.still_samples.min(max_samples);
+            if ui
+                .add(egui::Slider::new(&mut still_samples, 1..=max_samples).text("Supersampling"))
+                .changed()
+                || still_samples != data.still_samples
+            {
+                changes.still_samples = Some(still_samples);
+            }
+            if ui
+                .add_enabled(data.still_available, egui::Button::new("📷 Render still"))
+                .clicked()
+            {
+                changes.render_still = true;
+            }
+            ui.small(format!(
+                "F12 also renders; {}x the window, saved to screenshots/",
+                still_scale * still_samples
+            ));
+
+            ui.add_space(5.0);
+            let mut panorama_width = data.panorama_width;
+            egui::ComboBox::from_label("Panorama width")
+                .selected_text(panorama_width.to_string())
+                .show_ui(ui, |ui| {
+                    for width in [2048, 4096, 8192] {
+                        ui.selectable_value(&mut panorama_width, width, width.to_string());
+                    }
+                });
+            if panorama_width != data.panorama_width {
+                changes.panorama_width = Some(panorama_width);
+            }
+            if ui
+                .add_enabled(data.still_available, egui::Button::new("🌐 Capture 360° panorama"))
+                .clicked()
+            {
+                changes.capture_panorama = true;
+            }
+            ui.small("Equirectangular PNG + HDR from the camera position");
+
+            ui.add_space(10.0);
+            ui.heading("Export");
+            ui.separator();
+            ui.horizontal(|ui| {
+                for (format, label) in [(ExportFormat::Gltf, "💾 Export .gltf"), (ExportFormat::Glb, "💾 Export .glb")] {
+                    if ui.add_enabled(data.export_available, egui::Button::new(label)).clicked() {
+                        changes.export_scene = Some(format);
+                    }
+                }
+            });
+            ui.small("Model transform, edited materials, sun and camera; saved to exports/");
+
+            ui.add_space(10.0);
+            ui.heading("Textures");
+            ui.separator();
+            let mut filter = data.texture_filter;
+            ui.checkbox(&mut filter.mipmaps, "Mipmaps");
+            ui.small("Off samples only full-size texels, which shimmer at a distance");
+            if data.max_anisotropy > 0 {
+                ui.add(egui::Slider::new(&mut filter.anisotropy, 1..=16).text("Anisotropy"));
+                if filter.anisotropy > data.max_anisotropy {
+                    ui.small(format!("The GPU samples at most {}x", data.max_anisotropy));
+                }
+            } else {
+                ui.small("No anisotropic filtering on this GPU");
+            }
+            if filter != data.texture_filter {
+                changes.texture_filter = Some(filter);
+            }
+
+            ui.add_space(10.0);
+            ui.heading("Memory");
+            ui.separator();
+            let mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+            ui.label(format!(
+                "GPU: {:.1} MB allocated of {:.1} MB reserved",
+                mb(data.gpu_memory_allocated),
+                mb(data.gpu_memory_reserved)
+            ));
+            let mut defrag_enabled = data.defrag_enabled;
+            if ui.checkbox(&mut defrag_enabled, "Defragment when idle").changed() {
+                changes.defrag_enabled = Some(defrag_enabled);
+            }
+            ui.small(format!(
+                "{} blocks; moved {} buffers ({:.1} MB), freed {} blocks, {} old copies pending",
+                data.gpu_memory_blocks,
+                data.defrag.moved_buffers,
+                mb(data.defrag.moved_bytes),
+                data.defrag.blocks_freed,
+                data.defrag.retired
+            ));
+            let mut streaming_enabled = data.streaming_enabled;
+            if ui.checkbox(&mut streaming_enabled, "Texture streaming").changed() {
+                changes.streaming_enabled = Some(streaming_enabled);
+            }
+            let mut budget_mb = data.streaming_budget_mb;
+            if ui
+                .add_enabled(streaming_enabled, egui::Slider::new(&mut budget_mb, 1..=1024).text("Budget (MB)"))
+                .changed()
+            {
+                changes.streaming_budget_mb = Some(budget_mb);
+            }
+            if let Some(stats) = &data.texture_streaming {
+                ui.label(format!(
+                    "Textures: {:.1} MB resident of {:.1} MB",
+                    mb(stats.resident_bytes),
+                    mb(stats.full_bytes)
+                ));
+                for texture in &stats.textures {
+                    ui.small(format!(
+                        "{0}² texture: {1}² resident, {2}² target",
+                        texture.size, texture.resident, texture.target
+                    ));
+                }
+            }
+            if let Some(summary) = &data.virtual_texture {
+                ui.label(summary);
+            }
+            if let Some(summary) = &data.texture_arrays {
+                ui.small(summary);
+            }
+
             ui.add_space(10.0);
             ui.heading("Vulkan Info");
             ui.separator();
             ui.label(format!("GPU: {}", data.gpu_name));
             ui.label(format!("Vulkan: {}", data.vulkan_version));
+            ui.label(format!(
+                "Buffering: {} swapchain images, {} frames in flight",
+                data.swapchain_images, data.frames_in_flight
+            ));
+            ui.horizontal(|ui| {
+                ui.label("Present:");
+                let mut present_mode = data.present_mode;
+                for config in PresentModeConfig::ALL {
+                    ui.selectable_value(&mut present_mode, config, config.label());
+                }
+                if present_mode != data.present_mode {
+                    changes.present_mode = Some(present_mode);
+                }
+            });
+            ui.small(format!("Presenting with {}", data.present_mode_negotiated));
+            if ui.button("🪟 Open view window").clicked() {
+                changes.open_window = true;
+            }
+            ui.small("F2 also opens a window with another camera");
+            ui.horizontal_wrapped(|ui| {
+                ui.label("View window layers:");
+                changes.view_layers = layer_toggles(ui, data.view_layers);
+            });
+            if ui.button("📋 Copy diagnostics").clicked() {
+                changes.save_diagnostics = true;
+            }
+            ui.small("Saves device, memory, settings and recent log lines to diagnostics/ for bug reports");
             
             ui.add_space(10.0);
             ui.label("🦀 Rust + Bevy ECS + ash (Vulkan)");
             ui.small("Press F3 to toggle UI");
         });
 
+    if let Some(material) = data.material.filter(|_| data.material_editor_open) {
+        material_editor_window(ctx, data, material, &mut changes);
+    }
+
     changes
 }
+
+/// A row with an eye toggle for `node`, and below it its children when expanded
+fn scene_tree_node(ui: &mut egui::Ui, node: &SceneTreeNode, changes: &mut UiChanges) {
+    let row = |ui: &mut egui::Ui, changes: &mut UiChanges| {
+        let mut visible = node.visible;
+        if ui.toggle_value(&mut visible, "👁").changed() {
+            changes.visibility = Some((node.entity, visible));
+        }
+        let label = if node.shown { ui.label(&node.label) } else { ui.weak(&node.label) };
+        label.context_menu(|ui| {
+            if let Some(layers) = layer_toggles(ui, node.layers) {
+                changes.entity_layers = Some((node.entity, layers));
+            }
+        });
+    };
+    if node.children.is_empty() {
+        ui.horizontal(|ui| row(ui, changes));
+        return;
+    }
+    let id = ui.make_persistent_id(node.entity);
+    egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, false)
+        .show_header(ui, |ui| row(ui, changes))
+        .body(|ui| {
+            for child in &node.children {
+                scene_tree_node(ui, child, changes);
+            }
+        });
+}
+
+/// A toggle per render layer, returning the new layers once one is clicked
+fn layer_toggles(ui: &mut egui::Ui, layers: RenderLayers) -> Option<RenderLayers> {
+    let mut changed = None;
+    for layer in 0..RenderLayers::COUNT {
+        let mut on = layers.contains(layer);
+        if ui.toggle_value(&mut on, RenderLayers::name(layer)).changed() {
+            let bit = RenderLayers::layer(layer);
+            changed = Some(if on { layers.with(bit) } else { layers.without(bit) });
+        }
+    }
+    changed
+}
+
+/// Window editing the selected mesh's material, with a preview sphere
+fn material_editor_window(ctx: &egui::Context, data: &UiData, mut material: MaterialOverride, changes: &mut UiChanges) {
+    let mut open = true;
+    egui::Window::new("🎨 Material Editor")
+        .open(&mut open)
+        .default_pos([330.0, 10.0])
+        .resizable(false)
+        .show(ctx, |ui| {
+            let size = egui::Vec2::splat(material_preview::PREVIEW_SIZE as f32);
+            match data.material_preview {
+                Some(texture_id) => {
+                    ui.image(egui::load::SizedTexture::new(texture_id, size));
+                }
+                None => {
+                    ui.allocate_ui(size, |ui| ui.centered_and_justified(|ui| ui.spinner()));
+                }
+            }
+
+            let mut selected = data.material_selected_mesh;
+            egui::ComboBox::from_label("Primitive")
+                .selected_text(format!("Mesh {}", selected))
+                .show_ui(ui, |ui| {
+                    for i in 0..data.material_mesh_count {
+                        ui.selectable_value(&mut selected, i, format!("Mesh {}", i));
+                    }
+                });
+            if selected != data.material_selected_mesh {
+                changes.material_selected_mesh = Some(selected);
+            }
+
+            let mut edited = false;
+            ui.horizontal(|ui| {
+                ui.label("Base color");
+                edited |= ui.color_edit_button_rgba_unmultiplied(&mut material.base_color).changed();
+            });
+            edited |= ui.add(egui::Slider::new(&mut material.metallic, 0.0..=1.0).text("Metallic")).changed();
+            edited |= ui.add(egui::Slider::new(&mut material.roughness, 0.0..=1.0).text("Roughness")).changed();
+            ui.horizontal(|ui| {
+                ui.label("Emissive");
+                edited |= ui.color_edit_button_rgb(&mut material.emissive).changed();
+            });
+            edited |= ui
+                .add(egui::Slider::new(&mut material.emissive_strength, 0.0..=100.0).logarithmic(true).text("Emissive strength"))
+                .changed();
+            edited |= ui.checkbox(&mut material.use_texture, "Base color texture").changed();
+            edited |= ui.checkbox(&mut material.toon, "Toon (cel shaded, outlined)").changed();
+            if edited {
+                changes.material_override = Some(material);
+            }
+
+            ui.horizontal(|ui| {
+                if ui.add_enabled(data.material_overridden, egui::Button::new("Reset")).clicked() {
+                    changes.reset_material = true;
+                }
+                let can_save = data.material_overridden && data.material_save_target.is_some();
+                if ui.add_enabled(can_save, egui::Button::new("💾 Save to scene")).clicked() {
+                    changes.save_material = true;
+                }
+                if data.material_overridden {
+                    ui.small("Overridden");
+                }
+            });
+            match &data.material_save_target {
+                Some(target) => ui.small(format!("Saves to {}", target)),
+                None => ui.small("Only materials of a loaded .gltf file can be saved"),
+            };
+        });
+    if !open {
+        changes.material_editor_open = Some(false);
+    }
+}
+
+/// A per-frame counter: its last value and peak, above a line graph of `history`
+/// `count` with thousands separated, e.g. 1 234 567
+fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(' ');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+fn stat_graph(ui: &mut egui::Ui, label: &str, history: &[DrawStats], color: egui::Color32, value: impl Fn(&DrawStats) -> f64) {
+    let values: Vec<f64> = history.iter().map(value).collect();
+    let latest = values.last().copied().unwrap_or(0.0);
+    let peak = values.iter().copied().fold(0.0, f64::max);
+    ui.horizontal(|ui| {
+        ui.label(format!("{}:", label));
+        ui.colored_label(color, format!("{}", latest));
+        ui.small(format!("(peak {})", peak));
+    });
+
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 32.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, egui::Color32::from_black_alpha(60));
+    if values.len() < 2 {
+        return;
+    }
+    let scale = if peak > 0.0 { 1.0 / peak } else { 0.0 };
+    let step = rect.width() / (crate::draw_stats::HISTORY_LEN - 1) as f32;
+    // Newest frame at the right edge
+    let start = rect.right() - step * (values.len() - 1) as f32;
+    let points = values
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| egui::pos2(start + step * i as f32, rect.bottom() - 1.0 - (v * scale) as f32 * (rect.height() - 2.0)))
+        .collect();
+    painter.add(egui::Shape::line(points, egui::Stroke::new(1.0, color)));
+}
@@ -1,11 +1,25 @@
 //! Vulkan rendering backend for egui
 //! 
-//! Renders egui primitives directly using ash/Vulkan.
+//! Renders egui primitives directly using ash/Vulkan. Geometry is uploaded to the frame
+//! arena every frame, so each frame in flight reads its own copy.
+//!
+//! egui's colors and textures are sRGB-encoded (gamma) and its reference rendering blends
+//! them as they are. Into a framebuffer that encodes to sRGB on write, egui.frag decodes
+//! them to linear first, or the hardware would encode them a second time and wash the UI
+//! out; blending then happens on linear values, which is as close as such a target gets.
 
 use ash::vk;
-use std::ffi::CStr;
 use std::mem::size_of;
 
+use crate::command_encoder::{PipelineBinding, RenderPassEncoder};
+use crate::frame_arena::FrameArena;
+use crate::pipeline_builder::{BlendMode, GraphicsPipelineBuilder, VertexLayout};
+use crate::renderer::VulkanRenderer;
+use crate::sampler_cache::SamplerDesc;
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+use crate::swapchain;
+
 /// Vertex for egui rendering (matches egui::epaint::Vertex)
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -15,216 +29,115 @@ pub struct EguiVertex {
     pub color: [u8; 4],
 }
 
+impl VertexLayout for EguiVertex {
+    const ATTRIBUTES: &'static [(vk::Format, u32)] = &[
+        (vk::Format::R32G32_SFLOAT, std::mem::offset_of!(EguiVertex, pos) as u32),
+        (vk::Format::R32G32_SFLOAT, std::mem::offset_of!(EguiVertex, uv) as u32),
+        (vk::Format::R8G8B8A8_UNORM, std::mem::offset_of!(EguiVertex, color) as u32),
+    ];
+}
+
+/// Images registered with `register_user_texture`, besides the font atlas
+const MAX_USER_TEXTURES: u32 = 8;
+
 /// Push constants for egui rendering
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct EguiPushConstants {
     pub screen_size: [f32; 2],
+    pub srgb_framebuffer: u32, // 1 to decode colors to linear for an sRGB target
 }
 
 /// Vulkan egui renderer
 pub struct EguiVulkanRenderer {
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
+    render_pass: vk::RenderPass,
     descriptor_set_layout: vk::DescriptorSetLayout,
     descriptor_pool: vk::DescriptorPool,
     descriptor_set: vk::DescriptorSet,
+    user_textures: Vec<vk::DescriptorSet>, // egui::TextureId::User(i) samples user_textures[i]
     
     // Font texture
     font_image: vk::Image,
     font_image_memory: vk::DeviceMemory,
     font_image_view: vk::ImageView,
+    font_size: [u32; 2],
     font_sampler: vk::Sampler,
     
-    // Vertex/index buffers
-    vertex_buffer: vk::Buffer,
-    vertex_buffer_memory: vk::DeviceMemory,
-    vertex_buffer_size: usize,
-    index_buffer: vk::Buffer,
-    index_buffer_memory: vk::DeviceMemory,
-    index_buffer_size: usize,
-
-    // Scratch buffers to avoid per-frame allocations
+    srgb_framebuffer: bool, // The color attachment encodes to sRGB, see the module docs
+    
+    // Scratch buffers to avoid per-frame allocations; geometry is uploaded to the frame arena
     scratch_vertices: Vec<EguiVertex>,
     scratch_indices: Vec<u32>,
-    scratch_mesh_infos: Vec<(usize, usize, egui::Rect)>,
-
-    // Persistent mapped pointers (avoid map/unmap overhead)
-    vertex_mapped_ptr: *mut EguiVertex,
-    index_mapped_ptr: *mut u32,
+    scratch_mesh_infos: Vec<(usize, usize, egui::Rect, egui::TextureId)>,
 }
 
 impl EguiVulkanRenderer {
     pub fn new(
-        device: &ash::Device,
-        physical_device: vk::PhysicalDevice,
-        instance: &ash::Instance,
+        renderer: &VulkanRenderer,
         render_pass: vk::RenderPass,
+        color_format: vk::Format, // Of `render_pass`'s color attachment
         ctx: &egui::Context,
-        graphics_queue: vk::Queue,
-        graphics_queue_family_index: u32,
     ) -> Self {
         unsafe {
-            let memory_properties = instance.get_physical_device_memory_properties(physical_device);
+            let device = &renderer.device;
+            let memory_properties = renderer.instance.get_physical_device_memory_properties(renderer.physical_device);
             
-            let pool_info = vk::CommandPoolCreateInfo::default()
-                .queue_family_index(graphics_queue_family_index)
-                .flags(vk::CommandPoolCreateFlags::TRANSIENT);
-            let setup_command_pool = device.create_command_pool(&pool_info, None).unwrap();
+            // Load compiled SPIR-V shaders
+            let vert_code = load_shader("egui.vert", include_bytes!("../shaders/egui.vert.spv"));
+            let frag_code = load_shader("egui.frag", include_bytes!("../shaders/egui.frag.spv"));
+            let vert_reflection = ShaderReflection::reflect(&vert_code).expect("Failed to reflect egui.vert");
+            let frag_reflection = ShaderReflection::reflect(&frag_code).expect("Failed to reflect egui.frag");
+            let stages = [&vert_reflection, &frag_reflection];
             
             // Descriptor set layout
-            let sampler_binding = vk::DescriptorSetLayoutBinding::default()
-                .binding(0)
-                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .descriptor_count(1)
-                .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-            
-            let bindings = [sampler_binding];
+            let bindings = shader_reflection::set_layout_bindings(&stages, 0).unwrap();
             let layout_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
             let descriptor_set_layout = device.create_descriptor_set_layout(&layout_info, None).unwrap();
             
             // Pipeline layout
-            let push_constant_range = vk::PushConstantRange::default()
-                .stage_flags(vk::ShaderStageFlags::VERTEX)
-                .offset(0)
-                .size(size_of::<EguiPushConstants>() as u32);
-            
-            let push_constant_ranges = [push_constant_range];
+            let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+                &stages,
+                size_of::<EguiPushConstants>() as u32,
+            )
+            .unwrap()
+            .into_iter()
+            .collect();
             let set_layouts = [descriptor_set_layout];
             let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
                 .set_layouts(&set_layouts)
                 .push_constant_ranges(&push_constant_ranges);
             let pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None).unwrap();
             
-            // Load compiled SPIR-V shaders
-            let vert_code = load_spirv_file(include_bytes!("../shaders/egui.vert.spv"));
-            let frag_code = load_spirv_file(include_bytes!("../shaders/egui.frag.spv"));
-            
-            let vert_module_info = vk::ShaderModuleCreateInfo::default().code(&vert_code);
-            let frag_module_info = vk::ShaderModuleCreateInfo::default().code(&frag_code);
-            let vert_shader = device.create_shader_module(&vert_module_info, None).unwrap();
-            let frag_shader = device.create_shader_module(&frag_module_info, None).unwrap();
-            
-            let shader_entry_name = CStr::from_bytes_with_nul_unchecked(b"main\0");
-            let vert_stage = vk::PipelineShaderStageCreateInfo::default()
-                .stage(vk::ShaderStageFlags::VERTEX)
-                .module(vert_shader)
-                .name(shader_entry_name);
-            let frag_stage = vk::PipelineShaderStageCreateInfo::default()
-                .stage(vk::ShaderStageFlags::FRAGMENT)
-                .module(frag_shader)
-                .name(shader_entry_name);
-            let shader_stages = [vert_stage, frag_stage];
-            
-            // Vertex input
-            let binding_desc = vk::VertexInputBindingDescription::default()
-                .binding(0)
-                .stride(size_of::<EguiVertex>() as u32)
-                .input_rate(vk::VertexInputRate::VERTEX);
-            
-            let position_attr = vk::VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(0)
-                .format(vk::Format::R32G32_SFLOAT)
-                .offset(0);
-            let uv_attr = vk::VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(1)
-                .format(vk::Format::R32G32_SFLOAT)
-                .offset(8);
-            let color_attr = vk::VertexInputAttributeDescription::default()
-                .binding(0)
-                .location(2)
-                .format(vk::Format::R8G8B8A8_UNORM)
-                .offset(16);
-            
-            let binding_descs = [binding_desc];
-            let attr_descs = [position_attr, uv_attr, color_attr];
-            let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::default()
-                .vertex_binding_descriptions(&binding_descs)
-                .vertex_attribute_descriptions(&attr_descs);
-            
-            let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
-                .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
-            
-            let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-            let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
-            
-            let viewport_state = vk::PipelineViewportStateCreateInfo::default()
-                .viewport_count(1)
-                .scissor_count(1);
-            
-            let rasterization = vk::PipelineRasterizationStateCreateInfo::default()
-                .polygon_mode(vk::PolygonMode::FILL)
-                .line_width(1.0)
-                .cull_mode(vk::CullModeFlags::NONE)
-                .front_face(vk::FrontFace::COUNTER_CLOCKWISE);
-            
-            let multisampling = vk::PipelineMultisampleStateCreateInfo::default()
-                .rasterization_samples(vk::SampleCountFlags::TYPE_1);
-            
-            // Alpha blending
-            let color_blend_attachment = vk::PipelineColorBlendAttachmentState::default()
-                .blend_enable(true)
-                .src_color_blend_factor(vk::BlendFactor::ONE)
-                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                .color_blend_op(vk::BlendOp::ADD)
-                .src_alpha_blend_factor(vk::BlendFactor::ONE)
-                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                .alpha_blend_op(vk::BlendOp::ADD)
-                .color_write_mask(vk::ColorComponentFlags::RGBA);
-            
-            let attachments = [color_blend_attachment];
-            let color_blending = vk::PipelineColorBlendStateCreateInfo::default().attachments(&attachments);
-            
-            let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
-                .depth_test_enable(false)
-                .depth_write_enable(false);
-            
-            let pipeline_info = vk::GraphicsPipelineCreateInfo::default()
-                .stages(&shader_stages)
-                .vertex_input_state(&vertex_input_info)
-                .input_assembly_state(&input_assembly)
-                .viewport_state(&viewport_state)
-                .rasterization_state(&rasterization)
-                .multisample_state(&multisampling)
-                .color_blend_state(&color_blending)
-                .depth_stencil_state(&depth_stencil)
-                .dynamic_state(&dynamic_state_info)
-                .layout(pipeline_layout)
-                .render_pass(render_pass)
-                .subpass(0);
-            
-            let pipeline = device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None).unwrap()[0];
-            
-            device.destroy_shader_module(vert_shader, None);
-            device.destroy_shader_module(frag_shader, None);
+            let pipeline = create_pipeline(device, pipeline_layout, render_pass, &vert_code, &frag_code).unwrap();
             
             // Create font texture
-            let (font_image_vk, font_image_memory, font_image_view, font_sampler) = {
-                let font_image = ctx.fonts(|fonts| {
-                    let image = fonts.image();
-                    // egui font texture is single-channel coverage map
-                    // Convert to RGBA: white RGB with coverage as alpha
-                    let pixels: Vec<u8> = image.pixels.iter().flat_map(|&coverage| {
-                        let alpha = (coverage * 255.0) as u8;
-                        [255u8, 255u8, 255u8, alpha]
-                    }).collect();
-                    (image.width() as u32, image.height() as u32, pixels)
-                });
-                
-                create_font_texture(device, &memory_properties, font_image.0, font_image.1, &font_image.2, 
-                                   setup_command_pool, graphics_queue)
-            };
-            
-            device.destroy_command_pool(setup_command_pool, None);
+            let (font_width, font_height, font_pixels) = ctx.fonts(|fonts| {
+                let image = fonts.image();
+                (image.width() as u32, image.height() as u32, font_rgba(&image))
+            });
+            let (font_image_vk, font_image_memory, font_image_view) =
+                create_font_texture(device, &memory_properties, font_width, font_height);
+            upload_font_region(
+                renderer, &memory_properties, font_image_vk, [0, 0], [font_width, font_height], &font_pixels,
+                vk::ImageLayout::UNDEFINED,
+            );
+            let font_sampler = renderer
+                .samplers
+                .get(
+                    device,
+                    SamplerDesc::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                        .mipmap_mode(vk::SamplerMipmapMode::LINEAR),
+                )
+                .unwrap();
             
             // Descriptor pool and set
             let pool_sizes = [vk::DescriptorPoolSize::default()
                 .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .descriptor_count(1)];
+                .descriptor_count(1 + MAX_USER_TEXTURES)];
             let pool_info = vk::DescriptorPoolCreateInfo::default()
-                .max_sets(1)
+                .max_sets(1 + MAX_USER_TEXTURES)
                 .pool_sizes(&pool_sizes);
             let descriptor_pool = device.create_descriptor_pool(&pool_info, None).unwrap();
             
@@ -245,80 +158,142 @@ impl EguiVulkanRenderer {
                 .image_info(&image_infos);
             device.update_descriptor_sets(&[write_set], &[]);
             
-            // Buffers
-            let (vertex_buffer, vertex_buffer_memory) = create_buffer(
-                device, &memory_properties, 1024 * 1024,
-                vk::BufferUsageFlags::VERTEX_BUFFER,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            );
-            
-            let (index_buffer, index_buffer_memory) = create_buffer(
-                device, &memory_properties, 512 * 1024,
-                vk::BufferUsageFlags::INDEX_BUFFER,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            );
-            
-            // Persistently map the buffers (HOST_COHERENT so no flush needed)
-            let vertex_mapped_ptr = device.map_memory(
-                vertex_buffer_memory, 0, 1024 * 1024, vk::MemoryMapFlags::empty()
-            ).unwrap() as *mut EguiVertex;
-            let index_mapped_ptr = device.map_memory(
-                index_buffer_memory, 0, 512 * 1024, vk::MemoryMapFlags::empty()
-            ).unwrap() as *mut u32;
-
             Self {
                 pipeline_layout,
                 pipeline,
+                render_pass,
                 descriptor_set_layout,
                 descriptor_pool,
                 descriptor_set,
+                user_textures: Vec::new(),
                 font_image: font_image_vk,
                 font_image_memory,
                 font_image_view,
+                font_size: [font_width, font_height],
                 font_sampler,
-                vertex_buffer,
-                vertex_buffer_memory,
-                vertex_buffer_size: 1024 * 1024,
-                index_buffer,
-                index_buffer_memory,
-                index_buffer_size: 512 * 1024,
-
+                srgb_framebuffer: swapchain::is_srgb(color_format),
                 scratch_vertices: Vec::with_capacity(8 * 1024),
                 scratch_indices: Vec::with_capacity(16 * 1024),
                 scratch_mesh_infos: Vec::with_capacity(256),
-
-                vertex_mapped_ptr,
-                index_mapped_ptr,
             }
         }
     }
     
-    pub fn update_textures(
+    /// Apply font atlas changes. egui re-rasterizes the atlas when the scale factor changes
+    /// (full update) and adds glyphs as new text shows up (partial updates); other textures
+    /// aren't supported. The device must be idle if `textures_delta` sets anything.
+    pub fn update_textures(&mut self, renderer: &VulkanRenderer, textures_delta: &egui::TexturesDelta) {
+        for (id, delta) in &textures_delta.set {
+            let egui::ImageData::Font(image) = &delta.image else {
+                continue;
+            };
+            if *id != egui::TextureId::default() {
+                continue;
+            }
+            let size = [image.width() as u32, image.height() as u32];
+            let pixels = font_rgba(image);
+            
+            unsafe {
+                let memory_properties =
+                    renderer.instance.get_physical_device_memory_properties(renderer.physical_device);
+                let (offset, old_layout) = match delta.pos {
+                    Some([x, y]) => ([x as u32, y as u32], vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                    None => {
+                        if size != self.font_size {
+                            self.recreate_font_texture(&renderer.device, &memory_properties, size);
+                        }
+                        ([0, 0], vk::ImageLayout::UNDEFINED)
+                    }
+                };
+                upload_font_region(renderer, &memory_properties, self.font_image, offset, size, &pixels, old_layout);
+            }
+        }
+    }
+    
+    /// Let egui images show `image_view`, which must be in SHADER_READ_ONLY_OPTIMAL layout
+    /// whenever the UI is drawn. Registered textures live as long as the renderer.
+    pub unsafe fn register_user_texture(
         &mut self,
-        _device: &ash::Device,
-        _instance: &ash::Instance,
-        _physical_device: vk::PhysicalDevice,
-        _graphics_queue: vk::Queue,
-        _graphics_queue_family_index: u32,
-        _textures_delta: &egui::TexturesDelta,
+        device: &ash::Device,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+    ) -> Result<egui::TextureId, Box<dyn std::error::Error>> {
+        if self.user_textures.len() >= MAX_USER_TEXTURES as usize {
+            return Err(format!("at most {} egui user textures", MAX_USER_TEXTURES).into());
+        }
+        let set_layouts = [self.descriptor_set_layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+        let descriptor_set = device.allocate_descriptor_sets(&alloc_info)?[0];
+        
+        let image_infos = [vk::DescriptorImageInfo::default()
+            .sampler(sampler)
+            .image_view(image_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let write_set = vk::WriteDescriptorSet::default()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos);
+        device.update_descriptor_sets(&[write_set], &[]);
+        
+        self.user_textures.push(descriptor_set);
+        Ok(egui::TextureId::User(self.user_textures.len() as u64 - 1))
+    }
+    
+    /// Rebuild the UI pipeline from the current egui shaders, keeping the old one if they
+    /// fail to build. Call with the device idle.
+    pub unsafe fn reload_pipeline(&mut self, device: &ash::Device) -> Result<(), Box<dyn std::error::Error>> {
+        let vert_code = load_shader("egui.vert", include_bytes!("../shaders/egui.vert.spv"));
+        let frag_code = load_shader("egui.frag", include_bytes!("../shaders/egui.frag.spv"));
+        let pipeline = create_pipeline(device, self.pipeline_layout, self.render_pass, &vert_code, &frag_code)?;
+        device.destroy_pipeline(std::mem::replace(&mut self.pipeline, pipeline), None);
+        Ok(())
+    }
+    
+    /// Replace the font image with an empty one of `size` and point the descriptor set at it
+    unsafe fn recreate_font_texture(
+        &mut self,
+        device: &ash::Device,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        size: [u32; 2],
     ) {
-        // For now, we only handle the font texture which is set during initialization
-        // In a more complete implementation, this would handle dynamic texture updates
-        // from textures_delta.set and textures_delta.free
+        device.destroy_image_view(self.font_image_view, None);
+        device.destroy_image(self.font_image, None);
+        device.free_memory(self.font_image_memory, None);
+        
+        (self.font_image, self.font_image_memory, self.font_image_view) =
+            create_font_texture(device, memory_properties, size[0], size[1]);
+        self.font_size = size;
+        
+        let image_info = vk::DescriptorImageInfo::default()
+            .sampler(self.font_sampler)
+            .image_view(self.font_image_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let image_infos = [image_info];
+        let write_set = vk::WriteDescriptorSet::default()
+            .dst_set(self.descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos);
+        device.update_descriptor_sets(&[write_set], &[]);
     }
     
     pub fn render(
         &mut self,
-        device: &ash::Device,
-        command_buffer: vk::CommandBuffer,
-        screen_width: u32,
-        screen_height: u32,
+        pass: &mut RenderPassEncoder,
+        arena: &FrameArena,
+        frame_index: usize,
+        extent: vk::Extent2D,
         clipped_meshes: Vec<egui::ClippedPrimitive>,
         pixels_per_point: f32,
     ) {
         if clipped_meshes.is_empty() {
             return;
         }
+        // Uploading into another frame's memory would race with the GPU still reading it
+        assert_eq!(arena.frame_index(), frame_index, "frame arena not begun for this frame");
         
         unsafe {
             self.scratch_vertices.clear();
@@ -339,11 +314,12 @@ impl EguiVulkanRenderer {
                     }
                     
                     for idx in &mesh.indices {
+                        debug_assert!((*idx as usize) < mesh.vertices.len(), "egui index past its mesh");
                         self.scratch_indices.push(*idx + vertex_offset as u32);
                     }
                     
                     self.scratch_mesh_infos
-                        .push((index_offset, mesh.indices.len(), clipped.clip_rect));
+                        .push((index_offset, mesh.indices.len(), clipped.clip_rect, mesh.texture_id));
                 }
             }
             
@@ -351,65 +327,88 @@ impl EguiVulkanRenderer {
                 return;
             }
             
-            // Upload data via persistent mapped pointers (no map/unmap overhead)
-            std::ptr::copy_nonoverlapping(
-                self.scratch_vertices.as_ptr(),
-                self.vertex_mapped_ptr,
-                self.scratch_vertices.len(),
-            );
-            std::ptr::copy_nonoverlapping(
-                self.scratch_indices.as_ptr(),
-                self.index_mapped_ptr,
-                self.scratch_indices.len(),
-            );
+            // Upload into this frame's arena, which grows to fit; the UI is only skipped if
+            // that allocation fails
+            let (Some(vertices), Some(indices)) = (
+                arena.push(&self.scratch_vertices),
+                arena.push(&self.scratch_indices),
+            ) else {
+                return;
+            };
             
             // Render
-            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
-            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline_layout, 0, &[self.descriptor_set], &[]);
+            pass.bind_pipeline(PipelineBinding {
+                pipeline: self.pipeline,
+                layout: self.pipeline_layout,
+                render_pass: self.render_pass,
+                push_constant_size: size_of::<EguiPushConstants>() as u32,
+            });
+            pass.bind_descriptor_set(0, self.descriptor_set);
+            let mut bound_texture = egui::TextureId::default();
             
+            // Vertices are in points; the viewport covers the physical swapchain
             let push_constants = EguiPushConstants {
-                screen_size: [screen_width as f32, screen_height as f32],
+                screen_size: [
+                    extent.width as f32 / pixels_per_point,
+                    extent.height as f32 / pixels_per_point,
+                ],
+                srgb_framebuffer: u32::from(self.srgb_framebuffer),
             };
-            let push_data = std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<EguiPushConstants>());
-            device.cmd_push_constants(command_buffer, self.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, push_data);
+            pass.push_constants(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT, 0, &push_constants);
             
             let viewport = vk::Viewport::default()
-                .width(screen_width as f32)
-                .height(screen_height as f32)
+                .width(extent.width as f32)
+                .height(extent.height as f32)
                 .min_depth(0.0)
                 .max_depth(1.0);
-            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
-            
-            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer], &[0]);
-            device.cmd_bind_index_buffer(command_buffer, self.index_buffer, 0, vk::IndexType::UINT32);
-            
-            for (index_offset, index_count, clip_rect) in self.scratch_mesh_infos.drain(..) {
-                let min_x = (clip_rect.min.x * pixels_per_point).max(0.0) as i32;
-                let min_y = (clip_rect.min.y * pixels_per_point).max(0.0) as i32;
-                let max_x = (clip_rect.max.x * pixels_per_point).min(screen_width as f32) as u32;
-                let max_y = (clip_rect.max.y * pixels_per_point).min(screen_height as f32) as u32;
+            pass.set_viewport(viewport);
+            
+            pass.bind_vertex_buffers(&[vertices.buffer], &[vertices.offset]);
+            pass.bind_index_buffer(indices.buffer, indices.offset, vk::IndexType::UINT32);
+            
+            let uploaded_indices = indices.size / size_of::<u32>() as u64;
+            for (index_offset, index_count, clip_rect, texture_id) in self.scratch_mesh_infos.drain(..) {
+                assert!(
+                    (index_offset + index_count) as u64 <= uploaded_indices,
+                    "egui draw reads indices {}..{} of {}",
+                    index_offset,
+                    index_offset + index_count,
+                    uploaded_indices,
+                );
                 
-                if max_x <= min_x as u32 || max_y <= min_y as u32 {
+                // Round to whole pixels like egui does, so fractional scales don't clip a
+                // pixel row off the edge of a panel
+                let min_x = ((clip_rect.min.x * pixels_per_point).round() as u32).min(extent.width);
+                let min_y = ((clip_rect.min.y * pixels_per_point).round() as u32).min(extent.height);
+                let max_x = ((clip_rect.max.x * pixels_per_point).round() as u32).clamp(min_x, extent.width);
+                let max_y = ((clip_rect.max.y * pixels_per_point).round() as u32).clamp(min_y, extent.height);
+                
+                if max_x == min_x || max_y == min_y {
                     continue;
                 }
                 
                 let scissor = vk::Rect2D {
-                    offset: vk::Offset2D { x: min_x, y: min_y },
-                    extent: vk::Extent2D { width: max_x - min_x as u32, height: max_y - min_y as u32 },
+                    offset: vk::Offset2D { x: min_x as i32, y: min_y as i32 },
+                    extent: vk::Extent2D { width: max_x - min_x, height: max_y - min_y },
                 };
-                device.cmd_set_scissor(command_buffer, 0, &[scissor]);
-                device.cmd_draw_indexed(command_buffer, index_count as u32, 1, index_offset as u32, 0, 0);
+                pass.set_scissor(scissor);
+                if texture_id != bound_texture {
+                    let descriptor_set = match texture_id {
+                        egui::TextureId::User(i) => self.user_textures.get(i as usize).copied(),
+                        egui::TextureId::Managed(_) => Some(self.descriptor_set),
+                    };
+                    let Some(descriptor_set) = descriptor_set else {
+                        continue;
+                    };
+                    pass.bind_descriptor_set(0, descriptor_set);
+                    bound_texture = texture_id;
+                }
+                pass.draw_indexed(index_count as u32, 1, index_offset as u32);
             }
         }
     }
     
     pub unsafe fn cleanup(&self, device: &ash::Device) {
-        device.destroy_buffer(self.index_buffer, None);
-        device.free_memory(self.index_buffer_memory, None);
-        device.destroy_buffer(self.vertex_buffer, None);
-        device.free_memory(self.vertex_buffer_memory, None);
-        device.destroy_sampler(self.font_sampler, None);
         device.destroy_image_view(self.font_image_view, None);
         device.destroy_image(self.font_image, None);
         device.free_memory(self.font_image_memory, None);
@@ -421,38 +420,22 @@ impl EguiVulkanRenderer {
 }
 
 // Helper functions
+/// egui's font atlas is a coverage map; expand it to white RGBA with coverage as alpha
+fn font_rgba(image: &egui::FontImage) -> Vec<u8> {
+    image.pixels.iter().flat_map(|&coverage| {
+        let alpha = (coverage * 255.0) as u8;
+        [255u8, 255u8, 255u8, alpha]
+    }).collect()
+}
+
+/// An empty sampled font image and its view; fill it with `upload_font_region`
 fn create_font_texture(
     device: &ash::Device,
     memory_properties: &vk::PhysicalDeviceMemoryProperties,
     width: u32,
     height: u32,
-    pixels: &[u8],
-    command_pool: vk::CommandPool,
-    queue: vk::Queue,
-) -> (vk::Image, vk::DeviceMemory, vk::ImageView, vk::Sampler) {
+) -> (vk::Image, vk::DeviceMemory, vk::ImageView) {
     unsafe {
-        let image_size = (width * height * 4) as u64;
-        
-        // Create staging buffer
-        let staging_buffer_info = vk::BufferCreateInfo::default()
-            .size(image_size)
-            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
-        let staging_buffer = device.create_buffer(&staging_buffer_info, None).unwrap();
-        let staging_mem_requirements = device.get_buffer_memory_requirements(staging_buffer);
-        
-        let staging_alloc_info = vk::MemoryAllocateInfo::default()
-            .allocation_size(staging_mem_requirements.size)
-            .memory_type_index(find_memory_type(memory_properties, staging_mem_requirements.memory_type_bits,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT));
-        let staging_memory = device.allocate_memory(&staging_alloc_info, None).unwrap();
-        device.bind_buffer_memory(staging_buffer, staging_memory, 0).unwrap();
-        
-        // Upload pixels to staging buffer
-        let ptr = device.map_memory(staging_memory, 0, image_size, vk::MemoryMapFlags::empty()).unwrap() as *mut u8;
-        std::ptr::copy_nonoverlapping(pixels.as_ptr(), ptr, pixels.len());
-        device.unmap_memory(staging_memory);
-        
         // Create image with OPTIMAL tiling (proper GPU layout)
         let image_info = vk::ImageCreateInfo::default()
             .image_type(vk::ImageType::TYPE_2D)
@@ -477,9 +460,62 @@ fn create_font_texture(
         let memory = device.allocate_memory(&alloc_info, None).unwrap();
         device.bind_image_memory(image, memory, 0).unwrap();
         
+        let view_info = vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(vk::Format::R8G8B8A8_UNORM)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            });
+        let image_view = device.create_image_view(&view_info, None).unwrap();
+        
+        (image, memory, image_view)
+    }
+}
+
+/// Copy RGBA `pixels` into the `size` region of `image` at `offset` and leave the image
+/// shader-readable. `old_layout` is UNDEFINED when the whole image is replaced.
+fn upload_font_region(
+    renderer: &VulkanRenderer,
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    image: vk::Image,
+    offset: [u32; 2],
+    size: [u32; 2],
+    pixels: &[u8],
+    old_layout: vk::ImageLayout,
+) {
+    unsafe {
+        let device = &renderer.device;
+        let [width, height] = size;
+        let image_size = (width * height * 4) as u64;
+        
+        // Create staging buffer
+        let staging_buffer_info = vk::BufferCreateInfo::default()
+            .size(image_size)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let staging_buffer = device.create_buffer(&staging_buffer_info, None).unwrap();
+        let staging_mem_requirements = device.get_buffer_memory_requirements(staging_buffer);
+        
+        let staging_alloc_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(staging_mem_requirements.size)
+            .memory_type_index(find_memory_type(memory_properties, staging_mem_requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT));
+        let staging_memory = device.allocate_memory(&staging_alloc_info, None).unwrap();
+        device.bind_buffer_memory(staging_buffer, staging_memory, 0).unwrap();
+        
+        // Upload pixels to staging buffer
+        let ptr = device.map_memory(staging_memory, 0, image_size, vk::MemoryMapFlags::empty()).unwrap() as *mut u8;
+        std::ptr::copy_nonoverlapping(pixels.as_ptr(), ptr, pixels.len());
+        device.unmap_memory(staging_memory);
+        
         // Transfer data from staging buffer to image
         let alloc_info = vk::CommandBufferAllocateInfo::default()
-            .command_pool(command_pool)
+            .command_pool(renderer.command_pool)
             .level(vk::CommandBufferLevel::PRIMARY)
             .command_buffer_count(1);
         let command_buffer = device.allocate_command_buffers(&alloc_info).unwrap()[0];
@@ -489,7 +525,7 @@ fn create_font_texture(
         
         // Transition to TRANSFER_DST_OPTIMAL
         let barrier = vk::ImageMemoryBarrier::default()
-            .old_layout(vk::ImageLayout::UNDEFINED)
+            .old_layout(old_layout)
             .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
             .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
             .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
@@ -518,7 +554,7 @@ fn create_font_texture(
                 base_array_layer: 0,
                 layer_count: 1,
             })
-            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_offset(vk::Offset3D { x: offset[0] as i32, y: offset[1] as i32, z: 0 })
             .image_extent(vk::Extent3D { width, height, depth: 1 });
         
         device.cmd_copy_buffer_to_image(command_buffer, staging_buffer, image, 
@@ -546,62 +582,29 @@ fn create_font_texture(
         
         device.end_command_buffer(command_buffer).unwrap();
         let submit_info = vk::SubmitInfo::default().command_buffers(std::slice::from_ref(&command_buffer));
-        device.queue_submit(queue, &[submit_info], vk::Fence::null()).unwrap();
-        device.queue_wait_idle(queue).unwrap();
-        device.free_command_buffers(command_pool, &[command_buffer]);
+        device.queue_submit(renderer.graphics_queue, &[submit_info], vk::Fence::null()).unwrap();
+        device.queue_wait_idle(renderer.graphics_queue).unwrap();
+        device.free_command_buffers(renderer.command_pool, &[command_buffer]);
         
         // Cleanup staging buffer
         device.destroy_buffer(staging_buffer, None);
         device.free_memory(staging_memory, None);
-        
-        let view_info = vk::ImageViewCreateInfo::default()
-            .image(image)
-            .view_type(vk::ImageViewType::TYPE_2D)
-            .format(vk::Format::R8G8B8A8_UNORM)
-            .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            });
-        let image_view = device.create_image_view(&view_info, None).unwrap();
-        
-        let sampler_info = vk::SamplerCreateInfo::default()
-            .mag_filter(vk::Filter::LINEAR)
-            .min_filter(vk::Filter::LINEAR)
-            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE);
-        let sampler = device.create_sampler(&sampler_info, None).unwrap();
-        
-        (image, memory, image_view, sampler)
     }
 }
 
-fn create_buffer(
+unsafe fn create_pipeline(
     device: &ash::Device,
-    memory_properties: &vk::PhysicalDeviceMemoryProperties,
-    size: usize,
-    usage: vk::BufferUsageFlags,
-    properties: vk::MemoryPropertyFlags,
-) -> (vk::Buffer, vk::DeviceMemory) {
-    unsafe {
-        let buffer_info = vk::BufferCreateInfo::default()
-            .size(size as u64)
-            .usage(usage)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
-        let buffer = device.create_buffer(&buffer_info, None).unwrap();
-        let mem_requirements = device.get_buffer_memory_requirements(buffer);
-        
-        let alloc_info = vk::MemoryAllocateInfo::default()
-            .allocation_size(mem_requirements.size)
-            .memory_type_index(find_memory_type(memory_properties, mem_requirements.memory_type_bits, properties));
-        let memory = device.allocate_memory(&alloc_info, None).unwrap();
-        device.bind_buffer_memory(buffer, memory, 0).unwrap();
-        
-        (buffer, memory)
-    }
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    vert_code: &[u32],
+    frag_code: &[u32],
+) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+    GraphicsPipelineBuilder::new(layout, render_pass)
+        .shader(vk::ShaderStageFlags::VERTEX, vert_code)
+        .shader(vk::ShaderStageFlags::FRAGMENT, frag_code)
+        .vertex_buffer::<EguiVertex>()
+        .blend(BlendMode::PremultipliedAlpha)
+        .build(device)
 }
 
 fn find_memory_type(
@@ -618,9 +621,3 @@ fn find_memory_type(
     }
     panic!("Failed to find suitable memory type");
 }
-
-/// Load SPIR-V from bytes (handles alignment)
-fn load_spirv_file(bytes: &[u8]) -> Vec<u32> {
-    let mut cursor = std::io::Cursor::new(bytes);
-    ash::util::read_spv(&mut cursor).expect("Failed to read SPIR-V")
-}
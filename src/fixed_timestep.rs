@@ -0,0 +1,177 @@
+//! Fixed-timestep simulation
+//!
+//! Movement, rotation and keyframe animation run in their own schedule, in steps of
+//! `FixedTime::step` seconds of scene time, so motion is the same whatever the frame rate:
+//! a fast frame may run no step at all and a slow one several. The scene time left over
+//! after the last whole step is carried into the next frame.
+//!
+//! Entities the simulation moves carry a `TransformInterpolation` (the simulated
+//! components require it). Each step records their transforms before and after it, and
+//! every frame `interpolate_transforms` shows them part of the way between the two by
+//! how far the leftover time is into the next step, so motion stays smooth between steps.
+//! The next step starts from the simulated transform again, unless something else moved
+//! the entity since, which then becomes where the simulation continues from.
+
+use bevy_ecs::prelude::*;
+
+use crate::Transform;
+
+/// Steps per second of scene time
+pub const STEP_RATE: f32 = 60.0;
+/// Most steps one frame runs; time past these is dropped rather than caught up with
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct FixedTime {
+    pub step: f32,    // Seconds of scene time per step
+    pub elapsed: f32, // Scene time simulated so far, in whole steps
+    accumulator: f32, // Scene time not simulated yet, less than a step after `accumulate`
+}
+
+impl Default for FixedTime {
+    fn default() -> Self {
+        Self { step: 1.0 / STEP_RATE, elapsed: 0.0, accumulator: 0.0 }
+    }
+}
+
+impl FixedTime {
+    /// Add a frame's `delta` of scene time, returning how many steps to run for it
+    pub fn accumulate(&mut self, delta: f32) -> u32 {
+        self.accumulator += delta;
+        let steps = (self.accumulator / self.step) as u32;
+        self.accumulator -= steps as f32 * self.step;
+        if steps > MAX_STEPS_PER_FRAME {
+            self.accumulator = 0.0;
+        }
+        steps.min(MAX_STEPS_PER_FRAME)
+    }
+
+    /// How far into the next step the scene time is, from 0 to 1
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step).clamp(0.0, 1.0)
+    }
+}
+
+/// A simulated entity's transforms around the last step
+#[derive(Component, Clone, Copy, Default)]
+pub struct TransformInterpolation {
+    previous: Option<Transform>, // Before the last step
+    current: Option<Transform>,  // After it
+    shown: Option<Transform>,    // What was last written for drawing
+}
+
+/// Start a step from the simulated transforms, or from where an entity was moved to
+pub fn begin_step(mut query: Query<(&mut Transform, &mut TransformInterpolation)>) {
+    for (mut transform, mut interpolation) in query.iter_mut() {
+        let start = match (interpolation.current, interpolation.shown) {
+            (Some(current), Some(shown)) if shown == *transform => current,
+            _ => *transform,
+        };
+        *transform = start;
+        interpolation.previous = Some(start);
+    }
+}
+
+/// Record the transforms a step ended with
+pub fn end_step(mut fixed_time: ResMut<FixedTime>, mut query: Query<(&Transform, &mut TransformInterpolation)>) {
+    fixed_time.elapsed += fixed_time.step;
+    for (transform, mut interpolation) in query.iter_mut() {
+        interpolation.current = Some(*transform);
+        interpolation.shown = Some(*transform);
+    }
+}
+
+/// Show every simulated entity between its last two steps
+pub fn interpolate_transforms(
+    fixed_time: Res<FixedTime>,
+    mut query: Query<(&mut Transform, &mut TransformInterpolation)>,
+) {
+    let alpha = fixed_time.alpha();
+    for (mut transform, mut interpolation) in query.iter_mut() {
+        // Moved by something else since: stay there until the next step
+        if interpolation.shown.is_some_and(|shown| shown != *transform) {
+            interpolation.previous = Some(*transform);
+            interpolation.current = Some(*transform);
+        }
+        let (Some(previous), Some(current)) = (interpolation.previous, interpolation.current) else {
+            continue;
+        };
+        let shown = Transform {
+            position: previous.position.lerp(current.position, alpha),
+            rotation: previous.rotation.slerp(current.rotation, alpha),
+            scale: previous.scale.lerp(current.scale, alpha),
+        };
+        *transform = shown;
+        interpolation.shown = Some(shown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    const STEP: f32 = 1.0 / STEP_RATE;
+
+    #[test]
+    fn leftover_time_carries_into_the_next_frame() {
+        let mut fixed_time = FixedTime::default();
+        assert_eq!(fixed_time.accumulate(STEP * 0.6), 0);
+        assert!((fixed_time.alpha() - 0.6).abs() < 1e-4);
+        assert_eq!(fixed_time.accumulate(STEP * 0.6), 1);
+        assert!((fixed_time.alpha() - 0.2).abs() < 1e-4);
+        assert_eq!(fixed_time.accumulate(STEP * 2.5), 2);
+        assert!((fixed_time.alpha() - 0.7).abs() < 1e-4);
+    }
+
+    #[test]
+    fn long_frames_drop_the_time_past_the_step_limit() {
+        let mut fixed_time = FixedTime::default();
+        assert_eq!(fixed_time.accumulate(1.0), MAX_STEPS_PER_FRAME);
+        assert_eq!(fixed_time.alpha(), 0.0);
+        assert_eq!(fixed_time.accumulate(STEP * 1.5), 1);
+    }
+
+    fn step_right(mut query: Query<&mut Transform>) {
+        for mut transform in query.iter_mut() {
+            transform.position.x += 1.0;
+        }
+    }
+
+    /// Run one step of `step_right` and show the result `alpha` of the way into the next
+    fn run_step(world: &mut World, alpha: f32) {
+        let mut step = Schedule::default();
+        step.add_systems((begin_step, step_right, end_step).chain());
+        step.run(world);
+        world.resource_mut::<FixedTime>().accumulator = alpha * STEP;
+        let mut frame = Schedule::default();
+        frame.add_systems(interpolate_transforms);
+        frame.run(world);
+    }
+
+    #[test]
+    fn transforms_are_shown_between_steps() {
+        let mut world = World::new();
+        world.insert_resource(FixedTime::default());
+        let entity = world.spawn((Transform::new(), TransformInterpolation::default())).id();
+        let position = |world: &World| world.get::<Transform>(entity).unwrap().position;
+
+        run_step(&mut world, 0.25);
+        assert!((position(&world) - Vec3::new(0.25, 0.0, 0.0)).length() < 1e-5);
+        // The next step continues from the simulated transform, not the shown one
+        run_step(&mut world, 0.5);
+        assert!((position(&world) - Vec3::new(1.5, 0.0, 0.0)).length() < 1e-5);
+        assert!((world.resource::<FixedTime>().elapsed - 2.0 * STEP).abs() < 1e-6);
+    }
+
+    #[test]
+    fn moving_an_entity_restarts_its_simulation_there() {
+        let mut world = World::new();
+        world.insert_resource(FixedTime::default());
+        let entity = world.spawn((Transform::new(), TransformInterpolation::default())).id();
+        run_step(&mut world, 0.5);
+        world.get_mut::<Transform>(entity).unwrap().position = Vec3::new(10.0, 0.0, 0.0);
+        run_step(&mut world, 0.5);
+        assert!((world.get::<Transform>(entity).unwrap().position.x - 10.5).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,350 @@
+//! Per-frame transient memory
+//!
+//! Data that only lives for one frame (egui geometry, per-frame uniforms, instance matrices,
+//! and what GPU passes write for the frame's own draws, see `reserve`) is sub-allocated
+//! from one persistently mapped, host-visible ring buffer shared by all frames in flight,
+//! and descriptor sets pointing into it come from a descriptor pool of the frame's own.
+//! Nothing is created, freed or mapped per use.
+//!
+//! Allocations move a head forward through the ring; one that would run past the end
+//! starts over at the beginning instead. What the GPU may still read lies between the tail
+//! and the head. `begin_frame` retires a frame slot once its fence has signalled: every byte
+//! written up to the end of that slot's last frame is free again, since frames finish in
+//! the order they were submitted, and the tail moves past them.
+//!
+//! When the head would catch up with the tail the ring is replaced by one twice as large.
+//! The old ring stays alive, because commands recorded by the frames in flight point into
+//! it, until the current frame slot comes around again.
+
+use ash::vk;
+use ash::Device;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+use crate::renderer::MAX_FRAMES_IN_FLIGHT;
+
+/// Initial bytes of transient data per frame in flight; the ring holds this for each
+pub const FRAME_ARENA_SIZE: u64 = 4 * 1024 * 1024;
+/// Transient descriptor sets per frame in flight
+const MAX_FRAME_DESCRIPTOR_SETS: u32 = 64;
+
+/// A range of the current frame's arena memory. Valid until that frame slot is reused, so
+/// only reference it from the frame's own command buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct ArenaSlice {
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+}
+
+impl ArenaSlice {
+    pub fn descriptor_info(&self) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer: self.buffer,
+            offset: self.offset,
+            range: self.size,
+        }
+    }
+}
+
+/// One ring of arena memory. Positions count bytes written since the ring was created and
+/// only grow; the byte a position refers to is at `position % size`.
+struct Ring {
+    buffer: vk::Buffer,
+    allocation: Option<Allocation>,
+    size: u64, // A power of two, so aligned positions stay aligned in the buffer
+    head: u64,
+    tail: u64, // Bytes before it are free again
+    frame_ends: [u64; MAX_FRAMES_IN_FLIGHT], // `head` when each slot's last frame ended
+}
+
+impl Ring {
+    /// Place `size` bytes aligned to `alignment`, wrapping to the start if they don't fit
+    /// before the end. None if the ring is full.
+    fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let mut start = self.head.next_multiple_of(alignment);
+        if start % self.size + size > self.size {
+            start = start.next_multiple_of(self.size);
+        }
+        if start + size - self.tail > self.size {
+            return None;
+        }
+        self.head = start + size;
+        Some(start % self.size)
+    }
+}
+
+struct ArenaFrame {
+    retired: Vec<Ring>, // Outgrown while this slot was current, until it comes around again
+    descriptor_pool: vk::DescriptorPool,
+}
+
+struct ArenaState {
+    ring: Ring,
+    frames: Vec<ArenaFrame>,
+    frame: usize,
+    rings_created: u32, // For naming allocations
+}
+
+pub struct FrameArena {
+    device: Arc<Device>,
+    allocator: Arc<Mutex<Allocator>>,
+    alignment: u64, // Satisfies uniform and storage buffer offset alignment
+    state: Mutex<ArenaState>,
+}
+
+impl FrameArena {
+    /// # Safety
+    ///
+    /// `device` and `allocator` must belong to the same device, and `limits` to its physical
+    /// device.
+    pub unsafe fn new(
+        device: Arc<Device>,
+        allocator: Arc<Mutex<Allocator>>,
+        limits: &vk::PhysicalDeviceLimits,
+        capacity: u64,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let alignment = limits
+            .min_uniform_buffer_offset_alignment
+            .max(limits.min_storage_buffer_offset_alignment)
+            .max(16);
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: MAX_FRAME_DESCRIPTOR_SETS,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: MAX_FRAME_DESCRIPTOR_SETS,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: MAX_FRAME_DESCRIPTOR_SETS,
+            },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&pool_sizes)
+            .max_sets(MAX_FRAME_DESCRIPTOR_SETS);
+
+        let capacity = (capacity * MAX_FRAMES_IN_FLIGHT as u64).next_power_of_two();
+        let ring = create_ring(&device, &allocator, capacity, 0)?;
+        let mut frames = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
+            frames.push(ArenaFrame {
+                retired: Vec::new(),
+                descriptor_pool,
+            });
+        }
+
+        println!(
+            "✓ Frame arena: {} KiB ring + {} descriptor sets per frame in flight",
+            capacity / 1024,
+            MAX_FRAME_DESCRIPTOR_SETS
+        );
+
+        Ok(Self {
+            device,
+            allocator,
+            alignment,
+            state: Mutex::new(ArenaState { ring, frames, frame: 0, rings_created: 1 }),
+        })
+    }
+
+    /// Make `frame_index` current and release everything allocated in it last time around.
+    /// Call after the frame's in-flight fence has been waited on, before any `push`.
+    ///
+    /// # Safety
+    ///
+    /// The GPU must be done with everything allocated the last time `frame_index` was current.
+    pub unsafe fn begin_frame(&self, frame_index: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+
+        // The frame before ends here; the one this slot last held, and every earlier one,
+        // is done with its part of the ring
+        let ring = &mut state.ring;
+        ring.frame_ends[state.frame] = ring.head;
+        ring.tail = ring.tail.max(ring.frame_ends[frame_index]);
+        state.frame = frame_index;
+
+        let frame = &mut state.frames[frame_index];
+        self.device
+            .reset_descriptor_pool(frame.descriptor_pool, vk::DescriptorPoolResetFlags::empty())?;
+        for ring in frame.retired.drain(..) {
+            destroy_ring(&self.device, &self.allocator, ring);
+        }
+        Ok(())
+    }
+
+    /// The frame in flight `begin_frame` last made current
+    pub fn frame_index(&self) -> usize {
+        self.state.lock().frame
+    }
+
+    /// Copy `data` into the ring for the current frame, growing the ring if it is full.
+    /// Returns `None` only if that allocation fails.
+    ///
+    /// # Safety
+    ///
+    /// Only call between `begin_frame` and the submit of the frame it began.
+    pub unsafe fn push<T: Copy>(&self, data: &[T]) -> Option<ArenaSlice> {
+        let size = std::mem::size_of_val(data) as u64;
+        let (slice, mapped) = self.allocate(size, std::mem::align_of::<T>() as u64)?;
+        std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, mapped, size as usize);
+        Some(slice)
+    }
+
+    /// Room for `count` values of `T` in the current frame, left unwritten, for the GPU to
+    /// fill in before the frame's draws read it
+    pub fn reserve<T: Copy>(&self, count: usize) -> Option<ArenaSlice> {
+        let size = (std::mem::size_of::<T>() * count) as u64;
+        unsafe { self.allocate(size, std::mem::align_of::<T>() as u64) }.map(|(slice, _)| slice)
+    }
+
+    /// Place `size` bytes in the ring, growing it if it is full. Returns the slice and where
+    /// it is mapped.
+    unsafe fn allocate(&self, size: u64, alignment: u64) -> Option<(ArenaSlice, *mut u8)> {
+        let alignment = self.alignment.max(alignment);
+
+        let mut state = self.state.lock();
+        let offset = match state.ring.allocate(size, alignment) {
+            Some(offset) => offset,
+            None => {
+                let capacity = (state.ring.size * 2).max((size + alignment).next_power_of_two());
+                let ring = match create_ring(&self.device, &self.allocator, capacity, state.rings_created) {
+                    Ok(ring) => ring,
+                    Err(e) => {
+                        println!("⚠ Frame arena ring of {} KiB failed: {}", capacity / 1024, e);
+                        return None;
+                    }
+                };
+                state.rings_created += 1;
+                let old = std::mem::replace(&mut state.ring, ring);
+                let frame = state.frame;
+                state.frames[frame].retired.push(old);
+                println!("ℹ Frame arena grown to {} KiB", capacity / 1024);
+                state.ring.allocate(size, alignment)?
+            }
+        };
+
+        let ring = &state.ring;
+        let mapped = ring.allocation.as_ref()?.mapped_ptr()?.as_ptr() as *mut u8;
+
+        let slice = ArenaSlice {
+            buffer: ring.buffer,
+            offset,
+            size,
+        };
+        Some((slice, mapped.add(offset as usize)))
+    }
+
+    /// A descriptor set that is freed when the current frame slot comes around again
+    ///
+    /// # Safety
+    ///
+    /// `layout` must be a layout whose descriptors the frame pools were sized for.
+    pub unsafe fn allocate_descriptor_set(&self, layout: vk::DescriptorSetLayout) -> Result<vk::DescriptorSet, vk::Result> {
+        let state = self.state.lock();
+        let layouts = [layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(state.frames[state.frame].descriptor_pool)
+            .set_layouts(&layouts);
+        Ok(self.device.allocate_descriptor_sets(&alloc_info)?[0])
+    }
+
+    /// Upload `value` and return a transient set of `layout` with it bound as the uniform
+    /// buffer at binding 0. `None` if the frame's memory or descriptor sets ran out.
+    ///
+    /// # Safety
+    ///
+    /// `layout` must have a single uniform buffer at binding 0 that `T` matches.
+    pub unsafe fn uniform_set<T: Copy>(&self, layout: vk::DescriptorSetLayout, value: &T) -> Option<vk::DescriptorSet> {
+        let slice = self.push(std::slice::from_ref(value))?;
+        let descriptor_set = self.allocate_descriptor_set(layout).ok()?;
+
+        let buffer_info = slice.descriptor_info();
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(std::slice::from_ref(&buffer_info));
+        self.device.update_descriptor_sets(&[write], &[]);
+
+        Some(descriptor_set)
+    }
+
+    /// Free the ring and every frame's pool; none may still be in use
+    ///
+    /// # Safety
+    ///
+    /// The device must be idle.
+    pub unsafe fn destroy(&self) {
+        let mut state = self.state.lock();
+        for mut frame in state.frames.drain(..) {
+            self.device.destroy_descriptor_pool(frame.descriptor_pool, None);
+            for ring in frame.retired.drain(..) {
+                destroy_ring(&self.device, &self.allocator, ring);
+            }
+        }
+        self.device.destroy_buffer(state.ring.buffer, None);
+        if let Some(allocation) = state.ring.allocation.take() {
+            let _ = self.allocator.lock().free(allocation);
+        }
+    }
+}
+
+unsafe fn create_ring(
+    device: &Device,
+    allocator: &Mutex<Allocator>,
+    size: u64,
+    index: u32,
+) -> Result<Ring, Box<dyn std::error::Error>> {
+    let buffer_info = vk::BufferCreateInfo::default()
+        .size(size)
+        .usage(
+            vk::BufferUsageFlags::VERTEX_BUFFER
+                | vk::BufferUsageFlags::INDEX_BUFFER
+                | vk::BufferUsageFlags::UNIFORM_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST,
+        )
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = device.create_buffer(&buffer_info, None)?;
+    let requirements = device.get_buffer_memory_requirements(buffer);
+
+    let allocation = match allocator.lock().allocate(&AllocationCreateDesc {
+        name: &format!("Frame Arena Ring {}", index),
+        requirements,
+        location: MemoryLocation::CpuToGpu,
+        linear: true,
+        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+    }) {
+        Ok(allocation) => allocation,
+        Err(e) => {
+            device.destroy_buffer(buffer, None);
+            return Err(e.into());
+        }
+    };
+    device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+
+    Ok(Ring {
+        buffer,
+        allocation: Some(allocation),
+        size,
+        head: 0,
+        tail: 0,
+        frame_ends: [0; MAX_FRAMES_IN_FLIGHT],
+    })
+}
+
+unsafe fn destroy_ring(device: &Device, allocator: &Mutex<Allocator>, mut ring: Ring) {
+    device.destroy_buffer(ring.buffer, None);
+    if let Some(allocation) = ring.allocation.take() {
+        let _ = allocator.lock().free(allocation);
+    }
+}
@@ -0,0 +1,38 @@
+//! Custom passes
+//!
+//! Code outside the renderer registers closures with `VulkanRenderer::add_pass`. They run
+//! every frame after the scene has been drawn and before the UI overlay, outside any render
+//! pass, and record into the frame's command buffer through a `FrameContext`. To draw over
+//! the scene, begin `render_pass` on `framebuffer`: it loads the swapchain image and keeps
+//! it presentable.
+
+use ash::vk;
+use glam::{Mat4, Vec3};
+
+use crate::frame_arena::FrameArena;
+
+/// Everything a custom pass needs to record this frame's commands
+#[allow(dead_code)] // Read by library users' passes; the app registers none
+pub struct FrameContext<'a> {
+    pub device: &'a ash::Device,
+    pub command_buffer: vk::CommandBuffer,
+    pub frame_index: usize, // Frame in flight, for per-frame resources
+    pub image_index: u32,   // Swapchain image being rendered
+    pub extent: vk::Extent2D,
+    pub render_pass: vk::RenderPass, // Color-only, loads the existing image
+    pub framebuffer: vk::Framebuffer,
+    pub frame_arena: &'a FrameArena, // Transient uniforms and geometry for this frame
+    pub camera_position: Vec3,
+    pub view: Mat4,
+    pub proj: Mat4, // Vulkan clip space (Y flipped)
+}
+
+#[allow(dead_code)]
+impl FrameContext<'_> {
+    pub fn view_proj(&self) -> Mat4 {
+        self.proj * self.view
+    }
+}
+
+/// A registered custom pass
+pub type FramePass = Box<dyn FnMut(&FrameContext)>;
@@ -0,0 +1,112 @@
+//! CPU frustum culling
+//!
+//! A `Frustum` holds the six planes of a camera's view-projection, taken from the matrix's
+//! rows like frustum.glsl does for the GPU culling (Vulkan's 0..1 depth). Draws recorded
+//! on the CPU test their mesh's axis-aligned bounds against it and are skipped when the box
+//! lies wholly behind one of the planes. A box just outside a corner of the frustum still
+//! passes, which only costs a draw.
+
+use glam::{Mat4, Vec3, Vec4};
+
+pub struct Frustum {
+    planes: [Vec4; 6], // xyz = normal pointing inside, w = distance; not normalized
+}
+
+impl Frustum {
+    pub fn from_view_proj(view_proj: &Mat4) -> Self {
+        let (x, y, z, w) = (view_proj.row(0), view_proj.row(1), view_proj.row(2), view_proj.row(3));
+        Self { planes: [w + x, w - x, w + y, w - y, z, w - z] }
+    }
+
+    /// Whether the box `min..max` (model space) placed by `model` may be in view
+    pub fn contains_box(&self, model: &Mat4, (min, max): (Vec3, Vec3)) -> bool {
+        // The world space box around the placed one
+        let center = model.transform_point3((min + max) * 0.5);
+        let half = (max - min) * 0.5;
+        let extent = model.x_axis.truncate().abs() * half.x
+            + model.y_axis.truncate().abs() * half.y
+            + model.z_axis.truncate().abs() * half.z;
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            normal.dot(center) + plane.w >= -normal.abs().dot(extent)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Quat;
+
+    /// Looking down -Z from the origin with a 90° field of view, seeing 0.1 to 100 away
+    fn camera() -> Frustum {
+        Frustum::from_view_proj(&Mat4::perspective_rh(90.0_f32.to_radians(), 1.0, 0.1, 100.0))
+    }
+
+    /// Signed distance of `point` from `plane`, positive inside
+    fn distance(plane: Vec4, point: Vec3) -> f32 {
+        (plane.truncate().dot(point) + plane.w) / plane.truncate().length()
+    }
+
+    fn unit_box() -> (Vec3, Vec3) {
+        (Vec3::splat(-0.5), Vec3::splat(0.5))
+    }
+
+    #[test]
+    fn planes_come_from_the_matrix_rows() {
+        let planes = Frustum::from_view_proj(&Mat4::IDENTITY).planes;
+        assert_eq!(
+            planes,
+            [
+                Vec4::new(1.0, 0.0, 0.0, 1.0),  // x >= -1
+                Vec4::new(-1.0, 0.0, 0.0, 1.0), // x <= 1
+                Vec4::new(0.0, 1.0, 0.0, 1.0),  // y >= -1
+                Vec4::new(0.0, -1.0, 0.0, 1.0), // y <= 1
+                Vec4::new(0.0, 0.0, 1.0, 0.0),  // z >= 0
+                Vec4::new(0.0, 0.0, -1.0, 1.0), // z <= 1
+            ]
+        );
+    }
+
+    #[test]
+    fn planes_bound_the_view_volume() {
+        let planes = camera().planes;
+        let point = Vec3::new(0.0, 0.0, -10.0);
+        // The side planes are 45° off the view axis
+        for side in &planes[..4] {
+            assert!((distance(*side, point) - 10.0 / 2.0_f32.sqrt()).abs() < 1e-4);
+        }
+        assert!((distance(planes[4], point) - 9.9).abs() < 1e-3); // Near
+        assert!((distance(planes[5], point) - 90.0).abs() < 1e-2); // Far
+        assert!(distance(planes[0], Vec3::new(-11.0, 0.0, -10.0)) < 0.0);
+        assert!(distance(planes[5], Vec3::new(0.0, 0.0, -101.0)) < 0.0);
+    }
+
+    #[test]
+    fn boxes_outside_a_plane_are_culled() {
+        let frustum = camera();
+        let at = |x: f32, y: f32, z: f32| Mat4::from_translation(Vec3::new(x, y, z));
+        assert!(frustum.contains_box(&at(0.0, 0.0, -10.0), unit_box()));
+        assert!(!frustum.contains_box(&at(0.0, 0.0, 10.0), unit_box())); // Behind
+        assert!(!frustum.contains_box(&at(0.0, 0.0, -200.0), unit_box())); // Past the far plane
+        assert!(!frustum.contains_box(&at(30.0, 0.0, -10.0), unit_box()));
+        assert!(!frustum.contains_box(&at(0.0, -30.0, -10.0), unit_box()));
+        // Straddling the right plane
+        assert!(frustum.contains_box(&at(10.3, 0.0, -10.0), unit_box()));
+    }
+
+    #[test]
+    fn boxes_are_placed_by_their_model_matrix() {
+        let frustum = camera();
+        // Off to the side, but scaled up far enough to reach into view
+        let position = Vec3::new(12.0, 0.0, -10.0);
+        assert!(!frustum.contains_box(&Mat4::from_translation(position), unit_box()));
+        let scaled = Mat4::from_scale_rotation_translation(Vec3::splat(5.0), Quat::IDENTITY, position);
+        assert!(frustum.contains_box(&scaled, unit_box()));
+        // A long thin box behind the camera only reaches into view once turned along it
+        let (long, behind) = ((Vec3::new(-8.0, -0.1, -0.1), Vec3::new(8.0, 0.1, 0.1)), Vec3::new(0.0, 0.0, 5.0));
+        assert!(!frustum.contains_box(&Mat4::from_translation(behind), long));
+        let turned = Mat4::from_rotation_translation(Quat::from_rotation_y(90.0_f32.to_radians()), behind);
+        assert!(frustum.contains_box(&turned, long));
+    }
+}
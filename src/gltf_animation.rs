@@ -0,0 +1,245 @@
+//! glTF animation playback
+//!
+//! A `GltfAnimationPlayer` plays one of the scene's animations at a time. Every frame
+//! `advance` moves its time on, wrapping around at the clip's end, and `node_transforms`
+//! samples the clip's channels over the nodes' rest transforms and composes the global
+//! transform of every node. Skinning poses the joints from those, see
+//! `SkinningPass::set_pose`, and static meshes under animated nodes move by their node's
+//! change from the rest pose they were baked in, see `GltfRenderer::animate`.
+
+use glam::{Mat4, Quat, Vec3, Vec4};
+
+use crate::gltf_loader::{ChannelValues, GltfAnimation, GltfChannel, GltfScene, Interpolation};
+
+pub struct GltfAnimationPlayer {
+    animations: Vec<GltfAnimation>,
+    rest: Vec<(Vec3, Quat, Vec3)>, // Local translation, rotation and scale of each node
+    parents: Vec<Option<usize>>,
+    clip: Option<usize>, // Animation playing, None for the rest pose
+    pub time: f32,       // Seconds into the clip
+    pub speed: f32,
+    pub playing: bool,
+}
+
+impl GltfAnimationPlayer {
+    /// Returns `None` if the scene has no animations; otherwise plays the first one
+    pub fn new(scene: &GltfScene) -> Option<Self> {
+        if scene.animations.is_empty() {
+            return None;
+        }
+        let rest = scene
+            .nodes
+            .iter()
+            .map(|node| {
+                let (scale, rotation, translation) = Mat4::from_cols_array_2d(&node.transform).to_scale_rotation_translation();
+                (translation, rotation, scale)
+            })
+            .collect();
+        Some(Self {
+            animations: scene.animations.clone(),
+            rest,
+            parents: scene.nodes.iter().map(|node| node.parent).collect(),
+            clip: Some(0),
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+        })
+    }
+
+    pub fn clip_names(&self) -> Vec<String> {
+        self.animations.iter().map(|animation| animation.name.clone()).collect()
+    }
+
+    pub fn clip(&self) -> Option<usize> {
+        self.clip
+    }
+
+    /// Play `clip` from its start, or show the rest pose for `None`
+    pub fn set_clip(&mut self, clip: Option<usize>) {
+        self.clip = clip.filter(|&clip| clip < self.animations.len());
+        self.time = 0.0;
+    }
+
+    /// Length of the clip playing in seconds, 0 without one
+    pub fn duration(&self) -> f32 {
+        self.clip.map_or(0.0, |clip| self.animations[clip].duration)
+    }
+
+    /// Whether the pose changes over time
+    pub fn animating(&self) -> bool {
+        self.playing && self.duration() > 0.0
+    }
+
+    /// Move on by `delta` seconds of scene time, looping
+    pub fn advance(&mut self, delta: f32) {
+        if self.animating() {
+            self.time = (self.time + delta * self.speed).rem_euclid(self.duration());
+        }
+    }
+
+    /// Global transform of every node at the current time, indexed like `GltfScene::nodes`
+    pub fn node_transforms(&self) -> Vec<Mat4> {
+        let mut local = self.rest.clone();
+        if let Some(clip) = self.clip {
+            for channel in &self.animations[clip].channels {
+                let Some((translation, rotation, scale)) = local.get_mut(channel.node) else {
+                    continue;
+                };
+                let value = sample(channel, self.time);
+                match channel.values {
+                    ChannelValues::Translations(_) => *translation = value.truncate(),
+                    ChannelValues::Rotations(_) => *rotation = Quat::from_vec4(value).normalize(),
+                    ChannelValues::Scales(_) => *scale = value.truncate(),
+                }
+            }
+        }
+        self.globals(&local)
+    }
+
+    /// Global transform of every node in the rest pose, the one static meshes are baked in
+    pub fn rest_transforms(&self) -> Vec<Mat4> {
+        self.globals(&self.rest)
+    }
+
+    fn globals(&self, local: &[(Vec3, Quat, Vec3)]) -> Vec<Mat4> {
+        let mut global: Vec<Option<Mat4>> = vec![None; local.len()];
+        for node in 0..local.len() {
+            resolve(node, local, &self.parents, &mut global);
+        }
+        global.into_iter().map(|transform| transform.unwrap_or(Mat4::IDENTITY)).collect()
+    }
+}
+
+/// Global transform of `node`, resolving its ancestors first
+fn resolve(node: usize, local: &[(Vec3, Quat, Vec3)], parents: &[Option<usize>], global: &mut [Option<Mat4>]) -> Mat4 {
+    if let Some(transform) = global[node] {
+        return transform;
+    }
+    let (translation, rotation, scale) = local[node];
+    let transform = Mat4::from_scale_rotation_translation(scale, rotation, translation);
+    let transform = match parents[node] {
+        Some(parent) => resolve(parent, local, parents, global) * transform,
+        None => transform,
+    };
+    global[node] = Some(transform);
+    transform
+}
+
+/// Value of `channel` at `time`, as XYZ and 0 for translations and scales, XYZW for
+/// rotations. Before the first keyframe and after the last the value holds.
+fn sample(channel: &GltfChannel, time: f32) -> Vec4 {
+    let key = |index: usize| match &channel.values {
+        ChannelValues::Translations(values) | ChannelValues::Scales(values) => Vec3::from(values[index]).extend(0.0),
+        ChannelValues::Rotations(values) => Vec4::from(values[index]),
+    };
+    let cubic = channel.interpolation == Interpolation::CubicSpline;
+    // Cubic splines store an in-tangent, the value and an out-tangent per keyframe
+    let value = |keyframe: usize| if cubic { key(keyframe * 3 + 1) } else { key(keyframe) };
+
+    let times = &channel.times;
+    let next = times.partition_point(|&t| t <= time);
+    if next == 0 {
+        return value(0);
+    }
+    if next == times.len() {
+        return value(times.len() - 1);
+    }
+    let (previous, span) = (next - 1, times[next] - times[next - 1]);
+    let t = if span > 0.0 { (time - times[previous]) / span } else { 0.0 };
+
+    match channel.interpolation {
+        Interpolation::Step => value(previous),
+        Interpolation::Linear => match channel.values {
+            ChannelValues::Rotations(_) => {
+                Vec4::from(Quat::from_vec4(value(previous)).slerp(Quat::from_vec4(value(next)), t))
+            }
+            _ => value(previous).lerp(value(next), t),
+        },
+        Interpolation::CubicSpline => {
+            // Hermite spline, the tangents scaled by the keyframe span
+            let (p0, m0) = (value(previous), key(previous * 3 + 2) * span);
+            let (p1, m1) = (value(next), key(next * 3) * span);
+            let (t2, t3) = (t * t, t * t * t);
+            p0 * (2.0 * t3 - 3.0 * t2 + 1.0) + m0 * (t3 - 2.0 * t2 + t) + p1 * (-2.0 * t3 + 3.0 * t2) + m1 * (t3 - t2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(interpolation: Interpolation, times: &[f32], values: ChannelValues) -> GltfChannel {
+        GltfChannel { node: 0, interpolation, times: times.to_vec(), values }
+    }
+
+    fn translations(interpolation: Interpolation) -> GltfChannel {
+        let values = ChannelValues::Translations(vec![[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [2.0, 4.0, 0.0]]);
+        channel(interpolation, &[0.0, 1.0, 2.0], values)
+    }
+
+    fn close(a: Vec4, b: Vec4) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn linear_keyframes_are_blended() {
+        let channel = translations(Interpolation::Linear);
+        assert!(close(sample(&channel, 0.0), Vec4::ZERO));
+        assert!(close(sample(&channel, 0.5), Vec4::new(1.0, 0.0, 0.0, 0.0)));
+        assert!(close(sample(&channel, 1.0), Vec4::new(2.0, 0.0, 0.0, 0.0)));
+        assert!(close(sample(&channel, 1.25), Vec4::new(2.0, 1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn values_hold_outside_the_keyframes() {
+        let channel = translations(Interpolation::Linear);
+        assert!(close(sample(&channel, -1.0), Vec4::ZERO));
+        assert!(close(sample(&channel, 5.0), Vec4::new(2.0, 4.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn step_keyframes_hold_until_the_next() {
+        let channel = translations(Interpolation::Step);
+        assert!(close(sample(&channel, 0.99), Vec4::ZERO));
+        assert!(close(sample(&channel, 1.5), Vec4::new(2.0, 0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn rotations_are_slerped() {
+        let quarter_turn = Quat::from_rotation_y(90.0_f32.to_radians());
+        let values = ChannelValues::Rotations(vec![Quat::IDENTITY.to_array(), quarter_turn.to_array()]);
+        let channel = channel(Interpolation::Linear, &[0.0, 2.0], values);
+        let halfway = Quat::from_vec4(sample(&channel, 1.0));
+        assert!(halfway.angle_between(Quat::from_rotation_y(45.0_f32.to_radians())) < 1e-4);
+    }
+
+    #[test]
+    fn cubic_splines_follow_their_tangents() {
+        // In-tangent, value and out-tangent per keyframe; both values 0, leaving the first
+        // at 1 per second
+        let values = ChannelValues::Translations(vec![
+            [0.0; 3], [0.0; 3], [1.0, 0.0, 0.0],
+            [0.0; 3], [0.0; 3], [0.0; 3],
+        ]);
+        let channel = channel(Interpolation::CubicSpline, &[0.0, 2.0], values);
+        // The out-tangent scaled by the 2 second span, times (t³ - 2t² + t) at t = 0.5
+        assert!(close(sample(&channel, 1.0), Vec4::new(0.25, 0.0, 0.0, 0.0)));
+        assert!(close(sample(&channel, 0.0), Vec4::ZERO));
+        assert!(close(sample(&channel, 2.0), Vec4::ZERO));
+    }
+
+    #[test]
+    fn children_are_placed_by_their_parents() {
+        let local = [
+            (Vec3::new(1.0, 0.0, 0.0), Quat::from_rotation_z(90.0_f32.to_radians()), Vec3::ONE),
+            (Vec3::new(1.0, 0.0, 0.0), Quat::IDENTITY, Vec3::ONE),
+        ];
+        let parents = [None, Some(0)];
+        let mut global = [None; 2];
+        // The child is resolved first, so it has to resolve its parent
+        let child = resolve(1, &local, &parents, &mut global);
+        assert!((child.transform_point3(Vec3::ZERO) - Vec3::new(1.0, 1.0, 0.0)).length() < 1e-5);
+        assert!(global[0].is_some());
+    }
+}
@@ -0,0 +1,425 @@
+//! glTF scene export
+//!
+//! `GltfScene::save` writes a loaded scene back out the way it is arranged in the viewer:
+//! its meshes under one node carrying the model's transform, each with its material as
+//! edited, plus the sun as a `KHR_lights_punctual` directional light and the camera. That
+//! makes the viewer usable for light scene assembly, not only for looking.
+//!
+//! Geometry is written as loaded (positions, normals, vertex colors and both UV sets, with
+//! the node transforms baked in) and textures are re-encoded as PNG. Skins and animations
+//! aren't written; skinned meshes come out in their bind pose. A `.glb` holds everything;
+//! a `.gltf` gets its buffer in a `.bin` next to it.
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use glam::{Mat4, Vec3};
+use gltf::json;
+use json::validation::Checked::Valid;
+use json::validation::USize64;
+
+use crate::gltf_loader::{AlphaMode, GltfMaterial, GltfScene};
+
+/// File format of an export
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Gltf, // JSON with a separate .bin
+    Glb,  // Everything in one binary file
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Gltf => "gltf",
+            Self::Glb => "glb",
+        }
+    }
+}
+
+/// Where the scene is and how it looks, beyond what `GltfScene` itself holds
+pub struct SceneArrangement {
+    pub model: Mat4,                             // Model to world
+    pub mesh_materials: Vec<Option<GltfMaterial>>, // Per mesh: the material as edited, None as loaded
+    pub sun_direction: Option<Vec3>,             // Towards the sun
+    pub camera: Option<(Mat4, Mat4)>,            // View and (Vulkan, perspective) projection
+}
+
+/// `exports/scene_<unix time>.<extension>`, creating the directory
+pub fn output_path(format: ExportFormat) -> Result<PathBuf, std::io::Error> {
+    std::fs::create_dir_all("exports")?;
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Ok(PathBuf::from(format!("exports/scene_{}.{}", timestamp, format.extension())))
+}
+
+/// The binary buffer everything is packed into, one 4-byte aligned view after another
+#[derive(Default)]
+struct BinaryBuffer {
+    data: Vec<u8>,
+}
+
+impl BinaryBuffer {
+    fn push(&mut self, root: &mut json::Root, bytes: &[u8], target: Option<json::buffer::Target>) -> json::Index<json::buffer::View> {
+        let offset = self.data.len();
+        self.data.extend_from_slice(bytes);
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+        root.push(json::buffer::View {
+            buffer: json::Index::new(0),
+            byte_length: USize64::from(bytes.len()),
+            byte_offset: Some(USize64::from(offset)),
+            byte_stride: None,
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            target: target.map(Valid),
+        })
+    }
+
+    /// A tightly packed accessor of `count` elements over `bytes`
+    fn accessor(
+        &mut self,
+        root: &mut json::Root,
+        bytes: &[u8],
+        count: usize,
+        component_type: json::accessor::ComponentType,
+        type_: json::accessor::Type,
+        bounds: Option<([f32; 3], [f32; 3])>,
+    ) -> json::Index<json::Accessor> {
+        let target = match type_ {
+            json::accessor::Type::Scalar => json::buffer::Target::ElementArrayBuffer,
+            _ => json::buffer::Target::ArrayBuffer,
+        };
+        let view = self.push(root, bytes, Some(target));
+        root.push(json::Accessor {
+            buffer_view: Some(view),
+            byte_offset: None,
+            count: USize64::from(count),
+            component_type: Valid(json::accessor::GenericComponentType(component_type)),
+            extensions: Default::default(),
+            extras: Default::default(),
+            type_: Valid(type_),
+            min: bounds.map(|(min, _)| json::Value::from(min.to_vec())),
+            max: bounds.map(|(_, max)| json::Value::from(max.to_vec())),
+            name: None,
+            normalized: false,
+            sparse: None,
+        })
+    }
+}
+
+fn bytes_of<T: Copy>(values: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(values.as_ptr() as *const u8, std::mem::size_of_val(values)) }
+}
+
+impl GltfScene {
+    /// Write the scene as arranged to `path`, as a `.glb` or a `.gltf` (with a `.bin`)
+    /// depending on its extension
+    pub fn save(&self, path: &Path, arrangement: &SceneArrangement) -> Result<(), Box<dyn std::error::Error>> {
+        let format = match path.extension().and_then(|e| e.to_str()) {
+            Some("glb") => ExportFormat::Glb,
+            Some("gltf") => ExportFormat::Gltf,
+            _ => return Err(format!("{} is neither .gltf nor .glb", path.display()).into()),
+        };
+        let mut root = json::Root {
+            asset: json::Asset { generator: Some("funkyrenderer".into()), ..Default::default() },
+            ..Default::default()
+        };
+        let mut buffer = BinaryBuffer::default();
+
+        // Textures, one per image, so material texture indices carry over as loaded
+        for (i, texture) in self.textures.iter().enumerate() {
+            // Compressed (KTX2) textures aren't decoded, so they go out white
+            let image = match texture.compressed {
+                Some(_) => image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4])),
+                None => image::RgbaImage::from_raw(texture.width, texture.height, texture.data.clone())
+                    .ok_or("texture data doesn't match its size")?,
+            };
+            let mut png = Vec::new();
+            image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)?;
+            let view = buffer.push(&mut root, &png, None);
+            let source = root.push(json::Image {
+                buffer_view: Some(view),
+                mime_type: Some(json::image::MimeType("image/png".into())),
+                name: Some(format!("texture {}", i)),
+                uri: None,
+                extensions: Default::default(),
+                extras: Default::default(),
+            });
+            root.push(json::Texture {
+                name: None,
+                sampler: None,
+                source,
+                extensions: Default::default(),
+                extras: Default::default(),
+            });
+        }
+
+        // Materials, shared by meshes whose (edited) materials are equal
+        let mut materials: Vec<GltfMaterial> = Vec::new();
+        let mut mesh_nodes = Vec::new();
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            let material = arrangement
+                .mesh_materials
+                .get(i)
+                .cloned()
+                .flatten()
+                .or_else(|| mesh.material_index.and_then(|index| self.materials.get(index).cloned()));
+            let material_index = material.map(|material| {
+                let index = match materials.iter().position(|m| *m == material) {
+                    Some(index) => index,
+                    None => {
+                        materials.push(material);
+                        materials.len() - 1
+                    }
+                };
+                json::Index::new(index as u32)
+            });
+
+            let positions: Vec<[f32; 3]> = mesh.vertices.iter().map(|v| v.position).collect();
+            let mut min = [f32::INFINITY; 3];
+            let mut max = [f32::NEG_INFINITY; 3];
+            for position in &positions {
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(position[axis]);
+                    max[axis] = max[axis].max(position[axis]);
+                }
+            }
+            let normals: Vec<[f32; 3]> = mesh.vertices.iter().map(|v| v.normal).collect();
+            let tex_coords: Vec<[f32; 2]> = mesh.vertices.iter().map(|v| v.tex_coord).collect();
+            let colors: Vec<[f32; 3]> = mesh.vertices.iter().map(|v| v.color).collect();
+
+            use json::accessor::{ComponentType, Type};
+            use json::mesh::Semantic;
+            let count = mesh.vertices.len();
+            let bounds = (!positions.is_empty()).then_some((min, max));
+            let mut attributes = std::collections::BTreeMap::new();
+            attributes.insert(
+                Valid(Semantic::Positions),
+                buffer.accessor(&mut root, bytes_of(&positions), count, ComponentType::F32, Type::Vec3, bounds),
+            );
+            attributes.insert(
+                Valid(Semantic::Normals),
+                buffer.accessor(&mut root, bytes_of(&normals), count, ComponentType::F32, Type::Vec3, None),
+            );
+            attributes.insert(
+                Valid(Semantic::TexCoords(0)),
+                buffer.accessor(&mut root, bytes_of(&tex_coords), count, ComponentType::F32, Type::Vec2, None),
+            );
+            attributes.insert(
+                Valid(Semantic::Colors(0)),
+                buffer.accessor(&mut root, bytes_of(&colors), count, ComponentType::F32, Type::Vec3, None),
+            );
+            if mesh.has_lightmap_uvs {
+                let lightmap_uvs: Vec<[f32; 2]> = mesh.vertices.iter().map(|v| v.lightmap_uv).collect();
+                attributes.insert(
+                    Valid(Semantic::TexCoords(1)),
+                    buffer.accessor(&mut root, bytes_of(&lightmap_uvs), count, ComponentType::F32, Type::Vec2, None),
+                );
+            }
+            let indices = buffer.accessor(
+                &mut root,
+                bytes_of(&mesh.indices),
+                mesh.indices.len(),
+                ComponentType::U32,
+                Type::Scalar,
+                None,
+            );
+
+            let json_mesh = root.push(json::Mesh {
+                extensions: Default::default(),
+                extras: Default::default(),
+                name: Some(format!("mesh {}", i)),
+                primitives: vec![json::mesh::Primitive {
+                    attributes,
+                    extensions: Default::default(),
+                    extras: Default::default(),
+                    indices: Some(indices),
+                    material: material_index,
+                    mode: Valid(json::mesh::Mode::Triangles),
+                    targets: None,
+                }],
+                weights: None,
+            });
+            mesh_nodes.push(root.push(json::Node { mesh: Some(json_mesh), ..Default::default() }));
+        }
+        for material in &materials {
+            root.push(export_material(material, self.textures.len()));
+        }
+        if materials.iter().any(|material| material.emissive_strength != 1.0) {
+            root.extensions_used.push("KHR_materials_emissive_strength".into());
+        }
+
+        // The model's transform goes on one node over all its meshes
+        let (scale, rotation, translation) = arrangement.model.to_scale_rotation_translation();
+        let mut scene_nodes = vec![root.push(json::Node {
+            name: Some("model".into()),
+            children: Some(mesh_nodes),
+            translation: Some(translation.to_array()),
+            rotation: Some(json::scene::UnitQuaternion(rotation.to_array())),
+            scale: Some(scale.to_array()),
+            ..Default::default()
+        })];
+
+        // glTF lights shine down their node's -Z
+        if let Some(sun) = arrangement.sun_direction {
+            root.extensions = Some(json::extensions::root::Root {
+                khr_lights_punctual: Some(json::extensions::root::KhrLightsPunctual {
+                    lights: vec![json::extensions::scene::khr_lights_punctual::Light {
+                        color: [1.0, 1.0, 1.0],
+                        extensions: None,
+                        extras: Default::default(),
+                        intensity: 1.0,
+                        name: Some("sun".into()),
+                        range: None,
+                        spot: None,
+                        type_: Valid(json::extensions::scene::khr_lights_punctual::Type::Directional),
+                    }],
+                }),
+                ..Default::default()
+            });
+            root.extensions_used.push("KHR_lights_punctual".into());
+            let rotation = glam::Quat::from_rotation_arc(Vec3::NEG_Z, -sun.normalize());
+            scene_nodes.push(root.push(json::Node {
+                name: Some("sun".into()),
+                rotation: Some(json::scene::UnitQuaternion(rotation.to_array())),
+                extensions: Some(json::extensions::scene::Node {
+                    khr_lights_punctual: Some(json::extensions::scene::khr_lights_punctual::KhrLightsPunctual {
+                        light: json::Index::new(0),
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }));
+        }
+
+        // glTF cameras look down their node's -Z with +Y up, like the view matrix's inverse
+        if let Some((view, proj)) = arrangement.camera {
+            // perspective_rh with Y flipped: [1][1] = -1 / tan(fov / 2), depth 0..1 from near to far
+            let znear = proj.w_axis.z / proj.z_axis.z;
+            let zfar = proj.w_axis.z / (proj.z_axis.z + 1.0);
+            let camera = root.push(json::Camera {
+                name: Some("camera".into()),
+                orthographic: None,
+                perspective: Some(json::camera::Perspective {
+                    aspect_ratio: Some(proj.y_axis.y.abs() / proj.x_axis.x),
+                    yfov: 2.0 * (1.0 / proj.y_axis.y.abs()).atan(),
+                    zfar: Some(zfar),
+                    znear,
+                    extensions: None,
+                    extras: Default::default(),
+                }),
+                type_: Valid(json::camera::Type::Perspective),
+                extensions: None,
+                extras: Default::default(),
+            });
+            let (_, rotation, translation) = view.inverse().to_scale_rotation_translation();
+            scene_nodes.push(root.push(json::Node {
+                name: Some("camera".into()),
+                camera: Some(camera),
+                translation: Some(translation.to_array()),
+                rotation: Some(json::scene::UnitQuaternion(rotation.to_array())),
+                ..Default::default()
+            }));
+        }
+
+        let scene = root.push(json::Scene {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            nodes: scene_nodes,
+        });
+        root.scene = Some(scene);
+
+        let bin_path = path.with_extension("bin");
+        root.push(json::Buffer {
+            byte_length: USize64::from(buffer.data.len()),
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            uri: match format {
+                ExportFormat::Gltf => bin_path.file_name().map(|name| name.to_string_lossy().into_owned()),
+                ExportFormat::Glb => None,
+            },
+        });
+
+        match format {
+            ExportFormat::Gltf => {
+                std::fs::write(&bin_path, &buffer.data)?;
+                std::fs::write(path, json::serialize::to_string_pretty(&root)?)?;
+            }
+            ExportFormat::Glb => {
+                // The writer pads the chunks and works out the header's length itself
+                let json = json::serialize::to_vec(&root)?;
+                let glb = gltf::binary::Glb {
+                    header: gltf::binary::Header {
+                        magic: *b"glTF",
+                        version: 2,
+                        length: (12 + 8 + json.len().next_multiple_of(4) + 8 + buffer.data.len())
+                            .try_into()
+                            .map_err(|_| "scene too large for a .glb")?,
+                    },
+                    json: Cow::Owned(json),
+                    bin: Some(Cow::Owned(buffer.data)),
+                };
+                glb.to_writer(std::fs::File::create(path)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `material` as glTF, leaving out texture references past `texture_count` (images the
+/// loader skipped)
+fn export_material(material: &GltfMaterial, texture_count: usize) -> json::Material {
+    let texture = |index: Option<usize>| index.filter(|&index| index < texture_count).map(|index| json::Index::new(index as u32));
+    json::Material {
+        alpha_cutoff: (material.alpha_mode == AlphaMode::Mask).then_some(json::material::AlphaCutoff(material.alpha_cutoff)),
+        alpha_mode: Valid(match material.alpha_mode {
+            AlphaMode::Opaque => json::material::AlphaMode::Opaque,
+            AlphaMode::Mask => json::material::AlphaMode::Mask,
+            AlphaMode::Blend => json::material::AlphaMode::Blend,
+        }),
+        double_sided: material.double_sided,
+        pbr_metallic_roughness: json::material::PbrMetallicRoughness {
+            base_color_factor: json::material::PbrBaseColorFactor(material.base_color),
+            base_color_texture: texture(material.base_color_texture_index).map(|index| json::texture::Info {
+                index,
+                tex_coord: 0,
+                extensions: None,
+                extras: Default::default(),
+            }),
+            metallic_factor: json::material::StrengthFactor(material.metallic),
+            roughness_factor: json::material::StrengthFactor(material.roughness),
+            ..Default::default()
+        },
+        normal_texture: texture(material.normal_texture_index).map(|index| json::material::NormalTexture {
+            index,
+            scale: 1.0,
+            tex_coord: 0,
+            extensions: None,
+            extras: Default::default(),
+        }),
+        occlusion_texture: texture(material.occlusion_texture_index).map(|index| json::material::OcclusionTexture {
+            index,
+            strength: json::material::StrengthFactor(material.occlusion_strength),
+            tex_coord: 0,
+            extensions: None,
+            extras: Default::default(),
+        }),
+        emissive_factor: json::material::EmissiveFactor(material.emissive_factor),
+        emissive_texture: texture(material.emissive_texture_index).map(|index| json::texture::Info {
+            index,
+            tex_coord: 0,
+            extensions: None,
+            extras: Default::default(),
+        }),
+        extensions: (material.emissive_strength != 1.0).then(|| json::extensions::material::Material {
+            emissive_strength: Some(json::extensions::material::EmissiveStrength {
+                emissive_strength: json::extensions::material::EmissiveStrengthFactor(material.emissive_strength),
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
@@ -2,27 +2,137 @@ use std::path::Path;
 use std::io::BufReader;
 use std::fs::File;
 
+use gltf::animation::util::ReadOutputs;
+
+use crate::ktx2;
+use crate::texture_streaming::MipLevel;
+
 #[derive(Clone, Debug)]
 pub struct GltfVertex {
     pub position: [f32; 3],
     pub normal: [f32; 3],
     pub tex_coord: [f32; 2],
     pub color: [f32; 3],
+    /// TEXCOORD_1, or the lightmap atlas position once `lightmap::prepare_scene` has run
+    pub lightmap_uv: [f32; 2],
 }
 
 #[derive(Clone, Debug)]
 pub struct GltfMesh {
+    /// The glTF mesh's name, with the primitive number when it has several
+    pub name: String,
     pub vertices: Vec<GltfVertex>,
     pub indices: Vec<u32>,
     pub material_index: Option<usize>,
+    /// Primitive has TEXCOORD_1 (lightmap UVs)
+    pub has_lightmap_uvs: bool,
+    /// Primitive has JOINTS_0/WEIGHTS_0 (skinned)
+    pub has_joints: bool,
+    /// Per-vertex joint indices and weights (empty unless `has_joints`)
+    pub joints: Vec<[u16; 4]>,
+    pub weights: Vec<[f32; 4]>,
+    /// Skin of the node that instantiates this mesh
+    pub skin_index: Option<usize>,
+    /// Node that instantiates this mesh, None in a file without scenes. A static mesh has
+    /// the node's global transform baked into its vertices; a skinned one is posed by its
+    /// skin, which already places it.
+    pub node: Option<usize>,
+    /// Axis-aligned bounds (model space) of the vertex positions, with the node's transform
+    /// baked in like the vertices. Zero for a mesh without positions.
+    pub bounds_min: [f32; 3],
+    pub bounds_max: [f32; 3],
+}
+
+/// A node of the glTF scene graph
+#[derive(Clone, Debug)]
+pub struct GltfNode {
+    /// The node's name, or "Node N" for unnamed nodes
+    pub name: String,
+    pub parent: Option<usize>,
+    /// Relative to the parent
+    pub transform: [[f32; 4]; 4],
 }
 
 #[derive(Clone, Debug)]
+pub struct GltfSkin {
+    /// Node index of each joint
+    pub joints: Vec<usize>,
+    /// Node name of each joint, or "joint N" for unnamed nodes
+    pub joint_names: Vec<String>,
+    /// Index (into `joints`) of each joint's nearest ancestor that is also a joint
+    pub joint_parents: Vec<Option<usize>>,
+    pub inverse_bind_matrices: Vec<[[f32; 4]; 4]>,
+    /// Joint matrices (global joint transform * inverse bind) for the node rest pose
+    pub rest_joint_matrices: Vec<[[f32; 4]; 4]>,
+}
+
+/// A glTF animation: keyframed channels, each driving one property of one node
+#[derive(Clone, Debug)]
+pub struct GltfAnimation {
+    /// The animation's name, or "Animation N" for unnamed ones
+    pub name: String,
+    pub channels: Vec<GltfChannel>,
+    /// Time of the last keyframe of any channel, in seconds
+    pub duration: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfChannel {
+    pub node: usize,
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds, ascending
+    pub times: Vec<f32>,
+    /// A value per keyframe, or an in-tangent, value and out-tangent per keyframe for
+    /// `Interpolation::CubicSpline`
+    pub values: ChannelValues,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    CubicSpline,
+}
+
+/// Keyframe values of the node property a channel drives
+#[derive(Clone, Debug)]
+pub enum ChannelValues {
+    Translations(Vec<[f32; 3]>),
+    Rotations(Vec<[f32; 4]>), // Quaternions, XYZW
+    Scales(Vec<[f32; 3]>),
+}
+
+impl ChannelValues {
+    fn len(&self) -> usize {
+        match self {
+            ChannelValues::Translations(values) | ChannelValues::Scales(values) => values.len(),
+            ChannelValues::Rotations(values) => values.len(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct GltfMaterial {
     pub base_color: [f32; 4],
     pub metallic: f32,
     pub roughness: f32,
     pub base_color_texture_index: Option<usize>,
+    pub normal_texture_index: Option<usize>,
+    pub emissive_factor: [f32; 3],
+    pub emissive_strength: f32, // KHR_materials_emissive_strength, 1.0 without it
+    pub emissive_texture_index: Option<usize>,
+    pub occlusion_texture_index: Option<usize>,
+    pub occlusion_strength: f32,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
 }
 
 impl Default for GltfMaterial {
@@ -32,6 +142,15 @@ impl Default for GltfMaterial {
             metallic: 0.0,
             roughness: 1.0,
             base_color_texture_index: None,
+            normal_texture_index: None,
+            emissive_factor: [0.0, 0.0, 0.0],
+            emissive_strength: 1.0,
+            emissive_texture_index: None,
+            occlusion_texture_index: None,
+            occlusion_strength: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
         }
     }
 }
@@ -40,7 +159,22 @@ impl Default for GltfMaterial {
 pub struct GltfTexture {
     pub width: u32,
     pub height: u32,
-    pub data: Vec<u8>,  // RGBA8
+    pub data: Vec<u8>,  // RGBA8, empty for a compressed texture
+    pub compressed: Option<CompressedTexture>,
+}
+
+impl GltfTexture {
+    /// Format of the texture's image
+    pub fn format(&self) -> ash::vk::Format {
+        self.compressed.as_ref().map_or(ash::vk::Format::R8G8B8A8_SRGB, |compressed| compressed.format)
+    }
+}
+
+/// Block-compressed mips of a KTX2 image (see ktx2.rs), uploaded as they are
+#[derive(Clone, Debug)]
+pub struct CompressedTexture {
+    pub format: ash::vk::Format,
+    pub mips: Vec<MipLevel>,
 }
 
 #[derive(Debug)]
@@ -48,82 +182,81 @@ pub struct GltfScene {
     pub meshes: Vec<GltfMesh>,
     pub materials: Vec<GltfMaterial>,
     pub textures: Vec<GltfTexture>,
+    pub skins: Vec<GltfSkin>,
+    pub nodes: Vec<GltfNode>,
+    pub animations: Vec<GltfAnimation>,
     /// Axis-aligned bounds (model space) across all mesh vertex positions.
     pub bounds_min: [f32; 3],
     pub bounds_max: [f32; 3],
+    /// Mip chains of `textures` once `texture_streaming::build_scene_mips` has run, else empty
+    pub mips: Vec<Vec<MipLevel>>,
 }
 
 impl GltfScene {
-    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+    /// Load the scene at `path`, reading KTX2 images only if `ktx2` (the device samples BC
+    /// formats). Without, textures with a KTX2 image use their PNG or JPEG fallback.
+    pub fn load<P: AsRef<Path>>(path: P, ktx2: bool) -> Result<Self, Box<dyn std::error::Error>> {
         let file = File::open(path.as_ref())?;
         let reader = BufReader::new(file);
-        let gltf = gltf::Gltf::from_reader(reader)?;
+        let mut gltf = gltf::Gltf::from_reader(reader)?;
         
         // Get the directory containing the gltf file for loading buffers
         let base_path = path.as_ref().parent().unwrap_or(Path::new(""));
         
-        // Load all buffer data
-        let mut buffer_data = Vec::new();
-        for buffer in gltf.buffers() {
-            match buffer.source() {
-                gltf::buffer::Source::Uri(uri) => {
-                    if uri.starts_with("data:") {
-                        return Err("Embedded data URIs not yet supported".into());
-                    } else {
-                        let buffer_path = base_path.join(uri);
-                        let data = std::fs::read(buffer_path)?;
-                        buffer_data.push(data);
-                    }
-                }
-                gltf::buffer::Source::Bin => {
-                    if let Some(blob) = gltf.blob.as_ref() {
-                        buffer_data.push(blob.clone());
-                    } else {
-                        return Err("Missing binary blob for GLB file".into());
-                    }
-                }
-            }
-        }
+        // Load all buffer data: files next to the glTF, base64 `data:` URIs, or the binary
+        // chunk of a GLB
+        let blob = gltf.blob.take();
+        let buffer_data: Vec<Vec<u8>> = gltf::import_buffers(&gltf, Some(base_path), blob)?
+            .into_iter()
+            .map(|data| data.0)
+            .collect();
         
-        // Load textures
+        // Load textures, one per image. A KTX2 image that can't be read is left white, and
+        // textures sampling it use their fallback source instead.
         let mut textures = Vec::new();
+        let mut readable = Vec::new();
         for image in gltf.images() {
-            match image.source() {
+            let encoded = match image.source() {
                 gltf::image::Source::Uri { uri, .. } => {
-                    if uri.starts_with("data:") {
-                        println!("  ⚠ Embedded texture data URIs not yet supported");
-                        continue;
+                    if !uri.starts_with("data:") {
+                        println!("  📷 Loading texture: {}", uri);
                     }
-                    let image_path = base_path.join(uri);
-                    println!("  📷 Loading texture: {}", uri);
-                    
-                    let img = image::open(&image_path)?;
-                    let rgba = img.to_rgba8();
-                    let (width, height) = rgba.dimensions();
-                    
-                    textures.push(GltfTexture {
-                        width,
-                        height,
-                        data: rgba.into_raw(),
-                    });
+                    // Read like a buffer: a (percent-encoded) path or a base64 data URI
+                    let source = gltf::buffer::Source::Uri(uri);
+                    gltf::buffer::Data::from_source(source, Some(base_path))?.0
                 }
                 gltf::image::Source::View { view, .. } => {
                     let buffer_idx = view.buffer().index();
                     let offset = view.offset();
                     let length = view.length();
-                    let data = &buffer_data[buffer_idx][offset..offset + length];
-                    
-                    let img = image::load_from_memory(data)?;
-                    let rgba = img.to_rgba8();
-                    let (width, height) = rgba.dimensions();
-                    
-                    textures.push(GltfTexture {
-                        width,
-                        height,
-                        data: rgba.into_raw(),
-                    });
+                    buffer_data[buffer_idx][offset..offset + length].to_vec()
                 }
+            };
+            
+            if ktx2::is_ktx2(&encoded) {
+                let read = if ktx2 { ktx2::read(&encoded) } else { Err("BC textures aren't supported".into()) };
+                match read {
+                    Ok(texture) => textures.push(texture),
+                    Err(e) => {
+                        println!("  ⚠ KTX2 image {}: {}", image.index(), e);
+                        textures.push(GltfTexture { width: 1, height: 1, data: vec![255; 4], compressed: None });
+                    }
+                }
+                readable.push(textures[image.index()].compressed.is_some());
+                continue;
             }
+            
+            let img = image::load_from_memory(&encoded)?;
+            let rgba = img.to_rgba8();
+            let (width, height) = rgba.dimensions();
+            
+            textures.push(GltfTexture {
+                width,
+                height,
+                data: rgba.into_raw(),
+                compressed: None,
+            });
+            readable.push(true);
         }
         
         // Load materials
@@ -134,16 +267,45 @@ impl GltfScene {
             let metallic = pbr.metallic_factor();
             let roughness = pbr.roughness_factor();
             
-            // Get texture index if available
-            let base_color_texture_index = pbr.base_color_texture().map(|info| {
-                info.texture().index()
+            // Index of the texture's image in `textures`, if available
+            let base_color_texture_index = pbr.base_color_texture().and_then(|info| {
+                texture_image(&info.texture(), &readable)
             });
             
+            let normal_texture_index = material.normal_texture().and_then(|info| {
+                texture_image(&info.texture(), &readable)
+            });
+            
+            let emissive_texture_index = material.emissive_texture().and_then(|info| {
+                texture_image(&info.texture(), &readable)
+            });
+            
+            let occlusion = material.occlusion_texture();
+            let occlusion_strength = occlusion.as_ref().map_or(1.0, |info| info.strength());
+            let occlusion_texture_index = occlusion.and_then(|info| {
+                texture_image(&info.texture(), &readable)
+            });
+            
+            let alpha_mode = match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            };
+            
             materials.push(GltfMaterial {
                 base_color,
                 metallic,
                 roughness,
                 base_color_texture_index,
+                normal_texture_index,
+                emissive_factor: material.emissive_factor(),
+                emissive_strength: material.emissive_strength().unwrap_or(1.0),
+                emissive_texture_index,
+                occlusion_texture_index,
+                occlusion_strength,
+                alpha_mode,
+                alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+                double_sided: material.double_sided(),
             });
         }
         
@@ -152,13 +314,105 @@ impl GltfScene {
             materials.push(GltfMaterial::default());
         }
         
-        // Load meshes
-        let mut meshes = Vec::new();
-
-        let mut bounds_min = [f32::INFINITY, f32::INFINITY, f32::INFINITY];
-        let mut bounds_max = [f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY];
+        // Load the node graph
+        let node_transforms = global_node_transforms(&gltf);
+        let mut node_parents = vec![None; gltf.nodes().len()];
+        for node in gltf.nodes() {
+            for child in node.children() {
+                node_parents[child.index()] = Some(node.index());
+            }
+        }
+        let nodes: Vec<GltfNode> = gltf
+            .nodes()
+            .map(|node| GltfNode {
+                name: node.name().map_or_else(|| format!("Node {}", node.index()), str::to_owned),
+                parent: node_parents[node.index()],
+                transform: node.transform().matrix(),
+            })
+            .collect();
+        
+        // Load skins, posed with each node's rest transform
+        let mut skins = Vec::new();
+        for skin in gltf.skins() {
+            let reader = skin.reader(|buffer| Some(&buffer_data[buffer.index()]));
+            let joints: Vec<usize> = skin.joints().map(|node| node.index()).collect();
+            let joint_names = skin
+                .joints()
+                .enumerate()
+                .map(|(i, node)| node.name().map_or_else(|| format!("joint {}", i), str::to_owned))
+                .collect();
+            let joint_parents = joints
+                .iter()
+                .map(|&joint| {
+                    let mut ancestor = node_parents[joint];
+                    while let Some(node) = ancestor {
+                        if let Some(index) = joints.iter().position(|&j| j == node) {
+                            return Some(index);
+                        }
+                        ancestor = node_parents[node];
+                    }
+                    None
+                })
+                .collect();
+            let inverse_bind_matrices: Vec<[[f32; 4]; 4]> = reader
+                .read_inverse_bind_matrices()
+                .map(|iter| iter.collect())
+                .unwrap_or_else(|| vec![glam::Mat4::IDENTITY.to_cols_array_2d(); joints.len()]);
+            let rest_joint_matrices = joints
+                .iter()
+                .zip(&inverse_bind_matrices)
+                .map(|(&joint, inverse_bind)| {
+                    (node_transforms[joint] * glam::Mat4::from_cols_array_2d(inverse_bind)).to_cols_array_2d()
+                })
+                .collect();
+            skins.push(GltfSkin {
+                joints,
+                joint_names,
+                joint_parents,
+                inverse_bind_matrices,
+                rest_joint_matrices,
+            });
+        }
+        
+        // Load animations. Channels animating morph target weights are skipped, and so are
+        // channels whose keyframe count doesn't match their values.
+        let mut animations = Vec::new();
+        for animation in gltf.animations() {
+            let mut channels = Vec::new();
+            for channel in animation.channels() {
+                let reader = channel.reader(|buffer| Some(&buffer_data[buffer.index()]));
+                let times: Vec<f32> = reader.read_inputs().map(|inputs| inputs.collect()).unwrap_or_default();
+                let values = match reader.read_outputs() {
+                    Some(ReadOutputs::Translations(values)) => ChannelValues::Translations(values.collect()),
+                    Some(ReadOutputs::Rotations(values)) => ChannelValues::Rotations(values.into_f32().collect()),
+                    Some(ReadOutputs::Scales(values)) => ChannelValues::Scales(values.collect()),
+                    Some(ReadOutputs::MorphTargetWeights(_)) | None => continue,
+                };
+                let interpolation = match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::Step => Interpolation::Step,
+                    gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                    gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                };
+                let values_per_key = if interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+                if times.is_empty() || values.len() != times.len() * values_per_key {
+                    continue;
+                }
+                channels.push(GltfChannel { node: channel.target().node().index(), interpolation, times, values });
+            }
+            let duration = channels.iter().filter_map(|channel| channel.times.last().copied()).fold(0.0, f32::max);
+            animations.push(GltfAnimation {
+                name: animation.name().map_or_else(|| format!("Animation {}", animation.index()), str::to_owned),
+                channels,
+                duration,
+            });
+        }
         
+        // Load the primitives of every glTF mesh, then place them below
+        let mut mesh_primitives: Vec<Vec<GltfMesh>> = Vec::new();
         for mesh in gltf.meshes() {
+            let mesh_name = mesh.name().map_or_else(|| format!("Mesh {}", mesh.index()), str::to_string);
+            let primitive_count = mesh.primitives().len();
+            let mut primitives = Vec::new();
             for primitive in mesh.primitives() {
                 let reader = primitive.reader(|buffer| Some(&buffer_data[buffer.index()]));
                 
@@ -168,16 +422,6 @@ impl GltfScene {
                     .map(|iter| iter.collect())
                     .unwrap_or_default();
 
-                // Update bounds
-                for p in &positions {
-                    bounds_min[0] = bounds_min[0].min(p[0]);
-                    bounds_min[1] = bounds_min[1].min(p[1]);
-                    bounds_min[2] = bounds_min[2].min(p[2]);
-                    bounds_max[0] = bounds_max[0].max(p[0]);
-                    bounds_max[1] = bounds_max[1].max(p[1]);
-                    bounds_max[2] = bounds_max[2].max(p[2]);
-                }
-                
                 // Read normals
                 let normals: Vec<[f32; 3]> = reader
                     .read_normals()
@@ -190,6 +434,13 @@ impl GltfScene {
                     .map(|coords| coords.into_f32().collect())
                     .unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
                 
+                // Lightmap UVs, if the asset was authored with them
+                let lightmap_uvs: Option<Vec<[f32; 2]>> = reader
+                    .read_tex_coords(1)
+                    .map(|coords| coords.into_f32().collect());
+                let has_lightmap_uvs = lightmap_uvs.is_some();
+                let lightmap_uvs = lightmap_uvs.unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
+                
                 // Read colors (if available)
                 let colors: Vec<[f32; 3]> = reader
                     .read_colors(0)
@@ -204,11 +455,13 @@ impl GltfScene {
                     .zip(normals.iter())
                     .zip(tex_coords.iter())
                     .zip(colors.iter())
-                    .map(|(((pos, norm), tex), col)| GltfVertex {
+                    .zip(lightmap_uvs.iter())
+                    .map(|((((pos, norm), tex), col), lightmap_uv)| GltfVertex {
                         position: *pos,
                         normal: *norm,
                         tex_coord: *tex,
                         color: *col,
+                        lightmap_uv: *lightmap_uv,
                     })
                     .collect();
                 
@@ -219,17 +472,100 @@ impl GltfScene {
                     .unwrap_or_else(|| (0..vertices.len() as u32).collect());
                 
                 let material_index = primitive.material().index();
+                let has_joints = primitive.get(&gltf::Semantic::Joints(0)).is_some()
+                    && primitive.get(&gltf::Semantic::Weights(0)).is_some();
+                
+                // Read skinning influences
+                let (joints, weights) = if has_joints {
+                    let joints: Vec<[u16; 4]> = reader
+                        .read_joints(0)
+                        .map(|joints| joints.into_u16().collect())
+                        .unwrap_or_default();
+                    let weights: Vec<[f32; 4]> = reader
+                        .read_weights(0)
+                        .map(|weights| weights.into_f32().collect())
+                        .unwrap_or_default();
+                    (joints, weights)
+                } else {
+                    (Vec::new(), Vec::new())
+                };
                 
-                meshes.push(GltfMesh {
+                let name = if primitive_count > 1 {
+                    format!("{} #{}", mesh_name, primitive.index())
+                } else {
+                    mesh_name.clone()
+                };
+                primitives.push(GltfMesh {
+                    name,
                     vertices,
                     indices,
                     material_index,
+                    has_lightmap_uvs,
+                    has_joints,
+                    joints,
+                    weights,
+                    skin_index: None,
+                    node: None,
+                    bounds_min: [0.0; 3],
+                    bounds_max: [0.0; 3],
                 });
             }
+            mesh_primitives.push(primitives);
+        }
+        
+        // One copy of a mesh's primitives per node instantiating it, in scene order. A file
+        // without scenes shows each mesh once, where it is.
+        let mut meshes = Vec::new();
+        match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+            Some(scene) => {
+                let mut instances = Vec::new();
+                let mut stack: Vec<gltf::Node> = scene.nodes().collect();
+                stack.reverse();
+                while let Some(node) = stack.pop() {
+                    if let Some(mesh) = node.mesh() {
+                        instances.push((node.index(), mesh.index(), node.skin().map(|skin| skin.index())));
+                    }
+                    stack.extend(node.children().collect::<Vec<_>>().into_iter().rev());
+                }
+                let mut uses = vec![0; mesh_primitives.len()];
+                for &(_, mesh, _) in &instances {
+                    uses[mesh] += 1;
+                }
+                for (node, mesh, skin_index) in instances {
+                    uses[mesh] -= 1;
+                    let primitives = if uses[mesh] == 0 {
+                        std::mem::take(&mut mesh_primitives[mesh])
+                    } else {
+                        mesh_primitives[mesh].clone()
+                    };
+                    for mut primitive in primitives {
+                        primitive.node = Some(node);
+                        primitive.skin_index = skin_index;
+                        if !primitive.has_joints || skin_index.is_none() {
+                            bake_transform(&mut primitive, node_transforms[node]);
+                        }
+                        meshes.push(primitive);
+                    }
+                }
+            }
+            None => meshes = mesh_primitives.into_iter().flatten().collect(),
+        }
+        
+        let mut bounds_min = [f32::INFINITY, f32::INFINITY, f32::INFINITY];
+        let mut bounds_max = [f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY];
+        for mesh in meshes.iter_mut().filter(|mesh| !mesh.vertices.is_empty()) {
+            let (min, max) = mesh.vertices.iter().fold(
+                (glam::Vec3::INFINITY, glam::Vec3::NEG_INFINITY),
+                |(min, max), vertex| (min.min(vertex.position.into()), max.max(vertex.position.into())),
+            );
+            mesh.bounds_min = min.into();
+            mesh.bounds_max = max.into();
+            bounds_min = min.min(bounds_min.into()).into();
+            bounds_max = max.max(bounds_max.into()).into();
         }
         
-        println!("  ✓ Loaded {} meshes, {} materials, {} textures", 
-                 meshes.len(), materials.len(), textures.len());
+        println!("  ✓ Loaded {} meshes, {} materials, {} textures, {} skins, {} animations", 
+                 meshes.len(), materials.len(), textures.len(), skins.len(), animations.len());
         
         // If the model had no positions, provide safe defaults.
         if !bounds_min[0].is_finite() {
@@ -241,8 +577,88 @@ impl GltfScene {
             meshes,
             materials,
             textures,
+            skins,
+            nodes,
+            animations,
             bounds_min,
             bounds_max,
+            mips: Vec::new(),
         })
     }
+
+    /// Per mesh, whether the animations move it: a static mesh whose node, or one of the
+    /// node's ancestors, is the target of a channel. Skinned meshes follow their skin instead.
+    pub fn animated_meshes(&self) -> Vec<bool> {
+        let targets: Vec<usize> = self
+            .animations
+            .iter()
+            .flat_map(|animation| animation.channels.iter().map(|channel| channel.node))
+            .collect();
+        self.meshes
+            .iter()
+            .map(|mesh| {
+                if mesh.has_joints && mesh.skin_index.is_some() {
+                    return false;
+                }
+                let mut node = mesh.node;
+                while let Some(index) = node {
+                    if targets.contains(&index) {
+                        return true;
+                    }
+                    node = self.nodes.get(index).and_then(|node| node.parent);
+                }
+                false
+            })
+            .collect()
+    }
+}
+
+/// Image `texture` samples: its `KHR_texture_basisu` KTX2 image if that could be read,
+/// else its plain `source`
+fn texture_image(texture: &gltf::Texture, readable: &[bool]) -> Option<usize> {
+    let is_readable = |index: &usize| readable.get(*index) == Some(&true);
+    let ktx2 = texture
+        .extension_value("KHR_texture_basisu")
+        .and_then(|basisu| basisu.get("source")?.as_u64())
+        .map(|index| index as usize);
+    ktx2.filter(is_readable)
+        .or_else(|| texture.source().map(|image| image.index()))
+        .filter(is_readable)
+}
+
+/// World transform of every node (indexed by node index), from the default scene's hierarchy
+fn global_node_transforms(gltf: &gltf::Gltf) -> Vec<glam::Mat4> {
+    fn visit(node: gltf::Node, parent: glam::Mat4, out: &mut [glam::Mat4]) {
+        let global = parent * glam::Mat4::from_cols_array_2d(&node.transform().matrix());
+        out[node.index()] = global;
+        for child in node.children() {
+            visit(child, global, out);
+        }
+    }
+    
+    let mut transforms = vec![glam::Mat4::IDENTITY; gltf.nodes().len()];
+    if let Some(scene) = gltf.default_scene().or_else(|| gltf.scenes().next()) {
+        for node in scene.nodes() {
+            visit(node, glam::Mat4::IDENTITY, &mut transforms);
+        }
+    }
+    transforms
+}
+
+/// Move `mesh` from its node's space into the scene's by the node's global `transform`.
+/// A mirroring transform turns the triangles inside out, so their winding is flipped back.
+fn bake_transform(mesh: &mut GltfMesh, transform: glam::Mat4) {
+    if transform == glam::Mat4::IDENTITY {
+        return;
+    }
+    let normal_matrix = glam::Mat3::from_mat4(transform).inverse().transpose();
+    for vertex in &mut mesh.vertices {
+        vertex.position = transform.transform_point3(vertex.position.into()).into();
+        vertex.normal = (normal_matrix * glam::Vec3::from(vertex.normal)).normalize_or_zero().into();
+    }
+    if transform.determinant() < 0.0 {
+        for triangle in mesh.indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
 }
@@ -1,13 +1,60 @@
 use ash::vk;
+use crate::acceleration_structure::{BlasGeometry, SceneAccelerationStructure};
+use crate::command_encoder::{CommandEncoder, PipelineBinding, RenderPassEncoder};
+use crate::defrag::MovableBuffer;
+use crate::draw_order;
+use crate::draw_stats;
+use crate::frustum::Frustum;
+use crate::frame_arena::ArenaSlice;
 use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
 use gpu_allocator::MemoryLocation;
 use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
-use crate::gltf_loader::GltfScene;
-use std::ffi::CString;
+use crate::sampler_cache::SamplerDesc;
+use crate::gltf_animation::GltfAnimationPlayer;
+use crate::gltf_loader::{AlphaMode, GltfMaterial, GltfMesh, GltfScene};
+use crate::gpu_driven::GpuDrivenPass;
+use crate::instance_culling::{InstanceCullPass, InstanceDraw};
+use crate::pipeline_statistics::PipelineStatistics;
+use crate::ground::{self, GroundStyle};
+use crate::hdr_image::{self, HdrPrecision};
+use crate::impostor::{self, ImpostorSource, Impostors};
+use crate::light_probes::{LightProbes, MAX_LIGHT_PROBES, SH_COEFFICIENTS};
+use crate::lightmap::{self, Lightmap};
+use crate::material::{MaterialHandle, MaterialOverride, MaterialRegistry};
+use crate::meshlets::{self, MeshletPass};
+use crate::pipeline_builder::{DepthMode, GraphicsPipelineBuilder, VertexLayout};
+use crate::planar_reflection;
+use crate::post_effects;
+use crate::primitives::MeshData;
+use crate::reflection_probes::{ReflectionProbes, MAX_REFLECTION_PROBES};
+use crate::render_layers::RenderLayers;
+use crate::render_target::{self, RenderTarget, RenderTargetDesc};
+use crate::shader_compiler::load_shader_permutation;
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+use crate::skinning::SkinningPass;
+use crate::skybox::Skybox;
+use crate::texture_array::{self, TextureArrays};
+use crate::texture_streaming::{self, MipLevel, TextureFilter, TextureStreamer};
+use crate::toon::{OutlinePass, ToonStyle};
+use crate::upload::{self, BufferUploader};
+use crate::virtual_texture::{self, VirtualTexture};
 use glam::{Mat4, Quat, Vec3};
+use std::collections::HashMap;
 
 const SHADOW_CASCADE_COUNT: usize = 4;
 const SHADOW_MAP_SIZE: u32 = 2048;
+// Shadow history texel: (shadow, ndc depth, ambient occlusion, unused)
+const SHADOW_HISTORY_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Edge of the square ground plane
+pub const GROUND_SIZE: f32 = 20.0;
+/// Clear color of the scene pass, which the faded ground blends into
+pub const SKY_COLOR: [f32; 3] = [0.53, 0.81, 0.92];
+/// Towards the sun, not normalized
+pub const SUN_DIRECTION: Vec3 = Vec3::new(0.5, 1.0, 0.3);
+/// Where the material preview sphere is drawn: far above the scene, so nothing in it
+/// shadows the sphere and no light probe reaches it
+pub const MATERIAL_PREVIEW_CENTER: Vec3 = Vec3::new(0.0, 500.0, 0.0);
 
 // Vertex format for glTF with tex coords
 #[repr(C)]
@@ -17,30 +64,61 @@ pub struct GltfVertex {
     pub color: [f32; 3],
     pub normal: [f32; 3],
     pub tex_coord: [f32; 2],
+    pub lightmap_uv: [f32; 2], // Lightmap atlas position, -1 when the mesh has none
+}
+
+impl VertexLayout for GltfVertex {
+    const ATTRIBUTES: &'static [(vk::Format, u32)] = &[
+        (vk::Format::R32G32B32_SFLOAT, std::mem::offset_of!(GltfVertex, pos) as u32),
+        (vk::Format::R32G32B32_SFLOAT, std::mem::offset_of!(GltfVertex, color) as u32),
+        (vk::Format::R32G32B32_SFLOAT, std::mem::offset_of!(GltfVertex, normal) as u32),
+        (vk::Format::R32G32_SFLOAT, std::mem::offset_of!(GltfVertex, tex_coord) as u32),
+        (vk::Format::R32G32_SFLOAT, std::mem::offset_of!(GltfVertex, lightmap_uv) as u32),
+    ];
 }
 
 pub struct GltfRenderer {
     pub meshes: Vec<GltfMeshBuffers>,
     pub ground: Option<GltfMeshBuffers>,
+    pub ground_style: GroundStyle, // Set with `set_ground_style`
+    pub preview_sphere: Option<GltfMeshBuffers>, // Unit sphere, see `render_material_preview`
+    pub skinning: Option<SkinningPass>, // Compute skinning of skinned meshes, if there are any
+    pub animation: Option<GltfAnimationPlayer>, // Plays the scene's animations, if it has any
+    pub acceleration_structure: Option<SceneAccelerationStructure>, // Meshes + ground, when ray queries are supported
+    pub meshlets: Option<MeshletPass>, // Task/mesh shader path for static meshes, when mesh shaders are supported
+    pub gpu_driven: Option<GpuDrivenPass>, // Culled indirect draws of static meshes, when meshlets are off
+    pub instance_culling: Option<InstanceCullPass>, // Culled indirect draws of the other instances
+    pub pipeline_statistics: Option<PipelineStatistics>, // Counted around the main scene pass, when supported
     pub texture: Option<TextureResources>,
-    pub pipeline: vk::Pipeline,
+    pub texture_streamer: Option<TextureStreamer>, // Mips of `texture`, without scene textures
+    pub virtual_texture: VirtualTexture, // Instead of `texture` and streaming for huge scene textures
+    pub texture_arrays: TextureArrays, // The other material textures, indexed per draw
+    pub texture_filter: TextureFilter, // How material textures are sampled, see `set_texture_filter`
+    pub materials: MaterialRegistry, // Also owns the scene pipelines
+    scene_materials: Vec<MaterialHandle>, // Per mesh, to return to after an override
+    hidden_meshes: Vec<bool>, // Per mesh, see `set_mesh_visible`
+    mesh_transforms: Vec<Mat4>, // Per mesh, under `duck_model`, see `animate`
+    animated_meshes: Vec<(usize, usize, Mat4)>, // Mesh, its node and the node's inverse rest transform
+    mesh_layers: Vec<RenderLayers>, // Per mesh, see `set_mesh_layers`
+    instances: Vec<ModelInstance>, // Besides the one at `duck_model`, see `set_instances`
+    instance_runs: Vec<InstanceRun>, // This frame's instanced draws, see `upload_instances`
+    instance_models: Option<ArenaSlice>, // Their model matrices, in the frame arena
+    impostors: HashMap<usize, Option<Impostors>>, // Per instanced mesh, None where its bake failed
+    pub impostor_distance: Option<f32>, // Instances farther from the camera are drawn as impostors
+    camera_position: Vec3, // Of the main view, which picks the instances drawn as impostors
+    pub layers: RenderLayers, // Of the main view
+    pub shader_variant: GltfShaderVariant,
     pub pipeline_layout: vk::PipelineLayout,
     pub descriptor_set_layout: vk::DescriptorSetLayout,
     pub descriptor_pool: vk::DescriptorPool,
     pub descriptor_sets: Vec<vk::DescriptorSet>,
-    pub uniform_buffers: Vec<vk::Buffer>,
-    pub uniform_allocations: Vec<Option<Allocation>>,
-    pub depth_images: Vec<vk::Image>,
-    pub depth_image_views: Vec<vk::ImageView>,
-    pub depth_allocations: Vec<Option<Allocation>>,
+    pub depth_targets: Vec<RenderTarget>, // One per swapchain image
     pub render_pass: vk::RenderPass,
+    pub color_format: vk::Format, // Of `render_pass`: the swapchain's, unless a `ScenePass` is swapped in
     pub framebuffers: Vec<vk::Framebuffer>,
 
     // Cascaded shadow maps (depth array)
-    pub shadow_image: vk::Image,
-    pub shadow_image_view: vk::ImageView, // 2D array view for sampling
-    pub shadow_layer_views: Vec<vk::ImageView>,
-    pub shadow_allocation: Option<Allocation>,
+    pub shadow_map: RenderTarget, // One layer per cascade
     pub shadow_sampler: vk::Sampler,
     pub shadow_depth_sampler: vk::Sampler,
 
@@ -49,32 +127,169 @@ pub struct GltfRenderer {
     pub scene_depth_sampler_nearest: vk::Sampler,  // Point sampling
 
     // Shadow history for shadow-specific TAA (per swapchain image, ping-pong)
-    pub shadow_history_images_a: Vec<vk::Image>,
-    pub shadow_history_views_a: Vec<vk::ImageView>,
-    pub shadow_history_allocations_a: Vec<Option<Allocation>>,
-    pub shadow_history_images_b: Vec<vk::Image>,
-    pub shadow_history_views_b: Vec<vk::ImageView>,
-    pub shadow_history_allocations_b: Vec<Option<Allocation>>,
+    pub shadow_history_a: Vec<RenderTarget>,
+    pub shadow_history_b: Vec<RenderTarget>,
     pub shadow_history_sampler: vk::Sampler,
     pub shadow_history_pingpong: Vec<u8>,
+    pub view_proj: Mat4, // Main camera, for GPU culling
+    pub light_view_proj: [Mat4; SHADOW_CASCADE_COUNT], // Main camera's cascades, for frustum gizmos
     pub prev_view_proj: Mat4,
     pub has_prev_view_proj: bool,
     pub shadow_frame_index: u32,
     pub shadow_render_pass: vk::RenderPass,
     pub shadow_framebuffers: Vec<vk::Framebuffer>,
     pub shadow_pipeline: vk::Pipeline,
+    pub shadow_instanced_pipeline: vk::Pipeline, // For the other instances, see `set_instances`
     pub shadow_pipeline_layout: vk::PipelineLayout,
 
     pub ground_model: Mat4,
     pub duck_model: Mat4,
+    pub model_bounds: (Vec3, Vec3), // Of the scene's meshes, in model space
+
+    pub light_probes: LightProbes, // Ambient light, see `light_probes`
+    pub reflection_probes: ReflectionProbes, // Local reflections, see `reflection_probes`
+    planar_reflection_placeholder: TextureResources, // Bound while there is no planar reflection or SSGI
+    pub planar_reflection_params: [f32; 4], // Of the main view, see `planar_reflection`
+    pub toon: ToonStyle, // Cel shading of every mesh and outlines, see `toon`
+    blue_noise: TextureResources, // Dithering thresholds, see `post_effects`
+    pub dither_params: [f32; 4], // See post_effects::dither_uniforms
+    pub weather_params: [f32; 4], // Wetness and snow cover, see `weather`
+    pub ssgi_params: [f32; 4], // Of the main view, see `ssgi`
+    outline: OutlinePass,
+    skybox: Option<Skybox>, // Environment image behind the scene, see `skybox`
+    pub lightmap: Lightmap, // Baked ambient light of static meshes, see `lightmap`
+}
+
+/// Debug visualization baked into the fragment shader (DEBUG_VIEW)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GltfDebugView {
+    Lit = 0,
+    Cascades = 1,
+}
+
+/// Quality/feature toggles compiled into the scene pipeline as specialization
+/// constants (constant_id 0..5 in gltf.frag). Each distinct variant gets its own
+/// pipeline, built the first time it is selected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GltfShaderVariant {
+    pub shadow_samples: u32,
+    pub use_pcss: bool,
+    pub light_count: u32,
+    pub debug_view: GltfDebugView,
+    /// Trace shadow rays instead of sampling the shadow maps
+    pub ray_query_shadows: bool,
+    /// Ray traced ambient occlusion rays per pixel, 0 = off
+    pub ao_rays: u32,
+}
+
+impl GltfShaderVariant {
+    /// Ray queries can't be compiled out by a specialization constant, so ray traced
+    /// effects select the `RAY_QUERY` permutation of every mesh instead.
+    pub fn uses_ray_queries(&self) -> bool {
+        self.ray_query_shadows || self.ao_rays > 0
+    }
+}
+
+impl Default for GltfShaderVariant {
+    fn default() -> Self {
+        Self {
+            shadow_samples: 16,
+            use_pcss: true,
+            light_count: 2,
+            debug_view: GltfDebugView::Lit,
+            ray_query_shadows: false,
+            ao_rays: 0,
+        }
+    }
+}
+
+/// Material features compiled into the glTF shaders as preprocessor defines, so
+/// meshes only pay for what their material uses. Every permutation in use gets its
+/// own pipeline per [`GltfShaderVariant`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GltfPermutation(u32);
+
+impl GltfPermutation {
+    pub const ALPHA_MASK: Self = Self(1 << 0);
+    /// Not a material feature: binds the scene TLAS for the ray traced effects of
+    /// [`GltfShaderVariant`] and is added to every mesh while any of them is on
+    pub const RAY_QUERY: Self = Self(1 << 1);
+    /// Not a material feature either: takes the model matrix from a per-instance vertex
+    /// buffer, for the instanced draws of the other instances (see `set_instances`)
+    pub const INSTANCED: Self = Self(1 << 2);
+
+    const DEFINES: [(Self, &'static str); 3] = [
+        (Self::ALPHA_MASK, "ALPHA_MASK"),
+        (Self::RAY_QUERY, "RAY_QUERY"),
+        (Self::INSTANCED, "INSTANCED"),
+    ];
+
+    /// Features of `material`
+    pub fn for_material(material: &GltfMaterial) -> Self {
+        if material.alpha_mode == AlphaMode::Mask {
+            Self::ALPHA_MASK
+        } else {
+            Self::default()
+        }
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Pipeline part of a draw sort key: alpha-tested permutations after all others, so
+    /// opaque surfaces lay down depth before the costlier masked ones
+    pub fn sort_rank(self) -> u16 {
+        let masked = if self.contains(Self::ALPHA_MASK) { 1 << 15 } else { 0 };
+        masked | self.0 as u16
+    }
+
+    pub fn defines(self) -> Vec<&'static str> {
+        Self::DEFINES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, define)| *define)
+            .collect()
+    }
+
+    /// Prebuilt SPIR-V of gltf.vert for this permutation (compiled by build.rs)
+    fn embedded_vert_spv(self) -> &'static [u8] {
+        if self.contains(Self::INSTANCED) {
+            include_bytes!("../shaders/gltf.vert.instanced.spv")
+        } else {
+            include_bytes!("../shaders/gltf.vert.spv")
+        }
+    }
+
+    /// Prebuilt SPIR-V for this permutation (compiled by build.rs)
+    fn embedded_frag_spv(self) -> &'static [u8] {
+        match (self.contains(Self::ALPHA_MASK), self.contains(Self::RAY_QUERY)) {
+            (true, true) => include_bytes!("../shaders/gltf.frag.alpha_mask.ray_query.spv"),
+            (true, false) => include_bytes!("../shaders/gltf.frag.alpha_mask.spv"),
+            (false, true) => include_bytes!("../shaders/gltf.frag.ray_query.spv"),
+            (false, false) => include_bytes!("../shaders/gltf.frag.spv"),
+        }
+    }
+}
+
+impl std::ops::BitOr for GltfPermutation {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
 }
 
+/// Pushed per draw, so every mesh and instance is drawn with its own model matrix; the
+/// uniform buffer only holds what the whole view shares
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct GltfPushConstants {
     pub model: [[f32; 4]; 4],
     pub use_texture: i32,
-    pub _pad: [i32; 3],
+    pub alpha_cutoff: f32,
+    pub object_id: u32, // Read by aov.frag only
+    pub texture_slot: u32, // Read by instanced draws only, others pass it as firstInstance
 }
 
 #[repr(C)]
@@ -103,6 +318,106 @@ pub struct GltfUniformBufferObject {
     pub shadow_bias: [f32; 4],
 
     pub prev_view_proj: [[f32; 4]; 4],
+
+    // Diffuse light probes
+    pub probe_positions: [[f32; 4]; MAX_LIGHT_PROBES], // xyz = position, w = radius
+    pub probe_irradiance: [[f32; 4]; MAX_LIGHT_PROBES * SH_COEFFICIENTS], // 9 SH coefficients per probe
+    pub probe_params: [f32; 4], // x = probe count, y = intensity
+
+    pub lightmap_params: [f32; 4], // x = 1 when the baked lightmap is in use
+    pub virtual_texture_params: [f32; 4], // See VirtualTexture::uniforms
+
+    // Reflection probes
+    pub reflection_probe_boxes: [[f32; 4]; MAX_REFLECTION_PROBES * 2], // Position + baked, half extents
+    pub reflection_probe_params: [f32; 4], // x = probe count, y = intensity, z = lowest mip
+
+    pub planar_reflection_params: [f32; 4], // See PlanarReflection::uniforms
+
+    pub toon_params: [f32; 4], // See ToonStyle::uniforms
+    pub dither_params: [f32; 4], // See post_effects::dither_uniforms
+    pub weather_params: [f32; 4], // See Weather::surface_uniforms
+    pub ssgi_params: [f32; 4], // See Ssgi::uniforms
+}
+
+/// How a camera maps the scene to the screen. `orthographic` blends from a perspective
+/// projection (0) to an orthographic one (1), so switching between them can be animated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Projection {
+    pub fov: f32,          // Vertical, in radians, for the perspective side
+    pub ortho_height: f32, // Meters the orthographic side shows from bottom to top
+    pub orthographic: f32,
+}
+
+impl Projection {
+    pub fn perspective(fov: f32) -> Self {
+        Self { fov, ortho_height: 1.0, orthographic: 0.0 }
+    }
+
+    /// Projection matrix, with Y flipped for Vulkan's clip space. In between the two
+    /// projections the matrices are blended, eased in and out.
+    pub fn matrix(&self, aspect_ratio: f32) -> Mat4 {
+        let perspective = Mat4::perspective_rh(self.fov, aspect_ratio, 0.1, 100.0);
+        let half_height = self.ortho_height * 0.5;
+        let half_width = half_height * aspect_ratio;
+        let orthographic = Mat4::orthographic_rh(-half_width, half_width, -half_height, half_height, 0.1, 100.0);
+        let t = self.orthographic.clamp(0.0, 1.0);
+        let t = t * t * (3.0 - 2.0 * t);
+        let mut proj = if t <= 0.0 {
+            perspective
+        } else if t >= 1.0 {
+            orthographic
+        } else {
+            perspective * (1.0 - t) + orthographic * t
+        };
+        proj.y_axis.y *= -1.0;
+        proj
+    }
+}
+
+/// Shadow options that are passed as uniforms, rather than baked into the pipelines
+#[derive(Clone, Copy, Default)]
+pub struct ShadowUniforms {
+    pub debug_cascades: bool,
+    pub softness: f32,
+    pub use_pcss: bool,
+    pub use_taa: bool,
+}
+
+/// View and projection matrices for one camera looking at the scene.
+#[derive(Clone, Copy)]
+pub struct ViewCamera {
+    pub position: Vec3,
+    pub view: Mat4,
+    pub proj: Mat4,
+}
+
+impl ViewCamera {
+    /// Build a camera from yaw/pitch angles (yaw 0 looks down +X, as in the app's camera controller).
+    pub fn from_yaw_pitch(position: Vec3, yaw: f32, pitch: f32, fov: f32, aspect_ratio: f32) -> Self {
+        Self::new(position, yaw, pitch, Projection::perspective(fov), aspect_ratio)
+    }
+
+    /// Like `from_yaw_pitch`, with any `projection`
+    pub fn new(position: Vec3, yaw: f32, pitch: f32, projection: Projection, aspect_ratio: f32) -> Self {
+        let front = Vec3::new(
+            yaw.cos() * pitch.cos(),
+            pitch.sin(),
+            yaw.sin() * pitch.cos(),
+        ).normalize();
+        
+        let view = Mat4::look_at_rh(position, position + front, Vec3::Y);
+
+        // Vulkan clip space has inverted Y compared to the typical math conventions used by
+        // many helper functions. The projection flips Y so "up" on input corresponds to "up"
+        // on screen.
+        let proj = projection.matrix(aspect_ratio);
+        
+        Self { position, view, proj }
+    }
+    
+    pub fn view_proj(&self) -> Mat4 {
+        self.proj * self.view
+    }
 }
 
 pub struct GltfMeshBuffers {
@@ -110,7 +425,36 @@ pub struct GltfMeshBuffers {
     pub vertex_allocation: Option<Allocation>,
     pub index_buffer: vk::Buffer,
     pub index_allocation: Option<Allocation>,
+    pub vertex_count: u32,
     pub index_count: u32,
+    pub permutation: GltfPermutation,
+    pub material: MaterialHandle,
+    pub center: Vec3, // Bounds center in model space, for depth sorting
+    pub bounds: (Vec3, Vec3), // Model space, for frustum culling
+}
+
+/// A placement of the scene's meshes besides the one at `GltfRenderer::duck_model`, see
+/// `GltfRenderer::set_instances`
+#[derive(Clone)]
+pub struct ModelInstance {
+    pub model: Mat4,
+    pub meshes: Vec<(usize, RenderLayers)>, // The instance's shown meshes, on their layers
+}
+
+/// An instanced draw of one mesh at the other instances showing it on `layers`, their
+/// `models` uploaded from `first` on in `GltfRenderer::instance_models`
+struct InstanceRun {
+    mesh: usize,
+    layers: RenderLayers,
+    first: u32,
+    models: Vec<Mat4>, // Kept for frustum culling
+}
+
+/// One mesh of one instance, see `GltfRenderer::mesh_draws`
+#[derive(Clone, Copy)]
+struct MeshDraw {
+    mesh: usize,
+    model: Mat4,
 }
 
 pub struct TextureResources {
@@ -118,31 +462,101 @@ pub struct TextureResources {
     pub image_view: vk::ImageView,
     pub sampler: vk::Sampler,
     pub allocation: Option<Allocation>,
+    pub mip_levels: u32,
+}
+
+impl TextureResources {
+    pub fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: self.image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        renderer.device.destroy_image_view(self.image_view, None);
+        renderer.device.destroy_image(self.image, None);
+        if let Some(allocation) = self.allocation.take() {
+            let _ = renderer.allocator.lock().free(allocation);
+        }
+    }
+}
+
+/// Per-view resources for rendering the scene from an additional camera.
+pub struct GltfView {
+    pub extent: vk::Extent2D,
+    pub descriptor_pool: vk::DescriptorPool,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub uniform_buffers: Vec<vk::Buffer>,
+    pub uniform_allocations: Vec<Option<Allocation>>,
+    // Shadow history targets: [0] = read (unused without TAA), [1] = storage write
+    pub history: Vec<RenderTarget>,
+    pub history_sampler: vk::Sampler,
+    pub view_proj: Mat4, // Set by update_view_uniform_buffer, for GPU culling
+    pub layers: RenderLayers, // Meshes on none of these are left out
+}
+
+/// Scene and meshlet pipelines built by a `GltfRenderer::scene_pipeline_builder` job
+pub struct ScenePipelines {
+    scene: HashMap<(GltfShaderVariant, GltfPermutation), vk::Pipeline>,
+    meshlets: HashMap<(GltfShaderVariant, GltfPermutation), vk::Pipeline>,
+}
+
+impl ScenePipelines {
+    unsafe fn destroy(self, device: &ash::Device) {
+        for pipeline in self.scene.into_values().chain(self.meshlets.into_values()) {
+            device.destroy_pipeline(pipeline, None);
+        }
+    }
+}
+
+/// A scene pass for another color format with every pipeline drawn in it, for captures
+/// into other targets than the swapchain's. Built by `create_scene_pass` and drawn with
+/// after `swap_scene_pass`.
+pub struct ScenePass {
+    render_pass: vk::RenderPass,
+    color_format: vk::Format,
+    pipelines: ScenePipelines,
+    outline: vk::Pipeline,
+    skybox: Option<vk::Pipeline>,
+    impostors: HashMap<usize, vk::Pipeline>,
+    dither_params: [f32; 4], // Zero: the noise is for 8-bit targets
+}
+
+impl ScenePass {
+    /// Destroy the pass and whichever pipelines it holds. Nothing may still be drawing with them.
+    pub unsafe fn destroy(self, device: &ash::Device) {
+        let pipelines = std::iter::once(self.outline).chain(self.skybox).chain(self.impostors.into_values());
+        for pipeline in pipelines {
+            device.destroy_pipeline(pipeline, None);
+        }
+        self.pipelines.destroy(device);
+        device.destroy_render_pass(self.render_pass, None);
+    }
 }
 
 impl GltfRenderer {
+    /// Create the renderer and upload `scene`. The scene pipelines are left to a
+    /// `scene_pipeline_builder` job; nothing may be drawn before `install_scene_pipelines`.
     pub unsafe fn new(
         renderer: &VulkanRenderer,
         scene: &GltfScene,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         // Create one depth buffer per swapchain image
         let depth_format = vk::Format::D32_SFLOAT;
-        let image_count = renderer.swapchain_image_views.len();
-        let mut depth_images = Vec::new();
-        let mut depth_image_views = Vec::new();
-        let mut depth_allocations = Vec::new();
-        
-        for _i in 0..image_count {
-            let (depth_image, depth_image_view, depth_allocation) = Self::create_depth_resources(
-                renderer,
-                renderer.swapchain_extent.width,
-                renderer.swapchain_extent.height,
+        let mut depth_targets = Vec::new();
+        render_target::resize_targets(
+            renderer,
+            &mut depth_targets,
+            renderer.swapchain_image_views.len(),
+            "depth_buffer",
+            RenderTargetDesc::depth(
                 depth_format,
-            )?;
-            depth_images.push(depth_image);
-            depth_image_views.push(depth_image_view);
-            depth_allocations.push(Some(depth_allocation));
-        }
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            ),
+            renderer.swapchain_extent,
+        )?;
         
         // Create render pass with depth attachment
         let render_pass = Self::create_render_pass(&renderer.device, renderer.swapchain_format, depth_format)?;
@@ -150,7 +564,7 @@ impl GltfRenderer {
         // Create framebuffers with depth attachment (one per swapchain image with its own depth)
         let mut framebuffers = Vec::new();
         for (i, &color_view) in renderer.swapchain_image_views.iter().enumerate() {
-            let attachments = [color_view, depth_image_views[i]];
+            let attachments = [color_view, depth_targets[i].depth().view];
             let framebuffer_info = vk::FramebufferCreateInfo::default()
                 .render_pass(render_pass)
                 .attachments(&attachments)
@@ -160,31 +574,47 @@ impl GltfRenderer {
             framebuffers.push(renderer.device.create_framebuffer(&framebuffer_info, None)?);
         }
         
-        // Load texture if available
-        let texture = if !scene.textures.is_empty() {
-            Some(Self::create_texture(renderer, &scene.textures[0])?)
-        } else {
+        // Load texture if available. The first one is bound on its own, the others go into
+        // `texture_arrays`; it starts out with its low mips and streams the rest in as the
+        // camera needs them. Textures too large for that are virtual, paged in by
+        // `virtual_texture` instead.
+        let virtual_source = scene.textures.first().filter(|texture| virtual_texture::is_virtual(texture));
+        let virtual_texture = VirtualTexture::new(renderer, virtual_source)?;
+        let texture_arrays = TextureArrays::new(renderer, scene)?;
+        let budget = texture_streaming::DEFAULT_BUDGET_MB as u64 * 1024 * 1024;
+        let texture_streamer = (!scene.textures.is_empty() && virtual_source.is_none())
+            .then(|| TextureStreamer::new(&scene.textures[..1], budget));
+        let texture = match &texture_streamer {
+            Some(streamer) => {
+                let streamed = &streamer.textures[0];
+                Some(Self::create_texture_with_format(renderer, streamed.mips_from(streamed.resident_base), streamed.format)?)
+            }
             // Create a white 1x1 fallback texture
-            Some(Self::create_fallback_texture(renderer)?)
+            None => Some(Self::create_fallback_texture(renderer)?),
         };
 
         // Create cascaded shadow map resources (depth array)
-        let (
-            shadow_image,
-            shadow_image_view,
-            shadow_layer_views,
-            shadow_allocation,
-            shadow_sampler,
-            shadow_depth_sampler,
-            scene_depth_sampler_linear,
-            scene_depth_sampler_nearest,
-        ) =
-            Self::create_shadow_resources(renderer, depth_format)?;
+        let shadow_map = RenderTarget::new(
+            renderer,
+            "shadow_map",
+            RenderTargetDesc::depth(
+                depth_format,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            )
+            .layers(SHADOW_CASCADE_COUNT as u32),
+            vk::Extent2D { width: SHADOW_MAP_SIZE, height: SHADOW_MAP_SIZE },
+        )?;
+        let (shadow_sampler, shadow_depth_sampler, scene_depth_sampler_linear, scene_depth_sampler_nearest) =
+            Self::create_shadow_samplers(renderer)?;
+        let lightmap = Lightmap::new(renderer)?;
+        let reflection_probes = ReflectionProbes::new(renderer)?;
+        let planar_reflection_placeholder = Self::create_fallback_texture(renderer)?;
+        let blue_noise = Self::create_blue_noise_texture(renderer)?;
 
         // Initialize the shadow image into a known layout so per-frame transitions are valid.
         Self::transition_depth_image_layout_array(
             renderer,
-            shadow_image,
+            shadow_map.depth().image,
             vk::ImageLayout::UNDEFINED,
             vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             SHADOW_CASCADE_COUNT as u32,
@@ -194,111 +624,80 @@ impl GltfRenderer {
         let shadow_framebuffers = Self::create_shadow_framebuffers(
             &renderer.device,
             shadow_render_pass,
-            &shadow_layer_views,
+            &shadow_map.depth().layer_views,
         )?;
 
         // Create shadow history resources for shadow-specific TAA
-        let (
-            shadow_history_images_a,
-            shadow_history_views_a,
-            shadow_history_allocations_a,
-            shadow_history_images_b,
-            shadow_history_views_b,
-            shadow_history_allocations_b,
-            shadow_history_sampler,
-            shadow_history_pingpong,
-        ) = Self::create_shadow_history_resources(
-            renderer,
-            renderer.swapchain_extent.width,
-            renderer.swapchain_extent.height,
-            renderer.swapchain_image_views.len(),
-        )?;
+        let image_count = renderer.swapchain_image_views.len();
+        let mut shadow_history_a = Vec::new();
+        let mut shadow_history_b = Vec::new();
+        Self::resize_history_targets(renderer, &mut shadow_history_a, image_count, renderer.swapchain_extent)?;
+        Self::resize_history_targets(renderer, &mut shadow_history_b, image_count, renderer.swapchain_extent)?;
+        let shadow_history_sampler = renderer.sampler(SamplerDesc::nearest(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
         
-        // Create descriptor set layout (UBO + albedo sampler + shadow compare sampler + shadow depth sampler + shadow history)
-        let ubo_binding = vk::DescriptorSetLayoutBinding::default()
-            .binding(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
-        
-        let sampler_binding = vk::DescriptorSetLayoutBinding::default()
-            .binding(1)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-
-        let shadow_binding = vk::DescriptorSetLayoutBinding::default()
-            .binding(2)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-
-        let shadow_depth_binding = vk::DescriptorSetLayoutBinding::default()
-            .binding(3)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-
-        let shadow_history_read_binding = vk::DescriptorSetLayoutBinding::default()
-            .binding(4)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-
-        let shadow_history_write_binding = vk::DescriptorSetLayoutBinding::default()
-            .binding(5)
-            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-
-        // Scene depth for contact shadow ray marching (Tiny Glade linear+point trick)
-        let scene_depth_linear_binding = vk::DescriptorSetLayoutBinding::default()
-            .binding(6)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-
-        let scene_depth_nearest_binding = vk::DescriptorSetLayoutBinding::default()
-            .binding(7)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-        
-        let bindings = [
-            ubo_binding,
-            sampler_binding,
-            shadow_binding,
-            shadow_depth_binding,
-            shadow_history_read_binding,
-            shadow_history_write_binding,
-            scene_depth_linear_binding,
-            scene_depth_nearest_binding,
-        ];
+        // Descriptor set layout and push constants are reflected from the shaders
+        // (UBO + albedo sampler + shadow compare sampler + shadow depth sampler + shadow history + scene depth)
+        let scene_vert = ShaderReflection::reflect(&load_shader("gltf.vert", include_bytes!("../shaders/gltf.vert.spv")))?;
+        let scene_frag = ShaderReflection::reflect(&load_shader("gltf.frag", include_bytes!("../shaders/gltf.frag.spv")))?;
+        let shadow_vert = ShaderReflection::reflect(&load_shader("shadow.vert", include_bytes!("../shaders/shadow.vert.spv")))?;
+        let shadow_frag = ShaderReflection::reflect(&load_shader("shadow.frag", include_bytes!("../shaders/shadow.frag.spv")))?;
+        let mut stages = vec![&scene_vert, &scene_frag, &shadow_vert, &shadow_frag];
+        
+        // The ray query permutation adds the TLAS binding, which only exists on devices
+        // that support it
+        let ray_query_frag = if renderer.ray_query_supported {
+            let code = load_shader_permutation(
+                "gltf.frag",
+                &GltfPermutation::RAY_QUERY.defines(),
+                GltfPermutation::RAY_QUERY.embedded_frag_spv(),
+            );
+            Some(ShaderReflection::reflect(&code)?)
+        } else {
+            None
+        };
+        stages.extend(ray_query_frag.as_ref());
+        
+        // Both pipelines share one set layout
+        let mut bindings = shader_reflection::set_layout_bindings(&stages, 0)?;
+        if renderer.mesh_shader_supported {
+            // The meshlet task/mesh shaders read the camera too (see meshlets.rs)
+            for binding in bindings.iter_mut().filter(|b| b.binding == 0) {
+                binding.stage_flags |= meshlets::MESHLET_STAGES;
+            }
+        }
         let layout_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
         let descriptor_set_layout = renderer.device.create_descriptor_set_layout(&layout_info, None)?;
         
+        // Material factors are set 1 (the shadow pass doesn't read them)
+        let materials = MaterialRegistry::new(renderer, scene, &texture_arrays, &[&scene_frag])?;
+        
         // Create pipeline layout
-        let push_constant_range = vk::PushConstantRange::default()
-            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
-            .offset(0)
-            .size(std::mem::size_of::<GltfPushConstants>() as u32);
-
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &[&scene_vert, &scene_frag],
+            std::mem::size_of::<GltfPushConstants>() as u32,
+        )?
+        .into_iter()
+        .collect();
+
+        let set_layouts = [descriptor_set_layout, materials.set_layout];
         let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
-            .set_layouts(std::slice::from_ref(&descriptor_set_layout))
-            .push_constant_ranges(std::slice::from_ref(&push_constant_range));
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
         let pipeline_layout = renderer.device.create_pipeline_layout(&pipeline_layout_info, None)?;
         
-        // Create pipeline
-        let pipeline = Self::create_pipeline(&renderer.device, render_pass, pipeline_layout)?;
+        // The scene pipelines come from `scene_pipeline_builder`
+        let shader_variant = GltfShaderVariant::default();
 
         // Create shadow pipeline layout + pipeline
-        let shadow_push_constant_range = vk::PushConstantRange::default()
-            .stage_flags(vk::ShaderStageFlags::VERTEX)
-            .offset(0)
-            .size(std::mem::size_of::<ShadowPushConstants>() as u32);
+        let shadow_push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &[&shadow_vert, &shadow_frag],
+            std::mem::size_of::<ShadowPushConstants>() as u32,
+        )?
+        .into_iter()
+        .collect();
         let shadow_pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
             .set_layouts(std::slice::from_ref(&descriptor_set_layout))
-            .push_constant_ranges(std::slice::from_ref(&shadow_push_constant_range));
+            .push_constant_ranges(&shadow_push_constant_ranges);
         let shadow_pipeline_layout = renderer
             .device
             .create_pipeline_layout(&shadow_pipeline_layout_info, None)?;
@@ -307,10 +706,14 @@ impl GltfRenderer {
             &renderer.device,
             shadow_render_pass,
             shadow_pipeline_layout,
+            false,
         )?;
+        let shadow_instanced_pipeline =
+            Self::create_shadow_pipeline(&renderer.device, shadow_render_pass, shadow_pipeline_layout, true)?;
+        let outline = OutlinePass::new(&renderer.device, render_pass, descriptor_set_layout)?;
         
         // Create descriptor pool
-        let pool_sizes = [
+        let mut pool_sizes = vec![
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::UNIFORM_BUFFER,
                 descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
@@ -318,26 +721,37 @@ impl GltfRenderer {
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
                 // binding=1 (albedo) + binding=2 (shadow compare) + binding=3 (shadow depth) + binding=4 (history read)
-                // + binding=6 (scene depth linear) + binding=7 (scene depth nearest)
-                descriptor_count: (MAX_FRAMES_IN_FLIGHT * 6) as u32,
+                // + binding=6 (scene depth linear) + binding=7 (scene depth nearest) + binding=9 (lightmap)
+                // + binding=10 (virtual texture page table) + binding=11 (virtual texture atlas)
+                // + binding=13 (texture arrays) + binding=14 (reflection probes) + binding=15 (planar reflection)
+                // + binding=16 (blue noise) + binding=17 (SSGI)
+                descriptor_count: (MAX_FRAMES_IN_FLIGHT * (13 + texture_array::MAX_TEXTURE_ARRAYS)) as u32,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::STORAGE_IMAGE,
                 // binding=5 (history write)
                 descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
             },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                // binding=12 (virtual texture feedback)
+                descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
+            },
         ];
+        if renderer.ray_query_supported {
+            // binding=8 (scene TLAS)
+            pool_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
+            });
+        }
         
         let pool_info = vk::DescriptorPoolCreateInfo::default()
             .pool_sizes(&pool_sizes)
             .max_sets(MAX_FRAMES_IN_FLIGHT as u32);
         let descriptor_pool = renderer.device.create_descriptor_pool(&pool_info, None)?;
         
-        // Create uniform buffers and descriptor sets
-        let mut uniform_buffers = Vec::new();
-        let mut uniform_allocations = Vec::new();
-        let ubo_size = std::mem::size_of::<GltfUniformBufferObject>() as u64;
-        
+        // Create descriptor sets; the uniforms come from the frame arena, see update_uniform_buffer
         let layouts = vec![descriptor_set_layout; MAX_FRAMES_IN_FLIGHT];
         let alloc_info = vk::DescriptorSetAllocateInfo::default()
             .descriptor_pool(descriptor_pool)
@@ -345,35 +759,6 @@ impl GltfRenderer {
         let descriptor_sets = renderer.device.allocate_descriptor_sets(&alloc_info)?;
         
         for i in 0..MAX_FRAMES_IN_FLIGHT {
-            // Create uniform buffer
-            let buffer_info = vk::BufferCreateInfo::default()
-                .size(ubo_size)
-                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE);
-            
-            let buffer = renderer.device.create_buffer(&buffer_info, None)?;
-            let requirements = renderer.device.get_buffer_memory_requirements(buffer);
-            
-            let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
-                name: &format!("glTF Uniform Buffer {}", i),
-                requirements,
-                location: MemoryLocation::CpuToGpu,
-                linear: true,
-                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-            })?;
-            
-            renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
-            
-            uniform_buffers.push(buffer);
-            uniform_allocations.push(Some(allocation));
-            
-            // Update descriptor sets
-            let buffer_info_desc = vk::DescriptorBufferInfo {
-                buffer,
-                offset: 0,
-                range: ubo_size,
-            };
-            
             let image_info = vk::DescriptorImageInfo {
                 sampler: texture.as_ref().unwrap().sampler,
                 image_view: texture.as_ref().unwrap().image_view,
@@ -382,47 +767,42 @@ impl GltfRenderer {
 
             let shadow_image_info = vk::DescriptorImageInfo {
                 sampler: shadow_sampler,
-                image_view: shadow_image_view,
+                image_view: shadow_map.depth().view,
                 image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             };
 
             let shadow_depth_image_info = vk::DescriptorImageInfo {
                 sampler: shadow_depth_sampler,
-                image_view: shadow_image_view,
+                image_view: shadow_map.depth().view,
                 image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             };
 
             // History bindings start at swapchain image 0 (updated per-frame in render())
             let history_read_image_info = vk::DescriptorImageInfo {
                 sampler: shadow_history_sampler,
-                image_view: shadow_history_views_a[0],
+                image_view: shadow_history_a[0].color().view,
                 image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             };
             let history_write_image_info = vk::DescriptorImageInfo {
                 sampler: vk::Sampler::null(),
-                image_view: shadow_history_views_b[0],
+                image_view: shadow_history_b[0].color().view,
                 image_layout: vk::ImageLayout::GENERAL,
             };
 
             // Scene depth for contact shadow ray marching (Tiny Glade linear+point trick)
-            // Use depth_image_views[0] as placeholder; updated per-frame in render()
+            // Use depth_targets[0] as placeholder; updated per-frame in render()
             let scene_depth_linear_info = vk::DescriptorImageInfo {
                 sampler: scene_depth_sampler_linear,
-                image_view: depth_image_views[0],
+                image_view: depth_targets[0].depth().view,
                 image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             };
             let scene_depth_nearest_info = vk::DescriptorImageInfo {
                 sampler: scene_depth_sampler_nearest,
-                image_view: depth_image_views[0],
+                image_view: depth_targets[0].depth().view,
                 image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             };
             
             let descriptor_writes = [
-                vk::WriteDescriptorSet::default()
-                    .dst_set(descriptor_sets[i])
-                    .dst_binding(0)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .buffer_info(std::slice::from_ref(&buffer_info_desc)),
                 vk::WriteDescriptorSet::default()
                     .dst_set(descriptor_sets[i])
                     .dst_binding(1)
@@ -462,520 +842,476 @@ impl GltfRenderer {
             
             renderer.device.update_descriptor_sets(&descriptor_writes, &[]);
         }
+        Self::write_lightmap_descriptors(&renderer.device, &lightmap, &descriptor_sets);
+        Self::write_reflection_probe_descriptors(&renderer.device, &reflection_probes, &descriptor_sets);
+        Self::write_image_descriptors(
+            &renderer.device,
+            15,
+            planar_reflection_placeholder.descriptor_info(),
+            &descriptor_sets,
+        );
+        Self::write_image_descriptors(&renderer.device, 16, blue_noise.descriptor_info(), &descriptor_sets);
+        Self::write_image_descriptors(
+            &renderer.device,
+            17,
+            planar_reflection_placeholder.descriptor_info(),
+            &descriptor_sets,
+        );
+        for (frame_index, &set) in descriptor_sets.iter().enumerate() {
+            virtual_texture.write_descriptors(&renderer.device, set, frame_index);
+            texture_arrays.write_descriptors(&renderer.device, set);
+        }
         
-        // Create mesh buffers
+        // Create mesh buffers, uploaded to device-local memory through staging buffers
         let mut meshes = Vec::new();
+        let mut uploader = BufferUploader::new();
         for gltf_mesh in &scene.meshes {
-            let vertices: Vec<GltfVertex> = gltf_mesh
-                .vertices
-                .iter()
-                .map(|v| {
-                    let color = if let Some(mat_idx) = gltf_mesh.material_index {
-                        if let Some(material) = scene.materials.get(mat_idx) {
-                            [material.base_color[0], material.base_color[1], material.base_color[2]]
-                        } else {
-                            v.color
-                        }
-                    } else {
-                        v.color
-                    };
-                    
-                    GltfVertex {
-                        pos: v.position,
-                        color,
-                        normal: v.normal,
-                        tex_coord: v.tex_coord,
-                    }
-                })
-                .collect();
+            let vertices = Self::mesh_vertices(gltf_mesh);
             
             let indices = &gltf_mesh.indices;
             
-            // Create vertex buffer
-            let vertex_buffer_size = (std::mem::size_of::<GltfVertex>() * vertices.len()) as u64;
-            
-            let vertex_buffer_info = vk::BufferCreateInfo::default()
-                .size(vertex_buffer_size)
-                .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE);
-            
-            let vertex_buffer = renderer.device.create_buffer(&vertex_buffer_info, None)?;
-            let vertex_requirements = renderer.device.get_buffer_memory_requirements(vertex_buffer);
-            
-            let vertex_allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
-                name: "gltf_vertex_buffer",
-                requirements: vertex_requirements,
-                location: MemoryLocation::CpuToGpu,
-                linear: true,
-                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-            })?;
-            
-            renderer.device.bind_buffer_memory(
-                vertex_buffer,
-                vertex_allocation.memory(),
-                vertex_allocation.offset(),
-            )?;
-            
-            let vertex_data_ptr = vertex_allocation.mapped_ptr().unwrap().as_ptr() as *mut GltfVertex;
-            std::ptr::copy_nonoverlapping(vertices.as_ptr(), vertex_data_ptr, vertices.len());
+            // Create vertex buffer (skinned meshes are also read by the skinning compute pass,
+            // static ones by the meshlet mesh shader)
+            let vertex_usage = if gltf_mesh.has_joints || renderer.mesh_shader_supported {
+                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER
+            } else {
+                vk::BufferUsageFlags::VERTEX_BUFFER
+            } | Self::acceleration_structure_input_usage(renderer);
+            let (vertex_buffer, vertex_allocation) =
+                uploader.upload(renderer, "gltf_vertex_buffer", &vertices, vertex_usage)?;
             
             // Create index buffer
-            let index_buffer_size = (std::mem::size_of::<u32>() * indices.len()) as u64;
-            
-            let index_buffer_info = vk::BufferCreateInfo::default()
-                .size(index_buffer_size)
-                .usage(vk::BufferUsageFlags::INDEX_BUFFER)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE);
-            
-            let index_buffer = renderer.device.create_buffer(&index_buffer_info, None)?;
-            let index_requirements = renderer.device.get_buffer_memory_requirements(index_buffer);
-            
-            let index_allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
-                name: "gltf_index_buffer",
-                requirements: index_requirements,
-                location: MemoryLocation::CpuToGpu,
-                linear: true,
-                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-            })?;
-            
-            renderer.device.bind_buffer_memory(
-                index_buffer,
-                index_allocation.memory(),
-                index_allocation.offset(),
-            )?;
-            
-            let index_data_ptr = index_allocation.mapped_ptr().unwrap().as_ptr() as *mut u32;
-            std::ptr::copy_nonoverlapping(indices.as_ptr(), index_data_ptr, indices.len());
+            let index_usage = vk::BufferUsageFlags::INDEX_BUFFER | Self::acceleration_structure_input_usage(renderer);
+            let (index_buffer, index_allocation) = uploader.upload(renderer, "gltf_index_buffer", indices, index_usage)?;
             
+            let material = materials.handle(gltf_mesh.material_index);
+            let bounds = (Vec3::from(gltf_mesh.bounds_min), Vec3::from(gltf_mesh.bounds_max));
             meshes.push(GltfMeshBuffers {
                 vertex_buffer,
                 vertex_allocation: Some(vertex_allocation),
                 index_buffer,
                 index_allocation: Some(index_allocation),
+                vertex_count: vertices.len() as u32,
                 index_count: indices.len() as u32,
+                permutation: materials.get(material).permutation,
+                material,
+                center: (bounds.0 + bounds.1) * 0.5,
+                bounds,
             });
         }
+        
+        uploader.finish(renderer)?;
 
-        // Create a simple ground plane
-        let ground = Some(Self::create_ground_plane(renderer)?);
+        let skinning = SkinningPass::new(renderer, scene, &meshes)?;
+        
+        let meshlets = MeshletPass::new(renderer, scene, &meshes, descriptor_set_layout, &materials, &scene_frag)?;
+        
+        let gpu_driven = GpuDrivenPass::new(renderer, scene, &meshes, &materials, &texture_arrays)?;
+        let instance_culling = InstanceCullPass::new(renderer)?;
+        let pipeline_statistics = PipelineStatistics::new(renderer)?;
         
+        let ground_style = GroundStyle::default();
+        let ground = Some(Self::create_ground_plane(renderer, &ground_style, lightmap::ground_tile(scene))?);
+        let preview_sphere = Some(Self::create_preview_sphere(renderer)?);
+        
+        // Ray traced shadows: one BLAS per mesh, then the ground (see tlas_transforms)
+        let blas_geometries: Vec<BlasGeometry> = meshes
+            .iter()
+            .chain(ground.as_ref())
+            .map(|buffers| BlasGeometry {
+                vertex_buffer: buffers.vertex_buffer,
+                vertex_count: buffers.vertex_count,
+                vertex_stride: std::mem::size_of::<GltfVertex>() as u64,
+                index_buffer: buffers.index_buffer,
+                index_count: buffers.index_count,
+            })
+            .collect();
+        let acceleration_structure = SceneAccelerationStructure::new(renderer, &blas_geometries)?;
+        if let Some(acceleration_structure) = &acceleration_structure {
+            Self::write_tlas_descriptors(&renderer.device, acceleration_structure, &descriptor_sets);
+        }
+        
+        // Static meshes are baked in the rest pose; animated ones move by their node's
+        // change from it
+        let animation = GltfAnimationPlayer::new(scene);
+        let rest = animation.as_ref().map(GltfAnimationPlayer::rest_transforms).unwrap_or_default();
+        let animated_meshes = scene
+            .animated_meshes()
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, animated)| animated)
+            .filter_map(|(i, _)| {
+                let node = scene.meshes[i].node?;
+                Some((i, node, rest.get(node)?.inverse()))
+            })
+            .collect();
+
         Ok(Self {
+            scene_materials: meshes.iter().map(|mesh| mesh.material).collect(),
+            hidden_meshes: vec![false; meshes.len()],
+            mesh_transforms: vec![Mat4::IDENTITY; meshes.len()],
+            animated_meshes,
+            mesh_layers: vec![RenderLayers::default(); meshes.len()],
+            instances: Vec::new(),
+            instance_runs: Vec::new(),
+            instance_models: None,
+            impostors: HashMap::new(),
+            impostor_distance: None,
+            camera_position: Vec3::ZERO,
+            layers: RenderLayers::default(),
             meshes,
             ground,
+            ground_style,
+            preview_sphere,
+            skinning,
+            animation,
+            acceleration_structure,
+            meshlets,
+            gpu_driven,
+            instance_culling: Some(instance_culling),
+            pipeline_statistics,
             texture,
-            pipeline,
+            texture_streamer,
+            virtual_texture,
+            texture_arrays,
+            texture_filter: TextureFilter::default(),
+            materials,
+            shader_variant,
             pipeline_layout,
             descriptor_set_layout,
             descriptor_pool,
             descriptor_sets,
-            uniform_buffers,
-            uniform_allocations,
-            depth_images,
-            depth_image_views,
-            depth_allocations,
+            depth_targets,
             render_pass,
+            color_format: renderer.swapchain_format,
             framebuffers,
 
-            shadow_image,
-            shadow_image_view,
-            shadow_layer_views,
-            shadow_allocation: Some(shadow_allocation),
+            shadow_map,
             shadow_sampler,
             shadow_depth_sampler,
 
             scene_depth_sampler_linear,
             scene_depth_sampler_nearest,
 
-            shadow_history_images_a,
-            shadow_history_views_a,
-            shadow_history_allocations_a,
-            shadow_history_images_b,
-            shadow_history_views_b,
-            shadow_history_allocations_b,
+            shadow_history_a,
+            shadow_history_b,
             shadow_history_sampler,
-            shadow_history_pingpong,
+            shadow_history_pingpong: vec![0; image_count],
+            view_proj: Mat4::IDENTITY,
+            light_view_proj: [Mat4::IDENTITY; SHADOW_CASCADE_COUNT],
             prev_view_proj: Mat4::IDENTITY,
             has_prev_view_proj: false,
             shadow_frame_index: 0,
             shadow_render_pass,
             shadow_framebuffers,
             shadow_pipeline,
+            shadow_instanced_pipeline,
             shadow_pipeline_layout,
 
             ground_model: Mat4::IDENTITY,
             duck_model: Mat4::IDENTITY,
+            model_bounds: (Vec3::from(scene.bounds_min), Vec3::from(scene.bounds_max)),
+
+            light_probes: LightProbes::default(),
+            reflection_probes,
+            planar_reflection_placeholder,
+            planar_reflection_params: [0.0; 4],
+            toon: ToonStyle::default(),
+            blue_noise,
+            dither_params: [0.0; 4],
+            weather_params: [0.0; 4],
+            ssgi_params: [0.0; 4],
+            outline,
+            skybox: None,
+            lightmap,
         })
     }
 
-    unsafe fn create_ground_plane(
-        renderer: &VulkanRenderer,
-    ) -> Result<GltfMeshBuffers, Box<dyn std::error::Error>> {
-        // Two triangles, centered at origin on Y=0
-        let size = 20.0_f32;
-        let half = size * 0.5;
-
-        let color = [0.35, 0.35, 0.35];
-        let up = [0.0, 1.0, 0.0];
-
-        let vertices = vec![
-            GltfVertex { pos: [-half, 0.0, -half], color, normal: up, tex_coord: [0.0, 0.0] },
-            GltfVertex { pos: [ half, 0.0, -half], color, normal: up, tex_coord: [10.0, 0.0] },
-            GltfVertex { pos: [ half, 0.0,  half], color, normal: up, tex_coord: [10.0, 10.0] },
-            GltfVertex { pos: [-half, 0.0,  half], color, normal: up, tex_coord: [0.0, 10.0] },
-        ];
+    /// GPU vertices of `mesh`, colored by its material's base color when it has one
+    pub fn mesh_vertices(mesh: &GltfMesh) -> Vec<GltfVertex> {
+        mesh.vertices
+            .iter()
+            .map(|v| GltfVertex {
+                pos: v.position,
+                color: v.color,
+                normal: v.normal,
+                tex_coord: v.tex_coord,
+                lightmap_uv: v.lightmap_uv,
+            })
+            .collect()
+    }
 
-        let indices: Vec<u32> = vec![0, 1, 2, 2, 3, 0];
+    /// The scene's model matrix with its origin at `position`, turned by `rotation` and
+    /// scaled by `scale`. The model is first turned around to face the default camera.
+    pub fn model_matrix(position: Vec3, rotation: Quat, scale: f32) -> Mat4 {
+        let duck_rotation = Quat::from_rotation_y(std::f32::consts::PI);
+        Mat4::from_scale_rotation_translation(Vec3::splat(scale), rotation * duck_rotation, position)
+    }
 
-        // Vertex buffer
-        let vertex_buffer_size = (std::mem::size_of::<GltfVertex>() * vertices.len()) as u64;
-        let vertex_buffer_info = vk::BufferCreateInfo::default()
-            .size(vertex_buffer_size)
-            .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
-        let vertex_buffer = renderer.device.create_buffer(&vertex_buffer_info, None)?;
-        let vertex_requirements = renderer.device.get_buffer_memory_requirements(vertex_buffer);
-        let vertex_allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
-            name: "ground_vertex_buffer",
-            requirements: vertex_requirements,
-            location: MemoryLocation::CpuToGpu,
-            linear: true,
-            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-        })?;
-        renderer
-            .device
-            .bind_buffer_memory(vertex_buffer, vertex_allocation.memory(), vertex_allocation.offset())?;
-        let vertex_data_ptr = vertex_allocation.mapped_ptr().unwrap().as_ptr() as *mut GltfVertex;
-        std::ptr::copy_nonoverlapping(vertices.as_ptr(), vertex_data_ptr, vertices.len());
-
-        // Index buffer
-        let index_buffer_size = (std::mem::size_of::<u32>() * indices.len()) as u64;
-        let index_buffer_info = vk::BufferCreateInfo::default()
-            .size(index_buffer_size)
-            .usage(vk::BufferUsageFlags::INDEX_BUFFER)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
-        let index_buffer = renderer.device.create_buffer(&index_buffer_info, None)?;
-        let index_requirements = renderer.device.get_buffer_memory_requirements(index_buffer);
-        let index_allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
-            name: "ground_index_buffer",
-            requirements: index_requirements,
-            location: MemoryLocation::CpuToGpu,
-            linear: true,
-            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-        })?;
-        renderer
-            .device
-            .bind_buffer_memory(index_buffer, index_allocation.memory(), index_allocation.offset())?;
-        let index_data_ptr = index_allocation.mapped_ptr().unwrap().as_ptr() as *mut u32;
-        std::ptr::copy_nonoverlapping(indices.as_ptr(), index_data_ptr, indices.len());
+    /// Bounding box of `vertices`
+    fn vertex_bounds(vertices: &[GltfVertex]) -> (Vec3, Vec3) {
+        let (min, max) = vertices.iter().fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), v| {
+            (min.min(Vec3::from(v.pos)), max.max(Vec3::from(v.pos)))
+        });
+        if vertices.is_empty() {
+            (Vec3::ZERO, Vec3::ZERO)
+        } else {
+            (min, max)
+        }
+    }
 
-        Ok(GltfMeshBuffers {
+    /// Extra usage for geometry buffers that acceleration structures are built from
+    fn acceleration_structure_input_usage(renderer: &VulkanRenderer) -> vk::BufferUsageFlags {
+        if renderer.ray_query_supported {
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+        } else {
+            vk::BufferUsageFlags::empty()
+        }
+    }
+
+    /// Point binding 8 of every set at the scene TLAS
+    unsafe fn write_tlas_descriptors(
+        device: &ash::Device,
+        acceleration_structure: &SceneAccelerationStructure,
+        descriptor_sets: &[vk::DescriptorSet],
+    ) {
+        let structures = [acceleration_structure.tlas.handle];
+        for &set in descriptor_sets {
+            let mut tlas_info = vk::WriteDescriptorSetAccelerationStructureKHR::default()
+                .acceleration_structures(&structures);
+            let write = vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(8)
+                .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+                .descriptor_count(1)
+                .push_next(&mut tlas_info);
+            device.update_descriptor_sets(&[write], &[]);
+        }
+    }
+
+    /// Point binding 9 of every set at the lightmap
+    unsafe fn write_lightmap_descriptors(
+        device: &ash::Device,
+        lightmap: &Lightmap,
+        descriptor_sets: &[vk::DescriptorSet],
+    ) {
+        let image_info = lightmap.descriptor_info();
+        for &set in descriptor_sets {
+            let write = vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(9)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(std::slice::from_ref(&image_info));
+            device.update_descriptor_sets(&[write], &[]);
+        }
+    }
+
+    /// Point binding 14 of every set at the reflection probe faces
+    unsafe fn write_reflection_probe_descriptors(
+        device: &ash::Device,
+        reflection_probes: &ReflectionProbes,
+        descriptor_sets: &[vk::DescriptorSet],
+    ) {
+        let image_info = reflection_probes.descriptor_info();
+        for &set in descriptor_sets {
+            let write = vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(14)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(std::slice::from_ref(&image_info));
+            device.update_descriptor_sets(&[write], &[]);
+        }
+    }
+
+    /// Point the sampled image at `binding` of every set at `image_info`
+    unsafe fn write_image_descriptors(
+        device: &ash::Device,
+        binding: u32,
+        image_info: vk::DescriptorImageInfo,
+        descriptor_sets: &[vk::DescriptorSet],
+    ) {
+        for &set in descriptor_sets {
+            let write = vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(binding)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(std::slice::from_ref(&image_info));
+            device.update_descriptor_sets(&[write], &[]);
+        }
+    }
+
+    /// Sample `source` as the main view's planar reflection, or the placeholder for `None`.
+    /// No frame in flight may be using the main descriptor sets.
+    pub unsafe fn set_planar_reflection_source(&self, device: &ash::Device, source: Option<vk::DescriptorImageInfo>) {
+        let image_info = source.unwrap_or_else(|| self.planar_reflection_placeholder.descriptor_info());
+        Self::write_image_descriptors(device, 15, image_info, &self.descriptor_sets);
+    }
+
+    /// Sample `source` as the main view's bounce light, or the placeholder for `None`. No
+    /// frame in flight may be using the main descriptor sets.
+    pub unsafe fn set_ssgi_source(&self, device: &ash::Device, source: Option<vk::DescriptorImageInfo>) {
+        let image_info = source.unwrap_or_else(|| self.planar_reflection_placeholder.descriptor_info());
+        Self::write_image_descriptors(device, 17, image_info, &self.descriptor_sets);
+    }
+
+    /// Instance transforms in BLAS order: every model mesh, then the ground. Hidden meshes
+    /// are scaled to nothing so rays miss them.
+    fn tlas_transforms(&self) -> Vec<Mat4> {
+        self.hidden_meshes
+            .iter()
+            .zip(&self.mesh_transforms)
+            .map(|(&hidden, &transform)| if hidden { Mat4::ZERO } else { self.duck_model * transform })
+            .chain(self.ground.as_ref().map(|_| self.ground_model))
+            .collect()
+    }
+
+    unsafe fn create_ground_plane(
+        renderer: &VulkanRenderer,
+        style: &GroundStyle,
+        lightmap_tile: lightmap::Tile,
+    ) -> Result<GltfMeshBuffers, Box<dyn std::error::Error>> {
+        let (vertices, indices) = ground::mesh(style, lightmap_tile);
+        Self::create_mesh_buffers(renderer, "ground", &vertices, &indices, false)
+    }
+
+    /// UV sphere of radius 1 around the origin
+    unsafe fn create_preview_sphere(renderer: &VulkanRenderer) -> Result<GltfMeshBuffers, Box<dyn std::error::Error>> {
+        let sphere = MeshData::sphere(1.0, 64, 32);
+        let vertices: Vec<GltfVertex> = sphere
+            .vertices
+            .iter()
+            .map(|vertex| GltfVertex {
+                pos: vertex.position,
+                color: [1.0, 1.0, 1.0],
+                normal: vertex.normal,
+                tex_coord: vertex.uv,
+                lightmap_uv: [-1.0, -1.0],
+            })
+            .collect();
+
+        Self::create_mesh_buffers(renderer, "preview_sphere", &vertices, &sphere.indices, true)
+    }
+
+    /// Vertex and index buffers of a mesh drawn with the default material, in device-local
+    /// memory or, for a mesh rewritten in place (the ground), host-visible
+    unsafe fn create_mesh_buffers(
+        renderer: &VulkanRenderer,
+        name: &str,
+        vertices: &[GltfVertex],
+        indices: &[u32],
+        device_local: bool,
+    ) -> Result<GltfMeshBuffers, Box<dyn std::error::Error>> {
+        let vertex_usage = vk::BufferUsageFlags::VERTEX_BUFFER | Self::acceleration_structure_input_usage(renderer);
+        let index_usage = vk::BufferUsageFlags::INDEX_BUFFER | Self::acceleration_structure_input_usage(renderer);
+        let vertex_name = format!("{}_vertex_buffer", name);
+        let index_name = format!("{}_index_buffer", name);
+
+        let ((vertex_buffer, vertex_allocation), (index_buffer, index_allocation)) = if device_local {
+            let mut uploader = BufferUploader::new();
+            let vertex = uploader.upload(renderer, &vertex_name, vertices, vertex_usage)?;
+            let index = uploader.upload(renderer, &index_name, indices, index_usage)?;
+            uploader.finish(renderer)?;
+            (vertex, index)
+        } else {
+            (
+                Self::create_host_buffer(renderer, &vertex_name, vertices, vertex_usage)?,
+                Self::create_host_buffer(renderer, &index_name, indices, index_usage)?,
+            )
+        };
+
+        let bounds = Self::vertex_bounds(vertices);
+        Ok(GltfMeshBuffers {
             vertex_buffer,
             vertex_allocation: Some(vertex_allocation),
             index_buffer,
             index_allocation: Some(index_allocation),
+            vertex_count: vertices.len() as u32,
             index_count: indices.len() as u32,
+            permutation: GltfPermutation::default(),
+            material: MaterialHandle::DEFAULT,
+            center: (bounds.0 + bounds.1) * 0.5,
+            bounds,
         })
     }
-    
-    unsafe fn create_depth_resources(
+
+    /// Host-visible buffer holding `data`
+    unsafe fn create_host_buffer<T: Copy>(
         renderer: &VulkanRenderer,
-        width: u32,
-        height: u32,
-        format: vk::Format,
-    ) -> Result<(vk::Image, vk::ImageView, Allocation), Box<dyn std::error::Error>> {
-        let image_info = vk::ImageCreateInfo::default()
-            .image_type(vk::ImageType::TYPE_2D)
-            .format(format)
-            .extent(vk::Extent3D { width, height, depth: 1 })
-            .mip_levels(1)
-            .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .initial_layout(vk::ImageLayout::UNDEFINED);
-        
-        let image = renderer.device.create_image(&image_info, None)?;
-        let requirements = renderer.device.get_image_memory_requirements(image);
-        
+        name: &str,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+    ) -> Result<(vk::Buffer, Allocation), Box<dyn std::error::Error>> {
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(std::mem::size_of_val(data) as u64)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = renderer.device.create_buffer(&buffer_info, None)?;
+        let requirements = renderer.device.get_buffer_memory_requirements(buffer);
         let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
-            name: "depth_buffer",
+            name,
             requirements,
-            location: MemoryLocation::GpuOnly,
-            linear: false,
+            location: MemoryLocation::CpuToGpu,
+            linear: true,
             allocation_scheme: AllocationScheme::GpuAllocatorManaged,
         })?;
-        
-        renderer.device.bind_image_memory(image, allocation.memory(), allocation.offset())?;
-        
-        let view_info = vk::ImageViewCreateInfo::default()
-            .image(image)
-            .view_type(vk::ImageViewType::TYPE_2D)
-            .format(format)
-            .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::DEPTH,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            });
-        
-        let image_view = renderer.device.create_image_view(&view_info, None)?;
-        
-        Ok((image, image_view, allocation))
+        renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+        let mapped = allocation.mapped_ptr().unwrap().as_ptr() as *mut T;
+        std::ptr::copy_nonoverlapping(data.as_ptr(), mapped, data.len());
+        Ok((buffer, allocation))
     }
-
-    unsafe fn create_shadow_resources(
+    
+    /// Shadow compare, shadow depth and scene depth (linear, nearest) samplers
+    unsafe fn create_shadow_samplers(
         renderer: &VulkanRenderer,
-        format: vk::Format,
-    ) -> Result<
-        (
-            vk::Image,
-            vk::ImageView,
-            Vec<vk::ImageView>,
-            Allocation,
-            vk::Sampler,
-            vk::Sampler,
-            vk::Sampler,  // scene_depth_linear
-            vk::Sampler,  // scene_depth_nearest
-        ),
-        Box<dyn std::error::Error>,
-    > {
-        let image_info = vk::ImageCreateInfo::default()
-            .image_type(vk::ImageType::TYPE_2D)
-            .format(format)
-            .extent(vk::Extent3D {
-                width: SHADOW_MAP_SIZE,
-                height: SHADOW_MAP_SIZE,
-                depth: 1,
-            })
-            .mip_levels(1)
-            .array_layers(SHADOW_CASCADE_COUNT as u32)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .initial_layout(vk::ImageLayout::UNDEFINED);
-
-        let image = renderer.device.create_image(&image_info, None)?;
-        let requirements = renderer.device.get_image_memory_requirements(image);
-
-        let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
-            name: "shadow_map",
-            requirements,
-            location: MemoryLocation::GpuOnly,
-            linear: false,
-            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-        })?;
-
-        renderer
-            .device
-            .bind_image_memory(image, allocation.memory(), allocation.offset())?;
-
-        let range = vk::ImageSubresourceRange {
-            aspect_mask: vk::ImageAspectFlags::DEPTH,
-            base_mip_level: 0,
-            level_count: 1,
-            base_array_layer: 0,
-            layer_count: SHADOW_CASCADE_COUNT as u32,
-        };
-
-        let array_view_info = vk::ImageViewCreateInfo::default()
-            .image(image)
-            .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
-            .format(format)
-            .subresource_range(range);
-        let array_view = renderer.device.create_image_view(&array_view_info, None)?;
-
-        let mut layer_views = Vec::with_capacity(SHADOW_CASCADE_COUNT);
-        for layer in 0..SHADOW_CASCADE_COUNT {
-            let layer_view_info = vk::ImageViewCreateInfo::default()
-                .image(image)
-                .view_type(vk::ImageViewType::TYPE_2D)
-                .format(format)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::DEPTH,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: layer as u32,
-                    layer_count: 1,
-                });
-            layer_views.push(renderer.device.create_image_view(&layer_view_info, None)?);
-        }
-
+    ) -> Result<(vk::Sampler, vk::Sampler, vk::Sampler, vk::Sampler), Box<dyn std::error::Error>> {
         // Shadow sampler with hardware comparison (fast PCF!)
-        let sampler_info = vk::SamplerCreateInfo::default()
-            .mag_filter(vk::Filter::LINEAR)
-            .min_filter(vk::Filter::LINEAR)
-            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
-            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
-            .compare_enable(true)
-            .compare_op(vk::CompareOp::LESS_OR_EQUAL)
-            .min_lod(0.0)
-            .max_lod(0.0);
-        let compare_sampler = renderer.device.create_sampler(&sampler_info, None)?;
+        let compare_sampler = renderer.sampler(
+            SamplerDesc::linear(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+                .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+                .compare(vk::CompareOp::LESS_OR_EQUAL),
+        )?;
 
         // Non-compare depth sampler for raw depth reads (needed for PCSS blocker search).
-        let depth_sampler_info = vk::SamplerCreateInfo::default()
-            .mag_filter(vk::Filter::NEAREST)
-            .min_filter(vk::Filter::NEAREST)
-            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
-            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
-            .compare_enable(false)
-            .min_lod(0.0)
-            .max_lod(0.0);
-        let depth_sampler = renderer.device.create_sampler(&depth_sampler_info, None)?;
+        let depth_sampler = renderer.sampler(
+            SamplerDesc::nearest(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+                .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE),
+        )?;
 
         // Scene depth samplers for contact shadow ray marching (Tiny Glade linear+point trick)
-        let scene_depth_linear_info = vk::SamplerCreateInfo::default()
-            .mag_filter(vk::Filter::LINEAR)
-            .min_filter(vk::Filter::LINEAR)
-            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
-            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-            .compare_enable(false)
-            .min_lod(0.0)
-            .max_lod(0.0);
-        let scene_depth_linear = renderer.device.create_sampler(&scene_depth_linear_info, None)?;
-
-        let scene_depth_nearest_info = vk::SamplerCreateInfo::default()
-            .mag_filter(vk::Filter::NEAREST)
-            .min_filter(vk::Filter::NEAREST)
-            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
-            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-            .compare_enable(false)
-            .min_lod(0.0)
-            .max_lod(0.0);
-        let scene_depth_nearest = renderer.device.create_sampler(&scene_depth_nearest_info, None)?;
-
-        Ok((image, array_view, layer_views, allocation, compare_sampler, depth_sampler, scene_depth_linear, scene_depth_nearest))
-    }
-
-    unsafe fn create_shadow_history_resources(
-        renderer: &VulkanRenderer,
-        width: u32,
-        height: u32,
-        count: usize,
-    ) -> Result<
-        (
-            Vec<vk::Image>,
-            Vec<vk::ImageView>,
-            Vec<Option<Allocation>>,
-            Vec<vk::Image>,
-            Vec<vk::ImageView>,
-            Vec<Option<Allocation>>,
-            vk::Sampler,
-            Vec<u8>,
-        ),
-        Box<dyn std::error::Error>,
-    > {
-        let mut images_a = Vec::with_capacity(count);
-        let mut views_a = Vec::with_capacity(count);
-        let mut allocs_a: Vec<Option<Allocation>> = Vec::with_capacity(count);
-        let mut images_b = Vec::with_capacity(count);
-        let mut views_b = Vec::with_capacity(count);
-        let mut allocs_b: Vec<Option<Allocation>> = Vec::with_capacity(count);
-
-        let image_info = vk::ImageCreateInfo::default()
-            .image_type(vk::ImageType::TYPE_2D)
-            .format(vk::Format::R16G16_SFLOAT)
-            .extent(vk::Extent3D { width, height, depth: 1 })
-            .mip_levels(1)
-            .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_DST)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let scene_depth_linear = renderer.sampler(SamplerDesc::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        let scene_depth_nearest = renderer.sampler(SamplerDesc::nearest(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
 
-        for _ in 0..count {
-            let (img_a, view_a, alloc_a) = Self::create_rg16f_image(renderer, &image_info)?;
-            images_a.push(img_a);
-            views_a.push(view_a);
-            allocs_a.push(Some(alloc_a));
-
-            let (img_b, view_b, alloc_b) = Self::create_rg16f_image(renderer, &image_info)?;
-            images_b.push(img_b);
-            views_b.push(view_b);
-            allocs_b.push(Some(alloc_b));
-        }
-
-        let sampler_info = vk::SamplerCreateInfo::default()
-            .mag_filter(vk::Filter::NEAREST)
-            .min_filter(vk::Filter::NEAREST)
-            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
-            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-            .compare_enable(false)
-            .min_lod(0.0)
-            .max_lod(0.0);
-        let sampler = renderer.device.create_sampler(&sampler_info, None)?;
-
-        // Initialize to fully lit (1.0) + far depth (1.0 in Vulkan NDC)
-        for img in images_a.iter().chain(images_b.iter()) {
-            Self::clear_rg16f_image(renderer, *img, 1.0, 1.0)?;
-        }
-
-        Ok((
-            images_a,
-            views_a,
-            allocs_a,
-            images_b,
-            views_b,
-            allocs_b,
-            sampler,
-            vec![0u8; count],
-        ))
+        Ok((compare_sampler, depth_sampler, scene_depth_linear, scene_depth_nearest))
     }
 
-    unsafe fn create_rg16f_image(
+    /// Make `targets` `count` shadow history targets of `extent`, cleared if any were
+    /// (re)created
+    unsafe fn resize_history_targets(
         renderer: &VulkanRenderer,
-        image_info: &vk::ImageCreateInfo,
-    ) -> Result<(vk::Image, vk::ImageView, Allocation), Box<dyn std::error::Error>> {
-        let image = renderer.device.create_image(image_info, None)?;
-        let requirements = renderer.device.get_image_memory_requirements(image);
-
-        let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
-            name: "shadow_history",
-            requirements,
-            location: MemoryLocation::GpuOnly,
-            linear: false,
-            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-        })?;
-        renderer
-            .device
-            .bind_image_memory(image, allocation.memory(), allocation.offset())?;
-
-        let view_info = vk::ImageViewCreateInfo::default()
-            .image(image)
-            .view_type(vk::ImageViewType::TYPE_2D)
-            .format(vk::Format::R16G16_SFLOAT)
-            .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            });
-        let view = renderer.device.create_image_view(&view_info, None)?;
-        Ok((image, view, allocation))
+        targets: &mut Vec<RenderTarget>,
+        count: usize,
+        extent: vk::Extent2D,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let desc = RenderTargetDesc::color(
+            SHADOW_HISTORY_FORMAT,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_DST,
+        );
+        if render_target::resize_targets(renderer, targets, count, "shadow_history", desc, extent)? {
+            // Initialize to fully lit (1.0) + far depth (1.0 in Vulkan NDC) + unoccluded (1.0)
+            for target in targets.iter() {
+                Self::clear_history_image(renderer, target.color().image, 1.0, 1.0, 1.0)?;
+            }
+        }
+        Ok(())
     }
 
-    unsafe fn clear_rg16f_image(
+    unsafe fn clear_history_image(
         renderer: &VulkanRenderer,
         image: vk::Image,
         shadow: f32,
         depth: f32,
+        ao: f32,
     ) -> Result<(), vk::Result> {
         let cmd_info = vk::CommandBufferAllocateInfo::default()
             .command_pool(renderer.command_pool)
@@ -1016,7 +1352,7 @@ impl GltfRenderer {
         );
 
         let clear = vk::ClearColorValue {
-            float32: [shadow, depth, 0.0, 0.0],
+            float32: [shadow, depth, ao, 0.0],
         };
         renderer.device.cmd_clear_color_image(
             cmd,
@@ -1114,121 +1450,33 @@ impl GltfRenderer {
             .collect()
     }
 
+    /// `instanced` takes the model matrix from a per-instance vertex buffer, as
+    /// [`GltfPermutation::INSTANCED`] does
     unsafe fn create_shadow_pipeline(
         device: &ash::Device,
         render_pass: vk::RenderPass,
         pipeline_layout: vk::PipelineLayout,
+        instanced: bool,
     ) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
-        let vert_code = include_bytes!("../shaders/shadow.vert.spv");
-        let frag_code = include_bytes!("../shaders/shadow.frag.spv");
-
-        let vert_module = Self::create_shader_module(device, vert_code)?;
-        let frag_module = Self::create_shader_module(device, frag_code)?;
-
-        let main_name = CString::new("main")?;
-
-        let shader_stages = [
-            vk::PipelineShaderStageCreateInfo::default()
-                .stage(vk::ShaderStageFlags::VERTEX)
-                .module(vert_module)
-                .name(&main_name),
-            vk::PipelineShaderStageCreateInfo::default()
-                .stage(vk::ShaderStageFlags::FRAGMENT)
-                .module(frag_module)
-                .name(&main_name),
-        ];
-
-        let binding = vk::VertexInputBindingDescription::default()
-            .binding(0)
-            .stride(std::mem::size_of::<GltfVertex>() as u32)
-            .input_rate(vk::VertexInputRate::VERTEX);
-
-        let attributes = [
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 0,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: 0,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 1,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: 12,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 2,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: 24,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 3,
-                format: vk::Format::R32G32_SFLOAT,
-                offset: 36,
-            },
-        ];
-
-        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
-            .vertex_binding_descriptions(std::slice::from_ref(&binding))
-            .vertex_attribute_descriptions(&attributes);
-
-        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
-
-        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
-            .viewport_count(1)
-            .scissor_count(1);
-
-        let dynamic_states = [
-            vk::DynamicState::VIEWPORT,
-            vk::DynamicState::SCISSOR,
-        ];
-        let dynamic_state = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+        let vert_code = if instanced {
+            load_shader_permutation("shadow.vert", &["INSTANCED"], include_bytes!("../shaders/shadow.vert.instanced.spv"))
+        } else {
+            load_shader("shadow.vert", include_bytes!("../shaders/shadow.vert.spv"))
+        };
+        let frag_code = load_shader("shadow.frag", include_bytes!("../shaders/shadow.frag.spv"));
 
         // No depth bias needed - using linear+point sampling trick instead
-        let rasterizer = vk::PipelineRasterizationStateCreateInfo::default()
-            .polygon_mode(vk::PolygonMode::FILL)
-            .line_width(1.0)
-            .cull_mode(vk::CullModeFlags::NONE)
-            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-            .depth_bias_enable(false);
-
-        let multisampling = vk::PipelineMultisampleStateCreateInfo::default()
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
-
-        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
-            .depth_test_enable(true)
-            .depth_write_enable(true)
-            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
-            .depth_bounds_test_enable(false)
-            .stencil_test_enable(false);
-
-        let color_blending = vk::PipelineColorBlendStateCreateInfo::default().attachments(&[]);
-
-        let pipeline_info = vk::GraphicsPipelineCreateInfo::default()
-            .stages(&shader_stages)
-            .vertex_input_state(&vertex_input)
-            .input_assembly_state(&input_assembly)
-            .viewport_state(&viewport_state)
-            .rasterization_state(&rasterizer)
-            .multisample_state(&multisampling)
-            .depth_stencil_state(&depth_stencil)
-            .color_blend_state(&color_blending)
-            .dynamic_state(&dynamic_state)
-            .layout(pipeline_layout)
-            .render_pass(render_pass)
-            .subpass(0);
-
-        let pipeline = device
-            .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
-            .map_err(|(_, e)| e)?[0];
-
-        device.destroy_shader_module(vert_module, None);
-        device.destroy_shader_module(frag_module, None);
-
-        Ok(pipeline)
+        let mut builder = GraphicsPipelineBuilder::new(pipeline_layout, render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+            .vertex_buffer::<GltfVertex>();
+        if instanced {
+            builder = builder.instance_buffer::<Mat4>();
+        }
+        builder
+            .color_targets(0)
+            .depth(DepthMode::ReadWrite(vk::CompareOp::LESS_OR_EQUAL))
+            .build(device)
     }
     
     unsafe fn create_render_pass(
@@ -1247,16 +1495,16 @@ impl GltfRenderer {
                 .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
                 .initial_layout(vk::ImageLayout::UNDEFINED)
                 .final_layout(vk::ImageLayout::PRESENT_SRC_KHR),
-            // Depth attachment
+            // Depth attachment, kept for screen-space passes over the finished frame (god rays)
             vk::AttachmentDescription::default()
                 .format(depth_format)
                 .samples(vk::SampleCountFlags::TYPE_1)
                 .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .store_op(vk::AttachmentStoreOp::STORE)
                 .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
                 .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
                 .initial_layout(vk::ImageLayout::UNDEFINED)
-                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
         ];
         
         let color_ref = vk::AttachmentReference {
@@ -1321,180 +1569,728 @@ impl GltfRenderer {
             .collect()
     }
     
-    unsafe fn create_pipeline(
-        device: &ash::Device,
-        render_pass: vk::RenderPass,
-        pipeline_layout: vk::PipelineLayout,
-    ) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
-        let vert_code = include_bytes!("../shaders/gltf.vert.spv");
-        let frag_code = include_bytes!("../shaders/gltf.frag.spv");
-        
-        let vert_module = Self::create_shader_module(device, vert_code)?;
-        let frag_module = Self::create_shader_module(device, frag_code)?;
-        
-        let main_name = CString::new("main")?;
-        
-        let shader_stages = [
-            vk::PipelineShaderStageCreateInfo::default()
-                .stage(vk::ShaderStageFlags::VERTEX)
-                .module(vert_module)
-                .name(&main_name),
-            vk::PipelineShaderStageCreateInfo::default()
-                .stage(vk::ShaderStageFlags::FRAGMENT)
-                .module(frag_module)
-                .name(&main_name),
-        ];
-        
-        // Vertex input - position, color, normal, texcoord
-        let binding = vk::VertexInputBindingDescription::default()
-            .binding(0)
-            .stride(std::mem::size_of::<GltfVertex>() as u32)
-            .input_rate(vk::VertexInputRate::VERTEX);
-        
-        let attributes = [
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 0,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: 0, // pos
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 1,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: 12, // color
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 2,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: 24, // normal
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 3,
-                format: vk::Format::R32G32_SFLOAT,
-                offset: 36, // tex_coord
-            },
-        ];
-        
-        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
-            .vertex_binding_descriptions(std::slice::from_ref(&binding))
-            .vertex_attribute_descriptions(&attributes);
-        
-        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
-        
-        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
-            .viewport_count(1)
-            .scissor_count(1);
-        
-        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-        let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
-            .dynamic_states(&dynamic_states);
-        
-        let rasterizer = vk::PipelineRasterizationStateCreateInfo::default()
-            .polygon_mode(vk::PolygonMode::FILL)
-            .line_width(1.0)
-            // Avoid 'see-through' artifacts from mismatched winding/handedness.
-            // Once the camera/projection conventions are fully standardized, this can be
-            // switched back to BACK with the correct front_face.
-            .cull_mode(vk::CullModeFlags::NONE)
-            .front_face(vk::FrontFace::COUNTER_CLOCKWISE);
-        
-        let multisampling = vk::PipelineMultisampleStateCreateInfo::default()
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
-        
-        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
-            .depth_test_enable(true)
-            .depth_write_enable(true)
-            .depth_compare_op(vk::CompareOp::LESS)
-            .depth_bounds_test_enable(false)
-            .stencil_test_enable(false);
-        
-        let color_blend_attachment = vk::PipelineColorBlendAttachmentState::default()
-            .color_write_mask(vk::ColorComponentFlags::RGBA)
-            .blend_enable(false);
-        
-        let color_blending = vk::PipelineColorBlendStateCreateInfo::default()
-            .attachments(std::slice::from_ref(&color_blend_attachment));
-        
-        let pipeline_info = vk::GraphicsPipelineCreateInfo::default()
-            .stages(&shader_stages)
-            .vertex_input_state(&vertex_input)
-            .input_assembly_state(&input_assembly)
-            .viewport_state(&viewport_state)
-            .rasterization_state(&rasterizer)
-            .multisample_state(&multisampling)
-            .depth_stencil_state(&depth_stencil)
-            .color_blend_state(&color_blending)
-            .dynamic_state(&dynamic_state)
-            .layout(pipeline_layout)
-            .render_pass(render_pass)
-            .subpass(0);
-        
-        let pipeline = device
-            .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
-            .map_err(|(_, e)| e)?[0];
-        
-        device.destroy_shader_module(vert_module, None);
-        device.destroy_shader_module(frag_module, None);
-        
-        Ok(pipeline)
+    /// Material factors mesh `mesh_index` is drawn with, and whether they are overridden
+    pub fn mesh_material(&self, mesh_index: usize) -> (MaterialOverride, bool) {
+        let material = self.meshes[mesh_index].material;
+        (MaterialOverride::of(self.materials.get(material)), self.materials.is_override(material))
     }
-    
-    unsafe fn create_shader_module(
-        device: &ash::Device,
-        code: &[u8],
-    ) -> Result<vk::ShaderModule, vk::Result> {
-        let code_u32: Vec<u32> = code
-            .chunks_exact(4)
-            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
-            .collect();
-        let create_info = vk::ShaderModuleCreateInfo::default().code(&code_u32);
-        device.create_shader_module(&create_info, None)
+
+    /// Index of mesh `mesh_index`'s material in the glTF file, None for the default material
+    pub fn scene_material_index(&self, mesh_index: usize) -> Option<usize> {
+        self.scene_materials[mesh_index].index().checked_sub(1)
     }
-    
-    unsafe fn create_texture(
+
+    /// Repaint the ground in `style`. Its vertex colors are rewritten in place, so the GPU
+    /// must be done with earlier frames.
+    pub unsafe fn set_ground_style(&mut self, style: GroundStyle) {
+        self.ground_style = style;
+        let Some(ground) = &mut self.ground else {
+            return;
+        };
+        if let Some(ptr) = ground.vertex_allocation.as_mut().and_then(|a| a.mapped_ptr()) {
+            let vertices = std::slice::from_raw_parts_mut(ptr.as_ptr() as *mut GltfVertex, ground.vertex_count as usize);
+            ground::paint(vertices, &style);
+        }
+    }
+
+    /// The ground, unless the style hides it
+    fn visible_ground(&self) -> Option<&GltfMeshBuffers> {
+        self.ground.as_ref().filter(|_| self.ground_style.visible)
+    }
+
+    /// Advance the animation playing by `delta` seconds, pose the skinned meshes and move
+    /// the static meshes under animated nodes
+    pub fn animate(&mut self, delta: f32) {
+        let Some(animation) = &mut self.animation else {
+            return;
+        };
+        animation.advance(delta);
+        let node_transforms = animation.node_transforms();
+        if let Some(skinning) = &mut self.skinning {
+            skinning.set_pose(&node_transforms);
+        }
+        for &(mesh_index, node, rest_inverse) in &self.animated_meshes {
+            self.mesh_transforms[mesh_index] = node_transforms[node] * rest_inverse;
+        }
+    }
+
+    /// Model matrix of mesh `mesh_index`: the model's, moved by the mesh's node animation
+    pub fn mesh_model(&self, mesh_index: usize) -> Mat4 {
+        self.duck_model * self.mesh_transforms[mesh_index]
+    }
+
+    /// Draw mesh `mesh_index` with its scene material edited by `material_override`, or
+    /// again with the unedited scene material for `None`. An overridden mesh leaves the
+    /// GPU-driven draws, whose buckets share one material.
+    pub fn set_material_override(&mut self, mesh_index: usize, material_override: Option<MaterialOverride>) {
+        let scene_material = self.scene_materials[mesh_index];
+        self.meshes[mesh_index].material = match &material_override {
+            Some(material_override) => self.materials.set_override(mesh_index, scene_material, material_override),
+            None => scene_material,
+        };
+        self.update_gpu_driven_exclusion(mesh_index);
+    }
+
+    /// Show or hide mesh `mesh_index` in every pass. A hidden mesh leaves the GPU-driven
+    /// draws like an overridden one, and the direct draws skip it.
+    pub fn set_mesh_visible(&mut self, mesh_index: usize, visible: bool) {
+        if self.hidden_meshes[mesh_index] != visible {
+            return;
+        }
+        self.hidden_meshes[mesh_index] = !visible;
+        self.update_gpu_driven_exclusion(mesh_index);
+    }
+
+    /// Put mesh `mesh_index` on `layers`, drawing it only in views that show one of them
+    /// (see `render_layers`). The GPU-driven draws are shared by every view, so a mesh off
+    /// the default layers leaves them and is drawn directly where it is seen.
+    pub fn set_mesh_layers(&mut self, mesh_index: usize, layers: RenderLayers) {
+        if self.mesh_layers[mesh_index] == layers {
+            return;
+        }
+        self.mesh_layers[mesh_index] = layers;
+        self.update_gpu_driven_exclusion(mesh_index);
+    }
+
+    /// Place the scene's meshes again at each of `instances`, besides at `duck_model`. Each
+    /// mesh is drawn once for all of them, hardware instanced (see `upload_instances`), and
+    /// they stay out of the ray traced shadows and the lightmap. Builds the instanced
+    /// pipelines on first use, and with `impostor_distance` set bakes the impostors of
+    /// meshes newly shown by an instance.
+    pub unsafe fn set_instances(
+        &mut self,
         renderer: &VulkanRenderer,
-        tex: &crate::gltf_loader::GltfTexture,
-    ) -> Result<TextureResources, Box<dyn std::error::Error>> {
-        let (width, height) = (tex.width, tex.height);
-        let data = &tex.data;
-        
-        // Create staging buffer
-        let buffer_size = (width * height * 4) as u64;
-        let staging_buffer_info = vk::BufferCreateInfo::default()
-            .size(buffer_size)
-            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
-        
-        let staging_buffer = renderer.device.create_buffer(&staging_buffer_info, None)?;
-        let staging_reqs = renderer.device.get_buffer_memory_requirements(staging_buffer);
-        
-        let staging_allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
-            name: "texture_staging",
-            requirements: staging_reqs,
-            location: MemoryLocation::CpuToGpu,
-            linear: true,
-            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-        })?;
-        
-        renderer.device.bind_buffer_memory(
-            staging_buffer,
-            staging_allocation.memory(),
-            staging_allocation.offset(),
-        )?;
-        
-        let ptr = staging_allocation.mapped_ptr().unwrap().as_ptr() as *mut u8;
-        std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
-        
+        instances: Vec<ModelInstance>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.instances = instances;
+        if self.impostor_distance.is_some() {
+            self.bake_impostors(renderer);
+        }
+        if !self.instances.is_empty() {
+            Self::create_permutation_pipelines(
+                &renderer.device,
+                self.render_pass,
+                self.pipeline_layout,
+                self.shader_variant,
+                &self.meshes,
+                &mut self.materials,
+                true,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Bake an impostor atlas for each mesh the other instances show that has none yet.
+    /// Skinned meshes move away from any bake and get none, and so do textured ones, whose
+    /// texture the bake can't show.
+    unsafe fn bake_impostors(&mut self, renderer: &VulkanRenderer) {
+        let shown: Vec<usize> =
+            self.instances.iter().flat_map(|instance| instance.meshes.iter().map(|&(mesh, _)| mesh)).collect();
+        for mesh_index in shown {
+            if self.impostors.contains_key(&mesh_index) || self.skinned(mesh_index) {
+                continue;
+            }
+            let mesh = &self.meshes[mesh_index];
+            // The bake only has vertex colors, which would stand in for the texture
+            if self.materials.get(mesh.material).base_color_texture.is_some() {
+                continue;
+            }
+            let (min, max) = mesh.bounds;
+            let source = ImpostorSource {
+                vertex_buffer: mesh.vertex_buffer,
+                index_buffer: mesh.index_buffer,
+                index_type: vk::IndexType::UINT32,
+                index_count: mesh.index_count,
+                radius: min.abs().max(max.abs()).length(), // Farthest corner of the bounds
+            };
+            let impostors = Impostors::new::<GltfVertex>(renderer, self.render_pass, &source)
+                .inspect_err(|e| eprintln!("✗ Failed to bake impostors of mesh {}: {}", mesh_index, e))
+                .ok();
+            self.impostors.insert(mesh_index, impostors);
+        }
+    }
+
+    /// Instances drawn as impostors last frame
+    pub fn impostor_count(&self) -> u32 {
+        self.impostors.values().flatten().map(|impostors| impostors.instance_count).sum()
+    }
+
+    /// Upload the model matrices of the other instances to the frame arena, in one run per
+    /// mesh and layers, each drawn with a single instanced call, and set the runs up for
+    /// GPU culling. Instances of baked meshes beyond `impostor_distance` on the default
+    /// layers go to the impostors instead, and so cast no shadows.
+    unsafe fn upload_instances(
+        &mut self,
+        renderer: &VulkanRenderer,
+        frame_index: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.instance_runs.clear();
+        self.instance_models = None;
+        let mut runs: Vec<(usize, RenderLayers, Vec<Mat4>)> = Vec::new();
+        let mut run_of: HashMap<(usize, RenderLayers), usize> = HashMap::new();
+        for instance in &self.instances {
+            for &(mesh, layers) in &instance.meshes {
+                let run = *run_of.entry((mesh, layers)).or_insert_with(|| {
+                    runs.push((mesh, layers, Vec::new()));
+                    runs.len() - 1
+                });
+                runs[run].2.push(instance.model * self.mesh_transforms[mesh]);
+            }
+        }
+
+        let mut far_models: HashMap<usize, Vec<Mat4>> = HashMap::new();
+        let mut models = Vec::new();
+        for (mesh, layers, mut run_models) in runs {
+            let baked = self.impostors.get(&mesh).is_some_and(Option::is_some);
+            if let Some(distance) = self.impostor_distance.filter(|_| baked && layers == RenderLayers::default()) {
+                let (near, far) = impostor::split_by_distance(&run_models, self.camera_position, distance);
+                far_models.entry(mesh).or_default().extend(far);
+                run_models = near;
+            }
+            if run_models.is_empty() {
+                continue;
+            }
+            let first = models.len() as u32;
+            models.extend_from_slice(&run_models);
+            self.instance_runs.push(InstanceRun { mesh, layers, first, models: run_models });
+        }
+        if !models.is_empty() {
+            self.instance_models = Some(renderer.frame_arena.push(&models).ok_or("frame arena allocation failed")?);
+        }
+        for (mesh, impostors) in &mut self.impostors {
+            if let Some(impostors) = impostors {
+                let far = far_models.get(mesh).map_or(&[][..], Vec::as_slice);
+                impostors.update_instances(renderer, frame_index, far)?;
+            }
+        }
+
+        let draws: Vec<InstanceDraw> = self
+            .instance_runs
+            .iter()
+            .map(|run| {
+                let bounds = Some(self.meshes[run.mesh].bounds).filter(|_| !self.skinned(run.mesh));
+                InstanceDraw::new(bounds, self.meshes[run.mesh].index_count, run.first, run.models.len() as u32)
+            })
+            .collect();
+        if let Some(instance_culling) = &mut self.instance_culling {
+            instance_culling.prepare(renderer, &draws, self.instance_models)?;
+        }
+        Ok(())
+    }
+
+    /// Vertex buffers of an instanced draw of `run` from its instance `first` on: the mesh's
+    /// vertices, then the instances' model matrices
+    fn instance_vertex_buffers(&self, run: &InstanceRun, models: ArenaSlice, first: u32) -> ([vk::Buffer; 2], [vk::DeviceSize; 2]) {
+        let offset = models.offset + (run.first + first) as vk::DeviceSize * std::mem::size_of::<Mat4>() as vk::DeviceSize;
+        ([self.mesh_vertex_buffer(run.mesh), models.buffer], [0, offset])
+    }
+
+    /// Whether mesh `mesh` is posed by the skinning pass, away from the bounds it was loaded with
+    fn skinned(&self, mesh: usize) -> bool {
+        self.skinning.as_ref().is_some_and(|skinning| skinning.vertex_buffer(mesh).is_some())
+    }
+
+    /// Whether mesh `mesh` placed by `model` may be seen through `frustum`. Skinned meshes
+    /// always may.
+    fn in_frustum(&self, frustum: &Frustum, mesh: usize, model: &Mat4) -> bool {
+        self.skinned(mesh) || frustum.contains_box(model, self.meshes[mesh].bounds)
+    }
+
+    /// Ranges of `run`'s instances that may be seen through `frustum`, each drawn with one
+    /// instanced call. The instances in between count as culled.
+    fn visible_instances(&self, frustum: &Frustum, run: &InstanceRun) -> Vec<std::ops::Range<u32>> {
+        let mut ranges: Vec<std::ops::Range<u32>> = Vec::new();
+        for (index, model) in (0..).zip(&run.models) {
+            if !self.in_frustum(frustum, run.mesh, model) {
+                draw_stats::culled(1);
+            } else if let Some(range) = ranges.last_mut().filter(|range| range.end == index) {
+                range.end += 1;
+            } else {
+                ranges.push(index..index + 1);
+            }
+        }
+        ranges
+    }
+
+    /// Leave mesh `mesh_index` out of the GPU-driven draws while it needs drawing on its
+    /// own: with an overridden material, hidden, or on other than the default layers
+    fn update_gpu_driven_exclusion(&mut self, mesh_index: usize) {
+        let excluded = self.materials.is_override(self.meshes[mesh_index].material)
+            || self.hidden_meshes[mesh_index]
+            || self.mesh_layers[mesh_index] != RenderLayers::default();
+        if let Some(gpu_driven) = &mut self.gpu_driven {
+            gpu_driven.set_excluded(mesh_index, excluded);
+        }
+    }
+
+    /// Layers captures of the main view (stills, panoramas, AOVs) show: the main view's,
+    /// without the gizmos
+    pub fn capture_layers(&self) -> RenderLayers {
+        self.layers.without(RenderLayers::GIZMOS)
+    }
+
+    /// Indices of the meshes not hidden with `set_mesh_visible`
+    fn visible_meshes(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.meshes.len()).filter(|&i| !self.hidden_meshes[i])
+    }
+
+    /// The shown meshes of the primary instance whose layers pass `seen`
+    fn primary_draws<'a>(&'a self, seen: impl Fn(RenderLayers) -> bool + Copy + 'a) -> impl Iterator<Item = MeshDraw> + 'a {
+        self.visible_meshes()
+            .filter(move |&mesh| seen(self.mesh_layers[mesh]))
+            .map(|mesh| MeshDraw { mesh, model: self.mesh_model(mesh) })
+    }
+
+    /// The shown meshes whose layers pass `seen`, of the primary instance and then of every
+    /// other one, for the passes that draw the instances one by one
+    fn mesh_draws<'a>(&'a self, seen: impl Fn(RenderLayers) -> bool + Copy + 'a) -> impl Iterator<Item = MeshDraw> + 'a {
+        let others = self.instances.iter().flat_map(move |instance| {
+            instance
+                .meshes
+                .iter()
+                .filter(move |&&(_, layers)| seen(layers))
+                .map(move |&(mesh, _)| MeshDraw { mesh, model: instance.model * self.mesh_transforms[mesh] })
+        });
+        self.primary_draws(seen).chain(others)
+    }
+
+    /// Switch the scene pipeline to another shader variant, building it on first use.
+    /// Previously built variants stay cached until cleanup, so frames still in flight
+    /// keep a valid pipeline.
+    pub unsafe fn set_shader_variant(
+        &mut self,
+        device: &ash::Device,
+        variant: GltfShaderVariant,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Fall back to the shadow maps (and no AO) where ray queries aren't available
+        let ray_queries = self.acceleration_structure.is_some();
+        let variant = GltfShaderVariant {
+            ray_query_shadows: variant.ray_query_shadows && ray_queries,
+            ao_rays: if ray_queries { variant.ao_rays } else { 0 },
+            ..variant
+        };
+        if variant == self.shader_variant {
+            return Ok(());
+        }
+        
+        self.create_variant_pipelines(device, variant)?;
+        self.shader_variant = variant;
+        Ok(())
+    }
+    
+    /// A job building the scene pipelines of the current shader variant: the base
+    /// permutation, the meshes' and their meshlet ones. Creating pipelines needs no queue,
+    /// so it may run on any thread while the main one keeps uploading and drawing.
+    pub fn scene_pipeline_builder(
+        &self,
+        device: &ash::Device,
+    ) -> impl FnOnce() -> Result<ScenePipelines, String> + Send + 'static {
+        let device = device.clone();
+        let (render_pass, variant) = (self.render_pass, self.shader_variant);
+        let mut jobs: Vec<(vk::PipelineLayout, GltfPermutation, bool)> =
+            std::iter::once(GltfPermutation::default())
+                .chain(self.meshes.iter().map(|mesh| mesh.permutation))
+                .map(|permutation| (self.pipeline_layout, permutation, false))
+                .collect();
+        if let Some(meshlets) = &self.meshlets {
+            let permutations = meshlets.meshes.iter().map(|mesh| self.meshes[mesh.mesh_index].permutation);
+            jobs.extend(permutations.map(|permutation| (meshlets.pipeline_layout, permutation, true)));
+        }
+        move || {
+            let mut pipelines = ScenePipelines { scene: HashMap::new(), meshlets: HashMap::new() };
+            for (layout, permutation, mesh_shading) in jobs {
+                let built = if mesh_shading { &mut pipelines.meshlets } else { &mut pipelines.scene };
+                if built.contains_key(&(variant, permutation)) {
+                    continue;
+                }
+                let pipeline = unsafe { Self::create_pipeline(&device, render_pass, layout, variant, permutation, mesh_shading) };
+                match pipeline {
+                    Ok(pipeline) => {
+                        built.insert((variant, permutation), pipeline);
+                    }
+                    Err(e) => {
+                        unsafe { pipelines.destroy(&device) };
+                        return Err(e.to_string());
+                    }
+                }
+            }
+            Ok(pipelines)
+        }
+    }
+
+    /// The scene pass for color attachments of `color_format`, with the pipelines of every
+    /// draw the current shader variant makes built for it. Draw with it after
+    /// `swap_scene_pass`, into an `OffscreenTarget` created `with_format` `color_format`.
+    pub unsafe fn create_scene_pass(
+        &self,
+        device: &ash::Device,
+        color_format: vk::Format,
+    ) -> Result<ScenePass, Box<dyn std::error::Error>> {
+        let mut pass = ScenePass {
+            render_pass: Self::create_render_pass(device, color_format, vk::Format::D32_SFLOAT)?,
+            color_format,
+            pipelines: ScenePipelines { scene: HashMap::new(), meshlets: HashMap::new() },
+            outline: vk::Pipeline::null(),
+            skybox: None,
+            impostors: HashMap::new(),
+            dither_params: [0.0; 4],
+        };
+        match self.build_scene_pass_pipelines(device, &mut pass) {
+            Ok(()) => Ok(pass),
+            Err(e) => {
+                pass.destroy(device);
+                Err(e)
+            }
+        }
+    }
+
+    unsafe fn build_scene_pass_pipelines(
+        &self,
+        device: &ash::Device,
+        pass: &mut ScenePass,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let variant = self.shader_variant;
+        for key in self.materials.pipeline_keys().filter(|key| key.0 == variant) {
+            let pipeline = Self::create_pipeline(device, pass.render_pass, self.pipeline_layout, key.0, key.1, false)?;
+            pass.pipelines.scene.insert(key, pipeline);
+        }
+        if let Some(meshlets) = &self.meshlets {
+            for &key in meshlets.pipelines.keys().filter(|key| key.0 == variant) {
+                let pipeline = Self::create_pipeline(device, pass.render_pass, meshlets.pipeline_layout, key.0, key.1, true)?;
+                pass.pipelines.meshlets.insert(key, pipeline);
+            }
+        }
+        pass.outline = self.outline.build_pipeline(device, pass.render_pass)?;
+        if let Some(skybox) = &self.skybox {
+            pass.skybox = Some(skybox.build_pipeline(device, pass.render_pass)?);
+        }
+        for (&mesh_index, impostors) in &self.impostors {
+            if let Some(impostors) = impostors {
+                pass.impostors.insert(mesh_index, impostors.build_pipeline(device, pass.render_pass)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Draw in `pass` from now on, and leave the pass and pipelines drawn with so far in it;
+    /// swap again to go back. Call with the device idle, and not across a change of shader
+    /// variant, scene or environment.
+    pub fn swap_scene_pass(&mut self, pass: &mut ScenePass) {
+        std::mem::swap(&mut self.render_pass, &mut pass.render_pass);
+        std::mem::swap(&mut self.color_format, &mut pass.color_format);
+        std::mem::swap(&mut self.dither_params, &mut pass.dither_params);
+        let render_pass = self.render_pass;
+        pass.pipelines.scene = self.materials.replace_pipelines(std::mem::take(&mut pass.pipelines.scene));
+        if let Some(meshlets) = &mut self.meshlets {
+            std::mem::swap(&mut meshlets.pipelines, &mut pass.pipelines.meshlets);
+        }
+        pass.outline = self.outline.swap_pipeline(render_pass, pass.outline);
+        if let (Some(skybox), Some(pipeline)) = (&mut self.skybox, &mut pass.skybox) {
+            *pipeline = skybox.swap_pipeline(render_pass, *pipeline);
+        }
+        for (mesh_index, pipeline) in &mut pass.impostors {
+            if let Some(Some(impostors)) = self.impostors.get_mut(mesh_index) {
+                *pipeline = impostors.swap_pipeline(render_pass, *pipeline);
+            }
+        }
+    }
+
+    /// Show the equirectangular HDR image at `path` behind the scene, see `skybox`. Call with
+    /// the device idle.
+    pub unsafe fn set_environment(
+        &mut self,
+        renderer: &VulkanRenderer,
+        path: &std::path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let skybox = Skybox::new(renderer, self.render_pass, path)?;
+        if let Some(mut old) = self.skybox.replace(skybox) {
+            old.destroy(renderer);
+        }
+        Ok(())
+    }
+
+    /// Start drawing with the pipelines a `scene_pipeline_builder` job built
+    pub unsafe fn install_scene_pipelines(&mut self, device: &ash::Device, pipelines: ScenePipelines) {
+        let stale_meshlets = match &mut self.meshlets {
+            Some(meshlets) => std::mem::replace(&mut meshlets.pipelines, pipelines.meshlets),
+            None => pipelines.meshlets,
+        };
+        let stale = ScenePipelines { scene: self.materials.replace_pipelines(pipelines.scene), meshlets: stale_meshlets };
+        stale.destroy(device);
+    }
+
+    /// Rebuild the scene pipelines of the current shader variant from the current glTF
+    /// shaders; other variants are rebuilt when next selected. If any fails to build the
+    /// old pipelines are kept. Call with the device idle.
+    pub unsafe fn reload_scene_pipelines(&mut self, device: &ash::Device) -> Result<(), Box<dyn std::error::Error>> {
+        let old_pipelines = self.materials.replace_pipelines(HashMap::new());
+        let old_meshlet_pipelines = self.meshlets.as_mut().map(|meshlets| std::mem::take(&mut meshlets.pipelines));
+        
+        let result = self.create_variant_pipelines(device, self.shader_variant);
+        let (stale, stale_meshlets) = if result.is_ok() {
+            (old_pipelines, old_meshlet_pipelines)
+        } else {
+            let new_meshlet_pipelines = self
+                .meshlets
+                .as_mut()
+                .zip(old_meshlet_pipelines)
+                .map(|(meshlets, old)| std::mem::replace(&mut meshlets.pipelines, old));
+            (self.materials.replace_pipelines(old_pipelines), new_meshlet_pipelines)
+        };
+        for pipeline in stale.into_values().chain(stale_meshlets.into_iter().flat_map(HashMap::into_values)) {
+            device.destroy_pipeline(pipeline, None);
+        }
+        result
+    }
+    
+    /// Rebuild the shadow map pipelines from the current shadow shaders, keeping the old ones
+    /// if either fails to build. Call with the device idle.
+    pub unsafe fn reload_shadow_pipeline(&mut self, device: &ash::Device) -> Result<(), Box<dyn std::error::Error>> {
+        let pipeline = Self::create_shadow_pipeline(device, self.shadow_render_pass, self.shadow_pipeline_layout, false)?;
+        let instanced = match Self::create_shadow_pipeline(device, self.shadow_render_pass, self.shadow_pipeline_layout, true) {
+            Ok(instanced) => instanced,
+            Err(e) => {
+                device.destroy_pipeline(pipeline, None);
+                return Err(e);
+            }
+        };
+        device.destroy_pipeline(std::mem::replace(&mut self.shadow_pipeline, pipeline), None);
+        device.destroy_pipeline(std::mem::replace(&mut self.shadow_instanced_pipeline, instanced), None);
+        Ok(())
+    }
+    
+    /// Build (once) the base pipeline of `variant` and those of the permutations and
+    /// meshlets drawn with it
+    unsafe fn create_variant_pipelines(
+        &mut self,
+        device: &ash::Device,
+        variant: GltfShaderVariant,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let built = self.materials.ensure_pipeline(variant, GltfPermutation::default(), || {
+            Self::create_pipeline(
+                device,
+                self.render_pass,
+                self.pipeline_layout,
+                variant,
+                GltfPermutation::default(),
+                false,
+            )
+        })?;
+        if built {
+            println!("✓ Built glTF pipeline variant {:?}", variant);
+        }
+        Self::create_permutation_pipelines(
+            device,
+            self.render_pass,
+            self.pipeline_layout,
+            variant,
+            &self.meshes,
+            &mut self.materials,
+            !self.instances.is_empty(),
+        )?;
+        if let Some(meshlets) = &mut self.meshlets {
+            Self::create_meshlet_pipelines(device, self.render_pass, variant, &self.meshes, meshlets)?;
+        }
+        Ok(())
+    }
+    
+    /// Build (once) the pipeline of every permutation used by `meshes` for `variant`, and
+    /// with `instanced` their instanced ones too
+    unsafe fn create_permutation_pipelines(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        pipeline_layout: vk::PipelineLayout,
+        variant: GltfShaderVariant,
+        meshes: &[GltfMeshBuffers],
+        materials: &mut MaterialRegistry,
+        instanced: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let instanced_permutations = meshes
+            .iter()
+            .filter(|_| instanced)
+            .map(|mesh| mesh.permutation | GltfPermutation::INSTANCED);
+        for permutation in meshes.iter().map(|mesh| mesh.permutation).chain(instanced_permutations) {
+            let built = materials.ensure_pipeline(variant, permutation, || {
+                Self::create_pipeline(device, render_pass, pipeline_layout, variant, permutation, false)
+            })?;
+            if built {
+                println!("✓ Built glTF permutation {:?}", permutation.defines());
+            }
+        }
+        Ok(())
+    }
+    
+    /// Build (once) the task/mesh shader pipeline of every permutation drawn as meshlets
+    unsafe fn create_meshlet_pipelines(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        variant: GltfShaderVariant,
+        meshes: &[GltfMeshBuffers],
+        meshlets: &mut MeshletPass,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for mesh in &meshlets.meshes {
+            let key = (variant, meshes[mesh.mesh_index].permutation);
+            if meshlets.pipelines.contains_key(&key) {
+                continue;
+            }
+            let pipeline = Self::create_pipeline(device, render_pass, meshlets.pipeline_layout, key.0, key.1, true)?;
+            println!("✓ Built glTF meshlet permutation {:?}", key.1.defines());
+            meshlets.pipelines.insert(key, pipeline);
+        }
+        Ok(())
+    }
+    
+    /// `mesh_shading` swaps gltf.vert for the meshlet task + mesh shaders (see meshlets.rs)
+    unsafe fn create_pipeline(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        pipeline_layout: vk::PipelineLayout,
+        variant: GltfShaderVariant,
+        permutation: GltfPermutation,
+        mesh_shading: bool,
+    ) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+        let permutation = if variant.uses_ray_queries() {
+            permutation | GltfPermutation::RAY_QUERY
+        } else {
+            permutation
+        };
+        let defines = permutation.defines();
+        let frag_code = load_shader_permutation("gltf.frag", &defines, permutation.embedded_frag_spv());
+        
+        // Stages before the fragment shader: gltf.vert, or the meshlet task + mesh shaders
+        let geometry_code = if mesh_shading {
+            let task_code = load_shader("gltf.task", include_bytes!("../shaders/gltf.task.spv"));
+            let mesh_code = load_shader("gltf.mesh", include_bytes!("../shaders/gltf.mesh.spv"));
+            vec![(vk::ShaderStageFlags::TASK_EXT, task_code), (vk::ShaderStageFlags::MESH_EXT, mesh_code)]
+        } else {
+            let vert_code = load_shader_permutation("gltf.vert", &defines, permutation.embedded_vert_spv());
+            vec![(vk::ShaderStageFlags::VERTEX, vert_code)]
+        };
+        
+        // Specialization constants: SHADOW_SAMPLES, USE_PCSS, LIGHT_COUNT, DEBUG_VIEW,
+        // RAY_TRACED_SHADOWS, AO_RAYS
+        let specialization_data: [u32; 6] = [
+            variant.shadow_samples.max(1),
+            variant.use_pcss as u32,
+            variant.light_count,
+            variant.debug_view as u32,
+            variant.ray_query_shadows as u32,
+            variant.ao_rays,
+        ];
+        let specialization_entries: Vec<vk::SpecializationMapEntry> = (0..6u32)
+            .map(|id| vk::SpecializationMapEntry {
+                constant_id: id,
+                offset: id * 4,
+                size: 4,
+            })
+            .collect();
+        let specialization_bytes = std::slice::from_raw_parts(
+            specialization_data.as_ptr() as *const u8,
+            std::mem::size_of_val(&specialization_data),
+        );
+        let specialization_info = vk::SpecializationInfo::default()
+            .map_entries(&specialization_entries)
+            .data(specialization_bytes);
+        
+        let mut builder = GraphicsPipelineBuilder::new(pipeline_layout, render_pass);
+        for (stage, code) in &geometry_code {
+            builder = builder.shader(*stage, code);
+        }
+        if !mesh_shading {
+            // Mesh shaders fetch their own vertices
+            builder = builder.vertex_buffer::<GltfVertex>();
+        }
+        if permutation.contains(GltfPermutation::INSTANCED) {
+            builder = builder.instance_buffer::<Mat4>();
+        }
+        builder
+            .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+            .specialization(&specialization_info)
+            .depth(DepthMode::ReadWrite(vk::CompareOp::LESS))
+            .build(device)
+    }
+    
+    /// Upload `mips` as a sampled sRGB image, `mips[0]` being its level 0
+    unsafe fn create_texture(
+        renderer: &VulkanRenderer,
+        mips: &[MipLevel],
+    ) -> Result<TextureResources, Box<dyn std::error::Error>> {
+        Self::create_texture_with_format(renderer, mips, vk::Format::R8G8B8A8_SRGB)
+    }
+
+    /// Load the Radiance HDR or OpenEXR image at `path` as a sampled float image, of half
+    /// floats where the device can't filter full ones
+    pub unsafe fn create_hdr_texture(
+        renderer: &VulkanRenderer,
+        path: &std::path::Path,
+        precision: HdrPrecision,
+    ) -> Result<TextureResources, Box<dyn std::error::Error>> {
+        let precision = precision.supported(renderer);
+        let texels = hdr_image::read(path, precision)?;
+        Self::create_texture_with_format(renderer, std::slice::from_ref(&texels), precision.format())
+    }
+
+    /// Upload `mips` as a sampled image of `format`: 4 bytes per texel, the blocks of a
+    /// compressed format, or RGBA half or full floats
+    unsafe fn create_texture_with_format(
+        renderer: &VulkanRenderer,
+        mips: &[MipLevel],
+        format: vk::Format,
+    ) -> Result<TextureResources, Box<dyn std::error::Error>> {
+        let (width, height) = (mips[0].width, mips[0].height);
+        let mip_levels = mips.len() as u32;
+        
+        // Create staging buffer with every level, tightly packed
+        let buffer_size: u64 = mips.iter().map(MipLevel::bytes).sum();
+        let staging_buffer_info = vk::BufferCreateInfo::default()
+            .size(buffer_size)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        
+        let staging_buffer = renderer.device.create_buffer(&staging_buffer_info, None)?;
+        let staging_reqs = renderer.device.get_buffer_memory_requirements(staging_buffer);
+        
+        let staging_allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: "texture_staging",
+            requirements: staging_reqs,
+            location: MemoryLocation::CpuToGpu,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        })?;
+        
+        renderer.device.bind_buffer_memory(
+            staging_buffer,
+            staging_allocation.memory(),
+            staging_allocation.offset(),
+        )?;
+        
+        let ptr = staging_allocation.mapped_ptr().unwrap().as_ptr() as *mut u8;
+        let mut regions = Vec::with_capacity(mips.len());
+        let mut offset = 0;
+        for (level, mip) in mips.iter().enumerate() {
+            std::ptr::copy_nonoverlapping(mip.data.as_ptr(), ptr.add(offset as usize), mip.data.len());
+            regions.push(vk::BufferImageCopy {
+                buffer_offset: offset,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: level as u32,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                image_extent: vk::Extent3D { width: mip.width, height: mip.height, depth: 1 },
+            });
+            offset += mip.bytes();
+        }
+        
         // Create image
         let image_info = vk::ImageCreateInfo::default()
             .image_type(vk::ImageType::TYPE_2D)
-            .format(vk::Format::R8G8B8A8_SRGB)
+            .format(format)
             .extent(vk::Extent3D { width, height, depth: 1 })
-            .mip_levels(1)
+            .mip_levels(mip_levels)
             .array_layers(1)
             .samples(vk::SampleCountFlags::TYPE_1)
             .tiling(vk::ImageTiling::OPTIMAL)
@@ -1519,15 +2315,17 @@ impl GltfRenderer {
         Self::transition_image_layout(
             renderer,
             image,
+            mip_levels,
             vk::ImageLayout::UNDEFINED,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
         )?;
         
-        Self::copy_buffer_to_image(renderer, staging_buffer, image, width, height)?;
+        Self::copy_buffer_to_image(renderer, staging_buffer, image, &regions)?;
         
         Self::transition_image_layout(
             renderer,
             image,
+            mip_levels,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
         )?;
@@ -1540,54 +2338,56 @@ impl GltfRenderer {
         let view_info = vk::ImageViewCreateInfo::default()
             .image(image)
             .view_type(vk::ImageViewType::TYPE_2D)
-            .format(vk::Format::R8G8B8A8_SRGB)
+            .format(format)
             .subresource_range(vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
                 base_mip_level: 0,
-                level_count: 1,
+                level_count: mip_levels,
                 base_array_layer: 0,
                 layer_count: 1,
             });
         
         let image_view = renderer.device.create_image_view(&view_info, None)?;
         
-        // Create sampler
-        let sampler_info = vk::SamplerCreateInfo::default()
-            .mag_filter(vk::Filter::LINEAR)
-            .min_filter(vk::Filter::LINEAR)
-            .address_mode_u(vk::SamplerAddressMode::REPEAT)
-            .address_mode_v(vk::SamplerAddressMode::REPEAT)
-            .address_mode_w(vk::SamplerAddressMode::REPEAT)
-            .anisotropy_enable(false)
-            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-            .unnormalized_coordinates(false)
-            .compare_enable(false)
-            .mipmap_mode(vk::SamplerMipmapMode::LINEAR);
-        
-        let sampler = renderer.device.create_sampler(&sampler_info, None)?;
+        let sampler = renderer.sampler(TextureFilter::default().sampler_desc(mip_levels))?;
         
         Ok(TextureResources {
             image,
             image_view,
             sampler,
             allocation: Some(image_allocation),
+            mip_levels,
         })
     }
     
     unsafe fn create_fallback_texture(
         renderer: &VulkanRenderer,
     ) -> Result<TextureResources, Box<dyn std::error::Error>> {
-        let tex = crate::gltf_loader::GltfTexture {
+        let white = MipLevel {
             width: 1,
             height: 1,
             data: vec![255, 255, 255, 255],
         };
-        Self::create_texture(renderer, &tex)
+        Self::create_texture(renderer, std::slice::from_ref(&white))
     }
     
+    /// The blue noise tile as linear values in every channel
+    unsafe fn create_blue_noise_texture(
+        renderer: &VulkanRenderer,
+    ) -> Result<TextureResources, Box<dyn std::error::Error>> {
+        let size = post_effects::BLUE_NOISE_SIZE;
+        let noise = MipLevel {
+            width: size as u32,
+            height: size as u32,
+            data: post_effects::blue_noise(size).into_iter().flat_map(|v| [v, v, v, 255]).collect(),
+        };
+        Self::create_texture_with_format(renderer, std::slice::from_ref(&noise), vk::Format::R8G8B8A8_UNORM)
+    }
+
     unsafe fn transition_image_layout(
         renderer: &VulkanRenderer,
         image: vk::Image,
+        mip_levels: u32,
         old_layout: vk::ImageLayout,
         new_layout: vk::ImageLayout,
     ) -> Result<(), vk::Result> {
@@ -1632,7 +2432,7 @@ impl GltfRenderer {
             .subresource_range(vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
                 base_mip_level: 0,
-                level_count: 1,
+                level_count: mip_levels,
                 base_array_layer: 0,
                 layer_count: 1,
             })
@@ -1734,8 +2534,7 @@ impl GltfRenderer {
         renderer: &VulkanRenderer,
         buffer: vk::Buffer,
         image: vk::Image,
-        width: u32,
-        height: u32,
+        regions: &[vk::BufferImageCopy],
     ) -> Result<(), vk::Result> {
         let cmd_info = vk::CommandBufferAllocateInfo::default()
             .command_pool(renderer.command_pool)
@@ -1748,26 +2547,12 @@ impl GltfRenderer {
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
         renderer.device.begin_command_buffer(cmd, &begin_info)?;
         
-        let region = vk::BufferImageCopy {
-            buffer_offset: 0,
-            buffer_row_length: 0,
-            buffer_image_height: 0,
-            image_subresource: vk::ImageSubresourceLayers {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                mip_level: 0,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
-            image_extent: vk::Extent3D { width, height, depth: 1 },
-        };
-        
         renderer.device.cmd_copy_buffer_to_image(
             cmd,
             buffer,
             image,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            &[region],
+            regions,
         );
         
         renderer.device.end_command_buffer(cmd)?;
@@ -1781,58 +2566,84 @@ impl GltfRenderer {
         Ok(())
     }
     
+    /// Upload this frame's uniforms and instance transforms to the frame arena and point the
+    /// frame's set at the uniforms. `model` places the primary model (see `model_matrix`).
+    /// Call after `VulkanRenderer::frame_arena` has begun the frame.
     pub unsafe fn update_uniform_buffer(
         &mut self,
+        renderer: &VulkanRenderer,
         current_frame: usize,
-        position: glam::Vec3,
-        camera_pos: glam::Vec3,
-        camera_yaw: f32,
-        camera_pitch: f32,
-        camera_fov: f32,
-        scale: f32,
-        aspect_ratio: f32,
-        debug_cascades: bool,
-        shadow_softness: f32,
-        use_pcss: bool,
-        use_shadow_taa: bool,
+        model: Mat4,
+        camera: &ViewCamera,
+        shadows: ShadowUniforms,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        // Calculate camera direction from yaw and pitch
-        let camera_front = glam::Vec3::new(
-            camera_yaw.cos() * camera_pitch.cos(),
-            camera_pitch.sin(),
-            camera_yaw.sin() * camera_pitch.cos(),
-        ).normalize();
-        
-        let target = camera_pos + camera_front;
-
         // Per-object transforms (sent via push constants)
         self.ground_model = Mat4::IDENTITY;
         
-        // Rotate duck to face the camera (180 degrees around Y axis)
-        let duck_rotation = Quat::from_rotation_y(std::f32::consts::PI);
-        self.duck_model = Mat4::from_scale_rotation_translation(Vec3::splat(scale), duck_rotation, position);
-        
-        let view = glam::Mat4::look_at_rh(camera_pos, target, glam::Vec3::Y);
-
-        // Vulkan clip space has inverted Y compared to the typical math conventions used by
-        // many helper functions. Flip Y so "up" on input corresponds to "up" on screen.
-        let mut proj = glam::Mat4::perspective_rh(camera_fov, aspect_ratio, 0.1, 100.0);
-        proj.y_axis.y *= -1.0;
+        self.duck_model = model;
 
-        let view_proj = proj * view;
+        let view_proj = camera.view_proj();
         let prev_view_proj = if self.has_prev_view_proj {
             self.prev_view_proj
         } else {
             view_proj
         };
 
-        // Cascaded shadow maps (4 splits)
-        let near_plane = 0.1_f32;
-        let far_plane = 100.0_f32;
-        let lambda = 0.6_f32;
+        let frame_f = (self.shadow_frame_index as f32) % 1024.0;
+        let debug_flags = [
+            if shadows.debug_cascades { 1.0 } else { 0.0 },
+            if shadows.use_pcss { 1.0 } else { 0.0 },
+            if shadows.use_taa { 1.0 } else { 0.0 },
+            frame_f,
+        ];
+        let ubo = self.build_uniforms(camera, prev_view_proj, debug_flags, shadows.softness, true);
+        
+        let slice = renderer
+            .frame_arena
+            .push(std::slice::from_ref(&ubo))
+            .ok_or("frame arena allocation failed")?;
+        let buffer_info = slice.descriptor_info();
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.descriptor_sets[current_frame])
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(std::slice::from_ref(&buffer_info));
+        renderer.device.update_descriptor_sets(&[write], &[]);
+        self.camera_position = camera.position;
+        self.upload_instances(renderer, current_frame)?;
 
-        let mut cascade_splits = [0.0_f32; 4];
-        for i in 0..SHADOW_CASCADE_COUNT {
+        self.view_proj = view_proj;
+        self.light_view_proj = ubo.light_view_proj.map(|m| Mat4::from_cols_array_2d(&m));
+        self.prev_view_proj = view_proj;
+        self.has_prev_view_proj = true;
+        self.shadow_frame_index = self.shadow_frame_index.wrapping_add(1);
+
+        Ok(())
+    }
+    
+    /// Camera, cascaded shadow map, light and reflection probe, lightmap, virtual texture and
+    /// planar reflection uniforms for one view of the scene. Only the main view asks for
+    /// virtual texture pages and samples the planar reflection.
+    fn build_uniforms(
+        &self,
+        camera: &ViewCamera,
+        prev_view_proj: Mat4,
+        debug_flags: [f32; 4],
+        shadow_softness: f32,
+        main_view: bool,
+    ) -> GltfUniformBufferObject {
+        let view = camera.view;
+        let proj = camera.proj;
+        let probes = self.light_probes.uniforms();
+        let reflection_probes = self.reflection_probes.uniforms();
+
+        // Cascaded shadow maps (4 splits)
+        let near_plane = 0.1_f32;
+        let far_plane = 100.0_f32;
+        let lambda = 0.6_f32;
+
+        let mut cascade_splits = [0.0_f32; 4];
+        for i in 0..SHADOW_CASCADE_COUNT {
             let p = (i as f32 + 1.0) / SHADOW_CASCADE_COUNT as f32;
             let log = near_plane * (far_plane / near_plane).powf(p);
             let uni = near_plane + (far_plane - near_plane) * p;
@@ -1857,7 +2668,7 @@ impl GltfRenderer {
             frustum_corners[i] = (p / p.w).truncate();
         }
 
-        let light_dir_world = glam::Vec3::new(0.5, 1.0, 0.3).normalize();
+        let light_dir_world = SUN_DIRECTION.normalize();
         let mut light_view_proj = [[[0.0_f32; 4]; 4]; SHADOW_CASCADE_COUNT];
 
         let mut prev_split = near_plane;
@@ -1946,16 +2757,14 @@ impl GltfRenderer {
 
             prev_split = split;
         }
-        
-        let frame_f = (self.shadow_frame_index as f32) % 1024.0;
 
-        let ubo = GltfUniformBufferObject {
+        GltfUniformBufferObject {
             view: view.to_cols_array_2d(),
             proj: proj.to_cols_array_2d(),
-            camera_pos: [camera_pos.x, camera_pos.y, camera_pos.z, 0.0],
+            camera_pos: [camera.position.x, camera.position.y, camera.position.z, 0.0],
             light_dir: {
-                let l = glam::Vec4::new(0.5, 1.0, 0.3, 0.0).normalize();
-                [l.x, l.y, l.z, l.w]
+                let l = SUN_DIRECTION.normalize();
+                [l.x, l.y, l.z, 0.0]
             },
 
             light_view_proj,
@@ -1967,29 +2776,30 @@ impl GltfRenderer {
                 1.0 / SHADOW_MAP_SIZE as f32,
             ],
 
-            debug_flags: [
-                if debug_cascades { 1.0 } else { 0.0 },
-                if use_pcss { 1.0 } else { 0.0 },
-                if use_shadow_taa { 1.0 } else { 0.0 },
-                frame_f,
-            ],
+            debug_flags,
             // Reusing this vec4 for shadow params:
             // x = Light size in texels (for PCSS penumbra / PCF radius)
             shadow_bias: [shadow_softness, 0.0, 0.0, 0.0],
 
             prev_view_proj: prev_view_proj.to_cols_array_2d(),
-        };
-        
-        if let Some(allocation) = &self.uniform_allocations[current_frame] {
-            let ptr = allocation.mapped_ptr().unwrap().as_ptr() as *mut GltfUniformBufferObject;
-            std::ptr::copy_nonoverlapping(&ubo, ptr, 1);
-        }
 
-        self.prev_view_proj = view_proj;
-        self.has_prev_view_proj = true;
-        self.shadow_frame_index = self.shadow_frame_index.wrapping_add(1);
+            probe_positions: probes.positions,
+            probe_irradiance: probes.irradiance,
+            probe_params: probes.params,
 
-        Ok(())
+            lightmap_params: self.lightmap.uniforms(),
+            virtual_texture_params: self.virtual_texture.uniforms(main_view),
+
+            reflection_probe_boxes: reflection_probes.boxes,
+            reflection_probe_params: reflection_probes.params,
+
+            planar_reflection_params: if main_view { self.planar_reflection_params } else { [0.0; 4] },
+
+            toon_params: self.toon.uniforms(),
+            dither_params: self.dither_params,
+            weather_params: self.weather_params,
+            ssgi_params: if main_view { self.ssgi_params } else { [0.0; 4] },
+        }
     }
     
     pub unsafe fn render(
@@ -2000,181 +2810,37 @@ impl GltfRenderer {
         image_index: u32,
         current_frame: usize,
     ) {
-        // --- Shadow pass (CSM) ---
-        {
-            let old_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-            let src_stage = vk::PipelineStageFlags::FRAGMENT_SHADER;
-            let src_access = vk::AccessFlags::SHADER_READ;
-
-            let barrier_to_depth = vk::ImageMemoryBarrier::default()
-                .old_layout(old_layout)
-                .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                .src_access_mask(src_access)
-                .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .image(self.shadow_image)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::DEPTH,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: SHADOW_CASCADE_COUNT as u32,
-                });
-            device.cmd_pipeline_barrier(
-                command_buffer,
-                src_stage,
-                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                std::slice::from_ref(&barrier_to_depth),
-            );
+        let descriptor_set = self.descriptor_sets[current_frame];
 
-            let shadow_viewport = vk::Viewport {
-                x: 0.0,
-                y: 0.0,
-                width: SHADOW_MAP_SIZE as f32,
-                height: SHADOW_MAP_SIZE as f32,
-                min_depth: 0.0,
-                max_depth: 1.0,
-            };
-            let shadow_scissor = vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: vk::Extent2D {
-                    width: SHADOW_MAP_SIZE,
-                    height: SHADOW_MAP_SIZE,
-                },
-            };
+        // Skin once for both the shadow and scene passes
+        if let Some(skinning) = &mut self.skinning {
+            skinning.record(device, command_buffer, current_frame);
+        }
 
-            unsafe fn push_shadow(
-                device: &ash::Device,
-                command_buffer: vk::CommandBuffer,
-                pipeline_layout: vk::PipelineLayout,
-                model: &Mat4,
-                cascade_index: i32,
-            ) {
-                let pc = ShadowPushConstants {
-                    model: model.to_cols_array_2d(),
-                    cascade_index,
-                    _pad: [0; 3],
-                };
-                let bytes = std::slice::from_raw_parts(
-                    (&pc as *const ShadowPushConstants) as *const u8,
-                    std::mem::size_of::<ShadowPushConstants>(),
-                );
-                device.cmd_push_constants(
-                    command_buffer,
-                    pipeline_layout,
-                    vk::ShaderStageFlags::VERTEX,
-                    0,
-                    bytes,
-                );
-            }
+        self.virtual_texture.record(device, command_buffer, current_frame);
 
-            for cascade in 0..SHADOW_CASCADE_COUNT {
-                let clear_values = [vk::ClearValue {
-                    depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
-                }];
-                let render_pass_info = vk::RenderPassBeginInfo::default()
-                    .render_pass(self.shadow_render_pass)
-                    .framebuffer(self.shadow_framebuffers[cascade])
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
-                        extent: vk::Extent2D {
-                            width: SHADOW_MAP_SIZE,
-                            height: SHADOW_MAP_SIZE,
-                        },
-                    })
-                    .clear_values(&clear_values);
-
-                device.cmd_begin_render_pass(
-                    command_buffer,
-                    &render_pass_info,
-                    vk::SubpassContents::INLINE,
-                );
-                device.cmd_bind_pipeline(
-                    command_buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    self.shadow_pipeline,
-                );
-                device.cmd_set_viewport(command_buffer, 0, &[shadow_viewport]);
-                device.cmd_set_scissor(command_buffer, 0, &[shadow_scissor]);
-
-                device.cmd_bind_descriptor_sets(
-                    command_buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    self.shadow_pipeline_layout,
-                    0,
-                    &[self.descriptor_sets[current_frame]],
-                    &[],
-                );
-
-                // Draw ground
-                if let Some(ground) = &self.ground {
-                    push_shadow(
-                        device,
-                        command_buffer,
-                        self.shadow_pipeline_layout,
-                        &self.ground_model,
-                        cascade as i32,
-                    );
-                    device.cmd_bind_vertex_buffers(command_buffer, 0, &[ground.vertex_buffer], &[0]);
-                    device.cmd_bind_index_buffer(
-                        command_buffer,
-                        ground.index_buffer,
-                        0,
-                        vk::IndexType::UINT32,
-                    );
-                    device.cmd_draw_indexed(command_buffer, ground.index_count, 1, 0, 0, 0);
-                }
+        if self.shader_variant.uses_ray_queries() {
+            // Extra views trace against the same TLAS, so it only moves here
+            let transforms = self.tlas_transforms();
+            if let Some(acceleration_structure) = &mut self.acceleration_structure {
+                acceleration_structure.update(device, command_buffer, current_frame, &transforms);
+            }
+        }
 
-                // Draw duck
-                push_shadow(
-                    device,
-                    command_buffer,
-                    self.shadow_pipeline_layout,
-                    &self.duck_model,
-                    cascade as i32,
-                );
-                for mesh in &self.meshes {
-                    device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer], &[0]);
-                    device.cmd_bind_index_buffer(
-                        command_buffer,
-                        mesh.index_buffer,
-                        0,
-                        vk::IndexType::UINT32,
-                    );
-                    device.cmd_draw_indexed(command_buffer, mesh.index_count, 1, 0, 0, 0);
-                }
+        if let Some(gpu_driven) = self.active_gpu_driven() {
+            gpu_driven.record_culling(device, command_buffer, &self.view_proj, &self.duck_model);
+            gpu_driven.record_readback(device, command_buffer, current_frame);
+        }
+        if let Some(instance_culling) = &self.instance_culling {
+            instance_culling.record_culling(device, command_buffer, &self.view_proj);
+        }
 
-                device.cmd_end_render_pass(command_buffer);
-            }
+        // Shadow and scene passes share one encoder; nothing else binds in between
+        let mut encoder = CommandEncoder::new(device, command_buffer);
 
-            let barrier_to_sample = vk::ImageMemoryBarrier::default()
-                .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
-                .dst_access_mask(vk::AccessFlags::SHADER_READ)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .image(self.shadow_image)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::DEPTH,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: SHADOW_CASCADE_COUNT as u32,
-                });
-            device.cmd_pipeline_barrier(
-                command_buffer,
-                vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
-                vk::PipelineStageFlags::FRAGMENT_SHADER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                std::slice::from_ref(&barrier_to_sample),
-            );
+        // --- Shadow pass (CSM) ---
+        if !self.shader_variant.ray_query_shadows {
+            self.record_shadow_pass(&mut encoder, descriptor_set);
         }
 
         // Shadow history TAA: update descriptors for this swapchain image and prepare storage write target
@@ -2182,19 +2848,12 @@ impl GltfRenderer {
             let idx = image_index as usize;
             let ping = self.shadow_history_pingpong[idx] as usize;
 
-            let (read_view, write_view, write_image) = if ping == 0 {
-                (
-                    self.shadow_history_views_a[idx],
-                    self.shadow_history_views_b[idx],
-                    self.shadow_history_images_b[idx],
-                )
+            let (read, write) = if ping == 0 {
+                (self.shadow_history_a[idx].color(), self.shadow_history_b[idx].color())
             } else {
-                (
-                    self.shadow_history_views_b[idx],
-                    self.shadow_history_views_a[idx],
-                    self.shadow_history_images_a[idx],
-                )
+                (self.shadow_history_b[idx].color(), self.shadow_history_a[idx].color())
             };
+            let (read_view, write_view, write_image) = (read.view, write.view, write.image);
 
             let history_read = vk::DescriptorImageInfo {
                 sampler: self.shadow_history_sampler,
@@ -2248,10 +2907,161 @@ impl GltfRenderer {
             );
         }
 
-        // Begin render pass
+        // Begin render pass, counted until `end_render_pass`
+        if let Some(pipeline_statistics) = &mut self.pipeline_statistics {
+            pipeline_statistics.begin(device, command_buffer, current_frame);
+        }
+        self.begin_scene_pass(device, command_buffer, self.framebuffers[image_index as usize], extent);
+        let mut pass = encoder.continue_render_pass(self.render_pass);
+        self.draw_scene(&mut pass, extent, descriptor_set, &self.view_proj, self.layers, current_frame);
+    }
+
+    /// Render all shadow cascades using the light matrices from `descriptor_set`'s UBO.
+    unsafe fn record_shadow_pass(&self, encoder: &mut CommandEncoder, descriptor_set: vk::DescriptorSet) {
+        let (device, command_buffer) = (encoder.device(), encoder.command_buffer());
+        let old_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        let src_stage = vk::PipelineStageFlags::FRAGMENT_SHADER;
+        let src_access = vk::AccessFlags::SHADER_READ;
+
+        let barrier_to_depth = vk::ImageMemoryBarrier::default()
+            .old_layout(old_layout)
+            .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .src_access_mask(src_access)
+            .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.shadow_map.depth().image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: SHADOW_CASCADE_COUNT as u32,
+            });
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            std::slice::from_ref(&barrier_to_depth),
+        );
+
+        let shadow_viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: SHADOW_MAP_SIZE as f32,
+            height: SHADOW_MAP_SIZE as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let shadow_scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: vk::Extent2D {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+            },
+        };
+
+        let shadow_pipeline = PipelineBinding {
+            pipeline: self.shadow_pipeline,
+            layout: self.shadow_pipeline_layout,
+            render_pass: self.shadow_render_pass,
+            push_constant_size: std::mem::size_of::<ShadowPushConstants>() as u32,
+        };
+        let push_shadow = |pass: &mut RenderPassEncoder, model: &Mat4, cascade_index: i32| {
+            let pc = ShadowPushConstants {
+                model: model.to_cols_array_2d(),
+                cascade_index,
+                _pad: [0; 3],
+            };
+            pass.push_constants(vk::ShaderStageFlags::VERTEX, 0, &pc);
+        };
+
+        for cascade in 0..SHADOW_CASCADE_COUNT {
+            let clear_values = [vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+            }];
+            let mut pass = encoder.begin_render_pass(
+                self.shadow_render_pass,
+                self.shadow_framebuffers[cascade],
+                shadow_scissor.extent,
+                &clear_values,
+            );
+            // Bindings outlive render passes, so only the first cascade records these
+            pass.bind_pipeline(shadow_pipeline);
+            pass.set_viewport(shadow_viewport);
+            pass.set_scissor(shadow_scissor);
+            pass.bind_descriptor_set(0, descriptor_set);
+
+            // Draw ground
+            if let Some(ground) = self.visible_ground() {
+                push_shadow(&mut pass, &self.ground_model, cascade as i32);
+                pass.bind_vertex_buffers(&[ground.vertex_buffer], &[0]);
+                pass.bind_index_buffer(ground.index_buffer, 0, vk::IndexType::UINT32);
+                pass.draw_indexed(ground.index_count, 1, 0);
+            }
+
+            // Draw duck
+            for draw in self.primary_draws(|_| true) {
+                let mesh = &self.meshes[draw.mesh];
+                push_shadow(&mut pass, &draw.model, cascade as i32);
+                pass.bind_vertex_buffers(&[self.mesh_vertex_buffer(draw.mesh)], &[0]);
+                pass.bind_index_buffer(mesh.index_buffer, 0, vk::IndexType::UINT32);
+                pass.draw_indexed(mesh.index_count, 1, 0);
+            }
+
+            // And the other instances, one instanced draw per mesh
+            if let Some(models) = self.instance_models {
+                pass.bind_pipeline(PipelineBinding { pipeline: self.shadow_instanced_pipeline, ..shadow_pipeline });
+                push_shadow(&mut pass, &Mat4::IDENTITY, cascade as i32);
+                for run in &self.instance_runs {
+                    let mesh = &self.meshes[run.mesh];
+                    let (buffers, offsets) = self.instance_vertex_buffers(run, models, 0);
+                    pass.bind_vertex_buffers(&buffers, &offsets);
+                    pass.bind_index_buffer(mesh.index_buffer, 0, vk::IndexType::UINT32);
+                    pass.draw_indexed(mesh.index_count, run.models.len() as u32, 0);
+                }
+            }
+        }
+
+        let barrier_to_sample = vk::ImageMemoryBarrier::default()
+            .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.shadow_map.depth().image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: SHADOW_CASCADE_COUNT as u32,
+            });
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            std::slice::from_ref(&barrier_to_sample),
+        );
+    }
+
+    unsafe fn begin_scene_pass(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        framebuffer: vk::Framebuffer,
+        extent: vk::Extent2D,
+    ) {
         let clear_values = [
             vk::ClearValue {
-                color: vk::ClearColorValue { float32: [0.53, 0.81, 0.92, 1.0] },
+                color: vk::ClearColorValue { float32: [SKY_COLOR[0], SKY_COLOR[1], SKY_COLOR[2], 1.0] },
             },
             vk::ClearValue {
                 depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
@@ -2260,7 +3070,7 @@ impl GltfRenderer {
         
         let render_pass_info = vk::RenderPassBeginInfo::default()
             .render_pass(self.render_pass)
-            .framebuffer(self.framebuffers[image_index as usize])
+            .framebuffer(framebuffer)
             .render_area(vk::Rect2D {
                 offset: vk::Offset2D { x: 0, y: 0 },
                 extent,
@@ -2268,76 +3078,297 @@ impl GltfRenderer {
             .clear_values(&clear_values);
         
         device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
-        
-        // Bind pipeline
-        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
-        
-        // Set viewport and scissor
-        let viewport = vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: extent.width as f32,
-            height: extent.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0,
-        };
-        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
-        
-        let scissor = vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent,
-        };
-        device.cmd_set_scissor(command_buffer, 0, &[scissor]);
-        
-        // Bind descriptor set
-        device.cmd_bind_descriptor_sets(
-            command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            self.pipeline_layout,
-            0,
-            &[self.descriptor_sets[current_frame]],
-            &[],
-        );
+    }
+
+    /// Vertex buffer to draw mesh `index` from: this frame's skinned output for skinned
+    /// meshes, the static buffer otherwise.
+    fn mesh_vertex_buffer(&self, index: usize) -> vk::Buffer {
+        self.skinning
+            .as_ref()
+            .and_then(|skinning| skinning.vertex_buffer(index))
+            .unwrap_or(self.meshes[index].vertex_buffer)
+    }
+
+    /// Buffers the defragmenter may move: those only bound while recording. Meshes the
+    /// skinning or meshlet passes read through descriptor sets stay where they are.
+    pub fn movable_buffers(&mut self, renderer: &VulkanRenderer) -> Vec<MovableBuffer<'_>> {
+        let pinned: Vec<usize> = self
+            .skinning
+            .iter()
+            .flat_map(|skinning| skinning.meshes.iter().map(|mesh| mesh.mesh_index))
+            .chain(self.meshlets.iter().flat_map(|meshlets| meshlets.meshes.iter().map(|mesh| mesh.mesh_index)))
+            .collect();
+        // Device-local meshes are copied on the GPU, as transfer source and destination
+        let input_usage = Self::acceleration_structure_input_usage(renderer) | upload::TRANSFER_USAGE;
+        let named = self
+            .meshes
+            .iter_mut()
+            .enumerate()
+            .filter(|(i, _)| !pinned.contains(i))
+            .map(|(_, mesh)| (("gltf_vertex_buffer", "gltf_index_buffer"), mesh))
+            .chain(self.ground.iter_mut().map(|mesh| (("ground_vertex_buffer", "ground_index_buffer"), mesh)))
+            .chain(
+                self.preview_sphere
+                    .iter_mut()
+                    .map(|mesh| (("preview_sphere_vertex_buffer", "preview_sphere_index_buffer"), mesh)),
+            );
+        let mut buffers = Vec::new();
+        for ((vertex_name, index_name), mesh) in named {
+            buffers.push(MovableBuffer {
+                name: vertex_name,
+                buffer: &mut mesh.vertex_buffer,
+                allocation: &mut mesh.vertex_allocation,
+                size: mesh.vertex_count as u64 * std::mem::size_of::<GltfVertex>() as u64,
+                usage: vk::BufferUsageFlags::VERTEX_BUFFER | input_usage,
+            });
+            buffers.push(MovableBuffer {
+                name: index_name,
+                buffer: &mut mesh.index_buffer,
+                allocation: &mut mesh.index_allocation,
+                size: mesh.index_count as u64 * std::mem::size_of::<u32>() as u64,
+                usage: vk::BufferUsageFlags::INDEX_BUFFER | input_usage,
+            });
+        }
+        buffers
+    }
+
+    /// The GPU-driven pass, unless it is off or meshlets already draw the static meshes
+    fn active_gpu_driven(&self) -> Option<&GpuDrivenPass> {
+        if self.meshlets.as_ref().is_some_and(|m| m.enabled) {
+            return None;
+        }
+        self.gpu_driven.as_ref().filter(|g| g.enabled)
+    }
 
-        unsafe fn push_model(
-            device: &ash::Device,
-            command_buffer: vk::CommandBuffer,
-            pipeline_layout: vk::PipelineLayout,
-            model: &Mat4,
-            use_texture: bool,
-        ) {
+    /// Draw the ground and the model meshes on `layers` with `pipeline`, a pipeline built for
+    /// `pipeline_layout` (e.g. the AOV pass), into the active render pass. Each draw pushes
+    /// its object ID: 1 for the ground, 2 + mesh index for the model. Meshes are drawn
+    /// directly, without GPU culling or meshlets; alpha-masked meshes get their cutoff, the
+    /// rest a cutoff of 0.
+    pub fn draw_object_ids(
+        &self,
+        pass: &mut RenderPassEncoder,
+        extent: vk::Extent2D,
+        descriptor_set: vk::DescriptorSet,
+        pipeline: PipelineBinding,
+        layers: RenderLayers,
+    ) {
+        pass.bind_pipeline(pipeline);
+        pass.set_full_viewport(extent);
+        pass.bind_descriptor_set(0, descriptor_set);
+
+        let ground = self.visible_ground().map(|ground| (1, ground, self.ground_model, ground.vertex_buffer));
+        let meshes = self
+            .mesh_draws(|mesh_layers| mesh_layers.intersects(layers))
+            .map(|draw| (2 + draw.mesh as u32, &self.meshes[draw.mesh], draw.model, self.mesh_vertex_buffer(draw.mesh)));
+        for (object_id, mesh, model, vertex_buffer) in ground.into_iter().chain(meshes) {
+            let material = self.materials.get(mesh.material);
             let pc = GltfPushConstants {
                 model: model.to_cols_array_2d(),
-                use_texture: if use_texture { 1 } else { 0 },
-                _pad: [0; 3],
+                use_texture: if material.base_color_texture.is_some() { 1 } else { 0 },
+                alpha_cutoff: material.alpha_cutoff,
+                object_id,
+                texture_slot: 0,
             };
-            let bytes = std::slice::from_raw_parts(
-                (&pc as *const GltfPushConstants) as *const u8,
-                std::mem::size_of::<GltfPushConstants>(),
-            );
-            device.cmd_push_constants(
-                command_buffer,
-                pipeline_layout,
-                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
-                0,
-                bytes,
-            );
+            pass.push_constants(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT, 0, &pc);
+            self.materials.bind(pass, 1, mesh.material);
+            pass.bind_vertex_buffers(&[vertex_buffer], &[0]);
+            pass.bind_index_buffer(mesh.index_buffer, 0, vk::IndexType::UINT32);
+            pass.draw_indexed(mesh.index_count, 1, 0);
+        }
+    }
+
+    /// `pipeline`, one of the scene pipelines, as bound through a command encoder
+    fn scene_pipeline(&self, pipeline: vk::Pipeline) -> PipelineBinding {
+        PipelineBinding {
+            pipeline,
+            layout: self.pipeline_layout,
+            render_pass: self.render_pass,
+            push_constant_size: std::mem::size_of::<GltfPushConstants>() as u32,
+        }
+    }
+
+    /// The primary instance's mesh draws of a view showing `layers`, in draw order for its
+    /// camera `view_proj`, see [`draw_order`]. Draws outside the camera's frustum are left
+    /// out, but for the GPU-driven ones, drawn in batches of their own.
+    fn draw_order(&self, view_proj: &Mat4, layers: RenderLayers) -> Vec<MeshDraw> {
+        let frustum = Frustum::from_view_proj(view_proj);
+        let gpu_driven = self.active_gpu_driven();
+        let (shown, culled): (Vec<MeshDraw>, Vec<MeshDraw>) = self
+            .primary_draws(|mesh_layers| mesh_layers.intersects(layers))
+            .partition(|draw| {
+                gpu_driven.is_some_and(|g| g.draws_mesh(draw.mesh)) || self.in_frustum(&frustum, draw.mesh, &draw.model)
+            });
+        draw_stats::culled(culled.len() as u32);
+        let mut keyed: Vec<(u64, MeshDraw)> = shown
+            .into_iter()
+            .map(|draw| {
+                let mesh = &self.meshes[draw.mesh];
+                let material = mesh.material.index().min(u16::MAX as usize) as u16;
+                let depth = draw_order::view_depth(view_proj, &draw.model, mesh.center);
+                (draw_order::opaque_key(mesh.permutation.sort_rank(), material, depth), draw)
+            })
+            .collect();
+        keyed.sort_by_key(|&(key, _)| key);
+        keyed.into_iter().map(|(_, draw)| draw).collect()
+    }
+
+    /// Draw the ground and the model meshes on `layers` into the active scene render pass,
+    /// sorted for the camera `view_proj`, with the impostors of frame `frame_index`.
+    unsafe fn draw_scene(
+        &self,
+        pass: &mut RenderPassEncoder,
+        extent: vk::Extent2D,
+        descriptor_set: vk::DescriptorSet,
+        view_proj: &Mat4,
+        layers: RenderLayers,
+        frame_index: usize,
+    ) {
+        if let Some(skybox) = &self.skybox {
+            skybox.draw(pass, extent, view_proj);
         }
 
+        let variant = self.shader_variant;
+        pass.bind_pipeline(self.scene_pipeline(self.materials.pipeline(variant, GltfPermutation::default())));
+        pass.set_full_viewport(extent);
+        pass.bind_descriptor_set(0, descriptor_set);
+
+        // Push constants of a draw with `material`, bound as set 1. Returns the draw's
+        // texture array slot.
+        let bind_material = |pass: &mut RenderPassEncoder, model: &Mat4, handle: MaterialHandle| {
+            let material = self.materials.get(handle);
+            let texture_slot = self.texture_arrays.slot(material.base_color_texture);
+            let pc = GltfPushConstants {
+                model: model.to_cols_array_2d(),
+                use_texture: if material.base_color_texture.is_some() { 1 } else { 0 },
+                alpha_cutoff: material.alpha_cutoff,
+                object_id: 0,
+                texture_slot,
+            };
+            pass.push_constants(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT, 0, &pc);
+            self.materials.bind(pass, 1, handle);
+            texture_slot
+        };
+
         // Draw ground
-        if let Some(ground) = &self.ground {
-            push_model(device, command_buffer, self.pipeline_layout, &self.ground_model, false);
-            device.cmd_bind_vertex_buffers(command_buffer, 0, &[ground.vertex_buffer], &[0]);
-            device.cmd_bind_index_buffer(command_buffer, ground.index_buffer, 0, vk::IndexType::UINT32);
-            device.cmd_draw_indexed(command_buffer, ground.index_count, 1, 0, 0, 0);
+        if let Some(ground) = self.visible_ground() {
+            let slot = bind_material(pass, &self.ground_model, ground.material);
+            pass.bind_vertex_buffers(&[ground.vertex_buffer], &[0]);
+            pass.bind_index_buffer(ground.index_buffer, 0, vk::IndexType::UINT32);
+            let first_instance = slot | planar_reflection::PLANAR_REFLECTION_INSTANCE_BIT;
+            pass.draw_indexed_from_instance(ground.index_count, 0, first_instance);
+        }
+        
+        // Draw duck meshes in sort order; the encoder switches pipelines only when the
+        // material permutation changes
+        let meshlets = self.meshlets.as_ref();
+        let gpu_driven = self.active_gpu_driven();
+        let order = self.draw_order(view_proj, layers);
+        for draw in &order {
+            let (i, mesh) = (draw.mesh, &self.meshes[draw.mesh]);
+            if meshlets.and_then(|m| m.mesh(i)).is_some() || gpu_driven.is_some_and(|g| g.draws_mesh(i)) {
+                continue;
+            }
+            pass.bind_pipeline(self.scene_pipeline(self.materials.pipeline(variant, mesh.permutation)));
+            let slot = bind_material(pass, &draw.model, mesh.material);
+            pass.bind_vertex_buffers(&[self.mesh_vertex_buffer(i)], &[0]);
+            pass.bind_index_buffer(mesh.index_buffer, 0, vk::IndexType::UINT32);
+            pass.draw_indexed_from_instance(mesh.index_count, 0, slot);
+        }
+        
+        // Static meshes as culled indirect draws, one call per pipeline and material. The
+        // draws carry their own texture array slots.
+        if let Some(gpu_driven) = gpu_driven {
+            pass.bind_vertex_buffers(&[gpu_driven.vertex_buffer], &[0]);
+            pass.bind_index_buffer(gpu_driven.index_buffer, 0, vk::IndexType::UINT32);
+            for (bucket_index, bucket) in gpu_driven.buckets.iter().enumerate() {
+                pass.bind_pipeline(self.scene_pipeline(self.materials.pipeline(variant, bucket.permutation)));
+                bind_material(pass, &self.duck_model, bucket.material);
+                gpu_driven.draw_bucket(pass, bucket_index);
+            }
+        }
+
+        // The other instances, one instanced draw per mesh and layers: culled on the GPU
+        // into an indirect draw, or else on the CPU into one draw per run of instances in
+        // view. The texture array slot comes with the push constants, gl_InstanceIndex
+        // counting the instances.
+        let frustum = Frustum::from_view_proj(view_proj);
+        let instance_culling = self.instance_culling.as_ref().filter(|culling| culling.active());
+        if let Some(models) = self.instance_models {
+            for (run_index, run) in self.instance_runs.iter().enumerate() {
+                if !run.layers.intersects(layers) {
+                    continue;
+                }
+                let visible = instance_culling.is_none().then(|| self.visible_instances(&frustum, run));
+                if visible.as_ref().is_some_and(|ranges| ranges.is_empty()) {
+                    continue;
+                }
+                let mesh = &self.meshes[run.mesh];
+                let permutation = mesh.permutation | GltfPermutation::INSTANCED;
+                pass.bind_pipeline(self.scene_pipeline(self.materials.pipeline(variant, permutation)));
+                bind_material(pass, &Mat4::IDENTITY, mesh.material);
+                pass.bind_index_buffer(mesh.index_buffer, 0, vk::IndexType::UINT32);
+                if let Some(instance_culling) = instance_culling {
+                    instance_culling.draw(pass, run_index, run.first, self.mesh_vertex_buffer(run.mesh));
+                }
+                for range in visible.into_iter().flatten() {
+                    let (buffers, offsets) = self.instance_vertex_buffers(run, models, range.start);
+                    pass.bind_vertex_buffers(&buffers, &offsets);
+                    pass.draw_indexed(mesh.index_count, range.len() as u32, 0);
+                }
+            }
+        }
+
+        // Far instances as one camera-facing quad each, the same ones in every view
+        if layers.intersects(RenderLayers::default()) {
+            for impostors in self.impostors.values().flatten() {
+                impostors.draw(pass, extent, frame_index, *view_proj, self.camera_position);
+            }
+        }
+
+        // Outlines of cel shaded meshes, over every path the meshes were drawn with
+        if self.toon.outline_width > 0.0 {
+            let outlined = self
+                .mesh_draws(|mesh_layers| mesh_layers.intersects(layers))
+                .filter(|draw| self.toon.enabled || self.materials.get(self.meshes[draw.mesh].material).toon)
+                .filter(|draw| self.in_frustum(&frustum, draw.mesh, &draw.model));
+            // Begun again whenever the model matrix changes, around animated meshes and
+            // between instances
+            let mut begun: Option<Mat4> = None;
+            for draw in outlined {
+                let mesh = &self.meshes[draw.mesh];
+                if begun != Some(draw.model) {
+                    self.outline.begin(pass, descriptor_set, &self.toon, &draw.model, extent);
+                    begun = Some(draw.model);
+                }
+                self.outline.draw(pass, self.mesh_vertex_buffer(draw.mesh), mesh.index_buffer, mesh.index_count);
+            }
         }
         
-        // Draw duck meshes
-        push_model(device, command_buffer, self.pipeline_layout, &self.duck_model, true);
-        for mesh in &self.meshes {
-            device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer], &[0]);
-            device.cmd_bind_index_buffer(command_buffer, mesh.index_buffer, 0, vk::IndexType::UINT32);
-            device.cmd_draw_indexed(command_buffer, mesh.index_count, 1, 0, 0, 0);
+        // Static meshes as meshlets. The meshlet layout's push constant ranges differ, so the
+        // scene and material sets are bound again for it.
+        let Some(meshlets) = meshlets.filter(|m| m.enabled) else {
+            return;
+        };
+        for meshlet_mesh in order.iter().filter_map(|draw| meshlets.mesh(draw.mesh)) {
+            let mesh = &self.meshes[meshlet_mesh.mesh_index];
+            let Some(&pipeline) = meshlets.pipelines.get(&(variant, mesh.permutation)) else {
+                continue;
+            };
+            let material = self.materials.get(mesh.material);
+            pass.bind_pipeline(meshlets.pipeline_binding(pipeline, self.render_pass));
+            pass.bind_descriptor_set(0, descriptor_set);
+            self.materials.bind(pass, 1, mesh.material);
+            meshlets.draw(
+                pass,
+                meshlet_mesh,
+                &self.duck_model,
+                material.base_color_texture.is_some(),
+                self.texture_arrays.slot(material.base_color_texture),
+                material.alpha_cutoff,
+            );
+            // Task shaders cull meshlets on the GPU; this counts the whole mesh
+            draw_stats::draw(1, mesh.index_count as u64 / 3);
         }
     }
     
@@ -2348,14 +3379,17 @@ impl GltfRenderer {
         image_index: u32,
     ) {
         device.cmd_end_render_pass(command_buffer);
+        if let Some(pipeline_statistics) = &mut self.pipeline_statistics {
+            pipeline_statistics.end(device, command_buffer);
+        }
 
         // Shadow history TAA: finalize storage write target
         let idx = image_index as usize;
         let ping = self.shadow_history_pingpong[idx] as usize;
         let write_image = if ping == 0 {
-            self.shadow_history_images_b[idx]
+            self.shadow_history_b[idx].color().image
         } else {
-            self.shadow_history_images_a[idx]
+            self.shadow_history_a[idx].color().image
         };
 
         let to_read = vk::ImageMemoryBarrier::default()
@@ -2387,19 +3421,55 @@ impl GltfRenderer {
     }
     
     pub unsafe fn cleanup(&mut self, renderer: &VulkanRenderer) {
-        // Cleanup ground
-        if let Some(mut ground) = self.ground.take() {
-            renderer.device.destroy_buffer(ground.vertex_buffer, None);
-            if let Some(allocation) = ground.vertex_allocation.take() {
+        // Cleanup ground and preview sphere
+        for mut mesh in self.ground.take().into_iter().chain(self.preview_sphere.take()) {
+            renderer.device.destroy_buffer(mesh.vertex_buffer, None);
+            if let Some(allocation) = mesh.vertex_allocation.take() {
                 let _ = renderer.allocator.lock().free(allocation);
             }
 
-            renderer.device.destroy_buffer(ground.index_buffer, None);
-            if let Some(allocation) = ground.index_allocation.take() {
+            renderer.device.destroy_buffer(mesh.index_buffer, None);
+            if let Some(allocation) = mesh.index_allocation.take() {
                 let _ = renderer.allocator.lock().free(allocation);
             }
         }
 
+        if let Some(skinning) = &mut self.skinning {
+            skinning.cleanup(renderer);
+        }
+        self.skinning = None;
+
+        if let Some(acceleration_structure) = &mut self.acceleration_structure {
+            acceleration_structure.destroy(renderer);
+        }
+        self.acceleration_structure = None;
+
+        if let Some(meshlets) = &mut self.meshlets {
+            meshlets.destroy(renderer);
+        }
+        self.meshlets = None;
+
+        if let Some(gpu_driven) = &mut self.gpu_driven {
+            gpu_driven.destroy(renderer);
+        }
+        self.gpu_driven = None;
+
+        if let Some(instance_culling) = &mut self.instance_culling {
+            instance_culling.destroy(renderer);
+        }
+        self.instance_culling = None;
+
+        for (_, impostors) in self.impostors.drain() {
+            if let Some(mut impostors) = impostors {
+                impostors.destroy(renderer);
+            }
+        }
+
+        if let Some(pipeline_statistics) = &mut self.pipeline_statistics {
+            pipeline_statistics.destroy(&renderer.device);
+        }
+        self.pipeline_statistics = None;
+        
         // Cleanup meshes
         for mesh in &mut self.meshes {
             renderer.device.destroy_buffer(mesh.vertex_buffer, None);
@@ -2413,34 +3483,21 @@ impl GltfRenderer {
             }
         }
         
+        self.lightmap.destroy(renderer);
+        self.reflection_probes.destroy(renderer);
+        self.planar_reflection_placeholder.destroy(renderer);
+        self.blue_noise.destroy(renderer);
+        self.virtual_texture.destroy(renderer);
+        self.texture_arrays.destroy(renderer);
+        
         // Cleanup texture
         if let Some(tex) = &mut self.texture {
-            renderer.device.destroy_sampler(tex.sampler, None);
-            renderer.device.destroy_image_view(tex.image_view, None);
-            renderer.device.destroy_image(tex.image, None);
-            if let Some(allocation) = tex.allocation.take() {
-                let _ = renderer.allocator.lock().free(allocation);
-            }
-        }
-        
-        // Cleanup uniform buffers
-        for (buffer, allocation) in self.uniform_buffers.iter().zip(self.uniform_allocations.iter_mut()) {
-            renderer.device.destroy_buffer(*buffer, None);
-            if let Some(alloc) = allocation.take() {
-                let _ = renderer.allocator.lock().free(alloc);
-            }
+            tex.destroy(renderer);
         }
         
         // Cleanup depth resources (one per swapchain image)
-        for ((&image, &view), allocation) in self.depth_images.iter()
-            .zip(self.depth_image_views.iter())
-            .zip(self.depth_allocations.iter_mut())
-        {
-            renderer.device.destroy_image_view(view, None);
-            renderer.device.destroy_image(image, None);
-            if let Some(alloc) = allocation.take() {
-                let _ = renderer.allocator.lock().free(alloc);
-            }
+        for target in &mut self.depth_targets {
+            target.destroy(renderer);
         }
 
         // Cleanup shadow map resources
@@ -2449,58 +3506,27 @@ impl GltfRenderer {
         }
         renderer.device.destroy_render_pass(self.shadow_render_pass, None);
         renderer.device.destroy_pipeline(self.shadow_pipeline, None);
+        renderer.device.destroy_pipeline(self.shadow_instanced_pipeline, None);
+        self.outline.destroy(&renderer.device);
+        if let Some(mut skybox) = self.skybox.take() {
+            skybox.destroy(renderer);
+        }
         renderer.device.destroy_pipeline_layout(self.shadow_pipeline_layout, None);
 
-        renderer.device.destroy_sampler(self.shadow_sampler, None);
-        renderer.device.destroy_sampler(self.shadow_depth_sampler, None);
-
         // Cleanup shadow history resources
-        renderer.device.destroy_sampler(self.shadow_history_sampler, None);
-        for (&view_a, &view_b) in self
-            .shadow_history_views_a
-            .iter()
-            .zip(self.shadow_history_views_b.iter())
-        {
-            renderer.device.destroy_image_view(view_a, None);
-            renderer.device.destroy_image_view(view_b, None);
-        }
-        for (&img_a, alloc_a) in self
-            .shadow_history_images_a
-            .iter()
-            .zip(self.shadow_history_allocations_a.iter_mut())
-        {
-            renderer.device.destroy_image(img_a, None);
-            if let Some(alloc) = alloc_a.take() {
-                let _ = renderer.allocator.lock().free(alloc);
-            }
-        }
-        for (&img_b, alloc_b) in self
-            .shadow_history_images_b
-            .iter()
-            .zip(self.shadow_history_allocations_b.iter_mut())
-        {
-            renderer.device.destroy_image(img_b, None);
-            if let Some(alloc) = alloc_b.take() {
-                let _ = renderer.allocator.lock().free(alloc);
-            }
+        for target in self.shadow_history_a.iter_mut().chain(&mut self.shadow_history_b) {
+            target.destroy(renderer);
         }
 
-        for &view in &self.shadow_layer_views {
-            renderer.device.destroy_image_view(view, None);
-        }
-        renderer.device.destroy_image_view(self.shadow_image_view, None);
-        renderer.device.destroy_image(self.shadow_image, None);
-        if let Some(allocation) = self.shadow_allocation.take() {
-            let _ = renderer.allocator.lock().free(allocation);
-        }
+        self.shadow_map.destroy(renderer);
         
         // Cleanup framebuffers
         for &fb in &self.framebuffers {
             renderer.device.destroy_framebuffer(fb, None);
         }
         
-        // Cleanup pipeline and layout
-        renderer.device.destroy_pipeline(self.pipeline, None);
+        // Cleanup materials, which own every built pipeline variant, and the layout
+        self.materials.destroy(renderer);
         renderer.device.destroy_pipeline_layout(self.pipeline_layout, None);
         renderer.device.destroy_render_pass(self.render_pass, None);
         renderer.device.destroy_descriptor_pool(self.descriptor_pool, None);
@@ -2516,41 +3542,24 @@ impl GltfRenderer {
             renderer.device.destroy_framebuffer(fb, None);
         }
         
-        // Cleanup old depth resources (one per swapchain image)
-        for ((&image, &view), allocation) in self.depth_images.iter()
-            .zip(self.depth_image_views.iter())
-            .zip(self.depth_allocations.iter_mut())
-        {
-            renderer.device.destroy_image_view(view, None);
-            renderer.device.destroy_image(image, None);
-            if let Some(alloc) = allocation.take() {
-                renderer.allocator.lock().free(alloc)?;
-            }
-        }
-        
-        // Recreate depth resources (one per swapchain image)
-        let depth_format = vk::Format::D32_SFLOAT;
+        // Resize depth resources (one per swapchain image)
         let image_count = renderer.swapchain_image_views.len();
-        self.depth_images.clear();
-        self.depth_image_views.clear();
-        self.depth_allocations.clear();
-        
-        for _i in 0..image_count {
-            let (depth_image, depth_image_view, depth_allocation) = Self::create_depth_resources(
-                renderer,
-                renderer.swapchain_extent.width,
-                renderer.swapchain_extent.height,
-                depth_format,
-            )?;
-            self.depth_images.push(depth_image);
-            self.depth_image_views.push(depth_image_view);
-            self.depth_allocations.push(Some(depth_allocation));
-        }
+        render_target::resize_targets(
+            renderer,
+            &mut self.depth_targets,
+            image_count,
+            "depth_buffer",
+            RenderTargetDesc::depth(
+                vk::Format::D32_SFLOAT,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            ),
+            renderer.swapchain_extent,
+        )?;
         
         // Recreate framebuffers (each with its own depth image view)
         self.framebuffers.clear();
         for (i, &color_view) in renderer.swapchain_image_views.iter().enumerate() {
-            let attachments = [color_view, self.depth_image_views[i]];
+            let attachments = [color_view, self.depth_targets[i].depth().view];
             let framebuffer_info = vk::FramebufferCreateInfo::default()
                 .render_pass(self.render_pass)
                 .attachments(&attachments)
@@ -2560,78 +3569,22 @@ impl GltfRenderer {
             self.framebuffers.push(renderer.device.create_framebuffer(&framebuffer_info, None)?);
         }
 
-        // Recreate shadow history resources (size depends on swapchain extent)
-        renderer.device.destroy_sampler(self.shadow_history_sampler, None);
-        for (&view_a, &view_b) in self
-            .shadow_history_views_a
-            .iter()
-            .zip(self.shadow_history_views_b.iter())
-        {
-            renderer.device.destroy_image_view(view_a, None);
-            renderer.device.destroy_image_view(view_b, None);
-        }
-        for ((&img_a, alloc_a), (&img_b, alloc_b)) in self
-            .shadow_history_images_a
-            .iter()
-            .zip(self.shadow_history_allocations_a.iter_mut())
-            .zip(
-                self.shadow_history_images_b
-                    .iter()
-                    .zip(self.shadow_history_allocations_b.iter_mut()),
-            )
-        {
-            renderer.device.destroy_image(img_a, None);
-            if let Some(alloc) = alloc_a.take() {
-                renderer.allocator.lock().free(alloc)?;
-            }
-            renderer.device.destroy_image(img_b, None);
-            if let Some(alloc) = alloc_b.take() {
-                renderer.allocator.lock().free(alloc)?;
-            }
-        }
-
-        self.shadow_history_images_a.clear();
-        self.shadow_history_views_a.clear();
-        self.shadow_history_allocations_a.clear();
-        self.shadow_history_images_b.clear();
-        self.shadow_history_views_b.clear();
-        self.shadow_history_allocations_b.clear();
-        self.shadow_history_pingpong.clear();
-
-        let (
-            shadow_history_images_a,
-            shadow_history_views_a,
-            shadow_history_allocations_a,
-            shadow_history_images_b,
-            shadow_history_views_b,
-            shadow_history_allocations_b,
-            shadow_history_sampler,
-            shadow_history_pingpong,
-        ) = Self::create_shadow_history_resources(
-            renderer,
-            renderer.swapchain_extent.width,
-            renderer.swapchain_extent.height,
-            renderer.swapchain_image_views.len(),
-        )?;
-        self.shadow_history_images_a = shadow_history_images_a;
-        self.shadow_history_views_a = shadow_history_views_a;
-        self.shadow_history_allocations_a = shadow_history_allocations_a;
-        self.shadow_history_images_b = shadow_history_images_b;
-        self.shadow_history_views_b = shadow_history_views_b;
-        self.shadow_history_allocations_b = shadow_history_allocations_b;
-        self.shadow_history_sampler = shadow_history_sampler;
-        self.shadow_history_pingpong = shadow_history_pingpong;
+        // Resize shadow history resources (size depends on swapchain extent)
+        let extent = renderer.swapchain_extent;
+        Self::resize_history_targets(renderer, &mut self.shadow_history_a, image_count, extent)?;
+        Self::resize_history_targets(renderer, &mut self.shadow_history_b, image_count, extent)?;
+        self.shadow_history_pingpong = vec![0; image_count];
 
         // Refresh descriptor sets with initial history views (render() overwrites per-frame anyway)
-        if !self.shadow_history_views_a.is_empty() {
+        if !self.shadow_history_a.is_empty() {
             let history_read = vk::DescriptorImageInfo {
                 sampler: self.shadow_history_sampler,
-                image_view: self.shadow_history_views_a[0],
+                image_view: self.shadow_history_a[0].color().view,
                 image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             };
             let history_write = vk::DescriptorImageInfo {
                 sampler: vk::Sampler::null(),
-                image_view: self.shadow_history_views_b[0],
+                image_view: self.shadow_history_b[0].color().view,
                 image_layout: vk::ImageLayout::GENERAL,
             };
 
@@ -2654,6 +3607,549 @@ impl GltfRenderer {
         
         Ok(())
     }
+    
+    /// Create an extra view of the scene (e.g. for a secondary window) with its own camera
+    /// uniforms. Views share meshes, textures and the shadow map with the main view.
+    pub unsafe fn create_view(
+        &self,
+        renderer: &VulkanRenderer,
+        extent: vk::Extent2D,
+    ) -> Result<GltfView, Box<dyn std::error::Error>> {
+        let mut pool_sizes = vec![
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: (MAX_FRAMES_IN_FLIGHT * (13 + texture_array::MAX_TEXTURE_ARRAYS)) as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
+            },
+        ];
+        if self.acceleration_structure.is_some() {
+            pool_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
+            });
+        }
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&pool_sizes)
+            .max_sets(MAX_FRAMES_IN_FLIGHT as u32);
+        let descriptor_pool = renderer.device.create_descriptor_pool(&pool_info, None)?;
+        
+        let layouts = vec![self.descriptor_set_layout; MAX_FRAMES_IN_FLIGHT];
+        let alloc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&layouts);
+        let descriptor_sets = renderer.device.allocate_descriptor_sets(&alloc_info)?;
+        
+        let ubo_size = std::mem::size_of::<GltfUniformBufferObject>() as u64;
+        let mut uniform_buffers = Vec::new();
+        let mut uniform_allocations = Vec::new();
+        
+        for (i, &set) in descriptor_sets.iter().enumerate() {
+            let buffer_info = vk::BufferCreateInfo::default()
+                .size(ubo_size)
+                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            
+            let buffer = renderer.device.create_buffer(&buffer_info, None)?;
+            let requirements = renderer.device.get_buffer_memory_requirements(buffer);
+            
+            let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+                name: &format!("glTF View Uniform Buffer {}", i),
+                requirements,
+                location: MemoryLocation::CpuToGpu,
+                linear: true,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            })?;
+            
+            renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+            
+            let buffer_info_desc = vk::DescriptorBufferInfo {
+                buffer,
+                offset: 0,
+                range: ubo_size,
+            };
+            let texture = self.texture.as_ref().ok_or("glTF renderer has no texture")?;
+            let image_info = vk::DescriptorImageInfo {
+                sampler: texture.sampler,
+                image_view: texture.image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            };
+            let shadow_image_info = vk::DescriptorImageInfo {
+                sampler: self.shadow_sampler,
+                image_view: self.shadow_map.depth().view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            };
+            let shadow_depth_image_info = vk::DescriptorImageInfo {
+                sampler: self.shadow_depth_sampler,
+                image_view: self.shadow_map.depth().view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            };
+            
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(std::slice::from_ref(&buffer_info_desc)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(&image_info)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(2)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(&shadow_image_info)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(3)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(&shadow_depth_image_info)),
+            ];
+            renderer.device.update_descriptor_sets(&descriptor_writes, &[]);
+            
+            uniform_buffers.push(buffer);
+            uniform_allocations.push(Some(allocation));
+        }
+        if let Some(acceleration_structure) = &self.acceleration_structure {
+            Self::write_tlas_descriptors(&renderer.device, acceleration_structure, &descriptor_sets);
+        }
+        Self::write_lightmap_descriptors(&renderer.device, &self.lightmap, &descriptor_sets);
+        Self::write_reflection_probe_descriptors(&renderer.device, &self.reflection_probes, &descriptor_sets);
+        // Views don't sample the planar reflection, which one of them may be rendering, nor
+        // the main view's bounce light
+        for binding in [15, 17] {
+            Self::write_image_descriptors(
+                &renderer.device,
+                binding,
+                self.planar_reflection_placeholder.descriptor_info(),
+                &descriptor_sets,
+            );
+        }
+        Self::write_image_descriptors(&renderer.device, 16, self.blue_noise.descriptor_info(), &descriptor_sets);
+        for (frame_index, &set) in descriptor_sets.iter().enumerate() {
+            self.virtual_texture.write_descriptors(&renderer.device, set, frame_index);
+            self.texture_arrays.write_descriptors(&renderer.device, set);
+        }
+        
+        let mut view = GltfView {
+            layers: RenderLayers::default(),
+            extent,
+            descriptor_pool,
+            descriptor_sets,
+            uniform_buffers,
+            uniform_allocations,
+            history: Vec::new(),
+            history_sampler: vk::Sampler::null(),
+            view_proj: Mat4::IDENTITY,
+        };
+        Self::create_view_history(renderer, &mut view)?;
+        
+        Ok(view)
+    }
+    
+    /// Size the view's shadow history targets to `view.extent` and bind them.
+    /// Views don't run shadow TAA, but gltf.frag always writes history, so it needs a target.
+    unsafe fn create_view_history(
+        renderer: &VulkanRenderer,
+        view: &mut GltfView,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Self::resize_history_targets(renderer, &mut view.history, 2, view.extent)?;
+        let sampler = renderer.sampler(SamplerDesc::nearest(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        view.history_sampler = sampler;
+        
+        let history_read = vk::DescriptorImageInfo {
+            sampler,
+            image_view: view.history[0].color().view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let history_write = vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: view.history[1].color().view,
+            image_layout: vk::ImageLayout::GENERAL,
+        };
+        // No sampleable scene depth for views: point contact shadows at the cleared
+        // history image (depth = 1.0 everywhere), which effectively disables them.
+        let scene_depth_linear = vk::DescriptorImageInfo {
+            sampler,
+            image_view: view.history[0].color().view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        
+        for &set in &view.descriptor_sets {
+            let writes = [
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(4)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(&history_read)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(5)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(std::slice::from_ref(&history_write)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(6)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(&scene_depth_linear)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(7)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(&scene_depth_linear)),
+            ];
+            renderer.device.update_descriptor_sets(&writes, &[]);
+        }
+        
+        Ok(())
+    }
+    
+    /// Resize a view's extent-dependent resources. The device must be idle.
+    pub unsafe fn resize_view(
+        &self,
+        renderer: &VulkanRenderer,
+        view: &mut GltfView,
+        extent: vk::Extent2D,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        view.extent = extent;
+        Self::create_view_history(renderer, view)
+    }
+    
+    /// Destroy a view. The device must be idle.
+    pub unsafe fn destroy_view(
+        &self,
+        renderer: &VulkanRenderer,
+        mut view: GltfView,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for target in &mut view.history {
+            target.destroy(renderer);
+        }
+        for &buffer in &view.uniform_buffers {
+            renderer.device.destroy_buffer(buffer, None);
+        }
+        for alloc in view.uniform_allocations.drain(..).flatten() {
+            renderer.allocator.lock().free(alloc)?;
+        }
+        renderer.device.destroy_descriptor_pool(view.descriptor_pool, None);
+        Ok(())
+    }
+    
+    /// Update a view's uniforms. Call after `update_uniform_buffer` so model transforms are current.
+    pub unsafe fn update_view_uniform_buffer(
+        &self,
+        view: &mut GltfView,
+        frame_index: usize,
+        camera: &ViewCamera,
+        debug_cascades: bool,
+        shadow_softness: f32,
+        use_pcss: bool,
+    ) {
+        let debug_flags = [
+            if debug_cascades { 1.0 } else { 0.0 },
+            if use_pcss { 1.0 } else { 0.0 },
+            0.0, // no shadow TAA for extra views
+            (self.shadow_frame_index as f32) % 1024.0,
+        ];
+        let ubo = self.build_uniforms(camera, camera.view_proj(), debug_flags, shadow_softness, false);
+        
+        if let Some(allocation) = &view.uniform_allocations[frame_index] {
+            let ptr = allocation.mapped_ptr().unwrap().as_ptr() as *mut GltfUniformBufferObject;
+            std::ptr::copy_nonoverlapping(&ubo, ptr, 1);
+        }
+        view.view_proj = camera.view_proj();
+    }
+    
+    /// Record shadow + scene passes for `view` into `framebuffer`, which must be compatible
+    /// with `self.render_pass` (same color format, D32 depth).
+    pub unsafe fn render_view(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        view: &GltfView,
+        framebuffer: vk::Framebuffer,
+        frame_index: usize,
+    ) {
+        let descriptor_set = view.descriptor_sets[frame_index];
+        let history_write = view.history[1].color().image;
+        
+        if let Some(gpu_driven) = self.active_gpu_driven() {
+            gpu_driven.record_culling(device, command_buffer, &view.view_proj, &self.duck_model);
+        }
+        if let Some(instance_culling) = &self.instance_culling {
+            instance_culling.record_culling(device, command_buffer, &view.view_proj);
+        }
+        
+        let mut encoder = CommandEncoder::new(device, command_buffer);
+        if !self.shader_variant.ray_query_shadows {
+            self.record_shadow_pass(&mut encoder, descriptor_set);
+        }
+        
+        Self::history_barrier(
+            device,
+            command_buffer,
+            history_write,
+            (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ),
+            (vk::ImageLayout::GENERAL, vk::AccessFlags::SHADER_WRITE),
+        );
+        
+        self.begin_scene_pass(device, command_buffer, framebuffer, view.extent);
+        self.draw_scene(
+            &mut encoder.continue_render_pass(self.render_pass),
+            view.extent,
+            descriptor_set,
+            &view.view_proj,
+            view.layers,
+            frame_index,
+        );
+        device.cmd_end_render_pass(command_buffer);
+        
+        Self::history_barrier(
+            device,
+            command_buffer,
+            history_write,
+            (vk::ImageLayout::GENERAL, vk::AccessFlags::SHADER_WRITE),
+            (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ),
+        );
+    }
+    
+    /// On-screen diameter in pixels of the meshes sampling the scene texture, seen by
+    /// `camera` in a viewport `viewport_height` pixels high
+    pub fn texture_coverage(&self, camera: &ViewCamera, viewport_height: u32) -> f32 {
+        let textured = self.meshes.iter().any(|mesh| self.materials.get(mesh.material).base_color_texture.is_some());
+        if !textured {
+            return 0.0;
+        }
+        let (min, max) = self.model_bounds;
+        let center = self.duck_model.transform_point3((min + max) * 0.5);
+        let scale = self.duck_model.to_scale_rotation_translation().0.max_element();
+        let radius = (max - min).length() * 0.5 * scale;
+        let distance = camera.position.distance(center);
+        if distance <= radius {
+            return f32::MAX; // Inside the bounds, full detail
+        }
+        // proj.y_axis.y is cot(fov / 2) in perspective, negative with Vulkan's flipped Y, and
+        // w grows with the distance; orthographic, w stays 1
+        let w = distance * -camera.proj.z_axis.w + camera.proj.w_axis.w;
+        radius / w * camera.proj.y_axis.y.abs() * viewport_height as f32
+    }
+
+    /// Move the scene texture's resident mips towards what `camera` needs (see
+    /// `texture_streaming`). A move waits for the device to go idle and rewrites the
+    /// texture binding of the renderer's descriptor sets and those of `views`.
+    pub unsafe fn stream_textures(
+        &mut self,
+        renderer: &VulkanRenderer,
+        camera: &ViewCamera,
+        viewport_height: u32,
+        views: &[&GltfView],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let coverage = self.texture_coverage(camera, viewport_height);
+        let Some(streamer) = &mut self.texture_streamer else {
+            return Ok(());
+        };
+        let Some((index, base)) = streamer.plan(&[coverage]) else {
+            return Ok(());
+        };
+        let streamed = &streamer.textures[index];
+        let mut texture = Self::create_texture_with_format(renderer, streamed.mips_from(base), streamed.format)?;
+        texture.sampler = renderer.sampler(self.texture_filter.sampler_desc(texture.mip_levels))?;
+        streamer.textures[index].resident_base = base;
+        
+        renderer.device.device_wait_idle()?;
+        if let Some(mut old) = self.texture.replace(texture) {
+            old.destroy(renderer);
+        }
+        self.write_texture_descriptors(&renderer.device, &self.descriptor_sets);
+        for view in views {
+            self.write_texture_descriptors(&renderer.device, &view.descriptor_sets);
+        }
+        Ok(())
+    }
+    
+    /// Sample the material textures with `filter`. Waits for the device to go idle and
+    /// rewrites the texture bindings of the renderer's descriptor sets and those of `views`.
+    pub unsafe fn set_texture_filter(
+        &mut self,
+        renderer: &VulkanRenderer,
+        filter: TextureFilter,
+        views: &[&GltfView],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if filter == self.texture_filter {
+            return Ok(());
+        }
+        renderer.device.device_wait_idle()?;
+        if let Some(texture) = &mut self.texture {
+            texture.sampler = renderer.sampler(filter.sampler_desc(texture.mip_levels))?;
+        }
+        self.texture_arrays.set_filter(renderer, filter)?;
+        self.materials.write_images(&renderer.device, &self.texture_arrays);
+        self.texture_filter = filter;
+        
+        let view_sets = views.iter().flat_map(|view| &view.descriptor_sets);
+        for &set in self.descriptor_sets.iter().chain(view_sets) {
+            self.write_texture_descriptors(&renderer.device, &[set]);
+            self.texture_arrays.write_descriptors(&renderer.device, set);
+        }
+        Ok(())
+    }
+    
+    /// Point the scene texture binding of `descriptor_sets` at `self.texture`
+    unsafe fn write_texture_descriptors(&self, device: &ash::Device, descriptor_sets: &[vk::DescriptorSet]) {
+        let Some(texture) = &self.texture else {
+            return;
+        };
+        let image_info = vk::DescriptorImageInfo {
+            sampler: texture.sampler,
+            image_view: texture.image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let writes: Vec<_> = descriptor_sets
+            .iter()
+            .map(|&set| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(&image_info))
+            })
+            .collect();
+        device.update_descriptor_sets(&writes, &[]);
+    }
+    
+    /// Record `view` of the preview sphere alone at `MATERIAL_PREVIEW_CENTER`, shaded with
+    /// the current material of mesh `mesh_index`, into `framebuffer`. The shadow pass still
+    /// runs, so the view's cascades are valid, and the color target ends up ready to be
+    /// sampled by the UI.
+    pub unsafe fn render_material_preview(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        view: &GltfView,
+        framebuffer: vk::Framebuffer,
+        color_image: vk::Image,
+        mesh_index: usize,
+    ) {
+        let Some(sphere) = &self.preview_sphere else {
+            return;
+        };
+        let descriptor_set = view.descriptor_sets[0];
+        let history_write = view.history[1].color().image;
+        
+        let mut encoder = CommandEncoder::new(device, command_buffer);
+        if !self.shader_variant.ray_query_shadows {
+            self.record_shadow_pass(&mut encoder, descriptor_set);
+        }
+        
+        Self::history_barrier(
+            device,
+            command_buffer,
+            history_write,
+            (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ),
+            (vk::ImageLayout::GENERAL, vk::AccessFlags::SHADER_WRITE),
+        );
+        
+        self.begin_scene_pass(device, command_buffer, framebuffer, view.extent);
+        {
+            let mut pass = encoder.continue_render_pass(self.render_pass);
+            let handle = self.meshes[mesh_index].material;
+            let material = self.materials.get(handle);
+            pass.bind_pipeline(self.scene_pipeline(self.materials.pipeline(self.shader_variant, material.permutation)));
+            pass.set_full_viewport(view.extent);
+            pass.bind_descriptor_set(0, descriptor_set);
+            self.materials.bind(&mut pass, 1, handle);
+            let pc = GltfPushConstants {
+                model: Mat4::from_translation(MATERIAL_PREVIEW_CENTER).to_cols_array_2d(),
+                use_texture: if material.base_color_texture.is_some() { 1 } else { 0 },
+                alpha_cutoff: material.alpha_cutoff,
+                object_id: 0,
+                texture_slot: 0,
+            };
+            pass.push_constants(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT, 0, &pc);
+            pass.bind_vertex_buffers(&[sphere.vertex_buffer], &[0]);
+            pass.bind_index_buffer(sphere.index_buffer, 0, vk::IndexType::UINT32);
+            pass.draw_indexed_from_instance(sphere.index_count, 0, self.texture_arrays.slot(material.base_color_texture));
+        }
+        device.cmd_end_render_pass(command_buffer);
+        
+        Self::history_barrier(
+            device,
+            command_buffer,
+            history_write,
+            (vk::ImageLayout::GENERAL, vk::AccessFlags::SHADER_WRITE),
+            (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ),
+        );
+        
+        // The scene pass leaves color ready for presentation; the UI samples it instead
+        let barrier = vk::ImageMemoryBarrier::default()
+            .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(color_image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            });
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+    }
+    
+    unsafe fn history_barrier(
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        (old_layout, src_access): (vk::ImageLayout, vk::AccessFlags),
+        (new_layout, dst_access): (vk::ImageLayout, vk::AccessFlags),
+    ) {
+        let barrier = vk::ImageMemoryBarrier::default()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            });
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            std::slice::from_ref(&barrier),
+        );
+    }
 }
 
 impl Drop for GltfRenderer {
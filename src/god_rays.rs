@@ -0,0 +1,236 @@
+//! Screen-space god rays
+//!
+//! Crepuscular rays as a cheap stand-in for volumetric fog outdoors: each pixel of the
+//! finished frame marches towards the sun's screen position through the scene depth,
+//! gathering the open sky around the sun with a decaying weight, and adds the result.
+//! Meshes block the march and cast shafts of shadow through the glow.
+//!
+//! The scene pass keeps its depth for this and leaves it ready to sample. The frame has no
+//! separate tonemapping step, so the rays go on after the scene, under the lens flares,
+//! grain and UI.
+
+use ash::vk;
+use glam::Vec2;
+
+use crate::command_encoder::{CommandEncoder, PipelineBinding};
+use crate::compute::DescriptorWriter;
+use crate::descriptor_buffer::DescriptorBuffer;
+use crate::gltf_renderer::GltfRenderer;
+use crate::pipeline_builder::{BlendMode, GraphicsPipelineBuilder};
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::sampler_cache::SamplerDesc;
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+
+/// Depth samples per pixel
+const SAMPLES: u32 = 48;
+/// How far off screen, in NDC, the sun may be and still send rays onto it
+const MAX_SUN_DISTANCE: f32 = 2.0;
+
+/// How the rays look
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GodRayStyle {
+    pub intensity: f32,  // 0 for no rays
+    pub length: f32,     // How far towards the sun each pixel gathers, 0 to 1
+    pub decay: f32,      // Weight kept from one sample to the next
+    pub sun_radius: f32, // Of the sky around the sun that glows, as a fraction of the screen's height
+}
+
+impl Default for GodRayStyle {
+    fn default() -> Self {
+        Self { intensity: 0.6, length: 0.9, decay: 0.97, sun_radius: 0.2 }
+    }
+}
+
+// Must match shaders/god_rays.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GodRayPushConstants {
+    sun: [f32; 4],    // xy = sun in texture coordinates, z = aspect ratio, w = intensity
+    params: [f32; 4], // x = samples, y = length, z = decay, w = sun radius
+}
+
+pub struct GodRays {
+    pub style: GodRayStyle,
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>, // Per frame in flight, pointed at that frame's depth
+    descriptor_buffer: Option<DescriptorBuffer>, // Instead of the sets where supported, a slot per frame in flight
+    sampler: vk::Sampler,
+}
+
+impl GodRays {
+    /// Pipeline for the renderer's color-only pass, which loads the finished frame
+    pub unsafe fn new(renderer: &VulkanRenderer) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let vert_code = load_shader("post.vert", include_bytes!("../shaders/post.vert.spv"));
+        let frag_code = load_shader("god_rays.frag", include_bytes!("../shaders/god_rays.frag.spv"));
+        let reflections = [&ShaderReflection::reflect(&vert_code)?, &ShaderReflection::reflect(&frag_code)?];
+
+        let bindings = shader_reflection::set_layout_bindings(&reflections, 0)?;
+        let use_descriptor_buffer = renderer.descriptor_buffer_supported;
+        let set_layout_flags = if use_descriptor_buffer {
+            vk::DescriptorSetLayoutCreateFlags::DESCRIPTOR_BUFFER_EXT
+        } else {
+            vk::DescriptorSetLayoutCreateFlags::empty()
+        };
+        let set_layout = device.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::default().flags(set_layout_flags).bindings(&bindings),
+            None,
+        )?;
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &reflections,
+            std::mem::size_of::<GodRayPushConstants>() as u32,
+        )?
+        .into_iter()
+        .collect();
+        let layout = device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(std::slice::from_ref(&set_layout))
+                .push_constant_ranges(&push_constant_ranges),
+            None,
+        )?;
+
+        let (descriptor_pool, descriptor_sets, descriptor_buffer) = if use_descriptor_buffer {
+            let descriptor_buffer = DescriptorBuffer::new(renderer, set_layout, &bindings, MAX_FRAMES_IN_FLIGHT)?;
+            (vk::DescriptorPool::null(), Vec::new(), Some(descriptor_buffer))
+        } else {
+            let pool_size = vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
+            };
+            let descriptor_pool = device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .pool_sizes(std::slice::from_ref(&pool_size))
+                    .max_sets(MAX_FRAMES_IN_FLIGHT as u32),
+                None,
+            )?;
+            let layouts = vec![set_layout; MAX_FRAMES_IN_FLIGHT];
+            let descriptor_sets = device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&layouts),
+            )?;
+            (descriptor_pool, descriptor_sets, None)
+        };
+        let pipeline_flags = if use_descriptor_buffer {
+            vk::PipelineCreateFlags::DESCRIPTOR_BUFFER_EXT
+        } else {
+            vk::PipelineCreateFlags::empty()
+        };
+
+        let mut god_rays = Self {
+            style: GodRayStyle::default(),
+            pipeline: vk::Pipeline::null(),
+            layout,
+            render_pass: renderer.render_pass,
+            set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            descriptor_buffer,
+            sampler: vk::Sampler::null(),
+        };
+        let created = renderer
+            .sampler(SamplerDesc::nearest(vk::SamplerAddressMode::CLAMP_TO_EDGE))
+            .map_err(Box::<dyn std::error::Error>::from)
+            .and_then(|sampler| {
+                god_rays.sampler = sampler;
+                GraphicsPipelineBuilder::new(layout, renderer.render_pass)
+                    .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+                    .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+                    .blend(BlendMode::Additive)
+                    .flags(pipeline_flags)
+                    .build(device)
+            });
+        match created {
+            Ok(pipeline) => god_rays.pipeline = pipeline,
+            Err(e) => {
+                god_rays.destroy(renderer);
+                return Err(e);
+            }
+        }
+        Ok(god_rays)
+    }
+
+    /// Add the rays of the sun at `sun` (NDC) to swapchain image `image_index`, reading the
+    /// depth `gltf_renderer`'s scene pass left for it. Record outside any render pass, after
+    /// the scene pass; does nothing while the rays are off or the sun is far off screen.
+    pub unsafe fn record(&self, renderer: &VulkanRenderer, gltf_renderer: &GltfRenderer, image_index: u32, sun: Vec2) {
+        if self.style.intensity <= 0.0 || sun.abs().max_element() > MAX_SUN_DISTANCE {
+            return;
+        }
+        let device = &renderer.device;
+        let command_buffer = renderer.command_buffers[renderer.current_frame];
+        let depth = gltf_renderer.depth_targets[image_index as usize].depth();
+
+        // This frame slot's previous use of its descriptors has completed
+        match &self.descriptor_buffer {
+            Some(descriptor_buffer) => descriptor_buffer.write_sampled_image(renderer.current_frame, 0, depth.view, self.sampler),
+            None => DescriptorWriter::new()
+                .sampled_image(0, depth.view, self.sampler)
+                .write(device, self.descriptor_sets[renderer.current_frame]),
+        }
+
+        // The scene pass's depth writes before the reads here; it already left the layout
+        let barrier = vk::ImageMemoryBarrier::default()
+            .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(depth.image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            });
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            std::slice::from_ref(&barrier),
+        );
+
+        let extent = renderer.swapchain_extent;
+        let mut encoder = CommandEncoder::new(device, command_buffer);
+        let mut pass = encoder.begin_render_pass(self.render_pass, renderer.framebuffers[image_index as usize], extent, &[]);
+        pass.bind_pipeline(PipelineBinding {
+            pipeline: self.pipeline,
+            layout: self.layout,
+            render_pass: self.render_pass,
+            push_constant_size: std::mem::size_of::<GodRayPushConstants>() as u32,
+        });
+        match &self.descriptor_buffer {
+            Some(descriptor_buffer) => descriptor_buffer.bind(&mut pass, 0, renderer.current_frame),
+            None => pass.bind_descriptor_set(0, self.descriptor_sets[renderer.current_frame]),
+        }
+        pass.set_full_viewport(extent);
+        let uv = sun * 0.5 + 0.5;
+        let pc = GodRayPushConstants {
+            sun: [uv.x, uv.y, extent.width as f32 / extent.height.max(1) as f32, self.style.intensity],
+            params: [SAMPLES as f32, self.style.length, self.style.decay, self.style.sun_radius],
+        };
+        pass.push_constants(vk::ShaderStageFlags::FRAGMENT, 0, &pc);
+        pass.draw(3, 1);
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.layout, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+        if let Some(descriptor_buffer) = &mut self.descriptor_buffer {
+            descriptor_buffer.destroy(renderer);
+        }
+    }
+}
@@ -0,0 +1,539 @@
+//! GPU-driven draws
+//!
+//! With `multiDrawIndirect` static glTF meshes are merged into one vertex and one index
+//! buffer when the scene is loaded, and their draws are laid out in one range per pipeline
+//! and material. With `VK_KHR_draw_indirect_count` (core in Vulkan 1.2) as well, before
+//! each scene pass a compute pass (cull.comp) frustum-culls every mesh and appends an
+//! indexed indirect draw for each survivor to its range, counting the draws in each range.
+//! The scene pass then issues one `vkCmdDrawIndexedIndirectCount` per range, so recording
+//! costs the same however many meshes share a material.
+//!
+//! Without draw indirect count the draws are written on the CPU, into a buffer per frame
+//! slot, and each range is one `vkCmdDrawIndexedIndirect` of all its meshes, unculled: one
+//! call per range still records far less than a draw per mesh, and the GPU rejects what is
+//! out of view. A slot's draws only change once its fence has been waited on.
+//!
+//! Skinned meshes keep their per-frame skinned buffers and are drawn one by one, as are
+//! meshes moved by node animations, each with its own transform. Meshlets take
+//! precedence when mesh shading is on. Every static mesh is drawn with the model's single
+//! transform, pushed once per range.
+//!
+//! For the draw statistics the main view's counts and draws are copied to a host-visible
+//! buffer per frame slot, flagged as written, and read once the slot's fence has been
+//! waited on. Unculled ranges only count as calls.
+
+use ash::vk;
+use glam::{Mat4, Vec3};
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+use crate::compute::{self, Access, ComputePipeline, DescriptorWriter};
+use crate::command_encoder::RenderPassEncoder;
+use crate::draw_stats;
+use crate::gltf_loader::GltfScene;
+use crate::gltf_renderer::{GltfMeshBuffers, GltfPermutation, GltfRenderer, GltfVertex};
+use crate::material::{MaterialHandle, MaterialRegistry};
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::shader_compiler::load_shader;
+use crate::texture_array::TextureArrays;
+
+/// Matches `DrawObject` in cull.comp
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct DrawObject {
+    center: [f32; 3],
+    radius: f32,
+    index_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+    bucket: u32,
+    draw_base: u32,
+    first_instance: u32, // Texture array slot, see texture_array.rs
+    _pad: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CullPushConstants {
+    view_proj: [[f32; 4]; 4],
+    model: [[f32; 4]; 4],
+}
+
+/// Draws sharing one pipeline and material: `capacity` commands starting at
+/// `first_draw` in the draw buffer, with the visible count at index `bucket` of the
+/// count buffer. Materials that differ only in a texture from the texture arrays share a
+/// bucket, bound as `material`.
+pub struct DrawBucket {
+    pub permutation: GltfPermutation,
+    pub material: MaterialHandle,
+    pub first_draw: u32,
+    pub capacity: u32,
+}
+
+pub struct GpuDrivenPass {
+    pub enabled: bool,
+    /// Draws culled by cull.comp, with draw indirect count support; else `batch_buffer`'s
+    pub culling: bool,
+    pub pipeline: ComputePipeline,
+    pub descriptor_set: vk::DescriptorSet,
+    /// Scene meshes drawn by this pass
+    pub mesh_indices: Vec<usize>,
+    radii: Vec<f32>, // Bounding sphere radius of each object, kept to restore excluded ones
+    excluded: Vec<bool>,
+    pub buckets: Vec<DrawBucket>,
+    pub vertex_buffer: vk::Buffer,
+    pub index_buffer: vk::Buffer,
+    pub object_buffer: vk::Buffer,
+    pub draw_buffer: vk::Buffer,  // VkDrawIndexedIndirectCommand per object, written by cull.comp
+    pub count_buffer: vk::Buffer, // One u32 per bucket, written by cull.comp
+    /// Per frame slot: every object's draw, laid out like `draw_buffer`, when not culling
+    batch_buffers: Vec<vk::Buffer>,
+    batch_allocations: Vec<Allocation>,
+    batch_slots: Vec<u32>, // Of each object in the batch buffers
+    batch_stale: Vec<bool>, // Per frame slot, whether exclusions changed since it was written
+    frame_index: usize, // The slot being recorded, see `begin_frame`
+    pub allocations: Vec<Option<Allocation>>,
+    /// Per frame slot: a copy of the counts followed by the draws, for the statistics
+    pub readback_buffers: Vec<vk::Buffer>,
+    pub readback_allocations: Vec<Allocation>,
+}
+
+impl GpuDrivenPass {
+    /// Returns `None` without multi-draw indirect support or static meshes. `mesh_buffers`
+    /// supplies each mesh's permutation and material, `texture_arrays` the slot of its
+    /// texture.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        scene: &GltfScene,
+        mesh_buffers: &[GltfMeshBuffers],
+        materials: &MaterialRegistry,
+        texture_arrays: &TextureArrays,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let animated = scene.animated_meshes();
+        let static_meshes: Vec<usize> = scene
+            .meshes
+            .iter()
+            .enumerate()
+            .filter(|&(i, mesh)| (!mesh.has_joints || mesh.skin_index.is_none()) && !animated[i] && !mesh.indices.is_empty())
+            .map(|(i, _)| i)
+            .collect();
+        if !renderer.multi_draw_indirect_supported || static_meshes.is_empty() {
+            return Ok(None);
+        }
+
+        // One bucket per distinct pipeline + material, counting materials with array
+        // textures as the same when nothing else differs
+        let texture_slot = |handle: MaterialHandle| texture_arrays.slot(materials.get(handle).base_color_texture);
+        let batches_with = |a: MaterialHandle, b: MaterialHandle| {
+            a == b
+                || (texture_slot(a) != 0
+                    && texture_slot(b) != 0
+                    && materials.get(a).differs_only_in_texture(materials.get(b)))
+        };
+        let mut buckets: Vec<DrawBucket> = Vec::new();
+        let mut mesh_buckets = Vec::new();
+        for &mesh_index in &static_meshes {
+            let mesh = &mesh_buffers[mesh_index];
+            let bucket = match buckets
+                .iter()
+                .position(|b| b.permutation == mesh.permutation && batches_with(b.material, mesh.material))
+            {
+                Some(bucket) => bucket,
+                None => {
+                    buckets.push(DrawBucket {
+                        permutation: mesh.permutation,
+                        material: mesh.material,
+                        first_draw: 0,
+                        capacity: 0,
+                    });
+                    buckets.len() - 1
+                }
+            };
+            buckets[bucket].capacity += 1;
+            mesh_buckets.push(bucket);
+        }
+        let mut first_draw = 0;
+        for bucket in &mut buckets {
+            bucket.first_draw = first_draw;
+            first_draw += bucket.capacity;
+        }
+        // Where each object's draw goes in its bucket's range, for the unculled draws
+        let mut filled = vec![0; buckets.len()];
+        let batch_slots: Vec<u32> = mesh_buckets
+            .iter()
+            .map(|&bucket| {
+                filled[bucket] += 1;
+                buckets[bucket].first_draw + filled[bucket] - 1
+            })
+            .collect();
+
+        let mut vertices: Vec<GltfVertex> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        let mut objects = Vec::new();
+        for (&mesh_index, &bucket) in static_meshes.iter().zip(&mesh_buckets) {
+            let mesh = &scene.meshes[mesh_index];
+            let (min, max) = mesh.vertices.iter().fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), v| {
+                (min.min(Vec3::from(v.position)), max.max(Vec3::from(v.position)))
+            });
+            let center = (min + max) * 0.5;
+            let radius = mesh
+                .vertices
+                .iter()
+                .map(|v| Vec3::from(v.position).distance(center))
+                .fold(0.0, f32::max);
+
+            objects.push(DrawObject {
+                center: center.to_array(),
+                radius,
+                index_count: mesh.indices.len() as u32,
+                first_index: indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+                bucket: bucket as u32,
+                draw_base: buckets[bucket].first_draw,
+                first_instance: texture_slot(mesh_buffers[mesh_index].material),
+                _pad: [0; 2],
+            });
+            vertices.extend(GltfRenderer::mesh_vertices(mesh));
+            indices.extend_from_slice(&mesh.indices);
+        }
+        let mut batch = vec![vk::DrawIndexedIndirectCommand::default(); objects.len()];
+        for (object, &slot) in objects.iter().zip(&batch_slots) {
+            batch[slot as usize] = vk::DrawIndexedIndirectCommand {
+                index_count: object.index_count,
+                instance_count: 1,
+                first_index: object.first_index,
+                vertex_offset: object.vertex_offset,
+                first_instance: object.first_instance,
+            };
+        }
+
+        let device = &renderer.device;
+        let (vertex_buffer, vertex_allocation) = create_buffer(
+            renderer,
+            "gpu_driven_vertices",
+            &vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let (index_buffer, index_allocation) =
+            create_buffer(renderer, "gpu_driven_indices", &indices, vk::BufferUsageFlags::INDEX_BUFFER)?;
+        let (object_buffer, object_allocation) =
+            create_buffer(renderer, "gpu_driven_objects", &objects, vk::BufferUsageFlags::STORAGE_BUFFER)?;
+        let (draw_buffer, draw_allocation) = create_gpu_buffer(
+            renderer,
+            "gpu_driven_draws",
+            (std::mem::size_of::<vk::DrawIndexedIndirectCommand>() * objects.len()) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_SRC,
+        )?;
+        let mut batch_buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        let mut batch_allocations = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let (buffer, allocation) =
+                create_buffer(renderer, "gpu_driven_batch", &batch, vk::BufferUsageFlags::INDIRECT_BUFFER)?;
+            batch_buffers.push(buffer);
+            batch_allocations.push(allocation);
+        }
+        let (count_buffer, count_allocation) = create_gpu_buffer(
+            renderer,
+            "gpu_driven_counts",
+            (std::mem::size_of::<u32>() * buckets.len()) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST
+                | vk::BufferUsageFlags::TRANSFER_SRC,
+        )?;
+
+        let readback_size = std::mem::size_of::<u32>() * (1 + buckets.len())
+            + std::mem::size_of::<vk::DrawIndexedIndirectCommand>() * objects.len();
+        let mut readback_buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        let mut readback_allocations = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            // Zeroed so slots not yet written are skipped
+            let (buffer, allocation) = allocate_buffer(
+                renderer,
+                "gpu_driven_readback",
+                readback_size as u64,
+                vk::BufferUsageFlags::TRANSFER_DST,
+                MemoryLocation::GpuToCpu,
+            )?;
+            std::ptr::write_bytes(allocation.mapped_ptr().unwrap().as_ptr() as *mut u8, 0, readback_size);
+            readback_buffers.push(buffer);
+            readback_allocations.push(allocation);
+        }
+
+        let code = load_shader("cull.comp", include_bytes!("../shaders/cull.comp.spv"));
+        let pipeline = ComputePipeline::new(renderer, &code, std::mem::size_of::<CullPushConstants>() as u32, 1)?;
+        let descriptor_set = pipeline.allocate_descriptor_set(device)?;
+        DescriptorWriter::new()
+            .storage_buffer(0, object_buffer)
+            .storage_buffer(1, draw_buffer)
+            .storage_buffer(2, count_buffer)
+            .write(device, descriptor_set);
+
+        let culling = renderer.draw_indirect_count_supported;
+        println!(
+            "✓ GPU-driven draws: {} static meshes in {} indirect calls{}",
+            static_meshes.len(),
+            buckets.len(),
+            if culling { "" } else { ", unculled" }
+        );
+
+        Ok(Some(Self {
+            enabled: true,
+            culling,
+            pipeline,
+            descriptor_set,
+            radii: objects.iter().map(|object| object.radius).collect(),
+            excluded: vec![false; static_meshes.len()],
+            mesh_indices: static_meshes,
+            buckets,
+            vertex_buffer,
+            index_buffer,
+            object_buffer,
+            draw_buffer,
+            count_buffer,
+            batch_buffers,
+            batch_allocations,
+            batch_slots,
+            batch_stale: vec![false; MAX_FRAMES_IN_FLIGHT],
+            frame_index: 0,
+            allocations: vec![
+                Some(vertex_allocation),
+                Some(index_allocation),
+                Some(object_allocation),
+                Some(draw_allocation),
+                Some(count_allocation),
+            ],
+            readback_buffers,
+            readback_allocations,
+        }))
+    }
+
+    /// Whether `mesh_index` is drawn by `draw_bucket` rather than on its own
+    pub fn draws_mesh(&self, mesh_index: usize) -> bool {
+        self.enabled
+            && self
+                .mesh_indices
+                .iter()
+                .position(|&i| i == mesh_index)
+                .is_some_and(|object| !self.excluded[object])
+    }
+
+    /// Leave `mesh_index` out of the culled draws (or take it back), for a mesh that no
+    /// longer matches its bucket, such as one with a material override. Culling rejects
+    /// the object through a negative radius, the unculled draws through no instances, from
+    /// each frame slot's `begin_frame` on.
+    pub fn set_excluded(&mut self, mesh_index: usize, excluded: bool) {
+        let Some(object) = self.mesh_indices.iter().position(|&i| i == mesh_index) else {
+            return;
+        };
+        self.excluded[object] = excluded;
+        if let Some(allocation) = &self.allocations[2] { // The object buffer
+            let objects = allocation.mapped_ptr().unwrap().as_ptr() as *mut DrawObject;
+            let radius = if excluded { -f32::MAX } else { self.radii[object] };
+            unsafe {
+                (*objects.add(object)).radius = radius;
+            }
+        }
+        self.batch_stale.fill(true);
+    }
+
+    /// Start recording frame slot `frame_index`, bringing its unculled draws up to date with
+    /// the exclusions. Call once the slot's fence has been waited on.
+    pub fn begin_frame(&mut self, frame_index: usize) {
+        self.frame_index = frame_index;
+        if !std::mem::take(&mut self.batch_stale[frame_index]) {
+            return;
+        }
+        let batch = self.batch_allocations[frame_index].mapped_ptr().unwrap().as_ptr() as *mut vk::DrawIndexedIndirectCommand;
+        for (&slot, &excluded) in self.batch_slots.iter().zip(&self.excluded) {
+            unsafe {
+                (*batch.add(slot as usize)).instance_count = u32::from(!excluded);
+            }
+        }
+    }
+
+    /// Cull every object for the camera `view_proj` and rewrite the draw commands and
+    /// counts. Record outside a render pass, before each pass that calls `draw_bucket`.
+    pub unsafe fn record_culling(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        view_proj: &Mat4,
+        model: &Mat4,
+    ) {
+        if !self.enabled || !self.culling {
+            return;
+        }
+
+        // Earlier passes (other views included) may still be reading the commands
+        compute::memory_barrier(device, command_buffer, Access::INDIRECT, Access::TRANSFER_WRITE);
+        device.cmd_fill_buffer(command_buffer, self.count_buffer, 0, vk::WHOLE_SIZE, 0);
+        compute::memory_barrier(device, command_buffer, Access::TRANSFER_WRITE, Access::COMPUTE_READ_WRITE);
+
+        let pc = CullPushConstants {
+            view_proj: view_proj.to_cols_array_2d(),
+            model: model.to_cols_array_2d(),
+        };
+        let bytes = std::slice::from_raw_parts(
+            (&pc as *const CullPushConstants) as *const u8,
+            std::mem::size_of::<CullPushConstants>(),
+        );
+        let object_count = self.mesh_indices.len() as u32;
+        self.pipeline
+            .dispatch_threads(device, command_buffer, self.descriptor_set, bytes, [object_count, 1, 1]);
+
+        compute::memory_barrier(device, command_buffer, Access::COMPUTE_WRITE, Access::INDIRECT);
+    }
+
+    /// Copy the counts and draws `record_culling` just wrote into `frame_index`'s readback
+    /// buffer for `read_statistics`
+    pub unsafe fn record_readback(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        if !self.enabled || !self.culling {
+            return;
+        }
+        let word = std::mem::size_of::<u32>() as u64;
+        let counts_size = word * self.buckets.len() as u64;
+        let draws_size = (std::mem::size_of::<vk::DrawIndexedIndirectCommand>() * self.mesh_indices.len()) as u64;
+        let readback = self.readback_buffers[frame_index];
+        compute::memory_barrier(device, command_buffer, Access::COMPUTE_WRITE, Access::TRANSFER_READ);
+        device.cmd_fill_buffer(command_buffer, readback, 0, word, 1);
+        device.cmd_copy_buffer(
+            command_buffer,
+            self.count_buffer,
+            readback,
+            &[vk::BufferCopy { src_offset: 0, dst_offset: word, size: counts_size }],
+        );
+        device.cmd_copy_buffer(
+            command_buffer,
+            self.draw_buffer,
+            readback,
+            &[vk::BufferCopy { src_offset: 0, dst_offset: word + counts_size, size: draws_size }],
+        );
+        compute::memory_barrier(
+            device,
+            command_buffer,
+            Access::TRANSFER_WRITE,
+            Access { stage: vk::PipelineStageFlags::HOST, access: vk::AccessFlags::HOST_READ },
+        );
+        // The next culling pass clears the counts
+        compute::memory_barrier(device, command_buffer, Access::TRANSFER_READ, Access::TRANSFER_WRITE);
+    }
+
+    /// Add what `frame_index`'s culling drew and culled to the draw statistics, if the slot
+    /// was written since the last call. Call once the slot's fence has been waited on.
+    pub unsafe fn read_statistics(&self, frame_index: usize) {
+        // Layout: written flag, one count per bucket, the draws
+        let base = self.readback_allocations[frame_index].mapped_ptr().unwrap().as_ptr() as *mut u32;
+        if *base == 0 {
+            return;
+        }
+        *base = 0;
+        let counts = std::slice::from_raw_parts(base.add(1), self.buckets.len());
+        let draws = std::slice::from_raw_parts(
+            base.add(1 + counts.len()) as *const vk::DrawIndexedIndirectCommand,
+            self.mesh_indices.len(),
+        );
+        let mut visible = 0;
+        let mut triangles = 0;
+        for (bucket, &count) in self.buckets.iter().zip(counts) {
+            let count = count.min(bucket.capacity);
+            let first = bucket.first_draw as usize;
+            visible += count;
+            triangles += draws[first..first + count as usize]
+                .iter()
+                .map(|draw| draw.index_count as u64 / 3)
+                .sum::<u64>();
+        }
+        draw_stats::gpu_culled(visible, triangles, self.mesh_indices.len() as u32 - visible);
+    }
+
+    /// Issue the draws of one bucket, culled or all of them. Its pipeline and push constants
+    /// and the merged `vertex_buffer` and `index_buffer` must be bound.
+    pub fn draw_bucket(&self, pass: &mut RenderPassEncoder, bucket_index: usize) {
+        let bucket = &self.buckets[bucket_index];
+        let stride = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u64;
+        if !self.culling {
+            let batch = self.batch_buffers[self.frame_index];
+            pass.draw_indexed_indirect((batch, bucket.first_draw as u64 * stride), bucket.capacity);
+            return;
+        }
+        pass.draw_indexed_indirect_count(
+            (self.draw_buffer, bucket.first_draw as u64 * stride),
+            (self.count_buffer, (bucket_index * std::mem::size_of::<u32>()) as u64),
+            bucket.capacity,
+        );
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        self.pipeline.destroy(renderer);
+        let buffers = [
+            self.vertex_buffer,
+            self.index_buffer,
+            self.object_buffer,
+            self.draw_buffer,
+            self.count_buffer,
+        ];
+        let per_frame = self.readback_buffers.drain(..).chain(self.batch_buffers.drain(..));
+        for buffer in buffers.into_iter().chain(per_frame) {
+            renderer.device.destroy_buffer(buffer, None);
+        }
+        let per_frame = self.readback_allocations.drain(..).chain(self.batch_allocations.drain(..));
+        for allocation in self.allocations.drain(..).flatten().chain(per_frame) {
+            let _ = renderer.allocator.lock().free(allocation);
+        }
+    }
+}
+
+/// Host-visible buffer holding `data`
+unsafe fn create_buffer<T: Copy>(
+    renderer: &VulkanRenderer,
+    name: &str,
+    data: &[T],
+    usage: vk::BufferUsageFlags,
+) -> Result<(vk::Buffer, Allocation), Box<dyn std::error::Error>> {
+    let (buffer, allocation) = allocate_buffer(
+        renderer,
+        name,
+        std::mem::size_of_val(data) as u64,
+        usage,
+        MemoryLocation::CpuToGpu,
+    )?;
+    let ptr = allocation.mapped_ptr().unwrap().as_ptr() as *mut T;
+    std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+    Ok((buffer, allocation))
+}
+
+/// Device-local buffer, written by the GPU only
+unsafe fn create_gpu_buffer(
+    renderer: &VulkanRenderer,
+    name: &str,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+) -> Result<(vk::Buffer, Allocation), Box<dyn std::error::Error>> {
+    allocate_buffer(renderer, name, size, usage, MemoryLocation::GpuOnly)
+}
+
+unsafe fn allocate_buffer(
+    renderer: &VulkanRenderer,
+    name: &str,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+    location: MemoryLocation,
+) -> Result<(vk::Buffer, Allocation), Box<dyn std::error::Error>> {
+    let buffer_info = vk::BufferCreateInfo::default()
+        .size(size.max(4))
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = renderer.device.create_buffer(&buffer_info, None)?;
+    let requirements = renderer.device.get_buffer_memory_requirements(buffer);
+    let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+        name,
+        requirements,
+        location,
+        linear: true,
+        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+    })?;
+    renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+    Ok((buffer, allocation))
+}
@@ -0,0 +1,110 @@
+//! Ground plane
+//!
+//! The square at Y=0 the model stands on. It is drawn by the scene pipeline like any mesh,
+//! so it receives shadows, ambient occlusion and lightmaps. Its looks live in the vertex
+//! colors, which the shader multiplies into the albedo: every one-meter cell has corners of
+//! its own, so a checkerboard gets hard edges, and the circular fade blends the colors
+//! towards the sky so the plane's edge melts into the horizon instead of ending in a line.
+//!
+//! Changing the style only rewrites those colors (see `GltfRenderer::set_ground_style`), so
+//! the buffers, the ground's BLAS and its lightmap tile stay as they are. A hidden ground
+//! is left out of the scene, shadow and object ID passes but still blocks traced rays from
+//! below, where nothing is.
+
+use glam::{Vec2, Vec3};
+
+use crate::gltf_renderer::{GltfVertex, GROUND_SIZE, SKY_COLOR};
+use crate::lightmap;
+
+/// Cells along each side of the plane, one per meter
+const CELLS: u32 = GROUND_SIZE as u32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroundPattern {
+    Solid,
+    Checker,
+}
+
+/// What the ground looks like
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GroundStyle {
+    pub visible: bool,
+    pub pattern: GroundPattern,
+    pub color: [f32; 3],
+    pub checker_color: [f32; 3], // Every other square of the checkerboard
+    pub checker_size: u32,       // Edge of a checkerboard square in meters
+    pub fade: bool,
+    pub fade_start: f32, // Distance from the center where the fade begins; it ends at the edge
+}
+
+impl Default for GroundStyle {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            pattern: GroundPattern::Solid,
+            color: [0.35, 0.35, 0.35],
+            checker_color: [0.2, 0.2, 0.2],
+            checker_size: 1,
+            fade: false,
+            fade_start: GROUND_SIZE * 0.3,
+        }
+    }
+}
+
+/// Vertices and counter-clockwise (seen from above) indices of the plane, painted in
+/// `style`, with planar lightmap UVs over `lightmap_tile`
+pub fn mesh(style: &GroundStyle, lightmap_tile: lightmap::Tile) -> (Vec<GltfVertex>, Vec<u32>) {
+    let half = GROUND_SIZE * 0.5;
+    let vertex = |i: u32, k: u32| {
+        let uv = Vec2::new(i as f32, k as f32) / CELLS as f32;
+        GltfVertex {
+            pos: [uv.x * GROUND_SIZE - half, 0.0, uv.y * GROUND_SIZE - half],
+            color: [0.0; 3],
+            normal: [0.0, 1.0, 0.0],
+            tex_coord: (uv * 10.0).to_array(),
+            lightmap_uv: lightmap_tile.uv(uv),
+        }
+    };
+
+    let mut vertices = Vec::with_capacity((CELLS * CELLS * 4) as usize);
+    let mut indices = Vec::with_capacity((CELLS * CELLS * 6) as usize);
+    for i in 0..CELLS {
+        for k in 0..CELLS {
+            let a = vertices.len() as u32;
+            vertices.extend([vertex(i, k), vertex(i + 1, k), vertex(i + 1, k + 1), vertex(i, k + 1)]);
+            indices.extend_from_slice(&[a, a + 2, a + 1, a + 2, a, a + 3]);
+        }
+    }
+    paint(&mut vertices, style);
+    (vertices, indices)
+}
+
+/// Set the colors of `vertices`, made by `mesh`, to `style`
+pub fn paint(vertices: &mut [GltfVertex], style: &GroundStyle) {
+    let half = GROUND_SIZE * 0.5;
+    let checker_size = style.checker_size.max(1) as f32;
+    for cell in vertices.chunks_exact_mut(4) {
+        // The cell's first corner is its lowest in X and Z; its center picks the square
+        let center = Vec2::new(cell[0].pos[0], cell[0].pos[2]) + half + 0.5;
+        let square = (center / checker_size).floor();
+        let odd = (square.x + square.y) as i32 % 2 == 1;
+        let base = match style.pattern {
+            GroundPattern::Checker if odd => style.checker_color,
+            _ => style.color,
+        };
+        for vertex in cell {
+            let distance = Vec2::new(vertex.pos[0], vertex.pos[2]).length();
+            let fade = if style.fade {
+                smoothstep(style.fade_start.min(half - 0.01), half, distance)
+            } else {
+                0.0
+            };
+            vertex.color = Vec3::from(base).lerp(Vec3::from(SKY_COLOR), fade).to_array();
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
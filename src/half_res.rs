@@ -0,0 +1,369 @@
+//! Half-resolution transparency
+//!
+//! Large blended effects cover many pixels many times over. Instead of blending them into
+//! the frame in the scene pass, the fountain and weather particles can be drawn into a
+//! target of half the width and height, a quarter of the fragments, and added onto the
+//! frame afterwards.
+//!
+//! The pass starts by downsampling the scene depth (keeping the farthest of each 2x2 block)
+//! into its own depth buffer so the particles are still hidden behind geometry. The
+//! composite then upsamples with depth-aware weights, preferring the half-resolution pixels
+//! whose depth matches the full-resolution one, which keeps silhouettes sharp.
+//!
+//! Both particle kinds are additive, so the target starts out black and the composite is a
+//! plain add. Targets are kept per frame in flight and follow the window's size.
+
+use ash::vk;
+use glam::Mat4;
+
+use crate::command_encoder::{CommandEncoder, PipelineBinding, RenderPassEncoder};
+use crate::compute::DescriptorWriter;
+use crate::gltf_renderer::GltfRenderer;
+use crate::pipeline_builder::{BlendMode, DepthMode, GraphicsPipelineBuilder};
+use crate::render_target::{RenderTarget, RenderTargetDesc};
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::sampler_cache::SamplerDesc;
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+
+const COLOR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+/// Relative depth difference at which a half-resolution pixel's weight has halved
+const DEPTH_TOLERANCE: f32 = 0.05;
+
+// Must match shaders/half_res_composite.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CompositePushConstants {
+    depth: [f32; 4], // x = projection[3][2], y = projection[2][2], z = depth tolerance
+}
+
+/// Half of `extent`, rounded up
+pub fn half_extent(extent: vk::Extent2D) -> vk::Extent2D {
+    vk::Extent2D { width: extent.width.div_ceil(2).max(1), height: extent.height.div_ceil(2).max(1) }
+}
+
+pub struct HalfResTransparency {
+    pub render_pass: vk::RenderPass, // Half-resolution color + depth; particles add pipelines for it
+    targets: Vec<Option<RenderTarget>>, // Per frame in flight, made on first use and on resize
+    framebuffers: Vec<vk::Framebuffer>,
+
+    downsample_pipeline: vk::Pipeline,
+    downsample_layout: vk::PipelineLayout,
+    downsample_set_layout: vk::DescriptorSetLayout,
+    composite_pipeline: vk::Pipeline,
+    composite_layout: vk::PipelineLayout,
+    composite_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    downsample_sets: Vec<vk::DescriptorSet>, // Per frame in flight
+    composite_sets: Vec<vk::DescriptorSet>,  // Per frame in flight
+    sampler: vk::Sampler,
+}
+
+impl HalfResTransparency {
+    pub unsafe fn new(renderer: &VulkanRenderer) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let mut half_res = Self {
+            render_pass: vk::RenderPass::null(),
+            targets: (0..MAX_FRAMES_IN_FLIGHT).map(|_| None).collect(),
+            framebuffers: vec![vk::Framebuffer::null(); MAX_FRAMES_IN_FLIGHT],
+            downsample_pipeline: vk::Pipeline::null(),
+            downsample_layout: vk::PipelineLayout::null(),
+            downsample_set_layout: vk::DescriptorSetLayout::null(),
+            composite_pipeline: vk::Pipeline::null(),
+            composite_layout: vk::PipelineLayout::null(),
+            composite_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            downsample_sets: Vec::new(),
+            composite_sets: Vec::new(),
+            sampler: vk::Sampler::null(),
+        };
+        if let Err(e) = half_res.create(renderer, device) {
+            half_res.destroy(renderer);
+            return Err(e);
+        }
+        Ok(half_res)
+    }
+
+    unsafe fn create(&mut self, renderer: &VulkanRenderer, device: &ash::Device) -> Result<(), Box<dyn std::error::Error>> {
+        self.render_pass = create_render_pass(device)?;
+        self.sampler = renderer.sampler(SamplerDesc::nearest(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+
+        let vert_code = load_shader("post.vert", include_bytes!("../shaders/post.vert.spv"));
+        let vert_reflection = ShaderReflection::reflect(&vert_code)?;
+
+        // Downsample: scene depth in, half-resolution depth (and black color) out
+        let frag_code = load_shader("half_res_depth.frag", include_bytes!("../shaders/half_res_depth.frag.spv"));
+        let reflections = [&vert_reflection, &ShaderReflection::reflect(&frag_code)?];
+        let bindings = shader_reflection::set_layout_bindings(&reflections, 0)?;
+        self.downsample_set_layout =
+            device.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings), None)?;
+        self.downsample_layout = device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default().set_layouts(std::slice::from_ref(&self.downsample_set_layout)),
+            None,
+        )?;
+        self.downsample_pipeline = GraphicsPipelineBuilder::new(self.downsample_layout, self.render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+            .depth(DepthMode::ReadWrite(vk::CompareOp::ALWAYS))
+            .build(device)?;
+
+        // Composite: depth-aware upsample added onto the frame
+        let frag_code = load_shader("half_res_composite.frag", include_bytes!("../shaders/half_res_composite.frag.spv"));
+        let reflections = [&vert_reflection, &ShaderReflection::reflect(&frag_code)?];
+        let bindings = shader_reflection::set_layout_bindings(&reflections, 0)?;
+        self.composite_set_layout =
+            device.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings), None)?;
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &reflections,
+            std::mem::size_of::<CompositePushConstants>() as u32,
+        )?
+        .into_iter()
+        .collect();
+        self.composite_layout = device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(std::slice::from_ref(&self.composite_set_layout))
+                .push_constant_ranges(&push_constant_ranges),
+            None,
+        )?;
+        self.composite_pipeline = GraphicsPipelineBuilder::new(self.composite_layout, renderer.render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+            .blend(BlendMode::Additive)
+            .build(device)?;
+
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 4 * MAX_FRAMES_IN_FLIGHT as u32,
+        };
+        self.descriptor_pool = device.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::default()
+                .pool_sizes(std::slice::from_ref(&pool_size))
+                .max_sets(2 * MAX_FRAMES_IN_FLIGHT as u32),
+            None,
+        )?;
+        let allocate = |set_layout| {
+            let layouts = vec![set_layout; MAX_FRAMES_IN_FLIGHT];
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(self.descriptor_pool)
+                    .set_layouts(&layouts),
+            )
+        };
+        self.downsample_sets = allocate(self.downsample_set_layout)?;
+        self.composite_sets = allocate(self.composite_set_layout)?;
+        Ok(())
+    }
+
+    /// Downsample the depth `gltf_renderer`'s scene pass left for swapchain image
+    /// `image_index` and begin the half-resolution pass, for the caller to draw the
+    /// transparency into at `half_extent` of the window. Record outside any render pass,
+    /// after the scene pass, and follow with `composite`. None if the target can't be made.
+    pub unsafe fn begin<'e, 'a>(
+        &mut self,
+        renderer: &VulkanRenderer,
+        encoder: &'e mut CommandEncoder<'a>,
+        gltf_renderer: &GltfRenderer,
+        image_index: u32,
+    ) -> Option<RenderPassEncoder<'e, 'a>> {
+        let device = &renderer.device;
+        let frame = renderer.current_frame;
+        let extent = half_extent(renderer.swapchain_extent);
+        // This frame slot's previous use of its target has completed
+        if let Err(e) = self.ensure_target(renderer, frame, extent) {
+            eprintln!("✗ Failed to create half-resolution transparency target: {}", e);
+            return None;
+        }
+        let target = self.targets[frame].as_ref()?;
+        let depth = gltf_renderer.depth_targets[image_index as usize].depth();
+
+        DescriptorWriter::new()
+            .sampled_image(0, depth.view, self.sampler)
+            .write(device, self.downsample_sets[frame]);
+        DescriptorWriter::new()
+            .sampled_image(0, depth.view, self.sampler)
+            .sampled_image(1, target.depth().view, self.sampler)
+            .sampled_image(2, target.color().view, self.sampler)
+            .write(device, self.composite_sets[frame]);
+
+        // The scene pass's depth writes before the reads here; it already left the layout
+        let barrier = vk::ImageMemoryBarrier::default()
+            .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(depth.image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            });
+        device.cmd_pipeline_barrier(
+            encoder.command_buffer(),
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            std::slice::from_ref(&barrier),
+        );
+
+        // Every pixel is written by the downsample, so nothing needs clearing
+        let mut pass = encoder.begin_render_pass(self.render_pass, self.framebuffers[frame], extent, &[]);
+        pass.bind_pipeline(PipelineBinding {
+            pipeline: self.downsample_pipeline,
+            layout: self.downsample_layout,
+            render_pass: self.render_pass,
+            push_constant_size: 0,
+        });
+        pass.bind_descriptor_set(0, self.downsample_sets[frame]);
+        pass.set_full_viewport(extent);
+        pass.draw(3, 1);
+        Some(pass)
+    }
+
+    /// Upsample what was drawn since `begin` and add it to swapchain image `image_index`.
+    /// `proj` is the camera's projection, to compare depths in view space.
+    pub unsafe fn composite(&self, renderer: &VulkanRenderer, image_index: u32, proj: Mat4) {
+        let frame = renderer.current_frame;
+        if self.targets[frame].is_none() {
+            return;
+        }
+        let extent = renderer.swapchain_extent;
+        let mut encoder = CommandEncoder::new(&renderer.device, renderer.command_buffers[frame]);
+        let mut pass = encoder.begin_render_pass(renderer.render_pass, renderer.framebuffers[image_index as usize], extent, &[]);
+        pass.bind_pipeline(PipelineBinding {
+            pipeline: self.composite_pipeline,
+            layout: self.composite_layout,
+            render_pass: renderer.render_pass,
+            push_constant_size: std::mem::size_of::<CompositePushConstants>() as u32,
+        });
+        pass.bind_descriptor_set(0, self.composite_sets[frame]);
+        pass.set_full_viewport(extent);
+        let pc = CompositePushConstants { depth: [proj.w_axis.z, proj.z_axis.z, DEPTH_TOLERANCE, 0.0] };
+        pass.push_constants(vk::ShaderStageFlags::FRAGMENT, 0, &pc);
+        pass.draw(3, 1);
+    }
+
+    /// Make frame slot `frame`'s target `extent`, rebuilding its framebuffer when it changes
+    unsafe fn ensure_target(
+        &mut self,
+        renderer: &VulkanRenderer,
+        frame: usize,
+        extent: vk::Extent2D,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let changed = match &mut self.targets[frame] {
+            Some(target) => target.resize(renderer, extent)?,
+            None => {
+                let desc = RenderTargetDesc::color(
+                    COLOR_FORMAT,
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                )
+                .with_depth(DEPTH_FORMAT, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED);
+                self.targets[frame] = Some(RenderTarget::new(renderer, "Half-Res Transparency", desc, extent)?);
+                true
+            }
+        };
+        if !changed {
+            return Ok(());
+        }
+        renderer.device.destroy_framebuffer(self.framebuffers[frame], None);
+        self.framebuffers[frame] = vk::Framebuffer::null();
+        let Some(target) = &self.targets[frame] else {
+            return Ok(());
+        };
+        let attachments = [target.color().view, target.depth().view];
+        let framebuffer_info = vk::FramebufferCreateInfo::default()
+            .render_pass(self.render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        match renderer.device.create_framebuffer(&framebuffer_info, None) {
+            Ok(framebuffer) => self.framebuffers[frame] = framebuffer,
+            Err(e) => {
+                // Leave the slot to be made again next time
+                if let Some(mut target) = self.targets[frame].take() {
+                    target.destroy(renderer);
+                }
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        for framebuffer in self.framebuffers.drain(..) {
+            device.destroy_framebuffer(framebuffer, None);
+        }
+        for mut target in self.targets.drain(..).flatten() {
+            target.destroy(renderer);
+        }
+        device.destroy_pipeline(self.downsample_pipeline, None);
+        device.destroy_pipeline_layout(self.downsample_layout, None);
+        device.destroy_descriptor_set_layout(self.downsample_set_layout, None);
+        device.destroy_pipeline(self.composite_pipeline, None);
+        device.destroy_pipeline_layout(self.composite_layout, None);
+        device.destroy_descriptor_set_layout(self.composite_set_layout, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_render_pass(self.render_pass, None);
+    }
+}
+
+unsafe fn create_render_pass(device: &ash::Device) -> Result<vk::RenderPass, vk::Result> {
+    let attachments = [
+        vk::AttachmentDescription::default()
+            .format(COLOR_FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        vk::AttachmentDescription::default()
+            .format(DEPTH_FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+    ];
+    let color_ref = vk::AttachmentReference { attachment: 0, layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL };
+    let depth_ref = vk::AttachmentReference { attachment: 1, layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL };
+    let subpass = vk::SubpassDescription::default()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(std::slice::from_ref(&color_ref))
+        .depth_stencil_attachment(&depth_ref);
+
+    let dependencies = [
+        vk::SubpassDependency::default()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE),
+        // The composite samples both attachments
+        vk::SubpassDependency::default()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ),
+    ];
+
+    let render_pass_info = vk::RenderPassCreateInfo::default()
+        .attachments(&attachments)
+        .subpasses(std::slice::from_ref(&subpass))
+        .dependencies(&dependencies);
+    device.create_render_pass(&render_pass_info, None)
+}
@@ -0,0 +1,124 @@
+//! HDR images
+//!
+//! Environment maps come as Radiance HDR (.hdr) or OpenEXR (.exr) images, whose texels hold
+//! linear radiance well above 1.0 that RGBA8 would clip. Both are decoded to RGBA floats
+//! and uploaded as R16G16B16A16_SFLOAT or R32G32B32A32_SFLOAT images (see
+//! `GltfRenderer::create_hdr_texture`), for now as the environment behind the scene (see
+//! `skybox`). Half floats reach ±65504 at three significant digits, plenty for sampling at
+//! half the memory, and brighter texels are clamped rather than turned infinite; full floats
+//! keep the sun of a captured sky exact. Images without alpha get 1.0.
+//!
+//! Only level 0 is read. Mips, or the prefiltered levels IBL wants, are left to whatever
+//! samples the image.
+
+use std::path::Path;
+
+use ash::vk;
+
+use crate::lightmap::f32_to_f16;
+use crate::renderer::VulkanRenderer;
+use crate::texture_streaming::MipLevel;
+
+/// Largest finite half float
+const HALF_MAX: f32 = 65504.0;
+
+/// Texel precision of an uploaded HDR image
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HdrPrecision {
+    #[default]
+    Half,
+    Full,
+}
+
+impl HdrPrecision {
+    pub fn format(self) -> vk::Format {
+        match self {
+            HdrPrecision::Half => vk::Format::R16G16B16A16_SFLOAT,
+            HdrPrecision::Full => vk::Format::R32G32B32A32_SFLOAT,
+        }
+    }
+
+    /// `self`, or half floats where the device can't filter full float images
+    pub unsafe fn supported(self, renderer: &VulkanRenderer) -> Self {
+        let filterable = renderer
+            .instance
+            .get_physical_device_format_properties(renderer.physical_device, HdrPrecision::Full.format())
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR);
+        if filterable { self } else { HdrPrecision::Half }
+    }
+}
+
+/// Whether `path` names an image this module reads, by its extension
+pub fn is_hdr(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("hdr") || extension.eq_ignore_ascii_case("exr"))
+}
+
+/// Decode the .hdr or .exr image at `path` into texels of `precision`
+pub fn read(path: &Path, precision: HdrPrecision) -> Result<MipLevel, Box<dyn std::error::Error>> {
+    if !is_hdr(path) {
+        return Err(format!("{} is not a .hdr or .exr image", path.display()).into());
+    }
+    let image = image::open(path)?.into_rgba32f();
+    let (width, height) = image.dimensions();
+    let data = match precision {
+        HdrPrecision::Half => image.into_raw().into_iter().flat_map(|value| f32_to_f16(value.clamp(-HALF_MAX, HALF_MAX)).to_ne_bytes()).collect(),
+        HdrPrecision::Full => image.into_raw().into_iter().flat_map(f32::to_ne_bytes).collect(),
+    };
+    Ok(MipLevel { width, height, data })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a 2x1 .hdr image of `texels` and read it back at `precision`. Texels share an
+    /// exponent over 8-bit mantissas, so only values that fit them come back exactly.
+    fn round_trip(name: &str, texels: [[f32; 3]; 2], precision: HdrPrecision) -> MipLevel {
+        let path = std::env::temp_dir().join(format!("funky_{}_{}.hdr", name, std::process::id()));
+        let image = image::Rgb32FImage::from_fn(2, 1, |x, _| image::Rgb(texels[x as usize]));
+        image::DynamicImage::ImageRgb32F(image).save(&path).unwrap();
+        let result = read(&path, precision);
+        let _ = std::fs::remove_file(&path);
+        result.unwrap()
+    }
+
+    #[test]
+    fn full_precision_keeps_texels_and_adds_alpha() {
+        let level = round_trip("full", [[0.5, 1.0, 2.0], [1000.0, 256.0, 0.0]], HdrPrecision::Full);
+        assert_eq!((level.width, level.height), (2, 1));
+        let texels: Vec<f32> = level.data.chunks_exact(4).map(|b| f32::from_ne_bytes(b.try_into().unwrap())).collect();
+        assert_eq!(texels, [0.5, 1.0, 2.0, 1.0, 1000.0, 256.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn half_precision_clamps_to_the_largest_half() {
+        let level = round_trip("half", [[0.5, 1.0, 2.0], [100_000.0, 0.0, 0.0]], HdrPrecision::Half);
+        let texels: Vec<u16> = level.data.chunks_exact(2).map(|b| u16::from_ne_bytes(b.try_into().unwrap())).collect();
+        assert_eq!(texels.len(), 8);
+        assert_eq!(texels[..4], [0x3800, 0x3c00, 0x4000, 0x3c00]); // 0.5, 1, 2 and alpha 1
+        assert_eq!(texels[4], 0x7bff); // 65504
+    }
+
+    #[test]
+    fn half_precision_clamps_negative_texels_too() {
+        // OpenEXR, unlike Radiance HDR, stores negative values
+        let path = std::env::temp_dir().join(format!("funky_negative_{}.exr", std::process::id()));
+        let image = image::Rgba32FImage::from_pixel(1, 1, image::Rgba([-100_000.0, -1.0, 0.0, 0.5]));
+        image::DynamicImage::ImageRgba32F(image).save(&path).unwrap();
+        let level = read(&path, HdrPrecision::Half);
+        let _ = std::fs::remove_file(&path);
+        let texels: Vec<u16> = level.unwrap().data.chunks_exact(2).map(|b| u16::from_ne_bytes(b.try_into().unwrap())).collect();
+        assert_eq!(texels, [0xfbff, 0xbc00, 0x0000, 0x3800]); // -65504, -1, 0, 0.5
+    }
+
+    #[test]
+    fn rejects_other_images() {
+        assert!(is_hdr(Path::new("sky.HDR")) && is_hdr(Path::new("sky.exr")));
+        assert!(!is_hdr(Path::new("sky.png")) && !is_hdr(Path::new("sky")));
+        assert!(read(Path::new("sky.png"), HdrPrecision::Half).is_err());
+        assert!(read(Path::new("missing.hdr"), HdrPrecision::Half).is_err());
+    }
+}
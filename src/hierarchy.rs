@@ -0,0 +1,99 @@
+//! Parent/child transform hierarchy
+//!
+//! An entity with a `Parent` has its `Transform` relative to that parent. Every frame
+//! `propagate_transforms` walks down from the root entities (those without a `Parent`) and
+//! writes each entity's world matrix into its `GlobalTransform`, which is what rendering
+//! reads. Link entities with `HierarchyCommandsExt::set_parent`, which keeps the parent's
+//! `Children` in step, and remove them with `despawn_recursive` so no child is orphaned.
+
+use bevy_ecs::prelude::*;
+
+use crate::Transform;
+
+/// World-space matrix of an entity, written by `propagate_transforms`
+#[derive(Component, Clone, Copy, Debug)]
+pub struct GlobalTransform(pub glam::Mat4);
+
+impl Default for GlobalTransform {
+    fn default() -> Self {
+        Self(glam::Mat4::IDENTITY)
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parent(pub Entity);
+
+#[derive(Component, Clone, Debug, Default)]
+pub struct Children(pub Vec<Entity>);
+
+pub trait HierarchyCommandsExt {
+    /// Make the entity a child of `parent`, detaching it from any previous parent. Its
+    /// `Transform` keeps its value and becomes relative to `parent`.
+    fn set_parent(&mut self, parent: Entity) -> &mut Self;
+}
+
+impl HierarchyCommandsExt for EntityCommands<'_> {
+    fn set_parent(&mut self, parent: Entity) -> &mut Self {
+        let child = self.id();
+        self.commands().queue(move |world: &mut World| {
+            detach(world, child);
+            let Ok(mut parent_entity) = world.get_entity_mut(parent) else {
+                return;
+            };
+            match parent_entity.get_mut::<Children>() {
+                Some(mut children) => children.0.push(child),
+                None => {
+                    parent_entity.insert(Children(vec![child]));
+                }
+            }
+            world.entity_mut(child).insert(Parent(parent));
+        });
+        self
+    }
+}
+
+/// Remove `entity`'s `Parent` and drop it from that parent's `Children`
+fn detach(world: &mut World, entity: Entity) {
+    let Some(Parent(parent)) = world.entity_mut(entity).take::<Parent>() else {
+        return;
+    };
+    if let Some(mut children) = world.get_mut::<Children>(parent) {
+        children.0.retain(|&child| child != entity);
+    }
+}
+
+/// Despawn `entity` together with all of its descendants
+pub fn despawn_recursive(world: &mut World, entity: Entity) {
+    if world.get_entity(entity).is_err() {
+        return;
+    }
+    detach(world, entity);
+    let mut stack = vec![entity];
+    while let Some(entity) = stack.pop() {
+        if let Some(children) = world.get::<Children>(entity) {
+            stack.extend_from_slice(&children.0);
+        }
+        world.despawn(entity);
+    }
+}
+
+/// Compute every `GlobalTransform` from the hierarchy, parents before their children
+pub fn propagate_transforms(
+    roots: Query<Entity, (With<Transform>, Without<Parent>)>,
+    transforms: Query<(&Transform, Option<&Children>)>,
+    mut globals: Query<&mut GlobalTransform>,
+) {
+    let mut stack: Vec<(Entity, glam::Mat4)> = roots.iter().map(|root| (root, glam::Mat4::IDENTITY)).collect();
+    while let Some((entity, parent_matrix)) = stack.pop() {
+        let Ok((transform, children)) = transforms.get(entity) else {
+            continue;
+        };
+        let matrix = parent_matrix * transform.compute_matrix();
+        if let Ok(mut global) = globals.get_mut(entity) {
+            global.0 = matrix;
+        }
+        if let Some(children) = children {
+            stack.extend(children.0.iter().map(|&child| (child, matrix)));
+        }
+    }
+}
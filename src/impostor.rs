@@ -0,0 +1,469 @@
+//! Impostor LOD
+//!
+//! Far away, a mesh covers a few pixels and its triangles are wasted work. At load time
+//! the mesh is rendered from `AROUND` x `ROWS` directions spread over the sphere into an
+//! atlas of its color and mesh-space normals. Instances beyond a distance are then drawn
+//! as one camera-facing quad each, showing the tile baked from the direction nearest the
+//! one they're seen from and lit from the stored normals, all in a single instanced draw.
+//!
+//! Views snap from tile to tile as an instance turns, which shows up close but not at the
+//! distances impostors are meant for. Only vertex colors are baked, so meshes with a base
+//! color texture get no impostors and are drawn in full at any distance.
+
+use ash::vk;
+use glam::{Mat4, Vec3};
+use gpu_allocator::vulkan::Allocation;
+
+use crate::command_encoder::{CommandEncoder, PipelineBinding, RenderPassEncoder};
+use crate::compute::DescriptorWriter;
+use crate::frame_arena::ArenaSlice;
+use crate::lightmap;
+use crate::offscreen;
+use crate::pipeline_builder::{DepthMode, GraphicsPipelineBuilder, VertexLayout};
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::sampler_cache::SamplerDesc;
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+
+/// Baked directions around the vertical axis
+const AROUND: u32 = 8;
+/// Baked elevations, from below to above
+const ROWS: u32 = 6;
+/// Side of one view in the atlas, in pixels
+const TILE_SIZE: u32 = 128;
+
+const ALBEDO_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+const NORMAL_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// The mesh to bake, drawn indexed. Its vertices start with a position, color and normal
+/// at locations 0 to 2, as `renderer::Vertex` and `GltfVertex` do.
+pub struct ImpostorSource {
+    pub vertex_buffer: vk::Buffer,
+    pub index_buffer: vk::Buffer,
+    pub index_type: vk::IndexType,
+    pub index_count: u32,
+    pub radius: f32, // Of a sphere about the origin holding the whole mesh
+}
+
+// Must match shaders/impostor_bake.vert
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BakePushConstants {
+    view_proj: [[f32; 4]; 4],
+}
+
+// Must match shaders/impostor.vert
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ImpostorPushConstants {
+    view_proj: [[f32; 4]; 4],
+    camera: [f32; 4], // xyz = camera position, w = bounding radius
+    atlas: [f32; 4],  // x = directions around, y = elevation rows
+}
+
+/// Split `models` into those within `distance` of `camera` and those beyond it
+pub fn split_by_distance(models: &[Mat4], camera: Vec3, distance: f32) -> (Vec<Mat4>, Vec<Mat4>) {
+    let distance_squared = distance * distance;
+    models
+        .iter()
+        .partition(|model| model.w_axis.truncate().distance_squared(camera) <= distance_squared)
+}
+
+/// A baked mesh and the pipeline drawing its impostors
+pub struct Impostors {
+    radius: f32,
+    // Albedo, then normals
+    images: Vec<vk::Image>,
+    views: Vec<vk::ImageView>,
+    allocations: Vec<Option<Allocation>>,
+    sampler: vk::Sampler,
+
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+
+    instance_slices: Vec<Option<ArenaSlice>>, // Per frame in flight, in the frame arena
+    pub instance_count: u32,
+}
+
+impl Impostors {
+    /// Bake `source`, whose vertices are `V`s, and create the pipeline drawing its
+    /// impostors in `render_pass`, the scene pass (color + depth)
+    pub unsafe fn new<V: VertexLayout>(
+        renderer: &VulkanRenderer,
+        render_pass: vk::RenderPass,
+        source: &ImpostorSource,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut impostors = Self {
+            radius: source.radius,
+            images: Vec::new(),
+            views: Vec::new(),
+            allocations: Vec::new(),
+            sampler: vk::Sampler::null(),
+            pipeline: vk::Pipeline::null(),
+            layout: vk::PipelineLayout::null(),
+            render_pass,
+            set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            instance_slices: vec![None; MAX_FRAMES_IN_FLIGHT],
+            instance_count: 0,
+        };
+        let created = impostors.bake::<V>(renderer, source).and_then(|()| impostors.create_pipeline(renderer));
+        if let Err(e) = created {
+            impostors.destroy(renderer);
+            return Err(e);
+        }
+
+        println!(
+            "✓ Impostor atlas baked: {} views, {}x{}",
+            AROUND * ROWS,
+            AROUND * TILE_SIZE,
+            ROWS * TILE_SIZE
+        );
+        Ok(impostors)
+    }
+
+    /// Render every view of `source` into the atlas, leaving it ready to sample
+    unsafe fn bake<V: VertexLayout>(
+        &mut self,
+        renderer: &VulkanRenderer,
+        source: &ImpostorSource,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let extent = vk::Extent2D { width: AROUND * TILE_SIZE, height: ROWS * TILE_SIZE };
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED;
+        for (name, format) in [("impostor_albedo", ALBEDO_FORMAT), ("impostor_normal", NORMAL_FORMAT)] {
+            let (image, view, allocation) =
+                offscreen::create_image(renderer, name, format, extent, usage, vk::ImageAspectFlags::COLOR)?;
+            self.images.push(image);
+            self.views.push(view);
+            self.allocations.push(Some(allocation));
+        }
+
+        // Only the color atlases outlive the bake
+        let (depth_image, depth_view, depth_allocation) = offscreen::create_image(
+            renderer,
+            "impostor_depth",
+            DEPTH_FORMAT,
+            extent,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+        let baked = create_bake_render_pass(device).map_err(Box::<dyn std::error::Error>::from).and_then(|bake_pass| {
+            let attachments = [self.views[0], self.views[1], depth_view];
+            let framebuffer_info = vk::FramebufferCreateInfo::default()
+                .render_pass(bake_pass)
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            let baked = device.create_framebuffer(&framebuffer_info, None).map_err(Box::from).and_then(|framebuffer| {
+                let baked = create_bake_pipeline::<V>(device, bake_pass).and_then(|(pipeline, layout)| {
+                    let baked = lightmap::submit_once(renderer, |device, cmd| {
+                        record_bake(device, cmd, (bake_pass, framebuffer, extent), (pipeline, layout), source);
+                    });
+                    device.destroy_pipeline(pipeline, None);
+                    device.destroy_pipeline_layout(layout, None);
+                    baked
+                });
+                device.destroy_framebuffer(framebuffer, None);
+                baked
+            });
+            device.destroy_render_pass(bake_pass, None);
+            baked
+        });
+        device.destroy_image_view(depth_view, None);
+        device.destroy_image(depth_image, None);
+        let _ = renderer.allocator.lock().free(depth_allocation);
+        baked
+    }
+
+    unsafe fn create_pipeline(&mut self, renderer: &VulkanRenderer) -> Result<(), Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let (vert_code, frag_code) = Self::shaders();
+        let reflections = [&ShaderReflection::reflect(&vert_code)?, &ShaderReflection::reflect(&frag_code)?];
+
+        let bindings = shader_reflection::set_layout_bindings(&reflections, 0)?;
+        self.set_layout =
+            device.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings), None)?;
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &reflections,
+            std::mem::size_of::<ImpostorPushConstants>() as u32,
+        )?
+        .into_iter()
+        .collect();
+        self.layout = device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(std::slice::from_ref(&self.set_layout))
+                .push_constant_ranges(&push_constant_ranges),
+            None,
+        )?;
+
+        let pool_size = vk::DescriptorPoolSize { ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER, descriptor_count: 2 };
+        self.descriptor_pool = device.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::default()
+                .pool_sizes(std::slice::from_ref(&pool_size))
+                .max_sets(1),
+            None,
+        )?;
+        self.descriptor_set = device.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(std::slice::from_ref(&self.set_layout)),
+        )?[0];
+        self.sampler = renderer.sampler(SamplerDesc::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        DescriptorWriter::new()
+            .sampled_image(0, self.views[0], self.sampler)
+            .sampled_image(1, self.views[1], self.sampler)
+            .write(device, self.descriptor_set);
+
+        self.pipeline = self.build_pipeline(device, self.render_pass)?;
+        Ok(())
+    }
+
+    fn shaders() -> (Vec<u32>, Vec<u32>) {
+        (
+            load_shader("impostor.vert", include_bytes!("../shaders/impostor.vert.spv")),
+            load_shader("impostor.frag", include_bytes!("../shaders/impostor.frag.spv")),
+        )
+    }
+
+    /// The impostor pipeline for `render_pass`, which may differ from the scene pass in its
+    /// color format. The caller owns it; see `swap_pipeline`.
+    pub unsafe fn build_pipeline(
+        &self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+    ) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+        let (vert_code, frag_code) = Self::shaders();
+        // Coverage is alpha tested, so impostors write depth like the meshes they replace
+        GraphicsPipelineBuilder::new(self.layout, render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+            .instance_buffer::<Mat4>()
+            .depth(DepthMode::ReadWrite(vk::CompareOp::LESS))
+            .build(device)
+    }
+
+    /// Draw with `pipeline`, built for `render_pass` by `build_pipeline`, from now on.
+    /// Returns the pipeline it replaces.
+    pub fn swap_pipeline(&mut self, render_pass: vk::RenderPass, pipeline: vk::Pipeline) -> vk::Pipeline {
+        self.render_pass = render_pass;
+        std::mem::replace(&mut self.pipeline, pipeline)
+    }
+
+    /// Upload the model matrices of this frame's impostors into the frame arena. Call
+    /// after `VulkanRenderer::frame_arena` has begun the frame.
+    pub unsafe fn update_instances(
+        &mut self,
+        renderer: &VulkanRenderer,
+        frame_index: usize,
+        models: &[Mat4],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.instance_count = models.len() as u32;
+        self.instance_slices[frame_index] = None;
+        if models.is_empty() {
+            return Ok(());
+        }
+        self.instance_slices[frame_index] = Some(renderer.frame_arena.push(models).ok_or("frame arena allocation failed")?);
+        Ok(())
+    }
+
+    /// Draw this frame's impostors inside the scene render pass
+    pub fn draw(
+        &self,
+        pass: &mut RenderPassEncoder,
+        extent: vk::Extent2D,
+        frame_index: usize,
+        view_proj: Mat4,
+        camera_position: Vec3,
+    ) {
+        let Some(instances) = self.instance_slices[frame_index] else {
+            return;
+        };
+        if self.instance_count == 0 {
+            return;
+        }
+        pass.bind_pipeline(PipelineBinding {
+            pipeline: self.pipeline,
+            layout: self.layout,
+            render_pass: self.render_pass,
+            push_constant_size: std::mem::size_of::<ImpostorPushConstants>() as u32,
+        });
+        pass.set_full_viewport(extent);
+        pass.bind_vertex_buffers(&[instances.buffer], &[instances.offset]);
+        pass.bind_descriptor_set(0, self.descriptor_set);
+        let push_constants = ImpostorPushConstants {
+            view_proj: view_proj.to_cols_array_2d(),
+            camera: camera_position.extend(self.radius).to_array(),
+            atlas: [AROUND as f32, ROWS as f32, 0.0, 0.0],
+        };
+        pass.push_constants(vk::ShaderStageFlags::VERTEX, 0, &push_constants);
+        pass.draw(6, self.instance_count);
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.layout, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+        for view in self.views.drain(..) {
+            device.destroy_image_view(view, None);
+        }
+        for image in self.images.drain(..) {
+            device.destroy_image(image, None);
+        }
+        for allocation in self.allocations.drain(..).flatten() {
+            let _ = renderer.allocator.lock().free(allocation);
+        }
+    }
+}
+
+/// Mesh-space direction the view in `column`, `row` of the atlas was baked from
+fn view_direction(column: u32, row: u32) -> Vec3 {
+    let around = column as f32 / AROUND as f32 * std::f32::consts::TAU;
+    let elevation = ((row as f32 + 0.5) / ROWS as f32 - 0.5) * std::f32::consts::PI;
+    Vec3::new(elevation.cos() * around.cos(), elevation.sin(), elevation.cos() * around.sin())
+}
+
+unsafe fn record_bake(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    (render_pass, framebuffer, extent): (vk::RenderPass, vk::Framebuffer, vk::Extent2D),
+    (pipeline, layout): (vk::Pipeline, vk::PipelineLayout),
+    source: &ImpostorSource,
+) {
+    // Transparent where the mesh isn't, so impostors can alpha test
+    let clear_values = [
+        vk::ClearValue { color: vk::ClearColorValue { float32: [0.0; 4] } },
+        vk::ClearValue { color: vk::ClearColorValue { float32: [0.5, 0.5, 1.0, 0.0] } },
+        vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } },
+    ];
+    let mut encoder = CommandEncoder::new(device, command_buffer);
+    let mut pass = encoder.begin_render_pass(render_pass, framebuffer, extent, &clear_values);
+    pass.bind_pipeline(PipelineBinding {
+        pipeline,
+        layout,
+        render_pass,
+        push_constant_size: std::mem::size_of::<BakePushConstants>() as u32,
+    });
+    pass.bind_vertex_buffers(&[source.vertex_buffer], &[0]);
+    pass.bind_index_buffer(source.index_buffer, 0, source.index_type);
+
+    let radius = source.radius;
+    let mut proj = Mat4::orthographic_rh(-radius, radius, -radius, radius, 0.0, 4.0 * radius);
+    // Vulkan clip space has inverted Y
+    proj.y_axis.y *= -1.0;
+    for row in 0..ROWS {
+        for column in 0..AROUND {
+            let eye = view_direction(column, row) * 2.0 * radius;
+            let view = Mat4::look_at_rh(eye, Vec3::ZERO, Vec3::Y);
+            let tile = vk::Rect2D {
+                offset: vk::Offset2D { x: (column * TILE_SIZE) as i32, y: (row * TILE_SIZE) as i32 },
+                extent: vk::Extent2D { width: TILE_SIZE, height: TILE_SIZE },
+            };
+            pass.set_viewport(vk::Viewport {
+                x: tile.offset.x as f32,
+                y: tile.offset.y as f32,
+                width: TILE_SIZE as f32,
+                height: TILE_SIZE as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            });
+            pass.set_scissor(tile);
+            let push_constants = BakePushConstants { view_proj: (proj * view).to_cols_array_2d() };
+            pass.push_constants(vk::ShaderStageFlags::VERTEX, 0, &push_constants);
+            pass.draw_indexed(source.index_count, 1, 0);
+        }
+    }
+}
+
+/// Albedo and normal atlases, left ready to sample, and a throwaway depth buffer
+unsafe fn create_bake_render_pass(device: &ash::Device) -> Result<vk::RenderPass, vk::Result> {
+    let color_attachment = |format| {
+        vk::AttachmentDescription::default()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+    };
+    let attachments = [
+        color_attachment(ALBEDO_FORMAT),
+        color_attachment(NORMAL_FORMAT),
+        vk::AttachmentDescription::default()
+            .format(DEPTH_FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+    ];
+    let color_refs = [
+        vk::AttachmentReference { attachment: 0, layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL },
+        vk::AttachmentReference { attachment: 1, layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL },
+    ];
+    let depth_ref = vk::AttachmentReference { attachment: 2, layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL };
+    let subpass = vk::SubpassDescription::default()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_refs)
+        .depth_stencil_attachment(&depth_ref);
+
+    // The atlas is sampled by fragment shaders once the bake is done
+    let dependency = vk::SubpassDependency::default()
+        .src_subpass(0)
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+    let render_pass_info = vk::RenderPassCreateInfo::default()
+        .attachments(&attachments)
+        .subpasses(std::slice::from_ref(&subpass))
+        .dependencies(std::slice::from_ref(&dependency));
+    device.create_render_pass(&render_pass_info, None)
+}
+
+unsafe fn create_bake_pipeline<V: VertexLayout>(
+    device: &ash::Device,
+    render_pass: vk::RenderPass,
+) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<dyn std::error::Error>> {
+    let vert_code = load_shader("impostor_bake.vert", include_bytes!("../shaders/impostor_bake.vert.spv"));
+    let frag_code = load_shader("impostor_bake.frag", include_bytes!("../shaders/impostor_bake.frag.spv"));
+    let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+        &[&ShaderReflection::reflect(&vert_code)?, &ShaderReflection::reflect(&frag_code)?],
+        std::mem::size_of::<BakePushConstants>() as u32,
+    )?
+    .into_iter()
+    .collect();
+    let layout = device.create_pipeline_layout(
+        &vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&push_constant_ranges),
+        None,
+    )?;
+    let pipeline = GraphicsPipelineBuilder::new(layout, render_pass)
+        .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+        .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+        .vertex_buffer::<V>()
+        .color_targets(2)
+        .depth(DepthMode::ReadWrite(vk::CompareOp::LESS))
+        .build(device);
+    match pipeline {
+        Ok(pipeline) => Ok((pipeline, layout)),
+        Err(e) => {
+            device.destroy_pipeline_layout(layout, None);
+            Err(e)
+        }
+    }
+}
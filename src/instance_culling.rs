@@ -0,0 +1,171 @@
+//! GPU culling of the other instances
+//!
+//! The instances of the scene besides the primary one are drawn hardware instanced, one
+//! draw per mesh (see `GltfRenderer::set_instances`). With this pass on, a compute pass
+//! (instance_cull.comp) frustum-culls every instance of every such draw before each scene
+//! pass: the model matrices of the survivors are appended to the draw's range of a second
+//! buffer and counted as the instance count of the draw's indexed indirect command, which
+//! the scene pass issues with `vkCmdDrawIndexedIndirect`. Recording then costs the same
+//! however many instances there are, where culling them on the CPU (see `frustum`) visits
+//! each one.
+//!
+//! Everything lives in the frame arena: the draws and model matrices uploaded every frame,
+//! and the survivors and commands the pass writes, cleared again before each view culls.
+//! Shadow passes still draw every instance. The instances drawn aren't read back for the
+//! draw statistics, so indirect instance draws only count as calls.
+
+use ash::vk;
+use glam::{Mat4, Vec3};
+use crate::command_encoder::RenderPassEncoder;
+use crate::compute::{self, Access, ComputePipeline, DescriptorWriter};
+use crate::frame_arena::ArenaSlice;
+use crate::renderer::VulkanRenderer;
+use crate::shader_compiler::load_shader;
+
+/// Matches `InstanceDraw` in instance_cull.comp
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct InstanceDraw {
+    center: [f32; 3],
+    radius: f32,
+    index_count: u32,
+    first: u32,
+    count: u32,
+    _pad: u32,
+}
+
+impl InstanceDraw {
+    /// `count` instances of a mesh of `index_count` indices within `bounds` (model space),
+    /// their model matrices from `first` on. Meshes without reliable bounds, such as
+    /// skinned ones, pass `None` and are never culled.
+    pub fn new(bounds: Option<(Vec3, Vec3)>, index_count: u32, first: u32, count: u32) -> Self {
+        let (center, radius) = bounds.map_or((Vec3::ZERO, f32::MAX), |(min, max)| {
+            ((min + max) * 0.5, (max - min).length() * 0.5)
+        });
+        Self { center: center.to_array(), radius, index_count, first, count, _pad: 0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct InstanceCullPushConstants {
+    view_proj: [[f32; 4]; 4],
+}
+
+/// This frame's draws in the frame arena, see `InstanceCullPass::prepare`
+struct FrameDraws {
+    descriptor_set: vk::DescriptorSet,
+    draw_count: u32,
+    max_count: u32, // Instances of the draw with the most
+    visible_models: ArenaSlice,
+    commands: ArenaSlice,
+}
+
+pub struct InstanceCullPass {
+    pub enabled: bool,
+    pipeline: ComputePipeline,
+    frame: Option<FrameDraws>,
+}
+
+impl InstanceCullPass {
+    pub unsafe fn new(renderer: &VulkanRenderer) -> Result<Self, Box<dyn std::error::Error>> {
+        let code = load_shader("instance_cull.comp", include_bytes!("../shaders/instance_cull.comp.spv"));
+        // Descriptor sets come from the frame arena
+        let pipeline = ComputePipeline::new(renderer, &code, std::mem::size_of::<InstanceCullPushConstants>() as u32, 0)?;
+        Ok(Self { enabled: true, pipeline, frame: None })
+    }
+
+    /// Set up this frame's `draws`, of the model matrices `models` in the frame arena, for
+    /// culling. Call once every frame before recording, also without draws, so none of the
+    /// last frame's are culled again.
+    pub unsafe fn prepare(
+        &mut self,
+        renderer: &VulkanRenderer,
+        draws: &[InstanceDraw],
+        models: Option<ArenaSlice>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.frame = None;
+        let Some(models) = models.filter(|_| self.enabled && !draws.is_empty()) else {
+            return Ok(());
+        };
+        let arena = &renderer.frame_arena;
+        let model_count = (models.size / std::mem::size_of::<Mat4>() as u64) as usize;
+        let draw_slice = arena.push(draws).ok_or("frame arena allocation failed")?;
+        let visible_models = arena.reserve::<Mat4>(model_count).ok_or("frame arena allocation failed")?;
+        let commands = arena
+            .reserve::<vk::DrawIndexedIndirectCommand>(draws.len())
+            .ok_or("frame arena allocation failed")?;
+        let descriptor_set = arena.allocate_descriptor_set(self.pipeline.descriptor_set_layout)?;
+        DescriptorWriter::new()
+            .storage_buffer_range(0, draw_slice.descriptor_info())
+            .storage_buffer_range(1, models.descriptor_info())
+            .storage_buffer_range(2, visible_models.descriptor_info())
+            .storage_buffer_range(3, commands.descriptor_info())
+            .write(&renderer.device, descriptor_set);
+
+        self.frame = Some(FrameDraws {
+            descriptor_set,
+            draw_count: draws.len() as u32,
+            max_count: draws.iter().map(|draw| draw.count).max().unwrap_or(0),
+            visible_models,
+            commands,
+        });
+        Ok(())
+    }
+
+    /// Whether this frame's instance draws go through `draw`
+    pub fn active(&self) -> bool {
+        self.enabled && self.frame.is_some()
+    }
+
+    /// Cull every instance for the camera `view_proj` and rewrite the survivors and draw
+    /// commands. Record outside a render pass, before each pass that calls `draw`.
+    pub unsafe fn record_culling(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, view_proj: &Mat4) {
+        let Some(frame) = self.frame.as_ref().filter(|_| self.enabled) else {
+            return;
+        };
+
+        // Earlier passes (other views included) may still be reading the commands and models
+        let draw_reads = Access {
+            stage: vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT,
+            access: vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+        };
+        compute::memory_barrier(device, command_buffer, draw_reads, Access::TRANSFER_WRITE);
+        device.cmd_fill_buffer(command_buffer, frame.commands.buffer, frame.commands.offset, frame.commands.size, 0);
+        compute::memory_barrier(device, command_buffer, Access::TRANSFER_WRITE, Access::COMPUTE_READ_WRITE);
+
+        let pc = InstanceCullPushConstants { view_proj: view_proj.to_cols_array_2d() };
+        let bytes = std::slice::from_raw_parts(
+            (&pc as *const InstanceCullPushConstants) as *const u8,
+            std::mem::size_of::<InstanceCullPushConstants>(),
+        );
+        self.pipeline.dispatch_threads(
+            device,
+            command_buffer,
+            frame.descriptor_set,
+            bytes,
+            [frame.max_count, frame.draw_count, 1],
+        );
+
+        compute::memory_barrier(device, command_buffer, Access::COMPUTE_WRITE, draw_reads);
+    }
+
+    /// Issue this frame's culled draw `draw_index`, whose model matrices start at `first`,
+    /// with the mesh's vertices from `vertex_buffer`. The mesh's pipeline, push constants
+    /// and index buffer must be bound.
+    pub fn draw(&self, pass: &mut RenderPassEncoder, draw_index: usize, first: u32, vertex_buffer: vk::Buffer) {
+        let Some(frame) = &self.frame else {
+            return;
+        };
+        let models = &frame.visible_models;
+        let model_offset = models.offset + first as vk::DeviceSize * std::mem::size_of::<Mat4>() as vk::DeviceSize;
+        pass.bind_vertex_buffers(&[vertex_buffer, models.buffer], &[0, model_offset]);
+        let stride = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as vk::DeviceSize;
+        pass.draw_indexed_indirect((frame.commands.buffer, frame.commands.offset + draw_index as vk::DeviceSize * stride), 1);
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        self.frame = None;
+        self.pipeline.destroy(renderer);
+    }
+}
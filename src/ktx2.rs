@@ -0,0 +1,217 @@
+//! KTX2 textures
+//!
+//! glTF files using `KHR_texture_basisu` point their textures at KTX2 images. A KTX2 image
+//! whose levels hold BC1, BC3, BC4, BC5 or BC7 blocks, stored as they are or zstd
+//! supercompressed, is read and its mips are uploaded compressed, at a quarter (BC7) to an
+//! eighth (BC1) of the memory of RGBA8 and without the mip filtering PNG textures go
+//! through.
+//!
+//! The extension itself requires Basis Universal data (ETC1S or UASTC), which needs the
+//! Basis transcoder to become BC7 or BC1 blocks, and that isn't built in. Conforming files
+//! therefore always fall back to the texture's PNG or JPEG source, and only KTX2 images
+//! written with BC blocks directly load compressed.
+
+use std::io::Read;
+
+use ash::vk;
+
+use crate::gltf_loader::{CompressedTexture, GltfTexture};
+use crate::texture_streaming::MipLevel;
+
+const IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+/// Identifier, header and index, before the level index
+const HEADER_SIZE: usize = 80;
+/// Bytes per level in the level index: offset, length and uncompressed length
+const LEVEL_ENTRY_SIZE: usize = 24;
+
+/// `supercompressionScheme` of levels stored as they are
+const SUPERCOMPRESSION_NONE: u32 = 0;
+/// `supercompressionScheme` of zstd compressed levels
+const SUPERCOMPRESSION_ZSTD: u32 = 2;
+
+pub fn is_ktx2(bytes: &[u8]) -> bool {
+    bytes.starts_with(&IDENTIFIER)
+}
+
+/// Bytes per 4x4 block of the block-compressed formats read, None for any other format
+fn block_bytes(format: vk::Format) -> Option<u64> {
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => Some(8),
+        vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => Some(16),
+        _ => None,
+    }
+}
+
+/// Read a KTX2 file holding a block-compressed 2D texture, with the mips it has
+pub fn read(bytes: &[u8]) -> Result<GltfTexture, Box<dyn std::error::Error>> {
+    if !is_ktx2(bytes) || bytes.len() < HEADER_SIZE {
+        return Err("not a KTX2 file".into());
+    }
+    let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+
+    let format = vk::Format::from_raw(u32_at(12) as i32);
+    let (width, height, depth) = (u32_at(20), u32_at(24), u32_at(28));
+    let (layers, faces, level_count) = (u32_at(32), u32_at(36), u32_at(40).max(1));
+    let supercompression = u32_at(44);
+
+    if format == vk::Format::UNDEFINED {
+        return Err("Basis Universal (ETC1S/UASTC) data needs the Basis transcoder, which isn't built in".into());
+    }
+    let block_bytes = block_bytes(format).ok_or_else(|| format!("format {:?} isn't supported", format))?;
+    if supercompression != SUPERCOMPRESSION_NONE && supercompression != SUPERCOMPRESSION_ZSTD {
+        return Err(format!("supercompression scheme {} isn't supported", supercompression).into());
+    }
+    if width == 0 || height == 0 || depth > 1 || layers > 1 || faces != 1 {
+        return Err("only 2D textures are supported".into());
+    }
+    // The chain ends at 1x1, so a larger count can only come from a malformed file
+    let max_levels = 32 - width.max(height).leading_zeros();
+    if level_count > max_levels {
+        return Err(format!("{} levels for a {}x{} texture, at most {}", level_count, width, height, max_levels).into());
+    }
+
+    let mut mips = Vec::with_capacity(level_count as usize);
+    for level in 0..level_count {
+        let entry = HEADER_SIZE + level as usize * LEVEL_ENTRY_SIZE;
+        if bytes.len() < entry + LEVEL_ENTRY_SIZE {
+            return Err("level index is truncated".into());
+        }
+        let (offset, length) = (u64_at(entry), u64_at(entry + 8));
+        let (mip_width, mip_height) = ((width >> level).max(1), (height >> level).max(1));
+        let expected = mip_width.div_ceil(4) as u64 * mip_height.div_ceil(4) as u64 * block_bytes;
+        let end = offset
+            .checked_add(length)
+            .filter(|&end| end <= bytes.len() as u64)
+            .ok_or_else(|| format!("level {} lies past the end of the file", level))?;
+        let stored = &bytes[offset as usize..end as usize];
+        let data = if supercompression == SUPERCOMPRESSION_ZSTD {
+            decompress_zstd(stored, expected).map_err(|e| format!("level {}: {}", level, e))?
+        } else {
+            stored.to_vec()
+        };
+        if data.len() as u64 != expected {
+            return Err(format!("level {} has {} bytes, expected {}", level, data.len(), expected).into());
+        }
+        mips.push(MipLevel { width: mip_width, height: mip_height, data });
+    }
+
+    Ok(GltfTexture { width, height, data: Vec::new(), compressed: Some(CompressedTexture { format, mips }) })
+}
+
+/// Inflate a zstd supercompressed level, reading at most one byte past the `expected` size
+/// so a level claiming to be larger can't take up more memory than it
+fn decompress_zstd(stored: &[u8], expected: u64) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let decoder = ruzstd::decoding::StreamingDecoder::new(stored)?;
+    let mut data = Vec::new();
+    decoder.take(expected + 1).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A KTX2 file of `format` and `width` x `height` with `levels` stored as given
+    fn ktx2_file(format: vk::Format, (width, height): (u32, u32), supercompression: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        let mut header = IDENTIFIER.to_vec();
+        for value in [format.as_raw() as u32, 1, width, height, 0, 0, 1, levels.len() as u32, supercompression] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        header.resize(HEADER_SIZE, 0); // Empty data format descriptor and key/value data
+        let mut offset = (HEADER_SIZE + levels.len() * LEVEL_ENTRY_SIZE) as u64;
+        let mut data = Vec::new();
+        for level in levels {
+            for value in [offset, level.len() as u64, level.len() as u64] {
+                header.extend_from_slice(&value.to_le_bytes());
+            }
+            offset += level.len() as u64;
+            data.extend_from_slice(level);
+        }
+        header.extend(data);
+        header
+    }
+
+    /// BC1 levels of a full chain for an 8x8 texture: 2x2 blocks, then one, then one
+    fn bc1_levels() -> Vec<Vec<u8>> {
+        vec![vec![1; 32], vec![2; 8], vec![3; 8]]
+    }
+
+    fn level_count_at(file: &mut [u8], count: u32) {
+        file[40..44].copy_from_slice(&count.to_le_bytes());
+    }
+
+    #[test]
+    fn reads_every_level() {
+        let file = ktx2_file(vk::Format::BC1_RGBA_SRGB_BLOCK, (8, 8), SUPERCOMPRESSION_NONE, &bc1_levels());
+        let texture = read(&file).unwrap();
+        let compressed = texture.compressed.unwrap();
+        assert_eq!(compressed.format, vk::Format::BC1_RGBA_SRGB_BLOCK);
+        let sizes: Vec<_> = compressed.mips.iter().map(|mip| (mip.width, mip.height, mip.data.len())).collect();
+        assert_eq!(sizes, [(8, 8, 32), (4, 4, 8), (2, 2, 8)]);
+        assert_eq!(compressed.mips[1].data, [2; 8]);
+    }
+
+    #[test]
+    fn reads_zstd_supercompressed_levels() {
+        let levels = bc1_levels();
+        let packed: Vec<_> = levels
+            .iter()
+            .map(|level| ruzstd::encoding::compress_to_vec(&level[..], ruzstd::encoding::CompressionLevel::Fastest))
+            .collect();
+        let file = ktx2_file(vk::Format::BC1_RGBA_SRGB_BLOCK, (8, 8), SUPERCOMPRESSION_ZSTD, &packed);
+        let mips = read(&file).unwrap().compressed.unwrap().mips;
+        assert!(mips.iter().zip(&levels).all(|(mip, level)| &mip.data == level));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let file = ktx2_file(vk::Format::BC1_RGBA_SRGB_BLOCK, (8, 8), SUPERCOMPRESSION_NONE, &bc1_levels());
+        assert!(read(&file[..HEADER_SIZE - 1]).is_err());
+        assert!(read(&file[..HEADER_SIZE + LEVEL_ENTRY_SIZE]).is_err()); // Into the level index
+        assert!(read(&file[..file.len() - 1]).is_err()); // Into the last level
+    }
+
+    #[test]
+    fn rejects_more_levels_than_the_chain_has() {
+        let mut file = ktx2_file(vk::Format::BC1_RGBA_SRGB_BLOCK, (8, 8), SUPERCOMPRESSION_NONE, &bc1_levels());
+        level_count_at(&mut file, 5);
+        assert!(read(&file).is_err());
+        // Rejected before the level index is read or anything is allocated for it
+        level_count_at(&mut file, u32::MAX);
+        assert!(read(&file).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let levels = bc1_levels();
+        let mut file = ktx2_file(vk::Format::BC1_RGBA_SRGB_BLOCK, (8, 8), SUPERCOMPRESSION_NONE, &levels);
+        file[0] = 0;
+        assert!(read(&file).is_err());
+
+        // Basis Universal, uncompressed RGBA, a cube map, BasisLZ
+        assert!(read(&ktx2_file(vk::Format::UNDEFINED, (8, 8), 1, &levels)).is_err());
+        assert!(read(&ktx2_file(vk::Format::R8G8B8A8_SRGB, (8, 8), SUPERCOMPRESSION_NONE, &levels)).is_err());
+        let mut cube = ktx2_file(vk::Format::BC1_RGBA_SRGB_BLOCK, (8, 8), SUPERCOMPRESSION_NONE, &levels);
+        cube[36..40].copy_from_slice(&6u32.to_le_bytes());
+        assert!(read(&cube).is_err());
+        assert!(read(&ktx2_file(vk::Format::BC1_RGBA_SRGB_BLOCK, (0, 8), SUPERCOMPRESSION_NONE, &levels)).is_err());
+
+        // A level of the wrong size, and one pointing past the end of the file
+        let short = ktx2_file(vk::Format::BC1_RGBA_SRGB_BLOCK, (8, 8), SUPERCOMPRESSION_NONE, &[vec![0; 16]]);
+        assert!(read(&short).is_err());
+        let mut outside = ktx2_file(vk::Format::BC1_RGBA_SRGB_BLOCK, (8, 8), SUPERCOMPRESSION_NONE, &levels);
+        outside[HEADER_SIZE..HEADER_SIZE + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(read(&outside).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! Lens flares
+//!
+//! The sun gets a glow and a string of ghosts mirrored through the center of the screen,
+//! drawn as additive sprites over the finished frame. How much of the sun is visible
+//! comes from an occlusion query: at the end of the scene pass, a small square at the far
+//! plane where the sun is counts the samples that pass the depth test, i.e. where nothing
+//! was drawn in front of the sky. The count is read back when the frame slot comes around
+//! again, a few frames later, and eased towards so flares fade rather than pop.
+//!
+//! Without `occlusionQueryPrecise` a query may only report whether any sample passed; the
+//! sun is then either fully visible or hidden.
+
+use ash::vk;
+use glam::{Mat4, Vec2};
+
+use crate::command_encoder::{CommandEncoder, PipelineBinding, RenderPassEncoder};
+use crate::gltf_renderer::SUN_DIRECTION;
+use crate::pipeline_builder::{BlendMode, DepthMode, GraphicsPipelineBuilder};
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+
+/// Half the side of the occlusion square, in pixels
+const OCCLUSION_RADIUS: f32 = 8.0;
+/// How far visibility moves towards a new reading each frame
+const FADE_RATE: f32 = 0.3;
+
+/// What the flares look like
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlareStyle {
+    pub intensity: f32,     // 0 for no flares
+    pub ghosts: u32,        // Ghost sprites besides the glow
+    pub ghost_spacing: f32, // Between ghosts, as a fraction of the way from the sun through the center
+    pub glow_size: f32,     // Of the glow, as a fraction of the screen's height
+}
+
+impl Default for FlareStyle {
+    fn default() -> Self {
+        Self { intensity: 1.0, ghosts: 5, ghost_spacing: 0.4, glow_size: 0.35 }
+    }
+}
+
+// Must match shaders/flare_occlusion.vert
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct OcclusionPushConstants {
+    rect: [f32; 4], // xy = center, zw = half size, in NDC
+}
+
+// Must match shaders/lens_flare.vert
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FlarePushConstants {
+    sun: [f32; 4],    // xy = sun in NDC, z = aspect ratio, w = intensity
+    params: [f32; 4], // x = ghost count, y = ghost spacing, z = glow size
+}
+
+/// Where the sun is on screen, in NDC, for a camera with `view_proj`; `None` behind it
+pub fn sun_position(view_proj: &Mat4) -> Option<Vec2> {
+    let clip = *view_proj * SUN_DIRECTION.normalize().extend(0.0);
+    (clip.w > 0.0).then(|| Vec2::new(clip.x, clip.y) / clip.w)
+}
+
+pub struct LensFlare {
+    pub style: FlareStyle,
+    occlusion_pipeline: vk::Pipeline,
+    occlusion_layout: vk::PipelineLayout,
+    scene_render_pass: vk::RenderPass,
+    flare_pipeline: vk::Pipeline,
+    flare_layout: vk::PipelineLayout,
+    overlay_render_pass: vk::RenderPass,
+
+    // One query per frame in flight, with the samples it would count if nothing hid the sun
+    query_pool: vk::QueryPool,
+    expected_samples: Vec<Option<f32>>,
+    precise: bool,
+    visibility: f32, // Eased, 0 to 1
+}
+
+impl LensFlare {
+    /// The occlusion pipeline for `scene_render_pass`, the flares for the renderer's
+    /// color-only pass
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        scene_render_pass: vk::RenderPass,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let (occlusion_pipeline, occlusion_layout) = Self::create_pipeline::<OcclusionPushConstants>(
+            device,
+            scene_render_pass,
+            ("flare_occlusion.vert", include_bytes!("../shaders/flare_occlusion.vert.spv")),
+            ("flare_occlusion.frag", include_bytes!("../shaders/flare_occlusion.frag.spv")),
+            DepthMode::ReadOnly(vk::CompareOp::LESS_OR_EQUAL),
+        )?;
+        let flare = Self::create_pipeline::<FlarePushConstants>(
+            device,
+            renderer.render_pass,
+            ("lens_flare.vert", include_bytes!("../shaders/lens_flare.vert.spv")),
+            ("lens_flare.frag", include_bytes!("../shaders/lens_flare.frag.spv")),
+            DepthMode::Disabled,
+        );
+        let query_pool = flare.and_then(|flare| {
+            let query_info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::OCCLUSION)
+                .query_count(MAX_FRAMES_IN_FLIGHT as u32);
+            match device.create_query_pool(&query_info, None) {
+                Ok(query_pool) => Ok((flare, query_pool)),
+                Err(e) => {
+                    device.destroy_pipeline(flare.0, None);
+                    device.destroy_pipeline_layout(flare.1, None);
+                    Err(e.into())
+                }
+            }
+        });
+        let ((flare_pipeline, flare_layout), query_pool) = match query_pool {
+            Ok(created) => created,
+            Err(e) => {
+                device.destroy_pipeline(occlusion_pipeline, None);
+                device.destroy_pipeline_layout(occlusion_layout, None);
+                return Err(e);
+            }
+        };
+
+        if !renderer.occlusion_query_precise_supported {
+            println!("ℹ No precise occlusion queries, lens flares switch on and off instead of fading");
+        }
+        Ok(Self {
+            style: FlareStyle::default(),
+            occlusion_pipeline,
+            occlusion_layout,
+            scene_render_pass,
+            flare_pipeline,
+            flare_layout,
+            overlay_render_pass: renderer.render_pass,
+            query_pool,
+            expected_samples: vec![None; MAX_FRAMES_IN_FLIGHT],
+            precise: renderer.occlusion_query_precise_supported,
+            visibility: 0.0,
+        })
+    }
+
+    /// Additive full-screen-space sprites with push constants `P` only
+    unsafe fn create_pipeline<P>(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        (vert_name, vert_spv): (&str, &[u8]),
+        (frag_name, frag_spv): (&str, &[u8]),
+        depth: DepthMode,
+    ) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<dyn std::error::Error>> {
+        let vert_code = load_shader(vert_name, vert_spv);
+        let frag_code = load_shader(frag_name, frag_spv);
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &[&ShaderReflection::reflect(&vert_code)?, &ShaderReflection::reflect(&frag_code)?],
+            std::mem::size_of::<P>() as u32,
+        )?
+        .into_iter()
+        .collect();
+        let layout = device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&push_constant_ranges),
+            None,
+        )?;
+
+        let pipeline = GraphicsPipelineBuilder::new(layout, render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+            .blend(BlendMode::Additive)
+            .depth(depth)
+            .build(device);
+        match pipeline {
+            Ok(pipeline) => Ok((pipeline, layout)),
+            Err(e) => {
+                device.destroy_pipeline_layout(layout, None);
+                Err(e)
+            }
+        }
+    }
+
+    /// How much of the sun was visible lately, 0 to 1
+    pub fn visibility(&self) -> f32 {
+        self.visibility
+    }
+
+    /// Read the query frame slot `frame_index` last recorded and reset it. Call once per
+    /// frame outside any render pass, after the slot's fence has been waited on.
+    pub unsafe fn begin_frame(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        let target = match self.expected_samples[frame_index].take() {
+            Some(expected) => {
+                let mut samples = [0u64; 1];
+                let read = device.get_query_pool_results(
+                    self.query_pool,
+                    frame_index as u32,
+                    &mut samples,
+                    vk::QueryResultFlags::TYPE_64,
+                );
+                match read {
+                    Ok(()) if !self.precise => (samples[0] > 0) as u32 as f32,
+                    Ok(()) => (samples[0] as f32 / expected).min(1.0),
+                    Err(_) => self.visibility,
+                }
+            }
+            // Off screen or behind the camera
+            None => 0.0,
+        };
+        self.visibility += (target - self.visibility) * FADE_RATE;
+        device.cmd_reset_query_pool(command_buffer, self.query_pool, frame_index as u32, 1);
+    }
+
+    /// Count how many samples of the sun at `sun` (NDC) nothing has been drawn over. Record
+    /// last in the scene pass, after all opaque geometry, with `device`. Nothing is counted
+    /// while flares are off.
+    pub fn query(
+        &mut self,
+        device: &ash::Device,
+        pass: &mut RenderPassEncoder,
+        frame_index: usize,
+        sun: Vec2,
+        extent: vk::Extent2D,
+    ) {
+        // The part of the square on screen, in pixels
+        let (width, height) = (extent.width as f32, extent.height as f32);
+        let center = (sun * 0.5 + 0.5) * Vec2::new(width, height);
+        let min = (center - OCCLUSION_RADIUS).max(Vec2::ZERO);
+        let max = (center + OCCLUSION_RADIUS).min(Vec2::new(width, height));
+        let area = (max - min).max(Vec2::ZERO);
+        if self.style.intensity <= 0.0 || area.x * area.y < 1.0 {
+            return;
+        }
+
+        pass.bind_pipeline(PipelineBinding {
+            pipeline: self.occlusion_pipeline,
+            layout: self.occlusion_layout,
+            render_pass: self.scene_render_pass,
+            push_constant_size: std::mem::size_of::<OcclusionPushConstants>() as u32,
+        });
+        pass.set_full_viewport(extent);
+        let pc = OcclusionPushConstants {
+            rect: [sun.x, sun.y, 2.0 * OCCLUSION_RADIUS / width, 2.0 * OCCLUSION_RADIUS / height],
+        };
+        pass.push_constants(vk::ShaderStageFlags::VERTEX, 0, &pc);
+        let flags = if self.precise { vk::QueryControlFlags::PRECISE } else { vk::QueryControlFlags::empty() };
+        unsafe {
+            device.cmd_begin_query(pass.command_buffer(), self.query_pool, frame_index as u32, flags);
+            pass.draw(6, 1);
+            device.cmd_end_query(pass.command_buffer(), self.query_pool, frame_index as u32);
+        }
+        self.expected_samples[frame_index] = Some(area.x * area.y);
+    }
+
+    /// Draw the flares of the sun at `sun` (NDC) onto swapchain image `image_index`.
+    /// Record outside any render pass; does nothing while the sun is hidden.
+    pub unsafe fn record(&self, renderer: &VulkanRenderer, image_index: u32, sun: Vec2) {
+        let style = &self.style;
+        let intensity = style.intensity * self.visibility;
+        if intensity <= 0.001 {
+            return;
+        }
+        let extent = renderer.swapchain_extent;
+        let mut encoder = CommandEncoder::new(&renderer.device, renderer.command_buffers[renderer.current_frame]);
+        let mut pass =
+            encoder.begin_render_pass(self.overlay_render_pass, renderer.framebuffers[image_index as usize], extent, &[]);
+        pass.bind_pipeline(PipelineBinding {
+            pipeline: self.flare_pipeline,
+            layout: self.flare_layout,
+            render_pass: self.overlay_render_pass,
+            push_constant_size: std::mem::size_of::<FlarePushConstants>() as u32,
+        });
+        pass.set_full_viewport(extent);
+        let pc = FlarePushConstants {
+            sun: [sun.x, sun.y, extent.width as f32 / extent.height.max(1) as f32, intensity],
+            params: [style.ghosts as f32, style.ghost_spacing, style.glow_size, 0.0],
+        };
+        pass.push_constants(vk::ShaderStageFlags::VERTEX, 0, &pc);
+        pass.draw(6, 1 + style.ghosts);
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        device.destroy_query_pool(self.query_pool, None);
+        device.destroy_pipeline(self.occlusion_pipeline, None);
+        device.destroy_pipeline_layout(self.occlusion_layout, None);
+        device.destroy_pipeline(self.flare_pipeline, None);
+        device.destroy_pipeline_layout(self.flare_layout, None);
+    }
+}
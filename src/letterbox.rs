@@ -0,0 +1,165 @@
+//! Fixed aspect ratio viewport
+//!
+//! With an aspect ratio locked, the 3D view keeps that shape whatever the window's, centered
+//! between black bars: above and below in a window narrower than the ratio (letterboxing),
+//! left and right in a wider one (pillarboxing). Stills are rendered at the viewport's size,
+//! so their framing matches what the bars leave visible.
+//!
+//! The scene is still rendered over the whole window, so the screen-space passes keep
+//! working on full-window targets: the projection is widened until the viewport shows
+//! exactly what an unboxed view of its shape would, and the bars are cleared over the
+//! frame before the UI is drawn.
+
+use ash::vk;
+use bevy_ecs::prelude::*;
+
+use crate::gltf_renderer::Projection;
+
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AspectLock {
+    #[default]
+    Off, // The view fills the window
+    Widescreen, // 16:9
+    Scope,      // 2.39:1
+    Square,     // 1:1
+}
+
+impl AspectLock {
+    pub const ALL: [AspectLock; 4] = [AspectLock::Off, AspectLock::Widescreen, AspectLock::Scope, AspectLock::Square];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            AspectLock::Off => "Window",
+            AspectLock::Widescreen => "16:9",
+            AspectLock::Scope => "2.39:1",
+            AspectLock::Square => "1:1",
+        }
+    }
+
+    /// Width over height, None when unlocked
+    pub fn ratio(self) -> Option<f32> {
+        match self {
+            AspectLock::Off => None,
+            AspectLock::Widescreen => Some(16.0 / 9.0),
+            AspectLock::Scope => Some(2.39),
+            AspectLock::Square => Some(1.0),
+        }
+    }
+
+    /// The part of a frame of `extent` the 3D view shows: all of it when unlocked,
+    /// otherwise the largest centered rectangle of the locked ratio
+    pub fn viewport(self, extent: vk::Extent2D) -> vk::Rect2D {
+        let full = vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent };
+        let Some(ratio) = self.ratio() else {
+            return full;
+        };
+        let (width, height) = (extent.width as f32, extent.height as f32);
+        let (box_width, box_height) = if width / height.max(1.0) > ratio {
+            ((height * ratio).round() as u32, extent.height)
+        } else {
+            (extent.width, (width / ratio).round() as u32)
+        };
+        let box_width = box_width.clamp(1, extent.width.max(1));
+        let box_height = box_height.clamp(1, extent.height.max(1));
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: ((extent.width - box_width) / 2) as i32,
+                y: ((extent.height - box_height) / 2) as i32,
+            },
+            extent: vk::Extent2D { width: box_width, height: box_height },
+        }
+    }
+
+    /// The bars around the viewport of a frame of `extent`, none when it fills the frame
+    pub fn bars(self, extent: vk::Extent2D) -> Vec<vk::Rect2D> {
+        let viewport = self.viewport(extent);
+        let (x, y) = (viewport.offset.x as u32, viewport.offset.y as u32);
+        let rects = if x > 0 {
+            let right = x + viewport.extent.width;
+            [
+                vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent: vk::Extent2D { width: x, height: extent.height } },
+                vk::Rect2D {
+                    offset: vk::Offset2D { x: right as i32, y: 0 },
+                    extent: vk::Extent2D { width: extent.width - right, height: extent.height },
+                },
+            ]
+        } else {
+            let bottom = y + viewport.extent.height;
+            [
+                vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent: vk::Extent2D { width: extent.width, height: y } },
+                vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: bottom as i32 },
+                    extent: vk::Extent2D { width: extent.width, height: extent.height - bottom },
+                },
+            ]
+        };
+        rects.into_iter().filter(|rect| rect.extent.width > 0 && rect.extent.height > 0).collect()
+    }
+
+    /// `projection` widened for a view over all of a frame of `extent`, so that its
+    /// viewport shows what `projection` would over the viewport alone. Only letterboxing
+    /// needs it: a pillarboxed view has the window's height and so its vertical extent.
+    pub fn fit(self, projection: Projection, extent: vk::Extent2D) -> Projection {
+        let viewport = self.viewport(extent);
+        let scale = extent.height as f32 / viewport.extent.height.max(1) as f32;
+        Projection {
+            fov: 2.0 * ((projection.fov * 0.5).tan() * scale).atan(),
+            ortho_height: projection.ortho_height * scale,
+            ..projection
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32) -> vk::Extent2D {
+        vk::Extent2D { width, height }
+    }
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> vk::Rect2D {
+        vk::Rect2D { offset: vk::Offset2D { x, y }, extent: extent(width, height) }
+    }
+
+    #[test]
+    fn viewport_is_the_largest_centered_box_of_the_ratio() {
+        assert_eq!(AspectLock::Off.viewport(extent(1920, 1200)), rect(0, 0, 1920, 1200));
+        assert_eq!(AspectLock::Widescreen.viewport(extent(1920, 1080)), rect(0, 0, 1920, 1080));
+        // Letterboxed
+        assert_eq!(AspectLock::Widescreen.viewport(extent(1920, 1200)), rect(0, 60, 1920, 1080));
+        assert_eq!(AspectLock::Scope.viewport(extent(1920, 1080)), rect(0, 138, 1920, 803));
+        // Pillarboxed
+        assert_eq!(AspectLock::Square.viewport(extent(1920, 1080)), rect(420, 0, 1080, 1080));
+        assert_eq!(AspectLock::Widescreen.viewport(extent(1000, 450)), rect(100, 0, 800, 450));
+    }
+
+    #[test]
+    fn bars_cover_the_rest_of_the_frame() {
+        assert!(AspectLock::Off.bars(extent(1920, 1080)).is_empty());
+        assert!(AspectLock::Widescreen.bars(extent(1920, 1080)).is_empty());
+        assert_eq!(
+            AspectLock::Square.bars(extent(1920, 1080)),
+            [rect(0, 0, 420, 1080), rect(1500, 0, 420, 1080)]
+        );
+        assert_eq!(
+            AspectLock::Scope.bars(extent(1920, 1080)),
+            [rect(0, 0, 1920, 138), rect(0, 941, 1920, 139)]
+        );
+    }
+
+    #[test]
+    fn fit_widens_a_letterboxed_projection() {
+        let projection = Projection { fov: 60.0_f32.to_radians(), ortho_height: 10.0, orthographic: 0.5 };
+        let fitted = AspectLock::Widescreen.fit(projection, extent(1920, 1200));
+        let scale = 1200.0 / 1080.0;
+        assert!(((fitted.fov * 0.5).tan() - (projection.fov * 0.5).tan() * scale).abs() < 1e-5);
+        assert!((fitted.ortho_height - 10.0 * scale).abs() < 1e-4);
+        assert_eq!(fitted.orthographic, 0.5);
+
+        // A pillarboxed view keeps the window's height, and so the projection
+        let pillarboxed = AspectLock::Square.fit(projection, extent(1920, 1080));
+        assert!((pillarboxed.fov - projection.fov).abs() < 1e-6);
+        assert!((pillarboxed.ortho_height - 10.0).abs() < 1e-6);
+    }
+}
@@ -9,10 +9,31 @@
 //! ```
 
 pub mod renderer;
+pub mod acceleration_structure;
+pub mod async_compute;
+pub mod command_encoder;
+pub mod compute;
 pub mod cube;
+pub mod debug_messenger;
+pub mod draw_stats;
+pub mod frame_arena;
+pub mod frame_hooks;
 pub mod multithreading;
+pub mod offscreen;
+pub mod particles;
+pub mod pipeline_builder;
+pub mod primitives;
+pub mod render_target;
+pub mod sampler_cache;
+pub mod shader_compiler;
+pub mod shader_reflection;
+pub mod state_cache;
+pub mod swapchain;
+pub mod upload;
+pub mod window_surface;
 
 // Re-exports for library usage
 pub use renderer::VulkanRenderer;
 pub use cube::CubeRenderer;
+pub use frame_hooks::FrameContext;
 pub use multithreading::MultiThreadedRenderer;
@@ -0,0 +1,166 @@
+//! Diffuse light probes
+//!
+//! A probe is a point with a radius of influence. Baking renders the six cube faces of the
+//! glTF scene around the probe (see `screenshot::render_cube_faces`) and projects them onto
+//! nine spherical-harmonic coefficients per color channel, already convolved with the cosine
+//! lobe so the fragment shader gets irradiance for a normal with one dot product per band.
+//!
+//! `gltf.frag` blends the probes whose radius reaches an object's origin, weighted by
+//! distance, and uses the result instead of the constant ambient term; objects only
+//! partly covered mix towards the constant term.
+//!
+//! Probes are baked with the constant ambient term in place, so they see direct light and
+//! one bounce of it but not each other.
+
+use glam::Vec3;
+
+use crate::gltf_renderer::GltfRenderer;
+use crate::renderer::VulkanRenderer;
+use crate::screenshot;
+
+/// Probes the shaders can blend; must match `gltf.frag`
+pub const MAX_LIGHT_PROBES: usize = 8;
+/// Coefficients per channel (bands 0 to 2)
+pub const SH_COEFFICIENTS: usize = 9;
+/// Edge of the cube faces rendered for a probe, in pixels
+const BAKE_FACE_SIZE: u32 = 64;
+
+#[derive(Clone, Copy)]
+pub struct LightProbe {
+    pub position: Vec3,
+    pub radius: f32,
+    pub irradiance: Option<[Vec3; SH_COEFFICIENTS]>, // None until baked
+}
+
+/// The probes in the scene and how strongly they light it
+pub struct LightProbes {
+    pub probes: Vec<LightProbe>,
+    pub intensity: f32,
+}
+
+impl Default for LightProbes {
+    fn default() -> Self {
+        Self { probes: Vec::new(), intensity: 1.0 }
+    }
+}
+
+/// Probe data as laid out in the glTF uniform buffer
+pub struct ProbeUniforms {
+    pub positions: [[f32; 4]; MAX_LIGHT_PROBES], // xyz = position, w = radius
+    pub irradiance: [[f32; 4]; MAX_LIGHT_PROBES * SH_COEFFICIENTS],
+    pub params: [f32; 4], // x = probe count, y = intensity
+}
+
+impl LightProbes {
+    pub fn baked_count(&self) -> usize {
+        self.probes.iter().filter(|p| p.irradiance.is_some()).count()
+    }
+
+    /// The baked probes, up to `MAX_LIGHT_PROBES` of them
+    pub fn uniforms(&self) -> ProbeUniforms {
+        let mut uniforms = ProbeUniforms {
+            positions: [[0.0; 4]; MAX_LIGHT_PROBES],
+            irradiance: [[0.0; 4]; MAX_LIGHT_PROBES * SH_COEFFICIENTS],
+            params: [0.0, self.intensity, 0.0, 0.0],
+        };
+        let baked = self
+            .probes
+            .iter()
+            .filter_map(|p| p.irradiance.map(|sh| (p, sh)))
+            .take(MAX_LIGHT_PROBES);
+        for (i, (probe, sh)) in baked.enumerate() {
+            uniforms.positions[i] = probe.position.extend(probe.radius).to_array();
+            for (k, c) in sh.iter().enumerate() {
+                uniforms.irradiance[i * SH_COEFFICIENTS + k] = c.extend(0.0).to_array();
+            }
+            uniforms.params[0] = (i + 1) as f32;
+        }
+        uniforms
+    }
+}
+
+/// Bake every probe in `probes` from the scene as `gltf_renderer` currently draws it.
+/// Waits for the device to go idle first and leaves it idle.
+pub unsafe fn bake(
+    renderer: &VulkanRenderer,
+    gltf_renderer: &GltfRenderer,
+    probes: &mut [LightProbe],
+    shadow_softness: f32,
+    use_pcss: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("💡 Baking {} light probe(s)", probes.len());
+    let to_linear = screenshot::srgb_to_linear_table();
+    for probe in probes.iter_mut() {
+        let faces = screenshot::render_cube_faces(
+            renderer,
+            gltf_renderer,
+            probe.position,
+            BAKE_FACE_SIZE,
+            false,
+            shadow_softness,
+            use_pcss,
+        )?;
+
+        let mut radiance = [Vec3::ZERO; SH_COEFFICIENTS];
+        let mut total_weight = 0.0;
+        for face in &faces {
+            let inverse_view_proj = face.view_proj.inverse();
+            for y in 0..BAKE_FACE_SIZE {
+                let v = (y as f32 + 0.5) / BAKE_FACE_SIZE as f32 * 2.0 - 1.0;
+                for x in 0..BAKE_FACE_SIZE {
+                    let u = (x as f32 + 0.5) / BAKE_FACE_SIZE as f32 * 2.0 - 1.0;
+                    let far = inverse_view_proj * glam::Vec4::new(u, v, 1.0, 1.0);
+                    let direction = (far.truncate() / far.w - probe.position).normalize();
+                    // Solid angle of the texel, up to a constant normalized away below
+                    let weight = 1.0 / (1.0 + u * u + v * v).powf(1.5);
+                    let o = ((y * BAKE_FACE_SIZE + x) * 4) as usize;
+                    let color = Vec3::new(
+                        to_linear[face.pixels[o] as usize],
+                        to_linear[face.pixels[o + 1] as usize],
+                        to_linear[face.pixels[o + 2] as usize],
+                    );
+                    for (c, basis) in radiance.iter_mut().zip(sh_basis(direction)) {
+                        *c += color * (basis * weight);
+                    }
+                    total_weight += weight;
+                }
+            }
+        }
+
+        // Normalize to the sphere's 4π and convolve each band with the clamped cosine
+        let band_scale = [
+            std::f32::consts::PI,
+            2.0 * std::f32::consts::PI / 3.0,
+            std::f32::consts::FRAC_PI_4,
+        ];
+        let band = |k: usize| match k {
+            0 => 0,
+            1..=3 => 1,
+            _ => 2,
+        };
+        let norm = 4.0 * std::f32::consts::PI / total_weight;
+        let mut irradiance = [Vec3::ZERO; SH_COEFFICIENTS];
+        for (k, c) in irradiance.iter_mut().enumerate() {
+            *c = radiance[k] * norm * band_scale[band(k)];
+        }
+        probe.irradiance = Some(irradiance);
+    }
+    println!("✓ Baked {} light probe(s)", probes.len());
+    Ok(())
+}
+
+/// Real spherical harmonics of bands 0 to 2 at unit `d`, in the order `gltf.frag` expects
+fn sh_basis(d: Vec3) -> [f32; SH_COEFFICIENTS] {
+    [
+        0.282_095,
+        0.488_603 * d.y,
+        0.488_603 * d.z,
+        0.488_603 * d.x,
+        1.092_548 * d.x * d.y,
+        1.092_548 * d.y * d.z,
+        0.315_392 * (3.0 * d.z * d.z - 1.0),
+        1.092_548 * d.x * d.z,
+        0.546_274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
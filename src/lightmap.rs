@@ -0,0 +1,1072 @@
+//! Baked lightmaps
+//!
+//! Static meshes and the ground get the ambient term of `gltf.frag` from a lightmap atlas
+//! instead of the constant term or the light probes. Every static mesh with lightmap UVs
+//! gets a square tile of the atlas, the ground the last one. Meshes use TEXCOORD_1 as
+//! their UVs within the tile when the asset has it; with `--bake-lightmap` (or once a
+//! lightmap has been saved for the scene) meshes without it are charted at load time, one
+//! triangle per half grid cell, which unwelds them. Skinned meshes are never lightmapped.
+//!
+//! Baking runs on the GPU, a few sample directions per frame, spread evenly over the
+//! sphere. For each direction:
+//!
+//! 1. the scene is rendered orthographically from that direction, back faces only, into a
+//!    direction map: the radiance each surface sends back along the direction, from the
+//!    sun (with a sun depth map rendered once at the start) plus the indirect light
+//!    accumulated so far;
+//! 2. the static meshes and the ground are rasterized at their lightmap UVs and every texel
+//!    facing the direction adds the radiance it receives from it: the direction map where
+//!    geometry is in the way, the sky elsewhere.
+//!
+//! So each texel integrates the sky and every bounce of the sun's light over the sphere,
+//! with each sample seeing the bounces of the samples before it. The lightmap stores that
+//! irradiance over pi, the Lambertian ambient radiance per unit albedo; the sun's direct
+//! light stays dynamic. Occluders are found with one depth layer per direction, so a texel
+//! behind two surfaces sees the outer one.
+//!
+//! The bake uses the model transform at the time it starts and is saved as
+//! `lightmaps/<scene>.hdr`, which is loaded with the scene from then on.
+
+use ash::vk;
+use glam::{Mat4, Vec2, Vec3};
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+use std::path::{Path, PathBuf};
+
+use crate::command_encoder::{CommandEncoder, PipelineBinding, RenderPassEncoder};
+use crate::gltf_loader::{GltfMesh, GltfScene};
+use crate::gltf_renderer::{GltfRenderer, GltfVertex, GROUND_SIZE, SUN_DIRECTION};
+use crate::offscreen;
+use crate::pipeline_builder::{BlendMode, DepthMode, GraphicsPipelineBuilder};
+use crate::render_target::{RenderTarget, RenderTargetDesc};
+use crate::renderer::VulkanRenderer;
+use crate::sampler_cache::SamplerDesc;
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+
+/// Edge of the lightmap atlas, in texels
+pub const LIGHTMAP_SIZE: u32 = 1024;
+const LIGHTMAP_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Free texels around each tile, and around each triangle of a generated chart
+const TILE_PADDING: f32 = 2.0;
+const CHART_PADDING: f32 = 1.5;
+/// Passes of `dilate`, i.e. how far texel values bleed out of the charts
+const DILATE_PASSES: usize = 4;
+
+/// Sample directions per bake
+const BAKE_SAMPLES: u32 = 512;
+/// Sample directions rendered per frame while baking
+const SAMPLES_PER_FRAME: u32 = 4;
+const DIRECTION_MAP_SIZE: u32 = 2048;
+const SUN_MAP_SIZE: u32 = 2048;
+const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+/// Radiance of the sky. A uniform sky of it gives the constant ambient term of `gltf.frag`.
+const SKY_RADIANCE: f32 = 0.25;
+/// Irradiance from the sun; `gltf.frag` lights with 0.65 * albedo * cos(theta)
+const SUN_IRRADIANCE: f32 = 0.65 * std::f32::consts::PI;
+
+// Must match shaders/lightmap.vert
+const MODE_DIRECTION: i32 = 0;
+const MODE_SUN: i32 = 1;
+const MODE_ATLAS: i32 = 2;
+
+/// Square region of the atlas belonging to one mesh (or the ground)
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    offset: Vec2,
+    size: f32,
+}
+
+impl Tile {
+    /// Atlas position of `uv` in the unit square of the tile
+    pub fn uv(&self, uv: Vec2) -> [f32; 2] {
+        (self.offset + uv.clamp(Vec2::ZERO, Vec2::ONE) * self.size).to_array()
+    }
+
+    fn texels(&self) -> f32 {
+        self.size * LIGHTMAP_SIZE as f32
+    }
+}
+
+/// Tile `index` of `count`, on a square grid
+fn tile(index: usize, count: usize) -> Tile {
+    let columns = (count as f32).sqrt().ceil().max(1.0) as usize;
+    let cell = 1.0 / columns as f32;
+    let padding = TILE_PADDING / LIGHTMAP_SIZE as f32;
+    let corner = Vec2::new((index % columns) as f32, (index / columns) as f32) * cell;
+    Tile { offset: corner + Vec2::splat(padding), size: cell - 2.0 * padding }
+}
+
+fn is_lightmapped(mesh: &GltfMesh) -> bool {
+    mesh.has_lightmap_uvs && !mesh.has_joints
+}
+
+/// The ground's tile, after those of the meshes
+pub fn ground_tile(scene: &GltfScene) -> Tile {
+    let count = scene.meshes.iter().filter(|m| is_lightmapped(m)).count();
+    tile(count, count + 1)
+}
+
+/// Move the lightmap UVs of every static mesh into its tile, first charting the meshes
+/// without TEXCOORD_1 if `generate_charts` is set. Meshes left without lightmap UVs and
+/// skinned meshes get -1. Returns how many static meshes are left without.
+pub fn prepare_scene(scene: &mut GltfScene, generate_charts: bool) -> usize {
+    if generate_charts {
+        let count = scene.meshes.iter().filter(|m| !m.has_joints).count();
+        let texels = tile(0, count + 1).texels();
+        for mesh in scene.meshes.iter_mut().filter(|m| !m.has_lightmap_uvs && !m.has_joints) {
+            generate_chart(mesh, texels);
+        }
+    }
+
+    let count = scene.meshes.iter().filter(|m| is_lightmapped(m)).count() + 1;
+    let mut index = 0;
+    let mut uncharted = 0;
+    for mesh in &mut scene.meshes {
+        if is_lightmapped(mesh) {
+            let tile = tile(index, count);
+            index += 1;
+            for vertex in &mut mesh.vertices {
+                vertex.lightmap_uv = tile.uv(Vec2::from(vertex.lightmap_uv));
+            }
+        } else {
+            uncharted += usize::from(!mesh.has_joints);
+            for vertex in &mut mesh.vertices {
+                vertex.lightmap_uv = [-1.0, -1.0];
+            }
+        }
+    }
+    uncharted
+}
+
+/// Give every triangle of `mesh` its own vertices and half a grid cell of the unit square,
+/// which is `texels` across in the atlas. Triangles all get the same area whatever their
+/// size, but the charts never overlap and need no authoring.
+fn generate_chart(mesh: &mut GltfMesh, texels: f32) {
+    let triangles = mesh.indices.len() / 3;
+    let cells = (triangles.div_ceil(2) as f32).sqrt().ceil().max(1.0) as usize;
+    let cell = 1.0 / cells as f32;
+    if texels * cell < 4.0 * CHART_PADDING {
+        println!("⚠ Mesh with {} triangles is too dense for a legible lightmap chart", triangles);
+    }
+
+    // Two right triangles per cell, split along the diagonal, padded in cell units
+    let p = (CHART_PADDING / (texels * cell)).min(0.2);
+    let lower = [Vec2::new(p, p), Vec2::new(1.0 - 2.0 * p, p), Vec2::new(p, 1.0 - 2.0 * p)];
+    let upper = [Vec2::new(1.0 - p, 1.0 - p), Vec2::new(2.0 * p, 1.0 - p), Vec2::new(1.0 - p, 2.0 * p)];
+
+    let mut vertices = Vec::with_capacity(triangles * 3);
+    for (t, triangle) in mesh.indices.chunks_exact(3).enumerate() {
+        let corner = Vec2::new(((t / 2) % cells) as f32, ((t / 2) / cells) as f32);
+        let shape = if t % 2 == 0 { &lower } else { &upper };
+        for (&index, &local) in triangle.iter().zip(shape) {
+            let mut vertex = mesh.vertices[index as usize].clone();
+            vertex.lightmap_uv = ((corner + local) * cell).to_array();
+            vertices.push(vertex);
+        }
+    }
+    mesh.indices = (0..vertices.len() as u32).collect();
+    mesh.vertices = vertices;
+    mesh.has_lightmap_uvs = true;
+}
+
+/// Where the lightmap of the scene at `scene_path` is saved
+pub fn path_for(scene_path: &Path) -> PathBuf {
+    let stem = scene_path.file_stem().map_or_else(|| "scene".into(), |stem| stem.to_string_lossy());
+    Path::new("lightmaps").join(format!("{}.hdr", stem))
+}
+
+pub fn save(path: &Path, texels: &[Vec3]) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let data: Vec<f32> = texels.iter().flat_map(|t| t.to_array()).collect();
+    image::DynamicImage::ImageRgb32F(
+        image::Rgb32FImage::from_raw(LIGHTMAP_SIZE, LIGHTMAP_SIZE, data).ok_or("Lightmap has the wrong size")?,
+    )
+    .save(path)?;
+    println!("✓ Lightmap saved to {}", path.display());
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Vec<Vec3>, Box<dyn std::error::Error>> {
+    let image = image::open(path)?.into_rgb32f();
+    if image.dimensions() != (LIGHTMAP_SIZE, LIGHTMAP_SIZE) {
+        return Err(format!("{} is not {}x{}", path.display(), LIGHTMAP_SIZE, LIGHTMAP_SIZE).into());
+    }
+    Ok(image.pixels().map(|p| Vec3::from(p.0)).collect())
+}
+
+/// The lightmap the scene samples, binding 9 of its descriptor sets. Black until a bake
+/// or a saved lightmap is uploaded.
+pub struct Lightmap {
+    texture: RenderTarget,
+    sampler: vk::Sampler,
+    pub baked: bool,               // Something was uploaded
+    pub enabled: bool,             // Used for the ambient term when baked
+    pub uncharted_meshes: usize,   // Static meshes without lightmap UVs, see `prepare_scene`
+}
+
+impl Lightmap {
+    pub unsafe fn new(renderer: &VulkanRenderer) -> Result<Self, Box<dyn std::error::Error>> {
+        let extent = vk::Extent2D { width: LIGHTMAP_SIZE, height: LIGHTMAP_SIZE };
+        let desc = RenderTargetDesc::color(
+            LIGHTMAP_FORMAT,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        );
+        let sampler = renderer.sampler(SamplerDesc::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        let mut texture = RenderTarget::new(renderer, "lightmap", desc, extent)?;
+        let image = texture.color().image;
+        let cleared = submit_once(renderer, |device, cmd| {
+            transition(
+                device,
+                cmd,
+                image,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+            device.cmd_clear_color_image(
+                cmd,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue { float32: [0.0; 4] },
+                &[color_range()],
+            );
+            transition(
+                device,
+                cmd,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+        });
+        if let Err(e) = cleared {
+            texture.destroy(renderer);
+            return Err(e);
+        }
+        Ok(Self {
+            texture,
+            sampler,
+            baked: false,
+            enabled: true,
+            uncharted_meshes: 0,
+        })
+    }
+
+    pub fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: self.texture.color().view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+
+    /// `lightmapParams` of the glTF uniform buffer
+    pub fn uniforms(&self) -> [f32; 4] {
+        let in_use = if self.baked && self.enabled { 1.0 } else { 0.0 };
+        [in_use, 0.0, 0.0, 0.0]
+    }
+
+    /// Replace the lightmap with `texels`, `LIGHTMAP_SIZE` squared of them. The device must
+    /// be idle.
+    pub unsafe fn upload(&mut self, renderer: &VulkanRenderer, texels: &[Vec3]) -> Result<(), Box<dyn std::error::Error>> {
+        let halves: Vec<u16> = texels.iter().flat_map(|t| [t.x, t.y, t.z, 1.0]).map(f32_to_f16).collect();
+        let size = (halves.len() * std::mem::size_of::<u16>()) as u64;
+        if texels.len() != (LIGHTMAP_SIZE * LIGHTMAP_SIZE) as usize {
+            return Err("Lightmap has the wrong size".into());
+        }
+
+        let device = &renderer.device;
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(size)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = device.create_buffer(&buffer_info, None)?;
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let allocation = match renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: "lightmap_staging",
+            requirements,
+            location: MemoryLocation::CpuToGpu,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        }) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy_buffer(buffer, None);
+                return Err(e.into());
+            }
+        };
+        device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+        if let Some(mapped) = allocation.mapped_ptr() {
+            std::ptr::copy_nonoverlapping(halves.as_ptr(), mapped.as_ptr() as *mut u16, halves.len());
+        }
+
+        let image = self.texture.color().image;
+        let extent = self.texture.extent;
+        let copied = submit_once(renderer, |device, cmd| {
+            transition(
+                device,
+                cmd,
+                image,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+            let region = vk::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image_offset: vk::Offset3D::default(),
+                image_extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+            };
+            device.cmd_copy_buffer_to_image(cmd, buffer, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region]);
+            transition(
+                device,
+                cmd,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+        });
+        device.destroy_buffer(buffer, None);
+        renderer.allocator.lock().free(allocation)?;
+        copied?;
+        self.baked = true;
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        self.texture.destroy(renderer);
+    }
+}
+
+// Must match BakeUniforms in shaders/lightmap.vert + shaders/lightmap_*.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BakeUniforms {
+    sun_view_proj: [[f32; 4]; 4],
+    direction_view_proj: [[f32; 4]; 4],
+    sun_direction: [f32; 4],    // xyz = towards the sun, w = sun irradiance
+    sample_direction: [f32; 4], // xyz = sampled direction, w = normal offset
+    sky_radiance: [f32; 4],
+}
+
+// Must match shaders/lightmap.vert
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BakePushConstants {
+    model: [[f32; 4]; 4],
+    base_color: [f32; 4],
+    mode: i32,
+}
+
+/// A lightmap bake in progress, see the module docs
+pub struct LightmapBaker {
+    // Accumulation (rgb = summed irradiance, a = samples), direction map (radiance + depth), sun depth map
+    targets: Vec<RenderTarget>,
+    accumulation_format: vk::Format,
+    accumulation_pass: vk::RenderPass,
+    direction_pass: vk::RenderPass,
+    sun_pass: vk::RenderPass,
+    framebuffers: Vec<vk::Framebuffer>, // Same order as `targets`
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_stages: vk::ShaderStageFlags,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    uniform_buffer: vk::Buffer,
+    uniform_allocation: Option<Allocation>,
+    accumulation_pipeline: vk::Pipeline,
+    direction_pipeline: vk::Pipeline,
+    sun_pipeline: vk::Pipeline,
+    center: Vec3, // Bounding sphere of the model and the ground
+    radius: f32,
+    sun_view_proj: Mat4,
+    samples_done: u32,
+}
+
+const ACCUMULATION: usize = 0;
+const DIRECTION_MAP: usize = 1;
+const SUN_MAP: usize = 2;
+
+impl LightmapBaker {
+    /// Start baking the scene as `gltf_renderer` currently places it: clears the
+    /// accumulation and renders the sun's depth map. Waits for the graphics queue to go idle.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        gltf_renderer: &GltfRenderer,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Summing hundreds of samples wants more precision than half floats, where blendable
+        let full_float = renderer
+            .instance
+            .get_physical_device_format_properties(renderer.physical_device, vk::Format::R32G32B32A32_SFLOAT)
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND);
+        let accumulation_format =
+            if full_float { vk::Format::R32G32B32A32_SFLOAT } else { vk::Format::R16G16B16A16_SFLOAT };
+
+        let sampled = vk::ImageUsageFlags::SAMPLED;
+        let color = vk::ImageUsageFlags::COLOR_ATTACHMENT | sampled;
+        let depth = vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | sampled;
+        let square = |size| vk::Extent2D { width: size, height: size };
+        let descs = [
+            (
+                "lightmap_accumulation",
+                RenderTargetDesc::color(
+                    accumulation_format,
+                    color | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST,
+                ),
+                square(LIGHTMAP_SIZE),
+            ),
+            (
+                "lightmap_direction_map",
+                RenderTargetDesc::color(vk::Format::R16G16B16A16_SFLOAT, color).with_depth(DEPTH_FORMAT, depth),
+                square(DIRECTION_MAP_SIZE),
+            ),
+            ("lightmap_sun_map", RenderTargetDesc::depth(DEPTH_FORMAT, depth), square(SUN_MAP_SIZE)),
+        ];
+        let mut targets = Vec::with_capacity(descs.len());
+        for (name, desc, extent) in descs {
+            match RenderTarget::new(renderer, name, desc, extent) {
+                Ok(target) => targets.push(target),
+                Err(e) => {
+                    for mut target in targets {
+                        target.destroy(renderer);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let (center, radius) = Self::bounding_sphere(gltf_renderer);
+        let sun = SUN_DIRECTION.normalize();
+        let mut baker = Self {
+            targets,
+            accumulation_format,
+            accumulation_pass: vk::RenderPass::null(),
+            direction_pass: vk::RenderPass::null(),
+            sun_pass: vk::RenderPass::null(),
+            framebuffers: Vec::new(),
+            set_layout: vk::DescriptorSetLayout::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            push_constant_stages: vk::ShaderStageFlags::empty(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            uniform_buffer: vk::Buffer::null(),
+            uniform_allocation: None,
+            accumulation_pipeline: vk::Pipeline::null(),
+            direction_pipeline: vk::Pipeline::null(),
+            sun_pipeline: vk::Pipeline::null(),
+            center,
+            radius,
+            sun_view_proj: view_proj(center, radius, sun),
+            samples_done: 0,
+        };
+        if let Err(e) = baker.create_resources(renderer).and_then(|()| baker.begin(renderer, gltf_renderer)) {
+            baker.destroy(renderer);
+            return Err(e);
+        }
+
+        println!("💡 Baking lightmap: {} samples at {}x{}", BAKE_SAMPLES, LIGHTMAP_SIZE, LIGHTMAP_SIZE);
+        Ok(baker)
+    }
+
+    /// Bounds of the model and the ground as currently placed
+    fn bounding_sphere(gltf_renderer: &GltfRenderer) -> (Vec3, f32) {
+        let (model_min, model_max) = gltf_renderer.model_bounds;
+        let corners = (0..8).map(|i| {
+            let pick = |bit: usize, min: f32, max: f32| if i & bit != 0 { max } else { min };
+            Vec3::new(
+                pick(1, model_min.x, model_max.x),
+                pick(2, model_min.y, model_max.y),
+                pick(4, model_min.z, model_max.z),
+            )
+        });
+        let mut points: Vec<Vec3> = corners.map(|c| gltf_renderer.duck_model.transform_point3(c)).collect();
+        if gltf_renderer.ground.is_some() {
+            let half = GROUND_SIZE * 0.5;
+            for corner in [Vec3::new(-half, 0.0, -half), Vec3::new(half, 0.0, half)] {
+                points.push(gltf_renderer.ground_model.transform_point3(corner));
+            }
+        }
+        let (min, max) = points
+            .iter()
+            .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), &p| (min.min(p), max.max(p)));
+        ((min + max) * 0.5, ((max - min).length() * 0.5).max(0.01))
+    }
+
+    unsafe fn create_resources(&mut self, renderer: &VulkanRenderer) -> Result<(), Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+
+        self.accumulation_pass = create_render_pass(device, Some(self.accumulation_format), false)?;
+        self.direction_pass = create_render_pass(device, Some(vk::Format::R16G16B16A16_SFLOAT), true)?;
+        self.sun_pass = create_render_pass(device, None, true)?;
+        let passes = [self.accumulation_pass, self.direction_pass, self.sun_pass];
+        let attachments = [
+            vec![self.targets[ACCUMULATION].color().view],
+            vec![self.targets[DIRECTION_MAP].color().view, self.targets[DIRECTION_MAP].depth().view],
+            vec![self.targets[SUN_MAP].depth().view],
+        ];
+        for ((target, render_pass), views) in self.targets.iter().zip(passes).zip(&attachments) {
+            let framebuffer_info = vk::FramebufferCreateInfo::default()
+                .render_pass(render_pass)
+                .attachments(views)
+                .width(target.extent.width)
+                .height(target.extent.height)
+                .layers(1);
+            self.framebuffers.push(device.create_framebuffer(&framebuffer_info, None)?);
+        }
+
+        let vert_code = load_shader("lightmap.vert", include_bytes!("../shaders/lightmap.vert.spv"));
+        let radiance_code = load_shader("lightmap_radiance.frag", include_bytes!("../shaders/lightmap_radiance.frag.spv"));
+        let accumulate_code =
+            load_shader("lightmap_accumulate.frag", include_bytes!("../shaders/lightmap_accumulate.frag.spv"));
+        let vert = ShaderReflection::reflect(&vert_code)?;
+        let radiance = ShaderReflection::reflect(&radiance_code)?;
+        let accumulate = ShaderReflection::reflect(&accumulate_code)?;
+        let stages = [&vert, &radiance, &accumulate];
+
+        let bindings = shader_reflection::set_layout_bindings(&stages, 0)?;
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        self.set_layout = device.create_descriptor_set_layout(&layout_info, None)?;
+        let push_constant_range =
+            shader_reflection::push_constant_range(&stages, std::mem::size_of::<BakePushConstants>() as u32)?
+                .ok_or("lightmap.vert has no push constants")?;
+        self.push_constant_stages = push_constant_range.stage_flags;
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(std::slice::from_ref(&self.set_layout))
+            .push_constant_ranges(std::slice::from_ref(&push_constant_range));
+        self.pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None)?;
+
+        // Sample directions see the faces looking back at the texels, i.e. away from them
+        self.direction_pipeline = GraphicsPipelineBuilder::new(self.pipeline_layout, self.direction_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &radiance_code)
+            .vertex_buffer::<GltfVertex>()
+            .cull_mode(vk::CullModeFlags::FRONT)
+            .depth(DepthMode::ReadWrite(vk::CompareOp::LESS))
+            .build(device)?;
+        self.sun_pipeline = GraphicsPipelineBuilder::new(self.pipeline_layout, self.sun_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .vertex_buffer::<GltfVertex>()
+            .color_targets(0)
+            .depth(DepthMode::ReadWrite(vk::CompareOp::LESS))
+            .build(device)?;
+        self.accumulation_pipeline = GraphicsPipelineBuilder::new(self.pipeline_layout, self.accumulation_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &accumulate_code)
+            .vertex_buffer::<GltfVertex>()
+            .blend(BlendMode::Accumulate)
+            .build(device)?;
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize { ty: vk::DescriptorType::UNIFORM_BUFFER, descriptor_count: 1 },
+            vk::DescriptorPoolSize { ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER, descriptor_count: 4 },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo::default().pool_sizes(&pool_sizes).max_sets(1);
+        self.descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
+        let alloc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(std::slice::from_ref(&self.set_layout));
+        self.descriptor_set = device.allocate_descriptor_sets(&alloc_info)?[0];
+
+        let ubo_size = std::mem::size_of::<BakeUniforms>() as u64;
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(ubo_size)
+            .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        self.uniform_buffer = device.create_buffer(&buffer_info, None)?;
+        let requirements = device.get_buffer_memory_requirements(self.uniform_buffer);
+        let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: "lightmap_bake_uniforms",
+            requirements,
+            location: MemoryLocation::CpuToGpu,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        })?;
+        device.bind_buffer_memory(self.uniform_buffer, allocation.memory(), allocation.offset())?;
+        self.uniform_allocation = Some(allocation);
+
+        let linear = renderer.sampler(SamplerDesc::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        let nearest = renderer.sampler(SamplerDesc::nearest(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        let buffer_info = vk::DescriptorBufferInfo { buffer: self.uniform_buffer, offset: 0, range: ubo_size };
+        let image_info = |sampler, view| vk::DescriptorImageInfo {
+            sampler,
+            image_view: view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let images = [
+            (1, image_info(nearest, self.targets[SUN_MAP].depth().view)),
+            (2, image_info(linear, self.targets[DIRECTION_MAP].color().view)),
+            (3, image_info(nearest, self.targets[DIRECTION_MAP].depth().view)),
+            (4, image_info(linear, self.targets[ACCUMULATION].color().view)),
+        ];
+        let mut writes = vec![vk::WriteDescriptorSet::default()
+            .dst_set(self.descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(std::slice::from_ref(&buffer_info))];
+        for (binding, info) in &images {
+            writes.push(
+                vk::WriteDescriptorSet::default()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(*binding)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(info)),
+            );
+        }
+        device.update_descriptor_sets(&writes, &[]);
+        Ok(())
+    }
+
+    /// Clear the accumulation and render the sun's depth map
+    unsafe fn begin(
+        &self,
+        renderer: &VulkanRenderer,
+        gltf_renderer: &GltfRenderer,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_uniforms(Vec3::Y);
+        let accumulation = self.targets[ACCUMULATION].color().image;
+        submit_once(renderer, |device, cmd| {
+            transition(device, cmd, accumulation, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+            device.cmd_clear_color_image(
+                cmd,
+                accumulation,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue { float32: [0.0; 4] },
+                &[color_range()],
+            );
+            let (from, to) = (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+            transition(device, cmd, accumulation, from, to);
+
+            let mut encoder = CommandEncoder::new(device, cmd);
+            let clear = [vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } }];
+            let extent = self.targets[SUN_MAP].extent;
+            let mut pass = encoder.begin_render_pass(self.sun_pass, self.framebuffers[SUN_MAP], extent, &clear);
+            self.draw_scene(&mut pass, gltf_renderer, (self.sun_pipeline, self.sun_pass), extent, MODE_SUN);
+        })
+    }
+
+    /// Render the next few sample directions. Waits for the graphics queue to go idle.
+    pub unsafe fn step(
+        &mut self,
+        renderer: &VulkanRenderer,
+        gltf_renderer: &GltfRenderer,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for _ in 0..SAMPLES_PER_FRAME {
+            if self.done() {
+                break;
+            }
+            self.write_uniforms(sample_direction(self.samples_done, BAKE_SAMPLES));
+            submit_once(renderer, |device, cmd| {
+                let mut encoder = CommandEncoder::new(device, cmd);
+
+                let clear = [
+                    vk::ClearValue { color: vk::ClearColorValue { float32: [0.0; 4] } },
+                    vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } },
+                ];
+                let extent = self.targets[DIRECTION_MAP].extent;
+                let framebuffer = self.framebuffers[DIRECTION_MAP];
+                let mut pass = encoder.begin_render_pass(self.direction_pass, framebuffer, extent, &clear);
+                let pipeline = (self.direction_pipeline, self.direction_pass);
+                self.draw_scene(&mut pass, gltf_renderer, pipeline, extent, MODE_DIRECTION);
+                drop(pass);
+
+                let extent = self.targets[ACCUMULATION].extent;
+                let framebuffer = self.framebuffers[ACCUMULATION];
+                let mut pass = encoder.begin_render_pass(self.accumulation_pass, framebuffer, extent, &[]);
+                let pipeline = (self.accumulation_pipeline, self.accumulation_pass);
+                self.draw_scene(&mut pass, gltf_renderer, pipeline, extent, MODE_ATLAS);
+            })?;
+            self.samples_done += 1;
+        }
+        Ok(())
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.samples_done as f32 / BAKE_SAMPLES as f32
+    }
+
+    pub fn done(&self) -> bool {
+        self.samples_done >= BAKE_SAMPLES
+    }
+
+    /// The lightmap baked so far, irradiance over pi per texel. Texels no chart covers
+    /// get the value of nearby ones. Waits for the graphics queue to go idle.
+    pub unsafe fn finish(&self, renderer: &VulkanRenderer) -> Result<Vec<Vec3>, Box<dyn std::error::Error>> {
+        let target = &self.targets[ACCUMULATION];
+        let full_float = self.accumulation_format == vk::Format::R32G32B32A32_SFLOAT;
+        let texel_size = if full_float { 16 } else { 8 };
+        let bytes = offscreen::read_image(
+            renderer,
+            target.color().image,
+            target.extent,
+            texel_size,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+        let sums: Vec<f32> = if full_float {
+            bytes.chunks_exact(4).map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]])).collect()
+        } else {
+            bytes.chunks_exact(2).map(|b| f16_to_f32(u16::from_ne_bytes([b[0], b[1]]))).collect()
+        };
+
+        let mut covered: Vec<bool> = sums.chunks_exact(4).map(|sum| sum[3] > 0.0).collect();
+        let mut texels: Vec<Vec3> = sums
+            .chunks_exact(4)
+            .map(|sum| match sum[3] {
+                0.0 => Vec3::ZERO,
+                count => Vec3::new(sum[0], sum[1], sum[2]) / (count * std::f32::consts::PI),
+            })
+            .collect();
+        dilate(&mut texels, &mut covered, LIGHTMAP_SIZE as usize);
+        Ok(texels)
+    }
+
+    /// Destroy the baker. Its work must have finished.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        for pipeline in [self.accumulation_pipeline, self.direction_pipeline, self.sun_pipeline] {
+            device.destroy_pipeline(pipeline, None);
+        }
+        device.destroy_buffer(self.uniform_buffer, None);
+        if let Some(allocation) = self.uniform_allocation.take() {
+            let _ = renderer.allocator.lock().free(allocation);
+        }
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+        for framebuffer in self.framebuffers.drain(..) {
+            device.destroy_framebuffer(framebuffer, None);
+        }
+        for render_pass in [self.accumulation_pass, self.direction_pass, self.sun_pass] {
+            device.destroy_render_pass(render_pass, None);
+        }
+        for target in &mut self.targets {
+            target.destroy(renderer);
+        }
+    }
+
+    fn write_uniforms(&self, direction: Vec3) {
+        let sun = SUN_DIRECTION.normalize();
+        let uniforms = BakeUniforms {
+            sun_view_proj: self.sun_view_proj.to_cols_array_2d(),
+            direction_view_proj: view_proj(self.center, self.radius, direction).to_cols_array_2d(),
+            sun_direction: sun.extend(SUN_IRRADIANCE).to_array(),
+            // About two direction map texels against self-shadowing
+            sample_direction: direction.extend(4.0 * self.radius / DIRECTION_MAP_SIZE as f32).to_array(),
+            sky_radiance: [SKY_RADIANCE, SKY_RADIANCE, SKY_RADIANCE, 0.0],
+        };
+        if let Some(mapped) = self.uniform_allocation.as_ref().and_then(|a| a.mapped_ptr()) {
+            unsafe { std::ptr::write(mapped.as_ptr() as *mut BakeUniforms, uniforms) };
+        }
+    }
+
+    /// Draw every mesh and the ground with `pipeline` in `mode`
+    fn draw_scene(
+        &self,
+        pass: &mut RenderPassEncoder,
+        gltf_renderer: &GltfRenderer,
+        (pipeline, render_pass): (vk::Pipeline, vk::RenderPass),
+        extent: vk::Extent2D,
+        mode: i32,
+    ) {
+        pass.bind_pipeline(PipelineBinding {
+            pipeline,
+            layout: self.pipeline_layout,
+            render_pass,
+            push_constant_size: std::mem::size_of::<BakePushConstants>() as u32,
+        });
+        pass.bind_descriptor_set(0, self.descriptor_set);
+        pass.set_full_viewport(extent);
+        let meshes = gltf_renderer.meshes.iter().enumerate().map(|(i, mesh)| (mesh, gltf_renderer.mesh_model(i)));
+        let ground = gltf_renderer.ground.iter().map(|ground| (ground, gltf_renderer.ground_model));
+        for (mesh, model) in meshes.chain(ground) {
+            let push_constants = BakePushConstants {
+                model: model.to_cols_array_2d(),
+                base_color: gltf_renderer.materials.get(mesh.material).base_color,
+                mode,
+            };
+            pass.push_constants(self.push_constant_stages, 0, &push_constants);
+            pass.bind_vertex_buffers(&[mesh.vertex_buffer], &[0]);
+            pass.bind_index_buffer(mesh.index_buffer, 0, vk::IndexType::UINT32);
+            pass.draw_indexed(mesh.index_count, 1, 0);
+        }
+    }
+}
+
+/// Orthographic view of the sphere at `center` looking along `-direction`
+fn view_proj(center: Vec3, radius: f32, direction: Vec3) -> Mat4 {
+    let up = if direction.y.abs() > 0.99 { Vec3::X } else { Vec3::Y };
+    let view = Mat4::look_at_rh(center + direction * (2.0 * radius), center, up);
+    Mat4::orthographic_rh(-radius, radius, -radius, radius, 0.0, 4.0 * radius) * view
+}
+
+/// Direction `index` of `count` spread evenly over the sphere (a Fibonacci spiral)
+fn sample_direction(index: u32, count: u32) -> Vec3 {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5.0_f32.sqrt());
+    let y = 1.0 - 2.0 * (index as f32 + 0.5) / count as f32;
+    let ring = (1.0 - y * y).max(0.0).sqrt();
+    let phi = index as f32 * golden_angle;
+    Vec3::new(ring * phi.cos(), y, ring * phi.sin())
+}
+
+/// Spread covered texels into their uncovered neighbors, so bilinear filtering at chart
+/// edges doesn't pull in black
+fn dilate(texels: &mut [Vec3], covered: &mut [bool], size: usize) {
+    for _ in 0..DILATE_PASSES {
+        let before = covered.to_vec();
+        for y in 0..size {
+            for x in 0..size {
+                if before[y * size + x] {
+                    continue;
+                }
+                let neighbors = [
+                    (x > 0).then(|| y * size + x - 1),
+                    (x + 1 < size).then(|| y * size + x + 1),
+                    (y > 0).then(|| (y - 1) * size + x),
+                    (y + 1 < size).then(|| (y + 1) * size + x),
+                ];
+                let (sum, count) = neighbors
+                    .into_iter()
+                    .flatten()
+                    .filter(|&i| before[i])
+                    .fold((Vec3::ZERO, 0), |(sum, count), i| (sum + texels[i], count + 1));
+                if count > 0 {
+                    texels[y * size + x] = sum / count as f32;
+                    covered[y * size + x] = true;
+                }
+            }
+        }
+    }
+}
+
+/// A single-subpass pass over an optional color and an optional depth attachment that
+/// leaves both to be sampled. Without depth the color is added to rather than cleared.
+unsafe fn create_render_pass(
+    device: &ash::Device,
+    color_format: Option<vk::Format>,
+    depth: bool,
+) -> Result<vk::RenderPass, vk::Result> {
+    let mut attachments = Vec::new();
+    let mut color_refs = Vec::new();
+    if let Some(format) = color_format {
+        let (load_op, initial_layout) = if depth {
+            (vk::AttachmentLoadOp::CLEAR, vk::ImageLayout::UNDEFINED)
+        } else {
+            (vk::AttachmentLoadOp::LOAD, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        };
+        color_refs.push(vk::AttachmentReference {
+            attachment: attachments.len() as u32,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        });
+        attachments.push(
+            vk::AttachmentDescription::default()
+                .format(format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(load_op)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(initial_layout)
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        );
+    }
+    let depth_ref = vk::AttachmentReference {
+        attachment: attachments.len() as u32,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+    if depth {
+        attachments.push(
+            vk::AttachmentDescription::default()
+                .format(DEPTH_FORMAT)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        );
+    }
+
+    let mut subpass = vk::SubpassDescription::default()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_refs);
+    if depth {
+        subpass = subpass.depth_stencil_attachment(&depth_ref);
+    }
+
+    // Earlier passes sampled what this one writes, and later ones sample its output
+    let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+    let attachment_access = vk::AccessFlags::COLOR_ATTACHMENT_READ
+        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+    let dependencies = [
+        vk::SubpassDependency::default()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(attachment_stages | vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER)
+            .src_access_mask(attachment_access | vk::AccessFlags::TRANSFER_WRITE)
+            .dst_stage_mask(attachment_stages)
+            .dst_access_mask(attachment_access),
+        vk::SubpassDependency::default()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(attachment_stages)
+            .src_access_mask(attachment_access)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ),
+    ];
+
+    let render_pass_info = vk::RenderPassCreateInfo::default()
+        .attachments(&attachments)
+        .subpasses(std::slice::from_ref(&subpass))
+        .dependencies(&dependencies);
+    device.create_render_pass(&render_pass_info, None)
+}
+
+fn color_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
+
+/// Move a color image between layouts. Only used around one-off transfers, so it simply
+/// waits for all earlier work.
+unsafe fn transition(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    image: vk::Image,
+    old: vk::ImageLayout,
+    new: vk::ImageLayout,
+) {
+    let barrier = vk::ImageMemoryBarrier::default()
+        .old_layout(old)
+        .new_layout(new)
+        .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+        .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(color_range());
+    device.cmd_pipeline_barrier(
+        cmd,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        std::slice::from_ref(&barrier),
+    );
+}
+
+/// Record commands with `record`, submit them to the graphics queue and wait for it
+pub unsafe fn submit_once(
+    renderer: &VulkanRenderer,
+    record: impl FnOnce(&ash::Device, vk::CommandBuffer),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let device = &renderer.device;
+    let cmd_info = vk::CommandBufferAllocateInfo::default()
+        .command_pool(renderer.command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1);
+    let cmd = device.allocate_command_buffers(&cmd_info)?[0];
+    let begin_info = vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(cmd, &begin_info)?;
+    record(device, cmd);
+    device.end_command_buffer(cmd)?;
+
+    let command_buffers = [cmd];
+    let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+    let result = device
+        .queue_submit(renderer.graphics_queue, &[submit_info], vk::Fence::null())
+        .and_then(|()| device.queue_wait_idle(renderer.graphics_queue));
+    device.free_command_buffers(renderer.command_pool, &command_buffers);
+    Ok(result?)
+}
+
+/// Nearest half float, as the lightmap and half precision HDR images store texels
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent >= 0x1f {
+        return sign | 0x7c00; // Too large: infinity
+    }
+    if exponent <= 0 {
+        // Subnormal, or zero when even that is too small
+        if exponent < -10 {
+            return sign;
+        }
+        let shift = (14 - exponent) as u32;
+        let mantissa = mantissa | 0x80_0000;
+        return sign | ((mantissa + (1 << (shift - 1))) >> shift) as u16;
+    }
+    // Rounding may carry into the exponent, which is still the nearest value
+    sign | (((exponent as u32) << 10) + ((mantissa + 0x1000) >> 13)) as u16
+}
+
+/// Value of a half float, as RGBA16F targets read back
+pub fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((half >> 10) & 0x1f);
+    let mantissa = f32::from(half & 0x3ff);
+    sign * match exponent {
+        0 => mantissa * 2.0_f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2.0_f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f32_to_f16_rounds_to_the_nearest_half() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(1.0 + 1.0 / 1024.0), 0x3c01);
+        assert_eq!(f32_to_f16(1.0 + 1.0 / 4096.0), 0x3c00); // Rounds down
+        assert_eq!(f32_to_f16(1.0 + 3.0 / 4096.0), 0x3c01); // Rounds up
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    }
+
+    #[test]
+    fn f32_to_f16_handles_the_edges_of_the_range() {
+        assert_eq!(f32_to_f16(2.0_f32.powi(-14)), 0x0400); // Smallest normal
+        assert_eq!(f32_to_f16(2.0_f32.powi(-24)), 0x0001); // Smallest subnormal
+        assert_eq!(f32_to_f16(2.0_f32.powi(-26)), 0x0000);
+        assert_eq!(f32_to_f16(65536.0), 0x7c00);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f32_to_f16(f32::NAN) & 0x7fff, 0x7e00);
+    }
+
+    #[test]
+    fn f16_round_trips() {
+        for value in [0.0, 1.0, -0.25, 3.140625, 65504.0, 2.0_f32.powi(-20)] {
+            assert_eq!(f16_to_f32(f32_to_f16(value)), value);
+        }
+    }
+}
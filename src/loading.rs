@@ -0,0 +1,191 @@
+//! Startup loading screen
+//!
+//! Large scenes take seconds to parse, chart and filter into mips, which used to leave a
+//! blank, unresponsive window until the first frame. Now the window and egui come up first
+//! and draw a progress screen while the rest of startup runs in stages:
+//!
+//! 1. A worker thread reads the glTF scene, charts it for the lightmap and builds the mip
+//!    chain of every texture.
+//! 2. Once it is done, the main thread creates the GPU objects stage by stage, one stage per
+//!    frame: the scene renderer with its uploads, the cubes, the screen effects and the
+//!    particles.
+//! 3. Meanwhile a second worker builds the scene renderer's pipelines, one per shader
+//!    permutation, which are handed over once it is done.
+//!
+//! Recording uploads stays on the main thread, since everything is submitted to the one
+//! graphics queue, which only the main thread touches. Creating a pipeline needs no queue,
+//! so the many scene pipelines are built on the worker. Between stages the window keeps
+//! handling events and redrawing the loading screen.
+
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use crate::gltf_loader::GltfScene;
+use crate::gltf_renderer::ScenePipelines;
+use crate::lightmap;
+use crate::texture_streaming;
+
+/// Where a scene is looked for, in order
+pub const SCENE_PATHS: [&str; 8] = [
+    "models/scene.gltf",
+    "models/scene.glb",
+    "models/model.gltf",
+    "models/model.glb",
+    "scene.gltf",
+    "scene.glb",
+    "model.gltf",
+    "model.glb",
+];
+
+/// A startup step, in the order they run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Scene,
+    SceneRenderer,
+    ScenePipelines,
+    Cubes,
+    Effects,
+    Particles,
+}
+
+impl Stage {
+    const ALL: [Stage; 6] = [
+        Stage::Scene,
+        Stage::SceneRenderer,
+        Stage::ScenePipelines,
+        Stage::Cubes,
+        Stage::Effects,
+        Stage::Particles,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Stage::Scene => "Reading scene",
+            Stage::SceneRenderer => "Uploading meshes and textures",
+            Stage::ScenePipelines => "Building scene pipelines",
+            Stage::Cubes => "Building cubes and impostors",
+            Stage::Effects => "Creating screen effects",
+            Stage::Particles => "Creating particles",
+        }
+    }
+}
+
+/// A scene read and prepared by the worker
+pub struct LoadedScene {
+    pub path: PathBuf,
+    pub scene: GltfScene,
+    pub uncharted: usize, // Meshes left without lightmap UVs, see `lightmap::prepare_scene`
+    pub lightmap_path: PathBuf,
+}
+
+/// What the worker found: nothing when no scene file exists
+type SceneResult = Option<Result<LoadedScene, String>>;
+
+type PipelineResult = Result<ScenePipelines, String>;
+
+pub struct Loading {
+    stage: usize, // Into `Stage::ALL`
+    worker: Option<JoinHandle<SceneResult>>,
+    scene: Option<SceneResult>, // Once the worker finished, until taken
+    pipeline_worker: Option<JoinHandle<PipelineResult>>,
+    pipelines: Option<PipelineResult>, // Once the pipeline worker finished, until taken
+    started: Instant,
+}
+
+impl Loading {
+    /// Start reading the scene on a worker thread. `charts` chart meshes without lightmap
+    /// UVs even when no saved lightmap needs them; `ktx2` reads KTX2 textures (see
+    /// `GltfScene::load`).
+    pub fn start(charts: bool, ktx2: bool) -> Self {
+        let worker = std::thread::Builder::new()
+            .name("scene loader".into())
+            .spawn(move || load_scene(charts, ktx2))
+            .expect("Failed to start the scene loader thread");
+        Self {
+            stage: 0,
+            worker: Some(worker),
+            scene: None,
+            pipeline_worker: None,
+            pipelines: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// The stage to run next, or None once startup is complete
+    pub fn stage(&self) -> Option<Stage> {
+        Stage::ALL.get(self.stage).copied()
+    }
+
+    pub fn advance(&mut self) {
+        self.stage += 1;
+    }
+
+    /// Whether the worker is done, collecting its result if so
+    pub fn scene_ready(&mut self) -> bool {
+        if self.worker.as_ref().is_some_and(|worker| worker.is_finished()) {
+            let result = self.worker.take().unwrap().join();
+            self.scene = Some(result.unwrap_or_else(|_| Some(Err("scene loader panicked".into()))));
+        }
+        self.worker.is_none()
+    }
+
+    /// The worker's result, once. None when no scene file exists.
+    pub fn take_scene(&mut self) -> SceneResult {
+        self.scene.take().flatten()
+    }
+
+    /// Run `build`, a `GltfRenderer::scene_pipeline_builder` job, on a worker thread
+    pub fn start_pipelines(&mut self, build: impl FnOnce() -> PipelineResult + Send + 'static) {
+        let worker = std::thread::Builder::new()
+            .name("pipeline builder".into())
+            .spawn(build)
+            .expect("Failed to start the pipeline builder thread");
+        self.pipeline_worker = Some(worker);
+    }
+
+    /// Whether the pipeline worker is done or was never started, collecting its result if so
+    pub fn pipelines_ready(&mut self) -> bool {
+        if self.pipeline_worker.as_ref().is_some_and(|worker| worker.is_finished()) {
+            let result = self.pipeline_worker.take().unwrap().join();
+            self.pipelines = Some(result.unwrap_or_else(|_| Err("pipeline builder panicked".into())));
+        }
+        self.pipeline_worker.is_none()
+    }
+
+    /// The pipeline worker's result, once. None when it was never started.
+    pub fn take_pipelines(&mut self) -> Option<PipelineResult> {
+        self.pipelines.take()
+    }
+
+    /// Draw the loading screen over the whole window
+    pub fn draw(&self, ctx: &egui::Context) {
+        let progress = self.stage as f32 / Stage::ALL.len() as f32;
+        let label = self.stage().map_or("Starting", Stage::label);
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(ui.available_height() * 0.4);
+                ui.heading("Funky Renderer");
+                ui.add_space(12.0);
+                ui.add(egui::ProgressBar::new(progress).desired_width(320.0).animate(true));
+                ui.add_space(6.0);
+                ui.label(format!("{}… ({:.1} s)", label, self.started.elapsed().as_secs_f32()));
+            });
+        });
+    }
+}
+
+/// Find, read and prepare the scene. Runs on the worker.
+fn load_scene(charts: bool, ktx2: bool) -> SceneResult {
+    let path = SCENE_PATHS.iter().map(Path::new).find(|path| path.exists())?;
+    println!("📦 Loading glTF scene from: {}", path.display());
+    let result = GltfScene::load(path, ktx2).map(|mut scene| {
+        // Charted before the vertex buffers are made; a saved lightmap was baked on the
+        // same charts
+        let lightmap_path = lightmap::path_for(path);
+        let uncharted = lightmap::prepare_scene(&mut scene, charts || lightmap_path.exists());
+        texture_streaming::build_scene_mips(&mut scene);
+        LoadedScene { path: path.to_path_buf(), scene, uncharted, lightmap_path }
+    });
+    Some(result.map_err(|e| e.to_string()))
+}
@@ -2,25 +2,140 @@
 //! 
 //! Uses Bevy's ECS for game logic, custom ash/Vulkan for rendering, egui for debug UI.
 
+/// Console output, also kept for diagnostics snapshots (see `diagnostics`). Defined ahead
+/// of the modules so it shadows the standard macro in all of them.
+macro_rules! println {
+    () => {
+        println!("")
+    };
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        std::println!("{}", line);
+        $crate::diagnostics::log_line(line);
+    }};
+}
+
+/// Error output, kept like `println!`
+macro_rules! eprintln {
+    () => {
+        eprintln!("")
+    };
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        std::eprintln!("{}", line);
+        $crate::diagnostics::log_line(line);
+    }};
+}
+
 mod renderer;
+mod acceleration_structure;
+mod animation;
+mod aov;
+mod async_compute;
+mod command_encoder;
+mod compute;
 mod cube;
+mod debug_messenger;
+mod defrag;
+mod descriptor_buffer;
+mod debug_draw;
+mod diagnostics;
+mod display;
+mod draw_order;
+mod draw_stats;
+mod fixed_timestep;
+mod frame_arena;
+mod frame_hooks;
+mod frustum;
 mod multithreading;
+mod offscreen;
+mod particles;
+mod pipeline_builder;
+mod pipeline_statistics;
+mod render_target;
+mod render_layers;
+mod profiling;
+mod reflection_probes;
+mod god_rays;
+mod half_res;
+mod ssgi;
+mod lens_flare;
+mod letterbox;
+mod weather;
+mod vegetation;
+mod impostor;
+mod planar_reflection;
+mod post_effects;
+mod primitives;
 mod egui_integration;
 mod egui_vulkan;
+mod gltf_animation;
+mod gltf_export;
 mod gltf_loader;
 mod gltf_renderer;
+mod gpu_driven;
+mod ground;
+mod hdr_image;
+mod hierarchy;
+mod instance_culling;
+mod ktx2;
+mod light_probes;
+mod lightmap;
+mod loading;
+mod material;
+mod material_preview;
+mod meshlets;
+mod sampler_cache;
+mod scene;
+mod screenshot;
+mod shader_compiler;
+mod shader_reflection;
+mod shader_watcher;
+mod skinning;
+mod skybox;
+mod state_cache;
+mod stress;
+mod swapchain;
+mod texture_array;
+mod toon;
+mod trackball;
+mod texture_streaming;
+mod time_control;
+mod upload;
+mod virtual_texture;
+mod visibility;
+#[cfg(test)]
+mod test_support;
+mod window_surface;
 
-use renderer::VulkanRenderer;
-use egui_integration::{EguiIntegration, UiData, ComponentCounts};
+use renderer::{RendererBuilder, VulkanRenderer};
+use animation::{AnimationPlayer, Easing, RepeatMode, TransformTrack};
+use async_compute::AsyncCompute;
+use profiling::GpuProfiler;
+use compute::Access;
+use command_encoder::CommandEncoder;
+use cube::CubeRenderer;
+use debug_draw::DebugDraw;
+use display::{DisplaySettings, FullscreenMode, VideoModeKey};
+use egui_integration::{EguiIntegration, UiData, ComponentCounts, SceneTreeNode};
 use egui_vulkan::EguiVulkanRenderer;
 use gltf_loader::GltfScene;
-use gltf_renderer::GltfRenderer;
+use gltf_renderer::{GltfRenderer, GltfView, ModelInstance, Projection, ShadowUniforms, ViewCamera};
+use hierarchy::{GlobalTransform, HierarchyCommandsExt};
+use fixed_timestep::{FixedTime, TransformInterpolation};
+use particles::ParticleSystem;
+use trackball::{Manipulation, Trackball};
+use visibility::{InheritedVisibility, Visibility};
+use render_layers::RenderLayers;
+use scene::{Scene, ScenePart};
+use vegetation::{FoliageKind, VegetationLayer};
+use window_surface::WindowSurface;
 use ash::vk;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use winit::{
     application::ApplicationHandler,
     event::WindowEvent,
-    event_loop::{ActiveEventLoop, EventLoop},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
     keyboard::{KeyCode, PhysicalKey},
     window::{Window, WindowId},
 };
@@ -32,7 +147,11 @@ use bevy_ecs::prelude::*;
 // COMPONENTS
 // ============================================================================
 
-#[derive(Component, Default, Clone, Copy)]
+/// Position, rotation and scale relative to the entity's `Parent`, or to the world for
+/// root entities; see `hierarchy`. Hidden with its `Visibility`, see `visibility`, and
+/// seen by the cameras sharing one of its `RenderLayers`, see `render_layers`.
+#[derive(Component, Default, Clone, Copy, PartialEq)]
+#[require(GlobalTransform, Visibility, RenderLayers)]
 pub struct Transform {
     pub position: glam::Vec3,
     pub rotation: glam::Quat,
@@ -47,9 +166,14 @@ impl Transform {
             scale: glam::Vec3::ONE,
         }
     }
+    
+    pub fn compute_matrix(&self) -> glam::Mat4 {
+        glam::Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.position)
+    }
 }
 
 #[derive(Component, Default, Clone, Copy)]
+#[require(TransformInterpolation)]
 pub struct Velocity {
     pub linear: glam::Vec3,
     pub angular: glam::Vec3,
@@ -58,6 +182,14 @@ pub struct Velocity {
 #[derive(Component)]
 pub struct SpinningCube;
 
+/// Bobs an entity up and down around `base_y`; used by the demo cube grid.
+#[derive(Component, Clone, Copy)]
+#[require(TransformInterpolation)]
+pub struct GridWave {
+    pub base_y: f32,
+    pub phase: f32,
+}
+
 #[derive(Component)]
 pub struct Renderable;
 
@@ -66,16 +198,36 @@ pub struct GltfModel {
     pub path: String,
 }
 
+/// One of the `GltfModel`'s meshes, a child of the `GltfModelNode` placing it or of the
+/// model entity, so the scene tree can hide it on its own
+#[derive(Component)]
+pub struct GltfMeshPart {
+    pub index: usize, // Into `GltfRenderer::meshes`
+    pub name: String,
+}
+
+/// A node of the `GltfModel`'s glTF scene graph above some of its meshes. The loader bakes
+/// node transforms into the meshes, so its own `Transform` stays at identity; hiding it
+/// hides the meshes below.
+#[derive(Component)]
+pub struct GltfModelNode {
+    pub index: usize, // Into `GltfScene::nodes`
+    pub name: String,
+}
+
+/// Mirrors the `CameraController`'s projection, see `sync_camera`
 #[derive(Component)]
 pub struct Camera {
     pub fov: f32,
     pub near: f32,
     pub far: f32,
+    pub orthographic: bool,
+    pub ortho_height: f32, // Meters shown from bottom to top while orthographic
 }
 
 impl Default for Camera {
     fn default() -> Self {
-        Self { fov: 45.0_f32.to_radians(), near: 0.1, far: 100.0 }
+        Self { fov: 45.0_f32.to_radians(), near: 0.1, far: 100.0, orthographic: false, ortho_height: 8.0 }
     }
 }
 
@@ -91,29 +243,91 @@ pub struct PerformanceStats {
     pub last_fps_update: Option<Instant>,
 }
 
+/// Frame times, in scene time unless named real (see `time_control`)
 #[derive(Resource)]
 pub struct FrameTiming {
-    pub start_time: Instant,
+    pub elapsed: f32, // Seconds of scene time since startup
     pub delta_time: f32,
+    pub real_delta_time: f32,
 }
 
 impl Default for FrameTiming {
     fn default() -> Self {
-        Self { start_time: Instant::now(), delta_time: 0.016 }
+        Self { elapsed: 0.0, delta_time: 0.016, real_delta_time: 0.016 }
     }
 }
 
+/// Seconds a switch between perspective and orthographic takes
+const PROJECTION_TRANSITION_TIME: f32 = 0.4;
+/// How quickly the field of view follows zooming, per second
+const FOV_EASE_RATE: f32 = 10.0;
+const MIN_FOV: f32 = 10.0_f32.to_radians();
+const MAX_FOV: f32 = 120.0_f32.to_radians();
+
 #[derive(Resource)]
 pub struct CameraController {
     pub position: glam::Vec3,
     pub yaw: f32,   // Rotation around Y axis
     pub pitch: f32, // Rotation around X axis
-    pub fov: f32,   // Field of view for zoom
+    pub fov: f32,   // Field of view, easing towards `target_fov`
+    pub target_fov: f32, // What zooming sets
+    pub orthographic: bool, // The projection `ortho_blend` is heading to
+    pub ortho_blend: f32,   // 0 = perspective, 1 = orthographic
+    pub ortho_height: f32,  // Meters shown from bottom to top while orthographic, what zooming scales then
     pub move_speed: f32,
     pub rotate_speed: f32,
     pub zoom_speed: f32,
 }
 
+impl CameraController {
+    pub fn projection(&self) -> Projection {
+        Projection { fov: self.fov, ortho_height: self.ortho_height, orthographic: self.ortho_blend }
+    }
+
+    /// Zoom in by `amount`, negative to zoom out: narrows the field of view, or shows less
+    /// of the scene when orthographic
+    pub fn zoom(&mut self, amount: f32) {
+        if self.orthographic {
+            self.ortho_height = (self.ortho_height * (-amount).exp()).clamp(0.1, 200.0);
+        } else {
+            self.target_fov = (self.target_fov - amount).clamp(MIN_FOV, MAX_FOV);
+        }
+    }
+
+    /// Start switching the projection. Whatever is `focus_distance` ahead keeps its size
+    /// on screen across the switch.
+    pub fn set_orthographic(&mut self, orthographic: bool, focus_distance: f32) {
+        if orthographic == self.orthographic {
+            return;
+        }
+        let focus_distance = focus_distance.max(0.1);
+        if orthographic {
+            self.ortho_height = 2.0 * focus_distance * (self.target_fov * 0.5).tan();
+        } else {
+            self.target_fov = (2.0 * (self.ortho_height * 0.5 / focus_distance).atan()).clamp(MIN_FOV, MAX_FOV);
+            self.fov = self.target_fov;
+        }
+        self.orthographic = orthographic;
+    }
+
+    /// Ease the field of view and projection towards their targets
+    pub fn animate(&mut self, delta: f32) {
+        self.fov += (self.target_fov - self.fov) * (1.0 - (-FOV_EASE_RATE * delta).exp());
+        let step = delta / PROJECTION_TRANSITION_TIME;
+        self.ortho_blend = if self.orthographic {
+            (self.ortho_blend + step).min(1.0)
+        } else {
+            (self.ortho_blend - step).max(0.0)
+        };
+    }
+
+    /// Whether the projection is still easing towards its targets
+    pub fn animating(&self) -> bool {
+        let blend_target = if self.orthographic { 1.0 } else { 0.0 };
+        (self.fov - self.target_fov).abs() > 1e-4 || self.ortho_blend != blend_target
+    }
+}
+
 impl Default for CameraController {
     fn default() -> Self {
         // Spawn camera already looking at the origin (where we place the duck)
@@ -129,6 +343,10 @@ impl Default for CameraController {
             yaw,
             pitch,
             fov: 45.0_f32.to_radians(),
+            target_fov: 45.0_f32.to_radians(),
+            orthographic: false,
+            ortho_blend: 0.0,
+            ortho_height: 8.0,
             move_speed: 5.0,
             rotate_speed: 3.0, // Fast enough for comfortable 360° rotation
             zoom_speed: 0.5,
@@ -140,6 +358,19 @@ impl Default for CameraController {
 pub struct SceneObjects {
     pub gltf_scale: f32,
     pub gltf_min_y: f32,
+    pub cube_spawn_count: u32,
+    pub stress_count: u32, // Objects in the stress-test scene, see `stress`
+    pub compute_skinning: bool,
+    pub mesh_shading: bool,
+    pub gpu_driven: bool,
+}
+
+impl SceneObjects {
+    /// Where the glTF model's origin goes before its entity `Transform` applies: just above
+    /// the ground plane, with the lowest point of the model resting on it
+    pub fn model_origin(&self) -> glam::Vec3 {
+        glam::Vec3::new(0.0, -self.gltf_min_y * self.gltf_scale + 0.001, 0.0)
+    }
 }
 
 impl Default for SceneObjects {
@@ -147,11 +378,162 @@ impl Default for SceneObjects {
         Self {
             gltf_scale: 0.01,
             gltf_min_y: 0.0,
+            cube_spawn_count: 16,
+            stress_count: 10_000,
+            compute_skinning: true,
+            mesh_shading: true,
+            gpu_driven: true,
         }
     }
 }
 
+/// Model matrices of all `Renderable` cube entities, gathered once per frame
+/// and uploaded to the instanced `CubeRenderer`.
+#[derive(Resource, Default)]
+pub struct CubeInstances {
+    pub transforms: Vec<glam::Mat4>,
+}
+
+/// Render-on-demand power saving: instead of redrawing back to back, draw only when input
+/// arrives, the scene animates or egui asks for a repaint, and otherwise at a low idle rate
+/// so temporal effects still settle.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct RedrawSettings {
+    pub on_demand: bool,
+    pub idle_rate_hz: f32, // Frames per second while nothing changes
+}
+
+impl RedrawSettings {
+    pub fn idle_interval(&self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.idle_rate_hz.max(0.1))
+    }
+}
+
+impl Default for RedrawSettings {
+    fn default() -> Self {
+        Self { on_demand: false, idle_rate_hz: 10.0 }
+    }
+}
+
+/// Size of stills from "Render still" (F12) and of panoramas, see `screenshot`
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct StillSettings {
+    pub scale: u32,   // Output resolution, times the window's
+    pub samples: u32, // Supersampling per axis
+    pub panorama_width: u32,
+}
+
+impl Default for StillSettings {
+    fn default() -> Self {
+        Self { scale: 2, samples: 2, panorama_width: 4096 }
+    }
+}
+
+/// Material editor window and the mesh whose material it edits
+#[derive(Resource, Default)]
+pub struct MaterialEditor {
+    pub open: bool,
+    pub selected_mesh: usize,
+}
+
+/// Texture streaming controls from the memory panel, see `texture_streaming`
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct TextureStreamingSettings {
+    pub enabled: bool,
+    pub budget_mb: u32,
+}
+
+impl Default for TextureStreamingSettings {
+    fn default() -> Self {
+        Self { enabled: true, budget_mb: texture_streaming::DEFAULT_BUDGET_MB }
+    }
+}
+
+/// Radius given to light probes placed from the debug UI, see `light_probes`
+#[derive(Resource, Clone, Copy)]
+pub struct LightProbeSettings {
+    pub radius: f32,
+}
+
+impl Default for LightProbeSettings {
+    fn default() -> Self {
+        Self { radius: 8.0 }
+    }
+}
+
+/// Box given to reflection probes placed from the debug UI, see `reflection_probes`
 #[derive(Resource, Clone, Copy)]
+pub struct ReflectionProbeSettings {
+    pub half_extents: glam::Vec3,
+}
+
+impl Default for ReflectionProbeSettings {
+    fn default() -> Self {
+        Self { half_extents: glam::Vec3::new(5.0, 3.0, 5.0) }
+    }
+}
+
+/// Mirror reflections on the ground plane, see `planar_reflection`
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct PlanarReflectionSettings {
+    pub enabled: bool,
+    pub resolution_scale: f32, // Of the mirrored view, relative to the window
+    pub strength: f32,         // How much the ground reflects head-on; grazing angles reflect more
+}
+
+impl Default for PlanarReflectionSettings {
+    fn default() -> Self {
+        Self { enabled: false, resolution_scale: 0.5, strength: 0.5 }
+    }
+}
+
+/// Final touches on the frame, see `post_effects`
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct PostEffectSettings {
+    pub dither: f32,   // In 8-bit steps
+    pub grain: f32,    // 0 to 1
+    pub vignette: f32, // 0 to 1
+}
+
+impl Default for PostEffectSettings {
+    fn default() -> Self {
+        Self { dither: 1.0, grain: 0.0, vignette: 0.0 }
+    }
+}
+
+/// Distant cubes and glTF instances drawn as impostors, see `impostor`
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ImpostorSettings {
+    pub enabled: bool,
+    pub distance: f32, // From the camera, in meters
+}
+
+impl Default for ImpostorSettings {
+    fn default() -> Self {
+        Self { enabled: true, distance: 30.0 }
+    }
+}
+
+/// Blended particles drawn into a half-resolution target and upsampled, see `half_res`
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct TransparencySettings {
+    pub half_resolution: bool,
+}
+
+impl Default for TransparencySettings {
+    fn default() -> Self {
+        Self { half_resolution: true }
+    }
+}
+
+/// Joint hierarchy overlay for skinned models, see `SkinningPass::draw_skeletons`
+#[derive(Resource, Clone, Copy, Default)]
+pub struct SkeletonDebugSettings {
+    pub visible: bool,
+    pub labels: bool, // Joint names next to each joint
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
 pub struct ShadowSettings {
     pub debug_cascades: bool,
     // Shadow softness / light size in texels (higher = softer / more expensive).
@@ -160,6 +542,46 @@ pub struct ShadowSettings {
     pub use_pcss: bool,
     // Shadow-only TAA (history reprojection + variance clamp) to stabilize soft penumbras.
     pub use_shadow_taa: bool,
+    // PCF/PCSS taps per lookup (specialization constant, rebuilds the pipeline).
+    pub samples: u32,
+    // 1 = sun only, 2 = sun + fill light (specialization constant).
+    pub light_count: u32,
+    // Hardware ray-traced shadows; ignored (shadow maps) without ray query support.
+    pub ray_traced: bool,
+    // Ray traced ambient occlusion; ignored without ray query support.
+    pub ray_traced_ao: bool,
+    // AO rays per pixel (specialization constant), accumulated over frames.
+    pub ao_rays: u32,
+    // Debug-draw each cascade's light volume and the view windows' camera frustums.
+    pub show_frustums: bool,
+}
+
+impl ShadowSettings {
+    /// The options that are uniforms rather than specialization constants
+    fn uniforms(&self) -> ShadowUniforms {
+        ShadowUniforms {
+            debug_cascades: self.debug_cascades,
+            softness: self.softness,
+            use_pcss: self.use_pcss,
+            use_taa: self.use_shadow_taa,
+        }
+    }
+
+    /// Pipeline variant for the settings that are baked in as specialization constants
+    pub fn shader_variant(&self) -> gltf_renderer::GltfShaderVariant {
+        gltf_renderer::GltfShaderVariant {
+            shadow_samples: self.samples,
+            use_pcss: self.use_pcss,
+            light_count: self.light_count,
+            debug_view: if self.debug_cascades {
+                gltf_renderer::GltfDebugView::Cascades
+            } else {
+                gltf_renderer::GltfDebugView::Lit
+            },
+            ray_query_shadows: self.ray_traced,
+            ao_rays: if self.ray_traced_ao { self.ao_rays } else { 0 },
+        }
+    }
 }
 
 impl Default for ShadowSettings {
@@ -169,7 +591,77 @@ impl Default for ShadowSettings {
             softness: 2.5,
             use_pcss: true, // Default to PCSS for Tiny Glade style shadows
             use_shadow_taa: true,
+            samples: 16,
+            light_count: 2,
+            ray_traced: true,
+            ray_traced_ao: false,
+            ao_rays: 4,
+            show_frustums: false,
+        }
+    }
+}
+
+// ============================================================================
+// SPAWN HELPERS
+// ============================================================================
+
+/// Uniform scale of cubes spawned through `SceneCommandsExt::spawn_cube`.
+pub const CUBE_SCALE: f32 = 0.5;
+
+/// Convenience spawners so systems and UI code build scene entities the same way.
+pub trait SceneCommandsExt {
+    /// Spawn a renderable cube at `position` that moves/spins with `velocity`.
+    fn spawn_cube(&mut self, position: glam::Vec3, velocity: Velocity) -> Entity;
+    /// Spawn an instance of the glTF `scene` at `transform`: a `GltfModel` root with a
+    /// `GltfMeshPart` for each mesh, below `GltfModelNode`s mirroring the nodes that place
+    /// them. The instance joins `Scene::instances` when the commands are applied.
+    fn spawn_model(&mut self, scene: &Scene, transform: Transform) -> Entity;
+    /// Despawn the instance with root `model` and everything below it
+    fn despawn_model(&mut self, model: Entity);
+}
+
+impl SceneCommandsExt for Commands<'_, '_> {
+    fn spawn_cube(&mut self, position: glam::Vec3, velocity: Velocity) -> Entity {
+        self.spawn((
+            Transform {
+                position,
+                rotation: glam::Quat::IDENTITY,
+                scale: glam::Vec3::splat(CUBE_SCALE),
+            },
+            velocity,
+            SpinningCube,
+            Renderable,
+        ))
+        .id()
+    }
+    
+    fn spawn_model(&mut self, scene: &Scene, transform: Transform) -> Entity {
+        let model = self.spawn((GltfModel { path: scene.path.clone() }, transform, Renderable)).id();
+        let mut entities: Vec<Entity> = Vec::with_capacity(scene.nodes.len());
+        for node in &scene.nodes {
+            let name = node.name.clone();
+            let mut entity = match node.part {
+                ScenePart::Node(index) => self.spawn((GltfModelNode { index, name }, Transform::new())),
+                ScenePart::Mesh(index) => self.spawn((GltfMeshPart { index, name }, Transform::new())),
+            };
+            entity.set_parent(node.parent.map_or(model, |parent| entities[parent]));
+            entities.push(entity.id());
         }
+        self.queue(move |world: &mut World| {
+            if let Some(mut scene) = world.get_resource_mut::<Scene>() {
+                scene.instances.push(model);
+            }
+        });
+        model
+    }
+
+    fn despawn_model(&mut self, model: Entity) {
+        self.queue(move |world: &mut World| {
+            if let Some(mut scene) = world.get_resource_mut::<Scene>() {
+                scene.instances.retain(|&instance| instance != model);
+            }
+            hierarchy::despawn_recursive(world, model);
+        });
     }
 }
 
@@ -179,13 +671,113 @@ impl Default for ShadowSettings {
 
 fn setup_scene(mut commands: Commands) {
     println!("🎬 Setting up scene with Bevy ECS...");
-    commands.spawn((Camera::default(), Transform::new()));
+    // The main view shows the debug geometry too
+    commands.spawn((Camera::default(), Transform::new(), RenderLayers::SCENE.with(RenderLayers::GIZMOS)));
 
     println!("✓ Scene setup complete - 1 camera");
 }
 
-fn rotation_system(timing: Res<FrameTiming>, mut query: Query<(&mut Transform, &Velocity)>) {
-    let dt = timing.delta_time;
+/// Demo: an animated 8x8 grid of cubes behind the model.
+fn spawn_cube_grid(mut commands: Commands) {
+    const GRID_SIZE: i32 = 8;
+    const SPACING: f32 = 1.0;
+    let origin = glam::Vec3::new(-(GRID_SIZE - 1) as f32 * SPACING * 0.5, CUBE_SCALE * 0.5, -8.0);
+    
+    for x in 0..GRID_SIZE {
+        for z in 0..GRID_SIZE {
+            let position = origin + glam::Vec3::new(x as f32 * SPACING, 0.0, z as f32 * -SPACING);
+            let entity = commands.spawn_cube(
+                position,
+                Velocity {
+                    linear: glam::Vec3::ZERO,
+                    angular: glam::Vec3::new(0.0, 1.0, 0.0),
+                },
+            );
+            commands.entity(entity).insert(GridWave {
+                base_y: position.y,
+                phase: (x + z) as f32 * 0.5,
+            });
+        }
+    }
+    
+    println!("✓ Spawned {}x{} demo cube grid", GRID_SIZE, GRID_SIZE);
+}
+
+/// Demo: keyframed props between the model and the grid, a turntable carrying a small
+/// cube and a platform sliding back and forth.
+fn spawn_animated_props(mut commands: Commands) {
+    let scale = glam::Vec3::splat(CUBE_SCALE);
+    let turntable = std::sync::Arc::new(TransformTrack::turntable(6.0));
+    let turntable = commands
+        .spawn((
+            Transform { position: glam::Vec3::new(3.0, CUBE_SCALE * 0.5, -4.0), rotation: glam::Quat::IDENTITY, scale },
+            AnimationPlayer::new(turntable).with_repeat(RepeatMode::Loop),
+            Renderable,
+        ))
+        .id();
+    // Relative to the turntable, so it rides along at the rim
+    commands
+        .spawn((
+            Transform {
+                position: glam::Vec3::new(1.5, 1.0, 0.0),
+                rotation: glam::Quat::IDENTITY,
+                scale: glam::Vec3::splat(0.5),
+            },
+            Renderable,
+        ))
+        .set_parent(turntable);
+    
+    let platform = TransformTrack::new()
+        .with_position(0.0, glam::Vec3::new(-3.0, 0.25, -4.0), Easing::EaseInOut)
+        .with_position(2.0, glam::Vec3::new(-3.0, 1.5, -4.0), Easing::EaseInOut)
+        .with_position(4.0, glam::Vec3::new(0.0, 1.5, -4.0), Easing::EaseInOut)
+        .with_scale(0.0, scale * glam::Vec3::new(2.0, 0.25, 2.0), Easing::Linear);
+    commands.spawn((
+        Transform::new(),
+        AnimationPlayer::new(std::sync::Arc::new(platform)).with_repeat(RepeatMode::PingPong),
+        Renderable,
+    ));
+    
+    println!("✓ Spawned 2 keyframe-animated props and a rider");
+}
+
+/// Density mask for the demo grass, from `--grass-mask`
+#[derive(Resource)]
+pub struct GrassMask(pub std::sync::Arc<vegetation::DensityMask>);
+
+/// Demo: a meadow of grass and ferns around the model, with a clearing where it stands
+fn spawn_vegetation(mut commands: Commands, grass_mask: Option<Res<GrassMask>>) {
+    const HALF_EXTENT: f32 = 12.0;
+    let clearing = std::sync::Arc::new(vegetation::DensityMask::from_fn(64, 64, |u, v| {
+        let distance = glam::Vec2::new(u - 0.5, v - 0.5).length() * 2.0 * HALF_EXTENT;
+        ((distance - 1.5) / 1.5).clamp(0.0, 1.0)
+    }));
+    let grass_mask = grass_mask.map_or_else(|| clearing.clone(), |mask| mask.0.clone());
+    let half_extent = glam::Vec2::splat(HALF_EXTENT);
+    commands.spawn(
+        VegetationLayer::new(FoliageKind::Grass, glam::Vec3::ZERO, half_extent)
+            .with_seed(1)
+            .with_mask(grass_mask),
+    );
+    commands.spawn(
+        VegetationLayer::new(FoliageKind::Fern, glam::Vec3::ZERO, half_extent)
+            .with_seed(2)
+            .with_density(0.6)
+            .with_mask(clearing),
+    );
+    
+    println!("✓ Spawned grass and fern vegetation layers");
+}
+
+fn grid_wave_system(fixed_time: Res<FixedTime>, mut query: Query<(&mut Transform, &GridWave)>) {
+    let t = fixed_time.elapsed;
+    for (mut transform, wave) in query.iter_mut() {
+        transform.position.y = wave.base_y + 0.5 + (t * 2.0 + wave.phase).sin() * 0.5;
+    }
+}
+
+fn rotation_system(fixed_time: Res<FixedTime>, mut query: Query<(&mut Transform, &Velocity)>) {
+    let dt = fixed_time.step;
     for (mut transform, velocity) in query.iter_mut() {
         if velocity.angular != glam::Vec3::ZERO {
             let rotation = glam::Quat::from_euler(
@@ -200,6 +792,189 @@ fn rotation_system(timing: Res<FrameTiming>, mut query: Query<(&mut Transform, &
     }
 }
 
+/// The cubes the main camera sees
+fn gather_cube_instances(
+    mut instances: ResMut<CubeInstances>,
+    cameras: Query<&RenderLayers, With<Camera>>,
+    query: Query<(&GlobalTransform, &InheritedVisibility, &RenderLayers, Has<GltfModel>), With<Renderable>>,
+) {
+    let camera_layers = cameras.iter().next().copied().unwrap_or_default();
+    instances.transforms.clear();
+    instances.transforms.extend(
+        query
+            .iter()
+            .filter(|&(_, shown, layers, model)| shown.0 && layers.intersects(camera_layers) && !model)
+            .map(|(global, ..)| global.0),
+    );
+}
+
+/// Spawn `count` spinning cubes scattered around the origin on a golden-angle spiral,
+/// continuing outward from the cubes that already exist.
+fn spawn_cubes(world: &mut World, count: u32) {
+    let existing = world
+        .query_filtered::<(), With<SpinningCube>>()
+        .iter(world)
+        .count() as u32;
+    
+    const GOLDEN_ANGLE: f32 = 2.399_963;
+    
+    let mut commands = world.commands();
+    for i in existing..existing + count {
+        let angle = i as f32 * GOLDEN_ANGLE;
+        let radius = 1.5 + 0.35 * (i as f32).sqrt();
+        let position = glam::Vec3::new(
+            angle.cos() * radius,
+            CUBE_SCALE * 0.5,
+            angle.sin() * radius,
+        );
+        
+        commands.spawn_cube(
+            position,
+            Velocity {
+                linear: glam::Vec3::ZERO,
+                angular: glam::Vec3::new(0.0, 0.5 + (i % 5) as f32 * 0.25, 0.0),
+            },
+        );
+    }
+    world.flush();
+    
+    println!("🧊 Spawned {} cubes ({} total)", count, existing + count);
+}
+
+/// Root entity and `Transform` of the primary instance of the glTF scene, see `scene`
+fn primary_model(world: &World) -> Option<(Entity, Transform)> {
+    let entity = world.get_resource::<Scene>()?.primary()?;
+    Some((entity, *world.get::<Transform>(entity)?))
+}
+
+/// Spawn another instance of the glTF scene in line beside the last, spaced by the width
+/// of the scene's model-space `bounds` at the current scale
+fn spawn_model_instance(world: &mut World, bounds: Option<(glam::Vec3, glam::Vec3)>) {
+    let Some(last) = world.get_resource::<Scene>().map(|scene| scene.instances.last().copied()) else {
+        return;
+    };
+    let scale = world.resource::<SceneObjects>().gltf_scale;
+    let width = bounds.map_or(1.0, |(min, max)| (max - min).max_element() * scale);
+    let mut transform = Transform::new();
+    if let Some(last) = last.and_then(|entity| world.get::<Transform>(entity)) {
+        transform.position = last.position + glam::Vec3::X * width * 1.25;
+    }
+    world.resource_scope(|world, scene: Mut<Scene>| {
+        world.commands().spawn_model(&scene, transform);
+    });
+    world.flush();
+
+    let count = world.resource::<Scene>().instances.len();
+    println!("✓ Spawned a glTF scene instance ({} total)", count);
+}
+
+/// Despawn every cube entity (UI spawned and demo grid alike).
+fn despawn_cubes(world: &mut World) {
+    let cubes: Vec<Entity> = world
+        .query_filtered::<Entity, With<SpinningCube>>()
+        .iter(world)
+        .collect();
+    
+    for &entity in &cubes {
+        hierarchy::despawn_recursive(world, entity);
+    }
+    
+    println!("🧹 Despawned {} cubes", cubes.len());
+}
+
+/// Rows of the scene tree panel: every renderable root entity with its descendants, except
+/// the cubes, which the panel shows and hides together
+fn scene_tree(world: &mut World) -> Vec<SceneTreeNode> {
+    let roots: Vec<Entity> = world
+        .query_filtered::<Entity, (With<Renderable>, Without<hierarchy::Parent>, Without<SpinningCube>)>()
+        .iter(world)
+        .collect();
+    roots.into_iter().map(|root| scene_tree_node(world, root)).collect()
+}
+
+fn scene_tree_node(world: &World, entity: Entity) -> SceneTreeNode {
+    let entity_ref = world.entity(entity);
+    let label = if let Some(model) = entity_ref.get::<GltfModel>() {
+        let path = std::path::Path::new(&model.path);
+        path.file_name().map_or_else(|| model.path.clone(), |name| name.to_string_lossy().into_owned())
+    } else if let Some(part) = entity_ref.get::<GltfMeshPart>() {
+        part.name.clone()
+    } else if let Some(node) = entity_ref.get::<GltfModelNode>() {
+        node.name.clone()
+    } else if entity_ref.contains::<SpinningCube>() {
+        "Cube".to_string()
+    } else if entity_ref.contains::<AnimationPlayer>() {
+        "Animated prop".to_string()
+    } else {
+        format!("Entity {}", entity.index())
+    };
+    let children = entity_ref
+        .get::<hierarchy::Children>()
+        .map_or_else(Vec::new, |children| children.0.iter().map(|&child| scene_tree_node(world, child)).collect());
+    SceneTreeNode {
+        entity,
+        label,
+        visible: entity_ref.get::<Visibility>().is_none_or(|visibility| visibility.is_visible()),
+        shown: entity_ref.get::<InheritedVisibility>().is_none_or(|shown| shown.0),
+        layers: entity_ref.get::<RenderLayers>().copied().unwrap_or_default(),
+        children,
+    }
+}
+
+/// Layers of the main camera's entity, see `render_layers`
+fn main_camera_layers(world: &mut World) -> RenderLayers {
+    world
+        .query_filtered::<&RenderLayers, With<Camera>>()
+        .iter(world)
+        .next()
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Show or hide every cube not carried by another entity
+fn set_cubes_visible(world: &mut World, visible: bool) {
+    for mut visibility in world
+        .query_filtered::<&mut Visibility, (With<SpinningCube>, Without<hierarchy::Parent>)>()
+        .iter_mut(world)
+    {
+        *visibility = Visibility::new(visible);
+    }
+}
+
+/// Replace all cubes with the stress-test grid (see `stress`) and light it with both lights.
+fn spawn_stress_grid(world: &mut World, count: u32) {
+    despawn_cubes(world);
+    
+    let mut commands = world.commands();
+    for object in stress::grid(count) {
+        let entity = commands.spawn_cube(
+            object.position,
+            Velocity {
+                linear: glam::Vec3::ZERO,
+                angular: object.spin,
+            },
+        );
+        commands.entity(entity).insert(Transform {
+            position: object.position,
+            rotation: object.rotation,
+            scale: glam::Vec3::splat(object.scale),
+        });
+    }
+    world.flush();
+    world.resource_mut::<ShadowSettings>().light_count = 2;
+    
+    println!("🧊 Spawned {} stress-test cubes", count);
+}
+
+/// Copy the controller's projection to the camera entity
+fn sync_camera(controller: Res<CameraController>, mut cameras: Query<&mut Camera>) {
+    for mut camera in cameras.iter_mut() {
+        camera.fov = controller.fov;
+        camera.orthographic = controller.orthographic;
+        camera.ortho_height = controller.ortho_height;
+    }
+}
+
 fn update_performance_stats(mut stats: ResMut<PerformanceStats>) {
     stats.frame_count += 1;
     let now = Instant::now();
@@ -218,14 +993,63 @@ fn update_performance_stats(mut stats: ResMut<PerformanceStats>) {
 // APP
 // ============================================================================
 
+/// An extra window showing the same scene from its own fixed camera.
+struct SecondaryWindow {
+    window: Window,
+    surface: WindowSurface,
+    view: GltfView,
+    camera_position: glam::Vec3,
+    camera_yaw: f32,
+    camera_pitch: f32,
+}
+
+impl SecondaryWindow {
+    fn camera(&self, fov: f32) -> ViewCamera {
+        let extent = self.surface.swapchain_extent;
+        ViewCamera::from_yaw_pitch(
+            self.camera_position,
+            self.camera_yaw,
+            self.camera_pitch,
+            fov,
+            extent.width as f32 / extent.height.max(1) as f32,
+        )
+    }
+}
+
+/// Debug-draw colors of the shadow cascades, as in the "Debug cascades" view
+const CASCADE_COLORS: [egui::Color32; 4] = [
+    egui::Color32::from_rgb(255, 51, 51),
+    egui::Color32::from_rgb(51, 255, 51),
+    egui::Color32::from_rgb(51, 102, 255),
+    egui::Color32::from_rgb(255, 255, 51),
+];
+
+/// Share of a view window camera's 100 unit view distance its frustum gizmo shows
+const CAMERA_GIZMO_DEPTH: f32 = 0.03;
+
 struct App {
     window: Option<Window>,
     renderer: Option<VulkanRenderer>,
     gltf_renderer: Option<GltfRenderer>,
+    cube_renderer: Option<CubeRenderer>,
+    particles: Option<ParticleSystem>,
+    async_compute: Option<AsyncCompute>, // None when the device has no compute-only queue
+    gpu_profiler: Option<GpuProfiler>, // Tracy GPU zones, with the `tracy` feature
+    post_effects: Option<post_effects::PostEffects>, // Grain and vignette
+    lens_flare: Option<lens_flare::LensFlare>, // Sun flares, occlusion queried in the scene pass
+    god_rays: Option<god_rays::GodRays>, // Light shafts from the scene depth
+    weather: Option<weather::WeatherParticles>, // Rain and snow, collided with the scene depth
+    half_res: Option<half_res::HalfResTransparency>, // Where the particles go when drawn at half resolution
+    ssgi: Option<ssgi::Ssgi>, // Bounce light for the next frame's ambient term, resized with the window
+    defragmenter: defrag::Defragmenter, // Compacts GPU memory on idle frames
+    loading: Option<loading::Loading>, // Until startup finishes, which the loading screen shows
+    vegetation: Option<vegetation::Vegetation>, // Draws the `VegetationLayer`s
+    impostors: Option<impostor::Impostors>, // Far cubes, baked from the cube renderer's mesh
     
     // Bevy ECS
     world: World,
     schedule: Schedule,
+    fixed_schedule: Schedule, // Run `FixedTime::step` at a time, see `fixed_timestep`
     startup_schedule: Schedule,
     startup_done: bool,
     
@@ -233,9 +1057,52 @@ struct App {
     egui_integration: Option<EguiIntegration>,
     egui_vulkan: Option<EguiVulkanRenderer>,
     
+    // Additional windows (opened with F2 or from the debug UI)
+    secondary_windows: std::collections::HashMap<WindowId, SecondaryWindow>,
+    view_layers: RenderLayers, // What the view windows show
+    open_window_requested: bool,
+    still_requested: bool,    // Rendered after the next frame
+    panorama_requested: bool, // Likewise
+    probe_bake_requested: bool, // Likewise
+    reflection_bake_requested: bool, // Likewise
+    export_requested: Option<gltf_export::ExportFormat>, // Likewise
+    present_mode_requested: Option<swapchain::PresentModeConfig>, // Likewise
+    diagnostics_requested: bool, // Likewise
+    
+    // Lightmap baking (`--bake-lightmap` or the debug UI), see `lightmap`
+    lightmap_charts: bool, // Chart meshes without lightmap UVs when loading the scene
+    renderer_builder: RendererBuilder, // Buffering, present mode and validation the renderer is created with, from the command line
+    lightmap_path: Option<std::path::PathBuf>, // Where the loaded scene's lightmap is saved
+    lightmap_bake_requested: bool, // Started after the next frame
+    lightmap_baker: Option<lightmap::LightmapBaker>,
+    
+    // Monitor and mode F11 switches to, persisted across runs
+    display: DisplaySettings,
+    
+    // Stress test (`--stress N` or the debug UI), see `stress`
+    stress_requested: Option<u32>, // Object count; started on the next frame
+    stress_run: Option<stress::StressRun>,
+    stress_report: Option<stress::StressReport>, // Of the last finished run
+    draw_history: draw_stats::DrawStatsHistory,
+    
+    // Per-frame AOV export (`--export-aovs`), see `aov`
+    aov_dir: Option<std::path::PathBuf>,
+    environment: Option<std::path::PathBuf>, // HDR image behind the scene, see `skybox`
+    aov_pass: Option<aov::AovPass>, // Created on the first export, rebuilt on resize
+    aov_frame: u64,
+    
+    scene_path: Option<std::path::PathBuf>, // The loaded glTF file, which edited materials are saved to
+    material_preview: Option<material_preview::MaterialPreview>, // Created when the material editor opens
+    planar_reflection: Option<planar_reflection::PlanarReflection>, // Exists while enabled, rebuilt on resize
+    shader_watcher: shader_watcher::ShaderWatcher, // Edited shaders, reloaded every frame
+    
     last_frame_time: Instant,
     minimized: bool,
     
+    // Render-on-demand state (see `RedrawSettings`)
+    redraw_pending: bool,              // An event arrived since the last frame
+    egui_repaint_at: Option<Instant>, // When egui wants its next frame, if ever
+    
     // Input state
     keys_pressed: std::collections::HashSet<KeyCode>,
 }
@@ -245,36 +1112,121 @@ impl App {
         let mut world = World::new();
         world.insert_resource(PerformanceStats::default());
         world.insert_resource(FrameTiming::default());
+        world.insert_resource(time_control::TimeControl::default());
+        world.insert_resource(FixedTime::default());
         world.insert_resource(CameraController::default());
         world.insert_resource(SceneObjects::default());
         world.insert_resource(ShadowSettings::default());
+        world.insert_resource(CubeInstances::default());
+        world.insert_resource(RedrawSettings::default());
+        world.insert_resource(StillSettings::default());
+        world.insert_resource(LightProbeSettings::default());
+        world.insert_resource(ReflectionProbeSettings::default());
+        world.insert_resource(PlanarReflectionSettings::default());
+        world.insert_resource(PostEffectSettings::default());
+        world.insert_resource(ImpostorSettings::default());
+        world.insert_resource(TransparencySettings::default());
+        world.insert_resource(weather::Weather::default());
+        world.insert_resource(SkeletonDebugSettings::default());
+        world.insert_resource(MaterialEditor::default());
+        world.insert_resource(TextureStreamingSettings::default());
+        world.insert_resource(texture_streaming::TextureFilter::default());
+        world.insert_resource(DebugDraw::default());
+        world.insert_resource(Trackball::default());
+        world.insert_resource(letterbox::AspectLock::default());
         
         let mut startup_schedule = Schedule::default();
-        startup_schedule.add_systems(setup_scene);
+        startup_schedule.add_systems((setup_scene, spawn_cube_grid, spawn_animated_props, spawn_vegetation));
+        
+        let mut fixed_schedule = Schedule::default();
+        fixed_schedule.add_systems(
+            (
+                fixed_timestep::begin_step,
+                (animation::animation_system, rotation_system, grid_wave_system),
+                fixed_timestep::end_step,
+            )
+                .chain(),
+        );
         
         let mut schedule = Schedule::default();
-        schedule.add_systems((rotation_system, update_performance_stats));
+        schedule.add_systems((
+            (
+                fixed_timestep::interpolate_transforms,
+                hierarchy::propagate_transforms,
+                visibility::propagate_visibility,
+                gather_cube_instances,
+            )
+                .chain(),
+            update_performance_stats,
+            sync_camera,
+        ));
         
         Self {
             window: None,
             renderer: None,
             gltf_renderer: None,
+            cube_renderer: None,
+            particles: None,
+            async_compute: None,
+            gpu_profiler: None,
+            post_effects: None,
+            lens_flare: None,
+            god_rays: None,
+            weather: None,
+            half_res: None,
+            ssgi: None,
+            defragmenter: defrag::Defragmenter::default(),
+            loading: None,
+            vegetation: None,
+            impostors: None,
             world,
             schedule,
+            fixed_schedule,
             startup_schedule,
             startup_done: false,
             egui_integration: None,
             egui_vulkan: None,
+            secondary_windows: std::collections::HashMap::new(),
+            view_layers: RenderLayers::default(),
+            open_window_requested: false,
+            still_requested: false,
+            panorama_requested: false,
+            export_requested: None,
+            present_mode_requested: None,
+            diagnostics_requested: false,
+            probe_bake_requested: false,
+            reflection_bake_requested: false,
+            lightmap_charts: false,
+            renderer_builder: RendererBuilder::default(),
+            lightmap_path: None,
+            lightmap_bake_requested: false,
+            lightmap_baker: None,
+            display: DisplaySettings::load(),
+            stress_requested: None,
+            stress_run: None,
+            stress_report: None,
+            draw_history: draw_stats::DrawStatsHistory::default(),
+            aov_dir: None,
+            environment: None,
+            aov_pass: None,
+            aov_frame: 0,
+            scene_path: None,
+            material_preview: None,
+            planar_reflection: None,
+            shader_watcher: shader_watcher::ShaderWatcher::new(),
             last_frame_time: Instant::now(),
             minimized: false,
+            redraw_pending: true,
+            egui_repaint_at: None,
             keys_pressed: std::collections::HashSet::new(),
         }
     }
     
     fn update_camera(&mut self) {
+        // The camera moves in real time, paused or not
         let delta = {
             let timing = self.world.resource::<FrameTiming>();
-            timing.delta_time
+            timing.real_delta_time
         };
         
         let mut camera = self.world.resource_mut::<CameraController>();
@@ -339,108 +1291,782 @@ impl App {
         // Keep yaw in [0, 2π) to avoid float precision issues over time
         camera.yaw = camera.yaw.rem_euclid(std::f32::consts::TAU);
         
-        // Z/X keys for zoom (adjust FOV)
+        // Z/X keys for zoom (adjust FOV, or the orthographic height)
+        let zoom = camera.zoom_speed * delta;
         if self.keys_pressed.contains(&KeyCode::KeyZ) {
-            camera.fov = (camera.fov - camera.zoom_speed * delta).clamp(10.0_f32.to_radians(), 120.0_f32.to_radians());
+            camera.zoom(zoom);
         }
         if self.keys_pressed.contains(&KeyCode::KeyX) {
-            camera.fov = (camera.fov + camera.zoom_speed * delta).clamp(10.0_f32.to_radians(), 120.0_f32.to_radians());
+            camera.zoom(-zoom);
         }
+        camera.animate(delta);
     }
     
-    fn update_window_title(&self) {
-        if let Some(window) = &self.window {
-            let stats = self.world.resource::<PerformanceStats>();
-            let title = format!(
-                "Funky Renderer | FPS: {:.1} | Frame: {:.2}ms | Bevy ECS + Vulkan + egui",
-                stats.fps, stats.frame_time_ms
-            );
-            window.set_title(&title);
-        }
-    }
-}
+    /// Move the trackball's cursor and apply what a drag in progress does to the primary
+    /// instance of the glTF scene
+    fn trackball_moved(&mut self, cursor: glam::Vec2) {
+        let (Some(window), Some(gltf_renderer)) = (&self.window, &self.gltf_renderer) else {
+            return;
+        };
+        let size = window.inner_size();
+        let viewport = glam::Vec2::new(size.width as f32, size.height as f32);
+        let camera = {
+            let camera = self.world.resource::<CameraController>();
+            let extent = vk::Extent2D { width: size.width, height: size.height };
+            let projection = self.world.resource::<letterbox::AspectLock>().fit(camera.projection(), extent);
+            let aspect_ratio = viewport.x / viewport.y.max(1.0);
+            ViewCamera::new(camera.position, camera.yaw, camera.pitch, projection, aspect_ratio)
+        };
+        let (origin, scale) = {
+            let objects = self.world.resource::<SceneObjects>();
+            (objects.model_origin(), objects.gltf_scale)
+        };
+        let Some((entity, transform)) = primary_model(&self.world) else {
+            return;
+        };
 
-impl ApplicationHandler for App {
-    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        if self.window.is_some() {
+        let position = origin + transform.position;
+        let (min, max) = gltf_renderer.model_bounds;
+        let model = GltfRenderer::model_matrix(position, transform.rotation, scale);
+        let center = model.transform_point3((min + max) * 0.5);
+        let manipulation = self
+            .world
+            .resource_mut::<Trackball>()
+            .cursor_moved(cursor, viewport, &camera, center, 0.0);
+        let Some(mut transform) = self.world.get_mut::<Transform>(entity) else {
             return;
+        };
+        match manipulation {
+            Some(Manipulation::Rotate(rotation)) => {
+                // Around the bounds center rather than the model's origin
+                transform.rotation = (rotation * transform.rotation).normalize();
+                transform.position += center + rotation * (position - center) - position;
+            }
+            Some(Manipulation::Move(offset)) => transform.position += offset,
+            None => {}
         }
+    }
+
+    fn open_secondary_window(&mut self, event_loop: &ActiveEventLoop) {
+        let (renderer, gltf_renderer) = match (&self.renderer, &self.gltf_renderer) {
+            (Some(r), Some(g)) => (r, g),
+            _ => {
+                println!("ℹ Secondary windows need a loaded glTF scene");
+                return;
+            }
+        };
         
-        println!("🚀 Funky Vulkan Renderer - Bevy ECS + egui Edition");
-        println!("════════════════════════════════════════════");
+        // Each new window orbits a quarter turn further around the model.
+        let index = self.secondary_windows.len() + 1;
+        let angle = index as f32 * std::f32::consts::FRAC_PI_2;
+        let target = glam::Vec3::new(0.0, 0.6, 0.0);
+        let camera_position = glam::Vec3::new(angle.sin() * 10.0, 3.0, angle.cos() * 10.0);
+        let dir = (target - camera_position).normalize_or_zero();
         
         let window_attributes = Window::default_attributes()
-            .with_title("Funky Renderer | Initializing...")
-            .with_inner_size(winit::dpi::LogicalSize::new(1280, 720))
+            .with_title(format!("Funky Renderer | View {}", index))
+            .with_inner_size(winit::dpi::LogicalSize::new(640, 360))
             .with_resizable(true);
         
-        let window = event_loop.create_window(window_attributes).unwrap();
+        let window = match event_loop.create_window(window_attributes) {
+            Ok(w) => w,
+            Err(e) => {
+                eprintln!("✗ Failed to create window: {}", e);
+                return;
+            }
+        };
         
         unsafe {
-            match VulkanRenderer::new(&window) {
-                Ok(renderer) => {
-                    println!("✓ Vulkan renderer initialized");
-                    println!("  Resolution: {}x{}", 
-                        renderer.swapchain_extent.width, 
-                        renderer.swapchain_extent.height);
-                    
-                    // Load glTF scene (if available)
-                    let gltf_paths = [
-                        "models/scene.gltf",
-                        "models/model.gltf",
-                        "scene.gltf",
-                        "model.gltf",
-                    ];
-                    
-                    for path in &gltf_paths {
-                        if std::path::Path::new(path).exists() {
-                            println!("📦 Loading glTF scene from: {}", path);
-                            match GltfScene::load(path) {
-                                Ok(scene) => {
-                                    // Store model bounds so we can place it on the ground plane.
-                                    {
-                                        let mut objects = self.world.resource_mut::<SceneObjects>();
-                                        objects.gltf_min_y = scene.bounds_min[1];
-                                    }
-                                    match GltfRenderer::new(&renderer, &scene) {
-                                        Ok(gltf_renderer) => {
-                                            println!("  ✓ glTF renderer created with textures");
-                                            self.gltf_renderer = Some(gltf_renderer);
-                                            break;
-                                        }
-                                        Err(e) => {
-                                            eprintln!("  ✗ Failed to create glTF renderer: {}", e);
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("  ✗ Failed to load glTF: {}", e);
-                                }
-                            }
-                            break;
-                        }
-                    }
-                    
-                    if self.gltf_renderer.is_none() {
-                        println!("ℹ No glTF scene loaded. Place a model.gltf in the project root or models/ folder.");
-                    }
-                    
-                    // Initialize egui
-                    let egui_integration = EguiIntegration::new(&window);
+            let surface = match WindowSurface::new(renderer, &window, gltf_renderer.render_pass) {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("✗ Failed to create window surface: {}", e);
+                    return;
+                }
+            };
+            let view = match gltf_renderer.create_view(renderer, surface.swapchain_extent) {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("✗ Failed to create scene view: {}", e);
+                    let mut surface = surface;
+                    surface.destroy(renderer);
+                    return;
+                }
+            };
+            
+            println!("🪟 Opened view window {} ({}x{})",
+                index, surface.swapchain_extent.width, surface.swapchain_extent.height);
+            
+            window.request_redraw();
+            self.secondary_windows.insert(window.id(), SecondaryWindow {
+                window,
+                surface,
+                view,
+                camera_position,
+                camera_yaw: dir.z.atan2(dir.x),
+                camera_pitch: dir.y.asin(),
+            });
+        }
+    }
+    
+    fn close_secondary_window(&mut self, id: WindowId) {
+        if let Some(mut secondary) = self.secondary_windows.remove(&id) {
+            if let (Some(renderer), Some(gltf_renderer)) = (&self.renderer, &self.gltf_renderer) {
+                unsafe {
+                    let _ = renderer.device.device_wait_idle();
+                    if let Err(e) = gltf_renderer.destroy_view(renderer, secondary.view) {
+                        eprintln!("Failed to destroy scene view: {}", e);
+                    }
+                    secondary.surface.destroy(renderer);
+                }
+            }
+        }
+    }
+    
+    fn handle_secondary_window_event(&mut self, id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => self.close_secondary_window(id),
+            WindowEvent::Resized(_) => {
+                if let Some(secondary) = self.secondary_windows.get_mut(&id) {
+                    secondary.surface.framebuffer_resized = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    /// Render every secondary window with its own camera. Runs after the main window's
+    /// submit, so the shared shadow map is re-rendered per view in queue order.
+    fn render_secondary_windows(&mut self) {
+        let (renderer, gltf_renderer) = match (&self.renderer, &self.gltf_renderer) {
+            (Some(r), Some(g)) => (r, g),
+            _ => return,
+        };
+        let camera_fov = self.world.resource::<CameraController>().fov;
+        let shadow_settings = *self.world.resource::<ShadowSettings>();
+        
+        for secondary in self.secondary_windows.values_mut() {
+            let size = secondary.window.inner_size();
+            if size.width == 0 || size.height == 0 {
+                continue;
+            }
+            let camera = secondary.camera(camera_fov);
+            secondary.view.layers = self.view_layers;
+            
+            unsafe {
+                let surface = &mut secondary.surface;
+                
+                if surface.framebuffer_resized {
+                    if let Err(e) = surface.recreate_swapchain(renderer, size.width, size.height)
+                        .and_then(|_| gltf_renderer.resize_view(renderer, &mut secondary.view, surface.swapchain_extent))
+                    {
+                        eprintln!("View window swapchain recreate failed: {}", e);
+                    }
+                    continue;
+                }
+                
+                let frame = surface.current_frame;
+                let timeout = 1_000_000_000;
+                if renderer.device.wait_for_fences(&[surface.in_flight_fences[frame]], true, timeout).is_err() {
+                    continue;
+                }
+                
+                let image_index = match renderer.swapchain_fn.acquire_next_image(
+                    surface.swapchain,
+                    u64::MAX,
+                    surface.image_available_semaphores[frame],
+                    vk::Fence::null(),
+                ) {
+                    Ok((index, suboptimal)) => {
+                        surface.framebuffer_resized |= suboptimal;
+                        index
+                    }
+                    Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                        surface.framebuffer_resized = true;
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to acquire view window image: {:?}", e);
+                        continue;
+                    }
+                };
+                
+                let image_fence = surface.images_in_flight[image_index as usize];
+                if image_fence != vk::Fence::null() {
+                    let _ = renderer.device.wait_for_fences(&[image_fence], true, timeout);
+                }
+                surface.images_in_flight[image_index as usize] = surface.in_flight_fences[frame];
+                renderer.device.reset_fences(&[surface.in_flight_fences[frame]]).unwrap();
+                
+                gltf_renderer.update_view_uniform_buffer(
+                    &mut secondary.view,
+                    frame,
+                    &camera,
+                    shadow_settings.debug_cascades,
+                    shadow_settings.softness,
+                    shadow_settings.use_pcss,
+                );
+                
+                let command_buffer = surface.command_buffers[frame];
+                let begin_info = vk::CommandBufferBeginInfo::default();
+                renderer.device.begin_command_buffer(command_buffer, &begin_info).unwrap();
+                gltf_renderer.render_view(
+                    &renderer.device,
+                    command_buffer,
+                    &secondary.view,
+                    surface.framebuffers[image_index as usize],
+                    frame,
+                );
+                renderer.device.end_command_buffer(command_buffer).unwrap();
+                
+                let wait_semaphores = [surface.image_available_semaphores[frame]];
+                let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+                let command_buffers = [command_buffer];
+                let signal_semaphores = [surface.render_finished_semaphores[frame]];
+                let submit_info = vk::SubmitInfo::default()
+                    .wait_semaphores(&wait_semaphores)
+                    .wait_dst_stage_mask(&wait_stages)
+                    .command_buffers(&command_buffers)
+                    .signal_semaphores(&signal_semaphores);
+                renderer.device.queue_submit(
+                    renderer.graphics_queue,
+                    &[submit_info],
+                    surface.in_flight_fences[frame],
+                ).unwrap();
+                
+                let swapchains = [surface.swapchain];
+                let image_indices = [image_index];
+                let present_info = vk::PresentInfoKHR::default()
+                    .wait_semaphores(&signal_semaphores)
+                    .swapchains(&swapchains)
+                    .image_indices(&image_indices);
+                match renderer.swapchain_fn.queue_present(renderer.present_queue, &present_info) {
+                    Ok(suboptimal) => surface.framebuffer_resized |= suboptimal,
+                    Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => surface.framebuffer_resized = true,
+                    Err(e) => eprintln!("View window present error: {:?}", e),
+                }
+                
+                surface.current_frame = (frame + 1) % renderer::MAX_FRAMES_IN_FLIGHT;
+            }
+        }
+    }
+    
+    /// Switch VSync on or off, recreating the swapchain and what's built on it
+    fn set_present_mode(&mut self, config: swapchain::PresentModeConfig) {
+        let Some(renderer) = &mut self.renderer else {
+            return;
+        };
+        unsafe {
+            match renderer.set_present_mode(config) {
+                Ok(true) => {
+                    if let Some(gltf) = &mut self.gltf_renderer {
+                        if let Err(e) = gltf.recreate_swapchain_resources(renderer) {
+                            eprintln!("glTF swapchain resource recreate failed: {}", e);
+                        }
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => eprintln!("Swapchain recreate failed: {:?}", e),
+            }
+        }
+    }
+    
+    /// Re-render the main camera's view as a supersampled still (see `screenshot`)
+    fn render_still(&mut self) {
+        let (renderer, gltf_renderer) = match (&self.renderer, &self.gltf_renderer) {
+            (Some(r), Some(g)) => (r, g),
+            _ => {
+                println!("⚠ Stills need a loaded glTF scene");
+                return;
+            }
+        };
+        let camera = self.world.resource::<CameraController>();
+        let still = *self.world.resource::<StillSettings>();
+        let shadow_settings = *self.world.resource::<ShadowSettings>();
+        let request = screenshot::StillRequest {
+            camera_position: camera.position,
+            camera_yaw: camera.yaw,
+            camera_pitch: camera.pitch,
+            projection: camera.projection(),
+            extent: self.world.resource::<letterbox::AspectLock>().viewport(renderer.swapchain_extent).extent,
+            scale: still.scale,
+            samples: still.samples,
+            debug_cascades: shadow_settings.debug_cascades,
+            shadow_softness: shadow_settings.softness,
+            use_pcss: shadow_settings.use_pcss,
+        };
+        if let Err(e) = unsafe { screenshot::render_still(renderer, gltf_renderer, &request) } {
+            eprintln!("✗ Failed to render still: {}", e);
+        }
+    }
+    
+    /// Write the loaded scene as currently arranged to `exports/` (see `gltf_export`). The
+    /// file is read again rather than kept in memory after its upload, without KTX2 images,
+    /// so textures that have one are exported from their fallback.
+    fn export_scene(&self, format: gltf_export::ExportFormat) {
+        let (Some(renderer), Some(gltf_renderer), Some(scene_path)) = (&self.renderer, &self.gltf_renderer, &self.scene_path) else {
+            println!("⚠ Exporting needs a loaded glTF scene");
+            return;
+        };
+        let result = GltfScene::load(scene_path, false).and_then(|scene| {
+            // Meshes line up with the renderer's, whose edited materials replace the loaded ones
+            let mesh_materials = (0..scene.meshes.len().min(gltf_renderer.meshes.len()))
+                .map(|i| {
+                    let (edit, overridden) = gltf_renderer.mesh_material(i);
+                    let loaded = scene.meshes[i].material_index.and_then(|index| scene.materials.get(index))?;
+                    overridden.then(|| edit.apply_to_gltf(loaded))
+                })
+                .collect();
+            let camera = self.world.resource::<CameraController>();
+            let extent = self.world.resource::<letterbox::AspectLock>().viewport(renderer.swapchain_extent).extent;
+            let aspect_ratio = extent.width as f32 / extent.height.max(1) as f32;
+            let view_camera = ViewCamera::new(camera.position, camera.yaw, camera.pitch, camera.projection(), aspect_ratio);
+            let arrangement = gltf_export::SceneArrangement {
+                model: gltf_renderer.duck_model,
+                mesh_materials,
+                sun_direction: Some(gltf_renderer::SUN_DIRECTION),
+                camera: Some((view_camera.view, view_camera.proj)),
+            };
+            let path = gltf_export::output_path(format)?;
+            scene.save(&path, &arrangement)?;
+            Ok(path)
+        });
+        match result {
+            Ok(path) => println!("✓ Scene exported to {}", path.display()),
+            Err(e) => eprintln!("✗ Failed to export scene: {}", e),
+        }
+    }
+
+    /// Write a snapshot of the device, scene, settings and recent console output to
+    /// `diagnostics/`, for attaching to bug reports (see `diagnostics`)
+    fn save_diagnostics(&self) {
+        let Some(renderer) = &self.renderer else {
+            return;
+        };
+        let debug = |value: &dyn std::fmt::Debug| gltf::json::Value::from(format!("{:?}", value));
+        let assets: gltf::json::Value = [
+            ("scene", gltf::json::Value::from(self.scene_path.as_ref().map(|path| path.display().to_string()))),
+            ("lightmap", self.lightmap_path.as_ref().map(|path| path.display().to_string()).into()),
+            ("meshes", self.gltf_renderer.as_ref().map(|g| g.meshes.len()).into()),
+            ("ground", self.gltf_renderer.as_ref().map(|g| format!("{:?}", g.ground_style)).into()),
+            ("virtual_texture", self.gltf_renderer.as_ref().and_then(|g| g.virtual_texture.summary()).into()),
+            ("texture_arrays", self.gltf_renderer.as_ref().and_then(|g| g.texture_arrays.summary()).into()),
+            ("cubes", self.world.resource::<CubeInstances>().transforms.len().into()),
+        ]
+        .into_iter()
+        .collect();
+        let camera = self.world.resource::<CameraController>();
+        let settings: gltf::json::Value = [
+            ("camera_position", debug(&camera.position)),
+            ("projection", debug(&camera.projection())),
+            ("aspect_lock", debug(self.world.resource::<letterbox::AspectLock>())),
+            ("time", debug(self.world.resource::<time_control::TimeControl>())),
+            ("display", debug(&self.display)),
+            ("redraw", debug(self.world.resource::<RedrawSettings>())),
+            ("shadows", debug(self.world.resource::<ShadowSettings>())),
+            ("post_effects", debug(self.world.resource::<PostEffectSettings>())),
+            ("planar_reflection", debug(self.world.resource::<PlanarReflectionSettings>())),
+            ("transparency", debug(self.world.resource::<TransparencySettings>())),
+            ("impostors", debug(self.world.resource::<ImpostorSettings>())),
+            ("texture_streaming", debug(self.world.resource::<TextureStreamingSettings>())),
+            ("texture_filter", debug(self.world.resource::<texture_streaming::TextureFilter>())),
+            ("stills", debug(self.world.resource::<StillSettings>())),
+            ("weather", debug(self.world.resource::<weather::Weather>())),
+            ("toon", self.gltf_renderer.as_ref().map_or(gltf::json::Value::Null, |g| debug(&g.toon))),
+        ]
+        .into_iter()
+        .collect();
+        let sections = vec![
+            ("device", unsafe { diagnostics::device(renderer) }),
+            ("swapchain", diagnostics::swapchain(renderer)),
+            ("memory", diagnostics::memory(renderer)),
+            ("assets", assets),
+            ("settings", settings),
+            ("log", diagnostics::log()),
+        ];
+        match diagnostics::save(sections) {
+            Ok(path) => println!("✓ Diagnostics saved to {}", path.display()),
+            Err(e) => eprintln!("✗ Failed to save diagnostics: {}", e),
+        }
+    }
+
+    /// Capture a 360° panorama around the main camera (see `screenshot`)
+    fn render_panorama(&mut self) {
+        let (renderer, gltf_renderer) = match (&self.renderer, &mut self.gltf_renderer) {
+            (Some(r), Some(g)) => (r, g),
+            _ => {
+                println!("⚠ Panoramas need a loaded glTF scene");
+                return;
+            }
+        };
+        let camera = self.world.resource::<CameraController>();
+        let shadow_settings = *self.world.resource::<ShadowSettings>();
+        let request = screenshot::PanoramaRequest {
+            camera_position: camera.position,
+            camera_yaw: camera.yaw,
+            width: self.world.resource::<StillSettings>().panorama_width,
+            debug_cascades: shadow_settings.debug_cascades,
+            shadow_softness: shadow_settings.softness,
+            use_pcss: shadow_settings.use_pcss,
+        };
+        if let Err(e) = unsafe { screenshot::render_panorama(renderer, gltf_renderer, &request) } {
+            eprintln!("✗ Failed to capture panorama: {}", e);
+        }
+    }
+    
+    /// Bake every light probe from the current scene (see `light_probes`)
+    fn bake_light_probes(&mut self) {
+        let (renderer, gltf_renderer) = match (&self.renderer, &mut self.gltf_renderer) {
+            (Some(r), Some(g)) => (r, g),
+            _ => return,
+        };
+        let shadow_settings = *self.world.resource::<ShadowSettings>();
+        // Taken out while baking so the probes are rendered with the constant ambient term
+        let mut probes = std::mem::take(&mut gltf_renderer.light_probes.probes);
+        if let Err(e) = unsafe {
+            light_probes::bake(renderer, gltf_renderer, &mut probes, shadow_settings.softness, shadow_settings.use_pcss)
+        } {
+            eprintln!("✗ Failed to bake light probes: {}", e);
+        }
+        gltf_renderer.light_probes.probes = probes;
+    }
+
+    /// Bake every reflection probe from the current scene (see `reflection_probes`)
+    fn bake_reflection_probes(&mut self) {
+        let (renderer, gltf_renderer) = match (&self.renderer, &mut self.gltf_renderer) {
+            (Some(r), Some(g)) => (r, g),
+            _ => return,
+        };
+        let shadow_settings = *self.world.resource::<ShadowSettings>();
+        if let Err(e) = unsafe {
+            reflection_probes::bake(renderer, gltf_renderer, shadow_settings.softness, shadow_settings.use_pcss)
+        } {
+            eprintln!("✗ Failed to bake reflection probes: {}", e);
+        }
+    }
+    
+    /// Start baking the lightmap of the current scene (see `lightmap`)
+    fn start_lightmap_bake(&mut self) {
+        let (renderer, gltf_renderer) = match (&self.renderer, &self.gltf_renderer) {
+            (Some(r), Some(g)) => (r, g),
+            _ => {
+                println!("⚠ Lightmaps need a loaded glTF scene");
+                return;
+            }
+        };
+        if self.lightmap_baker.is_some() {
+            return;
+        }
+        match unsafe { lightmap::LightmapBaker::new(renderer, gltf_renderer) } {
+            Ok(baker) => self.lightmap_baker = Some(baker),
+            Err(e) => eprintln!("✗ Failed to start lightmap bake: {}", e),
+        }
+    }
+    
+    /// Render the next lightmap samples; once all are done, save the lightmap and use it
+    fn step_lightmap_bake(&mut self) {
+        let (Some(renderer), Some(gltf_renderer), Some(baker)) =
+            (&self.renderer, &mut self.gltf_renderer, &mut self.lightmap_baker)
+        else {
+            return;
+        };
+        let result = unsafe {
+            baker.step(renderer, gltf_renderer).and_then(|()| {
+                if !baker.done() {
+                    return Ok(false);
+                }
+                let texels = baker.finish(renderer)?;
+                if let Some(path) = &self.lightmap_path {
+                    if let Err(e) = lightmap::save(path, &texels) {
+                        eprintln!("⚠ Failed to save lightmap: {}", e);
+                    }
+                }
+                renderer.device.device_wait_idle()?;
+                gltf_renderer.lightmap.upload(renderer, &texels)?;
+                gltf_renderer.lightmap.enabled = true;
+                println!("✓ Lightmap baked");
+                Ok(true)
+            })
+        };
+        let finished = result.unwrap_or_else(|e| {
+            eprintln!("✗ Lightmap bake failed: {}", e);
+            true
+        });
+        if finished {
+            if let Some(mut baker) = self.lightmap_baker.take() {
+                unsafe { baker.destroy(renderer) };
+            }
+        }
+    }
+    
+    /// Spawn the stress-test scene and start measuring (see `stress`)
+    fn start_stress_test(&mut self, count: u32) {
+        spawn_stress_grid(&mut self.world, count);
+        self.world.resource_mut::<SceneObjects>().stress_count = count;
+        
+        // Main pass: one instanced draw for all cubes, one per glTF mesh and the ground
+        let (gltf_draws, gltf_triangles) = self.gltf_renderer.as_ref().map_or((0, 0), |gltf_renderer| {
+            let meshes = gltf_renderer.meshes.iter().chain(&gltf_renderer.ground);
+            meshes.fold((0, 0), |(draws, triangles), mesh| (draws + 1, triangles + mesh.index_count as u64 / 3))
+        });
+        let cube_triangles = self.cube_renderer.as_ref().map_or(0, |cube_renderer| cube_renderer.index_count as u64 / 3);
+        let load = stress::SceneLoad {
+            objects: count,
+            draw_calls: gltf_draws + u32::from(count > 0 && self.cube_renderer.is_some()),
+            triangles: gltf_triangles + cube_triangles * count as u64,
+        };
+        self.stress_run = Some(stress::StressRun::new(load));
+        self.stress_report = None;
+    }
+    
+    /// Write the main camera's beauty image and AOVs for this frame (see `aov`)
+    fn export_aovs(&mut self) {
+        let (Some(renderer), Some(gltf_renderer), Some(dir)) = (&self.renderer, &self.gltf_renderer, &self.aov_dir) else {
+            return;
+        };
+        // Like stills, AOVs are framed to the locked aspect ratio
+        let extent = self.world.resource::<letterbox::AspectLock>().viewport(renderer.swapchain_extent).extent;
+        unsafe {
+            if self.aov_pass.as_ref().is_some_and(|pass| pass.extent != extent) {
+                let _ = renderer.device.device_wait_idle();
+                if let Some(mut pass) = self.aov_pass.take() {
+                    pass.destroy(renderer, gltf_renderer);
+                }
+            }
+            if self.aov_pass.is_none() {
+                match aov::AovPass::new(renderer, gltf_renderer, extent, dir) {
+                    Ok(pass) => self.aov_pass = Some(pass),
+                    Err(e) => {
+                        eprintln!("✗ Failed to set up AOV export, disabling it: {}", e);
+                        self.aov_dir = None;
+                        return;
+                    }
+                }
+            }
+        }
+        
+        let camera = self.world.resource::<CameraController>();
+        let view_camera = ViewCamera::new(
+            camera.position,
+            camera.yaw,
+            camera.pitch,
+            camera.projection(),
+            extent.width as f32 / extent.height as f32,
+        );
+        let shadow_settings = *self.world.resource::<ShadowSettings>();
+        let shadows = (shadow_settings.debug_cascades, shadow_settings.softness, shadow_settings.use_pcss);
+        let Some(pass) = &mut self.aov_pass else {
+            return;
+        };
+        if let Err(e) = unsafe { pass.export_frame(renderer, gltf_renderer, &view_camera, self.aov_frame, shadows) } {
+            eprintln!("✗ Failed to export AOVs for frame {}: {}", self.aov_frame, e);
+        }
+        self.aov_frame += 1;
+    }
+    
+    /// Apply the texture filter, and move the scene texture's resident mips
+    /// towards what the main camera needs
+    fn stream_textures(&mut self) {
+        let (Some(renderer), Some(gltf_renderer)) = (&self.renderer, &mut self.gltf_renderer) else {
+            return;
+        };
+        let settings = *self.world.resource::<TextureStreamingSettings>();
+        if let Some(streamer) = &mut gltf_renderer.texture_streamer {
+            streamer.enabled = settings.enabled;
+            streamer.budget = settings.budget_mb as u64 * 1024 * 1024;
+        }
+        
+        let extent = renderer.swapchain_extent;
+        let camera = self.world.resource::<CameraController>();
+        let view_camera = ViewCamera::new(
+            camera.position,
+            camera.yaw,
+            camera.pitch,
+            self.world.resource::<letterbox::AspectLock>().fit(camera.projection(), extent),
+            extent.width as f32 / extent.height.max(1) as f32,
+        );
+        // Every view samples the texture, so each has its binding rewritten on a move
+        let views: Vec<_> = self
+            .secondary_windows
+            .values()
+            .map(|secondary| &secondary.view)
+            .chain(self.aov_pass.as_ref().and_then(|pass| pass.view()))
+            .chain(self.material_preview.as_ref().and_then(|preview| preview.view()))
+            .chain(self.planar_reflection.as_ref().and_then(|reflection| reflection.view()))
+            .collect();
+        let filter = *self.world.resource::<texture_streaming::TextureFilter>();
+        if let Err(e) = unsafe { gltf_renderer.set_texture_filter(renderer, filter, &views) } {
+            eprintln!("✗ Failed to change the texture filter: {}", e);
+        }
+        if let Err(e) = unsafe { gltf_renderer.stream_textures(renderer, &view_camera, extent.height, &views) } {
+            eprintln!("✗ Texture streaming failed: {}", e);
+        }
+    }
+    
+    /// Create, resize or destroy the planar reflection to match its settings and the window
+    fn update_planar_reflection(&mut self) {
+        let (Some(renderer), Some(gltf_renderer)) = (&self.renderer, &self.gltf_renderer) else {
+            return;
+        };
+        let settings = *self.world.resource::<PlanarReflectionSettings>();
+        let window = renderer.swapchain_extent;
+        let extent = vk::Extent2D {
+            width: ((window.width as f32 * settings.resolution_scale) as u32).max(1),
+            height: ((window.height as f32 * settings.resolution_scale) as u32).max(1),
+        };
+        unsafe {
+            if self
+                .planar_reflection
+                .as_ref()
+                .is_some_and(|reflection| !settings.enabled || reflection.extent() != extent)
+            {
+                let _ = renderer.device.device_wait_idle();
+                if let Some(mut reflection) = self.planar_reflection.take() {
+                    reflection.destroy(renderer, gltf_renderer);
+                }
+            }
+            if settings.enabled && self.planar_reflection.is_none() {
+                // The main view's descriptor sets are rewritten
+                let _ = renderer.device.device_wait_idle();
+                match planar_reflection::PlanarReflection::new(renderer, gltf_renderer, extent) {
+                    Ok(reflection) => self.planar_reflection = Some(reflection),
+                    Err(e) => {
+                        eprintln!("✗ Failed to set up planar reflections, disabling them: {}", e);
+                        self.world.resource_mut::<PlanarReflectionSettings>().enabled = false;
+                    }
+                }
+            }
+        }
+    }
+    
+    /// Free the buffers defragmentation left behind and, on idle frames, move some more
+    fn defragment_memory(&mut self) {
+        let Some(renderer) = &self.renderer else {
+            return;
+        };
+        unsafe { self.defragmenter.collect(renderer) };
+        let idle = self.keys_pressed.is_empty() && self.lightmap_baker.is_none();
+        if let (true, Some(gltf_renderer)) = (idle, &mut self.gltf_renderer) {
+            let buffers = gltf_renderer.movable_buffers(renderer);
+            unsafe { self.defragmenter.step(renderer, buffers) };
+        }
+    }
+    
+    /// Follow the window's size with the SSGI targets
+    fn update_ssgi(&mut self) {
+        let (Some(renderer), Some(gltf_renderer), Some(ssgi)) = (&self.renderer, &self.gltf_renderer, &mut self.ssgi) else {
+            return;
+        };
+        if ssgi.extent() == Some(half_res::half_extent(renderer.swapchain_extent)) {
+            return;
+        }
+        unsafe {
+            // The main view's descriptor sets are rewritten
+            let _ = renderer.device.device_wait_idle();
+            if let Err(e) = ssgi.resize(renderer, gltf_renderer) {
+                eprintln!("✗ Failed to resize screen-space global illumination, disabling it: {}", e);
+                if let Some(mut ssgi) = self.ssgi.take() {
+                    ssgi.destroy(renderer, gltf_renderer);
+                }
+            }
+        }
+    }
+    
+    /// Render the material editor's preview sphere, if the edited material changed
+    fn update_material_preview(&mut self) {
+        let (Some(renderer), Some(gltf_renderer), Some(egui_vulkan)) =
+            (&self.renderer, &self.gltf_renderer, &mut self.egui_vulkan)
+        else {
+            return;
+        };
+        if gltf_renderer.meshes.is_empty() {
+            return;
+        }
+        if self.material_preview.is_none() {
+            match unsafe { material_preview::MaterialPreview::new(renderer, gltf_renderer, egui_vulkan) } {
+                Ok(preview) => self.material_preview = Some(preview),
+                Err(e) => {
+                    eprintln!("✗ Failed to set up the material preview: {}", e);
+                    self.world.resource_mut::<MaterialEditor>().open = false;
+                    return;
+                }
+            }
+        }
+        
+        let mesh_index = self.world.resource::<MaterialEditor>().selected_mesh.min(gltf_renderer.meshes.len() - 1);
+        let shadow_settings = *self.world.resource::<ShadowSettings>();
+        let shadows = (shadow_settings.debug_cascades, shadow_settings.softness, shadow_settings.use_pcss);
+        let Some(preview) = &mut self.material_preview else {
+            return;
+        };
+        if let Err(e) = unsafe { preview.update(renderer, gltf_renderer, mesh_index, shadows) } {
+            eprintln!("✗ Failed to render the material preview: {}", e);
+        }
+    }
+    
+    fn update_window_title(&self) {
+        if let Some(window) = &self.window {
+            let stats = self.world.resource::<PerformanceStats>();
+            let title = format!(
+                "Funky Renderer | FPS: {:.1} | Frame: {:.2}ms | Bevy ECS + Vulkan + egui",
+                stats.fps, stats.frame_time_ms
+            );
+            window.set_title(&title);
+        }
+    }
+    
+    /// Whether any entity moves on its own. Particles are ambient and don't keep the viewer
+    /// awake; on demand they advance at the idle rate.
+    fn scene_animating(&mut self) -> bool {
+        let moving = self.world
+            .query_filtered::<(), Or<(With<Velocity>, With<GridWave>)>>()
+            .iter(&self.world)
+            .next()
+            .is_some();
+        let gltf_playing = self.gltf_renderer.as_ref().and_then(|g| g.animation.as_ref()).is_some_and(|a| a.animating());
+        moving || gltf_playing || self.world.query::<&AnimationPlayer>().iter(&self.world).any(|player| player.playing)
+    }
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
+        }
+        
+        println!("🚀 Funky Vulkan Renderer - Bevy ECS + egui Edition");
+        println!("════════════════════════════════════════════");
+        
+        let window_attributes = Window::default_attributes()
+            .with_title("Funky Renderer | Initializing...")
+            .with_inner_size(winit::dpi::LogicalSize::new(1280, 720))
+            .with_resizable(true);
+        
+        let window = event_loop.create_window(window_attributes).unwrap();
+        
+        unsafe {
+            match self.renderer_builder.build(&window) {
+                Ok(renderer) => {
+                    println!("✓ Vulkan renderer initialized");
+                    println!("  Resolution: {}x{}", 
+                        renderer.swapchain_extent.width, 
+                        renderer.swapchain_extent.height);
+                    
+                    // Initialize egui
+                    let egui_integration = EguiIntegration::new(&window);
                     let egui_vulkan = EguiVulkanRenderer::new(
-                        &renderer.device,
-                        renderer.physical_device,
-                        &renderer.instance,
+                        &renderer,
                         renderer.render_pass,
+                        renderer.swapchain_format,
                         &egui_integration.ctx,
-                        renderer.graphics_queue,
-                        renderer.graphics_queue_family_index,
                     );
                     self.egui_integration = Some(egui_integration);
                     self.egui_vulkan = Some(egui_vulkan);
                     println!("✓ egui debug UI initialized");
                     
+                    let ktx2 = renderer.texture_compression_bc_supported;
                     self.renderer = Some(renderer);
+                    self.loading = Some(loading::Loading::start(self.lightmap_charts, ktx2));
                 }
                 Err(e) => {
                     eprintln!("✗ Failed to initialize Vulkan: {}", e);
@@ -450,6 +2076,253 @@ impl ApplicationHandler for App {
             }
         }
         
+        // The scene and the rest of startup load behind the loading screen
+        window.request_redraw();
+        
+        self.window = Some(window);
+    }
+    
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, id: WindowId, event: WindowEvent) {
+        if !matches!(event, WindowEvent::RedrawRequested) {
+            self.redraw_pending = true;
+        }
+        if self.secondary_windows.contains_key(&id) {
+            self.handle_secondary_window_event(id, event);
+            return;
+        }
+        
+        // Let egui handle events first. We still want to keep camera controls responsive,
+        // so we only suppress *key presses* when egui actively wants keyboard input.
+        let mut egui_consumed = false;
+        let mut egui_wants_keyboard = false;
+        if let (Some(egui), Some(window)) = (&mut self.egui_integration, &self.window) {
+            egui_consumed = egui.handle_event(window, &event);
+            egui_wants_keyboard = egui.ui_visible && egui.ctx.wants_keyboard_input();
+        }
+
+        // The trackball follows the cursor and lets go of the model even over egui, so a
+        // drag never jumps or sticks
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.trackball_moved(glam::Vec2::new(position.x as f32, position.y as f32));
+            }
+            WindowEvent::MouseInput { state, button: winit::event::MouseButton::Left, .. } if !state.is_pressed() => {
+                self.world.resource_mut::<Trackball>().release();
+            }
+            _ => {}
+        }
+
+        // If egui consumed this event and it's not keyboard input, don't also handle it here.
+        // (We still handle keyboard input so camera controls remain usable.)
+        if egui_consumed {
+            if !matches!(event, WindowEvent::KeyboardInput { .. }) {
+                return;
+            }
+        }
+        
+        match event {
+            WindowEvent::CloseRequested => {
+                self.cleanup();
+                event_loop.exit();
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                if let PhysicalKey::Code(keycode) = event.physical_key {
+                    if event.state.is_pressed() {
+                        // Always allow app-level hotkeys, but avoid stealing input from egui
+                        // when it is editing a text field.
+                        let is_app_hotkey = matches!(keycode, KeyCode::Escape | KeyCode::F2 | KeyCode::F3 | KeyCode::F11 | KeyCode::F12);
+                        if is_app_hotkey || !egui_wants_keyboard {
+                            self.keys_pressed.insert(keycode);
+                        }
+                        
+                        match keycode {
+                            KeyCode::Escape => {
+                                self.cleanup();
+                                event_loop.exit();
+                            }
+                            KeyCode::F2 => {
+                                self.open_window_requested = true;
+                            }
+                            KeyCode::F3 => {
+                                if let Some(egui) = &mut self.egui_integration {
+                                    egui.toggle_ui();
+                                }
+                            }
+                            KeyCode::F11 => {
+                                if let Some(window) = &self.window {
+                                    self.display.toggle_fullscreen(window);
+                                }
+                            }
+                            KeyCode::F12 => {
+                                self.still_requested = true;
+                            }
+                            KeyCode::KeyP if !egui_wants_keyboard => {
+                                self.world.resource_mut::<time_control::TimeControl>().toggle_pause();
+                            }
+                            KeyCode::Period if !egui_wants_keyboard => {
+                                self.world.resource_mut::<time_control::TimeControl>().step();
+                            }
+                            KeyCode::BracketLeft if !egui_wants_keyboard => {
+                                self.world.resource_mut::<time_control::TimeControl>().slower();
+                            }
+                            KeyCode::BracketRight if !egui_wants_keyboard => {
+                                self.world.resource_mut::<time_control::TimeControl>().faster();
+                            }
+                            _ => {}
+                        }
+                    } else {
+                        // Always remove on release to avoid stuck movement if egui consumed
+                        // the press side of the event.
+                        self.keys_pressed.remove(&keycode);
+                    }
+                }
+            }
+            WindowEvent::MouseInput { state, button: winit::event::MouseButton::Left, .. } if state.is_pressed() => {
+                let shift = self.keys_pressed.contains(&KeyCode::ShiftLeft) || self.keys_pressed.contains(&KeyCode::ShiftRight);
+                self.world.resource_mut::<Trackball>().press(shift);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let scroll_amount = match delta {
+                    winit::event::MouseScrollDelta::LineDelta(_, y) => y * 0.1,
+                    winit::event::MouseScrollDelta::PixelDelta(pos) => (pos.y as f32) * 0.01,
+                };
+                
+                self.world.resource_mut::<CameraController>().zoom(scroll_amount);
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                // egui has already picked up the new pixels-per-point; the physical size
+                // changes with it, so rebuild the swapchain even if no Resized follows
+                println!("ℹ Display scale factor: {:.2}", scale_factor);
+                if let Some(renderer) = &mut self.renderer {
+                    renderer.framebuffer_resized = true;
+                }
+            }
+            WindowEvent::Resized(new_size) => {
+                if new_size.width == 0 || new_size.height == 0 {
+                    self.minimized = true;
+                } else {
+                    self.minimized = false;
+                    if let Some(renderer) = &mut self.renderer {
+                        renderer.framebuffer_resized = true;
+                    }
+                }
+            }
+            WindowEvent::RedrawRequested => {
+                self.redraw_pending = false;
+                if self.loading.is_some() {
+                    // Keep the loading screen moving until startup is done
+                    if !self.minimized {
+                        self.render_loading_frame();
+                    }
+                    self.step_loading();
+                    if let Some(window) = &self.window {
+                        window.request_redraw();
+                    }
+                    return;
+                }
+                if !self.minimized {
+                    self.render_frame();
+                }
+
+                // Drive continuous animation even while the window is being interacted with.
+                // (Relying only on about_to_wait can stall during certain OS modal loops.)
+                // On demand, about_to_wait decides when the next frame is due instead.
+                if !self.world.resource::<RedrawSettings>().on_demand {
+                    if let Some(window) = &self.window {
+                        window.request_redraw();
+                    }
+                }
+            }
+            _ => {}
+        }
+
+    }
+    
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        // Redraws are chained from RedrawRequested; only window creation happens here
+        // because it needs the event loop.
+        if self.open_window_requested {
+            self.open_window_requested = false;
+            self.open_secondary_window(event_loop);
+        }
+        
+        let settings = *self.world.resource::<RedrawSettings>();
+        if !settings.on_demand || self.minimized {
+            event_loop.set_control_flow(ControlFlow::Wait);
+            return;
+        }
+        
+        // Redraw now if anything is changing, otherwise sleep until the idle frame (or an
+        // egui animation) is due
+        let mut next_frame = self.last_frame_time + settings.idle_interval();
+        if let Some(repaint_at) = self.egui_repaint_at {
+            next_frame = next_frame.min(repaint_at);
+        }
+        let active = self.redraw_pending
+            || !self.keys_pressed.is_empty()
+            || self.scene_animating()
+            || self.world.resource::<CameraController>().animating()
+            || self.lightmap_baker.is_some();
+        if active || Instant::now() >= next_frame {
+            if let Some(window) = &self.window {
+                window.request_redraw();
+            }
+            event_loop.set_control_flow(ControlFlow::Wait);
+        } else {
+            event_loop.set_control_flow(ControlFlow::WaitUntil(next_frame));
+        }
+    }
+}
+
+impl App {
+    /// Run the next startup stage while the loading screen shows, once what it needs is
+    /// ready. After the last one the startup systems run and real frames begin.
+    fn step_loading(&mut self) {
+        let Some(loading) = &mut self.loading else {
+            return;
+        };
+        let Some(stage) = loading.stage() else {
+            return;
+        };
+        unsafe {
+            match stage {
+                loading::Stage::Scene => {
+                    if !loading.scene_ready() {
+                        return;
+                    }
+                }
+                loading::Stage::SceneRenderer => {
+                    let scene = loading.take_scene();
+                    self.create_scene_renderer(scene);
+                    if let (Some(renderer), Some(gltf_renderer), Some(loading)) =
+                        (&self.renderer, &self.gltf_renderer, &mut self.loading)
+                    {
+                        loading.start_pipelines(gltf_renderer.scene_pipeline_builder(&renderer.device));
+                    }
+                }
+                loading::Stage::ScenePipelines => {
+                    if !loading.pipelines_ready() {
+                        return;
+                    }
+                    if let Some(pipelines) = loading.take_pipelines() {
+                        self.install_scene_pipelines(pipelines);
+                    }
+                }
+                loading::Stage::Cubes => self.create_cube_renderer(),
+                loading::Stage::Effects => self.create_effects(),
+                loading::Stage::Particles => self.create_particles(),
+            }
+        }
+        let Some(loading) = &mut self.loading else {
+            return;
+        };
+        loading.advance();
+        if loading.stage().is_some() {
+            return;
+        }
+        self.loading = None;
+        self.last_frame_time = Instant::now(); // The first frame's step shouldn't cover the loading
+        
         if !self.startup_done {
             self.startup_schedule.run(&mut self.world);
             self.startup_done = true;
@@ -458,136 +2331,424 @@ impl ApplicationHandler for App {
         println!("\n🎮 Controls:");        println!("   WASD - Move camera");
         println!("   Q/E - Move up/down");
         println!("   Arrow Keys - Rotate camera");        println!("   ESC - Exit");
+        println!("   Mouse drag - Turn the model, shift to move it (with Trackball on)");
+        println!("   F2 - Open another view window");
         println!("   F3 - Toggle UI");
-        println!("   F11 - Toggle Fullscreen\n");
+        println!("   F11 - Toggle Fullscreen");
+        println!("   F12 - Render supersampled still\n");
+    }
+    
+    /// Upload the scene the loader read, if it found and read one
+    unsafe fn create_scene_renderer(&mut self, scene: Option<Result<loading::LoadedScene, String>>) {
+        let Some(renderer) = &self.renderer else {
+            return;
+        };
+        match scene {
+            Some(Ok(loaded)) => {
+                // Store model bounds so we can place it on the ground plane.
+                {
+                    let mut objects = self.world.resource_mut::<SceneObjects>();
+                    objects.gltf_min_y = loaded.scene.bounds_min[1];
+                }
+                match GltfRenderer::new(renderer, &loaded.scene) {
+                    Ok(mut gltf_renderer) => {
+                        println!("  ✓ glTF renderer created with textures");
+                        gltf_renderer.lightmap.uncharted_meshes = loaded.uncharted;
+                        if loaded.lightmap_path.exists() {
+                            let lightmap = lightmap::load(&loaded.lightmap_path)
+                                .and_then(|texels| gltf_renderer.lightmap.upload(renderer, &texels));
+                            match lightmap {
+                                Ok(()) => println!("  ✓ Lightmap loaded from {}", loaded.lightmap_path.display()),
+                                Err(e) => eprintln!("  ⚠ Failed to load lightmap: {}", e),
+                            }
+                        }
+                        if let Some(path) = &self.environment {
+                            match gltf_renderer.set_environment(renderer, path) {
+                                Ok(()) => println!("  ✓ Environment loaded from {}", path.display()),
+                                Err(e) => eprintln!("  ⚠ Failed to load environment {}: {}", path.display(), e),
+                            }
+                        }
+                        self.world.insert_resource(Scene::new(loaded.path.to_string_lossy(), &loaded.scene));
+                        self.world.resource_scope(|world, scene: Mut<Scene>| {
+                            world.commands().spawn_model(&scene, Transform::new());
+                        });
+                        self.world.flush();
+                        self.lightmap_path = Some(loaded.lightmap_path);
+                        self.scene_path = Some(loaded.path);
+                        self.gltf_renderer = Some(gltf_renderer);
+                    }
+                    Err(e) => {
+                        eprintln!("  ✗ Failed to create glTF renderer: {}", e);
+                    }
+                }
+            }
+            Some(Err(e)) => {
+                eprintln!("  ✗ Failed to load glTF: {}", e);
+            }
+            None => {}
+        }
         
-        // Request initial redraw
-        window.request_redraw();
+        if self.gltf_renderer.is_none() {
+            println!("ℹ No glTF scene loaded. Place a model.gltf or model.glb in the project root or models/ folder.");
+        }
+    }
+    
+    /// Hand the pipelines the worker built to the scene renderer, or drop the renderer if
+    /// they failed to build
+    unsafe fn install_scene_pipelines(&mut self, pipelines: Result<gltf_renderer::ScenePipelines, String>) {
+        let (Some(renderer), Some(gltf_renderer)) = (&self.renderer, &mut self.gltf_renderer) else {
+            return;
+        };
+        match pipelines {
+            Ok(pipelines) => {
+                gltf_renderer.install_scene_pipelines(&renderer.device, pipelines);
+                println!("  ✓ glTF scene pipelines built");
+            }
+            Err(e) => {
+                eprintln!("  ✗ Failed to build glTF scene pipelines: {}", e);
+                gltf_renderer.cleanup(renderer);
+                self.gltf_renderer = None;
+            }
+        }
+    }
+
+    /// ECS cubes are drawn inside the glTF scene pass so they share its depth buffer
+    unsafe fn create_cube_renderer(&mut self) {
+        let Some(renderer) = &self.renderer else {
+            return;
+        };
+        // Without a glTF scene the cubes are drawn in the renderer's own pass, which has no
+        // depth buffer
+        let scene_pass = self.gltf_renderer.as_ref().map(|gltf_renderer| gltf_renderer.render_pass);
+        let cube_renderer = CubeRenderer::new(renderer).and_then(|mut cubes| {
+            cubes.create_instanced_pipeline(renderer, scene_pass.unwrap_or(renderer.render_pass), scene_pass.is_some())?;
+            Ok(cubes)
+        });
+        match cube_renderer {
+            Ok(cube_renderer) => {
+                println!("✓ Instanced cube renderer initialized");
+                let Some(scene_pass) = scene_pass else {
+                    self.cube_renderer = Some(cube_renderer);
+                    return;
+                };
+                let source = impostor::ImpostorSource {
+                    vertex_buffer: cube_renderer.vertex_buffer,
+                    index_buffer: cube_renderer.index_buffer,
+                    index_type: vk::IndexType::UINT16,
+                    index_count: cube_renderer.index_count,
+                    radius: 0.75f32.sqrt(), // Corner of the unit cube
+                };
+                match impostor::Impostors::new::<renderer::Vertex>(renderer, scene_pass, &source) {
+                    Ok(impostors) => self.impostors = Some(impostors),
+                    Err(e) => eprintln!("✗ Failed to bake cube impostors: {}", e),
+                }
+                self.cube_renderer = Some(cube_renderer);
+            }
+            Err(e) => {
+                eprintln!("✗ Failed to create cube renderer: {}", e);
+            }
+        }
+    }
+    
+    /// Profiling, post effects, and the effects drawn over the glTF scene
+    unsafe fn create_effects(&mut self) {
+        let Some(renderer) = &self.renderer else {
+            return;
+        };
+        match GpuProfiler::new(renderer) {
+            Ok(gpu_profiler) => self.gpu_profiler = gpu_profiler,
+            Err(e) => eprintln!("✗ Failed to set up GPU profiling: {}", e),
+        }
+        
+        match post_effects::PostEffects::new(renderer) {
+            Ok(post_effects) => self.post_effects = Some(post_effects),
+            Err(e) => eprintln!("✗ Failed to create post effects: {}", e),
+        }
         
-        self.window = Some(window);
+        let Some(gltf_renderer) = &self.gltf_renderer else {
+            return;
+        };
+        match lens_flare::LensFlare::new(renderer, gltf_renderer.render_pass) {
+            Ok(lens_flare) => self.lens_flare = Some(lens_flare),
+            Err(e) => eprintln!("✗ Failed to create lens flares: {}", e),
+        }
+        match god_rays::GodRays::new(renderer) {
+            Ok(god_rays) => self.god_rays = Some(god_rays),
+            Err(e) => eprintln!("✗ Failed to create god rays: {}", e),
+        }
+        match half_res::HalfResTransparency::new(renderer) {
+            Ok(half_res) => self.half_res = Some(half_res),
+            Err(e) => eprintln!("✗ Failed to create half-resolution transparency: {}", e),
+        }
+        match ssgi::Ssgi::new(renderer, gltf_renderer) {
+            Ok(ssgi) => self.ssgi = Some(ssgi),
+            Err(e) => eprintln!("✗ Failed to create screen-space global illumination: {}", e),
+        }
+        match weather::WeatherParticles::new(renderer, gltf_renderer.render_pass) {
+            Ok(mut weather) => {
+                if let Some(half_res) = &self.half_res {
+                    if let Err(e) = weather.add_render_pass(&renderer.device, half_res.render_pass) {
+                        eprintln!("✗ Failed to create half-resolution weather pipeline: {}", e);
+                    }
+                }
+                self.weather = Some(weather);
+            }
+            Err(e) => eprintln!("✗ Failed to create weather particles: {}", e),
+        }
+        match vegetation::Vegetation::new(renderer, gltf_renderer.render_pass) {
+            Ok(vegetation) => self.vegetation = Some(vegetation),
+            Err(e) => eprintln!("✗ Failed to create vegetation: {}", e),
+        }
     }
     
-    fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
-        // Let egui handle events first. We still want to keep camera controls responsive,
-        // so we only suppress *key presses* when egui actively wants keyboard input.
-        let mut egui_consumed = false;
-        let mut egui_wants_keyboard = false;
-        if let (Some(egui), Some(window)) = (&mut self.egui_integration, &self.window) {
-            egui_consumed = egui.handle_event(window, &event);
-            egui_wants_keyboard = egui.ui_visible && egui.ctx.wants_keyboard_input();
+    /// GPU particles, simulated on the async compute queue when there is one
+    unsafe fn create_particles(&mut self) {
+        let (Some(renderer), Some(gltf_renderer)) = (&self.renderer, &self.gltf_renderer) else {
+            return;
+        };
+        match AsyncCompute::new(renderer) {
+            Ok(async_compute) => self.async_compute = async_compute,
+            Err(e) => eprintln!("✗ Failed to set up async compute: {}", e),
         }
-
-        // If egui consumed this event and it's not keyboard input, don't also handle it here.
-        // (We still handle keyboard input so camera controls remain usable.)
-        if egui_consumed {
-            if !matches!(event, WindowEvent::KeyboardInput { .. }) {
-                return;
+        let compute_family = self.async_compute.as_ref().map(|a| a.queue_family_index);
+        match ParticleSystem::new(renderer, gltf_renderer.render_pass, compute_family) {
+            Ok(mut particles) => {
+                if let Some(half_res) = &self.half_res {
+                    if let Err(e) = particles.add_render_pass(&renderer.device, half_res.render_pass) {
+                        eprintln!("✗ Failed to create half-resolution particle pipeline: {}", e);
+                    }
+                }
+                println!(
+                    "✓ GPU particles initialized ({})",
+                    if compute_family.is_some() { "async compute queue" } else { "graphics queue" }
+                );
+                self.particles = Some(particles);
+            }
+            Err(e) => {
+                eprintln!("✗ Failed to create particle system: {}", e);
             }
         }
+    }
+    
+    /// Draw the loading screen, on its own: nothing else exists yet
+    fn render_loading_frame(&mut self) {
+        let (Some(renderer), Some(window), Some(loading)) = (&mut self.renderer, &self.window, &self.loading) else {
+            return;
+        };
+        let (Some(egui_int), Some(egui_vk)) = (&mut self.egui_integration, &mut self.egui_vulkan) else {
+            return;
+        };
+        let raw_input = egui_int.state.take_egui_input(window);
+        let full_output = egui_int.ctx.run(raw_input, |ctx| loading.draw(ctx));
         
-        match event {
-            WindowEvent::CloseRequested => {
-                self.cleanup();
-                event_loop.exit();
+        unsafe {
+            let timeout = 1_000_000_000; // 1 second in nanoseconds
+            let frame = renderer.current_frame;
+            if let Err(e) = renderer.device.wait_for_fences(&[renderer.in_flight_fences[frame]], true, timeout) {
+                eprintln!("Fence wait timeout or error: {:?}", e);
+                return;
             }
-            WindowEvent::KeyboardInput { event, .. } => {
-                if let PhysicalKey::Code(keycode) = event.physical_key {
-                    if event.state.is_pressed() {
-                        // Always allow app-level hotkeys, but avoid stealing input from egui
-                        // when it is editing a text field.
-                        let is_app_hotkey = matches!(keycode, KeyCode::Escape | KeyCode::F3 | KeyCode::F11);
-                        if is_app_hotkey || !egui_wants_keyboard {
-                            self.keys_pressed.insert(keycode);
-                        }
-                        
-                        match keycode {
-                            KeyCode::Escape => {
-                                self.cleanup();
-                                event_loop.exit();
-                            }
-                            KeyCode::F3 => {
-                                if let Some(egui) = &mut self.egui_integration {
-                                    egui.toggle_ui();
-                                }
-                            }
-                            KeyCode::F11 => {
-                                if let Some(window) = &self.window {
-                                    let is_fullscreen = window.fullscreen().is_some();
-                                    if is_fullscreen {
-                                        window.set_fullscreen(None);
-                                    } else {
-                                        window.set_fullscreen(Some(
-                                            winit::window::Fullscreen::Borderless(None)
-                                        ));
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
-                    } else {
-                        // Always remove on release to avoid stuck movement if egui consumed
-                        // the press side of the event.
-                        self.keys_pressed.remove(&keycode);
+            let image_index = match renderer.swapchain_fn.acquire_next_image(
+                renderer.swapchain,
+                u64::MAX,
+                renderer.image_available_semaphores[frame],
+                vk::Fence::null(),
+            ) {
+                Ok((index, _)) => index,
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    let size = window.inner_size();
+                    if let Err(e) = renderer.recreate_swapchain(size.width, size.height) {
+                        eprintln!("Swapchain recreate failed: {:?}", e);
                     }
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("Failed to acquire image: {:?}", e);
+                    return;
                 }
+            };
+            let image_fence = renderer.images_in_flight[image_index as usize];
+            if image_fence != vk::Fence::null() && renderer.device.wait_for_fences(&[image_fence], true, timeout).is_err() {
+                return;
             }
-            WindowEvent::MouseWheel { delta, .. } => {
-                let scroll_amount = match delta {
-                    winit::event::MouseScrollDelta::LineDelta(_, y) => y * 0.1,
-                    winit::event::MouseScrollDelta::PixelDelta(pos) => (pos.y as f32) * 0.01,
-                };
-                
-                let mut camera = self.world.resource_mut::<CameraController>();
-                camera.fov = (camera.fov - scroll_amount).clamp(10.0_f32.to_radians(), 120.0_f32.to_radians());
+            renderer.images_in_flight[image_index as usize] = renderer.in_flight_fences[frame];
+            renderer.device.reset_fences(&[renderer.in_flight_fences[frame]]).unwrap();
+            if let Err(e) = renderer.frame_arena.begin_frame(frame) {
+                eprintln!("Failed to reset frame arena: {}", e);
             }
-            WindowEvent::Resized(new_size) => {
-                if new_size.width == 0 || new_size.height == 0 {
-                    self.minimized = true;
-                } else {
-                    self.minimized = false;
-                    if let Some(renderer) = &mut self.renderer {
-                        renderer.framebuffer_resized = true;
-                    }
-                }
+            
+            if !full_output.textures_delta.set.is_empty() {
+                let _ = renderer.device.device_wait_idle();
             }
-            WindowEvent::RedrawRequested => {
-                if !self.minimized {
-                    self.render_frame();
+            egui_vk.update_textures(renderer, &full_output.textures_delta);
+            let clipped_primitives = egui_int.ctx.tessellate(full_output.shapes, full_output.pixels_per_point);
+            
+            let command_buffer = renderer.command_buffers[frame];
+            renderer.device.begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default()).unwrap();
+            // The UI pass loads the image, which no scene pass has drawn; the central panel
+            // covers all of it
+            compute::image_barrier(
+                &renderer.device,
+                command_buffer,
+                renderer.swapchain_images[image_index as usize],
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::PRESENT_SRC_KHR,
+                Access::COLOR_ATTACHMENT_WRITE,
+                Access::COLOR_ATTACHMENT_WRITE,
+            );
+            let clear_values = [vk::ClearValue {
+                color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 0.0] },
+            }];
+            let mut encoder = CommandEncoder::new(&renderer.device, command_buffer);
+            let mut pass = encoder.begin_render_pass(
+                renderer.render_pass,
+                renderer.framebuffers[image_index as usize],
+                renderer.swapchain_extent,
+                &clear_values,
+            );
+            egui_vk.render(
+                &mut pass,
+                &renderer.frame_arena,
+                frame,
+                renderer.swapchain_extent,
+                clipped_primitives,
+                full_output.pixels_per_point,
+            );
+            drop(pass);
+            renderer.device.end_command_buffer(command_buffer).unwrap();
+            
+            let wait_semaphores = [renderer.image_available_semaphores[frame]];
+            let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+            let command_buffers = [command_buffer];
+            let signal_semaphores = [renderer.render_finished_semaphores[frame]];
+            let submit_info = vk::SubmitInfo::default()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .command_buffers(&command_buffers)
+                .signal_semaphores(&signal_semaphores);
+            renderer.device.queue_submit(renderer.graphics_queue, &[submit_info], renderer.in_flight_fences[frame]).unwrap();
+            
+            let swapchains = [renderer.swapchain];
+            let image_indices = [image_index];
+            let present_info = vk::PresentInfoKHR::default()
+                .wait_semaphores(&signal_semaphores)
+                .swapchains(&swapchains)
+                .image_indices(&image_indices);
+            let should_recreate = match renderer.swapchain_fn.queue_present(renderer.present_queue, &present_info) {
+                Ok(suboptimal) => suboptimal || renderer.framebuffer_resized,
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => true,
+                Err(e) => {
+                    eprintln!("Present error: {:?}", e);
+                    false
                 }
-
-                // Drive continuous animation even while the window is being interacted with.
-                // (Relying only on about_to_wait can stall during certain OS modal loops.)
-                if let Some(window) = &self.window {
-                    window.request_redraw();
+            };
+            if should_recreate {
+                let size = window.inner_size();
+                if let Err(e) = renderer.recreate_swapchain(size.width, size.height) {
+                    eprintln!("Swapchain recreate failed: {:?}", e);
                 }
             }
-            _ => {}
+            renderer.advance_frame();
         }
-
     }
     
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
-        // No-op: redraws are chained from RedrawRequested.
+    /// Recompile the shaders edited since the last check and rebuild the pipelines that
+    /// use them. Shaders that fail to compile keep their current pipelines.
+    fn reload_shaders(&mut self) {
+        let mut stems = Vec::new();
+        for name in self.shader_watcher.poll() {
+            match shader_compiler::compile(&name, &[]) {
+                Ok(_) => {
+                    shader_compiler::mark_changed(&name);
+                    stems.push(name.split('.').next().unwrap_or_default().to_string());
+                }
+                Err(e) => eprintln!("⚠ Shader {} failed to compile, keeping its pipelines:\n{}", name, e),
+            }
+        }
+        stems.dedup();
+        if stems.is_empty() {
+            return;
+        }
+        let Some(renderer) = &mut self.renderer else {
+            return;
+        };
+        
+        unsafe {
+            if let Err(e) = renderer.device.device_wait_idle() {
+                eprintln!("⚠ Shader reload skipped: {}", e);
+                return;
+            }
+            for stem in &stems {
+                let result = match stem.as_str() {
+                    "cube" => renderer.reload_cube_pipeline(),
+                    "gltf" => match &mut self.gltf_renderer {
+                        Some(gltf) => gltf.reload_scene_pipelines(&renderer.device),
+                        None => Ok(()),
+                    },
+                    "shadow" => match &mut self.gltf_renderer {
+                        Some(gltf) => gltf.reload_shadow_pipeline(&renderer.device),
+                        None => Ok(()),
+                    },
+                    "egui" => match &mut self.egui_vulkan {
+                        Some(egui_vk) => egui_vk.reload_pipeline(&renderer.device),
+                        None => Ok(()),
+                    },
+                    _ => {
+                        println!("Shader {}.* applies to pipelines created from now on; restart to apply it everywhere", stem);
+                        continue;
+                    }
+                };
+                match result {
+                    Ok(()) => println!("✓ Reloaded {} shaders", stem),
+                    Err(e) => eprintln!("⚠ Failed to rebuild the {} pipelines, keeping the old ones: {}", stem, e),
+                }
+            }
+        }
     }
-}
-
-impl App {
+    
     fn render_frame(&mut self) {
         // Update delta time
         let now = Instant::now();
-        let delta = now.duration_since(self.last_frame_time).as_secs_f32();
+        let real_delta = now.duration_since(self.last_frame_time).as_secs_f32();
         self.last_frame_time = now;
+        self.egui_repaint_at = None; // Set again below if egui runs and wants another frame
         
+        // Scene time, slowed, sped up or stopped by the time controls
+        let delta = self.world.resource_mut::<time_control::TimeControl>().advance(real_delta);
         {
             let mut timing = self.world.resource_mut::<FrameTiming>();
             timing.delta_time = delta;
+            timing.real_delta_time = real_delta;
+            timing.elapsed += delta;
+        }
+        
+        if let Some(count) = self.stress_requested.take() {
+            self.start_stress_test(count);
+        } else if let Some(run) = &mut self.stress_run {
+            if let Some(report) = run.record(real_delta * 1000.0) {
+                self.stress_report = Some(report);
+                self.stress_run = None;
+            }
         }
         
-        // Run ECS systems
-        self.schedule.run(&mut self.world);
+        // Simulate the scene time that passed in fixed steps, then run the per-frame
+        // systems, which queue this frame's debug shapes afresh
+        {
+            let _scope = profiling::scope("Fixed update");
+            let steps = self.world.resource_mut::<FixedTime>().accumulate(delta);
+            for _ in 0..steps {
+                self.fixed_schedule.run(&mut self.world);
+            }
+        }
+        {
+            let _scope = profiling::scope("ECS schedule");
+            self.world.resource_mut::<DebugDraw>().clear();
+            self.schedule.run(&mut self.world);
+        }
         
         // Update camera from input
         self.update_camera();
+        self.reload_shaders();
         
         let renderer = match &mut self.renderer {
             Some(r) => r,
@@ -600,6 +2761,7 @@ impl App {
         unsafe {
             // Wait for previous frame with timeout to prevent indefinite blocking
             let timeout = 1_000_000_000; // 1 second in nanoseconds
+            let wait_scope = profiling::scope("Wait for frame");
             match renderer.device.wait_for_fences(
                 &[renderer.in_flight_fences[renderer.current_frame]],
                 true,
@@ -611,6 +2773,7 @@ impl App {
                     return;
                 }
             }
+            wait_scope.end();
             
             let result = renderer.swapchain_fn.acquire_next_image(
                 renderer.swapchain,
@@ -670,59 +2833,346 @@ impl App {
                 &[renderer.in_flight_fences[renderer.current_frame]],
             ).unwrap();
             
+            // This frame slot's transient data is no longer read by the GPU
+            if let Err(e) = renderer.frame_arena.begin_frame(renderer.current_frame) {
+                eprintln!("Failed to reset frame arena: {}", e);
+            }
+            // ...and its GPU culling results can be counted, and its unculled draws rewritten
+            if let Some(gpu_driven) = self.gltf_renderer.as_mut().and_then(|g| g.gpu_driven.as_mut()) {
+                gpu_driven.begin_frame(renderer.current_frame);
+                gpu_driven.read_statistics(renderer.current_frame);
+            }
+            // ...and the virtual texture pages it sampled requested
+            if let Some(gltf_renderer) = &mut self.gltf_renderer {
+                gltf_renderer.virtual_texture.begin_frame(renderer.current_frame);
+            }
+            // ...and what its scene pass cost read
+            if let Some(pipeline_statistics) = self.gltf_renderer.as_mut().and_then(|g| g.pipeline_statistics.as_mut()) {
+                pipeline_statistics.read(&renderer.device, renderer.current_frame);
+            }
+            
+            // Submit independent compute to the async queue first so it overlaps the shadow
+            // and opaque passes. Graphics only waits on the previous frame's compute.
+            let mut compute_wait_semaphore = None;
+            if let (Some(particles), Some(async_compute)) = (&mut self.particles, &mut self.async_compute) {
+                match async_compute.begin(&renderer.device, renderer.current_frame) {
+                    Ok(compute_command_buffer) => {
+                        particles.simulate(&renderer.device, compute_command_buffer, delta);
+                        match async_compute.submit(&renderer.device, renderer.current_frame) {
+                            Ok(semaphore) => compute_wait_semaphore = semaphore,
+                            Err(e) => eprintln!("Async compute submit failed: {:?}", e),
+                        }
+                    }
+                    Err(e) => eprintln!("Async compute begin failed: {:?}", e),
+                }
+            }
+            
             // Start command buffer
+            let record_scope = profiling::scope("Record commands");
             let begin_info = vk::CommandBufferBeginInfo::default();
             renderer.device.begin_command_buffer(
                 renderer.command_buffers[renderer.current_frame],
                 &begin_info,
             ).unwrap();
             
+            if let Some(gpu_profiler) = &mut self.gpu_profiler {
+                gpu_profiler.begin_frame(
+                    &renderer.device,
+                    renderer.command_buffers[renderer.current_frame],
+                    renderer.current_frame,
+                );
+            }
+            
+            if let Some(lens_flare) = &mut self.lens_flare {
+                lens_flare.begin_frame(
+                    &renderer.device,
+                    renderer.command_buffers[renderer.current_frame],
+                    renderer.current_frame,
+                );
+            }
+            
+            if let Some(async_compute) = &self.async_compute {
+                async_compute.begin_graphics_timing(
+                    &renderer.device,
+                    renderer.command_buffers[renderer.current_frame],
+                    renderer.current_frame,
+                );
+            } else if let Some(particles) = &mut self.particles {
+                // No async queue: simulate up front on the graphics queue
+                if let Some(gpu_profiler) = &mut self.gpu_profiler {
+                    gpu_profiler.begin_zone(
+                        &renderer.device,
+                        renderer.command_buffers[renderer.current_frame],
+                        renderer.current_frame,
+                        "Particle simulation",
+                    );
+                }
+                particles.simulate(&renderer.device, renderer.command_buffers[renderer.current_frame], delta);
+                compute::memory_barrier(
+                    &renderer.device,
+                    renderer.command_buffers[renderer.current_frame],
+                    Access::COMPUTE_WRITE,
+                    Access::VERTEX_SHADER_READ,
+                );
+                if let Some(gpu_profiler) = &mut self.gpu_profiler {
+                    gpu_profiler.end_zone(&renderer.device, renderer.command_buffers[renderer.current_frame], renderer.current_frame);
+                }
+            }
+            
             // Get camera controller
-            let (camera_pos, camera_yaw, camera_pitch, camera_fov) = {
+            let (camera_pos, camera_yaw, camera_pitch, projection) = {
                 let camera = self.world.resource::<CameraController>();
-                (camera.position, camera.yaw, camera.pitch, camera.fov)
+                let projection = self.world.resource::<letterbox::AspectLock>().fit(camera.projection(), renderer.swapchain_extent);
+                (camera.position, camera.yaw, camera.pitch, projection)
             };
             
             // Get object scales
-            let (gltf_scale, gltf_min_y) = {
+            let (gltf_scale, model_origin, compute_skinning, mesh_shading, gpu_driven) = {
                 let objects = self.world.resource::<SceneObjects>();
-                (objects.gltf_scale, objects.gltf_min_y)
+                (
+                    objects.gltf_scale,
+                    objects.model_origin(),
+                    objects.compute_skinning,
+                    objects.mesh_shading,
+                    objects.gpu_driven,
+                )
             };
 
             let shadow_settings = *self.world.resource::<ShadowSettings>();
 
-            // Put the duck on the ground plane (Y=0). Account for user scale.
-            let duck_pos = glam::Vec3::new(0.0, -gltf_min_y * gltf_scale, 0.0);
-            let duck_pos = duck_pos + glam::Vec3::new(0.0, 0.001, 0.0);
+            // Each instance stands on the ground plane, then goes wherever its entity was
+            // moved. The primary one's transform goes through the uniforms.
+            let primary = primary_model(&self.world);
+            let model_transform = primary.map_or_else(Transform::new, |(_, transform)| transform);
+            let duck_pos = model_origin + model_transform.position;
+            // A mesh is on the layers it, its nodes and its instance's root are all on
+            let mesh_parts: Vec<(Entity, usize, bool, RenderLayers)> = self
+                .world
+                .query::<(Entity, &GltfMeshPart, &InheritedVisibility, &RenderLayers)>()
+                .iter(&self.world)
+                .map(|(entity, part, shown, &layers)| {
+                    let (mut root, mut layers) = (entity, layers);
+                    while let Some(parent) = self.world.get::<hierarchy::Parent>(root) {
+                        root = parent.0;
+                        if let Some(&ancestor_layers) = self.world.get::<RenderLayers>(root) {
+                            layers = layers.intersection(ancestor_layers);
+                        }
+                    }
+                    (root, part.index, shown.0, layers)
+                })
+                .collect();
+            let mut instance_meshes: std::collections::HashMap<Entity, Vec<(usize, RenderLayers)>> = std::collections::HashMap::new();
+            for &(root, index, shown, layers) in &mesh_parts {
+                if shown {
+                    instance_meshes.entry(root).or_default().push((index, layers));
+                }
+            }
+            let instances: Vec<ModelInstance> = self
+                .world
+                .get_resource::<Scene>()
+                .map_or(&[][..], |scene| scene.instances.get(1..).unwrap_or_default())
+                .iter()
+                .filter_map(|&root| {
+                    let transform = self.world.get::<Transform>(root)?;
+                    Some(ModelInstance {
+                        model: GltfRenderer::model_matrix(model_origin + transform.position, transform.rotation, gltf_scale),
+                        meshes: instance_meshes.remove(&root).unwrap_or_default(),
+                    })
+                })
+                .collect();
+            let main_layers = main_camera_layers(&mut self.world);
             
             // Draw glTF model with its own pipeline and depth buffer
             if let Some(gltf_renderer) = &mut self.gltf_renderer {
+                if let Err(e) = gltf_renderer.set_shader_variant(&renderer.device, shadow_settings.shader_variant()) {
+                    eprintln!("Failed to build glTF pipeline variant: {}", e);
+                }
+                if let Some(skinning) = &mut gltf_renderer.skinning {
+                    skinning.enabled = compute_skinning;
+                }
+                if let Some(meshlets) = &mut gltf_renderer.meshlets {
+                    meshlets.enabled = mesh_shading;
+                }
+                if let Some(pass) = &mut gltf_renderer.gpu_driven {
+                    pass.enabled = gpu_driven;
+                }
+                if let Some(pass) = &mut gltf_renderer.instance_culling {
+                    pass.enabled = gpu_driven;
+                }
+                // Without a primary instance left, nothing stands at the model matrix
+                if primary.is_none() {
+                    for mesh_index in 0..gltf_renderer.meshes.len() {
+                        gltf_renderer.set_mesh_visible(mesh_index, false);
+                    }
+                }
+                for (root, mesh_index, visible, layers) in mesh_parts {
+                    if primary.is_some_and(|(primary, _)| primary == root) {
+                        gltf_renderer.set_mesh_visible(mesh_index, visible);
+                        gltf_renderer.set_mesh_layers(mesh_index, layers);
+                    }
+                }
+                let impostor_settings = *self.world.resource::<ImpostorSettings>();
+                gltf_renderer.impostor_distance = impostor_settings.enabled.then_some(impostor_settings.distance);
+                if let Err(e) = gltf_renderer.set_instances(renderer, instances) {
+                    eprintln!("Failed to build instanced glTF pipelines: {}", e);
+                }
+                gltf_renderer.layers = main_layers;
+                
+                let planar_settings = *self.world.resource::<PlanarReflectionSettings>();
+                let post_settings = *self.world.resource::<PostEffectSettings>();
+                gltf_renderer.animate(delta);
+                gltf_renderer.dither_params = post_effects::dither_uniforms(post_settings.dither, renderer.swapchain_format);
+                gltf_renderer.weather_params = {
+                    let mut weather = self.world.resource_mut::<weather::Weather>();
+                    weather.accumulate(delta);
+                    weather.surface_uniforms()
+                };
+                gltf_renderer.planar_reflection_params = match &self.planar_reflection {
+                    Some(_) => planar_reflection::PlanarReflection::uniforms(
+                        planar_settings.strength,
+                        renderer.swapchain_extent,
+                    ),
+                    None => [0.0; 4],
+                };
+                gltf_renderer.ssgi_params = self.ssgi.as_ref().map_or([0.0; 4], |ssgi| ssgi.uniforms());
+                
                 // Update uniform buffer
                 if let Err(e) = gltf_renderer.update_uniform_buffer(
+                    renderer,
                     renderer.current_frame,
-                    duck_pos,
-                    camera_pos,
-                    camera_yaw,
-                    camera_pitch,
-                    camera_fov,
-                    gltf_scale,
-                    aspect_ratio,
-                    shadow_settings.debug_cascades,
-                    shadow_settings.softness,
-                    shadow_settings.use_pcss,
-                    shadow_settings.use_shadow_taa,
+                    GltfRenderer::model_matrix(duck_pos, model_transform.rotation, gltf_scale),
+                    &ViewCamera::new(camera_pos, camera_yaw, camera_pitch, projection, aspect_ratio),
+                    shadow_settings.uniforms(),
                 ) {
                     eprintln!("Failed to update glTF uniform buffer: {}", e);
                 }
                 
-                // Render glTF (this starts its own render pass with depth)
-                gltf_renderer.render(
-                    &renderer.device,
-                    renderer.command_buffers[renderer.current_frame],
-                    renderer.swapchain_extent,
-                    image_index,
-                    renderer.current_frame,
-                );
+                // The mirrored view goes first, the scene pass samples it
+                if let Some(reflection) = &mut self.planar_reflection {
+                    if let Some(gpu_profiler) = &mut self.gpu_profiler {
+                        gpu_profiler.begin_zone(
+                            &renderer.device,
+                            renderer.command_buffers[renderer.current_frame],
+                            renderer.current_frame,
+                            "Planar reflection",
+                        );
+                    }
+                    let view_camera = ViewCamera::new(camera_pos, camera_yaw, camera_pitch, projection, aspect_ratio);
+                    reflection.render(
+                        &renderer.device,
+                        renderer.command_buffers[renderer.current_frame],
+                        gltf_renderer,
+                        renderer.current_frame,
+                        &view_camera,
+                        (shadow_settings.debug_cascades, shadow_settings.softness, shadow_settings.use_pcss),
+                    );
+                    if let Some(gpu_profiler) = &mut self.gpu_profiler {
+                        gpu_profiler.end_zone(&renderer.device, renderer.command_buffers[renderer.current_frame], renderer.current_frame);
+                    }
+                }
+                
+                // Render glTF (this starts its own render pass with depth)
+                if let Some(gpu_profiler) = &mut self.gpu_profiler {
+                    gpu_profiler.begin_zone(
+                        &renderer.device,
+                        renderer.command_buffers[renderer.current_frame],
+                        renderer.current_frame,
+                        "Scene",
+                    );
+                }
+                gltf_renderer.render(
+                    &renderer.device,
+                    renderer.command_buffers[renderer.current_frame],
+                    renderer.swapchain_extent,
+                    image_index,
+                    renderer.current_frame,
+                );
+                
+                // Draw ECS cubes and particles into the same pass (shares the scene depth buffer)
+                let mut encoder = CommandEncoder::new(&renderer.device, renderer.command_buffers[renderer.current_frame]);
+                let mut pass = encoder.continue_render_pass(gltf_renderer.render_pass);
+                if let Some(cube_renderer) = &mut self.cube_renderer {
+                    let instances = self.world.resource::<CubeInstances>();
+                    if let Err(e) = cube_renderer.update_camera(
+                        renderer,
+                        renderer.current_frame,
+                        camera_pos,
+                        camera_yaw,
+                        camera_pitch,
+                        projection.matrix(aspect_ratio),
+                    ) {
+                        eprintln!("Failed to update cube uniform buffer: {}", e);
+                    }
+                    // Far cubes go to the impostors
+                    let impostor_settings = *self.world.resource::<ImpostorSettings>();
+                    let split = self.impostors.as_ref().filter(|_| impostor_settings.enabled).map(|_| {
+                        impostor::split_by_distance(&instances.transforms, camera_pos, impostor_settings.distance)
+                    });
+                    let near = split.as_ref().map_or(&instances.transforms[..], |(near, _)| &near[..]);
+                    if let Err(e) = cube_renderer.update_instances(
+                        renderer,
+                        renderer.current_frame,
+                        near,
+                    ) {
+                        eprintln!("Failed to update cube instances: {}", e);
+                    }
+                    cube_renderer.draw_instanced(
+                        renderer,
+                        &mut pass,
+                        renderer.swapchain_extent,
+                        renderer.current_frame,
+                    );
+                    
+                    if let Some(impostors) = &mut self.impostors {
+                        let far = split.as_ref().map_or(&[][..], |(_, far)| &far[..]);
+                        if let Err(e) = impostors.update_instances(renderer, renderer.current_frame, far) {
+                            eprintln!("Failed to update impostor instances: {}", e);
+                        }
+                        let camera = ViewCamera::new(camera_pos, camera_yaw, camera_pitch, projection, aspect_ratio);
+                        impostors.draw(
+                            &mut pass,
+                            renderer.swapchain_extent,
+                            renderer.current_frame,
+                            camera.view_proj(),
+                            camera.position,
+                        );
+                    }
+                }
+                
+                if let Some(vegetation) = &mut self.vegetation {
+                    vegetation.sync(renderer, &mut self.world);
+                    let camera = ViewCamera::new(camera_pos, camera_yaw, camera_pitch, projection, aspect_ratio);
+                    let time = self.world.resource::<FrameTiming>().elapsed;
+                    vegetation.draw(
+                        &mut pass,
+                        renderer.swapchain_extent,
+                        camera.view_proj(),
+                        camera.position,
+                        self.world.resource::<weather::Weather>(),
+                        time,
+                    );
+                }
+                
+                // Blended particles go here unless they get their own half-resolution pass below
+                let half_resolution = self.half_res.is_some() && self.world.resource::<TransparencySettings>().half_resolution;
+                if !half_resolution {
+                    let camera = ViewCamera::new(camera_pos, camera_yaw, camera_pitch, projection, aspect_ratio);
+                    if let Some(particles) = &self.particles {
+                        particles.draw(&mut pass, renderer.swapchain_extent, camera.view, camera.view_proj());
+                    }
+                    if let Some(weather) = &self.weather {
+                        weather.draw(&mut pass, renderer.swapchain_extent, camera.position, camera.view_proj());
+                    }
+                }
+                
+                // After everything that can hide the sun
+                if let Some(lens_flare) = &mut self.lens_flare {
+                    let camera = ViewCamera::new(camera_pos, camera_yaw, camera_pitch, projection, aspect_ratio);
+                    if let Some(sun) = lens_flare::sun_position(&camera.view_proj()) {
+                        lens_flare.query(&renderer.device, &mut pass, renderer.current_frame, sun, renderer.swapchain_extent);
+                    }
+                }
+                drop(pass);
                 
                 // End glTF render pass
                 gltf_renderer.end_render_pass(
@@ -730,6 +3180,118 @@ impl App {
                     renderer.command_buffers[renderer.current_frame],
                     image_index,
                 );
+                
+                // Bounce light from the frame just rendered, for the next frame's ambient term
+                if let Some(ssgi) = &mut self.ssgi {
+                    let camera = ViewCamera::new(camera_pos, camera_yaw, camera_pitch, projection, aspect_ratio);
+                    ssgi.record(renderer, gltf_renderer, image_index, camera.view_proj(), camera.proj);
+                }
+                
+                // Step the weather against the depth just rendered, for the next frame to draw
+                if let Some(weather_particles) = &mut self.weather {
+                    let camera = ViewCamera::new(camera_pos, camera_yaw, camera_pitch, projection, aspect_ratio);
+                    weather_particles.simulate(
+                        &renderer.device,
+                        renderer.command_buffers[renderer.current_frame],
+                        gltf_renderer,
+                        image_index,
+                        self.world.resource::<weather::Weather>(),
+                        camera.position,
+                        camera.view_proj(),
+                        delta,
+                    );
+                }
+                
+                // The particles at half resolution, upsampled onto the frame
+                if let (true, Some(half_res)) = (half_resolution, &mut self.half_res) {
+                    let camera = ViewCamera::new(camera_pos, camera_yaw, camera_pitch, projection, aspect_ratio);
+                    let extent = half_res::half_extent(renderer.swapchain_extent);
+                    let mut encoder = CommandEncoder::new(&renderer.device, renderer.command_buffers[renderer.current_frame]);
+                    let pass = half_res.begin(renderer, &mut encoder, gltf_renderer, image_index);
+                    if let Some(mut pass) = pass {
+                        if let Some(particles) = &self.particles {
+                            particles.draw(&mut pass, extent, camera.view, camera.view_proj());
+                        }
+                        if let Some(weather) = &self.weather {
+                            weather.draw(&mut pass, extent, camera.position, camera.view_proj());
+                        }
+                        drop(pass);
+                        half_res.composite(renderer, image_index, camera.proj);
+                    }
+                }
+                if let Some(gpu_profiler) = &mut self.gpu_profiler {
+                    gpu_profiler.end_zone(&renderer.device, renderer.command_buffers[renderer.current_frame], renderer.current_frame);
+                }
+                
+                // Shadow + opaque done; this is the span async compute should overlap
+                if let Some(async_compute) = &mut self.async_compute {
+                    async_compute.end_graphics_timing(
+                        &renderer.device,
+                        renderer.command_buffers[renderer.current_frame],
+                        renderer.current_frame,
+                    );
+                }
+            } else if let Some(cube_renderer) = &mut self.cube_renderer {
+                // No scene pass to draw the ECS cubes into: they go into the renderer's own
+                // pass over the sky, back to front since it has no depth buffer
+                let command_buffer = renderer.command_buffers[renderer.current_frame];
+                compute::image_barrier(
+                    &renderer.device,
+                    command_buffer,
+                    renderer.swapchain_images[image_index as usize],
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                    Access::COLOR_ATTACHMENT_WRITE,
+                    Access::COLOR_ATTACHMENT_WRITE,
+                );
+                if let Err(e) = cube_renderer.update_camera(
+                    renderer,
+                    renderer.current_frame,
+                    camera_pos,
+                    camera_yaw,
+                    camera_pitch,
+                    projection.matrix(aspect_ratio),
+                ) {
+                    eprintln!("Failed to update cube uniform buffer: {}", e);
+                }
+                let mut transforms = self.world.resource::<CubeInstances>().transforms.clone();
+                let distance = |model: &glam::Mat4| model.w_axis.truncate().distance_squared(camera_pos);
+                transforms.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+                if let Err(e) = cube_renderer.update_instances(renderer, renderer.current_frame, &transforms) {
+                    eprintln!("Failed to update cube instances: {}", e);
+                }
+                
+                let mut encoder = CommandEncoder::new(&renderer.device, command_buffer);
+                let mut pass = encoder.begin_render_pass(
+                    renderer.render_pass,
+                    renderer.framebuffers[image_index as usize],
+                    renderer.swapchain_extent,
+                    &[],
+                );
+                let sky = gltf_renderer::SKY_COLOR;
+                let full = vk::Rect2D { offset: vk::Offset2D::default(), extent: renderer.swapchain_extent };
+                pass.clear_color_rects(0, [sky[0], sky[1], sky[2], 1.0], &[full]);
+                cube_renderer.draw_instanced(renderer, &mut pass, renderer.swapchain_extent, renderer.current_frame);
+            }
+            
+            // Custom passes draw over the scene, under the UI
+            let camera = ViewCamera::new(camera_pos, camera_yaw, camera_pitch, projection, aspect_ratio);
+            renderer.run_passes(image_index, camera.position, camera.view, camera.proj);
+            
+            // Sun effects: light shafts, then flares over them
+            if let Some(sun) = lens_flare::sun_position(&camera.view_proj()) {
+                if let (Some(god_rays), Some(gltf_renderer)) = (&self.god_rays, &self.gltf_renderer) {
+                    god_rays.record(renderer, gltf_renderer, image_index, sun);
+                }
+                if let Some(lens_flare) = &self.lens_flare {
+                    lens_flare.record(renderer, image_index, sun);
+                }
+            }
+            
+            // Grain and vignette over everything but the UI
+            if let Some(post_effects) = &mut self.post_effects {
+                let settings = *self.world.resource::<PostEffectSettings>();
+                post_effects.record(renderer, image_index, settings.grain, settings.vignette);
             }
             
             // Render egui (in the old render pass for overlays)
@@ -753,40 +3315,621 @@ impl App {
                         renderables: self.world.query::<&Renderable>().iter(&self.world).count(),
                     };
                     
-                    let current_gltf_scale = {
+                    let time_control = *self.world.resource::<time_control::TimeControl>();
+                    let (camera_fov, orthographic, ortho_height) = {
+                        let camera = self.world.resource::<CameraController>();
+                        (camera.target_fov, camera.orthographic, camera.ortho_height)
+                    };
+                    let (current_gltf_scale, cube_spawn_count, compute_skinning, mesh_shading, gpu_driven) = {
                         let objects = self.world.resource::<SceneObjects>();
-                        objects.gltf_scale
+                        (
+                            objects.gltf_scale,
+                            objects.cube_spawn_count,
+                            objects.compute_skinning,
+                            objects.mesh_shading,
+                            objects.gpu_driven,
+                        )
                     };
+                    let skinned_mesh_count = self
+                        .gltf_renderer
+                        .as_ref()
+                        .and_then(|g| g.skinning.as_ref())
+                        .map_or(0, |skinning| skinning.meshes.len());
+                    let animation = self.gltf_renderer.as_ref().and_then(|g| g.animation.as_ref());
+                    let animation_clips = animation.map_or_else(Vec::new, |animation| animation.clip_names());
+                    let (animation_clip, animation_playing, animation_speed, animation_time, animation_duration) = animation
+                        .map_or((None, false, 1.0, 0.0, 0.0), |animation| {
+                            (animation.clip(), animation.playing, animation.speed, animation.time, animation.duration())
+                        });
+                    let meshlet_count = self
+                        .gltf_renderer
+                        .as_ref()
+                        .and_then(|g| g.meshlets.as_ref())
+                        .map_or(0, |meshlets| meshlets.meshlet_count());
+                    let (gpu_driven_meshes, gpu_driven_calls, gpu_driven_culling) = self
+                        .gltf_renderer
+                        .as_ref()
+                        .and_then(|g| g.gpu_driven.as_ref())
+                        .map_or((0, 0, false), |pass| (pass.mesh_indices.len(), pass.buckets.len(), pass.culling));
+                    let memory_report = renderer.allocator.lock().generate_report();
+                    let cube_count = self.world.resource::<CubeInstances>().transforms.len();
+                    let cubes_visible = self
+                        .world
+                        .query_filtered::<&Visibility, (With<SpinningCube>, Without<hierarchy::Parent>)>()
+                        .iter(&self.world)
+                        .any(|visibility| visibility.is_visible());
+                    let scene_tree = scene_tree(&mut self.world);
+                    let ray_traced_shadows_supported = self
+                        .gltf_renderer
+                        .as_ref()
+                        .is_some_and(|g| g.acceleration_structure.is_some());
+
+                    let skeleton_settings = *self.world.resource::<SkeletonDebugSettings>();
+                    if let Some(gltf) = self.gltf_renderer.as_ref().filter(|_| skeleton_settings.visible) {
+                        if let Some(skinning) = &gltf.skinning {
+                            let mut debug_draw = self.world.resource_mut::<DebugDraw>();
+                            skinning.draw_skeletons(&mut debug_draw, gltf.duck_model, skeleton_settings.labels);
+                        }
+                    }
+                    let material_mesh_count = self.gltf_renderer.as_ref().map_or(0, |g| g.meshes.len());
+                    let material_selected_mesh =
+                        self.world.resource::<MaterialEditor>().selected_mesh.min(material_mesh_count.saturating_sub(1));
+                    let selected_material = self
+                        .gltf_renderer
+                        .as_ref()
+                        .filter(|_| material_mesh_count > 0)
+                        .map(|g| g.mesh_material(material_selected_mesh));
+                    let material_editor_open = self.world.resource::<MaterialEditor>().open;
+                    let material_preview = self
+                        .material_preview
+                        .as_ref()
+                        .filter(|preview| material_editor_open && preview.is_rendered())
+                        .map(|preview| preview.texture_id);
+                    let material_save_target = self
+                        .gltf_renderer
+                        .as_ref()
+                        .filter(|_| material_mesh_count > 0)
+                        .and_then(|g| g.scene_material_index(material_selected_mesh))
+                        .zip(self.scene_path.as_ref().filter(|path| path.extension().is_some_and(|e| e == "gltf")))
+                        .map(|(index, path)| format!("material {} of {}", index, path.display()));
 
                     let shadow_settings = *self.world.resource::<ShadowSettings>();
+                    if let Some(gltf) = self.gltf_renderer.as_ref().filter(|_| shadow_settings.show_frustums) {
+                        let mut debug_draw = self.world.resource_mut::<DebugDraw>();
+                        for (light_view_proj, color) in gltf.light_view_proj.iter().zip(CASCADE_COLORS) {
+                            debug_draw.frustum(*light_view_proj, 1.0, color);
+                        }
+                        for secondary in self.secondary_windows.values() {
+                            let camera = secondary.camera(projection.fov);
+                            debug_draw.frustum(camera.view_proj(), CAMERA_GIZMO_DEPTH, egui::Color32::WHITE);
+                        }
+                    }
+                    let main_layers = main_camera_layers(&mut self.world);
+                    let debug_overlay = if main_layers.intersects(RenderLayers::GIZMOS) {
+                        self.world.resource::<DebugDraw>().overlay(camera.view_proj())
+                    } else {
+                        debug_draw::Overlay::default()
+                    };
+
+                    let redraw_settings = *self.world.resource::<RedrawSettings>();
+                    let still_settings = *self.world.resource::<StillSettings>();
+                    
+                    let monitors: Vec<_> = window.available_monitors().collect();
+                    let selected_monitor = self.display.monitor(window);
+                    let video_modes = selected_monitor.as_ref().map(display::video_modes).unwrap_or_default();
                     
                     let ui_data = UiData {
                         fps,
                         frame_time_ms,
+                        render_on_demand: redraw_settings.on_demand,
+                        idle_rate_hz: redraw_settings.idle_rate_hz,
+                        async_compute: self.async_compute.as_ref().map(|a| a.stats),
+                        draw_history: self.draw_history.frames().copied().collect(),
+                        pipeline_stats: self
+                            .gltf_renderer
+                            .as_ref()
+                            .and_then(|g| g.pipeline_statistics.as_ref())
+                            .and_then(|stats| stats.last),
                         entity_count,
                         component_counts,
                         vulkan_version: renderer.vulkan_version.clone(),
+                        swapchain_images: renderer.swapchain_images.len(),
+                        frames_in_flight: renderer.frames_in_flight,
+                        present_mode: renderer.present_mode_config,
+                        present_mode_negotiated: format!("{:?}", renderer.present_mode),
                         gpu_name: renderer.gpu_name.clone(),
+                        camera_fov,
+                        orthographic,
+                        ortho_height,
+                        aspect_lock: *self.world.resource::<letterbox::AspectLock>(),
+                        time_scale: time_control.scale,
+                        time_paused: time_control.paused,
                         gltf_scale: current_gltf_scale,
+                        trackball: self.world.resource::<Trackball>().enabled,
+                        model_instances: self.world.get_resource::<Scene>().map_or(0, |scene| scene.instances.len()),
+                        ground: self.gltf_renderer.as_ref().map(|g| g.ground_style),
+                        cube_count,
+                        cubes_visible,
+                        scene_tree,
+                        main_layers,
+                        view_layers: self.view_layers,
+                        cube_spawn_count,
+                        skinned_mesh_count,
+                        compute_skinning,
+                        skeleton_debug: skeleton_settings.visible,
+                        joint_labels: skeleton_settings.labels,
+                        animation_clips,
+                        animation_clip,
+                        animation_playing,
+                        animation_speed,
+                        animation_time,
+                        animation_duration,
+                        debug_overlay,
+                        meshlet_count,
+                        mesh_shading,
+                        gpu_driven_meshes,
+                        gpu_driven_calls,
+                        gpu_driven_culling,
+                        gpu_driven,
+                        material_editor_open,
+                        material_mesh_count,
+                        material_selected_mesh,
+                        material: selected_material.map(|(material, _)| material),
+                        material_overridden: selected_material.is_some_and(|(_, overridden)| overridden),
+                        material_preview,
+                        material_save_target,
                         shadow_debug_cascades: shadow_settings.debug_cascades,
+                        shadow_show_frustums: shadow_settings.show_frustums,
                         shadow_softness: shadow_settings.softness,
                         shadow_use_pcss: shadow_settings.use_pcss,
                         shadow_use_taa: shadow_settings.use_shadow_taa,
+                        shadow_samples: shadow_settings.samples,
+                        light_count: shadow_settings.light_count,
+                        ray_traced_shadows_supported,
+                        shadow_ray_traced: shadow_settings.ray_traced,
+                        ray_traced_ao: shadow_settings.ray_traced_ao,
+                        ao_rays: shadow_settings.ao_rays,
+                        monitors: monitors.iter().map(display::monitor_label).collect(),
+                        monitor_index: selected_monitor
+                            .as_ref()
+                            .and_then(|selected| monitors.iter().position(|monitor| monitor == selected))
+                            .unwrap_or(0),
+                        video_modes: video_modes.iter().map(|mode| VideoModeKey::of(mode).label()).collect(),
+                        video_mode_index: self
+                            .display
+                            .video_mode
+                            .and_then(|key| video_modes.iter().position(|mode| VideoModeKey::of(mode) == key))
+                            .map_or(0, |index| index + 1),
+                        exclusive_fullscreen: self.display.mode == FullscreenMode::Exclusive,
+                        fullscreen: window.fullscreen().is_some(),
+                        still_available: self.gltf_renderer.is_some(),
+                        export_available: self.gltf_renderer.is_some() && self.scene_path.is_some(),
+                        still_scale: still_settings.scale,
+                        still_samples: still_settings.samples,
+                        panorama_width: still_settings.panorama_width,
+                        light_probes_available: self.gltf_renderer.is_some(),
+                        light_probe_count: self.gltf_renderer.as_ref().map_or(0, |g| g.light_probes.probes.len()),
+                        light_probes_baked: self.gltf_renderer.as_ref().map_or(0, |g| g.light_probes.baked_count()),
+                        light_probe_radius: self.world.resource::<LightProbeSettings>().radius,
+                        light_probe_intensity: self.gltf_renderer.as_ref().map_or(1.0, |g| g.light_probes.intensity),
+                        reflection_probe_count: self.gltf_renderer.as_ref().map_or(0, |g| g.reflection_probes.probes.len()),
+                        reflection_probes_baked: self.gltf_renderer.as_ref().map_or(0, |g| g.reflection_probes.baked_count()),
+                        reflection_probe_extents: self.world.resource::<ReflectionProbeSettings>().half_extents.to_array(),
+                        reflection_probe_intensity: self
+                            .gltf_renderer
+                            .as_ref()
+                            .map_or(1.0, |g| g.reflection_probes.intensity),
+                        planar_reflection_enabled: self.world.resource::<PlanarReflectionSettings>().enabled,
+                        planar_reflection_scale: self.world.resource::<PlanarReflectionSettings>().resolution_scale,
+                        planar_reflection_strength: self.world.resource::<PlanarReflectionSettings>().strength,
+                        toon: self.gltf_renderer.as_ref().map_or_else(toon::ToonStyle::default, |g| g.toon),
+                        dither: self.world.resource::<PostEffectSettings>().dither,
+                        grain: self.world.resource::<PostEffectSettings>().grain,
+                        vignette: self.world.resource::<PostEffectSettings>().vignette,
+                        lens_flare: self.lens_flare.as_ref().map(|l| l.style),
+                        sun_visibility: self.lens_flare.as_ref().map_or(0.0, |l| l.visibility()),
+                        god_rays: self.god_rays.as_ref().map(|g| g.style),
+                        ssgi: self.ssgi.as_ref().map(|s| s.style),
+                        weather: self.weather.as_ref().map(|_| *self.world.resource::<weather::Weather>()),
+                        half_res_transparency: self.half_res.as_ref().map(|_| self.world.resource::<TransparencySettings>().half_resolution),
+                        lightmap_available: self.gltf_renderer.is_some(),
+                        lightmap_baked: self.gltf_renderer.as_ref().is_some_and(|g| g.lightmap.baked),
+                        lightmap_enabled: self.gltf_renderer.as_ref().is_some_and(|g| g.lightmap.enabled),
+                        lightmap_bake_progress: self.lightmap_baker.as_ref().map(lightmap::LightmapBaker::progress),
+                        lightmap_uncharted_meshes: self.gltf_renderer.as_ref().map_or(0, |g| g.lightmap.uncharted_meshes),
+                        gpu_memory_allocated: memory_report.total_allocated_bytes,
+                        gpu_memory_reserved: memory_report.total_capacity_bytes,
+                        gpu_memory_blocks: memory_report.blocks.len(),
+                        defrag_enabled: self.defragmenter.enabled,
+                        defrag: self.defragmenter.stats,
+                        texture_streaming: self
+                            .gltf_renderer
+                            .as_ref()
+                            .and_then(|g| g.texture_streamer.as_ref())
+                            .map(texture_streaming::TextureStreamer::stats),
+                        streaming_enabled: self.world.resource::<TextureStreamingSettings>().enabled,
+                        streaming_budget_mb: self.world.resource::<TextureStreamingSettings>().budget_mb,
+                        texture_filter: *self.world.resource::<texture_streaming::TextureFilter>(),
+                        max_anisotropy: renderer.max_anisotropy,
+                        virtual_texture: self.gltf_renderer.as_ref().and_then(|g| g.virtual_texture.summary()),
+                        texture_arrays: self.gltf_renderer.as_ref().and_then(|g| g.texture_arrays.summary()),
+                        stress_count: self.world.resource::<SceneObjects>().stress_count,
+                        stress_running: self.stress_run.is_some() || self.stress_requested.is_some(),
+                        stress_report: self.stress_report.as_ref().map(stress::StressReport::summary),
+                        impostors_available: self.impostors.is_some() || self.gltf_renderer.is_some(),
+                        impostors_enabled: self.world.resource::<ImpostorSettings>().enabled,
+                        impostor_distance: self.world.resource::<ImpostorSettings>().distance,
+                        impostor_count: self.impostors.as_ref().map_or(0, |impostors| impostors.instance_count)
+                            + self.gltf_renderer.as_ref().map_or(0, |gltf_renderer| gltf_renderer.impostor_count()),
                     };
 
                     let (full_output, ui_changes) = egui_int.build_ui(window, &ui_data);
+                    self.egui_repaint_at = full_output
+                        .viewport_output
+                        .get(&egui::ViewportId::ROOT)
+                        .and_then(|viewport| Instant::now().checked_add(viewport.repaint_delay));
+
+                    if let Some(on_demand) = ui_changes.render_on_demand {
+                        self.world.resource_mut::<RedrawSettings>().on_demand = on_demand;
+                    }
 
                     if let Some(new_gltf_scale) = ui_changes.gltf_scale {
                         let mut objects = self.world.resource_mut::<SceneObjects>();
                         objects.gltf_scale = new_gltf_scale;
                     }
 
+                    if let Some(fov) = ui_changes.camera_fov {
+                        self.world.resource_mut::<CameraController>().target_fov = fov;
+                    }
+                    if let Some(height) = ui_changes.ortho_height {
+                        self.world.resource_mut::<CameraController>().ortho_height = height;
+                    }
+                    {
+                        let mut time_control = self.world.resource_mut::<time_control::TimeControl>();
+                        if let Some(paused) = ui_changes.time_paused {
+                            time_control.paused = paused;
+                        }
+                        if let Some(scale) = ui_changes.time_scale {
+                            time_control.set_scale(scale);
+                        }
+                        if ui_changes.time_step {
+                            time_control.step();
+                        }
+                    }
+                    if let Some(config) = ui_changes.present_mode {
+                        self.present_mode_requested = Some(config);
+                    }
+                    if let Some(aspect_lock) = ui_changes.aspect_lock {
+                        *self.world.resource_mut::<letterbox::AspectLock>() = aspect_lock;
+                    }
+                    if let Some(orthographic) = ui_changes.orthographic {
+                        // The model keeps its size on screen
+                        let focus = self
+                            .gltf_renderer
+                            .as_ref()
+                            .map_or(glam::Vec3::ZERO, |g| g.duck_model.w_axis.truncate());
+                        let mut camera = self.world.resource_mut::<CameraController>();
+                        let focus_distance = camera.position.distance(focus);
+                        camera.set_orthographic(orthographic, focus_distance);
+                    }
+
+                    if let Some(enabled) = ui_changes.trackball {
+                        let mut trackball = self.world.resource_mut::<Trackball>();
+                        trackball.enabled = enabled;
+                        trackball.release();
+                    }
+                    if ui_changes.reset_model_transform {
+                        if let Some((entity, _)) = primary_model(&self.world) {
+                            self.world.entity_mut(entity).insert(Transform::new());
+                        }
+                    }
+                    if ui_changes.spawn_model_instance {
+                        spawn_model_instance(&mut self.world, self.gltf_renderer.as_ref().map(|g| g.model_bounds));
+                    }
+                    if ui_changes.despawn_model_instance {
+                        let last = self.world.get_resource::<Scene>().and_then(|scene| scene.instances.last().copied());
+                        if let Some(entity) = last {
+                            self.world.commands().despawn_model(entity);
+                            self.world.flush();
+                        }
+                    }
+                    if let Some((entity, visible)) = ui_changes.visibility {
+                        if let Some(mut visibility) = self.world.get_mut::<Visibility>(entity) {
+                            *visibility = Visibility::new(visible);
+                        }
+                    }
+                    if let Some(visible) = ui_changes.cubes_visible {
+                        set_cubes_visible(&mut self.world, visible);
+                    }
+                    if let Some((entity, layers)) = ui_changes.entity_layers {
+                        if let Some(mut entity_layers) = self.world.get_mut::<RenderLayers>(entity) {
+                            *entity_layers = layers;
+                        }
+                    }
+                    if let Some(layers) = ui_changes.main_layers {
+                        for mut camera_layers in self
+                            .world
+                            .query_filtered::<&mut RenderLayers, With<Camera>>()
+                            .iter_mut(&mut self.world)
+                        {
+                            *camera_layers = layers;
+                        }
+                    }
+                    if let Some(layers) = ui_changes.view_layers {
+                        self.view_layers = layers;
+                    }
+
+                    if let (Some(gltf), Some(style)) = (&mut self.gltf_renderer, ui_changes.ground) {
+                        // The ground's vertex colors are rewritten in place
+                        let _ = renderer.device.device_wait_idle();
+                        gltf.set_ground_style(style);
+                    }
+
+                    if let Some(count) = ui_changes.cube_spawn_count {
+                        let mut objects = self.world.resource_mut::<SceneObjects>();
+                        objects.cube_spawn_count = count;
+                    }
+                    
+                    if let Some(enabled) = ui_changes.compute_skinning {
+                        let mut objects = self.world.resource_mut::<SceneObjects>();
+                        objects.compute_skinning = enabled;
+                    }
+                    
+                    if let Some(open) = ui_changes.material_editor_open {
+                        self.world.resource_mut::<MaterialEditor>().open = open;
+                    }
+                    
+                    if let Some(mesh) = ui_changes.material_selected_mesh {
+                        self.world.resource_mut::<MaterialEditor>().selected_mesh = mesh;
+                    }
+                    
+                    if let Some(gltf_renderer) = &mut self.gltf_renderer {
+                        if let Some(material) = ui_changes.material_override {
+                            gltf_renderer.set_material_override(material_selected_mesh, Some(material));
+                        } else if ui_changes.reset_material {
+                            gltf_renderer.set_material_override(material_selected_mesh, None);
+                        }
+                        
+                        let material_index = gltf_renderer.scene_material_index(material_selected_mesh);
+                        if let (true, Some(index), Some(path)) = (ui_changes.save_material, material_index, &self.scene_path) {
+                            let (material, _) = gltf_renderer.mesh_material(material_selected_mesh);
+                            match material_preview::save_material(path, index, &material) {
+                                Ok(()) => println!("✓ Saved material {} to {}", index, path.display()),
+                                Err(e) => eprintln!("✗ Failed to save material {}: {}", index, e),
+                            }
+                        }
+                    }
+                    
+                    if let Some(visible) = ui_changes.skeleton_debug {
+                        self.world.resource_mut::<SkeletonDebugSettings>().visible = visible;
+                    }
+                    
+                    if let Some(labels) = ui_changes.joint_labels {
+                        self.world.resource_mut::<SkeletonDebugSettings>().labels = labels;
+                    }
+                    
+                    if let Some(animation) = self.gltf_renderer.as_mut().and_then(|g| g.animation.as_mut()) {
+                        if let Some(clip) = ui_changes.animation_clip {
+                            animation.set_clip(clip);
+                        }
+                        if let Some(playing) = ui_changes.animation_playing {
+                            animation.playing = playing;
+                        }
+                        if let Some(speed) = ui_changes.animation_speed {
+                            animation.speed = speed;
+                        }
+                        if let Some(time) = ui_changes.animation_time {
+                            animation.time = time;
+                        }
+                    }
+                    
+                    if let Some(enabled) = ui_changes.mesh_shading {
+                        let mut objects = self.world.resource_mut::<SceneObjects>();
+                        objects.mesh_shading = enabled;
+                    }
+                    
+                    if let Some(enabled) = ui_changes.gpu_driven {
+                        let mut objects = self.world.resource_mut::<SceneObjects>();
+                        objects.gpu_driven = enabled;
+                    }
+                    
+                    if ui_changes.spawn_cubes {
+                        let count = self.world.resource::<SceneObjects>().cube_spawn_count;
+                        spawn_cubes(&mut self.world, count);
+                    }
+                    
+                    if ui_changes.despawn_cubes {
+                        despawn_cubes(&mut self.world);
+                    }
+                    
+                    if let Some(count) = ui_changes.stress_count {
+                        self.world.resource_mut::<SceneObjects>().stress_count = count;
+                    }
+                    if ui_changes.start_stress_test {
+                        self.stress_requested = Some(self.world.resource::<SceneObjects>().stress_count);
+                    }
+                    if let Some(enabled) = ui_changes.impostors_enabled {
+                        self.world.resource_mut::<ImpostorSettings>().enabled = enabled;
+                    }
+                    if let Some(distance) = ui_changes.impostor_distance {
+                        self.world.resource_mut::<ImpostorSettings>().distance = distance;
+                    }
+                    
+                    if ui_changes.open_window {
+                        self.open_window_requested = true;
+                    }
+                    
+                    let mut display_changed = false;
+                    if let Some(index) = ui_changes.monitor_index {
+                        self.display.monitor = monitors.get(index).and_then(|monitor| monitor.name());
+                        self.display.video_mode = None; // Modes are per monitor
+                        display_changed = true;
+                    }
+                    if let Some(index) = ui_changes.video_mode_index {
+                        // Entry 0 is "best available"
+                        self.display.video_mode = index
+                            .checked_sub(1)
+                            .and_then(|index| video_modes.get(index))
+                            .map(VideoModeKey::of);
+                        display_changed = true;
+                    }
+                    if let Some(exclusive) = ui_changes.exclusive_fullscreen {
+                        self.display.mode = if exclusive { FullscreenMode::Exclusive } else { FullscreenMode::Borderless };
+                        display_changed = true;
+                    }
+                    if display_changed {
+                        if let Err(e) = self.display.save() {
+                            eprintln!("⚠ Failed to save display settings: {}", e);
+                        }
+                        // Already fullscreen: move to the new monitor / mode right away
+                        if window.fullscreen().is_some() {
+                            window.set_fullscreen(self.display.fullscreen(window));
+                        }
+                    }
+                    if ui_changes.toggle_fullscreen {
+                        self.display.toggle_fullscreen(window);
+                    }
+                    
+                    if let Some(scale) = ui_changes.still_scale {
+                        self.world.resource_mut::<StillSettings>().scale = scale;
+                    }
+                    if let Some(samples) = ui_changes.still_samples {
+                        self.world.resource_mut::<StillSettings>().samples = samples;
+                    }
+                    if ui_changes.render_still {
+                        self.still_requested = true;
+                    }
+                    if let Some(width) = ui_changes.panorama_width {
+                        self.world.resource_mut::<StillSettings>().panorama_width = width;
+                    }
+                    if ui_changes.capture_panorama {
+                        self.panorama_requested = true;
+                    }
+                    if let Some(format) = ui_changes.export_scene {
+                        self.export_requested = Some(format);
+                    }
+                    if ui_changes.save_diagnostics {
+                        self.diagnostics_requested = true;
+                    }
+
+                    if let Some(radius) = ui_changes.light_probe_radius {
+                        self.world.resource_mut::<LightProbeSettings>().radius = radius;
+                    }
+                    if let Some(gltf_renderer) = &mut self.gltf_renderer {
+                        if let Some(intensity) = ui_changes.light_probe_intensity {
+                            gltf_renderer.light_probes.intensity = intensity;
+                        }
+                        if ui_changes.place_light_probe {
+                            gltf_renderer.light_probes.probes.push(light_probes::LightProbe {
+                                position: self.world.resource::<CameraController>().position,
+                                radius: self.world.resource::<LightProbeSettings>().radius,
+                                irradiance: None,
+                            });
+                            println!("📍 Placed light probe {} (bake to use it)", gltf_renderer.light_probes.probes.len());
+                        }
+                        if ui_changes.clear_light_probes {
+                            gltf_renderer.light_probes.probes.clear();
+                        }
+                        if let Some(intensity) = ui_changes.reflection_probe_intensity {
+                            gltf_renderer.reflection_probes.intensity = intensity;
+                        }
+                        if ui_changes.place_reflection_probe {
+                            gltf_renderer.reflection_probes.probes.push(reflection_probes::ReflectionProbe {
+                                position: self.world.resource::<CameraController>().position,
+                                half_extents: self.world.resource::<ReflectionProbeSettings>().half_extents,
+                                baked: false,
+                            });
+                            println!(
+                                "📍 Placed reflection probe {} (bake to use it)",
+                                gltf_renderer.reflection_probes.probes.len()
+                            );
+                        }
+                        if ui_changes.clear_reflection_probes {
+                            gltf_renderer.reflection_probes.probes.clear();
+                        }
+                        if let Some(style) = ui_changes.toon {
+                            gltf_renderer.toon = style;
+                        }
+                    }
+                    if ui_changes.bake_light_probes {
+                        self.probe_bake_requested = true;
+                    }
+                    if let Some(extents) = ui_changes.reflection_probe_extents {
+                        self.world.resource_mut::<ReflectionProbeSettings>().half_extents = glam::Vec3::from(extents);
+                    }
+                    if ui_changes.bake_reflection_probes {
+                        self.reflection_bake_requested = true;
+                    }
+                    {
+                        let mut planar = self.world.resource_mut::<PlanarReflectionSettings>();
+                        if let Some(enabled) = ui_changes.planar_reflection_enabled {
+                            planar.enabled = enabled;
+                        }
+                        if let Some(scale) = ui_changes.planar_reflection_scale {
+                            planar.resolution_scale = scale;
+                        }
+                        if let Some(strength) = ui_changes.planar_reflection_strength {
+                            planar.strength = strength;
+                        }
+                    }
+                    if let (Some(lens_flare), Some(style)) = (&mut self.lens_flare, ui_changes.lens_flare) {
+                        lens_flare.style = style;
+                    }
+                    if let (Some(god_rays), Some(style)) = (&mut self.god_rays, ui_changes.god_rays) {
+                        god_rays.style = style;
+                    }
+                    if let (Some(ssgi), Some(style)) = (&mut self.ssgi, ui_changes.ssgi) {
+                        ssgi.style = style;
+                    }
+                    if let Some(weather) = ui_changes.weather {
+                        *self.world.resource_mut::<weather::Weather>() = weather;
+                    }
+                    if let Some(enabled) = ui_changes.half_res_transparency {
+                        self.world.resource_mut::<TransparencySettings>().half_resolution = enabled;
+                    }
+                    {
+                        let mut post = self.world.resource_mut::<PostEffectSettings>();
+                        if let Some(dither) = ui_changes.dither {
+                            post.dither = dither;
+                        }
+                        if let Some(grain) = ui_changes.grain {
+                            post.grain = grain;
+                        }
+                        if let Some(vignette) = ui_changes.vignette {
+                            post.vignette = vignette;
+                        }
+                    }
+                    if let (Some(enabled), Some(gltf_renderer)) = (ui_changes.lightmap_enabled, &mut self.gltf_renderer) {
+                        gltf_renderer.lightmap.enabled = enabled;
+                    }
+                    if ui_changes.bake_lightmap {
+                        self.lightmap_bake_requested = true;
+                    }
+                    if let Some(enabled) = ui_changes.defrag_enabled {
+                        self.defragmenter.enabled = enabled;
+                    }
+                    if let Some(enabled) = ui_changes.streaming_enabled {
+                        self.world.resource_mut::<TextureStreamingSettings>().enabled = enabled;
+                    }
+                    if let Some(budget_mb) = ui_changes.streaming_budget_mb {
+                        self.world.resource_mut::<TextureStreamingSettings>().budget_mb = budget_mb;
+                    }
+                    if let Some(filter) = ui_changes.texture_filter {
+                        *self.world.resource_mut::<texture_streaming::TextureFilter>() = filter;
+                    }
+
                     if ui_changes.shadow_settings_changed {
                         let mut s = self.world.resource_mut::<ShadowSettings>();
                         s.debug_cascades = ui_changes.shadow_debug_cascades;
                         s.softness = ui_changes.shadow_softness;
                         s.use_pcss = ui_changes.shadow_use_pcss;
                         s.use_shadow_taa = ui_changes.shadow_use_taa;
+                        s.samples = ui_changes.shadow_samples;
+                        s.light_count = ui_changes.light_count;
+                        s.ray_traced = ui_changes.shadow_ray_traced;
+                        s.ray_traced_ao = ui_changes.ray_traced_ao;
+                        s.ao_rays = ui_changes.ao_rays;
+                        s.show_frustums = ui_changes.shadow_show_frustums;
                     }
 
                     // Keep Vulkan font atlas in sync with egui
@@ -794,14 +3937,7 @@ impl App {
                         // Wait for device idle before updating textures
                         let _ = renderer.device.device_wait_idle();
                     }
-                    egui_vk.update_textures(
-                        &renderer.device,
-                        &renderer.instance,
-                        renderer.physical_device,
-                        renderer.graphics_queue,
-                        renderer.graphics_queue_family_index,
-                        &full_output.textures_delta,
-                    );
+                    egui_vk.update_textures(renderer, &full_output.textures_delta);
 
                     let clipped_primitives = egui_int.ctx.tessellate(
                         full_output.shapes,
@@ -813,40 +3949,51 @@ impl App {
                         color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 0.0] },
                     }];
                     
-                    let render_pass_info = vk::RenderPassBeginInfo::default()
-                        .render_pass(renderer.render_pass)
-                        .framebuffer(renderer.framebuffers[image_index as usize])
-                        .render_area(vk::Rect2D {
-                            offset: vk::Offset2D { x: 0, y: 0 },
-                            extent: renderer.swapchain_extent,
-                        })
-                        .clear_values(&clear_values);
-                    
-                    renderer.device.cmd_begin_render_pass(
-                        renderer.command_buffers[renderer.current_frame],
-                        &render_pass_info,
-                        vk::SubpassContents::INLINE,
+                    if let Some(gpu_profiler) = &mut self.gpu_profiler {
+                        gpu_profiler.begin_zone(
+                            &renderer.device,
+                            renderer.command_buffers[renderer.current_frame],
+                            renderer.current_frame,
+                            "egui",
+                        );
+                    }
+                    let mut encoder = CommandEncoder::new(&renderer.device, renderer.command_buffers[renderer.current_frame]);
+                    let mut pass = encoder.begin_render_pass(
+                        renderer.render_pass,
+                        renderer.framebuffers[image_index as usize],
+                        renderer.swapchain_extent,
+                        &clear_values,
                     );
-                    
+                    let bars = self.world.resource::<letterbox::AspectLock>().bars(renderer.swapchain_extent);
+                    pass.clear_color_rects(0, [0.0, 0.0, 0.0, 1.0], &bars);
                     egui_vk.render(
-                        &renderer.device,
-                        renderer.command_buffers[renderer.current_frame],
-                        renderer.swapchain_extent.width,
-                        renderer.swapchain_extent.height,
+                        &mut pass,
+                        &renderer.frame_arena,
+                        renderer.current_frame,
+                        renderer.swapchain_extent,
                         clipped_primitives,
                         full_output.pixels_per_point,
                     );
-                    
-                    renderer.device.cmd_end_render_pass(renderer.command_buffers[renderer.current_frame]);
+                    drop(pass);
+                    if let Some(gpu_profiler) = &mut self.gpu_profiler {
+                        gpu_profiler.end_zone(&renderer.device, renderer.command_buffers[renderer.current_frame], renderer.current_frame);
+                    }
                 }
             }
             
             // End command buffer
             renderer.device.end_command_buffer(renderer.command_buffers[renderer.current_frame]).unwrap();
+            record_scope.end();
             
             // Submit command buffer
-            let wait_semaphores = [renderer.image_available_semaphores[renderer.current_frame]];
-            let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+            let submit_scope = profiling::scope("Submit");
+            let mut wait_semaphores = vec![renderer.image_available_semaphores[renderer.current_frame]];
+            let mut wait_stages = vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+            if let Some(semaphore) = compute_wait_semaphore {
+                // Last frame's particle state is read by the particle vertex shader
+                wait_semaphores.push(semaphore);
+                wait_stages.push(vk::PipelineStageFlags::VERTEX_SHADER);
+            }
             let command_buffers = [renderer.command_buffers[renderer.current_frame]];
             let signal_semaphores = [renderer.render_finished_semaphores[renderer.current_frame]];
             
@@ -861,8 +4008,11 @@ impl App {
                 &[submit_info],
                 renderer.in_flight_fences[renderer.current_frame],
             ).unwrap();
+            submit_scope.end();
+            self.draw_history.push(draw_stats::take());
             
             // Present
+            let present_scope = profiling::scope("Present");
             let swapchains = [renderer.swapchain];
             let image_indices = [image_index];
             let present_info = vk::PresentInfoKHR::default()
@@ -874,6 +4024,7 @@ impl App {
                 renderer.present_queue,
                 &present_info,
             );
+            present_scope.end();
             
             // Check if we need to recreate swapchain
             let should_recreate = match present_result {
@@ -902,8 +4053,66 @@ impl App {
                 }
             }
             
-            renderer.current_frame = (renderer.current_frame + 1) % renderer::MAX_FRAMES_IN_FLIGHT;
+            renderer.advance_frame();
+        }
+        
+        {
+            let _scope = profiling::scope("Secondary windows");
+            self.render_secondary_windows();
+        }
+        
+        if self.still_requested {
+            self.still_requested = false;
+            self.render_still();
+        }
+        if self.panorama_requested {
+            self.panorama_requested = false;
+            self.render_panorama();
+        }
+        if let Some(format) = self.export_requested.take() {
+            self.export_scene(format);
+        }
+        if let Some(config) = self.present_mode_requested.take() {
+            self.set_present_mode(config);
+        }
+        if self.diagnostics_requested {
+            self.diagnostics_requested = false;
+            self.save_diagnostics();
+        }
+        if self.probe_bake_requested {
+            self.probe_bake_requested = false;
+            self.bake_light_probes();
+        }
+        if self.reflection_bake_requested {
+            self.reflection_bake_requested = false;
+            self.bake_reflection_probes();
         }
+        if self.lightmap_bake_requested {
+            self.lightmap_bake_requested = false;
+            self.start_lightmap_bake();
+        }
+        if self.lightmap_baker.is_some() {
+            let _scope = profiling::scope("Lightmap bake");
+            self.step_lightmap_bake();
+        }
+        if self.aov_dir.is_some() {
+            let _scope = profiling::scope("AOV export");
+            self.export_aovs();
+        }
+        self.update_planar_reflection();
+        self.update_ssgi();
+        {
+            let _scope = profiling::scope("Defragmentation");
+            self.defragment_memory();
+        }
+        {
+            let _scope = profiling::scope("Texture streaming");
+            self.stream_textures();
+        }
+        if self.world.resource::<MaterialEditor>().open {
+            self.update_material_preview();
+        }
+        profiling::frame_mark();
         
         // Update window title
         let stats = self.world.resource::<PerformanceStats>();
@@ -923,7 +4132,76 @@ impl App {
                     egui_vk.cleanup(&renderer.device);
                 }
                 
+                for (_, mut secondary) in self.secondary_windows.drain() {
+                    if let Some(gltf_renderer) = &self.gltf_renderer {
+                        let _ = gltf_renderer.destroy_view(renderer, secondary.view);
+                    }
+                    secondary.surface.destroy(renderer);
+                }
+                
+                if let Some(cube_renderer) = &mut self.cube_renderer {
+                    cube_renderer.cleanup(renderer);
+                }
+                
+                if let Some(particles) = &mut self.particles {
+                    particles.cleanup(renderer);
+                }
+                
+                if let Some(async_compute) = &mut self.async_compute {
+                    async_compute.destroy(renderer);
+                }
+                
+                if let Some(gpu_profiler) = &mut self.gpu_profiler {
+                    gpu_profiler.destroy(renderer);
+                }
+                
+                if let Some(post_effects) = &mut self.post_effects {
+                    post_effects.destroy(renderer);
+                }
+                
+                if let Some(lens_flare) = &mut self.lens_flare {
+                    lens_flare.destroy(renderer);
+                }
+                
+                if let Some(god_rays) = &mut self.god_rays {
+                    god_rays.destroy(renderer);
+                }
+                
+                if let Some(weather) = &mut self.weather {
+                    weather.destroy(renderer);
+                }
+                
+                if let Some(half_res) = &mut self.half_res {
+                    half_res.destroy(renderer);
+                }
+                
+                if let Some(vegetation) = &mut self.vegetation {
+                    vegetation.destroy(renderer);
+                }
+                
+                if let Some(impostors) = &mut self.impostors {
+                    impostors.destroy(renderer);
+                }
+                
+                if let Some(mut baker) = self.lightmap_baker.take() {
+                    baker.destroy(renderer);
+                }
+                
+                self.defragmenter.destroy(renderer);
+                
                 if let Some(gltf_renderer) = &mut self.gltf_renderer {
+                    if let Some(mut pass) = self.aov_pass.take() {
+                        pass.destroy(renderer, gltf_renderer);
+                    }
+                    if let Some(mut preview) = self.material_preview.take() {
+                        preview.destroy(renderer, gltf_renderer);
+                    }
+                    if let Some(mut reflection) = self.planar_reflection.take() {
+                        reflection.destroy(renderer, gltf_renderer);
+                    }
+                    if let Some(mut ssgi) = self.ssgi.take() {
+                        ssgi.destroy(renderer, gltf_renderer);
+                    }
                     gltf_renderer.cleanup(renderer);
                 }
             }
@@ -942,8 +4220,54 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }));
     
-    let event_loop = EventLoop::new()?;
+    profiling::start();
+    
     let mut app = App::new();
+    let mut args = std::env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--stress" => match args.next_if(|next| !next.starts_with("--")).map(|count| count.parse::<u32>()) {
+                Some(Ok(count)) => app.stress_requested = Some(count),
+                _ => eprintln!("⚠ --stress needs an object count, e.g. --stress 10000"),
+            },
+            "--bake-lightmap" => {
+                app.lightmap_charts = true;
+                app.lightmap_bake_requested = true;
+            }
+            "--export-aovs" => {
+                // Optional directory; the next argument unless it is another flag
+                let dir = args.next_if(|next| !next.starts_with("--")).unwrap_or_else(|| "aovs".to_string());
+                app.aov_dir = Some(dir.into());
+            }
+            "--swapchain-images" => match args.next_if(|next| !next.starts_with("--")).map(|count| count.parse::<u32>()) {
+                Some(Ok(count)) => app.renderer_builder = app.renderer_builder.swapchain_images(count),
+                _ => eprintln!("⚠ --swapchain-images needs a count, 2 for double or 3 for triple buffering"),
+            },
+            "--frames-in-flight" => match args.next_if(|next| !next.starts_with("--")).map(|count| count.parse::<usize>()) {
+                Some(Ok(count)) => app.renderer_builder = app.renderer_builder.frames_in_flight(count),
+                _ => eprintln!("⚠ --frames-in-flight needs a count from 1 to {}", renderer::MAX_FRAMES_IN_FLIGHT),
+            },
+            "--present-mode" => match args.next_if(|next| !next.starts_with("--")).map(|name| swapchain::PresentModeConfig::from_name(&name)) {
+                Some(Some(config)) => app.renderer_builder = app.renderer_builder.present_mode(config),
+                _ => eprintln!("⚠ --present-mode needs fifo (vsync), mailbox or immediate"),
+            },
+            "--validation" => app.renderer_builder = app.renderer_builder.validation(true),
+            "--grass-mask" => match args.next_if(|next| !next.starts_with("--")) {
+                Some(path) => match vegetation::DensityMask::load(std::path::Path::new(&path)) {
+                    Ok(mask) => app.world.insert_resource(GrassMask(std::sync::Arc::new(mask))),
+                    Err(e) => eprintln!("⚠ Failed to load grass mask {}: {}", path, e),
+                },
+                None => eprintln!("⚠ --grass-mask needs an image, white where grass grows"),
+            },
+            "--environment" => match args.next_if(|next| !next.starts_with("--")) {
+                Some(path) if hdr_image::is_hdr(std::path::Path::new(&path)) => app.environment = Some(path.into()),
+                _ => eprintln!("⚠ --environment needs an equirectangular .hdr or .exr image"),
+            },
+            _ => eprintln!("⚠ Ignoring unknown argument: {}", arg),
+        }
+    }
+    
+    let event_loop = EventLoop::new()?;
     event_loop.run_app(&mut app)?;
     Ok(())
 }
@@ -0,0 +1,476 @@
+//! Materials
+//!
+//! A `Material` is what a mesh is shaded with apart from its geometry: the shader
+//! permutation its features select, its factors and the scene texture it samples. The
+//! `MaterialRegistry` owns the scene's materials and what drawing them takes on the GPU:
+//! a uniform block of factors per material in one shared buffer, a descriptor set per
+//! material (set 1 of the scene pipeline layouts, see gltf.frag) and the scene pipeline
+//! of every shader variant and permutation in use. Meshes refer to their material with a
+//! `MaterialHandle` instead of copying its values into their vertices.
+//!
+//! With `VK_KHR_push_descriptor` there are no per-material sets: the set layout is a push
+//! descriptor layout and each draw pushes its material's uniform block straight into the
+//! command buffer. Only set 1 is pushed. Set 0, the scene's, has too many bindings for
+//! some devices' push descriptor limit and changes only on resize and probe bakes.
+//!
+//! Binding 1 of set 1 is the material's base color texture when that didn't fit the
+//! texture arrays (see `texture_array`), and a white placeholder otherwise.
+//!
+//! Handle 0 is the default material (white, fully rough, opaque), used by the ground and
+//! by meshes without a material. After the scene's materials come one override slot per
+//! mesh, which a mesh switches to while its material is edited from the UI.
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+use crate::command_encoder::RenderPassEncoder;
+use crate::gltf_loader::{AlphaMode, GltfMaterial, GltfScene};
+use crate::gltf_renderer::{GltfPermutation, GltfShaderVariant};
+use crate::renderer::VulkanRenderer;
+use crate::shader_reflection::{self, ShaderReflection};
+use crate::texture_array::TextureArrays;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub permutation: GltfPermutation, // Material features only
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub alpha_cutoff: f32, // Read by the ALPHA_MASK permutation only
+    pub double_sided: bool,
+    pub base_color_texture: Option<usize>, // Scene texture index
+    pub emissive: [f32; 3],
+    pub emissive_strength: f32,
+    pub emissive_texture: Option<usize>, // Scene texture index, sampled from the texture arrays
+    pub occlusion_texture: Option<usize>, // Likewise
+    pub occlusion_strength: f32,
+    pub toon: bool, // Cel shaded and outlined, see `toon`
+}
+
+impl Material {
+    pub fn from_gltf(material: &GltfMaterial) -> Self {
+        Self {
+            permutation: GltfPermutation::for_material(material),
+            base_color: material.base_color,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            alpha_cutoff: if material.alpha_mode == AlphaMode::Mask { material.alpha_cutoff } else { 0.0 },
+            double_sided: material.double_sided,
+            base_color_texture: material.base_color_texture_index,
+            emissive: material.emissive_factor,
+            emissive_strength: material.emissive_strength,
+            emissive_texture: material.emissive_texture_index,
+            occlusion_texture: material.occlusion_texture_index,
+            occlusion_strength: material.occlusion_strength,
+            toon: false,
+        }
+    }
+
+    /// Whether everything but the texture matches `other`, so draws of the two can share a
+    /// material binding when their textures come from the texture arrays
+    pub fn differs_only_in_texture(&self, other: &Material) -> bool {
+        self.permutation == other.permutation
+            && self.base_color == other.base_color
+            && self.metallic == other.metallic
+            && self.roughness == other.roughness
+            && self.alpha_cutoff == other.alpha_cutoff
+            && self.double_sided == other.double_sided
+            && self.emissive == other.emissive
+            && self.emissive_strength == other.emissive_strength
+            && self.emissive_texture == other.emissive_texture
+            && self.occlusion_texture == other.occlusion_texture
+            && self.occlusion_strength == other.occlusion_strength
+            && self.toon == other.toon
+    }
+}
+
+/// Runtime edit of a mesh's material factors, see `MaterialRegistry::set_override`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MaterialOverride {
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    pub emissive_strength: f32,
+    pub use_texture: bool, // Sample the material's texture, or the first scene texture without one
+    pub toon: bool,
+}
+
+impl MaterialOverride {
+    /// Start from the current values of `material`
+    pub fn of(material: &Material) -> Self {
+        Self {
+            base_color: material.base_color,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            emissive: material.emissive,
+            emissive_strength: material.emissive_strength,
+            use_texture: material.base_color_texture.is_some(),
+            toon: material.toon,
+        }
+    }
+
+    /// glTF `material` with these factors, as `material_preview::save_material` writes them:
+    /// turning the texture off drops the base color texture, toon shading is left out
+    pub fn apply_to_gltf(&self, material: &GltfMaterial) -> GltfMaterial {
+        GltfMaterial {
+            base_color: self.base_color,
+            metallic: self.metallic,
+            roughness: self.roughness,
+            emissive_factor: self.emissive,
+            emissive_strength: self.emissive_strength,
+            base_color_texture_index: material.base_color_texture_index.filter(|_| self.use_texture),
+            ..material.clone()
+        }
+    }
+
+    /// `material` with these factors
+    fn apply(&self, material: &Material) -> Material {
+        Material {
+            base_color: self.base_color,
+            metallic: self.metallic,
+            roughness: self.roughness,
+            emissive: self.emissive,
+            emissive_strength: self.emissive_strength,
+            base_color_texture: self.use_texture.then_some(material.base_color_texture.unwrap_or(0)),
+            toon: self.toon,
+            ..material.clone()
+        }
+    }
+}
+
+/// Index of a material in its `MaterialRegistry`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MaterialHandle(u32);
+
+impl MaterialHandle {
+    pub const DEFAULT: Self = Self(0);
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// Must match MaterialParams in shaders/gltf.frag + shaders/aov.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MaterialParams {
+    base_color: [f32; 4],
+    metallic_roughness: [f32; 4], // x = metallic, y = roughness, z = 1 for cel shading
+    emissive: [f32; 4],           // rgb = factor * strength, a = occlusion strength
+    texture_slots: [u32; 4],      // x = emissive, y = occlusion texture array slot, 0 for none;
+                                  // z = 1 to sample the base color from set 1
+}
+
+pub struct MaterialRegistry {
+    materials: Vec<Material>, // Default, scene materials, then one override slot per mesh
+    texture_slots: Vec<u32>,  // Texture array slot per scene texture, see `TextureArrays::slot`
+    unpacked_textures: Vec<bool>, // Per scene texture, whether set 1 binds it
+    image_textures: Vec<Option<usize>>, // Per material, the base color texture set 1 binds
+    images: Vec<vk::DescriptorImageInfo>, // Per material, what set 1 binds at binding 1
+    scene_material_count: usize, // Including the default material
+    pub set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>, // Per material; empty with push descriptors
+    push_descriptor: Option<ash::khr::push_descriptor::Device>, // Where supported
+    params_buffer: vk::Buffer,
+    params_allocation: Option<Allocation>,
+    params_stride: u64,
+    pipelines: HashMap<(GltfShaderVariant, GltfPermutation), vk::Pipeline>,
+}
+
+impl MaterialRegistry {
+    /// Register the default material, every material of `scene` and an override slot for
+    /// each of its meshes. The set layout is reflected from set 1 of `stages`. Emissive and
+    /// occlusion textures are sampled from `texture_arrays`, and left out when not packed;
+    /// base color textures it couldn't pack are bound in each material's set.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        scene: &GltfScene,
+        texture_arrays: &TextureArrays,
+        stages: &[&ShaderReflection],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let mut materials: Vec<Material> = std::iter::once(Material::from_gltf(&GltfMaterial::default()))
+            .chain(scene.materials.iter().map(Material::from_gltf))
+            .collect();
+        let scene_material_count = materials.len();
+        materials.resize(scene_material_count + scene.meshes.len(), materials[0].clone());
+        let texture_slots: Vec<u32> = (0..scene.textures.len()).map(|i| texture_arrays.slot(Some(i))).collect();
+        let unpacked_textures: Vec<bool> =
+            (0..scene.textures.len()).map(|i| texture_arrays.is_unpacked(Some(i))).collect();
+        // An override slot keeps the texture of its mesh's scene material, or drops it
+        let image_textures: Vec<Option<usize>> = materials[..scene_material_count]
+            .iter()
+            .map(|material| material.base_color_texture)
+            .chain(scene.meshes.iter().map(|mesh| {
+                mesh.material_index.and_then(|i| materials.get(i + 1)).and_then(|material| material.base_color_texture)
+            }))
+            .collect();
+        let images: Vec<vk::DescriptorImageInfo> =
+            image_textures.iter().map(|&texture| texture_arrays.material_image(texture)).collect();
+
+        let bindings = shader_reflection::set_layout_bindings(stages, 1)?;
+        let push_descriptor = renderer
+            .push_descriptor_supported
+            .then(|| ash::khr::push_descriptor::Device::new(&renderer.instance, device));
+        let set_layout_flags = if push_descriptor.is_some() {
+            vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR
+        } else {
+            vk::DescriptorSetLayoutCreateFlags::empty()
+        };
+        let set_layout = device.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::default().flags(set_layout_flags).bindings(&bindings),
+            None,
+        )?;
+
+        // One block per material, each at an offset the device can bind
+        let alignment = renderer
+            .instance
+            .get_physical_device_properties(renderer.physical_device)
+            .limits
+            .min_uniform_buffer_offset_alignment;
+        let stride = (std::mem::size_of::<MaterialParams>() as u64).next_multiple_of(alignment.max(1));
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(stride * materials.len() as u64)
+            .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let params_buffer = device.create_buffer(&buffer_info, None)?;
+        let requirements = device.get_buffer_memory_requirements(params_buffer);
+        let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: "material_params",
+            requirements,
+            location: MemoryLocation::CpuToGpu,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        })?;
+        device.bind_buffer_memory(params_buffer, allocation.memory(), allocation.offset())?;
+
+        for (i, material) in materials.iter().enumerate() {
+            write_params(&allocation, stride, i, material, &texture_slots, &unpacked_textures);
+        }
+
+        let (descriptor_pool, descriptor_sets) = if push_descriptor.is_some() {
+            (vk::DescriptorPool::null(), Vec::new())
+        } else {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: materials.len() as u32,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: materials.len() as u32,
+                },
+            ];
+            let descriptor_pool = device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .pool_sizes(&pool_sizes)
+                    .max_sets(materials.len() as u32),
+                None,
+            )?;
+            let layouts = vec![set_layout; materials.len()];
+            let descriptor_sets = device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&layouts),
+            )?;
+            for (i, &set) in descriptor_sets.iter().enumerate() {
+                let params_info = vk::DescriptorBufferInfo {
+                    buffer: params_buffer,
+                    offset: i as u64 * stride,
+                    range: std::mem::size_of::<MaterialParams>() as u64,
+                };
+                let write = vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(std::slice::from_ref(&params_info));
+                let image_write = vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(&images[i]));
+                device.update_descriptor_sets(&[write, image_write], &[]);
+            }
+            (descriptor_pool, descriptor_sets)
+        };
+
+        println!("  ✓ Registered {} materials", scene_material_count);
+
+        Ok(Self {
+            materials,
+            texture_slots,
+            unpacked_textures,
+            image_textures,
+            images,
+            scene_material_count,
+            set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            push_descriptor,
+            params_buffer,
+            params_allocation: Some(allocation),
+            params_stride: stride,
+            pipelines: HashMap::new(),
+        })
+    }
+
+    /// Handle of the scene material at `material_index`, or the default material
+    pub fn handle(&self, material_index: Option<usize>) -> MaterialHandle {
+        match material_index {
+            Some(i) if i + 1 < self.scene_material_count => MaterialHandle(i as u32 + 1),
+            _ => MaterialHandle::DEFAULT,
+        }
+    }
+
+    pub fn get(&self, handle: MaterialHandle) -> &Material {
+        &self.materials[handle.index()]
+    }
+
+    /// Bind material `handle` as set number `set` of the pipeline bound to `pass`
+    pub fn bind(&self, pass: &mut RenderPassEncoder, set: u32, handle: MaterialHandle) {
+        let Some(push_descriptor) = &self.push_descriptor else {
+            pass.bind_descriptor_set(set, self.descriptor_sets[handle.index()]);
+            return;
+        };
+        let params_info = self.params_info(handle.index());
+        let writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(std::slice::from_ref(&params_info)),
+            vk::WriteDescriptorSet::default()
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(std::slice::from_ref(&self.images[handle.index()])),
+        ];
+        pass.push_descriptor_set(push_descriptor, set, &writes);
+    }
+
+    /// Point each material's texture binding at what `texture_arrays` holds for it now,
+    /// after its samplers changed. The sets must not be in use.
+    pub unsafe fn write_images(&mut self, device: &ash::Device, texture_arrays: &TextureArrays) {
+        for (i, &texture) in self.image_textures.iter().enumerate() {
+            self.images[i] = texture_arrays.material_image(texture);
+            if let Some(&set) = self.descriptor_sets.get(i) {
+                let write = vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(&self.images[i]));
+                device.update_descriptor_sets(&[write], &[]);
+            }
+        }
+    }
+
+    /// Uniform block of material `index` in the params buffer
+    fn params_info(&self, index: usize) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer: self.params_buffer,
+            offset: index as u64 * self.params_stride,
+            range: std::mem::size_of::<MaterialParams>() as u64,
+        }
+    }
+
+    /// Whether `handle` is a mesh's override slot rather than a scene material
+    pub fn is_override(&self, handle: MaterialHandle) -> bool {
+        handle.index() >= self.scene_material_count
+    }
+
+    /// Fill the override slot of mesh `mesh_index` with `material` edited by
+    /// `material_override` and return its handle. The factors are written in place, so a
+    /// frame still in flight may already draw with them.
+    pub fn set_override(
+        &mut self,
+        mesh_index: usize,
+        material: MaterialHandle,
+        material_override: &MaterialOverride,
+    ) -> MaterialHandle {
+        let index = self.scene_material_count + mesh_index;
+        self.materials[index] = material_override.apply(&self.materials[material.index()]);
+        if let Some(allocation) = &self.params_allocation {
+            let material = &self.materials[index];
+            write_params(allocation, self.params_stride, index, material, &self.texture_slots, &self.unpacked_textures);
+        }
+        MaterialHandle(index as u32)
+    }
+
+    /// Build the scene pipeline of `permutation` for `variant` with `build`, unless it
+    /// already exists. Returns whether it was built.
+    pub fn ensure_pipeline(
+        &mut self,
+        variant: GltfShaderVariant,
+        permutation: GltfPermutation,
+        build: impl FnOnce() -> Result<vk::Pipeline, Box<dyn std::error::Error>>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        match self.pipelines.entry((variant, permutation)) {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(entry) => {
+                entry.insert(build()?);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Scene pipeline of `permutation` for `variant`, or the variant's base permutation
+    /// while it hasn't been built. The base permutation must have been.
+    pub fn pipeline(&self, variant: GltfShaderVariant, permutation: GltfPermutation) -> vk::Pipeline {
+        match self.pipelines.get(&(variant, permutation)) {
+            Some(&pipeline) => pipeline,
+            None => self.pipelines[&(variant, GltfPermutation::default())],
+        }
+    }
+
+    /// The variants and permutations there are pipelines for
+    pub fn pipeline_keys(&self) -> impl Iterator<Item = (GltfShaderVariant, GltfPermutation)> + '_ {
+        self.pipelines.keys().copied()
+    }
+
+    /// Swap in another set of scene pipelines, returning the current ones
+    pub fn replace_pipelines(
+        &mut self,
+        pipelines: HashMap<(GltfShaderVariant, GltfPermutation), vk::Pipeline>,
+    ) -> HashMap<(GltfShaderVariant, GltfPermutation), vk::Pipeline> {
+        std::mem::replace(&mut self.pipelines, pipelines)
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        for (_, pipeline) in self.pipelines.drain() {
+            device.destroy_pipeline(pipeline, None);
+        }
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+        device.destroy_buffer(self.params_buffer, None);
+        if let Some(allocation) = self.params_allocation.take() {
+            let _ = renderer.allocator.lock().free(allocation);
+        }
+    }
+}
+
+/// Write the uniform block of material `index` into the mapped params buffer
+fn write_params(
+    allocation: &Allocation,
+    stride: u64,
+    index: usize,
+    material: &Material,
+    texture_slots: &[u32],
+    unpacked_textures: &[bool],
+) {
+    let slot = |texture: Option<usize>| texture.and_then(|i| texture_slots.get(i).copied()).unwrap_or(0);
+    let unpacked = material.base_color_texture.and_then(|i| unpacked_textures.get(i).copied()).unwrap_or(false);
+    let [r, g, b] = material.emissive.map(|c| c * material.emissive_strength);
+    let params = MaterialParams {
+        base_color: material.base_color,
+        metallic_roughness: [material.metallic, material.roughness, if material.toon { 1.0 } else { 0.0 }, 0.0],
+        emissive: [r, g, b, material.occlusion_strength],
+        texture_slots: [slot(material.emissive_texture), slot(material.occlusion_texture), unpacked as u32, 0],
+    };
+    let mapped = allocation.mapped_ptr().unwrap().as_ptr() as *mut u8;
+    unsafe {
+        std::ptr::write_unaligned(mapped.add(index * stride as usize) as *mut MaterialParams, params);
+    }
+}
@@ -0,0 +1,208 @@
+//! Material editor preview and saving
+//!
+//! The material editor window shows the selected mesh's material on a sphere, drawn by the
+//! scene pass under the current sun, sky and shadow settings into an offscreen target that
+//! egui samples. The sphere floats far above the scene (see `MATERIAL_PREVIEW_CENTER`), so
+//! it is lit but never shadowed. Like stills the preview stalls the GPU, so it is rendered
+//! again only when the material or the shader variant changed.
+//!
+//! `save_material` writes edited factors back into the loaded .gltf file, leaving the rest
+//! of its JSON as it was.
+
+use ash::vk;
+use glam::Vec3;
+use std::path::Path;
+
+use crate::egui_vulkan::EguiVulkanRenderer;
+use crate::gltf_renderer::{GltfRenderer, GltfShaderVariant, GltfView, ViewCamera, MATERIAL_PREVIEW_CENTER};
+use crate::material::MaterialOverride;
+use crate::offscreen::OffscreenTarget;
+use crate::renderer::VulkanRenderer;
+use crate::sampler_cache::SamplerDesc;
+
+/// Edge of the preview image in pixels
+pub const PREVIEW_SIZE: u32 = 256;
+/// Camera position relative to the sphere: in front and a little above, facing the sun
+const CAMERA_OFFSET: Vec3 = Vec3::new(0.0, 1.0, 2.8);
+
+/// Offscreen view of the preview sphere, registered as an egui texture
+pub struct MaterialPreview {
+    target: OffscreenTarget,
+    view: Option<GltfView>,
+    pub texture_id: egui::TextureId,
+    rendered: Option<(usize, MaterialOverride, GltfShaderVariant)>, // What the image shows
+}
+
+impl MaterialPreview {
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        gltf_renderer: &GltfRenderer,
+        egui_vulkan: &mut EguiVulkanRenderer,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let extent = vk::Extent2D { width: PREVIEW_SIZE, height: PREVIEW_SIZE };
+        let mut target = OffscreenTarget::new(renderer, gltf_renderer.render_pass, extent)?;
+        let texture_id = renderer
+            .sampler(SamplerDesc::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE))
+            .map_err(Box::<dyn std::error::Error>::from)
+            .and_then(|sampler| egui_vulkan.register_user_texture(&renderer.device, target.target.color().view, sampler));
+        let texture_id = match texture_id {
+            Ok(texture_id) => texture_id,
+            Err(e) => {
+                target.destroy(renderer);
+                return Err(e);
+            }
+        };
+
+        let mut preview = Self { target, view: None, texture_id, rendered: None };
+        match gltf_renderer.create_view(renderer, extent) {
+            Ok(view) => preview.view = Some(view),
+            Err(e) => {
+                preview.destroy(renderer, gltf_renderer);
+                return Err(e);
+            }
+        }
+        Ok(preview)
+    }
+
+    /// Whether the image shows something yet
+    pub fn is_rendered(&self) -> bool {
+        self.rendered.is_some()
+    }
+
+    pub fn view(&self) -> Option<&GltfView> {
+        self.view.as_ref()
+    }
+
+    /// Render the sphere with mesh `mesh_index`'s current material, unless the image already
+    /// shows it. Waits for the device to go idle first and leaves it idle.
+    pub unsafe fn update(
+        &mut self,
+        renderer: &VulkanRenderer,
+        gltf_renderer: &GltfRenderer,
+        mesh_index: usize,
+        (debug_cascades, shadow_softness, use_pcss): (bool, f32, bool),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (material, _) = gltf_renderer.mesh_material(mesh_index);
+        let shown = Some((mesh_index, material, gltf_renderer.shader_variant));
+        if self.rendered == shown {
+            return Ok(());
+        }
+        let device = &renderer.device;
+        let view = self.view.as_mut().ok_or("material preview view was not created")?;
+
+        let camera = ViewCamera::from_yaw_pitch(
+            MATERIAL_PREVIEW_CENTER + CAMERA_OFFSET,
+            -std::f32::consts::FRAC_PI_2, // Down -Z, at the sphere
+            -CAMERA_OFFSET.y.atan2(CAMERA_OFFSET.z),
+            45f32.to_radians(),
+            1.0,
+        );
+        device.device_wait_idle()?;
+        gltf_renderer.update_view_uniform_buffer(view, 0, &camera, debug_cascades, shadow_softness, use_pcss);
+
+        let cmd_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(renderer.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let cmd = device.allocate_command_buffers(&cmd_info)?[0];
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(cmd, &begin_info)?;
+
+        gltf_renderer.render_material_preview(
+            device,
+            cmd,
+            view,
+            self.target.framebuffer,
+            self.target.target.color().image,
+            mesh_index,
+        );
+
+        device.end_command_buffer(cmd)?;
+        let command_buffers = [cmd];
+        let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+        device.queue_submit(renderer.graphics_queue, &[submit_info], vk::Fence::null())?;
+        device.queue_wait_idle(renderer.graphics_queue)?;
+        device.free_command_buffers(renderer.command_pool, &command_buffers);
+
+        self.rendered = shown;
+        Ok(())
+    }
+
+    /// Destroy the preview. The device must be idle. Its egui texture stays registered,
+    /// so the UI must not show it anymore.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer, gltf_renderer: &GltfRenderer) {
+        if let Some(view) = self.view.take() {
+            let _ = gltf_renderer.destroy_view(renderer, view);
+        }
+        self.target.destroy(renderer);
+    }
+}
+
+/// Extension scaling `emissiveFactor`, which glTF caps at 1
+const EMISSIVE_STRENGTH: &str = "KHR_materials_emissive_strength";
+
+/// Write `material`'s factors into material `material_index` of the .gltf file at `path`.
+/// Turning the texture off removes the material's base color texture; other fields and
+/// files, such as the buffers, are left untouched. An emissive strength other than 1 is
+/// saved with `KHR_materials_emissive_strength`. Toon shading has no glTF equivalent and
+/// isn't saved.
+pub fn save_material(
+    path: &Path,
+    material_index: usize,
+    material: &MaterialOverride,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use gltf::json::Value;
+
+    if path.extension().and_then(|e| e.to_str()) != Some("gltf") {
+        return Err(format!("{} is not a .gltf file", path.display()).into());
+    }
+    let mut root: Value = gltf::json::deserialize::from_str(&std::fs::read_to_string(path)?)?;
+    let entry = root
+        .get_mut("materials")
+        .and_then(|materials| materials.get_mut(material_index))
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| format!("{} has no material {}", path.display(), material_index))?;
+    let pbr = entry
+        .entry("pbrMetallicRoughness")
+        .or_insert_with(|| Value::Object(Default::default()))
+        .as_object_mut()
+        .ok_or("pbrMetallicRoughness is not an object")?;
+
+    pbr.insert("baseColorFactor".into(), Value::from(material.base_color.to_vec()));
+    pbr.insert("metallicFactor".into(), Value::from(material.metallic));
+    pbr.insert("roughnessFactor".into(), Value::from(material.roughness));
+    if !material.use_texture {
+        pbr.remove("baseColorTexture");
+    }
+
+    entry.insert("emissiveFactor".into(), Value::from(material.emissive.to_vec()));
+    let extensions = entry
+        .entry("extensions")
+        .or_insert_with(|| Value::Object(Default::default()))
+        .as_object_mut()
+        .ok_or("extensions is not an object")?;
+    if material.emissive_strength == 1.0 {
+        extensions.remove(EMISSIVE_STRENGTH);
+        if extensions.is_empty() {
+            entry.remove("extensions");
+        }
+    } else {
+        let mut strength = Value::Object(Default::default());
+        strength["emissiveStrength"] = Value::from(material.emissive_strength);
+        extensions.insert(EMISSIVE_STRENGTH.into(), strength);
+        let used = root
+            .as_object_mut()
+            .ok_or("the root is not an object")?
+            .entry("extensionsUsed")
+            .or_insert_with(|| Value::Array(Vec::new()))
+            .as_array_mut()
+            .ok_or("extensionsUsed is not an array")?;
+        if !used.iter().any(|name| name == EMISSIVE_STRENGTH) {
+            used.push(Value::from(EMISSIVE_STRENGTH));
+        }
+    }
+
+    std::fs::write(path, gltf::json::serialize::to_string_pretty(&root)?)?;
+    Ok(())
+}
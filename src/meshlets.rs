@@ -0,0 +1,377 @@
+//! Mesh shader rendering
+//!
+//! On devices with `VK_EXT_mesh_shader` static glTF meshes are split into meshlets of at
+//! most 64 vertices and 124 triangles when the scene is loaded. They are drawn with a
+//! task + mesh shader pipeline: the task shader culls whole meshlets against the view
+//! frustum and by their normal cone before the mesh shader emits the survivors, and the
+//! fragment stage is the regular gltf.frag permutation of the mesh's material.
+//!
+//! Skinned and node-animated meshes (whose bounds and cones would go stale) and the shadow
+//! pass keep using the vertex pipeline. rspirv 0.11 predates `SPV_EXT_mesh_shader`, so the task/mesh
+//! shader interface is declared here by hand instead of being reflected.
+
+use ash::vk;
+use crate::command_encoder::{PipelineBinding, RenderPassEncoder};
+use glam::{Mat4, Vec3};
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+use crate::compute::DescriptorWriter;
+use crate::gltf_loader::{GltfMesh, GltfScene};
+use crate::gltf_renderer::{GltfMeshBuffers, GltfPermutation, GltfShaderVariant};
+use crate::material::MaterialRegistry;
+use crate::renderer::VulkanRenderer;
+use crate::shader_reflection::{self, ShaderReflection};
+use std::collections::HashMap;
+
+pub const MAX_MESHLET_VERTICES: usize = 64;
+pub const MAX_MESHLET_TRIANGLES: usize = 124;
+
+/// Meshlets culled per task shader workgroup (local_size_x in gltf.task)
+const TASK_WORKGROUP_SIZE: u32 = 32;
+
+/// Meshlet stages that read the scene descriptor set (camera UBO at binding 0)
+pub const MESHLET_STAGES: vk::ShaderStageFlags =
+    vk::ShaderStageFlags::from_raw(vk::ShaderStageFlags::TASK_EXT.as_raw() | vk::ShaderStageFlags::MESH_EXT.as_raw());
+
+/// Matches `Meshlet` in gltf.task / gltf.mesh
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Meshlet {
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub cone_axis: [f32; 3],
+    /// Sine of the normal cone's half angle; 1.0 disables cone culling
+    pub cone_cutoff: f32,
+    pub vertex_offset: u32,
+    pub triangle_offset: u32,
+    pub vertex_count: u32,
+    pub triangle_count: u32,
+}
+
+/// gltf.frag's push constants plus the meshlet count for the task shader
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MeshletPushConstants {
+    pub model: [[f32; 4]; 4],
+    pub use_texture: i32,
+    pub alpha_cutoff: f32,
+    pub meshlet_count: u32,
+    pub texture_slot: u32, // See texture_array.rs
+}
+
+/// Meshlets of one mesh. `vertices` maps meshlet vertices to mesh vertices, `triangles`
+/// packs three 8-bit meshlet-local indices per entry.
+#[derive(Default)]
+pub struct MeshletData {
+    pub meshlets: Vec<Meshlet>,
+    pub vertices: Vec<u32>,
+    pub triangles: Vec<u32>,
+}
+
+/// Greedily split `mesh` into meshlets in index order. Double-sided meshes get no normal
+/// cones, since their back faces are visible.
+pub fn build_meshlets(mesh: &GltfMesh, double_sided: bool) -> MeshletData {
+    let position = |index: u32| Vec3::from(mesh.vertices[index as usize].position);
+    let mut data = MeshletData::default();
+    let mut local_index = vec![u8::MAX; mesh.vertices.len()];
+    let mut vertices: Vec<u32> = Vec::new();
+    let mut triangles: Vec<[u8; 3]> = Vec::new();
+
+    let mut flush = |vertices: &mut Vec<u32>, triangles: &mut Vec<[u8; 3]>, local_index: &mut [u8]| {
+        if triangles.is_empty() {
+            return;
+        }
+        let (min, max) = vertices.iter().fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), &v| {
+            (min.min(position(v)), max.max(position(v)))
+        });
+        let center = (min + max) * 0.5;
+        let radius = vertices.iter().map(|&v| position(v).distance(center)).fold(0.0, f32::max);
+
+        let normals: Vec<Vec3> = triangles
+            .iter()
+            .map(|t| {
+                let [a, b, c] = t.map(|i| position(vertices[i as usize]));
+                (b - a).cross(c - a).normalize_or_zero()
+            })
+            .filter(|n| *n != Vec3::ZERO)
+            .collect();
+        let axis = normals.iter().sum::<Vec3>().normalize_or_zero();
+        let min_dot = normals.iter().map(|n| n.dot(axis)).fold(1.0, f32::min);
+        let cone_cutoff = if double_sided || axis == Vec3::ZERO || min_dot <= 0.0 {
+            1.0
+        } else {
+            (1.0 - min_dot * min_dot).sqrt()
+        };
+
+        data.meshlets.push(Meshlet {
+            center: center.to_array(),
+            radius,
+            cone_axis: axis.to_array(),
+            cone_cutoff,
+            vertex_offset: data.vertices.len() as u32,
+            triangle_offset: data.triangles.len() as u32,
+            vertex_count: vertices.len() as u32,
+            triangle_count: triangles.len() as u32,
+        });
+        data.vertices.extend_from_slice(vertices);
+        data.triangles.extend(triangles.iter().map(|t| t[0] as u32 | (t[1] as u32) << 8 | (t[2] as u32) << 16));
+
+        for &v in vertices.iter() {
+            local_index[v as usize] = u8::MAX;
+        }
+        vertices.clear();
+        triangles.clear();
+    };
+
+    for triangle in mesh.indices.chunks_exact(3) {
+        let new_vertices = triangle
+            .iter()
+            .enumerate()
+            .filter(|&(i, &v)| local_index[v as usize] == u8::MAX && !triangle[..i].contains(&v))
+            .count();
+        if vertices.len() + new_vertices > MAX_MESHLET_VERTICES || triangles.len() == MAX_MESHLET_TRIANGLES {
+            flush(&mut vertices, &mut triangles, &mut local_index);
+        }
+
+        let mut local = [0u8; 3];
+        for (slot, &v) in local.iter_mut().zip(triangle) {
+            if local_index[v as usize] == u8::MAX {
+                local_index[v as usize] = vertices.len() as u8;
+                vertices.push(v);
+            }
+            *slot = local_index[v as usize];
+        }
+        triangles.push(local);
+    }
+    flush(&mut vertices, &mut triangles, &mut local_index);
+
+    data
+}
+
+/// GPU meshlets of one scene mesh, bound as set 2 of the meshlet pipelines
+pub struct MeshletMesh {
+    pub mesh_index: usize,
+    pub meshlet_count: u32,
+    pub buffers: Vec<vk::Buffer>, // Meshlets, meshlet vertices, meshlet triangles
+    pub allocations: Vec<Option<Allocation>>,
+    pub descriptor_set: vk::DescriptorSet,
+}
+
+pub struct MeshletPass {
+    pub enabled: bool,
+    pub loader: ash::ext::mesh_shader::Device,
+    pub set_layout: vk::DescriptorSetLayout,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_pool: vk::DescriptorPool,
+    pub meshes: Vec<MeshletMesh>,
+    /// Task + mesh shader pipelines, built by `GltfRenderer` like its vertex pipelines
+    pub pipelines: HashMap<(GltfShaderVariant, GltfPermutation), vk::Pipeline>,
+}
+
+impl MeshletPass {
+    /// Returns `None` without mesh shader support or static meshes. The vertex buffers of
+    /// static meshes in `mesh_buffers` must have been created with `STORAGE_BUFFER` usage.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        scene: &GltfScene,
+        mesh_buffers: &[GltfMeshBuffers],
+        scene_set_layout: vk::DescriptorSetLayout,
+        materials: &MaterialRegistry,
+        scene_frag: &ShaderReflection,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let animated = scene.animated_meshes();
+        let static_meshes: Vec<usize> = scene
+            .meshes
+            .iter()
+            .enumerate()
+            .filter(|&(i, mesh)| (!mesh.has_joints || mesh.skin_index.is_none()) && !animated[i] && !mesh.indices.is_empty())
+            .map(|(i, _)| i)
+            .collect();
+        if !renderer.mesh_shader_supported || static_meshes.is_empty() {
+            return Ok(None);
+        }
+
+        let device = &renderer.device;
+        let storage_binding = |binding: u32, stages: vk::ShaderStageFlags| {
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(stages)
+        };
+        // Vertices, meshlets, meshlet vertices, meshlet triangles (set = 2 in gltf.task/gltf.mesh)
+        let bindings = [
+            storage_binding(0, vk::ShaderStageFlags::MESH_EXT),
+            storage_binding(1, MESHLET_STAGES),
+            storage_binding(2, vk::ShaderStageFlags::MESH_EXT),
+            storage_binding(3, vk::ShaderStageFlags::MESH_EXT),
+        ];
+        let set_layout = device.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings),
+            None,
+        )?;
+
+        let push_constant_size = std::mem::size_of::<MeshletPushConstants>() as u32;
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(&[scene_frag], push_constant_size)?
+            .into_iter()
+            .map(|range| range.stage_flags(range.stage_flags | MESHLET_STAGES))
+            .collect();
+        let set_layouts = [scene_set_layout, materials.set_layout, set_layout];
+        let pipeline_layout = device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(&set_layouts)
+                .push_constant_ranges(&push_constant_ranges),
+            None,
+        )?;
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: (static_meshes.len() * bindings.len()) as u32,
+        }];
+        let descriptor_pool = device.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::default()
+                .pool_sizes(&pool_sizes)
+                .max_sets(static_meshes.len() as u32),
+            None,
+        )?;
+
+        let mut meshes = Vec::new();
+        for mesh_index in static_meshes {
+            let mesh = &scene.meshes[mesh_index];
+            let double_sided = materials.get(mesh_buffers[mesh_index].material).double_sided;
+            let data = build_meshlets(mesh, double_sided);
+
+            let (meshlet_buffer, meshlet_allocation) = create_storage_buffer(renderer, "meshlets", &data.meshlets)?;
+            let (vertex_buffer, vertex_allocation) = create_storage_buffer(renderer, "meshlet_vertices", &data.vertices)?;
+            let (triangle_buffer, triangle_allocation) =
+                create_storage_buffer(renderer, "meshlet_triangles", &data.triangles)?;
+
+            let descriptor_set = device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(std::slice::from_ref(&set_layout)),
+            )?[0];
+            DescriptorWriter::new()
+                .storage_buffer(0, mesh_buffers[mesh_index].vertex_buffer)
+                .storage_buffer(1, meshlet_buffer)
+                .storage_buffer(2, vertex_buffer)
+                .storage_buffer(3, triangle_buffer)
+                .write(device, descriptor_set);
+
+            meshes.push(MeshletMesh {
+                mesh_index,
+                meshlet_count: data.meshlets.len() as u32,
+                buffers: vec![meshlet_buffer, vertex_buffer, triangle_buffer],
+                allocations: vec![Some(meshlet_allocation), Some(vertex_allocation), Some(triangle_allocation)],
+                descriptor_set,
+            });
+        }
+
+        println!(
+            "✓ Mesh shading: {} meshlets across {} static meshes",
+            meshes.iter().map(|m| m.meshlet_count).sum::<u32>(),
+            meshes.len()
+        );
+
+        Ok(Some(Self {
+            enabled: true,
+            loader: ash::ext::mesh_shader::Device::new(&renderer.instance, device),
+            set_layout,
+            pipeline_layout,
+            descriptor_pool,
+            meshes,
+            pipelines: HashMap::new(),
+        }))
+    }
+
+    /// Meshlets to draw `mesh_index` with, if it is static and mesh shading is on
+    pub fn mesh(&self, mesh_index: usize) -> Option<&MeshletMesh> {
+        if !self.enabled {
+            return None;
+        }
+        self.meshes.iter().find(|mesh| mesh.mesh_index == mesh_index)
+    }
+
+    pub fn meshlet_count(&self) -> u32 {
+        self.meshes.iter().map(|mesh| mesh.meshlet_count).sum()
+    }
+
+    /// `pipeline`, one of `pipelines`, as bound through a command encoder recording
+    /// `render_pass`
+    pub fn pipeline_binding(&self, pipeline: vk::Pipeline, render_pass: vk::RenderPass) -> PipelineBinding {
+        PipelineBinding {
+            pipeline,
+            layout: self.pipeline_layout,
+            render_pass,
+            push_constant_size: std::mem::size_of::<MeshletPushConstants>() as u32,
+        }
+    }
+
+    /// Draw one mesh's meshlets. A meshlet pipeline, the scene descriptor set (set 0) and
+    /// the mesh's material (set 1) must already be bound.
+    pub unsafe fn draw(
+        &self,
+        pass: &mut RenderPassEncoder,
+        mesh: &MeshletMesh,
+        model: &Mat4,
+        use_texture: bool,
+        texture_slot: u32,
+        alpha_cutoff: f32,
+    ) {
+        pass.bind_descriptor_set(2, mesh.descriptor_set);
+
+        let pc = MeshletPushConstants {
+            model: model.to_cols_array_2d(),
+            use_texture: if use_texture { 1 } else { 0 },
+            alpha_cutoff,
+            meshlet_count: mesh.meshlet_count,
+            texture_slot,
+        };
+        pass.push_constants(MESHLET_STAGES | vk::ShaderStageFlags::FRAGMENT, 0, &pc);
+
+        self.loader
+            .cmd_draw_mesh_tasks(pass.command_buffer(), mesh.meshlet_count.div_ceil(TASK_WORKGROUP_SIZE), 1, 1);
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        for (_, pipeline) in self.pipelines.drain() {
+            renderer.device.destroy_pipeline(pipeline, None);
+        }
+        for mut mesh in self.meshes.drain(..) {
+            for buffer in mesh.buffers.drain(..) {
+                renderer.device.destroy_buffer(buffer, None);
+            }
+            for allocation in mesh.allocations.drain(..).flatten() {
+                let _ = renderer.allocator.lock().free(allocation);
+            }
+        }
+        renderer.device.destroy_descriptor_pool(self.descriptor_pool, None);
+        renderer.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        renderer.device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
+
+/// Host-visible storage buffer holding `data`
+unsafe fn create_storage_buffer<T: Copy>(
+    renderer: &VulkanRenderer,
+    name: &str,
+    data: &[T],
+) -> Result<(vk::Buffer, Allocation), Box<dyn std::error::Error>> {
+    let buffer_info = vk::BufferCreateInfo::default()
+        .size(std::mem::size_of_val(data).max(4) as u64)
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = renderer.device.create_buffer(&buffer_info, None)?;
+    let requirements = renderer.device.get_buffer_memory_requirements(buffer);
+    let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+        name,
+        requirements,
+        location: MemoryLocation::CpuToGpu,
+        linear: true,
+        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+    })?;
+    renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+    let ptr = allocation.mapped_ptr().unwrap().as_ptr() as *mut T;
+    std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+    Ok((buffer, allocation))
+}
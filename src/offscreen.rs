@@ -0,0 +1,318 @@
+//! Offscreen render targets
+//!
+//! A color + D32 depth `RenderTarget` with a framebuffer, for render passes built for the
+//! swapchain (such as the glTF scene pass) or for another color format, at any size, whose
+//! color can be read back to the host or sampled. Used to render stills larger than the window, for data export and
+//! for the material editor's preview.
+//! `read_image` reads back any single-layer color image.
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+use crate::render_target::{RenderTarget, RenderTargetDesc};
+use crate::renderer::VulkanRenderer;
+
+const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+pub struct OffscreenTarget {
+    pub target: RenderTarget,
+    pub format: vk::Format, // The main swapchain's unless created `with_format`
+    pub framebuffer: vk::Framebuffer,
+}
+
+impl OffscreenTarget {
+    /// Create a target for `render_pass`, which must use the main swapchain's color format
+    /// and a D32 depth attachment.
+    ///
+    /// # Safety
+    ///
+    /// `render_pass` must outlive the target.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        render_pass: vk::RenderPass,
+        extent: vk::Extent2D,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_format(renderer, render_pass, renderer.swapchain_format, extent)
+    }
+
+    /// Like `new`, for a `render_pass` whose color attachment has `format`
+    ///
+    /// # Safety
+    ///
+    /// `render_pass` must outlive the target.
+    pub unsafe fn with_format(
+        renderer: &VulkanRenderer,
+        render_pass: vk::RenderPass,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let desc = RenderTargetDesc::color(
+            format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::SAMPLED,
+        )
+        // The scene pass leaves depth ready to sample
+        .with_depth(DEPTH_FORMAT, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED);
+        let mut target = RenderTarget::new(renderer, "offscreen", desc, extent)?;
+
+        let attachments = [target.color().view, target.depth().view];
+        let framebuffer_info = vk::FramebufferCreateInfo::default()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = match renderer.device.create_framebuffer(&framebuffer_info, None) {
+            Ok(framebuffer) => framebuffer,
+            Err(e) => {
+                target.destroy(renderer);
+                return Err(e.into());
+            }
+        };
+
+        Ok(Self { target, format, framebuffer })
+    }
+
+    /// Copy the color attachment to the host as tightly packed RGBA8 rows. `layout` is the
+    /// layout the last render pass left it in. Waits for the graphics queue to go idle.
+    ///
+    /// # Safety
+    ///
+    /// The color attachment must be in `layout` once all submitted work has finished.
+    pub unsafe fn read_rgba8(
+        &self,
+        renderer: &VulkanRenderer,
+        layout: vk::ImageLayout,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut pixels = read_image(renderer, self.target.color().image, self.target.extent, 4, layout)?;
+        if matches!(self.format, vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok(pixels)
+    }
+
+    /// Copy the color attachment to the host as tightly packed rows of the target's texels:
+    /// four halves for RGBA16F, else RGBA8 as `read_rgba8` reads it.
+    ///
+    /// # Safety
+    ///
+    /// As for `read_rgba8`.
+    pub unsafe fn read_texels(
+        &self,
+        renderer: &VulkanRenderer,
+        layout: vk::ImageLayout,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match self.format {
+            vk::Format::R16G16B16A16_SFLOAT => read_image(renderer, self.target.color().image, self.target.extent, 8, layout),
+            _ => self.read_rgba8(renderer, layout),
+        }
+    }
+
+    /// Destroy the target
+    ///
+    /// # Safety
+    ///
+    /// The device must be idle.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        renderer.device.destroy_framebuffer(self.framebuffer, None);
+        self.target.destroy(renderer);
+    }
+}
+
+/// Copy a color image with `texel_size` bytes per texel to the host as tightly packed rows.
+/// `layout` is the image's current layout, which it is returned to. Waits for the graphics
+/// queue to go idle.
+///
+/// # Safety
+///
+/// `image` must have been created with `TRANSFER_SRC` usage, and `texel_size` must match its
+/// format.
+pub unsafe fn read_image(
+    renderer: &VulkanRenderer,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    texel_size: u64,
+    layout: vk::ImageLayout,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let device = &renderer.device;
+    let size = extent.width as u64 * extent.height as u64 * texel_size;
+
+    let buffer_info = vk::BufferCreateInfo::default()
+        .size(size)
+        .usage(vk::BufferUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = device.create_buffer(&buffer_info, None)?;
+    let requirements = device.get_buffer_memory_requirements(buffer);
+    let allocation = match renderer.allocator.lock().allocate(&AllocationCreateDesc {
+        name: "offscreen_readback",
+        requirements,
+        location: MemoryLocation::GpuToCpu,
+        linear: true,
+        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+    }) {
+        Ok(allocation) => allocation,
+        Err(e) => {
+            device.destroy_buffer(buffer, None);
+            return Err(e.into());
+        }
+    };
+    device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+
+    let cmd_info = vk::CommandBufferAllocateInfo::default()
+        .command_pool(renderer.command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1);
+    let cmd = device.allocate_command_buffers(&cmd_info)?[0];
+    let begin_info = vk::CommandBufferBeginInfo::default()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(cmd, &begin_info)?;
+
+    let range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    let to_transfer = vk::ImageMemoryBarrier::default()
+        .old_layout(layout)
+        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(range);
+    device.cmd_pipeline_barrier(
+        cmd,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        std::slice::from_ref(&to_transfer),
+    );
+
+    let region = vk::BufferImageCopy {
+        buffer_offset: 0,
+        buffer_row_length: 0,
+        buffer_image_height: 0,
+        image_subresource: vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        image_offset: vk::Offset3D::default(),
+        image_extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+    };
+    device.cmd_copy_image_to_buffer(
+        cmd,
+        image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        buffer,
+        std::slice::from_ref(&region),
+    );
+
+    // Back to what the next render pass expects to start from
+    let to_render = vk::ImageMemoryBarrier::default()
+        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .new_layout(layout)
+        .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(range);
+    let to_host = vk::BufferMemoryBarrier::default()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(buffer)
+        .offset(0)
+        .size(size);
+    device.cmd_pipeline_barrier(
+        cmd,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::DependencyFlags::empty(),
+        &[],
+        std::slice::from_ref(&to_host),
+        std::slice::from_ref(&to_render),
+    );
+
+    device.end_command_buffer(cmd)?;
+    let command_buffers = [cmd];
+    let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+    device.queue_submit(renderer.graphics_queue, &[submit_info], vk::Fence::null())?;
+    device.queue_wait_idle(renderer.graphics_queue)?;
+    device.free_command_buffers(renderer.command_pool, &command_buffers);
+
+    let mut pixels = vec![0u8; size as usize];
+    if let Some(mapped) = allocation.mapped_ptr() {
+        std::ptr::copy_nonoverlapping(mapped.as_ptr() as *const u8, pixels.as_mut_ptr(), pixels.len());
+    }
+    device.destroy_buffer(buffer, None);
+    renderer.allocator.lock().free(allocation)?;
+    Ok(pixels)
+}
+
+/// A single-mip 2D image with a view, in GPU memory
+///
+/// # Safety
+///
+/// The caller owns the returned image, view and allocation and must free them with `renderer`.
+pub unsafe fn create_image(
+    renderer: &VulkanRenderer,
+    name: &str,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    usage: vk::ImageUsageFlags,
+    aspect_mask: vk::ImageAspectFlags,
+) -> Result<(vk::Image, vk::ImageView, Allocation), Box<dyn std::error::Error>> {
+    let image_info = vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(format)
+        .extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    let image = renderer.device.create_image(&image_info, None)?;
+    let requirements = renderer.device.get_image_memory_requirements(image);
+
+    let allocation = match renderer.allocator.lock().allocate(&AllocationCreateDesc {
+        name,
+        requirements,
+        location: MemoryLocation::GpuOnly,
+        linear: false,
+        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+    }) {
+        Ok(allocation) => allocation,
+        Err(e) => {
+            renderer.device.destroy_image(image, None);
+            return Err(e.into());
+        }
+    };
+    renderer.device.bind_image_memory(image, allocation.memory(), allocation.offset())?;
+
+    let view_info = vk::ImageViewCreateInfo::default()
+        .image(image)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(format)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        });
+    let view = renderer.device.create_image_view(&view_info, None)?;
+
+    Ok((image, view, allocation))
+}
@@ -0,0 +1,350 @@
+//! GPU particle fountain
+//!
+//! Simulated entirely in a compute shader and drawn as camera-facing quads straight from
+//! the particle buffer. Each frame reads the previous state and writes a new buffer; the
+//! draw uses the state from one frame back so the simulation can run on the async
+//! compute queue without the graphics queue waiting on it (see `async_compute`).
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+use crate::compute::{self, Access, ComputePipeline, DescriptorWriter};
+use crate::command_encoder::{PipelineBinding, RenderPassEncoder};
+use crate::pipeline_builder::{BlendMode, DepthMode, GraphicsPipelineBuilder};
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+
+pub const PARTICLE_COUNT: u32 = 8192;
+
+/// One more state buffer than frames in flight: the buffer being written is never one a
+/// frame still in flight may be drawing from
+pub const STATE_BUFFER_COUNT: usize = MAX_FRAMES_IN_FLIGHT + 1;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Particle {
+    pub position: [f32; 4], // xyz = position, w = age (seconds)
+    pub velocity: [f32; 4], // xyz = velocity, w = lifetime (seconds)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct SimulatePushConstants {
+    emitter: [f32; 4], // xyz = emitter position, w = delta time
+    time: f32,
+    count: u32,
+    speed: f32,
+    spread: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct DrawPushConstants {
+    view_proj: [[f32; 4]; 4],
+    camera_right: [f32; 4], // xyz = right vector, w = particle size
+    camera_up: [f32; 4],
+}
+
+pub struct ParticleSystem {
+    pub emitter: glam::Vec3,
+    pub speed: f32,
+    pub spread: f32,
+    pub size: f32,
+
+    pub simulate_pipeline: ComputePipeline,
+    pub simulate_sets: Vec<vk::DescriptorSet>, // [i] reads buffer i-1, writes buffer i
+
+    draw_pipelines: Vec<(vk::RenderPass, vk::Pipeline)>, // One per pass the particles can be drawn in
+    pub draw_pipeline_layout: vk::PipelineLayout,
+    pub draw_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub draw_descriptor_pool: vk::DescriptorPool,
+    pub draw_sets: Vec<vk::DescriptorSet>, // [i] reads buffer i
+
+    pub state_buffers: Vec<vk::Buffer>,
+    pub state_allocations: Vec<Option<Allocation>>,
+
+    frame: u64,
+    time: f32,
+}
+
+impl ParticleSystem {
+    /// `render_pass` is the scene pass the particles are drawn in (color + depth).
+    /// `compute_queue_family_index` is the async compute family, if simulation runs there.
+    ///
+    /// # Safety
+    ///
+    /// `render_pass` must outlive the particle system.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        render_pass: vk::RenderPass,
+        compute_queue_family_index: Option<u32>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+
+        // A negative age means "not spawned yet", staggering the first emission over 1.5s
+        let initial: Vec<Particle> = (0..PARTICLE_COUNT)
+            .map(|i| Particle {
+                position: [0.0, 0.0, 0.0, -1.5 * i as f32 / PARTICLE_COUNT as f32],
+                velocity: [0.0; 4],
+            })
+            .collect();
+
+        // Shared between the graphics and compute families so no ownership transfers are needed
+        let mut queue_families = vec![renderer.graphics_queue_family_index];
+        if let Some(index) = compute_queue_family_index {
+            if index != renderer.graphics_queue_family_index {
+                queue_families.push(index);
+            }
+        }
+
+        let (state_buffers, state_allocations) =
+            create_state_buffers(renderer, "Particle State", &initial, &queue_families)?;
+
+        // Simulation
+        let simulate_code = load_shader("particles.comp", include_bytes!("../shaders/particles.comp.spv"));
+        let simulate_pipeline = ComputePipeline::new(
+            renderer,
+            &simulate_code,
+            std::mem::size_of::<SimulatePushConstants>() as u32,
+            STATE_BUFFER_COUNT as u32,
+        )?;
+        let mut simulate_sets = Vec::new();
+        for i in 0..STATE_BUFFER_COUNT {
+            let set = simulate_pipeline.allocate_descriptor_set(device)?;
+            DescriptorWriter::new()
+                .storage_buffer(0, state_buffers[(i + STATE_BUFFER_COUNT - 1) % STATE_BUFFER_COUNT])
+                .storage_buffer(1, state_buffers[i])
+                .write(device, set);
+            simulate_sets.push(set);
+        }
+
+        // Drawing
+        let vert_code = load_shader("particles.vert", include_bytes!("../shaders/particles.vert.spv"));
+        let frag_code = load_shader("particles.frag", include_bytes!("../shaders/particles.frag.spv"));
+        let vert_reflection = ShaderReflection::reflect(&vert_code)?;
+        let frag_reflection = ShaderReflection::reflect(&frag_code)?;
+        let reflections = [&vert_reflection, &frag_reflection];
+
+        let bindings = shader_reflection::set_layout_bindings(&reflections, 0)?;
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        let draw_descriptor_set_layout = device.create_descriptor_set_layout(&layout_info, None)?;
+
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &reflections,
+            std::mem::size_of::<DrawPushConstants>() as u32,
+        )?
+        .into_iter()
+        .collect();
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(std::slice::from_ref(&draw_descriptor_set_layout))
+            .push_constant_ranges(&push_constant_ranges);
+        let draw_pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None)?;
+
+        let draw_pipeline = Self::create_draw_pipeline(device, render_pass, draw_pipeline_layout, &vert_code, &frag_code)?;
+
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: STATE_BUFFER_COUNT as u32,
+        };
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(std::slice::from_ref(&pool_size))
+            .max_sets(STATE_BUFFER_COUNT as u32);
+        let draw_descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
+
+        let layouts = vec![draw_descriptor_set_layout; STATE_BUFFER_COUNT];
+        let alloc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(draw_descriptor_pool)
+            .set_layouts(&layouts);
+        let draw_sets = device.allocate_descriptor_sets(&alloc_info)?;
+        for (set, buffer) in draw_sets.iter().zip(&state_buffers) {
+            DescriptorWriter::new().storage_buffer(0, *buffer).write(device, *set);
+        }
+
+        Ok(Self {
+            emitter: glam::Vec3::new(-2.0, 0.0, -2.0),
+            speed: 6.0,
+            spread: 1.2,
+            size: 0.04,
+            simulate_pipeline,
+            simulate_sets,
+            draw_pipelines: vec![(render_pass, draw_pipeline)],
+            draw_pipeline_layout,
+            draw_descriptor_set_layout,
+            draw_descriptor_pool,
+            draw_sets,
+            state_buffers,
+            state_allocations,
+            frame: 0,
+            time: 0.0,
+        })
+    }
+
+    /// Make the particles drawable in `render_pass` as well, such as the half-resolution
+    /// transparency pass; `draw` uses the pipeline of whichever pass it records into
+    #[allow(dead_code)] // The library has no half-resolution pass to add
+    pub(crate) unsafe fn add_render_pass(
+        &mut self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let vert_code = load_shader("particles.vert", include_bytes!("../shaders/particles.vert.spv"));
+        let frag_code = load_shader("particles.frag", include_bytes!("../shaders/particles.frag.spv"));
+        let pipeline = Self::create_draw_pipeline(device, render_pass, self.draw_pipeline_layout, &vert_code, &frag_code)?;
+        self.draw_pipelines.push((render_pass, pipeline));
+        Ok(())
+    }
+
+    unsafe fn create_draw_pipeline(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        pipeline_layout: vk::PipelineLayout,
+        vert_code: &[u32],
+        frag_code: &[u32],
+    ) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+        // Vertices are expanded from the storage buffer, no vertex input. Sprites test
+        // against the scene but don't write depth, so overlapping ones blend; the fragment
+        // shader premultiplies by alpha.
+        GraphicsPipelineBuilder::new(pipeline_layout, render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, frag_code)
+            .blend(BlendMode::Additive)
+            .depth(DepthMode::ReadOnly(vk::CompareOp::LESS))
+            .build(device)
+    }
+
+    /// Record one simulation step. Works on either the async compute queue or the
+    /// graphics queue; on the graphics queue, follow it with a compute-write to
+    /// vertex-shader-read barrier before drawing.
+    ///
+    /// # Safety
+    ///
+    /// `command_buffer` must be recording outside a render pass, on the queue family given to
+    /// `new`.
+    pub unsafe fn simulate(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, delta_time: f32) {
+        self.frame += 1;
+        self.time += delta_time;
+        let write_index = (self.frame % STATE_BUFFER_COUNT as u64) as usize;
+
+        // The previous step's output is this step's input
+        compute::memory_barrier(device, command_buffer, Access::COMPUTE_WRITE, Access::COMPUTE_READ);
+
+        let push_constants = SimulatePushConstants {
+            emitter: [self.emitter.x, self.emitter.y, self.emitter.z, delta_time.min(0.1)],
+            time: self.time,
+            count: PARTICLE_COUNT,
+            speed: self.speed,
+            spread: self.spread,
+        };
+        let bytes = std::slice::from_raw_parts(
+            &push_constants as *const _ as *const u8,
+            std::mem::size_of::<SimulatePushConstants>(),
+        );
+        self.simulate_pipeline.dispatch_threads(
+            device,
+            command_buffer,
+            self.simulate_sets[write_index],
+            bytes,
+            [PARTICLE_COUNT, 1, 1],
+        );
+    }
+
+    /// Draw inside the scene render pass (or one added with `add_render_pass`), using the
+    /// state from one step before the latest `simulate`.
+    pub fn draw(&self, pass: &mut RenderPassEncoder, extent: vk::Extent2D, view: glam::Mat4, view_proj: glam::Mat4) {
+        let read_index = ((self.frame + STATE_BUFFER_COUNT as u64 - 1) % STATE_BUFFER_COUNT as u64) as usize;
+        let Some(&(render_pass, pipeline)) = self.draw_pipelines.iter().find(|(rp, _)| *rp == pass.render_pass()) else {
+            return;
+        };
+
+        pass.bind_pipeline(PipelineBinding {
+            pipeline,
+            layout: self.draw_pipeline_layout,
+            render_pass,
+            push_constant_size: std::mem::size_of::<DrawPushConstants>() as u32,
+        });
+        pass.set_full_viewport(extent);
+        pass.bind_descriptor_set(0, self.draw_sets[read_index]);
+
+        // Camera basis vectors are the first two rows of the view rotation
+        let right = view.row(0).truncate();
+        let up = view.row(1).truncate();
+        let push_constants = DrawPushConstants {
+            view_proj: view_proj.to_cols_array_2d(),
+            camera_right: right.extend(self.size).to_array(),
+            camera_up: up.extend(0.0).to_array(),
+        };
+        pass.push_constants(vk::ShaderStageFlags::VERTEX, 0, &push_constants);
+
+        pass.draw(PARTICLE_COUNT * 6, 1);
+    }
+
+    /// # Safety
+    ///
+    /// The device must be idle.
+    pub unsafe fn cleanup(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        self.simulate_pipeline.destroy(renderer);
+
+        for (_, pipeline) in self.draw_pipelines.drain(..) {
+            device.destroy_pipeline(pipeline, None);
+        }
+        device.destroy_pipeline_layout(self.draw_pipeline_layout, None);
+        device.destroy_descriptor_pool(self.draw_descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.draw_descriptor_set_layout, None);
+
+        for buffer in self.state_buffers.drain(..) {
+            device.destroy_buffer(buffer, None);
+        }
+        for alloc in self.state_allocations.drain(..).flatten() {
+            let _ = renderer.allocator.lock().free(alloc);
+        }
+    }
+}
+
+/// Particle state buffers with their memory
+pub(crate) type StateBuffers = (Vec<vk::Buffer>, Vec<Option<Allocation>>);
+
+/// `STATE_BUFFER_COUNT` host-visible storage buffers, each filled with `initial`, shared
+/// between `queue_families` so no ownership transfers are needed
+pub(crate) unsafe fn create_state_buffers<T: Copy>(
+    renderer: &VulkanRenderer,
+    name: &str,
+    initial: &[T],
+    queue_families: &[u32],
+) -> Result<StateBuffers, Box<dyn std::error::Error>> {
+    let device = &renderer.device;
+    let size = std::mem::size_of_val(initial) as u64;
+    let mut state_buffers = Vec::new();
+    let mut state_allocations = Vec::new();
+    for i in 0..STATE_BUFFER_COUNT {
+        let mut buffer_info = vk::BufferCreateInfo::default()
+            .size(size)
+            .usage(vk::BufferUsageFlags::STORAGE_BUFFER);
+        buffer_info = if queue_families.len() > 1 {
+            buffer_info
+                .sharing_mode(vk::SharingMode::CONCURRENT)
+                .queue_family_indices(queue_families)
+        } else {
+            buffer_info.sharing_mode(vk::SharingMode::EXCLUSIVE)
+        };
+
+        let buffer = device.create_buffer(&buffer_info, None)?;
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: &format!("{} {}", name, i),
+            requirements,
+            location: MemoryLocation::CpuToGpu,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        })?;
+        device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+
+        let mapped = allocation.mapped_ptr().ok_or("Particle buffer not host visible")?.as_ptr() as *mut T;
+        std::ptr::copy_nonoverlapping(initial.as_ptr(), mapped, initial.len());
+
+        state_buffers.push(buffer);
+        state_allocations.push(Some(allocation));
+    }
+    Ok((state_buffers, state_allocations))
+}
@@ -0,0 +1,335 @@
+//! Graphics pipeline builder
+//!
+//! `GraphicsPipelineBuilder` creates a graphics pipeline from what actually differs between
+//! passes — shaders, vertex layout, blending, depth and culling — and fills in the state
+//! every pass in the renderer shares:
+//!
+//! - dynamic viewport and scissor (one of each), so pipelines survive resizes,
+//! - filled triangle lists, counter-clockwise front faces, no culling,
+//! - one sample, one color target, opaque, and no depth test.
+//!
+//! Vertex buffers are described by a type implementing [`VertexLayout`]; the builder checks
+//! them against the vertex shader's inputs. Stages are given as SPIR-V: their modules are
+//! created for `build` and destroyed again once the pipeline exists.
+
+use ash::vk;
+use std::ffi::CStr;
+use crate::shader_reflection::{self, ShaderReflection};
+
+const ENTRY_POINT: &CStr = c"main";
+
+/// Attribute layout of a vertex (or instance) type, read from a buffer of `Self`s
+pub trait VertexLayout: Copy {
+    /// Format and byte offset of each attribute, at consecutive locations
+    const ATTRIBUTES: &'static [(vk::Format, u32)];
+}
+
+/// A per-instance model matrix takes four locations, one per column
+impl VertexLayout for glam::Mat4 {
+    const ATTRIBUTES: &'static [(vk::Format, u32)] = &[
+        (vk::Format::R32G32B32A32_SFLOAT, 0),
+        (vk::Format::R32G32B32A32_SFLOAT, 16),
+        (vk::Format::R32G32B32A32_SFLOAT, 32),
+        (vk::Format::R32G32B32A32_SFLOAT, 48),
+    ];
+}
+
+/// How color targets combine with what's already there
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    Opaque,
+    /// `src + dst * (1 - src.a)`, for colors already multiplied by their alpha
+    PremultipliedAlpha,
+    /// `src + dst`, leaving the target's alpha alone
+    Additive,
+    /// `src + dst` on every channel, alpha included: sums and counts in float targets
+    Accumulate,
+    /// `src + dst * src.a`, leaving the target's alpha alone: scale what's there and add
+    /// to it, for full-screen overlays
+    ScaleAdd,
+}
+
+impl BlendMode {
+    fn attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+        let state = vk::PipelineColorBlendAttachmentState::default().color_write_mask(vk::ColorComponentFlags::RGBA);
+        match self {
+            Self::Opaque => state.blend_enable(false),
+            Self::PremultipliedAlpha => state
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::ONE)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .alpha_blend_op(vk::BlendOp::ADD),
+            Self::Additive => state
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::ONE)
+                .dst_color_blend_factor(vk::BlendFactor::ONE)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                .alpha_blend_op(vk::BlendOp::ADD),
+            Self::Accumulate => state
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::ONE)
+                .dst_color_blend_factor(vk::BlendFactor::ONE)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                .alpha_blend_op(vk::BlendOp::ADD),
+            Self::ScaleAdd => state
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::ONE)
+                .dst_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                .alpha_blend_op(vk::BlendOp::ADD),
+        }
+    }
+}
+
+/// Depth test and write
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthMode {
+    Disabled,
+    /// Test with the compare op and write: opaque geometry
+    ReadWrite(vk::CompareOp),
+    /// Test with the compare op without writing: blended geometry in front of the scene
+    ReadOnly(vk::CompareOp),
+}
+
+impl DepthMode {
+    fn state(self) -> vk::PipelineDepthStencilStateCreateInfo<'static> {
+        let (test, write, compare_op) = match self {
+            Self::Disabled => (false, false, vk::CompareOp::ALWAYS),
+            Self::ReadWrite(op) => (true, true, op),
+            Self::ReadOnly(op) => (true, false, op),
+        };
+        vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(test)
+            .depth_write_enable(write)
+            .depth_compare_op(compare_op)
+            .depth_bounds_test_enable(false)
+            .stencil_test_enable(false)
+    }
+}
+
+struct Stage<'a> {
+    stage: vk::ShaderStageFlags,
+    code: &'a [u32],
+    specialization: Option<&'a vk::SpecializationInfo<'a>>,
+}
+
+pub struct GraphicsPipelineBuilder<'a> {
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    stages: Vec<Stage<'a>>,
+    bindings: Vec<vk::VertexInputBindingDescription>,
+    attributes: Vec<vk::VertexInputAttributeDescription>,
+    cull_mode: vk::CullModeFlags,
+    blend: BlendMode,
+    color_targets: usize,
+    depth: DepthMode,
+    flags: vk::PipelineCreateFlags,
+}
+
+impl<'a> GraphicsPipelineBuilder<'a> {
+    /// Pipeline with `layout` for subpass 0 of `render_pass` (or passes compatible with it)
+    pub fn new(layout: vk::PipelineLayout, render_pass: vk::RenderPass) -> Self {
+        Self {
+            layout,
+            render_pass,
+            stages: Vec::new(),
+            bindings: Vec::new(),
+            attributes: Vec::new(),
+            // No culling until the camera/projection conventions agree on handedness
+            // everywhere; opaque passes that know their winding can opt back in
+            cull_mode: vk::CullModeFlags::NONE,
+            blend: BlendMode::Opaque,
+            color_targets: 1,
+            depth: DepthMode::Disabled,
+            flags: vk::PipelineCreateFlags::empty(),
+        }
+    }
+
+    pub fn shader(mut self, stage: vk::ShaderStageFlags, code: &'a [u32]) -> Self {
+        self.stages.push(Stage { stage, code, specialization: None });
+        self
+    }
+
+    /// Specialization constants of the last shader added
+    pub fn specialization(mut self, info: &'a vk::SpecializationInfo<'a>) -> Self {
+        self.stages.last_mut().expect("add a shader before its specialization").specialization = Some(info);
+        self
+    }
+
+    /// Per-vertex buffer of `V`s at the next binding, its attributes at the next locations
+    pub fn vertex_buffer<V: VertexLayout>(self) -> Self {
+        self.buffer::<V>(vk::VertexInputRate::VERTEX)
+    }
+
+    /// Per-instance buffer of `V`s at the next binding, its attributes at the next locations
+    pub fn instance_buffer<V: VertexLayout>(self) -> Self {
+        self.buffer::<V>(vk::VertexInputRate::INSTANCE)
+    }
+
+    fn buffer<V: VertexLayout>(mut self, input_rate: vk::VertexInputRate) -> Self {
+        let binding = self.bindings.len() as u32;
+        self.bindings.push(
+            vk::VertexInputBindingDescription::default()
+                .binding(binding)
+                .stride(std::mem::size_of::<V>() as u32)
+                .input_rate(input_rate),
+        );
+        let first_location = self.attributes.len() as u32;
+        for (i, &(format, offset)) in V::ATTRIBUTES.iter().enumerate() {
+            self.attributes.push(vk::VertexInputAttributeDescription {
+                binding,
+                location: first_location + i as u32,
+                format,
+                offset,
+            });
+        }
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /// Blending of every color target
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    /// Number of color attachments in the subpass; 0 for depth-only passes
+    pub fn color_targets(mut self, count: usize) -> Self {
+        self.color_targets = count;
+        self
+    }
+
+    pub fn depth(mut self, depth: DepthMode) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// E.g. `DESCRIPTOR_BUFFER_EXT` when the layout's sets come from descriptor buffers
+    pub fn flags(mut self, flags: vk::PipelineCreateFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// # Safety
+    ///
+    /// The layout, render pass and shader code given to the builder must be valid for `device`.
+    pub unsafe fn build(&self, device: &ash::Device) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+        if let Some(vertex) = self.stages.iter().find(|s| s.stage == vk::ShaderStageFlags::VERTEX) {
+            shader_reflection::validate_vertex_input(&ShaderReflection::reflect(vertex.code)?, &self.attributes)?;
+        }
+
+        let mut modules = Vec::with_capacity(self.stages.len());
+        for stage in &self.stages {
+            match device.create_shader_module(&vk::ShaderModuleCreateInfo::default().code(stage.code), None) {
+                Ok(module) => modules.push(module),
+                Err(e) => {
+                    for module in modules {
+                        device.destroy_shader_module(module, None);
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let pipeline = self.create(device, &modules);
+
+        for module in modules {
+            device.destroy_shader_module(module, None);
+        }
+        pipeline
+    }
+
+    unsafe fn create(
+        &self,
+        device: &ash::Device,
+        modules: &[vk::ShaderModule],
+    ) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+        let shader_stages: Vec<_> = self
+            .stages
+            .iter()
+            .zip(modules)
+            .map(|(stage, &module)| {
+                let info = vk::PipelineShaderStageCreateInfo::default()
+                    .stage(stage.stage)
+                    .module(module)
+                    .name(ENTRY_POINT);
+                match stage.specialization {
+                    Some(specialization) => info.specialization_info(specialization),
+                    None => info,
+                }
+            })
+            .collect();
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&self.bindings)
+            .vertex_attribute_descriptions(&self.attributes);
+
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .primitive_restart_enable(false);
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo::default()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(self.cull_mode)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .depth_bias_enable(false);
+
+        let multisampling = vk::PipelineMultisampleStateCreateInfo::default()
+            .sample_shading_enable(false)
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        let depth_stencil = self.depth.state();
+
+        let blend_attachments = vec![self.blend.attachment_state(); self.color_targets];
+        let color_blending = vk::PipelineColorBlendStateCreateInfo::default()
+            .logic_op_enable(false)
+            .attachments(&blend_attachments);
+
+        let mut pipeline_info = vk::GraphicsPipelineCreateInfo::default()
+            .flags(self.flags)
+            .stages(&shader_stages)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterizer)
+            .multisample_state(&multisampling)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blending)
+            .dynamic_state(&dynamic_state)
+            .layout(self.layout)
+            .render_pass(self.render_pass)
+            .subpass(0);
+        // Mesh shaders fetch their own vertices
+        if !self.stages.iter().any(|s| s.stage == vk::ShaderStageFlags::MESH_EXT) {
+            pipeline_info = pipeline_info
+                .vertex_input_state(&vertex_input)
+                .input_assembly_state(&input_assembly);
+        }
+
+        let pipeline = device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+            .map_err(|(_, e)| e)?[0];
+        Ok(pipeline)
+    }
+}
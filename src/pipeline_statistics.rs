@@ -0,0 +1,106 @@
+//! Pipeline statistics queries
+//!
+//! A `PIPELINE_STATISTICS` query around the main scene pass counts what the GPU actually
+//! did to draw it: vertices and primitives fetched, vertex shader invocations, primitives
+//! entering and leaving clipping, and fragment shader invocations. Unlike the draw
+//! statistics, which count what was recorded, these show the shading cost of culling,
+//! LOD and render scale changes. Mesh shader draws fetch no vertices and are not counted
+//! by the vertex stages.
+//!
+//! Each frame in flight has its own query, read back once its fence has been waited on,
+//! so the numbers are `VulkanRenderer::frames_in_flight` frames old.
+
+use ash::vk;
+
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+
+/// Counted statistics, in the order the query returns them
+const COUNTERS: [vk::QueryPipelineStatisticFlags; 6] = [
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES,
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES,
+    vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS,
+    vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS,
+    vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES,
+    vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS,
+];
+
+/// What one scene pass took
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    pub input_vertices: u64,
+    pub input_primitives: u64,
+    pub vertex_invocations: u64,
+    pub clipping_invocations: u64, // Primitives that reached clipping
+    pub clipping_primitives: u64,  // Primitives clipping output, split ones counted once per piece
+    pub fragment_invocations: u64,
+}
+
+pub struct PipelineStatistics {
+    query_pool: vk::QueryPool,
+    recorded: [bool; MAX_FRAMES_IN_FLIGHT], // Whether each frame slot's query has results coming
+    open: Option<u32>,                       // The query between `begin` and `end`
+    pub last: Option<PipelineStats>,          // Of the latest frame read back
+}
+
+impl PipelineStatistics {
+    /// None without pipeline statistics query support
+    pub unsafe fn new(renderer: &VulkanRenderer) -> Result<Option<Self>, vk::Result> {
+        if !renderer.pipeline_statistics_supported {
+            return Ok(None);
+        }
+        let flags = COUNTERS.iter().fold(vk::QueryPipelineStatisticFlags::empty(), |all, &flag| all | flag);
+        let query_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::PIPELINE_STATISTICS)
+            .pipeline_statistics(flags)
+            .query_count(MAX_FRAMES_IN_FLIGHT as u32);
+        let query_pool = renderer.device.create_query_pool(&query_info, None)?;
+        Ok(Some(Self { query_pool, recorded: [false; MAX_FRAMES_IN_FLIGHT], open: None, last: None }))
+    }
+
+    /// Read frame slot `frame_index`'s query, if it was recorded. Call once the slot's fence
+    /// has been waited on.
+    pub unsafe fn read(&mut self, device: &ash::Device, frame_index: usize) {
+        if !std::mem::take(&mut self.recorded[frame_index]) {
+            return;
+        }
+        let mut counts = [[0u64; COUNTERS.len()]; 1];
+        let read = device.get_query_pool_results(
+            self.query_pool,
+            frame_index as u32,
+            &mut counts,
+            vk::QueryResultFlags::TYPE_64,
+        );
+        if read.is_ok() {
+            let [input_vertices, input_primitives, vertex_invocations, clipping_invocations, clipping_primitives, fragment_invocations] =
+                counts[0];
+            self.last = Some(PipelineStats {
+                input_vertices,
+                input_primitives,
+                vertex_invocations,
+                clipping_invocations,
+                clipping_primitives,
+                fragment_invocations,
+            });
+        }
+    }
+
+    /// Start counting for frame slot `frame_index`. Record outside any render pass.
+    pub unsafe fn begin(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        let query = frame_index as u32;
+        device.cmd_reset_query_pool(command_buffer, self.query_pool, query, 1);
+        device.cmd_begin_query(command_buffer, self.query_pool, query, vk::QueryControlFlags::empty());
+        self.open = Some(query);
+    }
+
+    /// Stop the count `begin` started, if any. Record outside any render pass.
+    pub unsafe fn end(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        if let Some(query) = self.open.take() {
+            device.cmd_end_query(command_buffer, self.query_pool, query);
+            self.recorded[query as usize] = true;
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        device.destroy_query_pool(self.query_pool, None);
+    }
+}
@@ -0,0 +1,243 @@
+//! Planar reflections
+//!
+//! Flat reflective surfaces (the ground plane) reflect the scene as seen by the main camera
+//! mirrored in their plane. The mirrored camera renders the scene into an offscreen target,
+//! at a fraction of the main view's resolution, before the main scene pass; the surfaces
+//! then sample it at their own screen position. Unlike screen-space reflections this also
+//! shows what is off screen.
+//!
+//! The mirror flips the winding of every triangle, so the mirrored camera flips its image
+//! horizontally as well to keep back faces culled; the surfaces sample with X flipped back.
+//! An oblique near plane (Lengyel's method) clips away everything below the mirror plane,
+//! the reflecting surface included.
+
+use ash::vk;
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::gltf_renderer::{GltfRenderer, GltfView, ViewCamera};
+use crate::offscreen::OffscreenTarget;
+use crate::renderer::VulkanRenderer;
+use crate::sampler_cache::SamplerDesc;
+
+/// Set in a draw's `firstInstance`, above its texture array slot, for surfaces that sample
+/// the planar reflection; must match gltf.frag
+pub const PLANAR_REFLECTION_INSTANCE_BIT: u32 = 1 << 31;
+/// Height of the mirror plane: the ground's
+pub const PLANE_HEIGHT: f32 = 0.0;
+/// How far above the mirror plane the clip plane lies, so the reflecting surface itself is
+/// reliably clipped
+const CLIP_OFFSET: f32 = 0.005;
+
+/// The mirrored view and the target it renders into
+pub struct PlanarReflection {
+    target: OffscreenTarget,
+    view: Option<GltfView>,
+    sampler: vk::Sampler,
+}
+
+impl PlanarReflection {
+    /// Create the target at `extent` and point the main view's descriptor sets at it
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        gltf_renderer: &GltfRenderer,
+        extent: vk::Extent2D,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let target = OffscreenTarget::new(renderer, gltf_renderer.render_pass, extent)?;
+        let mut reflection = Self { target, view: None, sampler: vk::Sampler::null() };
+        let view = renderer
+            .sampler(SamplerDesc::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE))
+            .map_err(Box::<dyn std::error::Error>::from)
+            .and_then(|sampler| {
+                reflection.sampler = sampler;
+                gltf_renderer.create_view(renderer, extent)
+            });
+        match view {
+            Ok(view) => reflection.view = Some(view),
+            Err(e) => {
+                reflection.target.destroy(renderer);
+                return Err(e);
+            }
+        }
+
+        // Sampled before anything was rendered into it, the target must be readable
+        let image = reflection.target.target.color().image;
+        let transitioned = crate::lightmap::submit_once(renderer, |device, cmd| {
+            barrier(
+                device,
+                cmd,
+                image,
+                (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+                (
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::SHADER_READ,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                ),
+            );
+        });
+        if let Err(e) = transitioned {
+            reflection.destroy(renderer, gltf_renderer);
+            return Err(e);
+        }
+        gltf_renderer.set_planar_reflection_source(&renderer.device, Some(reflection.descriptor_info()));
+        Ok(reflection)
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.target.target.extent
+    }
+
+    pub fn view(&self) -> Option<&GltfView> {
+        self.view.as_ref()
+    }
+
+    fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: self.target.target.color().view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+
+    /// Uniforms of the main view: x = 1, y = `strength`, zw = 1 / `main_extent`, which
+    /// turns fragment coordinates into texture coordinates of the target
+    pub fn uniforms(strength: f32, main_extent: vk::Extent2D) -> [f32; 4] {
+        [
+            1.0,
+            strength,
+            1.0 / main_extent.width.max(1) as f32,
+            1.0 / main_extent.height.max(1) as f32,
+        ]
+    }
+
+    /// Render the scene as `camera` sees it in the mirror into the target, leaving it ready
+    /// to sample. Record before the main scene pass, outside any render pass.
+    pub unsafe fn render(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        gltf_renderer: &GltfRenderer,
+        frame_index: usize,
+        camera: &ViewCamera,
+        (debug_cascades, shadow_softness, use_pcss): (bool, f32, bool),
+    ) {
+        let image = self.target.target.color().image;
+        let Some(view) = &mut self.view else {
+            return;
+        };
+        let mirrored = mirrored_camera(camera, PLANE_HEIGHT);
+        view.layers = gltf_renderer.layers; // The mirror shows what the main view does
+        gltf_renderer.update_view_uniform_buffer(view, frame_index, &mirrored, debug_cascades, shadow_softness, use_pcss);
+
+        // The previous frame's scene pass may still be sampling the target
+        let sampled = (
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        );
+        barrier(
+            device,
+            command_buffer,
+            image,
+            sampled,
+            (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ),
+        );
+        gltf_renderer.render_view(device, command_buffer, view, self.target.framebuffer, frame_index);
+
+        // The scene pass leaves color ready for presentation
+        barrier(
+            device,
+            command_buffer,
+            image,
+            (
+                vk::ImageLayout::PRESENT_SRC_KHR,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ),
+            sampled,
+        );
+    }
+
+    /// Destroy the target and point the main view back at the placeholder. The device must
+    /// be idle.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer, gltf_renderer: &GltfRenderer) {
+        gltf_renderer.set_planar_reflection_source(&renderer.device, None);
+        if let Some(view) = self.view.take() {
+            let _ = gltf_renderer.destroy_view(renderer, view);
+        }
+        self.target.destroy(renderer);
+    }
+}
+
+/// `camera` mirrored in the horizontal plane at `height`, its image flipped horizontally
+/// and its near plane on the mirror plane
+pub fn mirrored_camera(camera: &ViewCamera, height: f32) -> ViewCamera {
+    let mirror = Mat4::from_translation(Vec3::Y * height)
+        * Mat4::from_scale(Vec3::new(1.0, -1.0, 1.0))
+        * Mat4::from_translation(Vec3::Y * -height);
+    let view = camera.view * mirror;
+    let mut proj = camera.proj;
+    proj.x_axis.x = -proj.x_axis.x;
+
+    // The clip plane in view space; points above it have positive distances
+    let plane = Vec4::new(0.0, 1.0, 0.0, -(height + CLIP_OFFSET));
+    let clip_plane = view.inverse().transpose() * plane;
+    ViewCamera {
+        position: mirror.transform_point3(camera.position),
+        view,
+        proj: oblique_projection(proj, clip_plane),
+    }
+}
+
+/// `proj` with its near plane replaced by `clip_plane` (in view space), keeping the far
+/// plane's corner opposite the plane in place. For Vulkan's 0 to 1 depth range.
+fn oblique_projection(proj: Mat4, clip_plane: Vec4) -> Mat4 {
+    // Which corner depends on the plane's direction in clip space, where X and Y may be flipped
+    let inverse = proj.inverse();
+    let clip_space_plane = inverse.transpose() * clip_plane;
+    let corner = inverse * Vec4::new(clip_space_plane.x.signum(), clip_space_plane.y.signum(), 1.0, 1.0);
+    let scaled = clip_plane * (proj.row(3).dot(corner) / clip_plane.dot(corner));
+    let mut oblique = proj;
+    oblique.x_axis.z = scaled.x;
+    oblique.y_axis.z = scaled.y;
+    oblique.z_axis.z = scaled.z;
+    oblique.w_axis.z = scaled.w;
+    oblique
+}
+
+/// Move the target's color image between layouts
+unsafe fn barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    (old_layout, src_access, src_stage): (vk::ImageLayout, vk::AccessFlags, vk::PipelineStageFlags),
+    (new_layout, dst_access, dst_stage): (vk::ImageLayout, vk::AccessFlags, vk::PipelineStageFlags),
+) {
+    let barrier = vk::ImageMemoryBarrier::default()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        });
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stage,
+        dst_stage,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        std::slice::from_ref(&barrier),
+    );
+}
@@ -0,0 +1,183 @@
+//! Post effects
+//!
+//! - Dithering: gltf.frag adds blue noise of about one 8-bit step to its output before the
+//!   target quantizes it, so smooth gradients such as lit floors don't band. It has to
+//!   happen there: once a color has been written to an 8-bit target, the lost precision
+//!   can't be recovered. The noise is a void-and-cluster pattern generated at startup.
+//! - Film grain and vignette: a full-screen pass over the finished frame, before the UI,
+//!   that scales and adds to what's there through blending (`BlendMode::ScaleAdd`). The
+//!   grain is white noise, animated every frame.
+
+use ash::vk;
+
+use crate::command_encoder::{CommandEncoder, PipelineBinding};
+use crate::pipeline_builder::{BlendMode, GraphicsPipelineBuilder};
+use crate::renderer::VulkanRenderer;
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+use crate::swapchain;
+
+/// Side of the blue noise tile; must match gltf.frag
+pub const BLUE_NOISE_SIZE: usize = 64;
+
+/// Blue noise by void and cluster (Ulichney 1993): a tile of `size`² texels (wrapping
+/// around) whose ranks, scaled to 0..=255, spread as evenly as possible at any threshold
+pub fn blue_noise(size: usize) -> Vec<u8> {
+    let n = size * size;
+    let sigma = 1.5f32;
+    // Energy a point adds around it, cut off where it's negligible
+    let radius = (4.0 * sigma) as isize;
+    let splat = |energy: &mut [f32], point: usize, sign: f32| {
+        let (px, py) = ((point % size) as isize, (point / size) as isize);
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let x = (px + dx).rem_euclid(size as isize) as usize;
+                let y = (py + dy).rem_euclid(size as isize) as usize;
+                let weight = (-((dx * dx + dy * dy) as f32) / (2.0 * sigma * sigma)).exp();
+                energy[y * size + x] += sign * weight;
+            }
+        }
+    };
+    let tightest_cluster = |on: &[bool], energy: &[f32]| {
+        (0..n).filter(|&i| on[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+    let largest_void = |on: &[bool], energy: &[f32]| {
+        (0..n).filter(|&i| !on[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+
+    // A random tenth of the points (xorshift, so every run gets the same tile)
+    let mut on = vec![false; n];
+    let mut energy = vec![0.0f32; n];
+    let mut state = 0x9E37_79B9u32;
+    for _ in 0..n / 10 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let point = state as usize % n;
+        if !on[point] {
+            on[point] = true;
+            splat(&mut energy, point, 1.0);
+        }
+    }
+    // Move points from the tightest cluster to the largest void until that changes nothing
+    while let Some(cluster) = tightest_cluster(&on, &energy) {
+        on[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let Some(void) = largest_void(&on, &energy) else {
+            break;
+        };
+        on[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    // Rank the initial points by removing clusters, then the rest by filling voids
+    let mut rank = vec![0usize; n];
+    let initial = (on.clone(), energy.clone());
+    let mut count = on.iter().filter(|&&point| point).count();
+    while let Some(cluster) = tightest_cluster(&on, &energy) {
+        on[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        count -= 1;
+        rank[cluster] = count;
+    }
+    (on, energy) = initial;
+    count = on.iter().filter(|&&point| point).count();
+    while let Some(void) = largest_void(&on, &energy) {
+        on[void] = true;
+        splat(&mut energy, void, 1.0);
+        rank[void] = count;
+        count += 1;
+    }
+    rank.into_iter().map(|r| (r * 256 / n) as u8).collect()
+}
+
+/// Dithering uniforms of gltf.frag: x = strength in 8-bit steps, y = 1 when `format`
+/// encodes to sRGB on write, so the noise goes on the encoded value
+pub fn dither_uniforms(strength: f32, format: vk::Format) -> [f32; 4] {
+    [strength, if swapchain::is_srgb(format) { 1.0 } else { 0.0 }, 0.0, 0.0]
+}
+
+// Must match shaders/post.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PostPushConstants {
+    params: [f32; 4], // x = grain, y = vignette, z = frame, w = aspect ratio
+}
+
+/// The grain and vignette pass
+pub struct PostEffects {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    frame: u32, // Animates the grain
+}
+
+impl PostEffects {
+    /// Pipeline for the renderer's color-only pass, which loads the finished frame
+    pub unsafe fn new(renderer: &VulkanRenderer) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let vert_code = load_shader("post.vert", include_bytes!("../shaders/post.vert.spv"));
+        let frag_code = load_shader("post.frag", include_bytes!("../shaders/post.frag.spv"));
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &[&ShaderReflection::reflect(&vert_code)?, &ShaderReflection::reflect(&frag_code)?],
+            std::mem::size_of::<PostPushConstants>() as u32,
+        )?
+        .into_iter()
+        .collect();
+        let layout = device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&push_constant_ranges),
+            None,
+        )?;
+
+        // A full-screen triangle from gl_VertexIndex, no vertex input
+        let pipeline = GraphicsPipelineBuilder::new(layout, renderer.render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+            .blend(BlendMode::ScaleAdd)
+            .build(device);
+        match pipeline {
+            Ok(pipeline) => Ok(Self { pipeline, layout, render_pass: renderer.render_pass, frame: 0 }),
+            Err(e) => {
+                device.destroy_pipeline_layout(layout, None);
+                Err(e)
+            }
+        }
+    }
+
+    /// Apply `grain` (0 to 1) and `vignette` (0 to 1) to swapchain image `image_index`.
+    /// Record outside any render pass; does nothing when both are off.
+    pub unsafe fn record(&mut self, renderer: &VulkanRenderer, image_index: u32, grain: f32, vignette: f32) {
+        if grain <= 0.0 && vignette <= 0.0 {
+            return;
+        }
+        self.frame = self.frame.wrapping_add(1);
+        let extent = renderer.swapchain_extent;
+        let mut encoder = CommandEncoder::new(&renderer.device, renderer.command_buffers[renderer.current_frame]);
+        let mut pass = encoder.begin_render_pass(self.render_pass, renderer.framebuffers[image_index as usize], extent, &[]);
+        pass.bind_pipeline(PipelineBinding {
+            pipeline: self.pipeline,
+            layout: self.layout,
+            render_pass: self.render_pass,
+            push_constant_size: std::mem::size_of::<PostPushConstants>() as u32,
+        });
+        pass.set_full_viewport(extent);
+        let pc = PostPushConstants {
+            params: [
+                grain,
+                vignette,
+                (self.frame % 1024) as f32,
+                extent.width as f32 / extent.height.max(1) as f32,
+            ],
+        };
+        pass.push_constants(vk::ShaderStageFlags::FRAGMENT, 0, &pc);
+        pass.draw(3, 1);
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        renderer.device.destroy_pipeline(self.pipeline, None);
+        renderer.device.destroy_pipeline_layout(self.layout, None);
+    }
+}
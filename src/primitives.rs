@@ -0,0 +1,231 @@
+//! Procedural primitive meshes
+//!
+//! Spheres, planes, cylinders, tori and capsules as plain CPU-side mesh data: positions,
+//! normals, UVs and `u32` triangle indices, counter-clockwise seen from outside like the
+//! cube's. They are built around the origin with +Y up, and `transformed` moves them.
+//! `vertices` turns one into the cube pipeline's `Vertex` format, and `edges` gives its
+//! wireframe for gizmos.
+//!
+//! Everything round is a lathe: a profile of rings revolved around Y. UV seams and poles
+//! get their own vertices so textures wrap without stretching across the seam.
+
+use glam::{Mat3, Mat4, Vec3};
+use std::f32::consts::{PI, TAU};
+
+use crate::renderer::Vertex;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PrimitiveVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+/// An indexed triangle list
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub vertices: Vec<PrimitiveVertex>,
+    pub indices: Vec<u32>,
+}
+
+/// One ring of a lathe profile
+#[derive(Clone, Copy)]
+struct Ring {
+    radius: f32,
+    y: f32,
+    normal: [f32; 2], // Radial and Y component
+    v: f32,
+}
+
+#[allow(dead_code)] // Library API; the app itself only draws spheres
+impl MeshData {
+    /// UV sphere of `radius` with `sectors` around and `stacks` from pole to pole
+    pub fn sphere(radius: f32, sectors: u32, stacks: u32) -> Self {
+        let stacks = stacks.max(2);
+        let rings: Vec<Ring> = (0..=stacks)
+            .map(|k| {
+                let latitude = -PI / 2.0 + PI * k as f32 / stacks as f32;
+                Ring {
+                    radius: radius * latitude.cos(),
+                    y: radius * latitude.sin(),
+                    normal: [latitude.cos(), latitude.sin()],
+                    v: 1.0 - k as f32 / stacks as f32,
+                }
+            })
+            .collect();
+        let mut mesh = Self::default();
+        mesh.lathe(&rings, sectors);
+        mesh
+    }
+
+    /// `width` (X) by `depth` (Z) plane facing +Y, split into `subdivisions` squares per side
+    pub fn plane(width: f32, depth: f32, subdivisions: u32) -> Self {
+        let n = subdivisions.max(1);
+        let mut mesh = Self::default();
+        for i in 0..=n {
+            for k in 0..=n {
+                let (u, v) = (i as f32 / n as f32, k as f32 / n as f32);
+                mesh.vertices.push(PrimitiveVertex {
+                    position: [(u - 0.5) * width, 0.0, (v - 0.5) * depth],
+                    normal: [0.0, 1.0, 0.0],
+                    uv: [u, v],
+                });
+            }
+        }
+        for i in 0..n {
+            for k in 0..n {
+                let a = i * (n + 1) + k;
+                let (b, c, d) = (a + 1, a + n + 2, a + n + 1);
+                mesh.indices.extend_from_slice(&[a, b, c, a, c, d]);
+            }
+        }
+        mesh
+    }
+
+    /// Capped cylinder of `radius` and `height` along Y, with `sectors` around
+    pub fn cylinder(radius: f32, height: f32, sectors: u32) -> Self {
+        let half = height / 2.0;
+        let rings = [
+            Ring { radius, y: -half, normal: [1.0, 0.0], v: 1.0 },
+            Ring { radius, y: half, normal: [1.0, 0.0], v: 0.0 },
+        ];
+        let mut mesh = Self::default();
+        mesh.lathe(&rings, sectors);
+        mesh.cap(radius, half, sectors);
+        mesh.cap(radius, -half, sectors);
+        mesh
+    }
+
+    /// Torus around Y: a tube of `minor_radius` swept along a circle of `major_radius`
+    pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Self {
+        let minor_segments = minor_segments.max(3);
+        // Around the tube from the inside, under and over it back to the inside
+        let rings: Vec<Ring> = (0..=minor_segments)
+            .map(|k| {
+                let angle = -PI + TAU * k as f32 / minor_segments as f32;
+                Ring {
+                    radius: major_radius + minor_radius * angle.cos(),
+                    y: minor_radius * angle.sin(),
+                    normal: [angle.cos(), angle.sin()],
+                    v: k as f32 / minor_segments as f32,
+                }
+            })
+            .collect();
+        let mut mesh = Self::default();
+        mesh.lathe(&rings, major_segments);
+        mesh
+    }
+
+    /// Capsule along Y: a cylinder of `radius` and `height` with a hemisphere on each end,
+    /// `stacks` rings per hemisphere. The total height is `height + 2 * radius`.
+    pub fn capsule(radius: f32, height: f32, sectors: u32, stacks: u32) -> Self {
+        let stacks = stacks.max(1);
+        let half = height / 2.0;
+        // V runs along the outline, so the texture isn't squashed on the caps
+        let length = PI * radius + height;
+        let mut rings = Vec::new();
+        for (center, from) in [(-half, -PI / 2.0), (half, 0.0)] {
+            for k in 0..=stacks {
+                let latitude = from + PI / 2.0 * k as f32 / stacks as f32;
+                let arc = (latitude + PI / 2.0) * radius + if center > 0.0 { height } else { 0.0 };
+                rings.push(Ring {
+                    radius: radius * latitude.cos(),
+                    y: center + radius * latitude.sin(),
+                    normal: [latitude.cos(), latitude.sin()],
+                    v: 1.0 - arc / length,
+                });
+            }
+        }
+        let mut mesh = Self::default();
+        mesh.lathe(&rings, sectors);
+        mesh
+    }
+
+    /// The mesh with `transform` applied to its positions and normals
+    pub fn transformed(mut self, transform: Mat4) -> Self {
+        let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+        for vertex in &mut self.vertices {
+            vertex.position = transform.transform_point3(Vec3::from(vertex.position)).to_array();
+            vertex.normal = (normal_matrix * Vec3::from(vertex.normal)).normalize_or_zero().to_array();
+        }
+        self
+    }
+
+    /// Vertices for the cube pipeline, all of `color`; draw them with `indices`
+    pub fn vertices(&self, color: [f32; 3]) -> Vec<Vertex> {
+        self.vertices
+            .iter()
+            .map(|vertex| Vertex { pos: vertex.position, color, normal: vertex.normal })
+            .collect()
+    }
+
+    /// Every triangle edge once, as pairs of vertex indices; seams show up as the edges
+    /// of both sides, which coincide
+    pub fn edges(&self) -> Vec<(u32, u32)> {
+        let mut edges: Vec<(u32, u32)> = self
+            .indices
+            .chunks_exact(3)
+            .flat_map(|t| [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])])
+            .map(|(a, b)| (a.min(b), a.max(b)))
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+        edges
+    }
+
+    /// Revolve `rings` (bottom to top along the outside) around Y in `sectors` steps
+    fn lathe(&mut self, rings: &[Ring], sectors: u32) {
+        let sectors = sectors.max(3);
+        let base = self.vertices.len() as u32;
+        for ring in rings {
+            for j in 0..=sectors {
+                let u = j as f32 / sectors as f32;
+                let (sin, cos) = (u * TAU).sin_cos();
+                self.vertices.push(PrimitiveVertex {
+                    position: [ring.radius * cos, ring.y, -ring.radius * sin],
+                    normal: [ring.normal[0] * cos, ring.normal[1], -ring.normal[0] * sin],
+                    uv: [u, ring.v],
+                });
+            }
+        }
+        let row = sectors + 1;
+        for (i, pair) in rings.windows(2).enumerate() {
+            for j in 0..sectors {
+                let a = base + i as u32 * row + j;
+                let (b, c, d) = (a + 1, a + row + 1, a + row);
+                // Triangles that collapse onto a pole are left out
+                if pair[0].radius > 0.0 {
+                    self.indices.extend_from_slice(&[a, b, c]);
+                }
+                if pair[1].radius > 0.0 {
+                    self.indices.extend_from_slice(&[a, c, d]);
+                }
+            }
+        }
+    }
+
+    /// Disc of `radius` at height `y`, facing up when `y` is positive and down otherwise
+    fn cap(&mut self, radius: f32, y: f32, sectors: u32) {
+        let sectors = sectors.max(3);
+        let up = y > 0.0;
+        let normal = [0.0, if up { 1.0 } else { -1.0 }, 0.0];
+        let center = self.vertices.len() as u32;
+        self.vertices.push(PrimitiveVertex { position: [0.0, y, 0.0], normal, uv: [0.5, 0.5] });
+        for j in 0..=sectors {
+            let (sin, cos) = (j as f32 / sectors as f32 * TAU).sin_cos();
+            self.vertices.push(PrimitiveVertex {
+                position: [radius * cos, y, -radius * sin],
+                normal,
+                uv: [0.5 + 0.5 * cos, 0.5 + if up { 0.5 } else { -0.5 } * sin],
+            });
+        }
+        for j in 0..sectors {
+            let (a, b) = (center + 1 + j, center + 2 + j);
+            if up {
+                self.indices.extend_from_slice(&[center, a, b]);
+            } else {
+                self.indices.extend_from_slice(&[center, b, a]);
+            }
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Profiler instrumentation
+//!
+//! With the `tracy` feature the app connects to a running Tracy profiler: `scope` marks
+//! CPU work (ECS schedule, command recording, submit, present), `frame_mark` ends a frame,
+//! and `GpuProfiler` turns timestamp queries written around the graphics passes into GPU
+//! zones. Timestamps of a frame slot are read back once its fence has been waited on, so
+//! GPU zones show up `MAX_FRAMES_IN_FLIGHT` frames late. Without the feature everything
+//! here is a no-op and no query pool is created.
+
+use ash::vk;
+use crate::renderer::VulkanRenderer;
+#[cfg(feature = "tracy")]
+use crate::renderer::MAX_FRAMES_IN_FLIGHT;
+
+/// Begin + end timestamps of up to this many GPU zones per frame
+const MAX_GPU_ZONES: u32 = 32;
+const QUERIES_PER_FRAME: u32 = 2 * MAX_GPU_ZONES;
+
+/// Connect to Tracy; call once before any other instrumentation
+pub fn start() {
+    #[cfg(feature = "tracy")]
+    {
+        tracy_client::Client::start();
+        println!("✓ Tracy profiler client started");
+    }
+}
+
+/// CPU zone, ended when dropped
+pub struct Scope {
+    #[cfg(feature = "tracy")]
+    _span: Option<tracy_client::Span>,
+}
+
+impl Scope {
+    /// End the zone before the enclosing block does
+    pub fn end(self) {}
+}
+
+/// Time the rest of the enclosing block: `let _scope = profiling::scope("Present");`
+#[track_caller]
+pub fn scope(name: &str) -> Scope {
+    #[cfg(feature = "tracy")]
+    {
+        let location = std::panic::Location::caller();
+        Scope {
+            _span: tracy_client::Client::running()
+                .map(|client| client.span_alloc(Some(name), "", location.file(), location.line(), 0)),
+        }
+    }
+    #[cfg(not(feature = "tracy"))]
+    {
+        let _ = name;
+        Scope {}
+    }
+}
+
+/// End the current frame (after present)
+pub fn frame_mark() {
+    #[cfg(feature = "tracy")]
+    if let Some(client) = tracy_client::Client::running() {
+        client.frame_mark();
+    }
+}
+
+/// A GPU zone whose timestamps have been recorded but not read back yet
+#[cfg(feature = "tracy")]
+struct PendingZone {
+    span: tracy_client::GpuSpan,
+    begin_query: u32,
+    end_query: u32,
+}
+
+/// GPU zones from timestamp queries in the main graphics command buffer
+#[cfg_attr(not(feature = "tracy"), allow(dead_code))]
+pub struct GpuProfiler {
+    query_pool: vk::QueryPool,
+    next_query: Vec<u32>, // Per frame in flight, relative to the frame's first query
+    #[cfg(feature = "tracy")]
+    context: tracy_client::GpuContext,
+    #[cfg(feature = "tracy")]
+    open_zones: Vec<Option<(tracy_client::GpuSpan, u32)>>, // None for zones over budget
+    #[cfg(feature = "tracy")]
+    pending_zones: Vec<Vec<PendingZone>>,
+}
+
+impl GpuProfiler {
+    /// Returns `None` without the `tracy` feature, a running Tracy client or timestamp
+    /// support on the graphics queue.
+    pub unsafe fn new(renderer: &VulkanRenderer) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        #[cfg(feature = "tracy")]
+        {
+            let Some(client) = tracy_client::Client::running() else {
+                return Ok(None);
+            };
+            let properties = renderer.instance.get_physical_device_properties(renderer.physical_device);
+            let families = renderer
+                .instance
+                .get_physical_device_queue_family_properties(renderer.physical_device);
+            if families[renderer.graphics_queue_family_index as usize].timestamp_valid_bits == 0 {
+                println!("ℹ No timestamp support on the graphics queue, Tracy shows CPU zones only");
+                return Ok(None);
+            }
+
+            let device = &renderer.device;
+            let query_info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(QUERIES_PER_FRAME * MAX_FRAMES_IN_FLIGHT as u32);
+            let query_pool = device.create_query_pool(&query_info, None)?;
+
+            // Tracy needs one GPU timestamp taken "now" to line the GPU clock up with the CPU's
+            let command_buffer = device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(renderer.command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1),
+            )?[0];
+            device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+            device.cmd_reset_query_pool(command_buffer, query_pool, 0, QUERIES_PER_FRAME * MAX_FRAMES_IN_FLIGHT as u32);
+            device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, query_pool, 0);
+            device.end_command_buffer(command_buffer)?;
+            let command_buffers = [command_buffer];
+            device.queue_submit(
+                renderer.graphics_queue,
+                &[vk::SubmitInfo::default().command_buffers(&command_buffers)],
+                vk::Fence::null(),
+            )?;
+            device.queue_wait_idle(renderer.graphics_queue)?;
+            device.free_command_buffers(renderer.command_pool, &command_buffers);
+
+            let mut timestamp = [0u64; 1];
+            device.get_query_pool_results(
+                query_pool,
+                0,
+                &mut timestamp,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )?;
+            let context = client.new_gpu_context(
+                Some(&renderer.gpu_name),
+                tracy_client::GpuContextType::Vulkan,
+                timestamp[0] as i64,
+                properties.limits.timestamp_period,
+            )?;
+
+            println!("✓ Tracy GPU zones from {} timestamp queries per frame", QUERIES_PER_FRAME);
+
+            Ok(Some(Self {
+                query_pool,
+                next_query: vec![QUERIES_PER_FRAME; MAX_FRAMES_IN_FLIGHT], // Every query was reset above
+                context,
+                open_zones: Vec::new(),
+                pending_zones: (0..MAX_FRAMES_IN_FLIGHT).map(|_| Vec::new()).collect(),
+            }))
+        }
+        #[cfg(not(feature = "tracy"))]
+        {
+            let _ = renderer;
+            Ok(None)
+        }
+    }
+
+    /// Upload this frame slot's previous timestamps to Tracy and reset its queries. Record
+    /// first in the frame's command buffer, after its fence has been waited on.
+    pub unsafe fn begin_frame(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        let first = frame_index as u32 * QUERIES_PER_FRAME;
+        #[cfg(feature = "tracy")]
+        {
+            let used = self.next_query[frame_index].min(QUERIES_PER_FRAME) as usize;
+            let zones = std::mem::take(&mut self.pending_zones[frame_index]);
+            let mut timestamps = vec![0u64; used];
+            if used > 0 && !zones.is_empty() {
+                let read = device.get_query_pool_results(self.query_pool, first, &mut timestamps, vk::QueryResultFlags::TYPE_64);
+                if read.is_ok() {
+                    // Tracy wants timestamps in the order they were written (outer begin,
+                    // inner begin, inner end, outer end), which is query order
+                    let mut events: Vec<(u32, usize, bool)> = zones
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(i, zone)| [(zone.begin_query, i, true), (zone.end_query, i, false)])
+                        .collect();
+                    events.sort_by_key(|&(query, _, _)| query);
+                    for (query, i, is_begin) in events {
+                        let timestamp = timestamps[query as usize] as i64;
+                        if is_begin {
+                            zones[i].span.upload_timestamp_start(timestamp);
+                        } else {
+                            zones[i].span.upload_timestamp_end(timestamp);
+                        }
+                    }
+                }
+            }
+        }
+        device.cmd_reset_query_pool(command_buffer, self.query_pool, first, QUERIES_PER_FRAME);
+        self.next_query[frame_index] = 0;
+    }
+
+    /// Start a GPU zone; close it with `end_zone` in the same command buffer. Zones past the
+    /// per-frame budget are dropped.
+    #[track_caller]
+    pub unsafe fn begin_zone(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        name: &str,
+    ) {
+        #[cfg(feature = "tracy")]
+        {
+            // Keep an end query free for every open zone
+            let query = self.next_query[frame_index];
+            if query + 2 * (self.open_zones.len() as u32 + 1) > QUERIES_PER_FRAME {
+                self.open_zones.push(None);
+                return;
+            }
+            let location = std::panic::Location::caller();
+            let Ok(span) = self.context.span_alloc(name, "", location.file(), location.line()) else {
+                self.open_zones.push(None);
+                return;
+            };
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                frame_index as u32 * QUERIES_PER_FRAME + query,
+            );
+            self.next_query[frame_index] += 1;
+            self.open_zones.push(Some((span, query)));
+        }
+        #[cfg(not(feature = "tracy"))]
+        let _ = (device, command_buffer, frame_index, name);
+    }
+
+    /// End the innermost zone started with `begin_zone`
+    pub unsafe fn end_zone(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        #[cfg(feature = "tracy")]
+        {
+            let Some(Some((mut span, begin_query))) = self.open_zones.pop() else {
+                return;
+            };
+            let end_query = self.next_query[frame_index];
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                frame_index as u32 * QUERIES_PER_FRAME + end_query,
+            );
+            span.end_zone();
+            self.next_query[frame_index] += 1;
+            self.pending_zones[frame_index].push(PendingZone { span, begin_query, end_query });
+        }
+        #[cfg(not(feature = "tracy"))]
+        let _ = (device, command_buffer, frame_index);
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        renderer.device.destroy_query_pool(self.query_pool, None);
+    }
+}
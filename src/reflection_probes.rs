@@ -0,0 +1,305 @@
+//! Reflection probes
+//!
+//! A reflection probe is a capture point with a box around it, usually fitted to a room.
+//! Baking renders the six cube faces of the glTF scene at the capture point (see
+//! `screenshot::render_cube_faces`) into its six layers of one 2D array image, with a mip
+//! chain that rougher materials sample further down.
+//!
+//! `gltf.frag` picks, per object, the smallest baked box containing the object's origin and
+//! reflects the view ray off each fragment. The ray is intersected with the box and the
+//! face is looked up in the direction of that hit from the capture point (box projection),
+//! so reflections of the walls line up with them instead of sitting at infinity.
+
+use ash::vk;
+use glam::Vec3;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+
+use crate::gltf_loader::GltfTexture;
+use crate::gltf_renderer::GltfRenderer;
+use crate::lightmap;
+use crate::renderer::VulkanRenderer;
+use crate::sampler_cache::SamplerDesc;
+use crate::screenshot;
+use crate::texture_streaming::{self, MipLevel};
+
+/// Probes the shaders can pick from; must match `gltf.frag`
+pub const MAX_REFLECTION_PROBES: usize = 4;
+/// Edge of a probe's cube faces, in pixels
+const FACE_SIZE: u32 = 128;
+const FACE_MIP_LEVELS: u32 = FACE_SIZE.ilog2() + 1;
+
+#[derive(Clone, Copy)]
+pub struct ReflectionProbe {
+    pub position: Vec3,     // Where the faces are captured, the center of the box
+    pub half_extents: Vec3, // Of the box
+    pub baked: bool,
+}
+
+/// Probe boxes as laid out in the glTF uniform buffer
+pub struct ReflectionProbeUniforms {
+    pub boxes: [[f32; 4]; MAX_REFLECTION_PROBES * 2], // Per probe: xyz = position, w = 1 if baked; xyz = half extents
+    pub params: [f32; 4], // x = probe count, y = intensity, z = lowest mip
+}
+
+/// The probes in the scene and the faces they captured, six layers per probe
+pub struct ReflectionProbes {
+    pub probes: Vec<ReflectionProbe>,
+    pub intensity: f32,
+    image: vk::Image,
+    view: vk::ImageView,
+    sampler: vk::Sampler,
+    allocation: Option<Allocation>,
+}
+
+impl ReflectionProbes {
+    pub unsafe fn new(renderer: &VulkanRenderer) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let image_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::R8G8B8A8_SRGB)
+            .extent(vk::Extent3D { width: FACE_SIZE, height: FACE_SIZE, depth: 1 })
+            .mip_levels(FACE_MIP_LEVELS)
+            .array_layers(MAX_REFLECTION_PROBES as u32 * 6)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let image = device.create_image(&image_info, None)?;
+        let requirements = device.get_image_memory_requirements(image);
+        let allocation = match renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: "reflection_probes",
+            requirements,
+            location: MemoryLocation::GpuOnly,
+            linear: false,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        }) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy_image(image, None);
+                return Err(e.into());
+            }
+        };
+        let mut probes = Self {
+            probes: Vec::new(),
+            intensity: 1.0,
+            image,
+            view: vk::ImageView::null(),
+            sampler: vk::Sampler::null(),
+            allocation: None,
+        };
+        let bound = device.bind_image_memory(image, allocation.memory(), allocation.offset());
+        probes.allocation = Some(allocation);
+        if let Err(e) = bound {
+            probes.destroy(renderer);
+            return Err(e.into());
+        }
+
+        // Unbaked layers are never sampled, but the whole image needs the sampled layout
+        let transitioned = lightmap::submit_once(renderer, |device, cmd| {
+            probes.barrier(device, cmd, vk::ImageLayout::UNDEFINED, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        });
+        let view_info = vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+            .format(vk::Format::R8G8B8A8_SRGB)
+            .subresource_range(Self::range());
+        match transitioned.and_then(|()| Ok(device.create_image_view(&view_info, None)?)) {
+            Ok(view) => probes.view = view,
+            Err(e) => {
+                probes.destroy(renderer);
+                return Err(e);
+            }
+        }
+        probes.sampler = renderer.sampler(
+            SamplerDesc::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                .max_lod(FACE_MIP_LEVELS - 1),
+        )?;
+        Ok(probes)
+    }
+
+    pub fn baked_count(&self) -> usize {
+        self.probes.iter().filter(|p| p.baked).count()
+    }
+
+    pub fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: self.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+
+    /// The first `MAX_REFLECTION_PROBES` probes; probe `i` owns layers `6 * i` to `6 * i + 5`
+    pub fn uniforms(&self) -> ReflectionProbeUniforms {
+        let mut uniforms = ReflectionProbeUniforms {
+            boxes: [[0.0; 4]; MAX_REFLECTION_PROBES * 2],
+            params: [0.0, self.intensity, (FACE_MIP_LEVELS - 1) as f32, 0.0],
+        };
+        for (i, probe) in self.probes.iter().take(MAX_REFLECTION_PROBES).enumerate() {
+            uniforms.boxes[i * 2] = probe.position.extend(if probe.baked { 1.0 } else { 0.0 }).to_array();
+            uniforms.boxes[i * 2 + 1] = probe.half_extents.extend(0.0).to_array();
+            uniforms.params[0] = (i + 1) as f32;
+        }
+        uniforms
+    }
+
+    fn range() -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: FACE_MIP_LEVELS,
+            base_array_layer: 0,
+            layer_count: MAX_REFLECTION_PROBES as u32 * 6,
+        }
+    }
+
+    /// Layout transition of the whole image between fragment shader reads and transfer writes
+    unsafe fn barrier(&self, device: &ash::Device, cmd: vk::CommandBuffer, old: vk::ImageLayout, new: vk::ImageLayout) {
+        let to_transfer = new == vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+        let (src_access, src_stage) = match old {
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL => (vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+            vk::ImageLayout::UNDEFINED => (vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+            _ => (vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER),
+        };
+        let (dst_access, dst_stage) = if to_transfer {
+            (vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER)
+        } else {
+            (vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER)
+        };
+        let barrier = vk::ImageMemoryBarrier::default()
+            .old_layout(old)
+            .new_layout(new)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(Self::range())
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access);
+        device.cmd_pipeline_barrier(
+            cmd,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            std::slice::from_ref(&barrier),
+        );
+    }
+
+    /// Replace the first `faces.len()` layers, each a full mip chain. The device must be idle.
+    unsafe fn upload(&self, renderer: &VulkanRenderer, faces: &[Vec<MipLevel>]) -> Result<(), Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let size: u64 = faces.iter().flatten().map(MipLevel::bytes).sum();
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(size)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = device.create_buffer(&buffer_info, None)?;
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let allocation = match renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: "reflection_probe_staging",
+            requirements,
+            location: MemoryLocation::CpuToGpu,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        }) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy_buffer(buffer, None);
+                return Err(e.into());
+            }
+        };
+        device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+
+        let ptr = allocation.mapped_ptr().unwrap().as_ptr() as *mut u8;
+        let mut regions = Vec::new();
+        let mut offset = 0;
+        for (layer, mips) in faces.iter().enumerate() {
+            for (level, mip) in mips.iter().enumerate() {
+                std::ptr::copy_nonoverlapping(mip.data.as_ptr(), ptr.add(offset as usize), mip.data.len());
+                regions.push(vk::BufferImageCopy {
+                    buffer_offset: offset,
+                    buffer_row_length: 0,
+                    buffer_image_height: 0,
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: level as u32,
+                        base_array_layer: layer as u32,
+                        layer_count: 1,
+                    },
+                    image_offset: vk::Offset3D::default(),
+                    image_extent: vk::Extent3D { width: mip.width, height: mip.height, depth: 1 },
+                });
+                offset += mip.bytes();
+            }
+        }
+
+        let copied = lightmap::submit_once(renderer, |device, cmd| {
+            let (sampled, transfer) = (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+            self.barrier(device, cmd, sampled, transfer);
+            device.cmd_copy_buffer_to_image(cmd, buffer, self.image, transfer, &regions);
+            self.barrier(device, cmd, transfer, sampled);
+        });
+        device.destroy_buffer(buffer, None);
+        renderer.allocator.lock().free(allocation)?;
+        copied
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        // The sampler belongs to the renderer's sampler cache
+        renderer.device.destroy_image_view(self.view, None);
+        renderer.device.destroy_image(self.image, None);
+        if let Some(allocation) = self.allocation.take() {
+            renderer.allocator.lock().free(allocation).ok();
+        }
+    }
+}
+
+/// Bake the first `MAX_REFLECTION_PROBES` probes of `gltf_renderer` from the scene as it
+/// currently draws it, reflections of earlier bakes included. Waits for the device to go
+/// idle first and leaves it idle.
+pub unsafe fn bake(
+    renderer: &VulkanRenderer,
+    gltf_renderer: &mut GltfRenderer,
+    shadow_softness: f32,
+    use_pcss: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let positions: Vec<Vec3> = gltf_renderer
+        .reflection_probes
+        .probes
+        .iter()
+        .take(MAX_REFLECTION_PROBES)
+        .map(|probe| probe.position)
+        .collect();
+    println!("🪞 Baking {} reflection probe(s)", positions.len());
+
+    let mut faces = Vec::with_capacity(positions.len() * 6);
+    for &position in &positions {
+        let rendered = screenshot::render_cube_faces(
+            renderer,
+            gltf_renderer,
+            position,
+            FACE_SIZE,
+            false,
+            shadow_softness,
+            use_pcss,
+        )?;
+        faces.extend(rendered.into_iter().map(|face| {
+            let texture = GltfTexture { width: FACE_SIZE, height: FACE_SIZE, data: face.pixels, compressed: None };
+            texture_streaming::build_mip_chain(&texture)
+        }));
+    }
+    gltf_renderer.reflection_probes.upload(renderer, &faces)?;
+
+    for probe in gltf_renderer.reflection_probes.probes.iter_mut().take(MAX_REFLECTION_PROBES) {
+        probe.baked = true;
+    }
+    if gltf_renderer.reflection_probes.probes.len() > MAX_REFLECTION_PROBES {
+        println!("⚠ Only the first {} reflection probes are used", MAX_REFLECTION_PROBES);
+    }
+    println!("✓ Baked {} reflection probe(s)", positions.len());
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! Render layers
+//!
+//! Every entity with a `Transform` is on one or more of `RenderLayers::COUNT` layers, and
+//! a camera draws the entities that share a layer with it. The main camera's layers are
+//! those of its entity; view windows, stills, panoramas and AOV exports each carry their
+//! own. Everything starts out on the scene layer only.
+//!
+//! The gizmos layer holds the debug geometry (`DebugDraw`), which only the main view can
+//! show. Captures always leave it out, whatever the main camera shows.
+//!
+//! A glTF mesh is drawn where its `GltfMeshPart` entity, the model entity and the glTF
+//! node entities between them are all seen, so a model's layers limit those of its meshes.
+
+use bevy_ecs::prelude::*;
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RenderLayers(pub u32);
+
+impl RenderLayers {
+    pub const COUNT: u32 = 8;
+    pub const SCENE: Self = Self(1 << 0);
+    pub const GIZMOS: Self = Self(1 << 1);
+
+    /// Just layer `layer`
+    pub const fn layer(layer: u32) -> Self {
+        Self(1 << layer)
+    }
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn contains(self, layer: u32) -> bool {
+        self.intersects(Self::layer(layer))
+    }
+
+    pub fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// The layers both are on
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// What the UI calls layer `layer`
+    pub fn name(layer: u32) -> String {
+        match layer {
+            0 => "Scene".to_string(),
+            1 => "Gizmos".to_string(),
+            _ => format!("Layer {}", layer),
+        }
+    }
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        Self::SCENE
+    }
+}
@@ -0,0 +1,259 @@
+//! Render targets
+//!
+//! A `RenderTarget` owns the images a pass renders into: an optional color and an optional
+//! depth attachment of one extent, each with its memory and views, and remembers how they
+//! were made so `resize` can make them again at another size. Layered targets (such as the
+//! shadow cascades) get an array view of all layers for sampling plus one view per layer
+//! for framebuffers.
+//!
+//! Targets don't own framebuffers or descriptor sets: whoever built those from a target's
+//! views rebuilds them after `resize` reports that the images changed.
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+use crate::renderer::VulkanRenderer;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttachmentDesc {
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
+}
+
+/// What a target is made of; its extent is given separately
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderTargetDesc {
+    pub color: Option<AttachmentDesc>,
+    pub depth: Option<AttachmentDesc>,
+    pub layers: u32,
+}
+
+impl RenderTargetDesc {
+    pub fn color(format: vk::Format, usage: vk::ImageUsageFlags) -> Self {
+        Self { color: Some(AttachmentDesc { format, usage }), depth: None, layers: 1 }
+    }
+
+    pub fn depth(format: vk::Format, usage: vk::ImageUsageFlags) -> Self {
+        Self { color: None, depth: Some(AttachmentDesc { format, usage }), layers: 1 }
+    }
+
+    pub fn with_depth(mut self, format: vk::Format, usage: vk::ImageUsageFlags) -> Self {
+        self.depth = Some(AttachmentDesc { format, usage });
+        self
+    }
+
+    pub fn layers(mut self, layers: u32) -> Self {
+        self.layers = layers;
+        self
+    }
+}
+
+/// One attachment of a target
+pub struct TargetImage {
+    pub image: vk::Image,
+    pub view: vk::ImageView,             // All layers; a 2D array view when layered
+    pub layer_views: Vec<vk::ImageView>, // One 2D view per layer when layered, otherwise empty
+    allocation: Option<Allocation>,
+}
+
+pub struct RenderTarget {
+    name: &'static str,
+    desc: RenderTargetDesc,
+    pub extent: vk::Extent2D,
+    color: Option<TargetImage>,
+    depth: Option<TargetImage>,
+}
+
+impl RenderTarget {
+    /// # Safety
+    ///
+    /// `renderer` must outlive the target.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        name: &'static str,
+        desc: RenderTargetDesc,
+        extent: vk::Extent2D,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut target = Self { name, desc, extent, color: None, depth: None };
+        if let Err(e) = target.create_images(renderer) {
+            target.destroy(renderer);
+            return Err(e);
+        }
+        Ok(target)
+    }
+
+    /// The color attachment; panics if the target has none
+    pub fn color(&self) -> &TargetImage {
+        self.color.as_ref().expect("render target has no color attachment")
+    }
+
+    /// The depth attachment; panics if the target has none
+    pub fn depth(&self) -> &TargetImage {
+        self.depth.as_ref().expect("render target has no depth attachment")
+    }
+
+    /// Recreate the images at `extent`, unless they already are that size. Returns whether
+    /// they were recreated, in which case framebuffers and descriptors using the old views
+    /// must be rebuilt and the contents are undefined.
+    ///
+    /// # Safety
+    ///
+    /// No submitted work may still use the images.
+    pub unsafe fn resize(
+        &mut self,
+        renderer: &VulkanRenderer,
+        extent: vk::Extent2D,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if extent == self.extent && (self.color.is_some() || self.depth.is_some()) {
+            return Ok(false);
+        }
+        self.destroy(renderer);
+        self.extent = extent;
+        self.create_images(renderer)?;
+        Ok(true)
+    }
+
+    /// Destroy the images. The target can be brought back with `resize`.
+    ///
+    /// # Safety
+    ///
+    /// No submitted work may still use the images.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        for mut target_image in [self.color.take(), self.depth.take()].into_iter().flatten() {
+            for view in target_image.layer_views.drain(..) {
+                renderer.device.destroy_image_view(view, None);
+            }
+            renderer.device.destroy_image_view(target_image.view, None);
+            renderer.device.destroy_image(target_image.image, None);
+            if let Some(allocation) = target_image.allocation.take() {
+                let _ = renderer.allocator.lock().free(allocation);
+            }
+        }
+    }
+
+    unsafe fn create_images(&mut self, renderer: &VulkanRenderer) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(color) = self.desc.color {
+            self.color = Some(self.create_image(renderer, color, vk::ImageAspectFlags::COLOR)?);
+        }
+        if let Some(depth) = self.desc.depth {
+            self.depth = Some(self.create_image(renderer, depth, vk::ImageAspectFlags::DEPTH)?);
+        }
+        Ok(())
+    }
+
+    unsafe fn create_image(
+        &self,
+        renderer: &VulkanRenderer,
+        attachment: AttachmentDesc,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> Result<TargetImage, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let layers = self.desc.layers;
+        let image_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(attachment.format)
+            .extent(vk::Extent3D { width: self.extent.width, height: self.extent.height, depth: 1 })
+            .mip_levels(1)
+            .array_layers(layers)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(attachment.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let image = device.create_image(&image_info, None)?;
+        let requirements = device.get_image_memory_requirements(image);
+
+        let allocation = match renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: self.name,
+            requirements,
+            location: MemoryLocation::GpuOnly,
+            linear: false,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        }) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy_image(image, None);
+                return Err(e.into());
+            }
+        };
+        let (memory, offset) = (allocation.memory(), allocation.offset());
+        // From here on `target_image` owns what exists, so errors can clean up through it
+        let mut target_image = TargetImage {
+            image,
+            view: vk::ImageView::null(),
+            layer_views: Vec::new(),
+            allocation: Some(allocation),
+        };
+        let result = (|| -> Result<(), vk::Result> {
+            device.bind_image_memory(image, memory, offset)?;
+            let view_info = |view_type, base_array_layer, layer_count| {
+                vk::ImageViewCreateInfo::default()
+                    .image(image)
+                    .view_type(view_type)
+                    .format(attachment.format)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask,
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer,
+                        layer_count,
+                    })
+            };
+            if layers == 1 {
+                target_image.view = device.create_image_view(&view_info(vk::ImageViewType::TYPE_2D, 0, 1), None)?;
+            } else {
+                target_image.view =
+                    device.create_image_view(&view_info(vk::ImageViewType::TYPE_2D_ARRAY, 0, layers), None)?;
+                for layer in 0..layers {
+                    let view = device.create_image_view(&view_info(vk::ImageViewType::TYPE_2D, layer, 1), None)?;
+                    target_image.layer_views.push(view);
+                }
+            }
+            Ok(())
+        })();
+
+        if let Err(e) = result {
+            for view in target_image.layer_views.drain(..) {
+                device.destroy_image_view(view, None);
+            }
+            if target_image.view != vk::ImageView::null() {
+                device.destroy_image_view(target_image.view, None);
+            }
+            device.destroy_image(image, None);
+            if let Some(allocation) = target_image.allocation.take() {
+                let _ = renderer.allocator.lock().free(allocation);
+            }
+            return Err(e.into());
+        }
+        Ok(target_image)
+    }
+}
+
+/// Make `targets` `count` targets of `extent`: resize the ones there are and create or
+/// destroy the difference. Returns whether any images were (re)created. Used for targets
+/// kept per swapchain image.
+///
+/// # Safety
+///
+/// No submitted work may still use any image in `targets`.
+pub unsafe fn resize_targets(
+    renderer: &VulkanRenderer,
+    targets: &mut Vec<RenderTarget>,
+    count: usize,
+    name: &'static str,
+    desc: RenderTargetDesc,
+    extent: vk::Extent2D,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut changed = false;
+    for mut target in targets.drain(count.min(targets.len())..) {
+        target.destroy(renderer);
+    }
+    for target in targets.iter_mut() {
+        changed |= target.resize(renderer, extent)?;
+    }
+    while targets.len() < count {
+        targets.push(RenderTarget::new(renderer, name, desc, extent)?);
+        changed = true;
+    }
+    Ok(changed)
+}
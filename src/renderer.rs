@@ -1,12 +1,21 @@
 use ash::vk;
 use ash::{Device, Entry, Instance};
-use gpu_allocator::vulkan::{Allocator, AllocatorCreateDesc};
+use gpu_allocator::vulkan::{Allocation, Allocator, AllocatorCreateDesc};
 use gpu_allocator::AllocationSizes;
 use parking_lot::Mutex;
 use std::ffi::CString;
 use std::sync::Arc;
 use winit::raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 
+use crate::debug_messenger::{self, DebugMessenger};
+use crate::frame_arena::{FrameArena, FRAME_ARENA_SIZE};
+use crate::frame_hooks::{FrameContext, FramePass};
+use crate::pipeline_builder::{GraphicsPipelineBuilder, VertexLayout};
+use crate::sampler_cache::{SamplerCache, SamplerDesc};
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+use crate::swapchain::{PresentModeConfig, SurfaceSupport};
+
 pub struct VulkanRenderer {
     pub entry: Entry,
     pub instance: Instance,
@@ -21,6 +30,10 @@ pub struct VulkanRenderer {
     pub swapchain_images: Vec<vk::Image>,
     pub swapchain_image_views: Vec<vk::ImageView>,
     pub swapchain_format: vk::Format,
+    pub swapchain_color_space: vk::ColorSpaceKHR,
+    pub swapchain_usage: vk::ImageUsageFlags,
+    pub present_mode: vk::PresentModeKHR, // Negotiated from `present_mode_config`
+    pub present_mode_config: PresentModeConfig, // Kept across swapchain recreation, see `set_present_mode`
     pub swapchain_extent: vk::Extent2D,
     pub render_pass: vk::RenderPass,
     pub framebuffers: Vec<vk::Framebuffer>,
@@ -33,20 +46,139 @@ pub struct VulkanRenderer {
     pub in_flight_fences: Vec<vk::Fence>,
     pub images_in_flight: Vec<vk::Fence>, // Track which fence is used by each swapchain image
     pub current_frame: usize,
+    pub frames_in_flight: usize, // Frame slots in use, up to `MAX_FRAMES_IN_FLIGHT`
+    pub requested_swapchain_images: Option<u32>, // Kept across swapchain recreation
     pub allocator: Arc<Mutex<Allocator>>,
+    pub samplers: SamplerCache, // Shared by every renderer, see `sampler`
+    pub frame_arena: FrameArena, // Transient per-frame buffers and descriptor sets, see frame_arena.rs
     pub descriptor_set_layout: vk::DescriptorSetLayout,
-    pub descriptor_pool: vk::DescriptorPool,
-    pub descriptor_sets: Vec<vk::DescriptorSet>,
     pub graphics_queue_family_index: u32,
+    pub present_queue_family_index: u32, // Same as graphics unless that family can't present
+    pub compute_queue: Option<vk::Queue>, // Dedicated compute-only queue for async work, if the device has one
+    pub compute_queue_family_index: Option<u32>,
+    pub ray_query_supported: bool, // VK_KHR_ray_query + acceleration structures enabled (see acceleration_structure.rs)
+    pub mesh_shader_supported: bool, // VK_EXT_mesh_shader task + mesh shaders enabled (see meshlets.rs)
+    pub multi_draw_indirect_supported: bool, // Several draws per indirect call (see gpu_driven.rs)
+    pub draw_indirect_count_supported: bool, // Multi-draw indirect with a GPU-written count (see gpu_driven.rs)
+    pub comparison_samplers_supported: bool, // False only on portability subset devices without them
+    pub max_anisotropy: u32, // Highest sampler anisotropy, up to 16; 0 without samplerAnisotropy
+    pub texture_compression_bc_supported: bool, // BC1-BC7 images, which KTX2 textures hold (see ktx2.rs)
+    pub occlusion_query_precise_supported: bool, // Occlusion queries count samples instead of only flagging any
+    pub descriptor_buffer_supported: bool, // VK_EXT_descriptor_buffer enabled (see descriptor_buffer.rs)
+    pub push_descriptor_supported: bool, // VK_KHR_push_descriptor enabled (see material.rs)
+    pub pipeline_statistics_supported: bool, // Pipeline statistics queries (see pipeline_statistics.rs)
     pub framebuffer_resized: bool,
+    pub passes: Vec<FramePass>, // Custom passes, see `add_pass`
     pub gpu_name: String,
     pub vulkan_version: String,
+    pub device_extensions: Vec<String>, // Enabled device extensions, for diagnostics
+    pub debug_messenger: Option<DebugMessenger>, // Prints validation messages, when validating
+    // Headless only: memory of the images standing in for the swapchain's
+    pub headless_image_allocations: Vec<Option<Allocation>>,
 }
 
+/// Frames the CPU may record ahead of the GPU at most. Per-frame resources are made for
+/// this many; a renderer cycles through the first `frames_in_flight` of them.
 pub const MAX_FRAMES_IN_FLIGHT: usize = 3;
+/// Color format of headless renderers, the one most desktop surfaces report first
+pub const HEADLESS_FORMAT: vk::Format = vk::Format::B8G8R8A8_UNORM;
+
+/// Buffering settings for a new renderer, for trading latency against throughput: fewer
+/// swapchain images and frames in flight show input sooner, more keep the GPU busier.
+/// Requests the device can't meet are clamped to what it supports.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RendererBuilder {
+    swapchain_images: Option<u32>,   // None: one more than the surface's minimum
+    frames_in_flight: Option<usize>, // None: `MAX_FRAMES_IN_FLIGHT`
+    validation: bool,                // Also on with `FUNKY_VALIDATION` set, see debug_messenger.rs
+    present_mode: PresentModeConfig,
+}
+
+impl RendererBuilder {
+    /// 2 for double buffering, 3 for triple buffering
+    pub fn swapchain_images(mut self, count: u32) -> Self {
+        self.swapchain_images = Some(count);
+        self
+    }
+
+    /// Frames the CPU may record ahead of the GPU, 1 to `MAX_FRAMES_IN_FLIGHT`
+    pub fn frames_in_flight(mut self, count: usize) -> Self {
+        self.frames_in_flight = Some(count);
+        self
+    }
+
+    /// VSync or not; see `PresentModeConfig` for the fallbacks when the surface lacks a mode
+    pub fn present_mode(mut self, config: PresentModeConfig) -> Self {
+        self.present_mode = config;
+        self
+    }
+
+    /// Enable the Khronos validation layer and print its messages, where it's installed
+    pub fn validation(mut self, enabled: bool) -> Self {
+        self.validation = enabled;
+        self
+    }
+
+    /// A renderer for `window`, see `VulkanRenderer::new_headless` for one without
+    ///
+    /// # Safety
+    ///
+    /// `window` must outlive the renderer.
+    pub unsafe fn build(self, window: &winit::window::Window) -> Result<VulkanRenderer, Box<dyn std::error::Error>> {
+        VulkanRenderer::create(Some(window), vk::Extent2D::default(), self)
+    }
+}
 
 impl VulkanRenderer {
-    pub unsafe fn new(window: &winit::window::Window) -> Result<Self, Box<dyn std::error::Error>> {
+    /// A renderer without a window, surface or swapchain, for tests and batch rendering.
+    /// A single offscreen image of `extent` stands in for the swapchain, so renderers built
+    /// on top of it work unchanged; draw through offscreen targets and read them back.
+    /// Prefers CPU implementations (lavapipe, SwiftShader) when installed, which render the
+    /// same everywhere.
+    ///
+    /// # Safety
+    ///
+    /// Everything created on the renderer must be destroyed before it is dropped.
+    #[cfg_attr(not(test), allow(dead_code))] // The app always has a window; see test_support.rs
+    pub unsafe fn new_headless(extent: vk::Extent2D) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut renderer = Self::create(None, extent, RendererBuilder::default())?;
+        
+        let (image, view, allocation) = crate::offscreen::create_image(
+            &renderer,
+            "headless_color",
+            HEADLESS_FORMAT,
+            extent,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+        renderer.swapchain_images.push(image);
+        renderer.swapchain_image_views.push(view);
+        renderer.headless_image_allocations.push(Some(allocation));
+        renderer.images_in_flight.push(vk::Fence::null());
+        
+        let attachments = [view];
+        let framebuffer_info = vk::FramebufferCreateInfo::default()
+            .render_pass(renderer.render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = renderer.device.create_framebuffer(&framebuffer_info, None)?;
+        renderer.framebuffers.push(framebuffer);
+        
+        Ok(renderer)
+    }
+    
+    /// Shared setup; without a window the swapchain stays empty (see `new_headless`)
+    unsafe fn create(
+        window: Option<&winit::window::Window>,
+        headless_extent: vk::Extent2D,
+        builder: RendererBuilder,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let frames_in_flight = builder.frames_in_flight.unwrap_or(MAX_FRAMES_IN_FLIGHT).clamp(1, MAX_FRAMES_IN_FLIGHT);
+        if builder.frames_in_flight.is_some_and(|requested| requested != frames_in_flight) {
+            println!("⚠ Frames in flight must be 1 to {}, using {}", MAX_FRAMES_IN_FLIGHT, frames_in_flight);
+        }
         let entry = Entry::linked();
         
         // Create instance
@@ -60,35 +192,78 @@ impl VulkanRenderer {
             .engine_version(vk::make_api_version(0, 1, 0, 0))
             .api_version(vk::API_VERSION_1_2);
         
-        let extension_names = ash_window::enumerate_required_extensions(
-            window.display_handle()?.as_raw()
-        )?.to_vec();
+        let mut extension_names = match window {
+            Some(window) => ash_window::enumerate_required_extensions(window.display_handle()?.as_raw())?.to_vec(),
+            None => Vec::new(),
+        };
         
-        let create_info = vk::InstanceCreateInfo::default()
+        // MoltenVK is a portability (non-conformant) implementation: the loader only lists it
+        // when the app opts in to enumerating those
+        let portability_enumeration = entry
+            .enumerate_instance_extension_properties(None)?
+            .iter()
+            .any(|ext| ext.extension_name_as_c_str() == Ok(ash::khr::portability_enumeration::NAME));
+        let mut instance_flags = vk::InstanceCreateFlags::empty();
+        if portability_enumeration {
+            extension_names.push(ash::khr::portability_enumeration::NAME.as_ptr());
+            instance_flags |= vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR;
+        }
+        
+        let validation = (builder.validation || debug_messenger::requested_by_env()) && {
+            let available = debug_messenger::available(&entry)?;
+            if !available {
+                println!("⚠ Validation requested, but {:?} is not installed", debug_messenger::VALIDATION_LAYER);
+            }
+            available
+        };
+        let mut layer_names = Vec::new();
+        if validation {
+            layer_names.push(debug_messenger::VALIDATION_LAYER.as_ptr());
+            extension_names.push(ash::ext::debug_utils::NAME.as_ptr());
+        }
+        
+        let mut messenger_info = debug_messenger::create_info();
+        let mut create_info = vk::InstanceCreateInfo::default()
             .application_info(&app_info)
-            .enabled_extension_names(&extension_names);
+            .enabled_layer_names(&layer_names)
+            .enabled_extension_names(&extension_names)
+            .flags(instance_flags);
+        if validation {
+            create_info = create_info.push_next(&mut messenger_info);
+        }
         
         let instance = entry.create_instance(&create_info, None)?;
+        let debug_messenger = if validation {
+            println!("✓ Vulkan validation enabled");
+            Some(DebugMessenger::new(&entry, &instance)?)
+        } else {
+            None
+        };
         
         // Create surface
-        let surface = ash_window::create_surface(
-            &entry,
-            &instance,
-            window.display_handle()?.as_raw(),
-            window.window_handle()?.as_raw(),
-            None,
-        )?;
+        let surface = match window {
+            Some(window) => ash_window::create_surface(
+                &entry,
+                &instance,
+                window.display_handle()?.as_raw(),
+                window.window_handle()?.as_raw(),
+                None,
+            )?,
+            None => vk::SurfaceKHR::null(),
+        };
         let surface_fn = ash::khr::surface::Instance::new(&entry, &instance);
         
         // Pick physical device - prefer discrete GPU over integrated
         let physical_devices = instance.enumerate_physical_devices()?;
         
-        // Sort: discrete GPUs first, then integrated, then others
+        // Sort: discrete GPUs first, then integrated, then others. Headless renderers want
+        // reproducible output and take a software rasterizer first.
         let physical_device = physical_devices
             .iter()
             .map(|&pd| {
                 let props = instance.get_physical_device_properties(pd);
                 let priority = match props.device_type {
+                    vk::PhysicalDeviceType::CPU if window.is_none() => -1,
                     vk::PhysicalDeviceType::DISCRETE_GPU => 0,   // Best - dedicated GPU like 1650 Ti
                     vk::PhysicalDeviceType::INTEGRATED_GPU => 1, // Fallback - Intel UHD
                     vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
@@ -113,37 +288,264 @@ impl VulkanRenderer {
         
         // Find queue families
         let queue_families = instance.get_physical_device_queue_family_properties(physical_device);
+        let can_present = |index: usize| {
+            surface != vk::SurfaceKHR::null()
+                && surface_fn
+                    .get_physical_device_surface_support(physical_device, index as u32, surface)
+                    .unwrap_or(false)
+        };
+        // Compute work is recorded alongside graphics, so require both
+        let is_graphics = |queue_family: &vk::QueueFamilyProperties| {
+            queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+        };
+        // Prefer a family that can also present; otherwise (e.g. PRIME offload) present
+        // from whichever family can
         let graphics_queue_family_index = queue_families
             .iter()
             .enumerate()
-            .find(|(i, queue_family)| {
-                queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-                    && surface_fn
-                        .get_physical_device_surface_support(physical_device, *i as u32, surface)
-                        .unwrap_or(false)
+            .position(|(i, queue_family)| is_graphics(queue_family) && can_present(i))
+            .or_else(|| queue_families.iter().position(is_graphics))
+            .ok_or("No suitable queue family found")? as u32;
+        let present_queue_family_index = if window.is_none() || can_present(graphics_queue_family_index as usize) {
+            graphics_queue_family_index
+        } else {
+            let index = (0..queue_families.len())
+                .find(|&i| can_present(i))
+                .ok_or("No queue family can present to the window")? as u32;
+            println!("ℹ Presenting from separate queue family {}", index);
+            index
+        };
+        
+        // A compute family without graphics usually maps to separate hardware queues,
+        // letting independent compute work overlap with rendering
+        let compute_queue_family_index = queue_families
+            .iter()
+            .position(|queue_family| {
+                queue_family.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                    && !queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS)
             })
-            .map(|(i, _)| i as u32)
-            .ok_or("No suitable queue family found")?;
+            .map(|i| i as u32);
+        match compute_queue_family_index {
+            Some(index) => println!("✓ Async compute queue family: {}", index),
+            None => println!("ℹ No compute-only queue family, compute runs on the graphics queue"),
+        }
         
         // Create logical device
         let queue_priorities = [1.0];
-        let queue_create_info = vk::DeviceQueueCreateInfo::default()
+        let mut queue_create_infos = vec![vk::DeviceQueueCreateInfo::default()
             .queue_family_index(graphics_queue_family_index)
-            .queue_priorities(&queue_priorities);
+            .queue_priorities(&queue_priorities)];
+        if let Some(index) = compute_queue_family_index {
+            queue_create_infos.push(
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(index)
+                    .queue_priorities(&queue_priorities),
+            );
+        }
+        if present_queue_family_index != graphics_queue_family_index
+            && Some(present_queue_family_index) != compute_queue_family_index
+        {
+            queue_create_infos.push(
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(present_queue_family_index)
+                    .queue_priorities(&queue_priorities),
+            );
+        }
+        
+        // Ray queries need the acceleration structure extensions, buffer device addresses
+        // and SPIR-V 1.4+ (Vulkan 1.2). Use them when all of it is there.
+        let available_extensions = instance.enumerate_device_extension_properties(physical_device)?;
+        let has_extension = |name: &std::ffi::CStr| {
+            available_extensions
+                .iter()
+                .any(|ext| ext.extension_name_as_c_str() == Ok(name))
+        };
+        let ray_query_extensions = [
+            ash::khr::acceleration_structure::NAME,
+            ash::khr::ray_query::NAME,
+            ash::khr::deferred_host_operations::NAME,
+        ];
+        let mut supported_vulkan12 = vk::PhysicalDeviceVulkan12Features::default();
+        let mut supported_acceleration_structure = vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+        let mut supported_ray_query = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+        let ray_query_supported = props.api_version >= vk::API_VERSION_1_2
+            && ray_query_extensions.iter().all(|name| has_extension(name))
+            && {
+                let mut features = vk::PhysicalDeviceFeatures2::default()
+                    .push_next(&mut supported_vulkan12)
+                    .push_next(&mut supported_acceleration_structure)
+                    .push_next(&mut supported_ray_query);
+                instance.get_physical_device_features2(physical_device, &mut features);
+                supported_vulkan12.buffer_device_address == vk::TRUE
+                    && supported_acceleration_structure.acceleration_structure == vk::TRUE
+                    && supported_ray_query.ray_query == vk::TRUE
+            };
+        if ray_query_supported {
+            println!("✓ Ray queries supported, hardware ray-traced shadows available");
+        } else {
+            println!("ℹ No ray query support, using shadow maps only");
+        }
         
-        let device_extension_names = [ash::khr::swapchain::NAME.as_ptr()];
         
-        let physical_device_features = vk::PhysicalDeviceFeatures::default();
+        // Task + mesh shaders also need SPIR-V 1.4+
+        let mut supported_mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+        let mesh_shader_supported = props.api_version >= vk::API_VERSION_1_2
+            && has_extension(ash::ext::mesh_shader::NAME)
+            && {
+                let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut supported_mesh_shader);
+                instance.get_physical_device_features2(physical_device, &mut features);
+                supported_mesh_shader.task_shader == vk::TRUE && supported_mesh_shader.mesh_shader == vk::TRUE
+            };
+        if mesh_shader_supported {
+            println!("✓ Mesh shaders supported, drawing static meshes as meshlets");
+        } else {
+            println!("ℹ No mesh shader support, using the vertex pipeline only");
+        }
         
-        let device_create_info = vk::DeviceCreateInfo::default()
-            .queue_create_infos(std::slice::from_ref(&queue_create_info))
+        
+        // GPU-driven draws: many indirect draws per call, with the count read from a buffer
+        let supported_features = instance.get_physical_device_features(physical_device);
+        let multi_draw_indirect_supported = supported_features.multi_draw_indirect == vk::TRUE;
+        let draw_indirect_count_supported = props.api_version >= vk::API_VERSION_1_2
+            && multi_draw_indirect_supported
+            && {
+                let mut supported_vulkan12 = vk::PhysicalDeviceVulkan12Features::default();
+                let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut supported_vulkan12);
+                instance.get_physical_device_features2(physical_device, &mut features);
+                supported_vulkan12.draw_indirect_count == vk::TRUE
+            };
+        if draw_indirect_count_supported {
+            println!("✓ Draw indirect count supported, GPU-driven glTF draws available");
+        } else if multi_draw_indirect_supported {
+            println!("ℹ No draw indirect count support, glTF meshes are batched in unculled indirect draws");
+        } else {
+            println!("ℹ No multi-draw indirect support, glTF meshes are drawn one by one");
+        }
+        
+        // Descriptors written straight into buffers, which are bound by device address
+        let descriptor_buffer_supported = props.api_version >= vk::API_VERSION_1_2
+            && has_extension(ash::ext::descriptor_buffer::NAME)
+            && {
+                let mut supported_vulkan12 = vk::PhysicalDeviceVulkan12Features::default();
+                let mut supported_descriptor_buffer = vk::PhysicalDeviceDescriptorBufferFeaturesEXT::default();
+                let mut features = vk::PhysicalDeviceFeatures2::default()
+                    .push_next(&mut supported_vulkan12)
+                    .push_next(&mut supported_descriptor_buffer);
+                instance.get_physical_device_features2(physical_device, &mut features);
+                supported_vulkan12.buffer_device_address == vk::TRUE
+                    && supported_descriptor_buffer.descriptor_buffer == vk::TRUE
+            };
+        if descriptor_buffer_supported {
+            println!("✓ Descriptor buffers supported, used where passes opt in");
+        } else {
+            println!("ℹ No descriptor buffer support, using descriptor sets only");
+        }
+        let buffer_device_address = ray_query_supported || descriptor_buffer_supported;
+        
+        // Descriptors recorded straight into the command buffer, for per-draw bindings
+        let push_descriptor_supported = has_extension(ash::khr::push_descriptor::NAME);
+        if push_descriptor_supported {
+            println!("✓ Push descriptors supported, binding materials without descriptor sets");
+        }
+        
+        // Portability subset devices (MoltenVK) must enable the extension and lack some core
+        // features; the renderer only depends on comparison samplers for shadow PCF
+        let portability_subset = has_extension(ash::khr::portability_subset::NAME);
+        let comparison_samplers_supported = !portability_subset || {
+            let mut subset_features = vk::PhysicalDevicePortabilitySubsetFeaturesKHR::default();
+            let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut subset_features);
+            instance.get_physical_device_features2(physical_device, &mut features);
+            subset_features.mutable_comparison_samplers == vk::TRUE
+        };
+        if portability_subset {
+            println!("ℹ Vulkan portability subset device (e.g. MoltenVK)");
+            if !comparison_samplers_supported {
+                println!("⚠ No comparison samplers, glTF shadow rendering is unavailable");
+            }
+        }
+        
+        let mut device_extension_names = Vec::new();
+        if window.is_some() {
+            device_extension_names.push(ash::khr::swapchain::NAME.as_ptr());
+        }
+        if portability_subset {
+            device_extension_names.push(ash::khr::portability_subset::NAME.as_ptr());
+        }
+        if ray_query_supported {
+            device_extension_names.extend(ray_query_extensions.iter().map(|name| name.as_ptr()));
+        }
+        if mesh_shader_supported {
+            device_extension_names.push(ash::ext::mesh_shader::NAME.as_ptr());
+        }
+        if descriptor_buffer_supported {
+            device_extension_names.push(ash::ext::descriptor_buffer::NAME.as_ptr());
+        }
+        if push_descriptor_supported {
+            device_extension_names.push(ash::khr::push_descriptor::NAME.as_ptr());
+        }
+        let device_extensions = device_extension_names
+            .iter()
+            .map(|&name| std::ffi::CStr::from_ptr(name).to_string_lossy().into_owned())
+            .collect();
+        
+        // Lens flares fade with the number of sun samples an occlusion query counts
+        let occlusion_query_precise_supported = supported_features.occlusion_query_precise == vk::TRUE;
+        // The debug UI shows what the scene pass cost the GPU
+        let pipeline_statistics_supported = supported_features.pipeline_statistics_query == vk::TRUE;
+        // Sharper textures at grazing angles (see texture_streaming.rs)
+        let max_anisotropy = if supported_features.sampler_anisotropy == vk::TRUE {
+            (props.limits.max_sampler_anisotropy as u32).min(16)
+        } else {
+            0
+        };
+        // KTX2 textures are uploaded in their block-compressed formats
+        let texture_compression_bc_supported = supported_features.texture_compression_bc == vk::TRUE;
+        
+        // gltf.frag writes shadow history and virtual texture feedback
+        let physical_device_features = vk::PhysicalDeviceFeatures::default()
+            .occlusion_query_precise(occlusion_query_precise_supported)
+            .pipeline_statistics_query(pipeline_statistics_supported)
+            .sampler_anisotropy(max_anisotropy > 0)
+            .texture_compression_bc(texture_compression_bc_supported)
+            .multi_draw_indirect(multi_draw_indirect_supported)
+            .fragment_stores_and_atomics(supported_features.fragment_stores_and_atomics == vk::TRUE);
+        let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features::default()
+            .buffer_device_address(buffer_device_address)
+            .draw_indirect_count(draw_indirect_count_supported);
+        let mut acceleration_structure_features = vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default()
+            .acceleration_structure(true);
+        let mut ray_query_features = vk::PhysicalDeviceRayQueryFeaturesKHR::default()
+            .ray_query(true);
+        let mut mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::default()
+            .task_shader(true)
+            .mesh_shader(true);
+        let mut descriptor_buffer_features = vk::PhysicalDeviceDescriptorBufferFeaturesEXT::default()
+            .descriptor_buffer(true);
+        
+        let mut device_create_info = vk::DeviceCreateInfo::default()
+            .queue_create_infos(&queue_create_infos)
             .enabled_extension_names(&device_extension_names)
             .enabled_features(&physical_device_features);
+        if buffer_device_address || draw_indirect_count_supported {
+            device_create_info = device_create_info.push_next(&mut vulkan12_features);
+        }
+        if ray_query_supported {
+            device_create_info = device_create_info
+                .push_next(&mut acceleration_structure_features)
+                .push_next(&mut ray_query_features);
+        }
+        if mesh_shader_supported {
+            device_create_info = device_create_info.push_next(&mut mesh_shader_features);
+        }
+        if descriptor_buffer_supported {
+            device_create_info = device_create_info.push_next(&mut descriptor_buffer_features);
+        }
         
         let device = Arc::new(instance.create_device(physical_device, &device_create_info, None)?);
         
         let graphics_queue = device.get_device_queue(graphics_queue_family_index, 0);
-        let present_queue = graphics_queue;
+        let present_queue = device.get_device_queue(present_queue_family_index, 0);
+        let compute_queue = compute_queue_family_index.map(|index| device.get_device_queue(index, 0));
         
         // Create allocator
         let allocator = Allocator::new(&AllocatorCreateDesc {
@@ -151,83 +553,81 @@ impl VulkanRenderer {
             device: (*device).clone(),
             physical_device,
             debug_settings: Default::default(),
-            buffer_device_address: false,
+            // Acceleration structure builds and descriptor buffers use device addresses
+            buffer_device_address,
             allocation_sizes: AllocationSizes::default(),
         })?;
         let allocator = Arc::new(Mutex::new(allocator));
         
-        // Create swapchain
-        let surface_capabilities = surface_fn
-            .get_physical_device_surface_capabilities(physical_device, surface)?;
-        let surface_formats = surface_fn
-            .get_physical_device_surface_formats(physical_device, surface)?;
-        let surface_format = surface_formats[0];
-        
-        // Check available present modes and pick best for max FPS
-        let present_modes = surface_fn
-            .get_physical_device_surface_present_modes(physical_device, surface)?;
-        let present_mode = if present_modes.contains(&vk::PresentModeKHR::IMMEDIATE) {
-            println!("✓ Using IMMEDIATE present mode (no vsync)");
-            vk::PresentModeKHR::IMMEDIATE
-        } else if present_modes.contains(&vk::PresentModeKHR::MAILBOX) {
-            println!("✓ Using MAILBOX present mode (triple buffering)");
-            vk::PresentModeKHR::MAILBOX
-        } else {
-            println!("⚠ Falling back to FIFO (vsync enabled by driver)");
-            vk::PresentModeKHR::FIFO
-        };
-        
-        let swapchain_extent = surface_capabilities.current_extent;
-        let max_images = if surface_capabilities.max_image_count == 0 {
-            u32::MAX
-        } else {
-            surface_capabilities.max_image_count
-        };
-        let image_count = (surface_capabilities.min_image_count + 1).min(max_images);
-        
-        let swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
-            .surface(surface)
-            .min_image_count(image_count)
-            .image_format(surface_format.format)
-            .image_color_space(surface_format.color_space)
-            .image_extent(swapchain_extent)
-            .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .pre_transform(surface_capabilities.current_transform)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(present_mode);
-        
+        // Create swapchain (none without a window)
         let swapchain_fn = ash::khr::swapchain::Device::new(&instance, &device);
-        let swapchain = swapchain_fn.create_swapchain(&swapchain_create_info, None)?;
-        
-        let swapchain_images = swapchain_fn.get_swapchain_images(swapchain)?;
-        
-        // Create image views
-        let swapchain_image_views: Vec<vk::ImageView> = swapchain_images
-            .iter()
-            .map(|&image| {
-                let create_info = vk::ImageViewCreateInfo::default()
-                    .image(image)
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .format(surface_format.format)
-                    .components(vk::ComponentMapping {
-                        r: vk::ComponentSwizzle::IDENTITY,
-                        g: vk::ComponentSwizzle::IDENTITY,
-                        b: vk::ComponentSwizzle::IDENTITY,
-                        a: vk::ComponentSwizzle::IDENTITY,
-                    })
-                    .subresource_range(vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    });
+        let (surface_format, swapchain_usage, present_mode, swapchain_extent, swapchain, swapchain_images, swapchain_image_views) =
+            if let Some(window) = window {
+                let support = SurfaceSupport::query(&surface_fn, physical_device, surface)?;
+                let surface_format = support.choose_format(None).ok_or("Surface reports no formats")?;
                 
-                device.create_image_view(&create_info, None)
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+                let present_mode = support.choose_present_mode(builder.present_mode.preferred_modes());
+                report_present_mode(builder.present_mode, present_mode);
+                
+                let window_size = window.inner_size();
+                let swapchain_extent = support
+                    .extent(window_size.width, window_size.height)
+                    .ok_or("Window surface has no area")?;
+                let image_count = support.image_count(builder.swapchain_images);
+                if let Some(requested) = builder.swapchain_images.filter(|&requested| requested != image_count) {
+                    println!("⚠ The surface can't use {} swapchain images, asking for {}", requested, image_count);
+                }
+                let queue_family_indices = [graphics_queue_family_index, present_queue_family_index];
+                let swapchain_create_info = support.swapchain_create_info(
+                    surface,
+                    surface_format,
+                    swapchain_extent,
+                    present_mode,
+                    &queue_family_indices,
+                    vk::SwapchainKHR::null(),
+                )
+                .min_image_count(image_count);
+                
+                let swapchain = swapchain_fn.create_swapchain(&swapchain_create_info, None)?;
+                
+                let swapchain_images = swapchain_fn.get_swapchain_images(swapchain)?;
+                println!("✓ {} swapchain images, {} frames in flight", swapchain_images.len(), frames_in_flight);
+                
+                // Create image views
+                let swapchain_image_views: Vec<vk::ImageView> = swapchain_images
+                    .iter()
+                    .map(|&image| {
+                        let create_info = vk::ImageViewCreateInfo::default()
+                            .image(image)
+                            .view_type(vk::ImageViewType::TYPE_2D)
+                            .format(surface_format.format)
+                            .components(vk::ComponentMapping {
+                                r: vk::ComponentSwizzle::IDENTITY,
+                                g: vk::ComponentSwizzle::IDENTITY,
+                                b: vk::ComponentSwizzle::IDENTITY,
+                                a: vk::ComponentSwizzle::IDENTITY,
+                            })
+                            .subresource_range(vk::ImageSubresourceRange {
+                                aspect_mask: vk::ImageAspectFlags::COLOR,
+                                base_mip_level: 0,
+                                level_count: 1,
+                                base_array_layer: 0,
+                                layer_count: 1,
+                            });
+                
+                        device.create_image_view(&create_info, None)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let swapchain_usage = swapchain_create_info.image_usage;
+                (surface_format, swapchain_usage, present_mode, swapchain_extent, swapchain, swapchain_images, swapchain_image_views)
+            } else {
+                let surface_format = vk::SurfaceFormatKHR {
+                    format: HEADLESS_FORMAT,
+                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                };
+                let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC;
+                (surface_format, usage, vk::PresentModeKHR::FIFO, headless_extent, vk::SwapchainKHR::null(), Vec::new(), Vec::new())
+            };
         
         // Create render pass (for egui overlay - loads existing content)
         let color_attachment = vk::AttachmentDescription::default()
@@ -264,15 +664,15 @@ impl VulkanRenderer {
         
         let render_pass = device.create_render_pass(&render_pass_info, None)?;
         
-        // Create descriptor set layout
-        let ubo_layout_binding = vk::DescriptorSetLayoutBinding::default()
-            .binding(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::VERTEX);
+        // Load shaders (embedded SPIR-V)
+        let vert_shader_code = load_shader("cube.vert", include_bytes!("../shaders/cube.vert.spv"));
+        let frag_shader_code = load_shader("cube.frag", include_bytes!("../shaders/cube.frag.spv"));
+        let vert_reflection = ShaderReflection::reflect(&vert_shader_code)?;
+        let frag_reflection = ShaderReflection::reflect(&frag_shader_code)?;
         
-        let layout_info = vk::DescriptorSetLayoutCreateInfo::default()
-            .bindings(std::slice::from_ref(&ubo_layout_binding));
+        // Create descriptor set layout (reflected from the shaders)
+        let bindings = shader_reflection::set_layout_bindings(&[&vert_reflection, &frag_reflection], 0)?;
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
         
         let descriptor_set_layout = device.create_descriptor_set_layout(&layout_info, None)?;
         
@@ -282,111 +682,7 @@ impl VulkanRenderer {
         
         let pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None)?;
         
-        // Load shaders (embedded SPIR-V)
-        let vert_shader_code = include_bytes!("../shaders/cube.vert.spv");
-        let frag_shader_code = include_bytes!("../shaders/cube.frag.spv");
-        
-        let vert_shader_module = Self::create_shader_module(&device, vert_shader_code)?;
-        let frag_shader_module = Self::create_shader_module(&device, frag_shader_code)?;
-        
-        let main_name = CString::new("main")?;
-        
-        let vert_stage_info = vk::PipelineShaderStageCreateInfo::default()
-            .stage(vk::ShaderStageFlags::VERTEX)
-            .module(vert_shader_module)
-            .name(&main_name);
-        
-        let frag_stage_info = vk::PipelineShaderStageCreateInfo::default()
-            .stage(vk::ShaderStageFlags::FRAGMENT)
-            .module(frag_shader_module)
-            .name(&main_name);
-        
-        let shader_stages = [vert_stage_info, frag_stage_info];
-        
-        // Vertex input
-        let binding_description = vk::VertexInputBindingDescription::default()
-            .binding(0)
-            .stride(std::mem::size_of::<Vertex>() as u32)
-            .input_rate(vk::VertexInputRate::VERTEX);
-        
-        let attribute_descriptions = [
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 0,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: 0,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 1,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: 12,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 2,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: 24,
-            },
-        ];
-        
-        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::default()
-            .vertex_binding_descriptions(std::slice::from_ref(&binding_description))
-            .vertex_attribute_descriptions(&attribute_descriptions);
-        
-        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-            .primitive_restart_enable(false);
-        
-        // Use dynamic viewport and scissor for resizing support
-        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
-            .viewport_count(1)
-            .scissor_count(1);
-        
-        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-        let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::default()
-            .dynamic_states(&dynamic_states);
-        
-        let rasterizer = vk::PipelineRasterizationStateCreateInfo::default()
-            .depth_clamp_enable(false)
-            .rasterizer_discard_enable(false)
-            .polygon_mode(vk::PolygonMode::FILL)
-            .line_width(1.0)
-            .cull_mode(vk::CullModeFlags::BACK)
-            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-            .depth_bias_enable(false);
-        
-        let multisampling = vk::PipelineMultisampleStateCreateInfo::default()
-            .sample_shading_enable(false)
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
-        
-        let color_blend_attachment = vk::PipelineColorBlendAttachmentState::default()
-            .color_write_mask(vk::ColorComponentFlags::RGBA)
-            .blend_enable(false);
-        
-        let color_blending = vk::PipelineColorBlendStateCreateInfo::default()
-            .logic_op_enable(false)
-            .attachments(std::slice::from_ref(&color_blend_attachment));
-        
-        let pipeline_info = vk::GraphicsPipelineCreateInfo::default()
-            .stages(&shader_stages)
-            .vertex_input_state(&vertex_input_info)
-            .input_assembly_state(&input_assembly)
-            .viewport_state(&viewport_state)
-            .rasterization_state(&rasterizer)
-            .multisample_state(&multisampling)
-            .color_blend_state(&color_blending)
-            .dynamic_state(&dynamic_state_info)
-            .layout(pipeline_layout)
-            .render_pass(render_pass)
-            .subpass(0);
-        
-        let graphics_pipeline = device
-            .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
-            .map_err(|(_, e)| e)?[0];
-        
-        device.destroy_shader_module(vert_shader_module, None);
-        device.destroy_shader_module(frag_shader_module, None);
+        let graphics_pipeline = create_cube_pipeline(&device, pipeline_layout, render_pass, &vert_shader_code, &frag_shader_code)?;
         
         // Create framebuffers
         let framebuffers: Vec<vk::Framebuffer> = swapchain_image_views
@@ -419,25 +715,7 @@ impl VulkanRenderer {
         
         let command_buffers = device.allocate_command_buffers(&alloc_info)?;
         
-        // Create descriptor pool
-        let pool_size = vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
-        };
-        
-        let pool_info = vk::DescriptorPoolCreateInfo::default()
-            .pool_sizes(std::slice::from_ref(&pool_size))
-            .max_sets(MAX_FRAMES_IN_FLIGHT as u32);
-        
-        let descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
-        
-        // Allocate descriptor sets
-        let layouts = vec![descriptor_set_layout; MAX_FRAMES_IN_FLIGHT];
-        let alloc_info = vk::DescriptorSetAllocateInfo::default()
-            .descriptor_pool(descriptor_pool)
-            .set_layouts(&layouts);
-        
-        let descriptor_sets = device.allocate_descriptor_sets(&alloc_info)?;
+        let frame_arena = FrameArena::new(device.clone(), allocator.clone(), &props.limits, FRAME_ARENA_SIZE)?;
         
         // Create sync objects
         let semaphore_info = vk::SemaphoreCreateInfo::default();
@@ -471,6 +749,10 @@ impl VulkanRenderer {
             swapchain_images,
             swapchain_image_views,
             swapchain_format: surface_format.format,
+            swapchain_color_space: surface_format.color_space,
+            swapchain_usage,
+            present_mode,
+            present_mode_config: builder.present_mode,
             swapchain_extent,
             render_pass,
             framebuffers,
@@ -483,22 +765,141 @@ impl VulkanRenderer {
             in_flight_fences,
             images_in_flight,
             current_frame: 0,
+            frames_in_flight,
+            requested_swapchain_images: builder.swapchain_images,
             allocator,
+            samplers: SamplerCache::new(),
+            frame_arena,
             descriptor_set_layout,
-            descriptor_pool,
-            descriptor_sets,
             graphics_queue_family_index,
+            present_queue_family_index,
+            compute_queue,
+            compute_queue_family_index,
+            ray_query_supported,
+            mesh_shader_supported,
+            multi_draw_indirect_supported,
+            draw_indirect_count_supported,
+            comparison_samplers_supported,
+            max_anisotropy,
+            texture_compression_bc_supported,
+            occlusion_query_precise_supported,
+            descriptor_buffer_supported,
+            push_descriptor_supported,
+            pipeline_statistics_supported,
             framebuffer_resized: false,
+            passes: Vec::new(),
             gpu_name,
             vulkan_version,
+            device_extensions,
+            debug_messenger,
+            headless_image_allocations: Vec::new(),
         })
     }
     
+    /// Move on to the next frame slot in use
+    pub fn advance_frame(&mut self) {
+        self.current_frame = (self.current_frame + 1) % self.frames_in_flight;
+    }
+    
+    /// Present with `config` from now on, recreating the swapchain when the mode it
+    /// negotiates differs from the current one. Returns whether it did, after which
+    /// resources built on the swapchain images must be recreated as after a resize.
+    ///
+    /// # Safety
+    ///
+    /// The device must be idle.
+    pub unsafe fn set_present_mode(&mut self, config: PresentModeConfig) -> Result<bool, vk::Result> {
+        self.present_mode_config = config;
+        if self.swapchain == vk::SwapchainKHR::null() {
+            return Ok(false);
+        }
+        let support = SurfaceSupport::query(&self.surface_fn, self.physical_device, self.surface)?;
+        let present_mode = support.choose_present_mode(config.preferred_modes());
+        report_present_mode(config, present_mode);
+        if present_mode == self.present_mode {
+            return Ok(false);
+        }
+        self.recreate_swapchain(self.swapchain_extent.width, self.swapchain_extent.height)?;
+        Ok(true)
+    }
+    
+    /// Rebuild the cube pipeline from the current cube shaders, keeping the old one if
+    /// they fail to build.
+    ///
+    /// # Safety
+    ///
+    /// The device must be idle.
+    pub unsafe fn reload_cube_pipeline(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let vert_shader_code = load_shader("cube.vert", include_bytes!("../shaders/cube.vert.spv"));
+        let frag_shader_code = load_shader("cube.frag", include_bytes!("../shaders/cube.frag.spv"));
+        let pipeline = create_cube_pipeline(
+            &self.device,
+            self.pipeline_layout,
+            self.render_pass,
+            &vert_shader_code,
+            &frag_shader_code,
+        )?;
+        self.device.destroy_pipeline(std::mem::replace(&mut self.graphics_pipeline, pipeline), None);
+        Ok(())
+    }
+    
+    /// Queue families that use swapchain images: rendered on graphics, presented on present
+    pub fn swapchain_queue_family_indices(&self) -> [u32; 2] {
+        [self.graphics_queue_family_index, self.present_queue_family_index]
+    }
+    
+    /// Register a custom pass, run every frame between the scene and the UI (see frame_hooks.rs)
+    #[allow(dead_code)] // Library API; the app registers no passes
+    pub fn add_pass(&mut self, pass: impl FnMut(&FrameContext) + 'static) {
+        self.passes.push(Box::new(pass));
+    }
+    
+    /// Run the custom passes in registration order.
+    ///
+    /// # Safety
+    ///
+    /// The frame's command buffer must be recording, with no render pass active.
+    pub unsafe fn run_passes(&mut self, image_index: u32, camera_position: glam::Vec3, view: glam::Mat4, proj: glam::Mat4) {
+        if self.passes.is_empty() {
+            return;
+        }
+        
+        let mut passes = std::mem::take(&mut self.passes);
+        let ctx = FrameContext {
+            device: &self.device,
+            command_buffer: self.command_buffers[self.current_frame],
+            frame_index: self.current_frame,
+            image_index,
+            extent: self.swapchain_extent,
+            render_pass: self.render_pass,
+            framebuffer: self.framebuffers[image_index as usize],
+            frame_arena: &self.frame_arena,
+            camera_position,
+            view,
+            proj,
+        };
+        for pass in &mut passes {
+            pass(&ctx);
+        }
+        self.passes = passes;
+    }
+    
     pub unsafe fn recreate_swapchain(&mut self, width: u32, height: u32) -> Result<(), vk::Result> {
         if width == 0 || height == 0 {
             return Ok(());
         }
         
+        // A minimized surface has no extent; keep the old swapchain until it has one again
+        let support = SurfaceSupport::query(&self.surface_fn, self.physical_device, self.surface)?;
+        let Some(new_extent) = support.extent(width, height) else {
+            return Ok(());
+        };
+        // Pipelines are built for the current format, only its color space may change
+        let surface_format = support
+            .choose_format(Some(self.swapchain_format))
+            .ok_or(vk::Result::ERROR_FORMAT_NOT_SUPPORTED)?;
+        let present_mode = support.choose_present_mode(self.present_mode_config.preferred_modes());
+        
         self.device.device_wait_idle()?;
         
         // Cleanup old swapchain resources
@@ -511,49 +912,20 @@ impl VulkanRenderer {
         
         let old_swapchain = self.swapchain;
         
-        // Get new surface capabilities
-        let surface_capabilities = self.surface_fn
-            .get_physical_device_surface_capabilities(self.physical_device, self.surface)?;
-        
-        // Determine new extent
-        let new_extent = if surface_capabilities.current_extent.width != u32::MAX {
-            surface_capabilities.current_extent
-        } else {
-            vk::Extent2D {
-                width: width.clamp(
-                    surface_capabilities.min_image_extent.width,
-                    surface_capabilities.max_image_extent.width,
-                ),
-                height: height.clamp(
-                    surface_capabilities.min_image_extent.height,
-                    surface_capabilities.max_image_extent.height,
-                ),
-            }
-        };
-        
-        let max_images = if surface_capabilities.max_image_count == 0 {
-            u32::MAX
-        } else {
-            surface_capabilities.max_image_count
-        };
-        let image_count = (surface_capabilities.min_image_count + 1).min(max_images);
-        
         // Create new swapchain
-        let swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
-            .surface(self.surface)
-            .min_image_count(image_count)
-            .image_format(self.swapchain_format)
-            .image_color_space(vk::ColorSpaceKHR::SRGB_NONLINEAR)
-            .image_extent(new_extent)
-            .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .pre_transform(surface_capabilities.current_transform)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(vk::PresentModeKHR::IMMEDIATE)  // Max FPS - no vsync at all
-            .old_swapchain(old_swapchain);
+        let queue_family_indices = self.swapchain_queue_family_indices();
+        let swapchain_create_info = support.swapchain_create_info(
+            self.surface,
+            surface_format,
+            new_extent,
+            present_mode,
+            &queue_family_indices,
+            old_swapchain,
+        )
+        .min_image_count(support.image_count(self.requested_swapchain_images));
         
         self.swapchain = self.swapchain_fn.create_swapchain(&swapchain_create_info, None)?;
+        self.swapchain_usage = swapchain_create_info.image_usage;
         
         // Destroy old swapchain
         self.swapchain_fn.destroy_swapchain(old_swapchain, None);
@@ -561,6 +933,8 @@ impl VulkanRenderer {
         // Get new images
         self.swapchain_images = self.swapchain_fn.get_swapchain_images(self.swapchain)?;
         self.swapchain_extent = new_extent;
+        self.swapchain_color_space = surface_format.color_space;
+        self.present_mode = present_mode;
         
         // Create new image views
         self.swapchain_image_views = self.swapchain_images
@@ -612,21 +986,46 @@ impl VulkanRenderer {
         Ok(())
     }
     
-    unsafe fn create_shader_module(
-        device: &Device,
-        code: &[u8],
-    ) -> Result<vk::ShaderModule, vk::Result> {
-        let code_u32: Vec<u32> = code
-            .chunks_exact(4)
-            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
-            .collect();
-        
-        let create_info = vk::ShaderModuleCreateInfo::default().code(&code_u32);
-        
-        device.create_shader_module(&create_info, None)
+    /// Shared sampler for `desc`, owned by the renderer; never destroy it. Comparison
+    /// samplers fail with `ERROR_FEATURE_NOT_PRESENT` where the device lacks them.
+    /// Anisotropy is clamped to `max_anisotropy`, so it is off where unsupported.
+    ///
+    /// # Safety
+    ///
+    /// The sampler is destroyed with the renderer; it must not be used after that.
+    pub unsafe fn sampler(&self, desc: SamplerDesc) -> Result<vk::Sampler, vk::Result> {
+        if desc.compare_op.is_some() && !self.comparison_samplers_supported {
+            return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
+        }
+        let desc = desc.anisotropy(desc.anisotropy.min(self.max_anisotropy));
+        self.samplers.get(&self.device, desc)
     }
 }
 
+fn report_present_mode(config: PresentModeConfig, present_mode: vk::PresentModeKHR) {
+    match present_mode {
+        vk::PresentModeKHR::IMMEDIATE => println!("✓ Using IMMEDIATE present mode (no vsync)"),
+        vk::PresentModeKHR::MAILBOX => println!("✓ Using MAILBOX present mode (triple buffering)"),
+        _ if config == PresentModeConfig::Fifo => println!("✓ Using FIFO present mode (vsync)"),
+        _ => println!("⚠ Falling back to FIFO (vsync enabled by driver)"),
+    }
+}
+
+unsafe fn create_cube_pipeline(
+    device: &ash::Device,
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    vert_shader_code: &[u32],
+    frag_shader_code: &[u32],
+) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+    GraphicsPipelineBuilder::new(layout, render_pass)
+        .shader(vk::ShaderStageFlags::VERTEX, vert_shader_code)
+        .shader(vk::ShaderStageFlags::FRAGMENT, frag_shader_code)
+        .vertex_buffer::<Vertex>()
+        .cull_mode(vk::CullModeFlags::BACK)
+        .build(device)
+}
+
 impl Drop for VulkanRenderer {
     fn drop(&mut self) {
         unsafe {
@@ -643,6 +1042,8 @@ impl Drop for VulkanRenderer {
             }
             
             self.device.destroy_command_pool(self.command_pool, None);
+            self.samplers.destroy(&self.device);
+            self.frame_arena.destroy();
             
             for &framebuffer in &self.framebuffers {
                 self.device.destroy_framebuffer(framebuffer, None);
@@ -656,12 +1057,26 @@ impl Drop for VulkanRenderer {
                 self.device.destroy_image_view(image_view, None);
             }
             
-            self.swapchain_fn.destroy_swapchain(self.swapchain, None);
+            if self.swapchain != vk::SwapchainKHR::null() {
+                self.swapchain_fn.destroy_swapchain(self.swapchain, None);
+            } else {
+                // Headless: the images are ours
+                for &image in &self.swapchain_images {
+                    self.device.destroy_image(image, None);
+                }
+                for allocation in self.headless_image_allocations.drain(..).flatten() {
+                    let _ = self.allocator.lock().free(allocation);
+                }
+            }
             
-            self.device.destroy_descriptor_pool(self.descriptor_pool, None);
             self.device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
             
-            self.surface_fn.destroy_surface(self.surface, None);
+            if self.surface != vk::SurfaceKHR::null() {
+                self.surface_fn.destroy_surface(self.surface, None);
+            }
+            if let Some(debug_messenger) = &self.debug_messenger {
+                debug_messenger.destroy();
+            }
         }
     }
 }
@@ -674,6 +1089,14 @@ pub struct Vertex {
     pub normal: [f32; 3],
 }
 
+impl VertexLayout for Vertex {
+    const ATTRIBUTES: &'static [(vk::Format, u32)] = &[
+        (vk::Format::R32G32B32_SFLOAT, std::mem::offset_of!(Vertex, pos) as u32),
+        (vk::Format::R32G32B32_SFLOAT, std::mem::offset_of!(Vertex, color) as u32),
+        (vk::Format::R32G32B32_SFLOAT, std::mem::offset_of!(Vertex, normal) as u32),
+    ];
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct UniformBufferObject {
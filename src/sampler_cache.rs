@@ -0,0 +1,126 @@
+//! Shared samplers
+//!
+//! Samplers are immutable and cheap to share, and devices cap how many can exist
+//! (`maxSamplerAllocationCount`). Renderers ask `VulkanRenderer::sampler` for the settings
+//! they need instead of creating their own; identical settings get the same handle, and
+//! every sampler is destroyed once with the renderer. Callers never destroy them.
+
+use ash::vk;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+/// Sampler settings, the cache key. Address mode applies to all axes; mips 0..=`max_lod`
+/// are sampled, only mip 0 by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerDesc {
+    pub filter: vk::Filter, // Min + mag
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode: vk::SamplerAddressMode,
+    pub border_color: vk::BorderColor, // Used with CLAMP_TO_BORDER
+    /// Max anisotropy, 0 to disable. Needs the `samplerAnisotropy` device feature.
+    pub anisotropy: u32,
+    /// Depth comparison for hardware PCF
+    pub compare_op: Option<vk::CompareOp>,
+    pub max_lod: u32,
+}
+
+impl SamplerDesc {
+    pub const fn new(filter: vk::Filter, address_mode: vk::SamplerAddressMode) -> Self {
+        Self {
+            filter,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode,
+            border_color: vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+            anisotropy: 0,
+            compare_op: None,
+            max_lod: 0,
+        }
+    }
+
+    pub const fn linear(address_mode: vk::SamplerAddressMode) -> Self {
+        Self::new(vk::Filter::LINEAR, address_mode)
+    }
+
+    pub const fn nearest(address_mode: vk::SamplerAddressMode) -> Self {
+        Self::new(vk::Filter::NEAREST, address_mode)
+    }
+
+    pub const fn mipmap_mode(mut self, mipmap_mode: vk::SamplerMipmapMode) -> Self {
+        self.mipmap_mode = mipmap_mode;
+        self
+    }
+
+    pub const fn border_color(mut self, border_color: vk::BorderColor) -> Self {
+        self.border_color = border_color;
+        self
+    }
+
+    pub const fn compare(mut self, compare_op: vk::CompareOp) -> Self {
+        self.compare_op = Some(compare_op);
+        self
+    }
+
+    pub const fn anisotropy(mut self, anisotropy: u32) -> Self {
+        self.anisotropy = anisotropy;
+        self
+    }
+
+    pub const fn max_lod(mut self, max_lod: u32) -> Self {
+        self.max_lod = max_lod;
+        self
+    }
+
+    fn create_info(&self) -> vk::SamplerCreateInfo<'static> {
+        vk::SamplerCreateInfo::default()
+            .mag_filter(self.filter)
+            .min_filter(self.filter)
+            .mipmap_mode(self.mipmap_mode)
+            .address_mode_u(self.address_mode)
+            .address_mode_v(self.address_mode)
+            .address_mode_w(self.address_mode)
+            .border_color(self.border_color)
+            .anisotropy_enable(self.anisotropy > 0)
+            .max_anisotropy(self.anisotropy as f32)
+            .compare_enable(self.compare_op.is_some())
+            .compare_op(self.compare_op.unwrap_or(vk::CompareOp::NEVER))
+            .min_lod(0.0)
+            .max_lod(self.max_lod as f32)
+    }
+}
+
+#[derive(Default)]
+pub struct SamplerCache {
+    samplers: Mutex<HashMap<SamplerDesc, vk::Sampler>>,
+}
+
+impl SamplerCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The sampler for `desc`, created on first use
+    ///
+    /// # Safety
+    ///
+    /// `device` must be the same for every call.
+    pub unsafe fn get(&self, device: &ash::Device, desc: SamplerDesc) -> Result<vk::Sampler, vk::Result> {
+        let mut samplers = self.samplers.lock();
+        if let Some(&sampler) = samplers.get(&desc) {
+            return Ok(sampler);
+        }
+        let sampler = device.create_sampler(&desc.create_info(), None)?;
+        samplers.insert(desc, sampler);
+        Ok(sampler)
+    }
+
+    /// Destroy every sampler; none may still be in use
+    ///
+    /// # Safety
+    ///
+    /// The device must be idle; samplers handed out before are invalid afterwards.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for (_, sampler) in self.samplers.lock().drain() {
+            device.destroy_sampler(sampler, None);
+        }
+    }
+}
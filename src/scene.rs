@@ -0,0 +1,82 @@
+//! Instances of the loaded glTF scene
+//!
+//! `Scene` outlines the loaded glTF scene as the entities each instance of it is made of:
+//! a `SceneNode` for every glTF node above a mesh and for every mesh. The scene is loaded
+//! and uploaded once and placed any number of times, each instance under a root entity
+//! with a `GltfModel` and its own `Transform`; see `SceneCommandsExt::spawn_model` and
+//! `despawn_model`.
+//!
+//! The first instance still standing is the primary one. The renderer's GPU-driven draws,
+//! meshlets, ray traced shadows and lightmap cover only it; the others are hardware
+//! instanced, one draw per mesh for all of them (see `GltfRenderer::set_instances`), with
+//! the instances out of view culled on the GPU (see `instance_culling`).
+
+use bevy_ecs::prelude::*;
+
+use crate::gltf_loader::GltfScene;
+
+#[derive(Resource)]
+pub struct Scene {
+    pub path: String,
+    pub nodes: Vec<SceneNode>, // A node's parent comes before it
+    pub instances: Vec<Entity>, // Roots, in spawn order
+}
+
+/// An entity below each instance's root
+pub struct SceneNode {
+    pub name: String,
+    pub parent: Option<usize>, // Into `Scene::nodes`, None directly below the root
+    pub part: ScenePart,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ScenePart {
+    Node(usize), // Into `GltfScene::nodes`
+    Mesh(usize), // Into `GltfScene::meshes` and `GltfRenderer::meshes`
+}
+
+impl Scene {
+    /// Outline of `scene`, loaded from `path`, without instances. Only the nodes with a
+    /// mesh at or below them are kept, leaving out bare skeletons.
+    pub fn new(path: impl Into<String>, scene: &GltfScene) -> Self {
+        let mut placing = vec![false; scene.nodes.len()];
+        for mesh in &scene.meshes {
+            let mut node = mesh.node;
+            while let Some(index) = node.filter(|&index| !placing[index]) {
+                placing[index] = true;
+                node = scene.nodes[index].parent;
+            }
+        }
+
+        // Depth first from the roots, so parents come first
+        let mut children = vec![Vec::new(); scene.nodes.len()];
+        for (index, node) in scene.nodes.iter().enumerate() {
+            if let Some(parent) = node.parent.filter(|_| placing[index]) {
+                children[parent].push(index);
+            }
+        }
+        let mut stack: Vec<(usize, Option<usize>)> = (0..scene.nodes.len())
+            .rev()
+            .filter(|&index| placing[index] && scene.nodes[index].parent.is_none())
+            .map(|index| (index, None))
+            .collect();
+        let mut nodes = Vec::new();
+        let mut outline_of = vec![None; scene.nodes.len()];
+        while let Some((index, parent)) = stack.pop() {
+            outline_of[index] = Some(nodes.len());
+            stack.extend(children[index].iter().rev().map(|&child| (child, Some(nodes.len()))));
+            nodes.push(SceneNode { name: scene.nodes[index].name.clone(), parent, part: ScenePart::Node(index) });
+        }
+        for (index, mesh) in scene.meshes.iter().enumerate() {
+            let parent = mesh.node.and_then(|node| outline_of[node]);
+            nodes.push(SceneNode { name: mesh.name.clone(), parent, part: ScenePart::Mesh(index) });
+        }
+
+        Self { path: path.into(), nodes, instances: Vec::new() }
+    }
+
+    /// The instance the renderer's single-transform paths cover, if any is left
+    pub fn primary(&self) -> Option<Entity> {
+        self.instances.first().copied()
+    }
+}
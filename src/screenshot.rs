@@ -0,0 +1,431 @@
+//! Supersampled stills and 360° panoramas
+//!
+//! "Render still" re-renders the current view of the glTF scene offscreen at
+//! `scale * samples` times the window resolution, box-filters it down by `samples` in
+//! linear light and writes a PNG to `screenshots/`. Images larger than a tile (the device's
+//! image size limit, capped to keep VRAM use bounded) are rendered tile by tile with an
+//! off-center projection. Each tile fits its own shadow cascades, so very large stills
+//! can show faint shadow resolution changes at tile borders.
+//!
+//! "Capture panorama" renders the six faces of a cube map from the camera position and
+//! resamples them into an equirectangular image, centered on the camera's heading, saved as
+//! both PNG and Radiance HDR. The faces are rendered through a float copy of the scene pass
+//! (see `GltfRenderer::create_scene_pass`), so the HDR keeps light brighter than 1.0; the
+//! PNG clips it like the window does.
+//!
+//! Like the view windows, captures contain the glTF scene only, without cubes, particles or
+//! the UI, and of it only the render layers the main view shows other than the gizmos.
+
+use ash::vk;
+use glam::{Mat4, Vec3};
+use std::path::PathBuf;
+
+use crate::gltf_renderer::{GltfRenderer, Projection, ViewCamera};
+use crate::lightmap::f16_to_f32;
+use crate::offscreen::OffscreenTarget;
+use crate::renderer::VulkanRenderer;
+
+/// Largest tile edge in pixels, whatever the device allows
+const MAX_TILE_SIZE: u32 = 4096;
+/// Color format panorama faces are rendered in; every device can render to it
+const PANORAMA_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Upper bound for `scale * samples`, which sets the rendered size
+pub const MAX_RENDER_SCALE: u32 = 8;
+
+/// What the still is rendered from and how large
+pub struct StillRequest {
+    pub camera_position: Vec3,
+    pub camera_yaw: f32,
+    pub camera_pitch: f32,
+    pub projection: Projection,
+    pub extent: vk::Extent2D, // Window size the multipliers apply to
+    pub scale: u32,           // Output resolution multiplier
+    pub samples: u32,         // Supersampling per axis
+    pub debug_cascades: bool,
+    pub shadow_softness: f32,
+    pub use_pcss: bool,
+}
+
+/// Where a panorama is captured from and how wide the equirectangular image is
+pub struct PanoramaRequest {
+    pub camera_position: Vec3,
+    pub camera_yaw: f32, // Heading at the image center
+    pub width: u32,      // Height is half of it
+    pub debug_cascades: bool,
+    pub shadow_softness: f32,
+    pub use_pcss: bool,
+}
+
+/// Render and save a still, returning the path of the PNG. Waits for the device to go idle
+/// first and leaves it idle.
+pub unsafe fn render_still(
+    renderer: &VulkanRenderer,
+    gltf_renderer: &GltfRenderer,
+    request: &StillRequest,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let samples = request.samples.max(1);
+    let render_scale = (request.scale.max(1) * samples).min(MAX_RENDER_SCALE);
+    let full_width = request.extent.width * render_scale;
+    let full_height = request.extent.height * render_scale;
+
+    let limits = renderer.instance.get_physical_device_properties(renderer.physical_device).limits;
+    let tile_size = limits.max_image_dimension2_d.min(MAX_TILE_SIZE);
+    let tile_extent = vk::Extent2D {
+        width: full_width.min(tile_size),
+        height: full_height.min(tile_size),
+    };
+    let tiles_x = full_width.div_ceil(tile_extent.width);
+    let tiles_y = full_height.div_ceil(tile_extent.height);
+
+    println!(
+        "📷 Rendering {}x{} still ({} tile{}, {}x{} supersampling)",
+        full_width,
+        full_height,
+        tiles_x * tiles_y,
+        if tiles_x * tiles_y == 1 { "" } else { "s" },
+        samples,
+        samples
+    );
+
+    renderer.device.device_wait_idle()?;
+
+    let mut target = OffscreenTarget::new(renderer, gltf_renderer.render_pass, tile_extent)?;
+    let mut view = match gltf_renderer.create_view(renderer, tile_extent) {
+        Ok(view) => view,
+        Err(e) => {
+            target.destroy(renderer);
+            return Err(e);
+        }
+    };
+    view.layers = gltf_renderer.capture_layers();
+
+    let camera = ViewCamera::new(
+        request.camera_position,
+        request.camera_yaw,
+        request.camera_pitch,
+        request.projection,
+        full_width as f32 / full_height as f32,
+    );
+
+    let mut pixels = vec![0u8; full_width as usize * full_height as usize * 4];
+    let mut result = Ok(());
+    'tiles: for tile_y in 0..tiles_y {
+        for tile_x in 0..tiles_x {
+            let origin = (tile_x * tile_extent.width, tile_y * tile_extent.height);
+            let tile_camera = ViewCamera {
+                proj: tile_projection(origin, tile_extent, (full_width, full_height)) * camera.proj,
+                ..camera
+            };
+            gltf_renderer.update_view_uniform_buffer(
+                &mut view,
+                0,
+                &tile_camera,
+                request.debug_cascades,
+                request.shadow_softness,
+                request.use_pcss,
+            );
+
+            let tile = render_tile(renderer, gltf_renderer, &view, &target);
+            match tile {
+                Ok(tile) => copy_tile(&tile, tile_extent, &mut pixels, full_width, full_height, origin),
+                Err(e) => {
+                    result = Err(e);
+                    break 'tiles;
+                }
+            }
+        }
+    }
+
+    renderer.device.device_wait_idle()?;
+    gltf_renderer.destroy_view(renderer, view)?;
+    target.destroy(renderer);
+    result?;
+
+    let (width, height) = (full_width / samples, full_height / samples);
+    let pixels = downsample(&pixels, full_width, full_height, samples);
+
+    let path = output_path("still", width, height, "png")?;
+    image::RgbaImage::from_raw(width, height, pixels)
+        .ok_or("Still pixel buffer has the wrong size")?
+        .save(&path)?;
+
+    println!("✓ Saved still to {}", path.display());
+    Ok(path)
+}
+
+/// Render and save a panorama, returning the path of the PNG (the HDR is next to it).
+/// Waits for the device to go idle first and leaves it idle.
+pub unsafe fn render_panorama(
+    renderer: &VulkanRenderer,
+    gltf_renderer: &mut GltfRenderer,
+    request: &PanoramaRequest,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let limits = renderer.instance.get_physical_device_properties(renderer.physical_device).limits;
+    // A face spans 90°, a quarter of the panorama's width
+    let face_size = (request.width / 4).clamp(16, limits.max_image_dimension2_d.min(MAX_TILE_SIZE));
+    let (width, height) = (request.width, request.width / 2);
+
+    println!("🌐 Rendering {}x{} panorama from six {}x{} faces", width, height, face_size, face_size);
+
+    renderer.device.device_wait_idle()?;
+    let mut float_pass = gltf_renderer.create_scene_pass(&renderer.device, PANORAMA_FORMAT)?;
+    gltf_renderer.swap_scene_pass(&mut float_pass);
+    let face_images = render_cube_faces(
+        renderer,
+        gltf_renderer,
+        request.camera_position,
+        face_size,
+        request.debug_cascades,
+        request.shadow_softness,
+        request.use_pcss,
+    );
+    gltf_renderer.swap_scene_pass(&mut float_pass);
+    float_pass.destroy(&renderer.device);
+    let face_images = face_images?;
+    // Linear RGBA, from the halves the float pass left
+    let face_radiance: Vec<Vec<f32>> = face_images
+        .iter()
+        .map(|face| face.pixels.chunks_exact(2).map(|h| f16_to_f32(u16::from_ne_bytes([h[0], h[1]]))).collect())
+        .collect();
+
+    // Resample: each output pixel looks up its direction in the face it points at most
+    let mut radiance = vec![0.0_f32; width as usize * height as usize * 3];
+    for y in 0..height {
+        let latitude = (0.5 - (y as f32 + 0.5) / height as f32) * std::f32::consts::PI;
+        for x in 0..width {
+            let longitude = request.camera_yaw + ((x as f32 + 0.5) / width as f32 - 0.5) * std::f32::consts::TAU;
+            let direction = Vec3::new(
+                latitude.cos() * longitude.cos(),
+                latitude.sin(),
+                latitude.cos() * longitude.sin(),
+            );
+            let (face, texels) = face_images
+                .iter()
+                .zip(&face_radiance)
+                .max_by(|a, b| a.0.direction.dot(direction).total_cmp(&b.0.direction.dot(direction)))
+                .ok_or("No panorama faces")?;
+            let clip = face.view_proj * (request.camera_position + direction).extend(1.0);
+            let u = (clip.x / clip.w * 0.5 + 0.5) * face_size as f32 - 0.5;
+            let v = (clip.y / clip.w * 0.5 + 0.5) * face_size as f32 - 0.5;
+            let color = sample_bilinear(texels, face_size, u, v);
+            let o = (y as usize * width as usize + x as usize) * 3;
+            radiance[o..o + 3].copy_from_slice(&color);
+        }
+    }
+
+    let png: Vec<u8> = radiance.iter().map(|&c| linear_to_srgb(c)).collect();
+    let path = output_path("panorama", width, height, "png")?;
+    image::RgbImage::from_raw(width, height, png)
+        .ok_or("Panorama pixel buffer has the wrong size")?
+        .save(&path)?;
+    let hdr_path = path.with_extension("hdr");
+    image::DynamicImage::ImageRgb32F(
+        image::Rgb32FImage::from_raw(width, height, radiance).ok_or("Panorama pixel buffer has the wrong size")?,
+    )
+    .save(&hdr_path)?;
+
+    println!("✓ Saved panorama to {} and {}", path.display(), hdr_path.display());
+    Ok(path)
+}
+
+/// One face of a cube map rendered around a point
+pub struct CubeFace {
+    pub direction: Vec3, // Axis the face looks along
+    pub view_proj: Mat4,
+    pub pixels: Vec<u8>, // Texels of the scene pass's format, see `OffscreenTarget::read_texels`
+}
+
+/// Render the six `face_size` faces of a cube map of the glTF scene around `position`.
+/// Waits for the device to go idle first and leaves it idle.
+pub unsafe fn render_cube_faces(
+    renderer: &VulkanRenderer,
+    gltf_renderer: &GltfRenderer,
+    position: Vec3,
+    face_size: u32,
+    debug_cascades: bool,
+    shadow_softness: f32,
+    use_pcss: bool,
+) -> Result<Vec<CubeFace>, Box<dyn std::error::Error>> {
+    let face_extent = vk::Extent2D { width: face_size, height: face_size };
+
+    renderer.device.device_wait_idle()?;
+
+    let mut target =
+        OffscreenTarget::with_format(renderer, gltf_renderer.render_pass, gltf_renderer.color_format, face_extent)?;
+    let mut view = match gltf_renderer.create_view(renderer, face_extent) {
+        Ok(view) => view,
+        Err(e) => {
+            target.destroy(renderer);
+            return Err(e);
+        }
+    };
+    view.layers = gltf_renderer.capture_layers();
+
+    let mut proj = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+    proj.y_axis.y *= -1.0;
+    let faces = [
+        (Vec3::X, Vec3::Y),
+        (Vec3::NEG_X, Vec3::Y),
+        (Vec3::Y, Vec3::Z),
+        (Vec3::NEG_Y, Vec3::Z),
+        (Vec3::Z, Vec3::Y),
+        (Vec3::NEG_Z, Vec3::Y),
+    ];
+    let mut face_images = Vec::with_capacity(faces.len());
+    let mut result = Ok(());
+    for (direction, up) in faces {
+        let camera = ViewCamera {
+            position,
+            view: Mat4::look_at_rh(position, position + direction, up),
+            proj,
+        };
+        gltf_renderer.update_view_uniform_buffer(
+            &mut view,
+            0,
+            &camera,
+            debug_cascades,
+            shadow_softness,
+            use_pcss,
+        );
+        match render_tile(renderer, gltf_renderer, &view, &target) {
+            Ok(pixels) => face_images.push(CubeFace { direction, view_proj: camera.view_proj(), pixels }),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+
+    renderer.device.device_wait_idle()?;
+    gltf_renderer.destroy_view(renderer, view)?;
+    target.destroy(renderer);
+    result?;
+    Ok(face_images)
+}
+
+/// `screenshots/<kind>_<unix time>_<w>x<h>.<extension>`, creating the directory
+fn output_path(kind: &str, width: u32, height: u32, extension: &str) -> Result<PathBuf, std::io::Error> {
+    std::fs::create_dir_all("screenshots")?;
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Ok(PathBuf::from(format!("screenshots/{}_{}_{}x{}.{}", kind, timestamp, width, height, extension)))
+}
+
+/// Maps the part of clip space covered by the tile at `origin` (in pixels of the full
+/// image) onto the whole viewport
+fn tile_projection(origin: (u32, u32), tile: vk::Extent2D, full: (u32, u32)) -> Mat4 {
+    let scale_x = full.0 as f32 / tile.width as f32;
+    let scale_y = full.1 as f32 / tile.height as f32;
+    // Tile center in the full image's NDC (Vulkan: +Y is down, like pixel rows)
+    let center_x = (2 * origin.0 + tile.width) as f32 / full.0 as f32 - 1.0;
+    let center_y = (2 * origin.1 + tile.height) as f32 / full.1 as f32 - 1.0;
+    Mat4::from_cols_array(&[
+        scale_x, 0.0, 0.0, 0.0,
+        0.0, scale_y, 0.0, 0.0,
+        0.0, 0.0, 1.0, 0.0,
+        -center_x * scale_x, -center_y * scale_y, 0.0, 1.0,
+    ])
+}
+
+/// Render `view` into `target` and read it back, as RGBA8 unless the target is a float one
+/// (see `OffscreenTarget::read_texels`). Waits for the queue to go idle.
+pub unsafe fn render_tile(
+    renderer: &VulkanRenderer,
+    gltf_renderer: &GltfRenderer,
+    view: &crate::gltf_renderer::GltfView,
+    target: &OffscreenTarget,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let device = &renderer.device;
+    let cmd_info = vk::CommandBufferAllocateInfo::default()
+        .command_pool(renderer.command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1);
+    let cmd = device.allocate_command_buffers(&cmd_info)?[0];
+    let begin_info = vk::CommandBufferBeginInfo::default()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(cmd, &begin_info)?;
+
+    gltf_renderer.render_view(device, cmd, view, target.framebuffer, 0);
+
+    device.end_command_buffer(cmd)?;
+    let command_buffers = [cmd];
+    let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+    device.queue_submit(renderer.graphics_queue, &[submit_info], vk::Fence::null())?;
+    device.queue_wait_idle(renderer.graphics_queue)?;
+    device.free_command_buffers(renderer.command_pool, &command_buffers);
+
+    // The scene pass leaves color ready for presentation
+    target.read_texels(renderer, vk::ImageLayout::PRESENT_SRC_KHR)
+}
+
+/// Copy the visible part of a tile into the full image
+fn copy_tile(tile: &[u8], extent: vk::Extent2D, pixels: &mut [u8], full_width: u32, full_height: u32, origin: (u32, u32)) {
+    let width = extent.width.min(full_width - origin.0) as usize;
+    let height = extent.height.min(full_height - origin.1) as usize;
+    for row in 0..height {
+        let src = row * extent.width as usize * 4;
+        let dst = ((origin.1 as usize + row) * full_width as usize + origin.0 as usize) * 4;
+        pixels[dst..dst + width * 4].copy_from_slice(&tile[src..src + width * 4]);
+    }
+}
+
+/// Read-back pixels are sRGB encoded whether the swapchain format is UNORM (shaders
+/// encode) or SRGB (the hardware does); filtering happens in linear light.
+pub fn srgb_to_linear_table() -> Vec<f32> {
+    (0..256)
+        .map(|v| {
+            let c = v as f32 / 255.0;
+            if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+        })
+        .collect()
+}
+
+pub fn linear_to_srgb(c: f32) -> u8 {
+    let c = if c <= 0.003_130_8 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+/// RGB of a face of linear RGBA texels at texel coordinates `u`, `v`, clamped to the edges
+fn sample_bilinear(pixels: &[f32], size: u32, u: f32, v: f32) -> [f32; 3] {
+    let max = (size - 1) as f32;
+    let (u, v) = (u.clamp(0.0, max), v.clamp(0.0, max));
+    let (x0, y0) = (u.floor() as usize, v.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(size as usize - 1), (y0 + 1).min(size as usize - 1));
+    let (fx, fy) = (u.fract(), v.fract());
+    let texel = |x: usize, y: usize, c: usize| pixels[(y * size as usize + x) * 4 + c];
+    std::array::from_fn(|c| {
+        let top = texel(x0, y0, c) * (1.0 - fx) + texel(x1, y0, c) * fx;
+        let bottom = texel(x0, y1, c) * (1.0 - fx) + texel(x1, y1, c) * fx;
+        top * (1.0 - fy) + bottom * fy
+    })
+}
+
+/// Average `samples` x `samples` blocks in linear light
+fn downsample(pixels: &[u8], width: u32, height: u32, samples: u32) -> Vec<u8> {
+    let to_linear = srgb_to_linear_table();
+
+    let (out_width, out_height) = (width / samples, height / samples);
+    let weight = 1.0 / (samples * samples) as f32;
+    let mut out = vec![0u8; out_width as usize * out_height as usize * 4];
+    for y in 0..out_height as usize {
+        for x in 0..out_width as usize {
+            let mut sum = [0.0_f32; 3];
+            for sy in 0..samples as usize {
+                let row = (y * samples as usize + sy) * width as usize;
+                for sx in 0..samples as usize {
+                    let i = (row + x * samples as usize + sx) * 4;
+                    for (sum, &value) in sum.iter_mut().zip(&pixels[i..i + 3]) {
+                        *sum += to_linear[value as usize];
+                    }
+                }
+            }
+            let o = (y * out_width as usize + x) * 4;
+            for (value, sum) in out[o..o + 3].iter_mut().zip(sum) {
+                *value = linear_to_srgb(sum * weight);
+            }
+            out[o + 3] = 255;
+        }
+    }
+    out
+}
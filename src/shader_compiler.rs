@@ -0,0 +1,181 @@
+//! Shader loading
+//!
+//! By default shaders are the SPIR-V blobs committed next to their GLSL sources and
+//! embedded with `include_bytes!`. With the `runtime-shaders` feature the GLSL in
+//! `shaders/` is compiled with shaderc when a pipeline is created, and the result is
+//! cached in `.shader_cache/` keyed by a hash of the source, the files it `#include`s
+//! (looked up in `shaders/`, e.g. `frustum.glsl`) and the defines. If runtime
+//! compilation fails the embedded SPIR-V is used instead, with the compiler error printed.
+//!
+//! A source edited while the app runs is marked with `mark_changed` (see
+//! shader_watcher.rs), after which it is compiled from source like that even without the
+//! feature, with `glslc` from the Vulkan SDK or the `PATH` instead of shaderc.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// The GLSL sources
+pub const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shaders");
+const CACHE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/.shader_cache");
+
+/// Sources edited since startup, which no longer match their embedded SPIR-V
+static CHANGED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Load a shader by its source file name (e.g. `"cube.vert"`), falling back to the
+/// embedded SPIR-V compiled ahead of time.
+pub fn load_shader(name: &str, embedded_spv: &[u8]) -> Vec<u32> {
+    load_shader_permutation(name, &[], embedded_spv)
+}
+
+/// Load a shader permutation compiled with the given preprocessor defines. The embedded
+/// SPIR-V must be the matching prebuilt permutation (see build.rs).
+pub fn load_shader_permutation(name: &str, defines: &[&str], embedded_spv: &[u8]) -> Vec<u32> {
+    if cfg!(feature = "runtime-shaders") || is_changed(name) {
+        match compile(name, defines) {
+            Ok(code) => return code,
+            Err(e) => eprintln!("⚠ Runtime compile of {} {:?} failed, using embedded SPIR-V:\n{}", name, defines, e),
+        }
+    }
+
+    read_spirv(embedded_spv).expect("Embedded SPIR-V is malformed")
+}
+
+/// Compile source `name` from `shaders/` from now on, since it was edited
+pub fn mark_changed(name: &str) {
+    if let Ok(mut changed) = CHANGED.lock() {
+        if !changed.iter().any(|changed| changed == name) {
+            changed.push(name.to_string());
+        }
+    }
+}
+
+fn is_changed(name: &str) -> bool {
+    CHANGED.lock().is_ok_and(|changed| changed.iter().any(|changed| changed == name))
+}
+
+/// Compile `shaders/<name>` with `defines`, or take the result of an earlier compile of
+/// the same source from the cache
+pub fn compile(name: &str, defines: &[&str]) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let source_path = PathBuf::from(SHADER_DIR).join(name);
+    let source = std::fs::read_to_string(&source_path)?;
+
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    for include in includes(&source) {
+        std::fs::read_to_string(PathBuf::from(SHADER_DIR).join(include)).unwrap_or_default().hash(&mut hasher);
+    }
+    defines.hash(&mut hasher);
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    let cache_path = PathBuf::from(CACHE_DIR).join(format!("{}.{:016x}.spv", name, hasher.finish()));
+
+    if let Ok(bytes) = std::fs::read(&cache_path) {
+        if let Ok(code) = read_spirv(&bytes) {
+            return Ok(code);
+        }
+    }
+
+    // Ray queries and mesh shaders need SPIR-V 1.4, i.e. a Vulkan 1.2 target
+    let extension = source_path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let spirv_1_4 = defines.contains(&"RAY_QUERY") || matches!(extension, "task" | "mesh");
+
+    std::fs::create_dir_all(CACHE_DIR)?;
+    let code = compile_source(name, &source, defines, spirv_1_4)?;
+    let bytes: Vec<u8> = code.iter().flat_map(|word| word.to_le_bytes()).collect();
+    std::fs::write(&cache_path, bytes)?;
+    println!("✓ Compiled shader {} {:?} (cached)", name, defines);
+
+    Ok(code)
+}
+
+/// Names of the files `source` includes with `#include "name"`
+pub fn includes(source: &str) -> impl Iterator<Item = &str> {
+    source
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("#include"))
+        .filter_map(|rest| rest.trim().strip_prefix('"')?.strip_suffix('"'))
+}
+
+#[cfg(feature = "runtime-shaders")]
+fn compile_source(
+    name: &str,
+    source: &str,
+    defines: &[&str],
+    spirv_1_4: bool,
+) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let kind = match name.rsplit('.').next() {
+        Some("vert") => shaderc::ShaderKind::Vertex,
+        Some("frag") => shaderc::ShaderKind::Fragment,
+        Some("comp") => shaderc::ShaderKind::Compute,
+        Some("task") => shaderc::ShaderKind::Task,
+        Some("mesh") => shaderc::ShaderKind::Mesh,
+        _ => return Err(format!("Unknown shader stage for {}", name).into()),
+    };
+
+    let mut compiler = shaderc::Compiler::new().ok_or("Failed to initialize shaderc")?;
+    let mut options = shaderc::CompileOptions::new().ok_or("Failed to create shaderc options")?;
+    let env_version = if spirv_1_4 {
+        shaderc::EnvVersion::Vulkan1_2
+    } else {
+        shaderc::EnvVersion::Vulkan1_0
+    };
+    options.set_target_env(shaderc::TargetEnv::Vulkan, env_version as u32);
+    for define in defines {
+        options.add_macro_definition(define, None);
+    }
+    options.set_include_callback(|requested, _, _, _| {
+        let path = PathBuf::from(SHADER_DIR).join(requested);
+        std::fs::read_to_string(&path)
+            .map(|content| shaderc::ResolvedInclude { resolved_name: path.display().to_string(), content })
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    });
+
+    let artifact = compiler.compile_into_spirv(source, kind, name, "main", Some(&options))?;
+    if artifact.get_num_warnings() > 0 {
+        println!("⚠ {}: {}", name, artifact.get_warning_messages());
+    }
+    Ok(artifact.as_binary().to_vec())
+}
+
+/// Without shaderc, run the Vulkan SDK's `glslc` (or one on the `PATH`) on the file
+#[cfg(not(feature = "runtime-shaders"))]
+fn compile_source(
+    name: &str,
+    _source: &str,
+    defines: &[&str],
+    spirv_1_4: bool,
+) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let executable = if cfg!(windows) { "glslc.exe" } else { "glslc" };
+    let glslc = std::env::var_os("VULKAN_SDK")
+        .map(|sdk| PathBuf::from(sdk).join("bin").join(executable))
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(executable));
+    let output_path = PathBuf::from(CACHE_DIR).join(format!("{}.glslc.spv", name));
+
+    let mut command = std::process::Command::new(&glslc);
+    if spirv_1_4 {
+        command.arg("--target-env=vulkan1.2");
+    }
+    for define in defines {
+        command.arg(format!("-D{}", define));
+    }
+    let output = command
+        .arg(PathBuf::from(SHADER_DIR).join(name))
+        .arg("-o")
+        .arg(&output_path)
+        .output()
+        .map_err(|e| format!("Failed to run {} (install the Vulkan SDK): {}", glslc.display(), e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned().into());
+    }
+    let bytes = std::fs::read(&output_path)?;
+    let _ = std::fs::remove_file(&output_path);
+    Ok(read_spirv(&bytes)?)
+}
+
+/// Decode little-endian SPIR-V bytes into words (handles alignment and magic check).
+pub fn read_spirv(bytes: &[u8]) -> std::io::Result<Vec<u32>> {
+    let mut cursor = std::io::Cursor::new(bytes);
+    ash::util::read_spv(&mut cursor)
+}
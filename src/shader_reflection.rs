@@ -0,0 +1,370 @@
+//! SPIR-V reflection
+//!
+//! Reads descriptor bindings, push-constant blocks and vertex inputs straight out of
+//! the compiled shaders, so pipeline layouts are derived from (and validated against)
+//! the SPIR-V instead of being kept in sync with the GLSL by hand.
+
+use ash::vk;
+use rspirv::dr::{Instruction, Operand};
+use rspirv::spirv::{Decoration, Dim, ExecutionMode, ExecutionModel, Op, StorageClass, Word};
+use std::collections::HashMap;
+
+/// A resource binding declared by a shader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+}
+
+/// A vertex shader input (`layout(location = N) in ...`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReflectedInput {
+    pub location: u32,
+    pub components: u32,
+    pub is_float: bool,
+}
+
+/// Everything the pipeline layout and vertex input state need to know about one stage
+#[derive(Debug, Clone)]
+pub struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    pub bindings: Vec<ReflectedBinding>,
+    /// Size in bytes of the push-constant block (0 if the stage has none)
+    pub push_constant_size: u32,
+    pub inputs: Vec<ReflectedInput>,
+    /// Compute workgroup size (`local_size_x/y/z`), `[1, 1, 1]` for graphics stages
+    pub local_size: [u32; 3],
+}
+
+impl ShaderReflection {
+    pub fn reflect(code: &[u32]) -> Result<Self, Box<dyn std::error::Error>> {
+        let module = rspirv::dr::load_words(code)?;
+
+        let stage = match module.entry_points.first().map(|e| e.operands[0].unwrap_execution_model()) {
+            Some(ExecutionModel::Vertex) => vk::ShaderStageFlags::VERTEX,
+            Some(ExecutionModel::Fragment) => vk::ShaderStageFlags::FRAGMENT,
+            Some(ExecutionModel::GLCompute) => vk::ShaderStageFlags::COMPUTE,
+            Some(model) => return Err(format!("Unsupported execution model {:?}", model).into()),
+            None => return Err("SPIR-V module has no entry point".into()),
+        };
+
+        let types = TypeTable::new(&module);
+
+        let mut local_size = [1, 1, 1];
+        for mode in &module.execution_modes {
+            if mode.operands.get(1).map(|o| o.unwrap_execution_mode()) == Some(ExecutionMode::LocalSize) {
+                for (axis, operand) in mode.operands[2..].iter().take(3).enumerate() {
+                    local_size[axis] = operand.unwrap_literal_int32();
+                }
+            }
+        }
+
+        let mut bindings = Vec::new();
+        let mut inputs = Vec::new();
+        let mut push_constant_size = 0;
+
+        for inst in &module.types_global_values {
+            if inst.class.opcode != Op::Variable {
+                continue;
+            }
+            let (Some(id), Some(pointer_type)) = (inst.result_id, inst.result_type) else {
+                continue;
+            };
+            let storage_class = inst.operands[0].unwrap_storage_class();
+            let pointee = types.pointee(pointer_type)?;
+
+            match storage_class {
+                StorageClass::Uniform | StorageClass::UniformConstant | StorageClass::StorageBuffer => {
+                    let set = types.decoration(id, Decoration::DescriptorSet).unwrap_or(0);
+                    let Some(binding) = types.decoration(id, Decoration::Binding) else {
+                        continue;
+                    };
+                    let (element, count) = types.array_element(pointee)?;
+                    bindings.push(ReflectedBinding {
+                        set,
+                        binding,
+                        descriptor_type: types.descriptor_type(element, storage_class)?,
+                        count,
+                    });
+                }
+                StorageClass::PushConstant => {
+                    push_constant_size = types.size_of(pointee)?;
+                }
+                StorageClass::Input if stage == vk::ShaderStageFlags::VERTEX => {
+                    if types.has_decoration(id, Decoration::BuiltIn) {
+                        continue;
+                    }
+                    let Some(location) = types.decoration(id, Decoration::Location) else {
+                        continue;
+                    };
+                    let (components, is_float) = types.scalar_layout(pointee)?;
+                    inputs.push(ReflectedInput { location, components, is_float });
+                }
+                _ => {}
+            }
+        }
+
+        bindings.sort_by_key(|b| (b.set, b.binding));
+        inputs.sort_by_key(|i| i.location);
+
+        Ok(Self { stage, bindings, push_constant_size, inputs, local_size })
+    }
+}
+
+/// Merge the bindings of every stage in a pipeline into one descriptor set layout
+pub fn set_layout_bindings(
+    shaders: &[&ShaderReflection],
+    set: u32,
+) -> Result<Vec<vk::DescriptorSetLayoutBinding<'static>>, String> {
+    let mut merged: Vec<vk::DescriptorSetLayoutBinding<'static>> = Vec::new();
+
+    for shader in shaders {
+        for b in shader.bindings.iter().filter(|b| b.set == set) {
+            if let Some(existing) = merged.iter_mut().find(|m| m.binding == b.binding) {
+                if existing.descriptor_type != b.descriptor_type || existing.descriptor_count != b.count {
+                    return Err(format!(
+                        "Binding {} in set {} is {:?}[{}] in one stage but {:?}[{}] in another",
+                        b.binding, set, existing.descriptor_type, existing.descriptor_count,
+                        b.descriptor_type, b.count,
+                    ));
+                }
+                existing.stage_flags |= shader.stage;
+            } else {
+                merged.push(
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(b.binding)
+                        .descriptor_type(b.descriptor_type)
+                        .descriptor_count(b.count)
+                        .stage_flags(shader.stage),
+                );
+            }
+        }
+    }
+
+    merged.sort_by_key(|b| b.binding);
+    Ok(merged)
+}
+
+/// Push-constant range covering every stage that declares a block. `cpu_size` is the
+/// size of the Rust struct pushed from the CPU; a shader block larger than that is an error.
+pub fn push_constant_range(
+    shaders: &[&ShaderReflection],
+    cpu_size: u32,
+) -> Result<Option<vk::PushConstantRange>, String> {
+    let mut stages = vk::ShaderStageFlags::empty();
+    for shader in shaders.iter().filter(|s| s.push_constant_size > 0) {
+        if shader.push_constant_size > cpu_size {
+            return Err(format!(
+                "{:?} push constants are {} bytes but the CPU struct is {} bytes",
+                shader.stage, shader.push_constant_size, cpu_size,
+            ));
+        }
+        stages |= shader.stage;
+    }
+
+    Ok((!stages.is_empty()).then(|| {
+        vk::PushConstantRange::default()
+            .stage_flags(stages)
+            .offset(0)
+            .size(cpu_size)
+    }))
+}
+
+/// Check that every vertex shader input is fed by an attribute with a compatible format
+pub fn validate_vertex_input(
+    vertex_shader: &ShaderReflection,
+    attributes: &[vk::VertexInputAttributeDescription],
+) -> Result<(), String> {
+    for input in &vertex_shader.inputs {
+        let Some(attribute) = attributes.iter().find(|a| a.location == input.location) else {
+            return Err(format!("Vertex input location {} has no attribute", input.location));
+        };
+        let (components, is_float) = format_layout(attribute.format)
+            .ok_or_else(|| format!("Unsupported vertex format {:?}", attribute.format))?;
+        if components != input.components || is_float != input.is_float {
+            return Err(format!(
+                "Vertex input location {} expects {} {} components but the attribute is {:?}",
+                input.location,
+                input.components,
+                if input.is_float { "float" } else { "integer" },
+                attribute.format,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Component count and float-ness of the vertex formats used in this renderer
+fn format_layout(format: vk::Format) -> Option<(u32, bool)> {
+    Some(match format {
+        vk::Format::R32_SFLOAT => (1, true),
+        vk::Format::R32G32_SFLOAT => (2, true),
+        vk::Format::R32G32B32_SFLOAT => (3, true),
+        vk::Format::R32G32B32A32_SFLOAT => (4, true),
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SNORM => (4, true),
+        vk::Format::R16G16B16A16_UNORM | vk::Format::R16G16B16A16_SFLOAT => (4, true),
+        vk::Format::R32_UINT | vk::Format::R32_SINT => (1, false),
+        vk::Format::R32G32_UINT | vk::Format::R32G32_SINT => (2, false),
+        vk::Format::R32G32B32A32_UINT | vk::Format::R32G32B32A32_SINT => (4, false),
+        vk::Format::R16G16B16A16_UINT | vk::Format::R8G8B8A8_UINT => (4, false),
+        _ => return None,
+    })
+}
+
+/// Id lookups over the module's types, constants and decorations
+struct TypeTable<'a> {
+    defs: HashMap<Word, &'a Instruction>,
+    decorations: HashMap<Word, Vec<(Decoration, Option<u32>)>>,
+    member_offsets: HashMap<(Word, u32), u32>,
+    strides: HashMap<Word, u32>,
+}
+
+impl<'a> TypeTable<'a> {
+    fn new(module: &'a rspirv::dr::Module) -> Self {
+        let defs = module
+            .types_global_values
+            .iter()
+            .filter_map(|inst| inst.result_id.map(|id| (id, inst)))
+            .collect();
+
+        let mut decorations: HashMap<Word, Vec<(Decoration, Option<u32>)>> = HashMap::new();
+        let mut member_offsets = HashMap::new();
+        let mut strides = HashMap::new();
+
+        for inst in &module.annotations {
+            match inst.class.opcode {
+                Op::Decorate => {
+                    let target = inst.operands[0].unwrap_id_ref();
+                    let decoration = inst.operands[1].unwrap_decoration();
+                    let value = match inst.operands.get(2) {
+                        Some(Operand::LiteralInt32(v)) => Some(*v),
+                        _ => None,
+                    };
+                    if decoration == Decoration::ArrayStride {
+                        if let Some(v) = value {
+                            strides.insert(target, v);
+                        }
+                    }
+                    decorations.entry(target).or_default().push((decoration, value));
+                }
+                Op::MemberDecorate => {
+                    let target = inst.operands[0].unwrap_id_ref();
+                    let member = inst.operands[1].unwrap_literal_int32();
+                    if inst.operands[2].unwrap_decoration() == Decoration::Offset {
+                        member_offsets.insert((target, member), inst.operands[3].unwrap_literal_int32());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self { defs, decorations, member_offsets, strides }
+    }
+
+    fn def(&self, id: Word) -> Result<&'a Instruction, String> {
+        self.defs.get(&id).copied().ok_or_else(|| format!("SPIR-V id {} is not defined", id))
+    }
+
+    fn decoration(&self, id: Word, decoration: Decoration) -> Option<u32> {
+        self.decorations.get(&id)?.iter().find(|(d, _)| *d == decoration)?.1
+    }
+
+    fn has_decoration(&self, id: Word, decoration: Decoration) -> bool {
+        self.decorations.get(&id).is_some_and(|d| d.iter().any(|(d, _)| *d == decoration))
+    }
+
+    fn pointee(&self, pointer_type: Word) -> Result<Word, String> {
+        let inst = self.def(pointer_type)?;
+        match inst.class.opcode {
+            Op::TypePointer => Ok(inst.operands[1].unwrap_id_ref()),
+            op => Err(format!("Expected a pointer type, found {:?}", op)),
+        }
+    }
+
+    fn constant_u32(&self, id: Word) -> Result<u32, String> {
+        match self.def(id)?.operands.first() {
+            Some(Operand::LiteralInt32(v)) => Ok(*v),
+            _ => Err(format!("SPIR-V id {} is not a 32-bit constant", id)),
+        }
+    }
+
+    /// Unwrap a (possibly arrayed) resource type into its element type and descriptor count
+    fn array_element(&self, ty: Word) -> Result<(Word, u32), String> {
+        let inst = self.def(ty)?;
+        match inst.class.opcode {
+            Op::TypeArray => Ok((inst.operands[0].unwrap_id_ref(), self.constant_u32(inst.operands[1].unwrap_id_ref())?)),
+            Op::TypeRuntimeArray => Err("Runtime-sized descriptor arrays are not supported".into()),
+            _ => Ok((ty, 1)),
+        }
+    }
+
+    fn descriptor_type(&self, ty: Word, storage_class: StorageClass) -> Result<vk::DescriptorType, String> {
+        let inst = self.def(ty)?;
+        Ok(match (inst.class.opcode, storage_class) {
+            (Op::TypeStruct, StorageClass::StorageBuffer) => vk::DescriptorType::STORAGE_BUFFER,
+            (Op::TypeStruct, _) if self.has_decoration(ty, Decoration::BufferBlock) => vk::DescriptorType::STORAGE_BUFFER,
+            (Op::TypeStruct, _) => vk::DescriptorType::UNIFORM_BUFFER,
+            (Op::TypeSampledImage, _) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (Op::TypeSampler, _) => vk::DescriptorType::SAMPLER,
+            (Op::TypeImage, _) => {
+                let is_buffer = inst.operands[1].unwrap_dim() == Dim::DimBuffer;
+                let is_storage = inst.operands[5].unwrap_literal_int32() == 2;
+                match (is_buffer, is_storage) {
+                    (true, true) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                    (true, false) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                    (false, true) => vk::DescriptorType::STORAGE_IMAGE,
+                    (false, false) => vk::DescriptorType::SAMPLED_IMAGE,
+                }
+            }
+            (Op::TypeAccelerationStructureKHR, _) => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            (op, _) => return Err(format!("Unsupported resource type {:?}", op)),
+        })
+    }
+
+    /// Component count and float-ness of a scalar/vector input type
+    fn scalar_layout(&self, ty: Word) -> Result<(u32, bool), String> {
+        let inst = self.def(ty)?;
+        match inst.class.opcode {
+            Op::TypeFloat => Ok((1, true)),
+            Op::TypeInt => Ok((1, false)),
+            Op::TypeVector => {
+                let (_, is_float) = self.scalar_layout(inst.operands[0].unwrap_id_ref())?;
+                Ok((inst.operands[1].unwrap_literal_int32(), is_float))
+            }
+            op => Err(format!("Unsupported vertex input type {:?}", op)),
+        }
+    }
+
+    /// Byte size of a type laid out with explicit Offset/ArrayStride/MatrixStride decorations
+    fn size_of(&self, ty: Word) -> Result<u32, String> {
+        let inst = self.def(ty)?;
+        match inst.class.opcode {
+            Op::TypeFloat | Op::TypeInt => Ok(inst.operands[0].unwrap_literal_int32() / 8),
+            Op::TypeVector => Ok(self.size_of(inst.operands[0].unwrap_id_ref())? * inst.operands[1].unwrap_literal_int32()),
+            Op::TypeMatrix => {
+                let columns = inst.operands[1].unwrap_literal_int32();
+                let column_size = self.size_of(inst.operands[0].unwrap_id_ref())?;
+                Ok(columns * column_size.next_multiple_of(16))
+            }
+            Op::TypeArray => {
+                let length = self.constant_u32(inst.operands[1].unwrap_id_ref())?;
+                let stride = match self.strides.get(&ty) {
+                    Some(stride) => *stride,
+                    None => self.size_of(inst.operands[0].unwrap_id_ref())?,
+                };
+                Ok(length * stride)
+            }
+            Op::TypeStruct => {
+                let mut size = 0;
+                for (member, operand) in inst.operands.iter().enumerate() {
+                    let offset = self.member_offsets.get(&(ty, member as u32)).copied().unwrap_or(size);
+                    size = size.max(offset + self.size_of(operand.unwrap_id_ref())?);
+                }
+                Ok(size)
+            }
+            op => Err(format!("Cannot compute size of {:?}", op)),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Shader hot reload
+//!
+//! While the app runs, the GLSL sources in `shaders/` are checked for edits a couple of
+//! times a second by their modification times. An edited shader is recompiled (see
+//! `shader_compiler`) and, when that succeeds, the pipelines built from it are rebuilt
+//! with the new code; a shader that fails to compile leaves the running pipelines alone
+//! and prints the compiler's errors instead. An edited include (`.glsl`) counts as an edit
+//! of every source including it.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::shader_compiler::{self, SHADER_DIR};
+
+/// How often the sources are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Extensions of the shader sources; the compiled `.spv` files next to them are ignored
+const SOURCE_EXTENSIONS: [&str; 5] = ["vert", "frag", "comp", "task", "mesh"];
+/// Extension of the files sources `#include`
+const INCLUDE_EXTENSION: &str = "glsl";
+
+pub struct ShaderWatcher {
+    modified: HashMap<String, SystemTime>, // Last seen modification time of each source
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> Self {
+        Self { modified: scan(Path::new(SHADER_DIR)), last_poll: Instant::now() }
+    }
+
+    /// Names of the sources (e.g. `"cube.frag"`) added or modified since the last poll.
+    /// Only looks at the files once every `POLL_INTERVAL`.
+    pub fn poll(&mut self) -> Vec<String> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let modified = scan(Path::new(SHADER_DIR));
+        let (includes, mut changed): (Vec<String>, Vec<String>) = modified
+            .iter()
+            .filter(|&(name, time)| self.modified.get(name) != Some(time))
+            .map(|(name, _)| name.clone())
+            .partition(|name| is_include(Path::new(name)));
+        for include in &includes {
+            changed.extend(includers(&modified, include));
+        }
+        changed.sort();
+        changed.dedup();
+        self.modified = modified;
+        changed
+    }
+}
+
+/// Modification time of every shader source and include in `dir`
+fn scan(dir: &Path) -> HashMap<String, SystemTime> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| is_source(path) || is_include(path))
+        .filter_map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()?;
+            Some((path.file_name()?.to_str()?.to_string(), modified))
+        })
+        .collect()
+}
+
+fn is_source(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| SOURCE_EXTENSIONS.contains(&extension))
+}
+
+fn is_include(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == INCLUDE_EXTENSION)
+}
+
+/// The sources among `files` that include `include`
+fn includers(files: &HashMap<String, SystemTime>, include: &str) -> Vec<String> {
+    files
+        .keys()
+        .filter(|name| is_source(Path::new(name)))
+        .filter(|name| {
+            std::fs::read_to_string(Path::new(SHADER_DIR).join(name))
+                .is_ok_and(|source| shader_compiler::includes(&source).any(|included| included == include))
+        })
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,323 @@
+//! Compute skinning
+//!
+//! Skinned glTF meshes are skinned once per frame in a compute pass into a transient
+//! vertex buffer (one per frame in flight). The shadow and main passes then bind that
+//! buffer with the regular glTF pipelines instead of each re-skinning in a vertex shader.
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+use crate::compute::{self, Access, ComputePipeline, DescriptorWriter};
+use crate::debug_draw::DebugDraw;
+use crate::gltf_loader::{GltfScene, GltfSkin};
+use crate::gltf_renderer::{GltfMeshBuffers, GltfVertex};
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::shader_compiler::load_shader;
+
+/// Matches `Influence` in skinning.comp
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Influence {
+    joints: [u32; 4],
+    weights: [f32; 4],
+}
+
+pub struct SkinnedMesh {
+    pub mesh_index: usize,
+    pub skin_index: usize,
+    pub vertex_count: u32,
+    /// Current pose; uploaded to this frame's joint buffer by `SkinningPass::record`
+    pub joint_matrices: Vec<glam::Mat4>,
+    pub influence_buffer: vk::Buffer,
+    pub influence_allocation: Option<Allocation>,
+    pub joint_buffers: Vec<vk::Buffer>,
+    pub joint_allocations: Vec<Option<Allocation>>,
+    pub output_buffers: Vec<vk::Buffer>,
+    pub output_allocations: Vec<Option<Allocation>>,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+pub struct SkinningPass {
+    pub enabled: bool,
+    pub pipeline: ComputePipeline,
+    pub meshes: Vec<SkinnedMesh>,
+    pub skins: Vec<GltfSkin>, // All of the scene's skins, indexed by `SkinnedMesh::skin_index`
+    frame_index: usize, // Frame slot whose output buffers were written last
+}
+
+impl SkinningPass {
+    /// Returns `None` if the scene has no skinned meshes. The vertex buffers of skinned
+    /// meshes in `mesh_buffers` must have been created with `STORAGE_BUFFER` usage.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        scene: &GltfScene,
+        mesh_buffers: &[GltfMeshBuffers],
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let skinned: Vec<usize> = scene
+            .meshes
+            .iter()
+            .enumerate()
+            .filter(|(_, mesh)| mesh.has_joints && mesh.skin_index.is_some() && mesh.joints.len() == mesh.vertices.len())
+            .map(|(i, _)| i)
+            .collect();
+        if skinned.is_empty() {
+            return Ok(None);
+        }
+
+        let device = &renderer.device;
+        let code = load_shader("skinning.comp", include_bytes!("../shaders/skinning.comp.spv"));
+        let pipeline = ComputePipeline::new(
+            renderer,
+            &code,
+            std::mem::size_of::<u32>() as u32,
+            (skinned.len() * MAX_FRAMES_IN_FLIGHT) as u32,
+        )?;
+
+        let mut meshes = Vec::new();
+        for mesh_index in skinned {
+            let mesh = &scene.meshes[mesh_index];
+            let skin_index = mesh.skin_index.unwrap();
+            let skin = &scene.skins[skin_index];
+            let vertex_count = mesh.vertices.len();
+
+            let influences: Vec<Influence> = mesh
+                .joints
+                .iter()
+                .zip(&mesh.weights)
+                .map(|(joints, weights)| Influence {
+                    joints: joints.map(u32::from),
+                    weights: *weights,
+                })
+                .collect();
+            let (influence_buffer, influence_allocation) = create_buffer(
+                renderer,
+                "skin_influences",
+                std::mem::size_of_val(influences.as_slice()) as u64,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+            )?;
+            let ptr = influence_allocation.mapped_ptr().unwrap().as_ptr() as *mut Influence;
+            std::ptr::copy_nonoverlapping(influences.as_ptr(), ptr, influences.len());
+
+            let joint_buffer_size = (std::mem::size_of::<glam::Mat4>() * skin.joints.len().max(1)) as u64;
+            let output_size = (std::mem::size_of::<GltfVertex>() * vertex_count) as u64;
+            let mut joint_buffers = Vec::new();
+            let mut joint_allocations = Vec::new();
+            let mut output_buffers = Vec::new();
+            let mut output_allocations = Vec::new();
+            let mut descriptor_sets = Vec::new();
+            for _ in 0..MAX_FRAMES_IN_FLIGHT {
+                let (joint_buffer, joint_allocation) = create_buffer(
+                    renderer,
+                    "skin_joint_matrices",
+                    joint_buffer_size,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                )?;
+
+                // Written by the GPU only, so keep it in device-local memory
+                let buffer_info = vk::BufferCreateInfo::default()
+                    .size(output_size)
+                    .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE);
+                let output_buffer = device.create_buffer(&buffer_info, None)?;
+                let requirements = device.get_buffer_memory_requirements(output_buffer);
+                let output_allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+                    name: "skinned_vertex_buffer",
+                    requirements,
+                    location: MemoryLocation::GpuOnly,
+                    linear: true,
+                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                })?;
+                device.bind_buffer_memory(output_buffer, output_allocation.memory(), output_allocation.offset())?;
+
+                let set = pipeline.allocate_descriptor_set(device)?;
+                DescriptorWriter::new()
+                    .storage_buffer(0, mesh_buffers[mesh_index].vertex_buffer)
+                    .storage_buffer(1, influence_buffer)
+                    .storage_buffer(2, joint_buffer)
+                    .storage_buffer(3, output_buffer)
+                    .write(device, set);
+
+                joint_buffers.push(joint_buffer);
+                joint_allocations.push(Some(joint_allocation));
+                output_buffers.push(output_buffer);
+                output_allocations.push(Some(output_allocation));
+                descriptor_sets.push(set);
+            }
+
+            meshes.push(SkinnedMesh {
+                mesh_index,
+                skin_index,
+                vertex_count: vertex_count as u32,
+                joint_matrices: skin.rest_joint_matrices.iter().map(glam::Mat4::from_cols_array_2d).collect(),
+                influence_buffer,
+                influence_allocation: Some(influence_allocation),
+                joint_buffers,
+                joint_allocations,
+                output_buffers,
+                output_allocations,
+                descriptor_sets,
+            });
+        }
+
+        println!("✓ Compute skinning for {} skinned meshes", meshes.len());
+
+        Ok(Some(Self {
+            enabled: true,
+            pipeline,
+            meshes,
+            skins: scene.skins.clone(),
+            frame_index: 0,
+        }))
+    }
+
+    /// Upload joint matrices and skin every mesh for `frame_index`. Record before any
+    /// pass that draws the meshes.
+    pub unsafe fn record(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        if !self.enabled {
+            return;
+        }
+
+        // Earlier submits (other views included) may still be reading this slot's output
+        compute::memory_barrier(device, command_buffer, Access::VERTEX_INPUT, Access::COMPUTE_WRITE);
+
+        for mesh in &self.meshes {
+            if let Some(allocation) = &mesh.joint_allocations[frame_index] {
+                let ptr = allocation.mapped_ptr().unwrap().as_ptr() as *mut glam::Mat4;
+                std::ptr::copy_nonoverlapping(mesh.joint_matrices.as_ptr(), ptr, mesh.joint_matrices.len());
+            }
+
+            let bytes = std::slice::from_raw_parts(
+                &mesh.vertex_count as *const u32 as *const u8,
+                std::mem::size_of::<u32>(),
+            );
+            self.pipeline.dispatch_threads(
+                device,
+                command_buffer,
+                mesh.descriptor_sets[frame_index],
+                bytes,
+                [mesh.vertex_count, 1, 1],
+            );
+        }
+
+        compute::memory_barrier(device, command_buffer, Access::COMPUTE_WRITE, Access::VERTEX_INPUT);
+        self.frame_index = frame_index;
+    }
+
+    /// Pose every skinned mesh from the global transform of each node, indexed like
+    /// `GltfScene::nodes` (see `GltfAnimationPlayer::node_transforms`)
+    pub fn set_pose(&mut self, node_transforms: &[glam::Mat4]) {
+        for mesh in &mut self.meshes {
+            let skin = &self.skins[mesh.skin_index];
+            mesh.joint_matrices = skin
+                .joints
+                .iter()
+                .zip(&skin.inverse_bind_matrices)
+                .map(|(&joint, inverse_bind)| {
+                    let node = node_transforms.get(joint).copied().unwrap_or(glam::Mat4::IDENTITY);
+                    node * glam::Mat4::from_cols_array_2d(inverse_bind)
+                })
+                .collect();
+        }
+    }
+
+    /// Skinned vertex buffer to draw `mesh_index` with, if it is skinned and skinning is on
+    pub fn vertex_buffer(&self, mesh_index: usize) -> Option<vk::Buffer> {
+        if !self.enabled {
+            return None;
+        }
+        self.meshes
+            .iter()
+            .find(|mesh| mesh.mesh_index == mesh_index)
+            .map(|mesh| mesh.output_buffers[self.frame_index])
+    }
+
+    /// Queue the joint hierarchy of every skin in use: the current pose as octahedral bones
+    /// in orange, over a grey ghost of the bind pose the inverse bind matrices describe. A
+    /// skeleton in its rest pose should cover its ghost; where they part, the inverse bind
+    /// matrices disagree with the node hierarchy.
+    pub fn draw_skeletons(&self, draw: &mut DebugDraw, model: glam::Mat4, labels: bool) {
+        const POSE: egui::Color32 = egui::Color32::from_rgb(255, 170, 40);
+        const BIND_POSE: egui::Color32 = egui::Color32::from_gray(110);
+
+        for (skin_index, skin) in self.skins.iter().enumerate() {
+            let Some(mesh) = self.meshes.iter().find(|mesh| mesh.skin_index == skin_index) else {
+                continue;
+            };
+            let bind: Vec<glam::Mat4> = skin
+                .inverse_bind_matrices
+                .iter()
+                .map(|inverse_bind| glam::Mat4::from_cols_array_2d(inverse_bind).inverse())
+                .collect();
+            let world = |transform: glam::Mat4| model.transform_point3(transform.w_axis.truncate());
+            let bind_positions: Vec<glam::Vec3> = bind.iter().copied().map(world).collect();
+            let positions: Vec<glam::Vec3> =
+                mesh.joint_matrices.iter().zip(&bind).map(|(joint, bind)| world(*joint * *bind)).collect();
+
+            let bones: Vec<(usize, usize)> = skin
+                .joint_parents
+                .iter()
+                .enumerate()
+                .filter_map(|(joint, parent)| parent.map(|parent| (parent, joint)))
+                .collect();
+            let mean_length = bones
+                .iter()
+                .map(|&(parent, joint)| positions[parent].distance(positions[joint]))
+                .sum::<f32>()
+                / bones.len().max(1) as f32;
+            let joint_radius = (mean_length * 0.1).max(1e-3);
+
+            for &(parent, joint) in &bones {
+                draw.line(bind_positions[parent], bind_positions[joint], BIND_POSE);
+                draw.bone(positions[parent], positions[joint], POSE);
+            }
+            for (joint, &position) in positions.iter().enumerate() {
+                draw.octahedron(position, joint_radius, POSE);
+                if labels {
+                    draw.label(position, &skin.joint_names[joint], egui::Color32::WHITE);
+                }
+            }
+        }
+    }
+
+    pub unsafe fn cleanup(&mut self, renderer: &VulkanRenderer) {
+        self.pipeline.destroy(renderer);
+        for mut mesh in self.meshes.drain(..) {
+            let buffers = std::iter::once(mesh.influence_buffer)
+                .chain(mesh.joint_buffers.drain(..))
+                .chain(mesh.output_buffers.drain(..));
+            for buffer in buffers {
+                renderer.device.destroy_buffer(buffer, None);
+            }
+            let allocations = std::iter::once(mesh.influence_allocation.take())
+                .chain(mesh.joint_allocations.drain(..))
+                .chain(mesh.output_allocations.drain(..));
+            for alloc in allocations.flatten() {
+                let _ = renderer.allocator.lock().free(alloc);
+            }
+        }
+    }
+}
+
+/// Host-visible buffer, mapped for CPU writes
+unsafe fn create_buffer(
+    renderer: &VulkanRenderer,
+    name: &str,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+) -> Result<(vk::Buffer, Allocation), Box<dyn std::error::Error>> {
+    let buffer_info = vk::BufferCreateInfo::default()
+        .size(size)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = renderer.device.create_buffer(&buffer_info, None)?;
+    let requirements = renderer.device.get_buffer_memory_requirements(buffer);
+    let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+        name,
+        requirements,
+        location: MemoryLocation::CpuToGpu,
+        linear: true,
+        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+    })?;
+    renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+    Ok((buffer, allocation))
+}
@@ -0,0 +1,161 @@
+//! Environment skybox
+//!
+//! `--environment sky.hdr` (or .exr) puts an equirectangular HDR image behind the scene in
+//! place of the flat sky color. It is loaded as a float texture (see `hdr_image`) and drawn
+//! first in the scene pass, as a full-screen triangle looking up each pixel's view direction
+//! in it. The draw neither tests nor writes depth, so the scene covers it and the passes
+//! reading depth afterwards still find open sky there.
+//!
+//! The image is only a backdrop: ambient light and reflections don't sample it yet.
+
+use std::path::Path;
+
+use ash::vk;
+use glam::Mat4;
+
+use crate::command_encoder::{PipelineBinding, RenderPassEncoder};
+use crate::compute::DescriptorWriter;
+use crate::gltf_renderer::{GltfRenderer, TextureResources};
+use crate::hdr_image::HdrPrecision;
+use crate::pipeline_builder::{DepthMode, GraphicsPipelineBuilder};
+use crate::renderer::VulkanRenderer;
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+
+// Must match shaders/skybox.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SkyboxPushConstants {
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+pub struct Skybox {
+    texture: TextureResources,
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl Skybox {
+    /// Load the environment image at `path` and build its pipeline for `render_pass`, the
+    /// glTF scene pass
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        render_pass: vk::RenderPass,
+        path: &Path,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let texture = GltfRenderer::create_hdr_texture(renderer, path, HdrPrecision::default())?;
+        let mut skybox = Self {
+            texture,
+            pipeline: vk::Pipeline::null(),
+            layout: vk::PipelineLayout::null(),
+            render_pass,
+            set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+        };
+        if let Err(e) = skybox.create_pipeline(renderer) {
+            skybox.destroy(renderer);
+            return Err(e);
+        }
+        Ok(skybox)
+    }
+
+    unsafe fn create_pipeline(&mut self, renderer: &VulkanRenderer) -> Result<(), Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let (vert_code, frag_code) = Self::shaders();
+        let reflections = [&ShaderReflection::reflect(&vert_code)?, &ShaderReflection::reflect(&frag_code)?];
+
+        let bindings = shader_reflection::set_layout_bindings(&reflections, 0)?;
+        self.set_layout =
+            device.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings), None)?;
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &reflections,
+            std::mem::size_of::<SkyboxPushConstants>() as u32,
+        )?
+        .into_iter()
+        .collect();
+        self.layout = device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(std::slice::from_ref(&self.set_layout))
+                .push_constant_ranges(&push_constant_ranges),
+            None,
+        )?;
+
+        let pool_size = vk::DescriptorPoolSize { ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER, descriptor_count: 1 };
+        self.descriptor_pool = device.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::default()
+                .pool_sizes(std::slice::from_ref(&pool_size))
+                .max_sets(1),
+            None,
+        )?;
+        self.descriptor_set = device.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(std::slice::from_ref(&self.set_layout)),
+        )?[0];
+        DescriptorWriter::new()
+            .sampled_image(0, self.texture.image_view, self.texture.sampler)
+            .write(device, self.descriptor_set);
+
+        self.pipeline = self.build_pipeline(device, self.render_pass)?;
+        Ok(())
+    }
+
+    fn shaders() -> (Vec<u32>, Vec<u32>) {
+        (
+            load_shader("post.vert", include_bytes!("../shaders/post.vert.spv")),
+            load_shader("skybox.frag", include_bytes!("../shaders/skybox.frag.spv")),
+        )
+    }
+
+    /// The skybox pipeline for `render_pass`, which may differ from the scene pass in its
+    /// color format. The caller owns it; see `swap_pipeline`.
+    pub unsafe fn build_pipeline(
+        &self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+    ) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+        let (vert_code, frag_code) = Self::shaders();
+        GraphicsPipelineBuilder::new(self.layout, render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+            .depth(DepthMode::Disabled)
+            .build(device)
+    }
+
+    /// Draw with `pipeline`, built for `render_pass` by `build_pipeline`, from now on.
+    /// Returns the pipeline it replaces.
+    pub fn swap_pipeline(&mut self, render_pass: vk::RenderPass, pipeline: vk::Pipeline) -> vk::Pipeline {
+        self.render_pass = render_pass;
+        std::mem::replace(&mut self.pipeline, pipeline)
+    }
+
+    /// Fill a view of `extent` seen through `view_proj` with the environment. Draw before
+    /// anything else in the pass.
+    pub fn draw(&self, pass: &mut RenderPassEncoder, extent: vk::Extent2D, view_proj: &Mat4) {
+        pass.bind_pipeline(PipelineBinding {
+            pipeline: self.pipeline,
+            layout: self.layout,
+            render_pass: self.render_pass,
+            push_constant_size: std::mem::size_of::<SkyboxPushConstants>() as u32,
+        });
+        pass.set_full_viewport(extent);
+        pass.bind_descriptor_set(0, self.descriptor_set);
+        let pc = SkyboxPushConstants { inv_view_proj: view_proj.inverse().to_cols_array_2d() };
+        pass.push_constants(vk::ShaderStageFlags::FRAGMENT, 0, &pc);
+        pass.draw(3, 1);
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.layout, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+        self.texture.destroy(renderer);
+    }
+}
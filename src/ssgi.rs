@@ -0,0 +1,591 @@
+//! Screen-space global illumination
+//!
+//! One bounce of diffuse light from what is on screen: brightly lit surfaces tint the
+//! surfaces next to them. After the scene pass the frame is copied down to half resolution
+//! and each half-resolution pixel traces a few short rays through the scene depth (normals
+//! come from the depth too, the forward renderer keeps no G-buffer), picking up the color
+//! of whatever the rays hit. The noisy result is accumulated over frames, reprojected with
+//! the camera's motion, then blurred with depth-aware weights.
+//!
+//! The scene pass of the next frame adds the result to its ambient light, looking it up
+//! through last frame's view-projection (`prevViewProj`), so the light lags a frame behind
+//! moving objects. Copying the frame needs swapchain images usable as transfer sources;
+//! where the surface doesn't offer that, nothing is traced.
+
+use ash::vk;
+use glam::Mat4;
+
+use crate::command_encoder::{CommandEncoder, PipelineBinding};
+use crate::compute::DescriptorWriter;
+use crate::gltf_renderer::GltfRenderer;
+use crate::half_res::half_extent;
+use crate::pipeline_builder::GraphicsPipelineBuilder;
+use crate::render_target::{RenderTarget, RenderTargetDesc};
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::sampler_cache::SamplerDesc;
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// How far behind a surface a ray may pass and still count as hitting it, in meters
+const THICKNESS: f32 = 0.3;
+/// Weight of the reprojected history against each new frame's trace
+const HISTORY_WEIGHT: f32 = 0.9;
+/// Relative depth difference at which a neighbor's weight in the blur has halved
+const DEPTH_TOLERANCE: f32 = 0.05;
+
+/// How much bounce light there is and how it is traced
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SsgiStyle {
+    pub enabled: bool,
+    pub strength: f32,   // Scale of the bounce light added to the ambient term
+    pub rays: u32,       // Per half-resolution pixel and frame
+    pub ray_length: f32, // In meters
+}
+
+impl Default for SsgiStyle {
+    fn default() -> Self {
+        Self { enabled: true, strength: 1.0, rays: 4, ray_length: 1.5 }
+    }
+}
+
+// Must match shaders/ssgi_trace.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TracePushConstants {
+    proj: [f32; 4],   // x = projection[0][0], y = projection[1][1], z = projection[2][2], w = projection[3][2]
+    params: [f32; 4], // x = rays, y = ray length, z = thickness, w = frame index
+}
+
+// Must match shaders/ssgi_temporal.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TemporalPushConstants {
+    reprojection: [[f32; 4]; 4], // This frame's NDC to last frame's clip space
+    params: [f32; 4],            // x = history weight, y = 1 if the history is valid
+}
+
+// Must match shaders/ssgi_filter.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FilterPushConstants {
+    depth: [f32; 4], // x = projection[3][2], y = projection[2][2], z = depth tolerance
+}
+
+/// One full-screen pass of the chain
+struct Pass {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    set_layout: vk::DescriptorSetLayout,
+    sets: Vec<vk::DescriptorSet>, // Per frame in flight
+    push_constant_size: u32,
+}
+
+impl Pass {
+    const NULL: Self = Self {
+        pipeline: vk::Pipeline::null(),
+        layout: vk::PipelineLayout::null(),
+        set_layout: vk::DescriptorSetLayout::null(),
+        sets: Vec::new(),
+        push_constant_size: 0,
+    };
+
+    unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.layout, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
+
+/// The half-resolution images, all left ready to sample between frames
+struct Targets {
+    color: RenderTarget,        // The frame, copied down
+    raw: RenderTarget,          // This frame's trace
+    history: [RenderTarget; 2], // Accumulated light, written alternately
+    resolved: RenderTarget,     // Blurred, what the scene pass samples
+    framebuffers: Vec<vk::Framebuffer>, // Of raw, history[0], history[1] and resolved
+}
+
+impl Targets {
+    unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        for framebuffer in self.framebuffers.drain(..) {
+            renderer.device.destroy_framebuffer(framebuffer, None);
+        }
+        let [history_a, history_b] = &mut self.history;
+        for target in [&mut self.color, &mut self.raw, history_a, history_b, &mut self.resolved] {
+            target.destroy(renderer);
+        }
+    }
+}
+
+pub struct Ssgi {
+    pub style: SsgiStyle,
+    render_pass: vk::RenderPass,
+    trace: Pass,
+    temporal: Pass,
+    filter: Pass,
+    descriptor_pool: vk::DescriptorPool,
+    nearest: vk::Sampler,
+    linear: vk::Sampler,
+    targets: Option<Targets>,
+    history_index: usize,         // The history written last
+    prev_view_proj: Option<Mat4>, // Of the last traced frame; None when there is no history
+    frame: u32,
+}
+
+impl Ssgi {
+    /// Create the passes and targets at half the window's size and point the main view's
+    /// descriptor sets at the result. No frame in flight may be using them.
+    pub unsafe fn new(renderer: &VulkanRenderer, gltf_renderer: &GltfRenderer) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut ssgi = Self {
+            style: SsgiStyle::default(),
+            render_pass: vk::RenderPass::null(),
+            trace: Pass::NULL,
+            temporal: Pass::NULL,
+            filter: Pass::NULL,
+            descriptor_pool: vk::DescriptorPool::null(),
+            nearest: vk::Sampler::null(),
+            linear: vk::Sampler::null(),
+            targets: None,
+            history_index: 0,
+            prev_view_proj: None,
+            frame: 0,
+        };
+        if let Err(e) = ssgi.create(renderer).and_then(|()| ssgi.resize(renderer, gltf_renderer)) {
+            ssgi.destroy(renderer, gltf_renderer);
+            return Err(e);
+        }
+        Ok(ssgi)
+    }
+
+    unsafe fn create(&mut self, renderer: &VulkanRenderer) -> Result<(), Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        self.render_pass = create_render_pass(device)?;
+        self.nearest = renderer.sampler(SamplerDesc::nearest(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        self.linear = renderer.sampler(SamplerDesc::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+
+        let vert_code = load_shader("post.vert", include_bytes!("../shaders/post.vert.spv"));
+        let vert_reflection = ShaderReflection::reflect(&vert_code)?;
+        let create_pass = |name: &str, spirv: &[u8], push_constant_size: usize| -> Result<Pass, Box<dyn std::error::Error>> {
+            let frag_code = load_shader(name, spirv);
+            let reflections = [&vert_reflection, &ShaderReflection::reflect(&frag_code)?];
+            let bindings = shader_reflection::set_layout_bindings(&reflections, 0)?;
+            let mut pass = Pass { push_constant_size: push_constant_size as u32, ..Pass::NULL };
+            pass.set_layout =
+                device.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings), None)?;
+            let push_constant_ranges: Vec<_> =
+                shader_reflection::push_constant_range(&reflections, pass.push_constant_size)?.into_iter().collect();
+            let layout = device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(std::slice::from_ref(&pass.set_layout))
+                    .push_constant_ranges(&push_constant_ranges),
+                None,
+            );
+            match layout {
+                Ok(layout) => pass.layout = layout,
+                Err(e) => {
+                    pass.destroy(device);
+                    return Err(e.into());
+                }
+            }
+            let pipeline = GraphicsPipelineBuilder::new(pass.layout, self.render_pass)
+                .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+                .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+                .build(device);
+            match pipeline {
+                Ok(pipeline) => pass.pipeline = pipeline,
+                Err(e) => {
+                    pass.destroy(device);
+                    return Err(e);
+                }
+            }
+            Ok(pass)
+        };
+        self.trace = create_pass(
+            "ssgi_trace.frag",
+            include_bytes!("../shaders/ssgi_trace.frag.spv"),
+            std::mem::size_of::<TracePushConstants>(),
+        )?;
+        self.temporal = create_pass(
+            "ssgi_temporal.frag",
+            include_bytes!("../shaders/ssgi_temporal.frag.spv"),
+            std::mem::size_of::<TemporalPushConstants>(),
+        )?;
+        self.filter = create_pass(
+            "ssgi_filter.frag",
+            include_bytes!("../shaders/ssgi_filter.frag.spv"),
+            std::mem::size_of::<FilterPushConstants>(),
+        )?;
+
+        // Trace: depth and color; temporal: trace, history and depth; filter: history and depth
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 7 * MAX_FRAMES_IN_FLIGHT as u32,
+        };
+        self.descriptor_pool = device.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::default()
+                .pool_sizes(std::slice::from_ref(&pool_size))
+                .max_sets(3 * MAX_FRAMES_IN_FLIGHT as u32),
+            None,
+        )?;
+        for pass in [&mut self.trace, &mut self.temporal, &mut self.filter] {
+            let layouts = vec![pass.set_layout; MAX_FRAMES_IN_FLIGHT];
+            pass.sets = device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(self.descriptor_pool)
+                    .set_layouts(&layouts),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Half the size of the targets; that of the window they were made for
+    pub fn extent(&self) -> Option<vk::Extent2D> {
+        self.targets.as_ref().map(|targets| targets.resolved.extent)
+    }
+
+    /// Remake the targets at half the window's size, if they aren't already, and point the
+    /// main view's descriptor sets at the new result. The device must be idle.
+    pub unsafe fn resize(&mut self, renderer: &VulkanRenderer, gltf_renderer: &GltfRenderer) -> Result<(), Box<dyn std::error::Error>> {
+        let extent = half_extent(renderer.swapchain_extent);
+        if self.extent() == Some(extent) {
+            return Ok(());
+        }
+        gltf_renderer.set_ssgi_source(&renderer.device, None);
+        if let Some(mut targets) = self.targets.take() {
+            targets.destroy(renderer);
+        }
+        self.prev_view_proj = None;
+
+        let targets = self.create_targets(renderer, extent)?;
+        gltf_renderer.set_ssgi_source(
+            &renderer.device,
+            Some(vk::DescriptorImageInfo {
+                sampler: self.linear,
+                image_view: targets.resolved.color().view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }),
+        );
+        self.targets = Some(targets);
+        Ok(())
+    }
+
+    unsafe fn create_targets(&self, renderer: &VulkanRenderer, extent: vk::Extent2D) -> Result<Targets, Box<dyn std::error::Error>> {
+        let attachment = RenderTargetDesc::color(FORMAT, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED);
+        let copy = RenderTargetDesc::color(FORMAT, vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED);
+        let mut made = Vec::new();
+        let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+            made.push(RenderTarget::new(renderer, "SSGI Color", copy, extent)?);
+            for name in ["SSGI Trace", "SSGI History A", "SSGI History B", "SSGI Resolved"] {
+                made.push(RenderTarget::new(renderer, name, attachment, extent)?);
+            }
+            Ok(())
+        })();
+        if let Err(e) = result {
+            for mut target in made {
+                target.destroy(renderer);
+            }
+            return Err(e);
+        }
+        let [color, raw, history_a, history_b, resolved] = <[RenderTarget; 5]>::try_from(made).ok().expect("five targets");
+        let mut targets = Targets { color, raw, history: [history_a, history_b], resolved, framebuffers: Vec::new() };
+
+        let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+            for target in [&targets.raw, &targets.history[0], &targets.history[1], &targets.resolved] {
+                let framebuffer_info = vk::FramebufferCreateInfo::default()
+                    .render_pass(self.render_pass)
+                    .attachments(std::slice::from_ref(&target.color().view))
+                    .width(extent.width)
+                    .height(extent.height)
+                    .layers(1);
+                let framebuffer = renderer.device.create_framebuffer(&framebuffer_info, None)?;
+                targets.framebuffers.push(framebuffer);
+            }
+
+            // The scene pass samples the result, and the passes their inputs, before anything
+            // was written to them
+            let images: Vec<_> = [&targets.color, &targets.raw, &targets.history[0], &targets.history[1], &targets.resolved]
+                .iter()
+                .map(|target| target.color().image)
+                .collect();
+            crate::lightmap::submit_once(renderer, |device, cmd| {
+                let barriers: Vec<_> = images
+                    .iter()
+                    .map(|&image| {
+                        color_barrier(
+                            image,
+                            (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty()),
+                            (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ),
+                        )
+                    })
+                    .collect();
+                device.cmd_pipeline_barrier(
+                    cmd,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &barriers,
+                );
+            })
+        })();
+        if let Err(e) = result {
+            targets.destroy(renderer);
+            return Err(e);
+        }
+        Ok(targets)
+    }
+
+    /// Uniforms of the main view: x = 1 when the last frame traced bounce light, y = strength
+    pub fn uniforms(&self) -> [f32; 4] {
+        match self.prev_view_proj {
+            Some(_) if self.style.enabled => [1.0, self.style.strength, 0.0, 0.0],
+            _ => [0.0; 4],
+        }
+    }
+
+    /// Trace, accumulate and filter the bounce light of the frame `gltf_renderer`'s scene
+    /// pass just drew into swapchain image `image_index`, as seen through `view_proj` with
+    /// projection `proj`, for the next frame's scene pass. Record right after the scene pass.
+    pub unsafe fn record(
+        &mut self,
+        renderer: &VulkanRenderer,
+        gltf_renderer: &GltfRenderer,
+        image_index: u32,
+        view_proj: Mat4,
+        proj: Mat4,
+    ) {
+        let traceable = renderer.swapchain_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC);
+        let Some(targets) = self.targets.as_ref().filter(|_| self.style.enabled && traceable) else {
+            self.prev_view_proj = None;
+            return;
+        };
+        let device = &renderer.device;
+        let frame = renderer.current_frame;
+        let command_buffer = renderer.command_buffers[frame];
+        let extent = targets.resolved.extent;
+        let depth = gltf_renderer.depth_targets[image_index as usize].depth();
+        let swapchain_image = renderer.swapchain_images[image_index as usize];
+        let written = 1 - self.history_index;
+
+        DescriptorWriter::new()
+            .sampled_image(0, depth.view, self.nearest)
+            .sampled_image(1, targets.color.color().view, self.linear)
+            .write(device, self.trace.sets[frame]);
+        DescriptorWriter::new()
+            .sampled_image(0, targets.raw.color().view, self.nearest)
+            .sampled_image(1, targets.history[self.history_index].color().view, self.linear)
+            .sampled_image(2, depth.view, self.nearest)
+            .write(device, self.temporal.sets[frame]);
+        DescriptorWriter::new()
+            .sampled_image(0, targets.history[written].color().view, self.nearest)
+            .sampled_image(1, depth.view, self.nearest)
+            .write(device, self.filter.sets[frame]);
+
+        // Copy the frame down. The scene pass left its color ready to present and its depth
+        // ready to sample, after its writes.
+        let to_copy = [
+            color_barrier(
+                swapchain_image,
+                (vk::ImageLayout::PRESENT_SRC_KHR, vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+                (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::AccessFlags::TRANSFER_READ),
+            ),
+            color_barrier(
+                targets.color.color().image,
+                (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ),
+                (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE),
+            ),
+        ];
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_copy,
+        );
+        let full = renderer.swapchain_extent;
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let blit = vk::ImageBlit {
+            src_subresource: subresource,
+            src_offsets: [vk::Offset3D::default(), vk::Offset3D { x: full.width as i32, y: full.height as i32, z: 1 }],
+            dst_subresource: subresource,
+            dst_offsets: [vk::Offset3D::default(), vk::Offset3D { x: extent.width as i32, y: extent.height as i32, z: 1 }],
+        };
+        device.cmd_blit_image(
+            command_buffer,
+            swapchain_image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            targets.color.color().image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            std::slice::from_ref(&blit),
+            vk::Filter::LINEAR,
+        );
+        let mut depth_barrier = color_barrier(
+            depth.image,
+            (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE),
+            (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ),
+        );
+        depth_barrier.subresource_range.aspect_mask = vk::ImageAspectFlags::DEPTH;
+        let copied = [
+            color_barrier(
+                swapchain_image,
+                (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::AccessFlags::TRANSFER_READ),
+                (
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                    vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                ),
+            ),
+            color_barrier(
+                targets.color.color().image,
+                (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE),
+                (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ),
+            ),
+            depth_barrier,
+        ];
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &copied,
+        );
+
+        let trace = TracePushConstants {
+            proj: [proj.x_axis.x, proj.y_axis.y, proj.z_axis.z, proj.w_axis.z],
+            params: [
+                self.style.rays.max(1) as f32,
+                self.style.ray_length,
+                THICKNESS,
+                (self.frame % 64) as f32,
+            ],
+        };
+        let temporal = TemporalPushConstants {
+            reprojection: (self.prev_view_proj.unwrap_or(view_proj) * view_proj.inverse()).to_cols_array_2d(),
+            params: [HISTORY_WEIGHT, if self.prev_view_proj.is_some() { 1.0 } else { 0.0 }, 0.0, 0.0],
+        };
+        let filter = FilterPushConstants { depth: [proj.w_axis.z, proj.z_axis.z, DEPTH_TOLERANCE, 0.0] };
+
+        // Framebuffers: raw, history[0], history[1], resolved
+        let mut encoder = CommandEncoder::new(device, command_buffer);
+        self.draw(&mut encoder, &self.trace, targets.framebuffers[0], extent, frame, &trace);
+        self.draw(&mut encoder, &self.temporal, targets.framebuffers[1 + written], extent, frame, &temporal);
+        self.draw(&mut encoder, &self.filter, targets.framebuffers[3], extent, frame, &filter);
+
+        self.history_index = written;
+        self.prev_view_proj = Some(view_proj);
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    /// Run one pass of the chain over `framebuffer`
+    fn draw<T: Copy>(
+        &self,
+        encoder: &mut CommandEncoder,
+        pass: &Pass,
+        framebuffer: vk::Framebuffer,
+        extent: vk::Extent2D,
+        frame: usize,
+        push_constants: &T,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(self.render_pass, framebuffer, extent, &[]);
+        render_pass.bind_pipeline(PipelineBinding {
+            pipeline: pass.pipeline,
+            layout: pass.layout,
+            render_pass: self.render_pass,
+            push_constant_size: pass.push_constant_size,
+        });
+        render_pass.bind_descriptor_set(0, pass.sets[frame]);
+        render_pass.set_full_viewport(extent);
+        render_pass.push_constants(vk::ShaderStageFlags::FRAGMENT, 0, push_constants);
+        render_pass.draw(3, 1);
+    }
+
+    /// Destroy everything and point the main view back at the placeholder. The device must
+    /// be idle.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer, gltf_renderer: &GltfRenderer) {
+        let device = &renderer.device;
+        gltf_renderer.set_ssgi_source(device, None);
+        if let Some(mut targets) = self.targets.take() {
+            targets.destroy(renderer);
+        }
+        for pass in [&self.trace, &self.temporal, &self.filter] {
+            pass.destroy(device);
+        }
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_render_pass(self.render_pass, None);
+    }
+}
+
+/// A color image's layout transition; callers pick the stages
+fn color_barrier(
+    image: vk::Image,
+    (old_layout, src_access): (vk::ImageLayout, vk::AccessFlags),
+    (new_layout, dst_access): (vk::ImageLayout, vk::AccessFlags),
+) -> vk::ImageMemoryBarrier<'static> {
+    vk::ImageMemoryBarrier::default()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+}
+
+unsafe fn create_render_pass(device: &ash::Device) -> Result<vk::RenderPass, vk::Result> {
+    // Every pass writes every pixel, so nothing is loaded
+    let attachment = vk::AttachmentDescription::default()
+        .format(FORMAT)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    let color_ref = vk::AttachmentReference { attachment: 0, layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL };
+    let subpass = vk::SubpassDescription::default()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(std::slice::from_ref(&color_ref));
+
+    let dependencies = [
+        // Earlier passes and scene passes may still be sampling the image
+        vk::SubpassDependency::default()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+        // The next pass, and the next frame's scene pass, sample it
+        vk::SubpassDependency::default()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ),
+    ];
+
+    let render_pass_info = vk::RenderPassCreateInfo::default()
+        .attachments(std::slice::from_ref(&attachment))
+        .subpasses(std::slice::from_ref(&subpass))
+        .dependencies(&dependencies);
+    device.create_render_pass(&render_pass_info, None)
+}
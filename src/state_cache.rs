@@ -0,0 +1,160 @@
+//! Redundant state-change elimination
+//!
+//! A `StateCache` sits between a recording function and `vkCmdBind*` / `vkCmdSet*`: it
+//! remembers the graphics pipeline, descriptor sets, vertex buffer, index buffer, viewport
+//! and scissor last recorded into the command buffer and skips binds that would change
+//! nothing. Binds that are recorded are counted in the draw statistics.
+//!
+//! A cache only knows about what went through it: start a fresh one wherever other code
+//! may have recorded into the command buffer since.
+
+use ash::vk;
+use crate::draw_stats;
+
+/// Descriptor set numbers tracked; higher ones are always bound
+const MAX_SETS: usize = 4;
+
+#[derive(Default)]
+pub struct StateCache {
+    pipeline: Option<vk::Pipeline>,
+    descriptor_sets: [Option<(vk::PipelineLayout, vk::DescriptorSet)>; MAX_SETS],
+    vertex_buffers: Vec<(vk::Buffer, vk::DeviceSize)>, // From binding 0; empty when unknown
+    index_buffer: Option<(vk::Buffer, vk::DeviceSize, vk::IndexType)>,
+    viewport: Option<[f32; 6]>,
+    scissor: Option<vk::Rect2D>,
+}
+
+impl StateCache {
+    /// # Safety
+    ///
+    /// `command_buffer` must be recording and all binds into it must go through this cache.
+    pub unsafe fn bind_pipeline(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, pipeline: vk::Pipeline) {
+        if self.pipeline == Some(pipeline) {
+            return;
+        }
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        draw_stats::state_changes(1);
+        self.pipeline = Some(pipeline);
+    }
+
+    /// Bind `descriptor_set` as set number `set` of `layout`
+    ///
+    /// # Safety
+    ///
+    /// As for `bind_pipeline`; `descriptor_set` must be compatible with `layout`.
+    pub unsafe fn bind_descriptor_set(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::PipelineLayout,
+        set: u32,
+        descriptor_set: vk::DescriptorSet,
+    ) {
+        let index = set as usize;
+        if index < MAX_SETS && self.descriptor_sets[index] == Some((layout, descriptor_set)) {
+            return;
+        }
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            layout,
+            set,
+            &[descriptor_set],
+            &[],
+        );
+        draw_stats::state_changes(1);
+        if index < MAX_SETS {
+            // Sets bound with another layout may be disturbed by this one
+            for bound in &mut self.descriptor_sets {
+                if bound.is_some_and(|(bound_layout, _)| bound_layout != layout) {
+                    *bound = None;
+                }
+            }
+            self.descriptor_sets[index] = Some((layout, descriptor_set));
+        }
+    }
+
+    /// Forget the bound descriptor sets, after binding descriptor buffers disturbed them
+    pub fn forget_descriptor_sets(&mut self) {
+        self.descriptor_sets = Default::default();
+    }
+
+    /// Forget what is bound as set number `set`, after descriptors were pushed to it
+    pub fn forget_descriptor_set(&mut self, set: u32) {
+        if let Some(bound) = self.descriptor_sets.get_mut(set as usize) {
+            *bound = None;
+        }
+    }
+
+    /// Bind `buffers` at `offsets` to the vertex bindings starting at 0
+    ///
+    /// # Safety
+    ///
+    /// As for `bind_pipeline`; `buffers` and `offsets` must have the same length.
+    pub unsafe fn bind_vertex_buffers(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        buffers: &[vk::Buffer],
+        offsets: &[vk::DeviceSize],
+    ) {
+        let bound = self.vertex_buffers.len() == buffers.len()
+            && self.vertex_buffers.iter().zip(buffers.iter().zip(offsets)).all(|(&a, (&b, &o))| a == (b, o));
+        if bound {
+            return;
+        }
+        device.cmd_bind_vertex_buffers(command_buffer, 0, buffers, offsets);
+        draw_stats::state_changes(1);
+        self.vertex_buffers.clear();
+        self.vertex_buffers.extend(buffers.iter().copied().zip(offsets.iter().copied()));
+    }
+
+    /// # Safety
+    ///
+    /// As for `bind_pipeline`.
+    pub unsafe fn bind_index_buffer(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        index_type: vk::IndexType,
+    ) {
+        if self.index_buffer == Some((buffer, offset, index_type)) {
+            return;
+        }
+        device.cmd_bind_index_buffer(command_buffer, buffer, offset, index_type);
+        draw_stats::state_changes(1);
+        self.index_buffer = Some((buffer, offset, index_type));
+    }
+
+    /// # Safety
+    ///
+    /// As for `bind_pipeline`; the bound pipeline must have a dynamic viewport.
+    pub unsafe fn set_viewport(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, viewport: vk::Viewport) {
+        let key = [
+            viewport.x,
+            viewport.y,
+            viewport.width,
+            viewport.height,
+            viewport.min_depth,
+            viewport.max_depth,
+        ];
+        if self.viewport == Some(key) {
+            return;
+        }
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        self.viewport = Some(key);
+    }
+
+    /// # Safety
+    ///
+    /// As for `bind_pipeline`; the bound pipeline must have a dynamic scissor.
+    pub unsafe fn set_scissor(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, scissor: vk::Rect2D) {
+        if self.scissor == Some(scissor) {
+            return;
+        }
+        device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+        self.scissor = Some(scissor);
+    }
+}
@@ -0,0 +1,145 @@
+//! Stress-test scene
+//!
+//! `--stress N` (or "Run stress test" in the debug UI) replaces the cubes with N spinning
+//! cubes in a square grid around the model, each with its own rotation, size and spin, and
+//! turns on the fill light next to the sun. After a second of warm-up, frame times are
+//! recorded for five seconds and a report with the scene's draw calls, triangles and frame
+//! time percentiles is printed and shown in the UI, so runs before and after a change can
+//! be compared.
+//!
+//! Only cubes are multiplied: the glTF renderer draws a single model instance.
+
+use glam::{Quat, Vec3};
+use std::time::{Duration, Instant};
+
+const WARMUP: Duration = Duration::from_secs(1);
+const MEASURE: Duration = Duration::from_secs(5);
+const SPACING: f32 = 1.5;
+
+/// Where and how one stress object sits and moves
+pub struct StressObject {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub scale: f32,
+    pub spin: Vec3, // Angular velocity, radians per second
+}
+
+/// `count` objects on a square grid centered on the origin, skipping the middle where the
+/// model stands. Variation comes from a hash of the index, so runs are reproducible.
+pub fn grid(count: u32) -> Vec<StressObject> {
+    let side = (count as f32).sqrt().ceil() as i32;
+    let half = side / 2;
+    let clear = 2; // Cells kept free around the model
+    let mut objects = Vec::with_capacity(count as usize);
+    let mut cell = 0i32;
+    while objects.len() < count as usize {
+        // Rows are a little wider than `side` to make up for the skipped center
+        let width = side + 2 * clear;
+        let (x, z) = (cell % width - half - clear, cell / width - half - clear);
+        cell += 1;
+        if x.abs() < clear && z.abs() < clear {
+            continue;
+        }
+        let i = objects.len() as u32;
+        let (a, b, c) = (hash(i, 1), hash(i, 2), hash(i, 3));
+        objects.push(StressObject {
+            position: Vec3::new(x as f32 * SPACING, 0.3 + b * 0.6, z as f32 * SPACING),
+            rotation: Quat::from_euler(glam::EulerRot::YXZ, a * std::f32::consts::TAU, b * 0.8, c * 0.8),
+            scale: 0.3 + c * 0.5,
+            spin: Vec3::new(b - 0.5, 0.5 + a * 1.5, c - 0.5),
+        });
+    }
+    objects
+}
+
+/// Pseudo-random value in 0..1 for `index` and `salt`
+pub fn hash(index: u32, salt: u32) -> f32 {
+    let mut x = index.wrapping_mul(0x9E37_79B9) ^ salt.wrapping_mul(0x85EB_CA6B);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7FEB_352D);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846C_A68B);
+    x ^= x >> 16;
+    (x >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// What the scene submits per frame, counted when the run starts
+#[derive(Clone, Copy, Default)]
+pub struct SceneLoad {
+    pub objects: u32,
+    pub draw_calls: u32, // Main pass; shadow cascades draw the glTF meshes again
+    pub triangles: u64,
+}
+
+/// A run in progress
+pub struct StressRun {
+    pub load: SceneLoad,
+    started: Instant,
+    frame_times_ms: Vec<f32>,
+}
+
+impl StressRun {
+    pub fn new(load: SceneLoad) -> Self {
+        println!(
+            "🔥 Stress test: {} objects, {} draw calls, {} triangles per frame",
+            load.objects, load.draw_calls, load.triangles
+        );
+        Self { load, started: Instant::now(), frame_times_ms: Vec::new() }
+    }
+
+    /// Record a frame; returns the report once the measurement window has passed
+    pub fn record(&mut self, frame_time_ms: f32) -> Option<StressReport> {
+        let elapsed = self.started.elapsed();
+        if elapsed < WARMUP {
+            return None;
+        }
+        self.frame_times_ms.push(frame_time_ms);
+        if elapsed < WARMUP + MEASURE || self.frame_times_ms.is_empty() {
+            return None;
+        }
+
+        let mut sorted = self.frame_times_ms.clone();
+        sorted.sort_by(f32::total_cmp);
+        let percentile = |p: f32| sorted[((sorted.len() - 1) as f32 * p).round() as usize];
+        let report = StressReport {
+            load: self.load,
+            frames: sorted.len(),
+            average_ms: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            p50_ms: percentile(0.5),
+            p95_ms: percentile(0.95),
+            p99_ms: percentile(0.99),
+            max_ms: sorted[sorted.len() - 1],
+        };
+        println!("📊 {}", report.summary());
+        Some(report)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct StressReport {
+    pub load: SceneLoad,
+    pub frames: usize,
+    pub average_ms: f32,
+    pub p50_ms: f32,
+    pub p95_ms: f32,
+    pub p99_ms: f32,
+    pub max_ms: f32,
+}
+
+impl StressReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} objects, {} draw calls, {} triangles: {:.2} ms average ({:.0} FPS), p50 {:.2} / p95 {:.2} / p99 {:.2} / max {:.2} ms over {} frames",
+            self.load.objects,
+            self.load.draw_calls,
+            self.load.triangles,
+            self.average_ms,
+            1000.0 / self.average_ms.max(0.001),
+            self.p50_ms,
+            self.p95_ms,
+            self.p99_ms,
+            self.max_ms,
+            self.frames
+        )
+    }
+}
@@ -0,0 +1,199 @@
+//! Surface capability negotiation
+//!
+//! The main and secondary swapchains are created from what the surface reports instead of
+//! assumed values: a supported format and color space, an extent clamped to the surface
+//! limits (or taken from the window when the surface leaves it to the swapchain, as on
+//! Wayland), a present mode the surface offers, and a supported composite alpha.
+
+use ash::vk;
+
+/// Whether `format` encodes to sRGB on write (and decodes on read), so shaders work in
+/// linear values with it
+pub fn is_srgb(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32
+    )
+}
+
+/// How frames are presented, in the order of the modes tried for it. FIFO is the fallback
+/// of every choice, as the only mode every surface supports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PresentModeConfig {
+    Fifo, // VSync: a frame waits for the display's next refresh
+    Mailbox, // VSync without waiting: the newest frame replaces a queued one
+    #[default]
+    Immediate, // No VSync, for the most frames per second, with tearing
+}
+
+impl PresentModeConfig {
+    pub const ALL: [PresentModeConfig; 3] =
+        [PresentModeConfig::Fifo, PresentModeConfig::Mailbox, PresentModeConfig::Immediate];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PresentModeConfig::Fifo => "VSync",
+            PresentModeConfig::Mailbox => "Mailbox",
+            PresentModeConfig::Immediate => "Uncapped",
+        }
+    }
+
+    /// `fifo`, `mailbox` or `immediate`, as given on the command line
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "fifo" | "vsync" => Some(PresentModeConfig::Fifo),
+            "mailbox" => Some(PresentModeConfig::Mailbox),
+            "immediate" => Some(PresentModeConfig::Immediate),
+            _ => None,
+        }
+    }
+
+    /// Modes to try, best first, before falling back to FIFO
+    pub fn preferred_modes(self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            PresentModeConfig::Fifo => &[vk::PresentModeKHR::FIFO],
+            // Without mailbox, still avoid tearing
+            PresentModeConfig::Mailbox => &[vk::PresentModeKHR::MAILBOX],
+            // Without immediate, mailbox is the next fastest
+            PresentModeConfig::Immediate => &[vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX],
+        }
+    }
+}
+
+pub struct SurfaceSupport {
+    pub capabilities: vk::SurfaceCapabilitiesKHR,
+    pub formats: Vec<vk::SurfaceFormatKHR>,
+    pub present_modes: Vec<vk::PresentModeKHR>,
+}
+
+impl SurfaceSupport {
+    /// # Safety
+    ///
+    /// `surface` must have been created from the instance `surface_fn` was loaded for.
+    pub unsafe fn query(
+        surface_fn: &ash::khr::surface::Instance,
+        physical_device: vk::PhysicalDevice,
+        surface: vk::SurfaceKHR,
+    ) -> Result<Self, vk::Result> {
+        Ok(Self {
+            capabilities: surface_fn.get_physical_device_surface_capabilities(physical_device, surface)?,
+            formats: surface_fn.get_physical_device_surface_formats(physical_device, surface)?,
+            present_modes: surface_fn.get_physical_device_surface_present_modes(physical_device, surface)?,
+        })
+    }
+
+    /// `required` if the surface supports it (with its color space), otherwise `None`.
+    /// Without a requirement, the surface's first format. A lone `UNDEFINED` entry means
+    /// the surface takes any format.
+    pub fn choose_format(&self, required: Option<vk::Format>) -> Option<vk::SurfaceFormatKHR> {
+        if let [only] = self.formats.as_slice() {
+            if only.format == vk::Format::UNDEFINED {
+                return Some(vk::SurfaceFormatKHR {
+                    format: required.unwrap_or(vk::Format::B8G8R8A8_UNORM),
+                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                });
+            }
+        }
+        match required {
+            Some(format) => self
+                .formats
+                .iter()
+                .filter(|f| f.format == format)
+                // The same format can be listed once per color space; prefer plain sRGB
+                .min_by_key(|f| f.color_space != vk::ColorSpaceKHR::SRGB_NONLINEAR)
+                .copied(),
+            None => self.formats.first().copied(),
+        }
+    }
+
+    /// First of `preferred` the surface supports, else FIFO (always available)
+    pub fn choose_present_mode(&self, preferred: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+        preferred
+            .iter()
+            .copied()
+            .find(|mode| self.present_modes.contains(mode))
+            .unwrap_or(vk::PresentModeKHR::FIFO)
+    }
+
+    /// Swapchain extent for a window of `width` x `height`, or `None` while the surface has
+    /// no area (minimized) and no swapchain can be created.
+    pub fn extent(&self, width: u32, height: u32) -> Option<vk::Extent2D> {
+        let caps = &self.capabilities;
+        let extent = if caps.current_extent.width != u32::MAX {
+            caps.current_extent
+        } else {
+            vk::Extent2D {
+                width: width.clamp(caps.min_image_extent.width, caps.max_image_extent.width),
+                height: height.clamp(caps.min_image_extent.height, caps.max_image_extent.height),
+            }
+        };
+        (extent.width > 0 && extent.height > 0).then_some(extent)
+    }
+
+    /// `requested` images clamped to what the surface allows. By default one more image
+    /// than the minimum, so the CPU doesn't wait on the presentation engine.
+    pub fn image_count(&self, requested: Option<u32>) -> u32 {
+        let max_images = if self.capabilities.max_image_count == 0 {
+            u32::MAX
+        } else {
+            self.capabilities.max_image_count
+        };
+        requested
+            .unwrap_or(self.capabilities.min_image_count + 1)
+            .clamp(self.capabilities.min_image_count, max_images)
+    }
+
+    /// Opaque if supported, otherwise whatever mode the compositor accepts
+    pub fn composite_alpha(&self) -> vk::CompositeAlphaFlagsKHR {
+        let supported = self.capabilities.supported_composite_alpha;
+        [
+            vk::CompositeAlphaFlagsKHR::OPAQUE,
+            vk::CompositeAlphaFlagsKHR::INHERIT,
+            vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+            vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        ]
+        .into_iter()
+        .find(|&mode| supported.contains(mode))
+        .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE)
+    }
+
+    /// Color attachment, plus transfer source where the surface allows it so passes can copy
+    /// the frame (see `ssgi`)
+    pub fn image_usage(&self) -> vk::ImageUsageFlags {
+        let optional = vk::ImageUsageFlags::TRANSFER_SRC & self.capabilities.supported_usage_flags;
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | optional
+    }
+
+    /// Create info for a color-attachment swapchain with the negotiated settings and the
+    /// default `image_count`. Images are shared concurrently if `queue_family_indices` names
+    /// more than one family, so a separate present queue needs no ownership transfers.
+    pub fn swapchain_create_info<'a>(
+        &self,
+        surface: vk::SurfaceKHR,
+        format: vk::SurfaceFormatKHR,
+        extent: vk::Extent2D,
+        present_mode: vk::PresentModeKHR,
+        queue_family_indices: &'a [u32; 2],
+        old_swapchain: vk::SwapchainKHR,
+    ) -> vk::SwapchainCreateInfoKHR<'a> {
+        let create_info = vk::SwapchainCreateInfoKHR::default()
+            .surface(surface)
+            .min_image_count(self.image_count(None))
+            .image_format(format.format)
+            .image_color_space(format.color_space)
+            .image_extent(extent)
+            .image_array_layers(1)
+            .image_usage(self.image_usage())
+            .pre_transform(self.capabilities.current_transform)
+            .composite_alpha(self.composite_alpha())
+            .present_mode(present_mode)
+            .old_swapchain(old_swapchain);
+        if queue_family_indices[0] != queue_family_indices[1] {
+            create_info
+                .image_sharing_mode(vk::SharingMode::CONCURRENT)
+                .queue_family_indices(queue_family_indices)
+        } else {
+            create_info.image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! Headless rendering tests
+//!
+//! `cargo test` renders a few reference scenes with a windowless renderer
+//! (`VulkanRenderer::new_headless`, which picks lavapipe or SwiftShader when installed) and
+//! checks that each rendered and shows its subject against the background. No reference
+//! images are compared: they only match between runs on the same Vulkan implementation.
+//! Machines without any Vulkan device skip these tests, saying so on stderr past the test
+//! harness's output capture.
+
+use ash::vk;
+use glam::{Quat, Vec3};
+use std::sync::Mutex;
+
+use crate::cube::CubeRenderer;
+use crate::gltf_loader::GltfScene;
+use crate::gltf_renderer::{GltfRenderer, ShadowUniforms, ViewCamera};
+use crate::offscreen::{self, OffscreenTarget};
+use crate::renderer::VulkanRenderer;
+use crate::screenshot;
+
+/// Size of every reference image
+pub const EXTENT: vk::Extent2D = vk::Extent2D { width: 320, height: 240 };
+/// Largest per-channel difference from the background that still counts as background
+pub const CHANNEL_TOLERANCE: u8 = 8;
+/// Least share of pixels the subject of a reference scene covers
+pub const MIN_COVERAGE: f64 = 0.01;
+
+// One device at a time keeps memory use flat with parallel test threads
+static GPU: Mutex<()> = Mutex::new(());
+
+/// A headless renderer of `EXTENT`, or `None` (after saying why) if the machine has no
+/// usable Vulkan implementation
+pub fn headless_renderer() -> Option<VulkanRenderer> {
+    // A loader without global commands (no driver at all) would panic inside ash instead
+    // of returning an error
+    let entry = ash::Entry::linked();
+    let enumerate = unsafe {
+        entry.get_instance_proc_addr(vk::Instance::null(), c"vkEnumerateInstanceExtensionProperties".as_ptr())
+    };
+    if enumerate.is_none() {
+        report_skip("the Vulkan loader is unusable");
+        return None;
+    }
+    match unsafe { VulkanRenderer::new_headless(EXTENT) } {
+        Ok(renderer) => Some(renderer),
+        Err(e) => {
+            report_skip(&e.to_string());
+            None
+        }
+    }
+}
+
+/// Say a test was skipped where `cargo test` shows it: the harness captures `println!` and
+/// `eprintln!` of passing tests, but not writes to the stderr handle itself
+fn report_skip(reason: &str) {
+    use std::io::Write;
+    let _ = writeln!(std::io::stderr(), "⚠ skipped: no Vulkan device ({})", reason);
+}
+
+/// The reference cube: the teal cube pipeline, rotated so three faces are lit differently
+pub unsafe fn render_cube(renderer: &VulkanRenderer) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let device = &renderer.device;
+    renderer.frame_arena.begin_frame(0)?;
+    let mut cube = CubeRenderer::new(renderer)?;
+    let camera_pos = Vec3::new(0.0, 0.0, 3.0);
+    cube.update_uniform_buffer(
+        renderer,
+        0,
+        0.6,
+        Vec3::ZERO,
+        camera_pos,
+        std::f32::consts::PI, // Facing -Z, towards the cube
+        0.0,
+        45.0_f32.to_radians(),
+        1.0,
+    )?;
+
+    // The overlay render pass loads its color, so start from a cleared, presentable image
+    let image = renderer.swapchain_images[0];
+    let range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    let cmd_info = vk::CommandBufferAllocateInfo::default()
+        .command_pool(renderer.command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(2);
+    let command_buffers = device.allocate_command_buffers(&cmd_info)?;
+    let (clear_cmd, draw_cmd) = (command_buffers[0], command_buffers[1]);
+    let begin_info = vk::CommandBufferBeginInfo::default()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(clear_cmd, &begin_info)?;
+    let to_clear = vk::ImageMemoryBarrier::default()
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(range);
+    device.cmd_pipeline_barrier(
+        clear_cmd,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        std::slice::from_ref(&to_clear),
+    );
+    let clear_color = vk::ClearColorValue { float32: [0.39, 0.58, 0.93, 1.0] };
+    device.cmd_clear_color_image(
+        clear_cmd,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &clear_color,
+        std::slice::from_ref(&range),
+    );
+    let to_present = vk::ImageMemoryBarrier::default()
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(range);
+    device.cmd_pipeline_barrier(
+        clear_cmd,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        std::slice::from_ref(&to_present),
+    );
+    device.end_command_buffer(clear_cmd)?;
+
+    cube.record_commands(renderer, draw_cmd, renderer.framebuffers[0], 0)?;
+
+    let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+    device.queue_submit(renderer.graphics_queue, &[submit_info], vk::Fence::null())?;
+    device.queue_wait_idle(renderer.graphics_queue)?;
+    device.free_command_buffers(renderer.command_pool, &command_buffers);
+
+    let mut pixels = offscreen::read_image(renderer, image, EXTENT, 4, vk::ImageLayout::PRESENT_SRC_KHR)?;
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2); // HEADLESS_FORMAT is BGRA
+    }
+    cube.cleanup(renderer);
+    Ok(pixels)
+}
+
+/// Shadow settings for `render_duck`
+#[derive(Clone, Copy)]
+pub struct DuckShadows {
+    pub debug_cascades: bool,
+    pub softness: f32,
+    pub use_pcss: bool,
+}
+
+/// The reference glTF scene (`models/scene.gltf`) on its ground plane, from the app's
+/// default camera
+pub unsafe fn render_duck(
+    renderer: &VulkanRenderer,
+    shadows: DuckShadows,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let scene = GltfScene::load("models/scene.gltf", true)?;
+    let mut gltf_renderer = GltfRenderer::new(renderer, &scene)?;
+    let pipelines = gltf_renderer.scene_pipeline_builder(&renderer.device)()?;
+    gltf_renderer.install_scene_pipelines(&renderer.device, pipelines);
+
+    // Same placement and camera as a fresh start of the app
+    let scale = 0.01;
+    let position = Vec3::new(0.0, -scene.bounds_min[1] * scale + 0.001, 0.0);
+    let camera_pos = Vec3::new(0.0, 2.5, 10.0);
+    let dir = (Vec3::new(0.0, 0.6, 0.0) - camera_pos).normalize();
+    let (yaw, pitch, fov) = (dir.z.atan2(dir.x), dir.y.asin(), 45.0_f32.to_radians());
+    let aspect_ratio = EXTENT.width as f32 / EXTENT.height as f32;
+    let camera = ViewCamera::from_yaw_pitch(camera_pos, yaw, pitch, fov, aspect_ratio);
+    gltf_renderer.update_uniform_buffer(
+        renderer,
+        0,
+        GltfRenderer::model_matrix(position, Quat::IDENTITY, scale),
+        &camera,
+        ShadowUniforms {
+            debug_cascades: shadows.debug_cascades,
+            softness: shadows.softness,
+            use_pcss: shadows.use_pcss,
+            use_taa: false,
+        },
+    )?;
+
+    let mut target = OffscreenTarget::new(renderer, gltf_renderer.render_pass, EXTENT)?;
+    let mut view = gltf_renderer.create_view(renderer, EXTENT)?;
+    gltf_renderer.update_view_uniform_buffer(
+        &mut view,
+        0,
+        &camera,
+        shadows.debug_cascades,
+        shadows.softness,
+        shadows.use_pcss,
+    );
+    let result = screenshot::render_tile(renderer, &gltf_renderer, &view, &target);
+
+    renderer.device.device_wait_idle()?;
+    gltf_renderer.destroy_view(renderer, view)?;
+    target.destroy(renderer);
+    gltf_renderer.cleanup(renderer);
+    result
+}
+
+/// Share of RGBA8 `pixels` that differ from the top left one, the background in every
+/// reference scene, by more than `CHANNEL_TOLERANCE`
+pub fn coverage(pixels: &[u8]) -> f64 {
+    let background = &pixels[..4];
+    let covered = pixels
+        .chunks_exact(4)
+        .filter(|pixel| pixel.iter().zip(background).any(|(&a, &b)| a.abs_diff(b) > CHANNEL_TOLERANCE))
+        .count();
+    covered as f64 / (pixels.len() / 4) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(name: &str, render: impl FnOnce(&VulkanRenderer) -> Result<Vec<u8>, Box<dyn std::error::Error>>) {
+        let _gpu = GPU.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(renderer) = headless_renderer() else {
+            return;
+        };
+        let pixels = render(&renderer).unwrap_or_else(|e| panic!("Rendering {} failed: {}", name, e));
+        assert_eq!(pixels.len(), (EXTENT.width * EXTENT.height * 4) as usize);
+        let coverage = coverage(&pixels);
+        assert!(
+            coverage >= MIN_COVERAGE,
+            "{} covers {:.2}% of the image, expected at least {:.2}%",
+            name,
+            coverage * 100.0,
+            MIN_COVERAGE * 100.0
+        );
+    }
+
+    #[test]
+    fn cube_renders() {
+        check("cube", |renderer| unsafe { render_cube(renderer) });
+    }
+
+    #[test]
+    fn duck_renders() {
+        let shadows = DuckShadows { debug_cascades: false, softness: 1.0, use_pcss: false };
+        check("duck", |renderer| unsafe { render_duck(renderer, shadows) });
+    }
+
+    #[test]
+    fn duck_shadows_render() {
+        // Soft contact-hardening shadows with the cascades tinted, the PCSS and cascade
+        // debug paths
+        let shadows = DuckShadows { debug_cascades: true, softness: 4.0, use_pcss: true };
+        check("duck_shadows", |renderer| unsafe { render_duck(renderer, shadows) });
+    }
+}
@@ -0,0 +1,395 @@
+//! Texture arrays for material batching
+//!
+//! Without bindless descriptors every draw samples whatever texture its descriptor sets
+//! bind, so draws with different textures can't share an indirect call. Instead, the
+//! material textures are packed at load time into a few 2D array images, one per texture
+//! size, and each draw picks its layer through `firstInstance` (`gl_InstanceIndex` in
+//! gltf.vert, passed to gltf.frag as a flat varying). Draws whose materials differ only in
+//! their texture then merge into one bucket of `gpu_driven`.
+//!
+//! The first scene texture stays on binding 1, where it streams (`texture_streaming`) or
+//! pages (`virtual_texture`) in. A draw of it passes slot 0. Base color textures of kinds
+//! beyond the `MAX_TEXTURE_ARRAYS` most common ones, or past an array's `MAX_LAYERS`, get
+//! an array of their own, one layer deep, which their materials bind in set 1 (see
+//! `MaterialRegistry`); their draws pass slot 0 too and can't be merged.
+//!
+//! Emissive and occlusion textures are packed too. Their slots go in the material's uniform
+//! block rather than `firstInstance`; one left out of the arrays isn't sampled.
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+use std::collections::{HashMap, HashSet};
+
+use crate::gltf_loader::{GltfScene, GltfTexture};
+use crate::lightmap;
+use crate::renderer::VulkanRenderer;
+use crate::texture_streaming::{self, MipLevel, TextureFilter};
+use crate::virtual_texture;
+
+/// Array images bound at binding 13 of set 0, must match MAX_TEXTURE_ARRAYS in gltf.frag
+pub const MAX_TEXTURE_ARRAYS: usize = 4;
+/// Layers per array: the slot keeps the layer in its low 8 bits
+const MAX_LAYERS: usize = 256;
+
+/// Same-size, same-format textures as the layers of one image, every layer with the same
+/// mips
+struct TextureArray {
+    image: vk::Image,
+    view: vk::ImageView,
+    sampler: vk::Sampler,
+    allocation: Option<Allocation>,
+    size: (u32, u32),
+    layers: u32,
+    mip_levels: u32,
+    bytes: u64,
+}
+
+impl TextureArray {
+    /// Upload `layers` of `format`, all mip chains of the same size and length
+    unsafe fn new(
+        renderer: &VulkanRenderer,
+        format: vk::Format,
+        layers: &[&[MipLevel]],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let (width, height) = (layers[0][0].width, layers[0][0].height);
+        let mip_levels = layers[0].len() as u32;
+        let bytes: u64 = layers.iter().copied().flatten().map(MipLevel::bytes).sum();
+
+        // Staging buffer with every level of every layer, tightly packed
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(bytes)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = device.create_buffer(&buffer_info, None)?;
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let staging = match renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: "texture_array_staging",
+            requirements,
+            location: MemoryLocation::CpuToGpu,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        }) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy_buffer(buffer, None);
+                return Err(e.into());
+            }
+        };
+        device.bind_buffer_memory(buffer, staging.memory(), staging.offset())?;
+
+        let ptr = staging.mapped_ptr().unwrap().as_ptr() as *mut u8;
+        let mut regions = Vec::new();
+        let mut offset = 0;
+        for (layer, mips) in layers.iter().enumerate() {
+            for (level, mip) in mips.iter().enumerate() {
+                std::ptr::copy_nonoverlapping(mip.data.as_ptr(), ptr.add(offset as usize), mip.data.len());
+                regions.push(vk::BufferImageCopy {
+                    buffer_offset: offset,
+                    buffer_row_length: 0,
+                    buffer_image_height: 0,
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: level as u32,
+                        base_array_layer: layer as u32,
+                        layer_count: 1,
+                    },
+                    image_offset: vk::Offset3D::default(),
+                    image_extent: vk::Extent3D { width: mip.width, height: mip.height, depth: 1 },
+                });
+                offset += mip.bytes();
+            }
+        }
+
+        let image_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D { width, height, depth: 1 })
+            .mip_levels(mip_levels)
+            .array_layers(layers.len() as u32)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let image = device.create_image(&image_info, None)?;
+        let requirements = device.get_image_memory_requirements(image);
+        let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name: "texture_array",
+            requirements,
+            location: MemoryLocation::GpuOnly,
+            linear: false,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        })?;
+        device.bind_image_memory(image, allocation.memory(), allocation.offset())?;
+
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count: layers.len() as u32,
+        };
+        let copied = lightmap::submit_once(renderer, |device, cmd| {
+            let barrier = |old, new, src_access, dst_access| {
+                vk::ImageMemoryBarrier::default()
+                    .old_layout(old)
+                    .new_layout(new)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(image)
+                    .subresource_range(range)
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access)
+            };
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                )],
+            );
+            device.cmd_copy_buffer_to_image(cmd, buffer, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions);
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier(
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                )],
+            );
+        });
+        device.destroy_buffer(buffer, None);
+        renderer.allocator.lock().free(staging)?;
+
+        let mut array = Self {
+            image,
+            view: vk::ImageView::null(),
+            sampler: vk::Sampler::null(),
+            allocation: Some(allocation),
+            size: (width, height),
+            layers: layers.len() as u32,
+            mip_levels,
+            bytes,
+        };
+        if let Err(e) = copied {
+            array.destroy(renderer);
+            return Err(e);
+        }
+        let view_info = vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+            .format(format)
+            .subresource_range(range);
+        match device.create_image_view(&view_info, None) {
+            Ok(view) => array.view = view,
+            Err(e) => {
+                array.destroy(renderer);
+                return Err(e.into());
+            }
+        }
+        array.sampler = renderer.sampler(TextureFilter::default().sampler_desc(mip_levels))?;
+        Ok(array)
+    }
+
+    unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        // The sampler belongs to the renderer's sampler cache
+        renderer.device.destroy_image_view(self.view, None);
+        renderer.device.destroy_image(self.image, None);
+        if let Some(allocation) = self.allocation.take() {
+            renderer.allocator.lock().free(allocation).ok();
+        }
+    }
+}
+
+/// What the textures of one array share: size, format and the number of mips of a KTX2
+/// texture (which may have fewer than a full chain), 0 for the others
+type TextureKind = ((u32, u32), vk::Format, usize);
+
+pub struct TextureArrays {
+    arrays: Vec<TextureArray>,
+    placeholder: TextureArray, // 1x1 white, bound in place of the arrays not in use
+    slots: Vec<u32>,           // Per scene texture, 0 when it isn't packed
+    unpacked: HashMap<usize, TextureArray>, // Base color textures left out, by scene texture
+}
+
+impl TextureArrays {
+    /// Pack the base color, emissive and occlusion textures of `scene`, except the first
+    /// one and virtual ones, into arrays by size and format. The most common kinds get the
+    /// `MAX_TEXTURE_ARRAYS` arrays; base color textures of the others get one each.
+    pub unsafe fn new(renderer: &VulkanRenderer, scene: &GltfScene) -> Result<Self, Box<dyn std::error::Error>> {
+        let base_color: HashSet<usize> =
+            scene.materials.iter().filter_map(|material| material.base_color_texture_index).collect();
+        let mut used: Vec<usize> = scene
+            .materials
+            .iter()
+            .flat_map(|material| {
+                [material.base_color_texture_index, material.emissive_texture_index, material.occlusion_texture_index]
+            })
+            .flatten()
+            .filter(|&index| index > 0 && index < scene.textures.len())
+            .filter(|&index| !virtual_texture::is_virtual(&scene.textures[index]))
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+
+        let mut groups: Vec<(TextureKind, Vec<usize>)> = Vec::new();
+        for index in used {
+            let texture = &scene.textures[index];
+            let mip_count = texture.compressed.as_ref().map_or(0, |compressed| compressed.mips.len());
+            let kind = ((texture.width, texture.height), texture.format(), mip_count);
+            match groups.iter_mut().find(|(group_kind, _)| *group_kind == kind) {
+                Some((_, indices)) => indices.push(index),
+                None => groups.push((kind, vec![index])),
+            }
+        }
+        groups.sort_by_key(|(_, indices)| std::cmp::Reverse(indices.len()));
+
+        let mut arrays = Vec::new();
+        let mut slots = vec![0; scene.textures.len()];
+        let mut left_out = Vec::new();
+        for ((_, format, _), mut indices) in groups {
+            if arrays.len() == MAX_TEXTURE_ARRAYS {
+                left_out.append(&mut indices);
+                continue;
+            }
+            if indices.len() > MAX_LAYERS {
+                left_out.extend(indices.drain(MAX_LAYERS..));
+            }
+            let layers: Vec<_> = indices
+                .iter()
+                .map(|&index| texture_streaming::scene_mip_chain(scene, index))
+                .collect();
+            let layers: Vec<&[MipLevel]> = layers.iter().map(|mips| &**mips).collect();
+            for (layer, &index) in indices.iter().enumerate() {
+                slots[index] = 1 + ((arrays.len() << 8) | layer) as u32;
+            }
+            arrays.push(TextureArray::new(renderer, format, &layers)?);
+        }
+
+        let white = GltfTexture { width: 1, height: 1, data: vec![255; 4], compressed: None };
+        let placeholder = TextureArray::new(renderer, white.format(), &[&texture_streaming::build_mip_chain(&white)])?;
+
+        let mut unpacked = HashMap::new();
+        for index in left_out.into_iter().filter(|index| base_color.contains(index)) {
+            let texture = &scene.textures[index];
+            let mips = texture_streaming::scene_mip_chain(scene, index);
+            unpacked.insert(index, TextureArray::new(renderer, texture.format(), &[&mips])?);
+        }
+
+        if !arrays.is_empty() {
+            println!(
+                "✓ Texture arrays: {} textures in {} arrays",
+                arrays.iter().map(|array| array.layers).sum::<u32>(),
+                arrays.len()
+            );
+        }
+        if !unpacked.is_empty() {
+            println!("⚠ {} textures of other sizes or formats are not in texture arrays; their materials bind them", unpacked.len());
+        }
+
+        Ok(Self { arrays, placeholder, slots, unpacked })
+    }
+
+    /// `firstInstance` of a draw sampling `texture`: 1 + the array index << 8 | the layer,
+    /// or 0 to sample binding 1
+    pub fn slot(&self, texture: Option<usize>) -> u32 {
+        texture.and_then(|index| self.slots.get(index).copied()).unwrap_or(0)
+    }
+
+    /// Whether `texture` is a base color texture left out of the arrays, which its
+    /// materials bind instead
+    pub fn is_unpacked(&self, texture: Option<usize>) -> bool {
+        texture.is_some_and(|index| self.unpacked.contains_key(&index))
+    }
+
+    /// What a material sampling `texture` binds in set 1: the texture if it's unpacked,
+    /// otherwise the placeholder
+    pub fn material_image(&self, texture: Option<usize>) -> vk::DescriptorImageInfo {
+        let array = texture.and_then(|index| self.unpacked.get(&index)).unwrap_or(&self.placeholder);
+        vk::DescriptorImageInfo {
+            sampler: array.sampler,
+            image_view: array.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+
+    /// Sample the arrays with `filter`; rewrite the descriptors after, the materials' too
+    pub unsafe fn set_filter(&mut self, renderer: &VulkanRenderer, filter: TextureFilter) -> Result<(), vk::Result> {
+        let all = self.arrays.iter_mut().chain(self.unpacked.values_mut());
+        for array in all.chain(std::iter::once(&mut self.placeholder)) {
+            array.sampler = renderer.sampler(filter.sampler_desc(array.mip_levels))?;
+        }
+        Ok(())
+    }
+
+    /// Bind the arrays at binding 13 of the scene descriptor `set`
+    pub unsafe fn write_descriptors(&self, device: &ash::Device, set: vk::DescriptorSet) {
+        let image_infos: Vec<vk::DescriptorImageInfo> = (0..MAX_TEXTURE_ARRAYS)
+            .map(|i| {
+                let array = self.arrays.get(i).unwrap_or(&self.placeholder);
+                vk::DescriptorImageInfo {
+                    sampler: array.sampler,
+                    image_view: array.view,
+                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                }
+            })
+            .collect();
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(13)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos);
+        device.update_descriptor_sets(&[write], &[]);
+    }
+
+    /// One line per array for the UI, `None` without any
+    pub fn summary(&self) -> Option<String> {
+        if self.arrays.is_empty() && self.unpacked.is_empty() {
+            return None;
+        }
+        let mut lines: Vec<String> = self
+            .arrays
+            .iter()
+            .map(|array| {
+                format!(
+                    "{}x{} array: {} layers, {:.1} MB",
+                    array.size.0,
+                    array.size.1,
+                    array.layers,
+                    array.bytes as f64 / (1024.0 * 1024.0)
+                )
+            })
+            .collect();
+        if !self.unpacked.is_empty() {
+            let bytes: u64 = self.unpacked.values().map(|array| array.bytes).sum();
+            lines.push(format!(
+                "{} textures bound per material, {:.1} MB",
+                self.unpacked.len(),
+                bytes as f64 / (1024.0 * 1024.0)
+            ));
+        }
+        Some(lines.join("\n"))
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        for array in self.arrays.iter_mut().chain(self.unpacked.values_mut()) {
+            array.destroy(renderer);
+        }
+        self.placeholder.destroy(renderer);
+    }
+}
@@ -0,0 +1,271 @@
+//! Texture streaming
+//!
+//! Scene textures are kept on the CPU as full mip chains, and only the tail of each chain,
+//! from its resident base mip down to 1x1, lives in VRAM. Loading uploads just the mips up
+//! to `INITIAL_SIZE` texels. After that, `TextureStreamer::plan` runs every frame:
+//!
+//! 1. Each texture wants the mip whose size matches its screen coverage, the on-screen
+//!    diameter of the meshes sampling it.
+//! 2. The wishes are fitted into the memory budget in order of coverage. Textures further
+//!    down the list get coarser mips.
+//! 3. At most one texture moves one mip towards its target. Textures that shrink go first,
+//!    to free memory before more is taken.
+//!
+//! A move re-creates the texture with the new mip range (mip-tail re-creation) rather than
+//! binding sparse memory, which needs device features many GPUs lack. Swapping the image
+//! waits for the device to go idle so every descriptor set can be rewritten, so moves are
+//! at least `SWAP_INTERVAL` frames apart.
+
+use std::borrow::Cow;
+
+use ash::vk;
+use bevy_ecs::prelude::*;
+use rayon::prelude::*;
+
+use crate::gltf_loader::{GltfScene, GltfTexture};
+use crate::sampler_cache::SamplerDesc;
+
+/// Default memory budget for resident mips
+pub const DEFAULT_BUDGET_MB: u32 = 256;
+/// Largest edge, in texels, of the mips uploaded when the scene loads
+const INITIAL_SIZE: u32 = 256;
+/// Frames between two resident mip changes
+const SWAP_INTERVAL: u32 = 8;
+
+/// One level of a mip chain: RGBA8 texels, blocks of a compressed texture's format, or
+/// RGBA float texels of an HDR image (see `hdr_image`)
+#[derive(Clone, Debug)]
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl MipLevel {
+    pub fn bytes(&self) -> u64 {
+        self.data.len() as u64
+    }
+}
+
+/// How material textures are sampled, set from the debug UI
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureFilter {
+    /// Off samples level 0 alone, which shimmers at a distance but shows the full detail
+    pub mipmaps: bool,
+    /// Samples along the slope of surfaces seen at grazing angles, 1 (off) to 16. Clamped
+    /// to what the device supports (`VulkanRenderer::max_anisotropy`).
+    pub anisotropy: u32,
+}
+
+impl Default for TextureFilter {
+    fn default() -> Self {
+        Self { mipmaps: true, anisotropy: 8 }
+    }
+}
+
+impl TextureFilter {
+    /// Sampler of a material texture with `mip_levels` levels
+    pub fn sampler_desc(self, mip_levels: u32) -> SamplerDesc {
+        let max_lod = if self.mipmaps { mip_levels - 1 } else { 0 };
+        let anisotropy = if self.anisotropy > 1 { self.anisotropy } else { 0 };
+        SamplerDesc::linear(vk::SamplerAddressMode::REPEAT)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .anisotropy(anisotropy)
+            .max_lod(max_lod)
+    }
+}
+
+/// Full mip chain of an sRGB texture, each level a 2x2 box filter of the one above it in
+/// linear light. Odd edges repeat their last texel. Compressed textures come with theirs.
+pub fn build_mip_chain(texture: &GltfTexture) -> Vec<MipLevel> {
+    if let Some(compressed) = &texture.compressed {
+        return compressed.mips.clone();
+    }
+    let to_linear = crate::screenshot::srgb_to_linear_table();
+    let mut mips = vec![MipLevel { width: texture.width, height: texture.height, data: texture.data.clone() }];
+    while let Some(above) = mips.last().filter(|mip| mip.width > 1 || mip.height > 1) {
+        let (width, height) = ((above.width / 2).max(1), (above.height / 2).max(1));
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let texels = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| {
+                    let sx = (x * 2 + dx).min(above.width - 1);
+                    let sy = (y * 2 + dy).min(above.height - 1);
+                    ((sy * above.width + sx) * 4) as usize
+                });
+                for channel in 0..3 {
+                    let sum: f32 = texels.iter().map(|&t| to_linear[above.data[t + channel] as usize]).sum();
+                    data.push(crate::screenshot::linear_to_srgb(sum * 0.25));
+                }
+                let alpha: u32 = texels.iter().map(|&t| above.data[t + 3] as u32).sum();
+                data.push(((alpha + 2) / 4) as u8);
+            }
+        }
+        mips.push(MipLevel { width, height, data });
+    }
+    mips
+}
+
+/// Build the mip chain of every texture of `scene` in parallel, for the scene renderer to
+/// upload without filtering on the thread that creates it
+pub fn build_scene_mips(scene: &mut GltfScene) {
+    scene.mips = scene.textures.par_iter().map(build_mip_chain).collect();
+}
+
+/// Mip chain of `scene.textures[index]`, built now unless `build_scene_mips` already has
+pub fn scene_mip_chain(scene: &GltfScene, index: usize) -> Cow<'_, [MipLevel]> {
+    match scene.mips.get(index) {
+        Some(mips) => Cow::Borrowed(mips),
+        None => Cow::Owned(build_mip_chain(&scene.textures[index])),
+    }
+}
+
+pub struct StreamedTexture {
+    mips: Vec<MipLevel>,
+    pub format: vk::Format,
+    pub resident_base: usize, // First mip in VRAM
+    pub target_base: usize,   // Where streaming is headed, within the budget
+    pub coverage: f32,        // On-screen diameter in pixels, the priority
+}
+
+impl StreamedTexture {
+    fn new(texture: &GltfTexture) -> Self {
+        let mips = build_mip_chain(texture);
+        let initial = mips
+            .iter()
+            .position(|mip| mip.width.max(mip.height) <= INITIAL_SIZE)
+            .unwrap_or(mips.len() - 1);
+        Self { mips, format: texture.format(), resident_base: initial, target_base: initial, coverage: 0.0 }
+    }
+
+    /// Mips from `base` down, the contents of the image while `base` is resident
+    pub fn mips_from(&self, base: usize) -> &[MipLevel] {
+        &self.mips[base..]
+    }
+
+    pub fn mip_count(&self) -> usize {
+        self.mips.len()
+    }
+
+    /// Size of mip 0
+    pub fn size(&self) -> (u32, u32) {
+        (self.mips[0].width, self.mips[0].height)
+    }
+
+    pub fn bytes_from(&self, base: usize) -> u64 {
+        self.mips_from(base).iter().map(MipLevel::bytes).sum()
+    }
+
+    pub fn resident_bytes(&self) -> u64 {
+        self.bytes_from(self.resident_base)
+    }
+
+    /// First mip with about one texel per pixel across `coverage` pixels, assuming the UVs
+    /// span the texture once
+    fn wanted_base(&self, coverage: f32) -> usize {
+        let (width, height) = self.size();
+        let texels_per_pixel = width.max(height) as f32 / coverage.max(1.0);
+        (texels_per_pixel.log2().floor().max(0.0) as usize).min(self.mips.len() - 1)
+    }
+}
+
+pub struct TextureStreamer {
+    pub textures: Vec<StreamedTexture>,
+    pub enabled: bool, // When off, every texture streams in fully, ignoring the budget
+    pub budget: u64,   // Bytes of resident mips
+    frames_until_swap: u32,
+}
+
+impl TextureStreamer {
+    pub fn new(textures: &[GltfTexture], budget: u64) -> Self {
+        Self {
+            textures: textures.iter().map(StreamedTexture::new).collect(),
+            enabled: true,
+            budget,
+            frames_until_swap: 0,
+        }
+    }
+
+    pub fn resident_bytes(&self) -> u64 {
+        self.textures.iter().map(StreamedTexture::resident_bytes).sum()
+    }
+
+    /// Bytes of every texture with all of its mips
+    pub fn full_bytes(&self) -> u64 {
+        self.textures.iter().map(|texture| texture.bytes_from(0)).sum()
+    }
+
+    /// Update the targets from each texture's on-screen `coverage` and pick the next move:
+    /// the texture and the base mip to make resident. Call once per frame.
+    pub fn plan(&mut self, coverage: &[f32]) -> Option<(usize, usize)> {
+        for (texture, &coverage) in self.textures.iter_mut().zip(coverage) {
+            texture.coverage = coverage;
+        }
+
+        let mut order: Vec<usize> = (0..self.textures.len()).collect();
+        order.sort_by(|&a, &b| self.textures[b].coverage.total_cmp(&self.textures[a].coverage));
+        let mut remaining = self.budget;
+        for &i in &order {
+            let texture = &mut self.textures[i];
+            if !self.enabled {
+                texture.target_base = 0;
+                continue;
+            }
+            let mut base = texture.wanted_base(texture.coverage);
+            while base + 1 < texture.mip_count() && texture.bytes_from(base) > remaining {
+                base += 1;
+            }
+            texture.target_base = base;
+            remaining = remaining.saturating_sub(texture.bytes_from(base));
+        }
+
+        if self.frames_until_swap > 0 {
+            self.frames_until_swap -= 1;
+            return None;
+        }
+        let shrink = order.iter().rev().copied().find(|&i| self.textures[i].target_base > self.textures[i].resident_base);
+        let grow = order.iter().copied().find(|&i| self.textures[i].target_base < self.textures[i].resident_base);
+        let (i, step) = match (shrink, grow) {
+            (Some(i), _) => (i, 1),
+            (None, Some(i)) => (i, -1),
+            (None, None) => return None,
+        };
+        self.frames_until_swap = SWAP_INTERVAL;
+        Some((i, self.textures[i].resident_base.saturating_add_signed(step)))
+    }
+
+    pub fn stats(&self) -> StreamingStats {
+        let edge = |texture: &StreamedTexture, base: usize| {
+            let mip = &texture.mips[base];
+            mip.width.max(mip.height)
+        };
+        StreamingStats {
+            resident_bytes: self.resident_bytes(),
+            full_bytes: self.full_bytes(),
+            textures: self
+                .textures
+                .iter()
+                .map(|texture| StreamedTextureStats {
+                    size: edge(texture, 0),
+                    resident: edge(texture, texture.resident_base),
+                    target: edge(texture, texture.target_base),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Resident sizes for the memory panel
+#[derive(Clone, Debug, Default)]
+pub struct StreamingStats {
+    pub resident_bytes: u64,
+    pub full_bytes: u64,
+    pub textures: Vec<StreamedTextureStats>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StreamedTextureStats {
+    pub size: u32,     // Largest edge of mip 0
+    pub resident: u32, // Largest edge of the resident base mip
+    pub target: u32,   // Largest edge of the target base mip
+}
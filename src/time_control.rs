@@ -0,0 +1,66 @@
+//! Global time controls
+//!
+//! Scene time can run slower or faster than real time, be paused, and be stepped one frame
+//! at a time while paused. Every frame `advance` turns the real frame time into the scene
+//! time that passed, which is what `FrameTiming::delta_time` holds for particles and
+//! weather, and what the fixed simulation steps through (see `fixed_timestep`). The
+//! camera and UI keep real time, so the view can still be moved around a paused scene.
+
+use bevy_ecs::prelude::*;
+
+/// Seconds of scene time one frame step advances
+pub const STEP_TIME: f32 = 1.0 / 60.0;
+pub const MIN_SCALE: f32 = 0.05;
+pub const MAX_SCALE: f32 = 4.0;
+/// The speeds the UI offers buttons for
+pub const PRESET_SCALES: [f32; 5] = [0.1, 0.25, 0.5, 1.0, 2.0];
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct TimeControl {
+    pub scale: f32, // Scene seconds per real second
+    pub paused: bool,
+    step_requested: bool, // Advance one step on the next frame, then stay paused
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        Self { scale: 1.0, paused: false, step_requested: false }
+    }
+}
+
+impl TimeControl {
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Pause if running, and advance the next frame by `STEP_TIME`
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.step_requested = true;
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.clamp(MIN_SCALE, MAX_SCALE);
+    }
+
+    /// Halve the speed, down to `MIN_SCALE`
+    pub fn slower(&mut self) {
+        self.set_scale(self.scale * 0.5);
+    }
+
+    /// Double the speed, up to `MAX_SCALE`
+    pub fn faster(&mut self) {
+        self.set_scale(self.scale * 2.0);
+    }
+
+    /// The scene time that passes in a frame of `real_delta` seconds
+    pub fn advance(&mut self, real_delta: f32) -> f32 {
+        if std::mem::take(&mut self.step_requested) {
+            STEP_TIME
+        } else if self.paused {
+            0.0
+        } else {
+            real_delta * self.scale
+        }
+    }
+}
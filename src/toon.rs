@@ -0,0 +1,168 @@
+//! Toon (cel) shading
+//!
+//! A stylized alternative to the scene's lighting, selected for every mesh from the debug
+//! UI or per material from the material editor. gltf.frag quantizes the direct light into
+//! a few flat bands, replaces the specular lobe with a hard highlight and adds a rim light
+//! along silhouettes; local reflections are left out.
+//!
+//! Outlines are inverted hulls: after the scene's meshes, each cel shaded mesh is drawn
+//! again with its front faces culled and its vertices pushed out along their normals by a
+//! constant width in pixels, in a flat color. Only the back faces that stick out past the
+//! mesh survive the depth test, tracing its silhouette and creases.
+
+use ash::vk;
+use glam::Mat4;
+
+use crate::command_encoder::{PipelineBinding, RenderPassEncoder};
+use crate::gltf_renderer::GltfVertex;
+use crate::pipeline_builder::{DepthMode, GraphicsPipelineBuilder};
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+
+/// Global cel shading parameters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToonStyle {
+    pub enabled: bool, // Cel shade every mesh, not only materials that ask for it
+    pub bands: u32,    // Light levels of the diffuse ramp
+    pub rim: f32,      // Rim light strength
+    pub outline_width: f32, // In pixels, 0 for none
+    pub outline_color: [f32; 3],
+}
+
+impl Default for ToonStyle {
+    fn default() -> Self {
+        Self { enabled: false, bands: 3, rim: 0.4, outline_width: 2.0, outline_color: [0.02, 0.02, 0.03] }
+    }
+}
+
+impl ToonStyle {
+    /// Uniforms of gltf.frag: x = 1 to cel shade everything, y = bands, z = rim strength
+    pub fn uniforms(&self) -> [f32; 4] {
+        [if self.enabled { 1.0 } else { 0.0 }, self.bands.max(2) as f32, self.rim, 0.0]
+    }
+}
+
+// Must match shaders/outline.vert + shaders/outline.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct OutlinePushConstants {
+    model: [[f32; 4]; 4],
+    color: [f32; 4],
+    params: [f32; 4], // x = width in pixels, zw = 2 / viewport size
+}
+
+/// The inverted hull pipeline, for the glTF scene pass
+pub struct OutlinePass {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+}
+
+impl OutlinePass {
+    /// Pipeline for `render_pass` with the scene's set 0 layout, whose uniform block it reads
+    pub unsafe fn new(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        scene_set_layout: vk::DescriptorSetLayout,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (vert_code, frag_code) = Self::shaders();
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &[&ShaderReflection::reflect(&vert_code)?, &ShaderReflection::reflect(&frag_code)?],
+            std::mem::size_of::<OutlinePushConstants>() as u32,
+        )?
+        .into_iter()
+        .collect();
+        let layout = device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(std::slice::from_ref(&scene_set_layout))
+                .push_constant_ranges(&push_constant_ranges),
+            None,
+        )?;
+
+        let mut outline = Self { pipeline: vk::Pipeline::null(), layout, render_pass };
+        match outline.build_pipeline(device, render_pass) {
+            Ok(pipeline) => {
+                outline.pipeline = pipeline;
+                Ok(outline)
+            }
+            Err(e) => {
+                device.destroy_pipeline_layout(layout, None);
+                Err(e)
+            }
+        }
+    }
+
+    fn shaders() -> (Vec<u32>, Vec<u32>) {
+        (
+            load_shader("outline.vert", include_bytes!("../shaders/outline.vert.spv")),
+            load_shader("outline.frag", include_bytes!("../shaders/outline.frag.spv")),
+        )
+    }
+
+    /// The outline pipeline for `render_pass`, which may differ from this pass's in its
+    /// color format. The caller owns it; see `swap_pipeline`.
+    pub unsafe fn build_pipeline(
+        &self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+    ) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+        let (vert_code, frag_code) = Self::shaders();
+        GraphicsPipelineBuilder::new(self.layout, render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+            .vertex_buffer::<GltfVertex>()
+            .cull_mode(vk::CullModeFlags::FRONT)
+            .depth(DepthMode::ReadWrite(vk::CompareOp::LESS))
+            .build(device)
+    }
+
+    /// Draw with `pipeline`, built for `render_pass` by `build_pipeline`, from now on.
+    /// Returns the pipeline it replaces.
+    pub fn swap_pipeline(&mut self, render_pass: vk::RenderPass, pipeline: vk::Pipeline) -> vk::Pipeline {
+        self.render_pass = render_pass;
+        std::mem::replace(&mut self.pipeline, pipeline)
+    }
+
+    /// Bind the pipeline and `descriptor_set` as set 0 for outlines of `style` on a view of
+    /// `extent`; then `draw` each mesh
+    pub fn begin(
+        &self,
+        pass: &mut RenderPassEncoder,
+        descriptor_set: vk::DescriptorSet,
+        style: &ToonStyle,
+        model: &Mat4,
+        extent: vk::Extent2D,
+    ) {
+        pass.bind_pipeline(PipelineBinding {
+            pipeline: self.pipeline,
+            layout: self.layout,
+            render_pass: self.render_pass,
+            push_constant_size: std::mem::size_of::<OutlinePushConstants>() as u32,
+        });
+        pass.bind_descriptor_set(0, descriptor_set);
+        let [r, g, b] = style.outline_color;
+        let pc = OutlinePushConstants {
+            model: model.to_cols_array_2d(),
+            color: [r, g, b, 1.0],
+            params: [
+                style.outline_width,
+                0.0,
+                2.0 / extent.width.max(1) as f32,
+                2.0 / extent.height.max(1) as f32,
+            ],
+        };
+        pass.push_constants(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT, 0, &pc);
+    }
+
+    /// The outline of one mesh, after `begin`
+    pub fn draw(&self, pass: &mut RenderPassEncoder, vertex_buffer: vk::Buffer, index_buffer: vk::Buffer, index_count: u32) {
+        pass.bind_vertex_buffers(&[vertex_buffer], &[0]);
+        pass.bind_index_buffer(index_buffer, 0, vk::IndexType::UINT32);
+        pass.draw_indexed(index_count, 1, 0);
+    }
+
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.layout, None);
+    }
+}
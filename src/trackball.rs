@@ -0,0 +1,114 @@
+//! Trackball model manipulation
+//!
+//! With the trackball on, dragging with the left mouse button turns the loaded model
+//! instead of the camera having to fly around it: the cursor drags a point on a virtual
+//! sphere centered on the model's bounds (an arcball), and the model turns with it. Holding
+//! shift while dragging slides the model over the ground plane under the cursor instead.
+//! Both write to the model entity's `Transform`, which is what the scene is drawn with.
+
+use bevy_ecs::prelude::*;
+use glam::{Quat, Vec2, Vec3, Vec4Swizzles};
+
+use crate::gltf_renderer::ViewCamera;
+
+/// What a drag does, decided when the button goes down
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DragMode {
+    Rotate,
+    Move,
+}
+
+/// A model change from one cursor movement
+#[derive(Clone, Copy, Debug)]
+pub enum Manipulation {
+    /// Turn the model by this world-space rotation around its bounds center
+    Rotate(Quat),
+    /// Move the model by this much along the ground
+    Move(Vec3),
+}
+
+#[derive(Resource, Default)]
+pub struct Trackball {
+    pub enabled: bool,
+    drag: Option<DragMode>,
+    cursor: Option<Vec2>, // Physical pixels, once the cursor has entered the window
+}
+
+impl Trackball {
+    /// Start a drag where the cursor is. `shift` moves instead of rotating.
+    pub fn press(&mut self, shift: bool) {
+        if self.enabled && self.cursor.is_some() {
+            self.drag = Some(if shift { DragMode::Move } else { DragMode::Rotate });
+        }
+    }
+
+    pub fn release(&mut self) {
+        self.drag = None;
+    }
+
+    /// Follow the cursor to `position`, returning what a drag in progress does to the model.
+    /// `center` is the model's bounds center and `ground` the height it stands on, both in
+    /// world space; `viewport` is the window size in pixels.
+    pub fn cursor_moved(
+        &mut self,
+        position: Vec2,
+        viewport: Vec2,
+        camera: &ViewCamera,
+        center: Vec3,
+        ground: f32,
+    ) -> Option<Manipulation> {
+        let last = self.cursor.replace(position)?;
+        if last == position || viewport.min_element() <= 0.0 {
+            return None;
+        }
+        match self.drag? {
+            DragMode::Rotate => {
+                // The sphere is centered on the model as it appears on screen
+                let projected = camera.view_proj() * center.extend(1.0);
+                let pivot = if projected.w > 0.0 {
+                    (projected.xy() / projected.w * 0.5 + 0.5) * viewport
+                } else {
+                    viewport * 0.5
+                };
+                let radius = viewport.min_element() * 0.4;
+                let camera_rotation = Quat::from_mat4(&camera.view.inverse());
+                let from = camera_rotation * sphere_point(last, pivot, radius);
+                let to = camera_rotation * sphere_point(position, pivot, radius);
+                Some(Manipulation::Rotate(Quat::from_rotation_arc(from, to)))
+            }
+            DragMode::Move => {
+                let from = ground_point(last, viewport, camera, ground)?;
+                let to = ground_point(position, viewport, camera, ground)?;
+                Some(Manipulation::Move(Vec3::new(to.x - from.x, 0.0, to.z - from.z)))
+            }
+        }
+    }
+}
+
+/// Where `cursor` lands on the arcball sphere of `radius` pixels around `pivot`, in view
+/// space. Outside the sphere it slides along the silhouette.
+fn sphere_point(cursor: Vec2, pivot: Vec2, radius: f32) -> Vec3 {
+    // Screen y grows downwards, view space y upwards
+    let offset = (cursor - pivot) / radius * Vec2::new(1.0, -1.0);
+    let length_squared = offset.length_squared();
+    if length_squared <= 1.0 {
+        offset.extend((1.0 - length_squared).sqrt())
+    } else {
+        (offset / length_squared.sqrt()).extend(0.0)
+    }
+}
+
+/// The point on the horizontal plane at `height` under `cursor`, if the ray through it
+/// hits the plane in front of the camera
+fn ground_point(cursor: Vec2, viewport: Vec2, camera: &ViewCamera, height: f32) -> Option<Vec3> {
+    let ndc = cursor / viewport * 2.0 - 1.0;
+    let inverse = camera.view_proj().inverse();
+    let near = inverse.project_point3(ndc.extend(0.0));
+    let far = inverse.project_point3(ndc.extend(1.0));
+    let direction = far - near;
+    if direction.y.abs() < 1e-6 {
+        return None;
+    }
+    let t = (height - near.y) / direction.y;
+    (t > 0.0).then(|| near + direction * t)
+}
@@ -0,0 +1,196 @@
+//! Device-local buffer uploads
+//!
+//! Geometry that doesn't change once loaded belongs in DEVICE_LOCAL memory, which the host
+//! usually can't map. A `BufferUploader` writes each buffer's data to a host-visible
+//! staging buffer and queues a copy into a device-local destination; `finish` records the
+//! queued copies into one command buffer, waits for the GPU to run it and frees the
+//! staging buffers. Once `MAX_STAGING_BYTES` are staged the copies so far are submitted
+//! before more are staged, so a large scene never needs all of itself in staging memory.
+//!
+//! Destinations are created with TRANSFER_SRC too, so they can be copied again on the GPU
+//! (see `copy_buffer`), which is how the defragmenter moves them.
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+
+use crate::renderer::VulkanRenderer;
+
+/// Most staging memory held at once
+const MAX_STAGING_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Usage device-local destinations get on top of the one asked for
+pub const TRANSFER_USAGE: vk::BufferUsageFlags =
+    vk::BufferUsageFlags::from_raw(vk::BufferUsageFlags::TRANSFER_SRC.as_raw() | vk::BufferUsageFlags::TRANSFER_DST.as_raw());
+
+struct StagedCopy {
+    staging: vk::Buffer,
+    allocation: Allocation,
+    destination: vk::Buffer,
+    size: u64,
+}
+
+#[derive(Default)]
+pub struct BufferUploader {
+    staged: Vec<StagedCopy>, // Copies not submitted yet
+    staged_bytes: u64,
+}
+
+impl BufferUploader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A device-local buffer that will hold `data` once `finish` returns
+    ///
+    /// # Safety
+    ///
+    /// The caller owns the returned buffer and allocation; it must not be read before `finish`
+    /// returns.
+    pub unsafe fn upload<T: Copy>(
+        &mut self,
+        renderer: &VulkanRenderer,
+        name: &str,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+    ) -> Result<(vk::Buffer, Allocation), Box<dyn std::error::Error>> {
+        let size = std::mem::size_of_val(data) as u64;
+        if self.staged_bytes + size > MAX_STAGING_BYTES && !self.staged.is_empty() {
+            self.flush(renderer)?;
+        }
+
+        let (destination, destination_allocation) =
+            create_buffer(renderer, name, size, usage | TRANSFER_USAGE, MemoryLocation::GpuOnly)?;
+        if size == 0 {
+            return Ok((destination, destination_allocation));
+        }
+        let (staging, allocation) =
+            match create_buffer(renderer, "staging_buffer", size, vk::BufferUsageFlags::TRANSFER_SRC, MemoryLocation::CpuToGpu) {
+                Ok(staging) => staging,
+                Err(e) => {
+                    renderer.device.destroy_buffer(destination, None);
+                    let _ = renderer.allocator.lock().free(destination_allocation);
+                    return Err(e);
+                }
+            };
+        let mapped = allocation.mapped_ptr().unwrap().as_ptr() as *mut T;
+        std::ptr::copy_nonoverlapping(data.as_ptr(), mapped, data.len());
+
+        self.staged.push(StagedCopy { staging, allocation, destination, size });
+        self.staged_bytes += size;
+        Ok((destination, destination_allocation))
+    }
+
+    /// Copy everything uploaded into place and free the staging buffers
+    ///
+    /// # Safety
+    ///
+    /// `renderer` must be the one the buffers were uploaded with.
+    pub unsafe fn finish(mut self, renderer: &VulkanRenderer) -> Result<(), Box<dyn std::error::Error>> {
+        self.flush(renderer)
+    }
+
+    unsafe fn flush(&mut self, renderer: &VulkanRenderer) -> Result<(), Box<dyn std::error::Error>> {
+        let copies: Vec<(vk::Buffer, vk::Buffer, u64)> =
+            self.staged.iter().map(|copy| (copy.staging, copy.destination, copy.size)).collect();
+        let result = submit_copies(renderer, &copies);
+
+        // The staging buffers go either way: after a failed submit nothing reads them
+        for copy in self.staged.drain(..) {
+            renderer.device.destroy_buffer(copy.staging, None);
+            let _ = renderer.allocator.lock().free(copy.allocation);
+        }
+        self.staged_bytes = 0;
+        result
+    }
+}
+
+/// Copy the first `size` bytes of `source` to `destination` on the GPU and wait for it
+///
+/// # Safety
+///
+/// Both buffers must hold at least `size` bytes; `source` needs `TRANSFER_SRC` usage and
+/// `destination` `TRANSFER_DST`.
+pub unsafe fn copy_buffer(
+    renderer: &VulkanRenderer,
+    source: vk::Buffer,
+    destination: vk::Buffer,
+    size: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    submit_copies(renderer, &[(source, destination, size)])
+}
+
+/// Run `(source, destination, size)` copies on the graphics queue and wait for them. A
+/// barrier makes the copied data visible to whatever reads it next: vertex input, shaders
+/// or acceleration structure builds.
+unsafe fn submit_copies(
+    renderer: &VulkanRenderer,
+    copies: &[(vk::Buffer, vk::Buffer, u64)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if copies.is_empty() {
+        return Ok(());
+    }
+    let device = &renderer.device;
+    let cmd_info = vk::CommandBufferAllocateInfo::default()
+        .command_pool(renderer.command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1);
+    let cmd = device.allocate_command_buffers(&cmd_info)?[0];
+
+    let begin_info = vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(cmd, &begin_info)?;
+    for &(source, destination, size) in copies {
+        let region = vk::BufferCopy { src_offset: 0, dst_offset: 0, size };
+        device.cmd_copy_buffer(cmd, source, destination, &[region]);
+    }
+    let barrier = vk::MemoryBarrier::default()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::MEMORY_READ);
+    device.cmd_pipeline_barrier(
+        cmd,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[],
+        &[],
+    );
+    device.end_command_buffer(cmd)?;
+
+    let submit_info = vk::SubmitInfo::default().command_buffers(std::slice::from_ref(&cmd));
+    let result = device
+        .queue_submit(renderer.graphics_queue, &[submit_info], vk::Fence::null())
+        .and_then(|_| device.queue_wait_idle(renderer.graphics_queue));
+    device.free_command_buffers(renderer.command_pool, &[cmd]);
+    Ok(result?)
+}
+
+unsafe fn create_buffer(
+    renderer: &VulkanRenderer,
+    name: &str,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+    location: MemoryLocation,
+) -> Result<(vk::Buffer, Allocation), Box<dyn std::error::Error>> {
+    let buffer_info = vk::BufferCreateInfo::default()
+        .size(size.max(4))
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = renderer.device.create_buffer(&buffer_info, None)?;
+    let requirements = renderer.device.get_buffer_memory_requirements(buffer);
+    let allocation = match renderer.allocator.lock().allocate(&AllocationCreateDesc {
+        name,
+        requirements,
+        location,
+        linear: true,
+        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+    }) {
+        Ok(allocation) => allocation,
+        Err(e) => {
+            renderer.device.destroy_buffer(buffer, None);
+            return Err(e.into());
+        }
+    };
+    renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+    Ok((buffer, allocation))
+}
@@ -0,0 +1,487 @@
+//! Instanced vegetation
+//!
+//! A `VegetationLayer` on an entity scatters plants of one kind over a rectangle of ground:
+//! a fixed number per square meter, thinned by an optional density mask, placed and varied
+//! from a hash of the layer's seed so the same layer always grows the same way. Each layer
+//! becomes one instance buffer and one instanced draw of a small procedural mesh in the
+//! scene pass.
+//!
+//! Plants move in the vertex shader: tips lean with the `Weather` wind and gusts roll across
+//! the field along it. Past the layer's fade distances plants shrink away to nothing, so a
+//! layer can cover more ground than is worth drawing up close.
+
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+use std::sync::Arc;
+
+use ash::vk;
+use bevy_ecs::prelude::*;
+use glam::{Mat4, Vec2, Vec3};
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+
+use crate::command_encoder::{PipelineBinding, RenderPassEncoder};
+use crate::pipeline_builder::{DepthMode, GraphicsPipelineBuilder, VertexLayout};
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+use crate::stress::hash;
+use crate::weather::Weather;
+
+/// Most plants one layer scatters, whatever its size and density
+const MAX_PLANTS_PER_LAYER: u32 = 250_000;
+
+/// Which mesh a layer instances
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FoliageKind {
+    /// A tuft of thin blades
+    Grass,
+    /// Broad fronds arching out from the root
+    Fern,
+}
+
+impl FoliageKind {
+    const ALL: [Self; 2] = [Self::Grass, Self::Fern];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Grass => "grass",
+            Self::Fern => "fern",
+        }
+    }
+}
+
+/// How much of a layer's density grows where, over the layer's rectangle
+#[derive(Clone, Debug, PartialEq)]
+pub struct DensityMask {
+    width: u32,
+    height: u32,
+    values: Vec<f32>, // 0 to 1, row by row from -Z to +Z, each from -X to +X
+}
+
+impl DensityMask {
+    /// `width` x `height` values of `f(u, v)`, with u and v from 0 to 1 across the layer
+    pub fn from_fn(width: u32, height: u32, f: impl Fn(f32, f32) -> f32) -> Self {
+        let values = (0..width * height)
+            .map(|i| {
+                let u = ((i % width) as f32 + 0.5) / width as f32;
+                let v = ((i / width) as f32 + 0.5) / height as f32;
+                f(u, v).clamp(0.0, 1.0)
+            })
+            .collect();
+        Self { width, height, values }
+    }
+
+    /// The brightness of an image, white for full density
+    pub fn load(path: &std::path::Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let image = image::open(path)?.into_luma8();
+        let (width, height) = image.dimensions();
+        let values = image.into_raw().into_iter().map(|value| value as f32 / 255.0).collect();
+        Ok(Self { width, height, values })
+    }
+
+    /// Bilinear sample at `u`, `v`, each 0 to 1 across the layer
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = (u * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x as u32, y as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let value = |x: u32, y: u32| self.values[(y * self.width + x) as usize];
+        let (fx, fy) = (x.fract(), y.fract());
+        let top = value(x0, y0) + (value(x1, y0) - value(x0, y0)) * fx;
+        let bottom = value(x0, y1) + (value(x1, y1) - value(x0, y1)) * fx;
+        top + (bottom - top) * fy
+    }
+}
+
+/// Plants of one kind scattered over a rectangle of ground
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct VegetationLayer {
+    pub kind: FoliageKind,
+    pub center: Vec3,      // Middle of the rectangle, on the ground
+    pub half_extent: Vec2, // Of the rectangle along X and Z, in meters
+    pub density: f32,      // Plants per square meter where the mask is white
+    pub seed: u32,
+    pub mask: Option<Arc<DensityMask>>, // None for even cover
+    pub height: (f32, f32), // Shortest and tallest plant, in meters
+    pub color: [f32; 3],
+    pub fade: (f32, f32), // Distance from the camera where plants start to shrink, and are gone
+}
+
+impl VegetationLayer {
+    pub fn new(kind: FoliageKind, center: Vec3, half_extent: Vec2) -> Self {
+        let (density, height, color) = match kind {
+            FoliageKind::Grass => (30.0, (0.25, 0.5), [0.28, 0.5, 0.16]),
+            FoliageKind::Fern => (0.8, (0.4, 0.8), [0.18, 0.42, 0.14]),
+        };
+        Self { kind, center, half_extent, density, seed: 0, mask: None, height, color, fade: (20.0, 30.0) }
+    }
+
+    pub fn with_density(mut self, density: f32) -> Self {
+        self.density = density;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_mask(mut self, mask: Arc<DensityMask>) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    /// Where each plant grows and how it varies
+    fn scatter(&self) -> Vec<FoliageInstance> {
+        let area = 4.0 * self.half_extent.x * self.half_extent.y;
+        let candidates = ((area * self.density) as u32).min(MAX_PLANTS_PER_LAYER);
+        let salt = self.seed.wrapping_mul(8);
+        (0..candidates)
+            .filter_map(|i| {
+                let (u, v) = (hash(i, salt), hash(i, salt + 1));
+                if let Some(mask) = &self.mask {
+                    if hash(i, salt + 2) >= mask.sample(u, v) {
+                        return None;
+                    }
+                }
+                let offset = (Vec2::new(u, v) * 2.0 - 1.0) * self.half_extent;
+                let position = self.center + Vec3::new(offset.x, 0.0, offset.y);
+                let (shortest, tallest) = self.height;
+                let scale = shortest + (tallest - shortest) * hash(i, salt + 3);
+                Some(FoliageInstance {
+                    placement: position.extend(scale).to_array(),
+                    variation: [hash(i, salt + 4) * TAU, hash(i, salt + 5) * TAU, hash(i, salt + 6), 0.0],
+                })
+            })
+            .collect()
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct FoliageVertex {
+    position: [f32; 3], // For a plant one meter tall
+    normal: [f32; 3],
+    sway: f32, // 0 at the root, 1 at the tips
+}
+
+impl VertexLayout for FoliageVertex {
+    const ATTRIBUTES: &'static [(vk::Format, u32)] = &[
+        (vk::Format::R32G32B32_SFLOAT, std::mem::offset_of!(FoliageVertex, position) as u32),
+        (vk::Format::R32G32B32_SFLOAT, std::mem::offset_of!(FoliageVertex, normal) as u32),
+        (vk::Format::R32_SFLOAT, std::mem::offset_of!(FoliageVertex, sway) as u32),
+    ];
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct FoliageInstance {
+    placement: [f32; 4], // xyz = root position, w = height
+    variation: [f32; 4], // x = rotation about Y, y = wind phase, z = tint
+}
+
+impl VertexLayout for FoliageInstance {
+    const ATTRIBUTES: &'static [(vk::Format, u32)] = &[
+        (vk::Format::R32G32B32A32_SFLOAT, 0),
+        (vk::Format::R32G32B32A32_SFLOAT, 16),
+    ];
+}
+
+// Must match shaders/vegetation.vert
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct VegetationPushConstants {
+    view_proj: [[f32; 4]; 4],
+    camera: [f32; 4], // xyz = camera position, w = time
+    wind: [f32; 4],   // xy = wind velocity over XZ, z = base sway, w = gust strength
+    fade: [f32; 4],   // x = fade start, y = fade end
+    color: [f32; 4],
+}
+
+/// Triangles of a strip along `spine`, each point with the strip's width there, spread
+/// along `side` and facing `normal`
+fn strip(vertices: &mut Vec<FoliageVertex>, spine: &[(Vec3, f32)], side: Vec3, normal: Vec3) {
+    let edge = |(point, width): (Vec3, f32), sign: f32| FoliageVertex {
+        position: (point + side * width * 0.5 * sign).to_array(),
+        normal: normal.to_array(),
+        sway: point.y.clamp(0.0, 1.0),
+    };
+    for pair in spine.windows(2) {
+        let (a0, a1, b0, b1) = (edge(pair[0], -1.0), edge(pair[0], 1.0), edge(pair[1], -1.0), edge(pair[1], 1.0));
+        vertices.extend_from_slice(&[a0, a1, b1, b1, b0, a0]);
+    }
+}
+
+/// Triangle list of `kind`, one meter tall, rooted at the origin
+fn foliage_mesh(kind: FoliageKind) -> Vec<FoliageVertex> {
+    let mut vertices = Vec::new();
+    match kind {
+        FoliageKind::Grass => {
+            const BLADES: u32 = 5;
+            const SEGMENTS: u32 = 3;
+            for blade in 0..BLADES {
+                let angle = blade as f32 / BLADES as f32 * TAU + hash(blade, 100) * 0.8;
+                let facing = Mat4::from_rotation_y(angle);
+                let side = facing.transform_vector3(Vec3::X);
+                let out = facing.transform_vector3(Vec3::Z);
+                let root = out * 0.04 * hash(blade, 101);
+                let height = 0.7 + 0.3 * hash(blade, 102);
+                let lean = 0.1 + 0.2 * hash(blade, 103);
+                let spine: Vec<_> = (0..=SEGMENTS)
+                    .map(|segment| {
+                        let t = segment as f32 / SEGMENTS as f32;
+                        (root + out * lean * t * t + Vec3::Y * height * t, 0.04 * (1.0 - t))
+                    })
+                    .collect();
+                // Tilted up a little so blades catch the sky as well
+                strip(&mut vertices, &spine, side, (out + Vec3::Y * 0.5).normalize());
+            }
+        }
+        FoliageKind::Fern => {
+            const FRONDS: u32 = 7;
+            const SEGMENTS: u32 = 6;
+            for frond in 0..FRONDS {
+                let angle = frond as f32 / FRONDS as f32 * TAU + hash(frond, 200) * 0.5;
+                let facing = Mat4::from_rotation_y(angle);
+                let side = facing.transform_vector3(Vec3::X);
+                let out = facing.transform_vector3(Vec3::Z);
+                let reach = 0.8 + 0.3 * hash(frond, 201);
+                let spine: Vec<_> = (0..=SEGMENTS)
+                    .map(|segment| {
+                        let t = segment as f32 / SEGMENTS as f32;
+                        let arch = (t * PI * 0.8).sin() * 0.7 + t * 0.1;
+                        (out * reach * t + Vec3::Y * arch, 0.25 * (t * PI).sin())
+                    })
+                    .collect();
+                strip(&mut vertices, &spine, side, (Vec3::Y + out * 0.3).normalize());
+            }
+        }
+    }
+    vertices
+}
+
+/// A layer's plants on the GPU
+struct ScatteredLayer {
+    layer: VegetationLayer, // As scattered, to notice changes
+    buffer: vk::Buffer,
+    allocation: Option<Allocation>,
+    count: u32,
+}
+
+/// Draws every `VegetationLayer` in the world
+pub struct Vegetation {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    meshes: HashMap<FoliageKind, (vk::Buffer, Option<Allocation>, u32)>,
+    layers: HashMap<Entity, ScatteredLayer>,
+    retired: Vec<(u64, ScatteredLayer)>, // With the frame they went, until no frame in flight uses them
+    frame: u64,
+}
+
+impl Vegetation {
+    /// `render_pass` is the scene pass plants are drawn in (color + depth)
+    pub unsafe fn new(renderer: &VulkanRenderer, render_pass: vk::RenderPass) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let vert_code = load_shader("vegetation.vert", include_bytes!("../shaders/vegetation.vert.spv"));
+        let frag_code = load_shader("vegetation.frag", include_bytes!("../shaders/vegetation.frag.spv"));
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &[&ShaderReflection::reflect(&vert_code)?, &ShaderReflection::reflect(&frag_code)?],
+            std::mem::size_of::<VegetationPushConstants>() as u32,
+        )?
+        .into_iter()
+        .collect();
+        let layout = device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&push_constant_ranges),
+            None,
+        )?;
+
+        let mut vegetation = Self {
+            pipeline: vk::Pipeline::null(),
+            layout,
+            render_pass,
+            meshes: HashMap::new(),
+            layers: HashMap::new(),
+            retired: Vec::new(),
+            frame: 0,
+        };
+
+        // Blades are seen from both sides, so nothing is culled
+        let pipeline = GraphicsPipelineBuilder::new(layout, render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &vert_code)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &frag_code)
+            .vertex_buffer::<FoliageVertex>()
+            .instance_buffer::<FoliageInstance>()
+            .depth(DepthMode::ReadWrite(vk::CompareOp::LESS))
+            .build(device);
+        let created = pipeline.and_then(|pipeline| {
+            vegetation.pipeline = pipeline;
+            for kind in FoliageKind::ALL {
+                let vertices = foliage_mesh(kind);
+                let (buffer, allocation) = create_buffer(renderer, "Foliage Mesh", &vertices)?;
+                vegetation.meshes.insert(kind, (buffer, Some(allocation), vertices.len() as u32));
+            }
+            Ok(())
+        });
+        if let Err(e) = created {
+            vegetation.destroy(renderer);
+            return Err(e);
+        }
+        Ok(vegetation)
+    }
+
+    /// Scatter layers that are new or changed since the last call and drop those that are
+    /// gone. Call once per frame, before the scene pass.
+    pub unsafe fn sync(&mut self, renderer: &VulkanRenderer, world: &mut World) {
+        self.frame += 1;
+        let (frame, retired) = (self.frame, std::mem::take(&mut self.retired));
+        for (retired_at, mut layer) in retired {
+            if frame - retired_at > MAX_FRAMES_IN_FLIGHT as u64 {
+                Self::free(renderer, &mut layer);
+            } else {
+                self.retired.push((retired_at, layer));
+            }
+        }
+
+        let mut seen = Vec::new();
+        for (entity, layer) in world.query::<(Entity, &VegetationLayer)>().iter(world) {
+            seen.push(entity);
+            if self.layers.get(&entity).is_some_and(|scattered| scattered.layer == *layer) {
+                continue;
+            }
+            let instances = layer.scatter();
+            let scattered = if instances.is_empty() {
+                Ok(ScatteredLayer { layer: layer.clone(), buffer: vk::Buffer::null(), allocation: None, count: 0 })
+            } else {
+                create_buffer(renderer, "Vegetation Instances", &instances).map(|(buffer, allocation)| ScatteredLayer {
+                    layer: layer.clone(),
+                    buffer,
+                    allocation: Some(allocation),
+                    count: instances.len() as u32,
+                })
+            };
+            match scattered {
+                Ok(scattered) => {
+                    println!("✓ Scattered {} {} plants", scattered.count, layer.kind.name());
+                    if let Some(old) = self.layers.insert(entity, scattered) {
+                        self.retired.push((frame, old));
+                    }
+                }
+                Err(e) => eprintln!("✗ Failed to scatter vegetation: {}", e),
+            }
+        }
+        let gone: Vec<_> = self.layers.keys().filter(|entity| !seen.contains(entity)).copied().collect();
+        for entity in gone {
+            if let Some(old) = self.layers.remove(&entity) {
+                self.retired.push((frame, old));
+            }
+        }
+    }
+
+    /// Plants scattered over all layers
+    pub fn plant_count(&self) -> u32 {
+        self.layers.values().map(|scattered| scattered.count).sum()
+    }
+
+    /// Draw every layer inside the scene render pass, swaying in `weather`'s wind at `time`
+    /// seconds
+    pub fn draw(
+        &self,
+        pass: &mut RenderPassEncoder,
+        extent: vk::Extent2D,
+        view_proj: Mat4,
+        camera_position: Vec3,
+        weather: &Weather,
+        time: f32,
+    ) {
+        if self.plant_count() == 0 {
+            return;
+        }
+        pass.bind_pipeline(PipelineBinding {
+            pipeline: self.pipeline,
+            layout: self.layout,
+            render_pass: self.render_pass,
+            push_constant_size: std::mem::size_of::<VegetationPushConstants>() as u32,
+        });
+        pass.set_full_viewport(extent);
+
+        // Plants never stand quite still; stronger wind bends them further and gusts harder
+        let wind = weather.wind();
+        let sway = 0.03 + weather.wind_speed * 0.01;
+        let gusts = (weather.wind_speed * 0.03).min(0.4);
+        for scattered in self.layers.values().filter(|scattered| scattered.count > 0) {
+            let layer = &scattered.layer;
+            let (mesh, _, vertex_count) = self.meshes[&layer.kind];
+            pass.bind_vertex_buffers(&[mesh, scattered.buffer], &[0, 0]);
+            let push_constants = VegetationPushConstants {
+                view_proj: view_proj.to_cols_array_2d(),
+                camera: camera_position.extend(time).to_array(),
+                wind: [wind.x, wind.y, sway, gusts],
+                fade: [layer.fade.0, layer.fade.1, 0.0, 0.0],
+                color: [layer.color[0], layer.color[1], layer.color[2], 1.0],
+            };
+            pass.push_constants(vk::ShaderStageFlags::VERTEX, 0, &push_constants);
+            pass.draw(vertex_count, scattered.count);
+        }
+    }
+
+    unsafe fn free(renderer: &VulkanRenderer, layer: &mut ScatteredLayer) {
+        if layer.buffer != vk::Buffer::null() {
+            renderer.device.destroy_buffer(layer.buffer, None);
+        }
+        if let Some(allocation) = layer.allocation.take() {
+            let _ = renderer.allocator.lock().free(allocation);
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        for (_, mut layer) in self.layers.drain() {
+            Self::free(renderer, &mut layer);
+        }
+        for (_, mut layer) in self.retired.drain(..) {
+            Self::free(renderer, &mut layer);
+        }
+        for (_, (buffer, allocation, _)) in self.meshes.drain() {
+            device.destroy_buffer(buffer, None);
+            if let Some(allocation) = allocation {
+                let _ = renderer.allocator.lock().free(allocation);
+            }
+        }
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.layout, None);
+    }
+}
+
+/// Host-visible vertex buffer holding `data`
+unsafe fn create_buffer<T: Copy>(
+    renderer: &VulkanRenderer,
+    name: &str,
+    data: &[T],
+) -> Result<(vk::Buffer, Allocation), Box<dyn std::error::Error>> {
+    let buffer_info = vk::BufferCreateInfo::default()
+        .size(std::mem::size_of_val(data) as u64)
+        .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = renderer.device.create_buffer(&buffer_info, None)?;
+    let requirements = renderer.device.get_buffer_memory_requirements(buffer);
+    let allocation = renderer.allocator.lock().allocate(&AllocationCreateDesc {
+        name,
+        requirements,
+        location: MemoryLocation::CpuToGpu,
+        linear: true,
+        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+    });
+    let allocation = match allocation {
+        Ok(allocation) => allocation,
+        Err(e) => {
+            renderer.device.destroy_buffer(buffer, None);
+            return Err(e.into());
+        }
+    };
+    renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
+    let ptr = allocation.mapped_ptr().unwrap().as_ptr() as *mut T;
+    std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+    Ok((buffer, allocation))
+}
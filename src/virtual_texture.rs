@@ -0,0 +1,700 @@
+//! Sparse virtual texturing
+//!
+//! Scene textures too large to keep in VRAM, such as 16k photogrammetry scans, are split
+//! into square pages per mip, and only the pages the camera needs live in a fixed-size
+//! page cache atlas. `gltf.frag` finds a texel's page through the page table texture, which
+//! has one texel per page of each mip: the atlas slot of that page, or of its nearest
+//! resident ancestor while it is loading, so the shader always has something to sample.
+//!
+//! Each frame:
+//!
+//! 1. the main view writes the pages it sampled into its frame slot's feedback buffer (one
+//!    pixel of every 4x4 block per frame, each page hashed to its own entry);
+//! 2. once the slot's fence has been waited on, `begin_frame` reads the feedback, marks
+//!    those pages and their ancestors used, and asks the loader thread for missing ones,
+//!    coarsest first;
+//! 3. pages the loader has finished are given the least recently used slots, copied to
+//!    the slot's staging buffer with the rebuilt page table, and `record` uploads them
+//!    before the scene passes.
+//!
+//! The loader cuts pages out of a CPU mip chain built when the scene loads, with a
+//! border texel around each so bilinear filtering doesn't bleed between slots. The atlas
+//! has no mips of its own: pages are sampled at the mip nearest above the pixel's
+//! footprint.
+
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use gpu_allocator::MemoryLocation;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+
+use crate::gltf_loader::GltfTexture;
+use crate::lightmap;
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::sampler_cache::SamplerDesc;
+use crate::texture_streaming::{self, MipLevel};
+
+/// Scene textures with a larger edge are virtual instead of streamed
+pub const MIN_VIRTUAL_SIZE: u32 = 8192;
+/// Edge of a page in texels, without its border. Must match gltf.frag.
+const PAGE_SIZE: u32 = 128;
+/// Texels repeated from the neighbouring pages on each side. Must match gltf.frag.
+const PAGE_BORDER: u32 = 1;
+const SLOT_SIZE: u32 = PAGE_SIZE + 2 * PAGE_BORDER;
+const SLOT_BYTES: u64 = (SLOT_SIZE * SLOT_SIZE * 4) as u64;
+/// Atlas slots per side, so the cache holds the square of it in pages
+const ATLAS_SLOTS: u32 = 32;
+/// Entries of each feedback buffer. Must match gltf.frag.
+const FEEDBACK_SLOTS: usize = 4096;
+/// Pages copied into the atlas per frame at most
+const UPLOADS_PER_FRAME: usize = 16;
+/// Pages requested from the loader and not back yet, at most
+const MAX_PENDING: usize = 64;
+
+/// One page of one mip, in pages from the top left
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct PageId {
+    mip: u32,
+    x: u32,
+    y: u32,
+}
+
+impl PageId {
+    // Layout of a feedback entry: valid bit, 5 bits of mip, 13 bits each of y and x
+    fn from_feedback(entry: u32) -> Option<Self> {
+        (entry & 0x8000_0000 != 0).then_some(Self {
+            mip: (entry >> 26) & 0x1f,
+            y: (entry >> 13) & 0x1fff,
+            x: entry & 0x1fff,
+        })
+    }
+
+    fn parent(self) -> Self {
+        Self { mip: self.mip + 1, x: self.x / 2, y: self.y / 2 }
+    }
+}
+
+/// Whether `texture` is too large to stream and should be virtual. Pages are cut from
+/// RGBA8 texels, so compressed textures always stream.
+pub fn is_virtual(texture: &GltfTexture) -> bool {
+    texture.compressed.is_none() && texture.width.max(texture.height) > MIN_VIRTUAL_SIZE
+}
+
+/// Texels of `page` with its border, cut out of `mips`. Coordinates outside the mip wrap
+/// around, as a REPEAT sampler would.
+fn build_page(mips: &[MipLevel], page: PageId) -> Vec<u8> {
+    let mip = &mips[page.mip as usize];
+    let origin_x = (page.x * PAGE_SIZE) as i64 - PAGE_BORDER as i64;
+    let origin_y = (page.y * PAGE_SIZE) as i64 - PAGE_BORDER as i64;
+    let mut data = Vec::with_capacity(SLOT_BYTES as usize);
+    for y in 0..SLOT_SIZE as i64 {
+        let row = (origin_y + y).rem_euclid(mip.height as i64) as usize * mip.width as usize;
+        for x in 0..SLOT_SIZE as i64 {
+            let texel = (row + (origin_x + x).rem_euclid(mip.width as i64) as usize) * 4;
+            data.extend_from_slice(&mip.data[texel..texel + 4]);
+        }
+    }
+    data
+}
+
+struct Image {
+    image: vk::Image,
+    view: vk::ImageView,
+    allocation: Option<Allocation>,
+    mip_levels: u32,
+}
+
+impl Image {
+    unsafe fn new(
+        renderer: &VulkanRenderer,
+        name: &str,
+        format: vk::Format,
+        (width, height): (u32, u32),
+        mip_levels: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+        let image_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D { width, height, depth: 1 })
+            .mip_levels(mip_levels)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let image = device.create_image(&image_info, None)?;
+        let requirements = device.get_image_memory_requirements(image);
+        let allocation = match renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name,
+            requirements,
+            location: MemoryLocation::GpuOnly,
+            linear: false,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        }) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                device.destroy_image(image, None);
+                return Err(e.into());
+            }
+        };
+        let mut result = Self { image, view: vk::ImageView::null(), allocation: Some(allocation), mip_levels };
+        let bound = result
+            .allocation
+            .as_ref()
+            .map_or(Ok(()), |allocation| device.bind_image_memory(image, allocation.memory(), allocation.offset()));
+        let view_info = vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(result.range());
+        match bound.and_then(|()| device.create_image_view(&view_info, None)) {
+            Ok(view) => result.view = view,
+            Err(e) => {
+                result.destroy(renderer);
+                return Err(e.into());
+            }
+        }
+        Ok(result)
+    }
+
+    fn range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: self.mip_levels,
+            base_array_layer: 0,
+            layer_count: 1,
+        }
+    }
+
+    /// Barrier between fragment shader reads and transfer writes of the whole image
+    unsafe fn barrier(&self, device: &ash::Device, cmd: vk::CommandBuffer, to_transfer: bool) {
+        let (old, new) = (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        let (read, write) = (vk::AccessFlags::SHADER_READ, vk::AccessFlags::TRANSFER_WRITE);
+        let (fragment, transfer) = (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::PipelineStageFlags::TRANSFER);
+        let (old, new, src_access, dst_access, src_stage, dst_stage) = if to_transfer {
+            (old, new, read, write, fragment, transfer)
+        } else {
+            (new, old, write, read, transfer, fragment)
+        };
+        let barrier = vk::ImageMemoryBarrier::default()
+            .old_layout(old)
+            .new_layout(new)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(self.range());
+        device.cmd_pipeline_barrier(
+            cmd,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            std::slice::from_ref(&barrier),
+        );
+    }
+
+    unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        renderer.device.destroy_image_view(self.view, None);
+        renderer.device.destroy_image(self.image, None);
+        if let Some(allocation) = self.allocation.take() {
+            let _ = renderer.allocator.lock().free(allocation);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    page: Option<PageId>,
+    last_used: u64, // Frame the page was last requested in
+}
+
+/// Residency of the pages of the virtual texture, and the loader thread filling it
+struct PageCache {
+    size: (u32, u32),            // Of mip 0, in texels
+    levels: Vec<(u32, u32)>,     // Pages per side of each mip, the page table's mip sizes
+    slots: Vec<Slot>,
+    resident: HashMap<PageId, usize>, // Slot of each page in the atlas
+    pending: HashSet<PageId>,         // Requested from the loader
+    requests: Sender<PageId>,
+    pages: Receiver<(PageId, Vec<u8>)>,
+    frame: u64,
+    uploads: Vec<Vec<usize>>, // Per frame slot: atlas slots staged for `record`, in order
+}
+
+impl PageCache {
+    fn new(texture: &GltfTexture) -> Result<Self, Box<dyn std::error::Error>> {
+        let pages = |texels: u32| texels.div_ceil(PAGE_SIZE).next_power_of_two();
+        let (pages_x, pages_y) = (pages(texture.width), pages(texture.height));
+        let levels = (0..=pages_x.max(pages_y).ilog2())
+            .map(|mip| ((pages_x >> mip).max(1), (pages_y >> mip).max(1)))
+            .collect();
+
+        let mips = Arc::new(texture_streaming::build_mip_chain(texture));
+        let (requests, loader_requests) = mpsc::channel::<PageId>();
+        let (loader_pages, pages) = mpsc::channel();
+        // Stops once the cache is dropped and the request channel with it
+        std::thread::Builder::new().name("virtual texture loader".into()).spawn(move || {
+            for page in loader_requests {
+                if loader_pages.send((page, build_page(&mips, page))).is_err() {
+                    break;
+                }
+            }
+        })?;
+
+        Ok(Self {
+            size: (texture.width, texture.height),
+            levels,
+            slots: vec![Slot::default(); (ATLAS_SLOTS * ATLAS_SLOTS) as usize],
+            resident: HashMap::new(),
+            pending: HashSet::new(),
+            requests,
+            pages,
+            frame: 0,
+            uploads: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+        })
+    }
+
+    fn top_mip(&self) -> u32 {
+        self.levels.len() as u32 - 1
+    }
+
+    fn contains(&self, page: PageId) -> bool {
+        self.levels
+            .get(page.mip as usize)
+            .is_some_and(|&(width, height)| page.x < width && page.y < height)
+    }
+
+    /// Mark `requested` pages and their ancestors used and ask the loader for those missing
+    fn request(&mut self, requested: impl Iterator<Item = PageId>) {
+        let top = self.top_mip();
+        let mut wanted = HashSet::new();
+        // The top page backs every other one, so it is always wanted
+        wanted.insert(PageId { mip: top, x: 0, y: 0 });
+        for mut page in requested.filter(|&page| self.contains(page)) {
+            while wanted.insert(page) && page.mip < top {
+                page = page.parent();
+            }
+        }
+
+        let mut missing = Vec::new();
+        for page in wanted {
+            match self.resident.get(&page) {
+                Some(&slot) => self.slots[slot].last_used = self.frame,
+                None if !self.pending.contains(&page) => missing.push(page),
+                None => {}
+            }
+        }
+        missing.sort_by_key(|page| std::cmp::Reverse(page.mip));
+        for page in missing.into_iter().take(MAX_PENDING.saturating_sub(self.pending.len())) {
+            if self.requests.send(page).is_ok() {
+                self.pending.insert(page);
+            }
+        }
+    }
+
+    /// Slot for a new page: a free one, else the least recently used page not wanted this
+    /// frame. The top page is never evicted.
+    fn allocate_slot(&mut self) -> Option<usize> {
+        let top = self.top_mip();
+        let slot = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.page.is_none_or(|page| page.mip != top && slot.last_used < self.frame))
+            .min_by_key(|(_, slot)| (slot.page.is_some(), slot.last_used))
+            .map(|(index, _)| index)?;
+        if let Some(evicted) = self.slots[slot].page.take() {
+            self.resident.remove(&evicted);
+        }
+        Some(slot)
+    }
+
+    /// Page table contents, mip 0 first: the slot and mip of each page, or of its nearest
+    /// resident ancestor, with alpha 1; zero where nothing is resident
+    fn page_table(&self) -> Vec<[u8; 4]> {
+        let mut levels: Vec<Vec<[u8; 4]>> = Vec::with_capacity(self.levels.len());
+        for (mip, &(width, height)) in self.levels.iter().enumerate().rev() {
+            let parent = levels.last();
+            let mut entries = Vec::with_capacity((width * height) as usize);
+            for y in 0..height {
+                for x in 0..width {
+                    let page = PageId { mip: mip as u32, x, y };
+                    let entry = match self.resident.get(&page) {
+                        Some(&slot) => [(slot as u32 % ATLAS_SLOTS) as u8, (slot as u32 / ATLAS_SLOTS) as u8, mip as u8, 1],
+                        None => parent.map_or([0; 4], |parent| {
+                            let parent_width = self.levels[mip + 1].0;
+                            parent[((y / 2) * parent_width + x / 2) as usize]
+                        }),
+                    };
+                    entries.push(entry);
+                }
+            }
+            levels.push(entries);
+        }
+        levels.into_iter().rev().flatten().collect()
+    }
+}
+
+/// The virtual scene texture: page table, page cache atlas and feedback buffers, bindings
+/// 10 to 12 of the scene descriptor sets. Without a virtual texture they are tiny and
+/// unused.
+pub struct VirtualTexture {
+    atlas: Image,
+    page_table: Image,
+    atlas_sampler: vk::Sampler,
+    page_table_sampler: vk::Sampler,
+    feedback_buffers: Vec<vk::Buffer>, // Per frame slot, written by the main view
+    staging_buffers: Vec<vk::Buffer>,  // Per frame slot: pages, then the page table
+    allocations: Vec<Allocation>,      // Feedback buffers, then staging buffers
+    cache: Option<PageCache>,
+}
+
+impl VirtualTexture {
+    /// Make `texture` virtual, or create placeholder resources if it is `None`
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        texture: Option<&GltfTexture>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let cache = texture.map(PageCache::new).transpose()?;
+        let (atlas_size, table_size, table_levels, table_bytes) = match &cache {
+            Some(cache) => {
+                let table_entries: u32 = cache.levels.iter().map(|(width, height)| width * height).sum();
+                (ATLAS_SLOTS * SLOT_SIZE, cache.levels[0], cache.levels.len() as u32, table_entries as u64 * 4)
+            }
+            None => (1, (1, 1), 1, 0),
+        };
+
+        let atlas = Image::new(
+            renderer,
+            "virtual_texture_atlas",
+            vk::Format::R8G8B8A8_SRGB,
+            (atlas_size, atlas_size),
+            1,
+        )?;
+        let mut texture = Self {
+            atlas,
+            page_table: Image {
+                image: vk::Image::null(),
+                view: vk::ImageView::null(),
+                allocation: None,
+                mip_levels: table_levels,
+            },
+            atlas_sampler: renderer.sampler(SamplerDesc::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE))?,
+            page_table_sampler: renderer.sampler(SamplerDesc::nearest(vk::SamplerAddressMode::CLAMP_TO_EDGE))?,
+            feedback_buffers: Vec::new(),
+            staging_buffers: Vec::new(),
+            allocations: Vec::new(),
+            cache,
+        };
+        if let Err(e) = texture.create_resources(renderer, table_size, table_levels, table_bytes) {
+            texture.destroy(renderer);
+            return Err(e);
+        }
+        if let Some(cache) = &texture.cache {
+            println!(
+                "✓ Virtual texture {}x{}: {} mips of {}x{} pages, {} MB page cache",
+                cache.size.0,
+                cache.size.1,
+                cache.levels.len(),
+                cache.levels[0].0,
+                cache.levels[0].1,
+                cache.slots.len() as u64 * SLOT_BYTES / (1024 * 1024)
+            );
+        }
+        Ok(texture)
+    }
+
+    unsafe fn create_resources(
+        &mut self,
+        renderer: &VulkanRenderer,
+        table_size: (u32, u32),
+        table_levels: u32,
+        table_bytes: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.page_table =
+            Image::new(renderer, "virtual_texture_page_table", vk::Format::R8G8B8A8_UINT, table_size, table_levels)?;
+
+        let feedback_size = (FEEDBACK_SLOTS * std::mem::size_of::<u32>()) as u64;
+        let staging_size = UPLOADS_PER_FRAME as u64 * SLOT_BYTES + table_bytes;
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            // Zeroed, so nothing is requested before the first frame
+            let buffer = self.create_buffer(renderer, "virtual_texture_feedback", feedback_size, vk::BufferUsageFlags::STORAGE_BUFFER, MemoryLocation::GpuToCpu)?;
+            self.feedback_buffers.push(buffer);
+            if let Some(mapped) = self.allocations.last().and_then(Allocation::mapped_ptr) {
+                std::ptr::write_bytes(mapped.as_ptr() as *mut u8, 0, feedback_size as usize);
+            }
+        }
+        if self.cache.is_some() {
+            for _ in 0..MAX_FRAMES_IN_FLIGHT {
+                let buffer = self.create_buffer(renderer, "virtual_texture_staging", staging_size, vk::BufferUsageFlags::TRANSFER_SRC, MemoryLocation::CpuToGpu)?;
+                self.staging_buffers.push(buffer);
+            }
+        }
+
+        // Nothing resident: a zero page table, which gltf.frag shows as grey
+        let (atlas, page_table) = (self.atlas.image, self.page_table.image);
+        let (atlas_range, table_range) = (self.atlas.range(), self.page_table.range());
+        lightmap::submit_once(renderer, |device, cmd| {
+            let to_transfer = [(atlas, atlas_range), (page_table, table_range)].map(|(image, range)| {
+                vk::ImageMemoryBarrier::default()
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(image)
+                    .subresource_range(range)
+            });
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_transfer,
+            );
+            device.cmd_clear_color_image(
+                cmd,
+                atlas,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue { float32: [0.5, 0.5, 0.5, 1.0] },
+                &[atlas_range],
+            );
+            device.cmd_clear_color_image(
+                cmd,
+                page_table,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue { uint32: [0; 4] },
+                &[table_range],
+            );
+            self.atlas.barrier(device, cmd, false);
+            self.page_table.barrier(device, cmd, false);
+        })
+    }
+
+    unsafe fn create_buffer(
+        &mut self,
+        renderer: &VulkanRenderer,
+        name: &str,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> Result<vk::Buffer, Box<dyn std::error::Error>> {
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(size.max(4))
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = renderer.device.create_buffer(&buffer_info, None)?;
+        let requirements = renderer.device.get_buffer_memory_requirements(buffer);
+        let allocation = match renderer.allocator.lock().allocate(&AllocationCreateDesc {
+            name,
+            requirements,
+            location,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        }) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                renderer.device.destroy_buffer(buffer, None);
+                return Err(e.into());
+            }
+        };
+        let bound = renderer.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset());
+        self.allocations.push(allocation);
+        if let Err(e) = bound {
+            renderer.device.destroy_buffer(buffer, None);
+            return Err(e.into());
+        }
+        Ok(buffer)
+    }
+
+    /// `virtualTextureParams` of the glTF uniform buffer: in use, write feedback, size
+    pub fn uniforms(&self, feedback: bool) -> [f32; 4] {
+        match &self.cache {
+            Some(cache) => [1.0, if feedback { 1.0 } else { 0.0 }, cache.size.0 as f32, cache.size.1 as f32],
+            None => [0.0; 4],
+        }
+    }
+
+    /// Bindings 10 (page table), 11 (atlas) and 12 (feedback) of a set used in `frame_index`
+    pub unsafe fn write_descriptors(&self, device: &ash::Device, set: vk::DescriptorSet, frame_index: usize) {
+        let page_table_info = vk::DescriptorImageInfo {
+            sampler: self.page_table_sampler,
+            image_view: self.page_table.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let atlas_info = vk::DescriptorImageInfo {
+            sampler: self.atlas_sampler,
+            image_view: self.atlas.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let feedback_info = vk::DescriptorBufferInfo {
+            buffer: self.feedback_buffers[frame_index],
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        };
+        let writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(10)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(std::slice::from_ref(&page_table_info)),
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(11)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(std::slice::from_ref(&atlas_info)),
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(12)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(std::slice::from_ref(&feedback_info)),
+        ];
+        device.update_descriptor_sets(&writes, &[]);
+    }
+
+    /// Read `frame_index`'s feedback, request pages and stage those loaded since the last
+    /// frame for `record`. Call once the slot's fence has been waited on.
+    pub unsafe fn begin_frame(&mut self, frame_index: usize) {
+        let Some(cache) = &mut self.cache else {
+            return;
+        };
+        cache.frame += 1;
+        if let Some(mapped) = self.allocations[frame_index].mapped_ptr() {
+            let feedback = std::slice::from_raw_parts_mut(mapped.as_ptr() as *mut u32, FEEDBACK_SLOTS);
+            cache.request(feedback.iter().filter_map(|&entry| PageId::from_feedback(entry)));
+            feedback.fill(0);
+        }
+
+        let staging = &self.allocations[MAX_FRAMES_IN_FLIGHT + frame_index];
+        let Some(staging) = staging.mapped_ptr().map(|mapped| mapped.as_ptr() as *mut u8) else {
+            return;
+        };
+        let mut uploads = std::mem::take(&mut cache.uploads[frame_index]);
+        uploads.clear();
+        while uploads.len() < UPLOADS_PER_FRAME {
+            let Ok((page, texels)) = cache.pages.try_recv() else {
+                break;
+            };
+            cache.pending.remove(&page);
+            let Some(slot) = cache.allocate_slot() else {
+                continue; // Every slot is in use; it will be requested again
+            };
+            std::ptr::copy_nonoverlapping(texels.as_ptr(), staging.add(uploads.len() * SLOT_BYTES as usize), texels.len());
+            cache.slots[slot] = Slot { page: Some(page), last_used: cache.frame };
+            cache.resident.insert(page, slot);
+            uploads.push(slot);
+        }
+        // Evictions change the table too, so it is rebuilt whenever a slot changed
+        if !uploads.is_empty() {
+            let table = cache.page_table();
+            let offset = UPLOADS_PER_FRAME * SLOT_BYTES as usize;
+            std::ptr::copy_nonoverlapping(table.as_ptr() as *const u8, staging.add(offset), table.len() * 4);
+        }
+        cache.uploads[frame_index] = uploads;
+    }
+
+    /// Copy the pages and page table `begin_frame` staged into the atlas and page table.
+    /// Fragment shader reads of earlier frames finish first.
+    pub unsafe fn record(&self, device: &ash::Device, cmd: vk::CommandBuffer, frame_index: usize) {
+        let Some(cache) = &self.cache else {
+            return;
+        };
+        let uploads = &cache.uploads[frame_index];
+        if uploads.is_empty() {
+            return;
+        }
+        let staging = self.staging_buffers[frame_index];
+        let layers = |mip_level| vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        let regions: Vec<_> = uploads
+            .iter()
+            .enumerate()
+            .map(|(i, &slot)| vk::BufferImageCopy {
+                buffer_offset: i as u64 * SLOT_BYTES,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: layers(0),
+                image_offset: vk::Offset3D {
+                    x: ((slot as u32 % ATLAS_SLOTS) * SLOT_SIZE) as i32,
+                    y: ((slot as u32 / ATLAS_SLOTS) * SLOT_SIZE) as i32,
+                    z: 0,
+                },
+                image_extent: vk::Extent3D { width: SLOT_SIZE, height: SLOT_SIZE, depth: 1 },
+            })
+            .collect();
+        self.atlas.barrier(device, cmd, true);
+        device.cmd_copy_buffer_to_image(cmd, staging, self.atlas.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions);
+        self.atlas.barrier(device, cmd, false);
+
+        // The page table follows the pages in the staging buffer
+        {
+            let mut offset = UPLOADS_PER_FRAME as u64 * SLOT_BYTES;
+            let regions: Vec<_> = cache
+                .levels
+                .iter()
+                .enumerate()
+                .map(|(mip, &(width, height))| {
+                    let region = vk::BufferImageCopy {
+                        buffer_offset: offset,
+                        buffer_row_length: 0,
+                        buffer_image_height: 0,
+                        image_subresource: layers(mip as u32),
+                        image_offset: vk::Offset3D::default(),
+                        image_extent: vk::Extent3D { width, height, depth: 1 },
+                    };
+                    offset += (width * height * 4) as u64;
+                    region
+                })
+                .collect();
+            self.page_table.barrier(device, cmd, true);
+            device.cmd_copy_buffer_to_image(
+                cmd,
+                staging,
+                self.page_table.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+            self.page_table.barrier(device, cmd, false);
+        }
+    }
+
+    /// One line for the memory panel
+    pub fn summary(&self) -> Option<String> {
+        let cache = self.cache.as_ref()?;
+        Some(format!(
+            "Virtual texture {}x{}: {} of {} pages resident, {} loading",
+            cache.size.0,
+            cache.size.1,
+            cache.resident.len(),
+            cache.slots.len(),
+            cache.pending.len()
+        ))
+    }
+
+    /// The device must be idle. Stops the loader thread.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        self.cache = None;
+        self.atlas.destroy(renderer);
+        self.page_table.destroy(renderer);
+        for buffer in self.feedback_buffers.drain(..).chain(self.staging_buffers.drain(..)) {
+            renderer.device.destroy_buffer(buffer, None);
+        }
+        for allocation in self.allocations.drain(..) {
+            let _ = renderer.allocator.lock().free(allocation);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Entity visibility
+//!
+//! Every entity with a `Transform` has a `Visibility` of its own, and an entity hidden this
+//! way hides all of its descendants too. Every frame `propagate_visibility` walks down the
+//! hierarchy from the root entities like `propagate_transforms` does and writes whether
+//! each entity is actually shown into its `InheritedVisibility`, which is what rendering
+//! reads.
+
+use bevy_ecs::prelude::*;
+
+use crate::hierarchy::{Children, Parent};
+
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[require(InheritedVisibility)]
+pub enum Visibility {
+    #[default]
+    Visible,
+    Hidden,
+}
+
+impl Visibility {
+    pub fn new(visible: bool) -> Self {
+        if visible { Visibility::Visible } else { Visibility::Hidden }
+    }
+
+    pub fn is_visible(self) -> bool {
+        self == Visibility::Visible
+    }
+}
+
+/// Whether an entity is shown: visible itself and all of its ancestors visible. Written by
+/// `propagate_visibility`.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InheritedVisibility(pub bool);
+
+impl Default for InheritedVisibility {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// Compute every `InheritedVisibility` from the hierarchy, parents before their children
+pub fn propagate_visibility(
+    roots: Query<Entity, (With<Visibility>, Without<Parent>)>,
+    nodes: Query<(&Visibility, Option<&Children>)>,
+    mut inherited: Query<&mut InheritedVisibility>,
+) {
+    let mut stack: Vec<(Entity, bool)> = roots.iter().map(|root| (root, true)).collect();
+    while let Some((entity, parent_visible)) = stack.pop() {
+        let Ok((visibility, children)) = nodes.get(entity) else {
+            continue;
+        };
+        let visible = parent_visible && visibility.is_visible();
+        if let Ok(mut shown) = inherited.get_mut(entity) {
+            shown.0 = visible;
+        }
+        if let Some(children) = children {
+            stack.extend(children.0.iter().map(|&child| (child, visible)));
+        }
+    }
+}
@@ -0,0 +1,432 @@
+//! Weather
+//!
+//! Rain and snow as a second GPU particle system: drops and flakes fall in a column around
+//! the camera, pushed by the wind. The simulation runs on the graphics queue right after
+//! the scene pass, reading the depth the pass kept, so particles that pass behind the
+//! scene have landed on it: they stop, fade out and fall again from the top. Off screen
+//! only the ground plane stops them. The scene pass draws the state of the step before.
+//!
+//! Surfaces respond through the `Weather` resource: rain soaks them (darker and glossier)
+//! and snow settles on whatever faces up, building up while it falls and going away after.
+
+use ash::vk;
+use bevy_ecs::prelude::*;
+use gpu_allocator::vulkan::Allocation;
+
+use crate::command_encoder::{PipelineBinding, RenderPassEncoder};
+use crate::compute::{self, Access, ComputePipeline, DescriptorWriter};
+use crate::gltf_renderer::GltfRenderer;
+use crate::particles::{self, STATE_BUFFER_COUNT};
+use crate::pipeline_builder::{BlendMode, DepthMode, GraphicsPipelineBuilder};
+use crate::renderer::VulkanRenderer;
+use crate::sampler_cache::SamplerDesc;
+use crate::shader_compiler::load_shader;
+use crate::shader_reflection::{self, ShaderReflection};
+
+pub const WEATHER_PARTICLE_COUNT: u32 = 16384;
+/// Half the width of the column around the camera the weather falls in, in meters
+const COLUMN_RADIUS: f32 = 12.0;
+
+/// What falls from the sky
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precipitation {
+    None,
+    Rain,
+    Snow,
+}
+
+/// Starting points for the weather panel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeatherPreset {
+    Clear,
+    Drizzle,
+    Rainstorm,
+    Snowfall,
+    Blizzard,
+}
+
+impl WeatherPreset {
+    pub const ALL: [Self; 5] = [Self::Clear, Self::Drizzle, Self::Rainstorm, Self::Snowfall, Self::Blizzard];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Clear => "Clear",
+            Self::Drizzle => "Drizzle",
+            Self::Rainstorm => "Rainstorm",
+            Self::Snowfall => "Snowfall",
+            Self::Blizzard => "Blizzard",
+        }
+    }
+}
+
+/// The weather and what it has done to surfaces so far
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct Weather {
+    pub precipitation: Precipitation,
+    pub intensity: f32,      // 0 to 1, the share of particles falling
+    pub wind_direction: f32, // Degrees about +Y, 0 blowing towards +X
+    pub wind_speed: f32,     // m/s
+    pub wetness: f32,        // 0 to 1, how soaked surfaces are
+    pub snow_cover: f32,     // 0 to 1, how much snow lies on upward surfaces
+    pub accumulation: f32,   // How fast surfaces soak or get covered at full intensity, per second
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self::preset(WeatherPreset::Clear)
+    }
+}
+
+impl Weather {
+    pub fn preset(preset: WeatherPreset) -> Self {
+        let weather = |precipitation, intensity, wind_speed| Self {
+            precipitation,
+            intensity,
+            wind_direction: 30.0,
+            wind_speed,
+            wetness: 0.0,
+            snow_cover: 0.0,
+            accumulation: 0.05,
+        };
+        match preset {
+            WeatherPreset::Clear => weather(Precipitation::None, 0.0, 1.0),
+            WeatherPreset::Drizzle => weather(Precipitation::Rain, 0.25, 1.0),
+            WeatherPreset::Rainstorm => weather(Precipitation::Rain, 1.0, 6.0),
+            WeatherPreset::Snowfall => weather(Precipitation::Snow, 0.5, 0.5),
+            WeatherPreset::Blizzard => weather(Precipitation::Snow, 1.0, 9.0),
+        }
+    }
+
+    /// Switch to `preset`'s sky, keeping what surfaces have accumulated
+    pub fn apply_preset(&mut self, preset: WeatherPreset) {
+        *self = Self { wetness: self.wetness, snow_cover: self.snow_cover, ..Self::preset(preset) };
+    }
+
+    /// Soak or cover surfaces while it rains or snows, dry and melt slowly otherwise
+    pub fn accumulate(&mut self, delta_time: f32) {
+        let rate = self.accumulation * delta_time;
+        let (rain, snow) = match self.precipitation {
+            Precipitation::Rain => (self.intensity, 0.0),
+            Precipitation::Snow => (0.0, self.intensity),
+            Precipitation::None => (0.0, 0.0),
+        };
+        let approach = |value: f32, target: f32, rate: f32| {
+            if value < target {
+                (value + rate).min(target)
+            } else {
+                (value - rate).max(target)
+            }
+        };
+        // Melting snow leaves surfaces wet
+        let melted = if snow == 0.0 { (self.snow_cover.min(rate * 0.25)) * 2.0 } else { 0.0 };
+        self.snow_cover = approach(self.snow_cover, snow, if snow > 0.0 { rate * snow } else { rate * 0.25 });
+        self.wetness = approach(self.wetness, rain, if rain > 0.0 { rate * 2.0 * rain } else { rate * 0.2 });
+        self.wetness = (self.wetness + melted).min(1.0);
+    }
+
+    /// Wind velocity in the XZ plane, m/s
+    pub fn wind(&self) -> glam::Vec2 {
+        let angle = self.wind_direction.to_radians();
+        glam::Vec2::new(angle.cos(), angle.sin()) * self.wind_speed
+    }
+
+    /// Uniforms of gltf.frag: x = wetness, y = snow cover
+    pub fn surface_uniforms(&self) -> [f32; 4] {
+        [self.wetness, self.snow_cover, 0.0, 0.0]
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct SimulatePushConstants {
+    view_proj: [[f32; 4]; 4],
+    camera: [f32; 4], // xyz = camera position, w = delta time
+    wind: [f32; 4],   // xz = wind velocity, y = fall speed, w = time
+    params: [f32; 4], // x = active particles, y = 1 for snow, z = fade after landing, w = column radius
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct DrawPushConstants {
+    view_proj: [[f32; 4]; 4],
+    camera: [f32; 4], // xyz = camera position, w = 1 for snow
+    params: [f32; 4], // x = particle size, y = streak length per m/s, z = fade after landing
+}
+
+/// Fall speed, particle size, streak length and fade time of `precipitation`
+fn look(precipitation: Precipitation) -> (f32, f32, f32, f32) {
+    match precipitation {
+        Precipitation::Snow => (1.2, 0.025, 0.0, 2.5),
+        _ => (9.0, 0.006, 0.035, 0.15),
+    }
+}
+
+/// The rain and snow particles
+pub struct WeatherParticles {
+    simulate_pipeline: ComputePipeline,
+    simulate_sets: Vec<vk::DescriptorSet>, // [i] reads buffer i-1, writes buffer i; scene depth rebound every step
+    depth_sampler: vk::Sampler,
+
+    draw_pipelines: Vec<(vk::RenderPass, vk::Pipeline)>, // One per pass the particles can be drawn in
+    draw_pipeline_layout: vk::PipelineLayout,
+    draw_descriptor_set_layout: vk::DescriptorSetLayout,
+    draw_descriptor_pool: vk::DescriptorPool,
+    draw_sets: Vec<vk::DescriptorSet>, // [i] reads buffer i
+
+    state_buffers: Vec<vk::Buffer>,
+    state_allocations: Vec<Option<Allocation>>,
+
+    frame: u64,
+    time: f32,
+    precipitation: Precipitation, // Of the last step, for drawing
+}
+
+impl WeatherParticles {
+    /// `render_pass` is the scene pass the particles are drawn in (color + depth)
+    pub unsafe fn new(renderer: &VulkanRenderer, render_pass: vk::RenderPass) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = &renderer.device;
+
+        // Inactive until the first step spreads them through the column
+        let initial = vec![
+            particles::Particle { position: [0.0, 0.0, 0.0, -1.0], velocity: [0.0, 0.0, 0.0, -1.0] };
+            WEATHER_PARTICLE_COUNT as usize
+        ];
+        let (state_buffers, state_allocations) = particles::create_state_buffers(
+            renderer,
+            "Weather State",
+            &initial,
+            &[renderer.graphics_queue_family_index],
+        )?;
+
+        // Simulation
+        let depth_sampler = renderer.sampler(SamplerDesc::nearest(vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        let simulate_code = load_shader("weather.comp", include_bytes!("../shaders/weather.comp.spv"));
+        let simulate_pipeline = ComputePipeline::new(
+            renderer,
+            &simulate_code,
+            std::mem::size_of::<SimulatePushConstants>() as u32,
+            STATE_BUFFER_COUNT as u32,
+        )?;
+        let mut simulate_sets = Vec::new();
+        for i in 0..STATE_BUFFER_COUNT {
+            let set = simulate_pipeline.allocate_descriptor_set(device)?;
+            DescriptorWriter::new()
+                .storage_buffer(0, state_buffers[(i + STATE_BUFFER_COUNT - 1) % STATE_BUFFER_COUNT])
+                .storage_buffer(1, state_buffers[i])
+                .write(device, set);
+            simulate_sets.push(set);
+        }
+
+        // Drawing
+        let vert_code = load_shader("weather.vert", include_bytes!("../shaders/weather.vert.spv"));
+        let frag_code = load_shader("weather.frag", include_bytes!("../shaders/weather.frag.spv"));
+        let vert_reflection = ShaderReflection::reflect(&vert_code)?;
+        let frag_reflection = ShaderReflection::reflect(&frag_code)?;
+        let reflections = [&vert_reflection, &frag_reflection];
+
+        let bindings = shader_reflection::set_layout_bindings(&reflections, 0)?;
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        let draw_descriptor_set_layout = device.create_descriptor_set_layout(&layout_info, None)?;
+
+        let push_constant_ranges: Vec<_> = shader_reflection::push_constant_range(
+            &reflections,
+            std::mem::size_of::<DrawPushConstants>() as u32,
+        )?
+        .into_iter()
+        .collect();
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(std::slice::from_ref(&draw_descriptor_set_layout))
+            .push_constant_ranges(&push_constant_ranges);
+        let draw_pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None)?;
+
+        let draw_pipeline = create_draw_pipeline(device, render_pass, draw_pipeline_layout, &vert_code, &frag_code)?;
+
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: STATE_BUFFER_COUNT as u32,
+        };
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(std::slice::from_ref(&pool_size))
+            .max_sets(STATE_BUFFER_COUNT as u32);
+        let draw_descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
+
+        let layouts = vec![draw_descriptor_set_layout; STATE_BUFFER_COUNT];
+        let alloc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(draw_descriptor_pool)
+            .set_layouts(&layouts);
+        let draw_sets = device.allocate_descriptor_sets(&alloc_info)?;
+        for (set, buffer) in draw_sets.iter().zip(&state_buffers) {
+            DescriptorWriter::new().storage_buffer(0, *buffer).write(device, *set);
+        }
+
+        Ok(Self {
+            simulate_pipeline,
+            simulate_sets,
+            depth_sampler,
+            draw_pipelines: vec![(render_pass, draw_pipeline)],
+            draw_pipeline_layout,
+            draw_descriptor_set_layout,
+            draw_descriptor_pool,
+            draw_sets,
+            state_buffers,
+            state_allocations,
+            frame: 0,
+            time: 0.0,
+            precipitation: Precipitation::None,
+        })
+    }
+
+    /// Record one step of `weather` around `camera_position`. Record on the graphics queue
+    /// after the scene pass that rendered swapchain image `image_index` with `view_proj`,
+    /// outside any render pass.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn simulate(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        gltf_renderer: &GltfRenderer,
+        image_index: u32,
+        weather: &Weather,
+        camera_position: glam::Vec3,
+        view_proj: glam::Mat4,
+        delta_time: f32,
+    ) {
+        self.precipitation = weather.precipitation;
+        if weather.precipitation == Precipitation::None && self.frame == 0 {
+            return;
+        }
+        self.frame += 1;
+        self.time += delta_time;
+        let write_index = (self.frame % STATE_BUFFER_COUNT as u64) as usize;
+
+        // Written STATE_BUFFER_COUNT steps ago, so no frame in flight still uses the set
+        let depth = gltf_renderer.depth_targets[image_index as usize].depth();
+        DescriptorWriter::new()
+            .sampled_image(2, depth.view, self.depth_sampler)
+            .write(device, self.simulate_sets[write_index]);
+
+        // The scene pass wrote the depth; the previous step's output is this step's input
+        compute::memory_barrier(
+            device,
+            command_buffer,
+            Access {
+                stage: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS | vk::PipelineStageFlags::COMPUTE_SHADER,
+                access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE | vk::AccessFlags::SHADER_WRITE,
+            },
+            Access::COMPUTE_READ,
+        );
+
+        let (fall_speed, _, _, fade) = look(weather.precipitation);
+        let active = match weather.precipitation {
+            Precipitation::None => 0.0,
+            _ => weather.intensity.clamp(0.0, 1.0) * WEATHER_PARTICLE_COUNT as f32,
+        };
+        let wind = weather.wind();
+        let push_constants = SimulatePushConstants {
+            view_proj: view_proj.to_cols_array_2d(),
+            camera: camera_position.extend(delta_time.min(0.1)).to_array(),
+            wind: [wind.x, fall_speed, wind.y, self.time],
+            params: [
+                active,
+                if weather.precipitation == Precipitation::Snow { 1.0 } else { 0.0 },
+                fade,
+                COLUMN_RADIUS,
+            ],
+        };
+        let bytes = std::slice::from_raw_parts(
+            &push_constants as *const _ as *const u8,
+            std::mem::size_of::<SimulatePushConstants>(),
+        );
+        self.simulate_pipeline.dispatch_threads(
+            device,
+            command_buffer,
+            self.simulate_sets[write_index],
+            bytes,
+            [WEATHER_PARTICLE_COUNT, 1, 1],
+        );
+
+        // Drawn by the next frame's scene pass
+        compute::memory_barrier(device, command_buffer, Access::COMPUTE_WRITE, Access::VERTEX_SHADER_READ);
+    }
+
+    /// Make the particles drawable in `render_pass` as well, such as the half-resolution
+    /// transparency pass; `draw` uses the pipeline of whichever pass it records into
+    pub unsafe fn add_render_pass(
+        &mut self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let vert_code = load_shader("weather.vert", include_bytes!("../shaders/weather.vert.spv"));
+        let frag_code = load_shader("weather.frag", include_bytes!("../shaders/weather.frag.spv"));
+        let pipeline = create_draw_pipeline(device, render_pass, self.draw_pipeline_layout, &vert_code, &frag_code)?;
+        self.draw_pipelines.push((render_pass, pipeline));
+        Ok(())
+    }
+
+    /// Draw inside the scene render pass (or one added with `add_render_pass`), using the
+    /// state of the latest `simulate`
+    pub fn draw(&self, pass: &mut RenderPassEncoder, extent: vk::Extent2D, camera_position: glam::Vec3, view_proj: glam::Mat4) {
+        if self.frame == 0 {
+            return;
+        }
+        let read_index = (self.frame % STATE_BUFFER_COUNT as u64) as usize;
+        let Some(&(render_pass, pipeline)) = self.draw_pipelines.iter().find(|(rp, _)| *rp == pass.render_pass()) else {
+            return;
+        };
+
+        pass.bind_pipeline(PipelineBinding {
+            pipeline,
+            layout: self.draw_pipeline_layout,
+            render_pass,
+            push_constant_size: std::mem::size_of::<DrawPushConstants>() as u32,
+        });
+        pass.set_full_viewport(extent);
+        pass.bind_descriptor_set(0, self.draw_sets[read_index]);
+
+        let (_, size, streak, fade) = look(self.precipitation);
+        let snow = if self.precipitation == Precipitation::Snow { 1.0 } else { 0.0 };
+        let push_constants = DrawPushConstants {
+            view_proj: view_proj.to_cols_array_2d(),
+            camera: camera_position.extend(snow).to_array(),
+            params: [size, streak, fade, 0.0],
+        };
+        pass.push_constants(vk::ShaderStageFlags::VERTEX, 0, &push_constants);
+
+        pass.draw(WEATHER_PARTICLE_COUNT * 6, 1);
+    }
+
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        let device = &renderer.device;
+        self.simulate_pipeline.destroy(renderer);
+
+        for (_, pipeline) in self.draw_pipelines.drain(..) {
+            device.destroy_pipeline(pipeline, None);
+        }
+        device.destroy_pipeline_layout(self.draw_pipeline_layout, None);
+        device.destroy_descriptor_pool(self.draw_descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.draw_descriptor_set_layout, None);
+
+        for buffer in self.state_buffers.drain(..) {
+            device.destroy_buffer(buffer, None);
+        }
+        for alloc in self.state_allocations.drain(..).flatten() {
+            let _ = renderer.allocator.lock().free(alloc);
+        }
+    }
+}
+
+unsafe fn create_draw_pipeline(
+    device: &ash::Device,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+    vert_code: &[u32],
+    frag_code: &[u32],
+) -> Result<vk::Pipeline, Box<dyn std::error::Error>> {
+    // Like the fountain: test against the scene without writing depth, added on
+    GraphicsPipelineBuilder::new(pipeline_layout, render_pass)
+        .shader(vk::ShaderStageFlags::VERTEX, vert_code)
+        .shader(vk::ShaderStageFlags::FRAGMENT, frag_code)
+        .blend(BlendMode::Additive)
+        .depth(DepthMode::ReadOnly(vk::CompareOp::LESS))
+        .build(device)
+}
@@ -0,0 +1,259 @@
+//! Presentation target for additional windows.
+//!
+//! Each secondary window gets its own surface, swapchain, depth buffers and frame sync,
+//! while sharing the device, allocator and command pool of the main `VulkanRenderer`.
+
+use ash::vk;
+use winit::raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+use crate::render_target::{self, RenderTarget, RenderTargetDesc};
+use crate::renderer::{VulkanRenderer, MAX_FRAMES_IN_FLIGHT};
+use crate::swapchain::SurfaceSupport;
+
+const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+pub struct WindowSurface {
+    pub surface: vk::SurfaceKHR,
+    pub swapchain: vk::SwapchainKHR,
+    pub swapchain_images: Vec<vk::Image>,
+    pub swapchain_image_views: Vec<vk::ImageView>,
+    pub swapchain_format: vk::Format,
+    pub swapchain_extent: vk::Extent2D,
+    pub depth_targets: Vec<RenderTarget>, // One per swapchain image
+    /// Render pass the framebuffers are built for (owned by the caller, not destroyed here)
+    pub render_pass: vk::RenderPass,
+    pub framebuffers: Vec<vk::Framebuffer>,
+    pub command_buffers: Vec<vk::CommandBuffer>,
+    pub image_available_semaphores: Vec<vk::Semaphore>,
+    pub render_finished_semaphores: Vec<vk::Semaphore>,
+    pub in_flight_fences: Vec<vk::Fence>,
+    pub images_in_flight: Vec<vk::Fence>,
+    pub current_frame: usize,
+    pub framebuffer_resized: bool,
+}
+
+impl WindowSurface {
+    /// Create a surface + swapchain for `window`. `render_pass` must use the main swapchain's
+    /// color format and a D32 depth attachment (e.g. the glTF scene pass).
+    ///
+    /// # Safety
+    ///
+    /// `window` must outlive the surface.
+    pub unsafe fn new(
+        renderer: &VulkanRenderer,
+        window: &winit::window::Window,
+        render_pass: vk::RenderPass,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let surface = ash_window::create_surface(
+            &renderer.entry,
+            &renderer.instance,
+            window.display_handle()?.as_raw(),
+            window.window_handle()?.as_raw(),
+            None,
+        )?;
+
+        let supported = renderer.surface_fn.get_physical_device_surface_support(
+            renderer.physical_device,
+            renderer.present_queue_family_index,
+            surface,
+        )?;
+        if !supported {
+            renderer.surface_fn.destroy_surface(surface, None);
+            return Err("Present queue cannot present to the new window".into());
+        }
+
+        // Pipelines are shared with the main window, so the swapchain format has to match.
+        let support = SurfaceSupport::query(&renderer.surface_fn, renderer.physical_device, surface)?;
+        if support.choose_format(Some(renderer.swapchain_format)).is_none() {
+            renderer.surface_fn.destroy_surface(surface, None);
+            return Err(format!("Window surface does not support {:?}", renderer.swapchain_format).into());
+        }
+
+        // Command buffers + sync objects
+        let alloc_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(renderer.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(MAX_FRAMES_IN_FLIGHT as u32);
+        let command_buffers = renderer.device.allocate_command_buffers(&alloc_info)?;
+
+        let semaphore_info = vk::SemaphoreCreateInfo::default();
+        let fence_info = vk::FenceCreateInfo::default()
+            .flags(vk::FenceCreateFlags::SIGNALED);
+
+        let mut image_available_semaphores = Vec::new();
+        let mut render_finished_semaphores = Vec::new();
+        let mut in_flight_fences = Vec::new();
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            image_available_semaphores.push(renderer.device.create_semaphore(&semaphore_info, None)?);
+            render_finished_semaphores.push(renderer.device.create_semaphore(&semaphore_info, None)?);
+            in_flight_fences.push(renderer.device.create_fence(&fence_info, None)?);
+        }
+
+        let size = window.inner_size();
+        let mut window_surface = Self {
+            surface,
+            swapchain: vk::SwapchainKHR::null(),
+            swapchain_images: Vec::new(),
+            swapchain_image_views: Vec::new(),
+            swapchain_format: renderer.swapchain_format,
+            swapchain_extent: vk::Extent2D { width: size.width, height: size.height },
+            depth_targets: Vec::new(),
+            render_pass,
+            framebuffers: Vec::new(),
+            command_buffers,
+            image_available_semaphores,
+            render_finished_semaphores,
+            in_flight_fences,
+            images_in_flight: Vec::new(),
+            current_frame: 0,
+            framebuffer_resized: false,
+        };
+        window_surface.create_swapchain_resources(renderer, size.width, size.height)?;
+
+        Ok(window_surface)
+    }
+
+    unsafe fn create_swapchain_resources(
+        &mut self,
+        renderer: &VulkanRenderer,
+        width: u32,
+        height: u32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let support = SurfaceSupport::query(&renderer.surface_fn, renderer.physical_device, self.surface)?;
+        let Some(extent) = support.extent(width, height) else {
+            // Minimized: try again once the window has an area
+            self.framebuffer_resized = true;
+            return Ok(());
+        };
+        let surface_format = support
+            .choose_format(Some(self.swapchain_format))
+            .ok_or_else(|| format!("Window surface no longer supports {:?}", self.swapchain_format))?;
+
+        // Secondary windows don't need uncapped FPS; FIFO is always available.
+        let old_swapchain = self.swapchain;
+        let queue_family_indices = renderer.swapchain_queue_family_indices();
+        let swapchain_create_info = support.swapchain_create_info(
+            self.surface,
+            surface_format,
+            extent,
+            vk::PresentModeKHR::FIFO,
+            &queue_family_indices,
+            old_swapchain,
+        );
+
+        self.swapchain = renderer.swapchain_fn.create_swapchain(&swapchain_create_info, None)?;
+        if old_swapchain != vk::SwapchainKHR::null() {
+            renderer.swapchain_fn.destroy_swapchain(old_swapchain, None);
+        }
+
+        self.swapchain_images = renderer.swapchain_fn.get_swapchain_images(self.swapchain)?;
+        self.swapchain_extent = extent;
+
+        render_target::resize_targets(
+            renderer,
+            &mut self.depth_targets,
+            self.swapchain_images.len(),
+            "window_depth_buffer",
+            RenderTargetDesc::depth(DEPTH_FORMAT, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT),
+            extent,
+        )?;
+
+        for (&image, depth_target) in self.swapchain_images.iter().zip(&self.depth_targets) {
+            let view_info = vk::ImageViewCreateInfo::default()
+                .image(image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(self.swapchain_format)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                });
+            let color_view = renderer.device.create_image_view(&view_info, None)?;
+
+            let attachments = [color_view, depth_target.depth().view];
+            let framebuffer_info = vk::FramebufferCreateInfo::default()
+                .render_pass(self.render_pass)
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            let framebuffer = renderer.device.create_framebuffer(&framebuffer_info, None)?;
+
+            self.swapchain_image_views.push(color_view);
+            self.framebuffers.push(framebuffer);
+        }
+
+        self.images_in_flight = vec![vk::Fence::null(); self.swapchain_images.len()];
+
+        Ok(())
+    }
+
+    /// Destroy everything that depends on the swapchain images, keeping the swapchain
+    /// handle itself so it can be passed as `old_swapchain` and the depth targets so they
+    /// can be resized.
+    unsafe fn destroy_swapchain_resources(&mut self, renderer: &VulkanRenderer) {
+        for framebuffer in self.framebuffers.drain(..) {
+            renderer.device.destroy_framebuffer(framebuffer, None);
+        }
+        for view in self.swapchain_image_views.drain(..) {
+            renderer.device.destroy_image_view(view, None);
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The device must be idle.
+    pub unsafe fn recreate_swapchain(
+        &mut self,
+        renderer: &VulkanRenderer,
+        width: u32,
+        height: u32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+
+        renderer.device.device_wait_idle()?;
+
+        self.destroy_swapchain_resources(renderer);
+        self.framebuffer_resized = false;
+        self.create_swapchain_resources(renderer, width, height)?;
+
+        Ok(())
+    }
+
+    /// Destroy the surface and all per-window resources
+    ///
+    /// # Safety
+    ///
+    /// The device must be idle. Call once.
+    pub unsafe fn destroy(&mut self, renderer: &VulkanRenderer) {
+        self.destroy_swapchain_resources(renderer);
+        for mut target in self.depth_targets.drain(..) {
+            target.destroy(renderer);
+        }
+
+        if self.swapchain != vk::SwapchainKHR::null() {
+            renderer.swapchain_fn.destroy_swapchain(self.swapchain, None);
+            self.swapchain = vk::SwapchainKHR::null();
+        }
+
+        for semaphore in self.image_available_semaphores.drain(..) {
+            renderer.device.destroy_semaphore(semaphore, None);
+        }
+        for semaphore in self.render_finished_semaphores.drain(..) {
+            renderer.device.destroy_semaphore(semaphore, None);
+        }
+        for fence in self.in_flight_fences.drain(..) {
+            renderer.device.destroy_fence(fence, None);
+        }
+
+        if !self.command_buffers.is_empty() {
+            renderer.device.free_command_buffers(renderer.command_pool, &self.command_buffers);
+            self.command_buffers.clear();
+        }
+
+        renderer.surface_fn.destroy_surface(self.surface, None);
+    }
+}